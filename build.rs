@@ -0,0 +1,21 @@
+//! Embeds the git commit (when building from a checkout) as `MERKLEKV_GIT_COMMIT`.
+
+use std::process::Command;
+
+fn main() {
+    println!("cargo:rerun-if-changed=.git/HEAD");
+    println!("cargo:rerun-if-changed=.git/refs");
+    println!("cargo:rerun-if-env-changed=MERKLEKV_GIT_COMMIT");
+    if std::env::var("MERKLEKV_GIT_COMMIT").is_ok() {
+        return;
+    }
+    let commit = Command::new("git")
+        .args(["rev-parse", "--short=12", "HEAD"])
+        .output()
+        .ok()
+        .filter(|out| out.status.success())
+        .and_then(|out| String::from_utf8(out.stdout).ok());
+    if let Some(commit) = commit {
+        println!("cargo:rustc-env=MERKLEKV_GIT_COMMIT={}", commit.trim());
+    }
+}
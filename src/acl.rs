@@ -0,0 +1,231 @@
+//! # Per-User Access Control
+//!
+//! Users from `auth.users_file` can be limited to a set of commands and a set
+//! of key patterns, written after the password hash:
+//!
+//! ```text
+//! reporter:pbkdf2-sha256$600000$9f2c...$4be1... commands=GET,MGET,SCAN keys=reports:*,shared:?
+//! ```
+//!
+//! - **`commands=`**: comma-separated command names (the first word of the
+//!   command, e.g. `CLIENT` covers `CLIENT LIST`); `AUTH` is always allowed
+//! - **`keys=`**: comma-separated globs, where `*` matches any run of
+//!   characters and `?` exactly one
+//!
+//! A missing rule allows everything. Keys are checked against the command's
+//! `EXPLAIN` plan: every key it reads, writes, deletes or expires must match a
+//! pattern. A `SCAN`/`FINDBYVALUE` prefix is allowed only when a `<literal>*`
+//! pattern covers every key it could return, and whole-keyspace commands
+//! (`DBSIZE`, `FLUSHDB`, `SYNC`, ...) need the pattern `*`. Denied commands get
+//! `ERROR ERR_NOPERM <reason>`.
+//!
+//! The key check fails closed: a command whose plan names no key is allowed
+//! only if it is on `KEYLESS` (connection handshake and liveness commands
+//! that reveal no data); every other one (`SHUTDOWN`, `CONFIG SET`,
+//! `REPLICATE`, `TASKS RUN`, ...) needs the pattern `*` as well.
+
+use anyhow::{anyhow, Result};
+use std::collections::HashSet;
+
+use crate::protocol::{self, Command};
+
+/// Commands that touch no key and no node state, allowed under any `keys=` rule.
+fn keyless(command: &Command) -> bool {
+    matches!(
+        command,
+        Command::Auth { .. }
+            | Command::Ping { .. }
+            | Command::Echo { .. }
+            | Command::Hello { .. }
+            | Command::Version
+            | Command::Uptime
+            | Command::Select { .. }
+            | Command::Cancel
+            | Command::Unsubscribe
+            | Command::ClientCompress { .. }
+            | Command::ClientTokens { .. }
+    )
+}
+
+/// Commands and keys one user may use.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Acl {
+    /// Allowed command names, uppercase; None allows all
+    commands: Option<HashSet<String>>,
+    /// Allowed key globs; None allows all
+    key_patterns: Option<Vec<String>>,
+}
+
+impl Acl {
+    /// Parse the whitespace-separated `commands=` / `keys=` rules of a users
+    /// file line.
+    pub fn parse<'a>(rules: impl IntoIterator<Item = &'a str>) -> Result<Self> {
+        let mut acl = Acl::default();
+        for rule in rules {
+            let (name, list) = rule.split_once('=').ok_or_else(|| anyhow!("expected name=value rule, got '{}'", rule))?;
+            let items: Vec<&str> = list.split(',').filter(|item| !item.is_empty()).collect();
+            if items.is_empty() {
+                return Err(anyhow!("rule '{}' has an empty list", name));
+            }
+            let duplicate = match name {
+                "commands" => acl
+                    .commands
+                    .replace(items.iter().map(|c| canonical_command(&c.to_ascii_uppercase())).collect())
+                    .is_some(),
+                "keys" => acl.key_patterns.replace(items.iter().map(|k| k.to_string()).collect()).is_some(),
+                _ => return Err(anyhow!("unknown rule '{}' (expected commands= or keys=)", name)),
+            };
+            if duplicate {
+                return Err(anyhow!("rule '{}' given twice", name));
+            }
+        }
+        Ok(acl)
+    }
+
+    /// Check `command` against the rules.
+    ///
+    /// # Returns
+    /// * `Result<(), String>` - Err with the reason when the command is denied
+    pub fn check(&self, command: &Command) -> std::result::Result<(), String> {
+        let name = command.name();
+        if let Some(commands) = &self.commands {
+            if name != "AUTH" && !commands.contains(name) {
+                return Err(format!("command {} is not allowed", name));
+            }
+        }
+        let Some(patterns) = &self.key_patterns else {
+            return Ok(());
+        };
+        let ops = command.plan().ops;
+        if ops.is_empty() && !keyless(command) && !patterns.iter().any(|p| p == "*") {
+            return Err(format!("{} needs access to every key", name));
+        }
+        for op in ops {
+            let (kind, target) = op.split_once(':').unwrap_or((op.as_str(), ""));
+            let allowed = match (kind, target) {
+                (_, "*") => patterns.iter().any(|p| p == "*"),
+                ("scan" | "index", prefix) => patterns.iter().any(|p| covers_prefix(p, prefix)),
+                (_, key) => patterns.iter().any(|p| glob_match(p, key)),
+            };
+            if !allowed {
+                return Err(match target {
+                    "*" => format!("{} needs access to every key", name),
+                    prefix if kind == "scan" || kind == "index" => format!("prefix '{}' is not allowed", prefix),
+                    key => format!("key '{}' is not allowed", key),
+                });
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Map parser aliases to the names `Command::name` reports.
+fn canonical_command(name: &str) -> String {
+    match name {
+        "REPLICATION" => "REPLICATE".to_string(),
+        other => protocol::canonical_command(other),
+    }
+}
+
+/// Whether `pattern` matches every key starting with `prefix`: it must be a
+/// wildcard-free literal followed by a single trailing `*`.
+fn covers_prefix(pattern: &str, prefix: &str) -> bool {
+    match pattern.strip_suffix('*') {
+        Some(literal) if !literal.contains(['*', '?']) => prefix.starts_with(literal),
+        _ => false,
+    }
+}
+
+/// Match `text` against a glob with `*` (any run) and `?` (one char).
+pub fn glob_match(pattern: &str, text: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let text: Vec<char> = text.chars().collect();
+    let (mut p, mut t) = (0, 0);
+    // Position of the last `*` and the text index it is currently matched up to
+    let mut star: Option<(usize, usize)> = None;
+    while t < text.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                star = Some((p, t));
+                p += 1;
+            }
+            Some(&c) if c == '?' || c == text[t] => {
+                p += 1;
+                t += 1;
+            }
+            _ => match star {
+                // Let the last `*` swallow one more char and retry
+                Some((star_p, star_t)) => {
+                    star = Some((star_p, star_t + 1));
+                    p = star_p + 1;
+                    t = star_t + 1;
+                }
+                None => return false,
+            },
+        }
+    }
+    pattern[p..].iter().all(|&c| c == '*')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocol::Protocol;
+
+    fn check(acl: &Acl, line: &str) -> std::result::Result<(), String> {
+        acl.check(&Protocol::new().parse(line).unwrap())
+    }
+
+    #[test]
+    fn test_glob_match() {
+        assert!(glob_match("app:*", "app:1"));
+        assert!(glob_match("app:*", "app:"));
+        assert!(!glob_match("app:*", "other:1"));
+        assert!(glob_match("a?c", "abc"));
+        assert!(!glob_match("a?c", "ac"));
+        assert!(glob_match("*:tmp:*", "x:tmp:y"));
+        assert!(glob_match("*", ""));
+        assert!(!glob_match("a*b", "acbx"));
+        assert!(glob_match("é?", "éa"));
+    }
+
+    #[test]
+    fn test_parse_rules() {
+        let acl = Acl::parse(["commands=get,del,delete", "keys=app:*"]).unwrap();
+        assert_eq!(acl.commands, Some(["GET", "DEL"].iter().map(|s| s.to_string()).collect()));
+        assert_eq!(acl.key_patterns, Some(vec!["app:*".to_string()]));
+        assert_eq!(Acl::parse([]).unwrap(), Acl::default());
+        assert!(Acl::parse(["commands"]).is_err());
+        assert!(Acl::parse(["keys="]).is_err());
+        assert!(Acl::parse(["users=a"]).is_err());
+        assert!(Acl::parse(["keys=a", "keys=b"]).is_err());
+    }
+
+    #[test]
+    fn test_check_commands_and_keys() {
+        let acl = Acl::parse(["commands=GET,SET,SCAN,MGET,DBSIZE,PING", "keys=app:*,cfg"]).unwrap();
+        assert_eq!(check(&acl, "GET app:1"), Ok(()));
+        assert_eq!(check(&acl, "SET cfg v"), Ok(()));
+        assert_eq!(check(&acl, "PING"), Ok(()));
+        assert_eq!(check(&acl, "AUTH alice pw"), Ok(()), "AUTH is always allowed");
+        assert_eq!(check(&acl, "SCAN app:users:"), Ok(()));
+        assert_eq!(check(&acl, "DEL app:1"), Err("command DEL is not allowed".to_string()));
+        assert_eq!(check(&acl, "GET other"), Err("key 'other' is not allowed".to_string()));
+        assert_eq!(check(&acl, "MGET app:1 other"), Err("key 'other' is not allowed".to_string()));
+        assert_eq!(check(&acl, "SCAN ap"), Err("prefix 'ap' is not allowed".to_string()));
+        assert!(check(&acl, "DBSIZE").is_err(), "whole keyspace needs '*'");
+
+        // Commands without a plan are denied unless known to touch no key
+        let keys_only = Acl::parse(["keys=app:*"]).unwrap();
+        assert_eq!(check(&keys_only, "TOMBSTONES"), Err("TOMBSTONES needs access to every key".to_string()));
+        assert_eq!(check(&keys_only, "SHUTDOWN"), Err("SHUTDOWN needs access to every key".to_string()));
+        assert!(check(&keys_only, "CONFIG SET sync_interval_seconds 5").is_err());
+        assert!(check(&keys_only, "TASKS").is_err());
+        assert_eq!(check(&keys_only, "PING"), Ok(()));
+        assert_eq!(check(&keys_only, "SELECT 1"), Ok(()));
+        assert_eq!(check(&Acl::parse(["keys=*"]).unwrap(), "SHUTDOWN"), Ok(()));
+
+        assert_eq!(check(&Acl::parse(["keys=*"]).unwrap(), "FLUSHDB"), Ok(()));
+        assert_eq!(check(&Acl::default(), "DELETE anything"), Ok(()));
+    }
+}
@@ -0,0 +1,131 @@
+//! # IP Allowlist
+//!
+//! A simple network guard for the TCP server. When `server.allowed_cidrs` is
+//! non-empty, only clients whose IP falls within one of the listed CIDR blocks
+//! are served; everyone else is disconnected right after accept.
+//!
+//! ## Format
+//!
+//! Entries are standard CIDR strings (`10.0.0.0/8`, `2001:db8::/32`). A bare
+//! address (`127.0.0.1`, `::1`) is treated as a single-host block.
+//!
+//! IPv4-mapped IPv6 addresses (`::ffff:10.1.2.3`, as seen on dual-stack
+//! listeners) are matched against the IPv4 rules.
+
+use anyhow::{anyhow, Result};
+use std::net::IpAddr;
+
+/// A single CIDR block (network address + prefix length).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Cidr {
+    network: IpAddr,
+    prefix_len: u8,
+}
+
+impl Cidr {
+    /// Parse `addr/len` or a bare address.
+    pub fn parse(s: &str) -> Result<Self> {
+        let s = s.trim();
+        let (addr_part, len_part) = match s.split_once('/') {
+            Some((a, l)) => (a, Some(l)),
+            None => (s, None),
+        };
+        let addr: IpAddr = addr_part
+            .parse()
+            .map_err(|_| anyhow!("Invalid CIDR '{}': bad address", s))?;
+        let max_len = if addr.is_ipv4() { 32 } else { 128 };
+        let prefix_len = match len_part {
+            Some(l) => l
+                .parse::<u8>()
+                .ok()
+                .filter(|&l| l <= max_len)
+                .ok_or_else(|| anyhow!("Invalid CIDR '{}': prefix length must be 0..={}", s, max_len))?,
+            None => max_len,
+        };
+        Ok(Self { network: addr.to_canonical(), prefix_len })
+    }
+
+    /// Whether `ip` belongs to this block.
+    pub fn contains(&self, ip: IpAddr) -> bool {
+        match (self.network, ip.to_canonical()) {
+            (IpAddr::V4(net), IpAddr::V4(ip)) => {
+                let mask = u32::MAX.checked_shl(32 - self.prefix_len as u32).unwrap_or(0);
+                u32::from(net) & mask == u32::from(ip) & mask
+            }
+            (IpAddr::V6(net), IpAddr::V6(ip)) => {
+                let mask = u128::MAX.checked_shl(128 - self.prefix_len as u32).unwrap_or(0);
+                u128::from(net) & mask == u128::from(ip) & mask
+            }
+            _ => false,
+        }
+    }
+}
+
+/// The set of CIDR blocks allowed to connect. Empty means "allow everyone".
+#[derive(Debug, Clone, Default)]
+pub struct IpAllowlist {
+    blocks: Vec<Cidr>,
+}
+
+impl IpAllowlist {
+    /// Build an allowlist from configuration strings, failing on the first malformed entry.
+    pub fn from_cidrs(cidrs: &[String]) -> Result<Self> {
+        let blocks = cidrs.iter().map(|c| Cidr::parse(c)).collect::<Result<Vec<_>>>()?;
+        Ok(Self { blocks })
+    }
+
+    /// Whether a client at `ip` may connect.
+    pub fn is_allowed(&self, ip: IpAddr) -> bool {
+        self.blocks.is_empty() || self.blocks.iter().any(|b| b.contains(ip))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ip(s: &str) -> IpAddr {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn test_accepts_in_range_ip() {
+        let list = IpAllowlist::from_cidrs(&["10.0.0.0/8".to_string(), "192.168.1.0/24".to_string()]).unwrap();
+        assert!(list.is_allowed(ip("10.20.30.40")));
+        assert!(list.is_allowed(ip("192.168.1.200")));
+        // IPv4-mapped address from a dual-stack listener
+        assert!(list.is_allowed(ip("::ffff:10.1.1.1")));
+    }
+
+    #[test]
+    fn test_rejects_out_of_range_ip() {
+        let list = IpAllowlist::from_cidrs(&["10.0.0.0/8".to_string(), "192.168.1.0/24".to_string()]).unwrap();
+        assert!(!list.is_allowed(ip("11.0.0.1")));
+        assert!(!list.is_allowed(ip("192.168.2.1")));
+        assert!(!list.is_allowed(ip("::1")));
+    }
+
+    #[test]
+    fn test_ipv6_and_single_host() {
+        let list = IpAllowlist::from_cidrs(&["2001:db8::/32".to_string(), "127.0.0.1".to_string()]).unwrap();
+        assert!(list.is_allowed(ip("2001:db8:1::5")));
+        assert!(!list.is_allowed(ip("2001:db9::1")));
+        assert!(list.is_allowed(ip("127.0.0.1")));
+        assert!(!list.is_allowed(ip("127.0.0.2")));
+    }
+
+    #[test]
+    fn test_empty_allows_everyone_and_zero_prefix() {
+        assert!(IpAllowlist::default().is_allowed(ip("8.8.8.8")));
+        let any = IpAllowlist::from_cidrs(&["0.0.0.0/0".to_string()]).unwrap();
+        assert!(any.is_allowed(ip("8.8.8.8")));
+    }
+
+    #[test]
+    fn test_invalid_cidrs_rejected() {
+        assert!(Cidr::parse("10.0.0.0/33").is_err());
+        assert!(Cidr::parse("not-an-ip/8").is_err());
+        assert!(Cidr::parse("::/129").is_err());
+        assert!(IpAllowlist::from_cidrs(&["10.0.0.0/8".to_string(), "bogus".to_string()]).is_err());
+    }
+}
@@ -0,0 +1,338 @@
+//! # Authentication Providers
+//!
+//! `AUTH` checks credentials against an `AuthProvider`, picked from the config:
+//!
+//! - **`StaticPasswordProvider`**: the single `server.password`, for user `default`
+//! - **`UsersFileProvider`**: named users from `auth.users_file`
+//!
+//! Clients send `AUTH <password>` (user `default`) or `AUTH <user> <password>`.
+//! Passwords may contain spaces, so the whole argument is first tried as the
+//! password of `default`, then as `<user> <password>` if it is two words.
+//!
+//! ## Users File
+//!
+//! One `user:hash [rules...]` per line; blank lines and lines starting with
+//! `#` are ignored. Hashes are PBKDF2-HMAC-SHA256, written as
+//! `pbkdf2-sha256$<iterations>$<salt hex>$<digest hex>` so the cost travels
+//! with each hash and can be raised without breaking existing lines. Generate
+//! one with `merkle_kv --hash-password`, which reads the password from stdin
+//! so it stays out of the shell history and `ps`. The optional
+//! `commands=` / `keys=` rules restrict the user; see `acl`.
+//!
+//! ```text
+//! # users
+//! alice:pbkdf2-sha256$600000$9f2c...$4be1...
+//! app:pbkdf2-sha256$600000$07d1...$c3a9... commands=GET,SET,SCAN keys=app:*
+//! ```
+//!
+//! Single-round `sha256$<salt>$<digest>` hashes from older releases are
+//! refused at start: regenerate them.
+//!
+//! The file is read once at start. An unknown user is checked against a
+//! dummy hash of the same cost as the real ones, so a wrong user name takes
+//! as long to refuse as a wrong password and timing does not reveal which
+//! users exist.
+//!
+//! Hashing is deliberately slow: the server runs `authenticate` on the
+//! blocking thread pool, never on a connection's worker thread.
+
+use anyhow::{anyhow, Context, Result};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::Arc;
+
+use crate::acl::Acl;
+use crate::config::Config;
+
+/// User that `AUTH <password>` (no user name) authenticates as.
+pub const DEFAULT_USER: &str = "default";
+
+/// Outcome of a credentials check.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AuthResult {
+    /// Credentials accepted for this user
+    Authenticated(String),
+    /// Unknown user or wrong password
+    Denied,
+}
+
+/// Source of truth for `AUTH`.
+pub trait AuthProvider: Send + Sync {
+    fn authenticate(&self, user: &str, password: &str) -> AuthResult;
+
+    /// What an authenticated `user` may do; unrestricted unless overridden.
+    fn acl(&self, _user: &str) -> Acl {
+        Acl::default()
+    }
+}
+
+/// Build the provider for `config`, or None when authentication is off.
+pub fn provider_from_config(config: &Config) -> Result<Option<Arc<dyn AuthProvider>>> {
+    if let Some(path) = &config.auth.users_file {
+        let provider = UsersFileProvider::load(Path::new(path))
+            .with_context(|| format!("load `auth.users_file` {}", path))?;
+        return Ok(Some(Arc::new(provider)));
+    }
+    Ok(config
+        .server
+        .password
+        .clone()
+        .map(|password| Arc::new(StaticPasswordProvider { password }) as Arc<dyn AuthProvider>))
+}
+
+/// Check the argument of `AUTH` against `provider`; see the module docs for
+/// how `<password>` and `<user> <password>` are told apart.
+pub fn authenticate(provider: &dyn AuthProvider, arg: &str) -> AuthResult {
+    if let AuthResult::Authenticated(user) = provider.authenticate(DEFAULT_USER, arg) {
+        return AuthResult::Authenticated(user);
+    }
+    match arg.split_whitespace().collect::<Vec<_>>()[..] {
+        [user, password] => provider.authenticate(user, password),
+        _ => AuthResult::Denied,
+    }
+}
+
+/// Compare secrets without short-circuiting on the first differing byte.
+fn constant_time_eq(expected: &[u8], given: &[u8]) -> bool {
+    expected.len() == given.len() && expected.iter().zip(given).fold(0u8, |diff, (a, b)| diff | (a ^ b)) == 0
+}
+
+/// `server.password` as the password of user `default`.
+pub struct StaticPasswordProvider {
+    password: String,
+}
+
+impl AuthProvider for StaticPasswordProvider {
+    fn authenticate(&self, user: &str, password: &str) -> AuthResult {
+        if user == DEFAULT_USER && constant_time_eq(self.password.as_bytes(), password.as_bytes()) {
+            AuthResult::Authenticated(user.to_string())
+        } else {
+            AuthResult::Denied
+        }
+    }
+}
+
+/// Scheme name at the start of a hash string.
+const SCHEME: &str = "pbkdf2-sha256";
+
+/// PBKDF2 rounds for new hashes; tests use few so they stay fast.
+const ITERATIONS: u32 = if cfg!(test) { 1_000 } else { 600_000 };
+
+/// SHA-256 block size, the HMAC key pad length.
+const BLOCK: usize = 64;
+
+/// PBKDF2-HMAC-SHA256 password hash.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PasswordHash {
+    iterations: u32,
+    salt: Vec<u8>,
+    digest: Vec<u8>,
+}
+
+impl PasswordHash {
+    /// Hash `password` with a fresh random salt.
+    pub fn new(password: &str) -> Self {
+        let salt = uuid::Uuid::new_v4().as_bytes().to_vec();
+        let digest = pbkdf2_sha256(password.as_bytes(), &salt, ITERATIONS).to_vec();
+        Self { iterations: ITERATIONS, salt, digest }
+    }
+
+    /// Parse `pbkdf2-sha256$<iterations>$<salt hex>$<digest hex>`.
+    pub fn parse(s: &str) -> Result<Self> {
+        if s.starts_with("sha256$") {
+            return Err(anyhow!("single-round sha256 hashes are no longer accepted; regenerate with --hash-password"));
+        }
+        let mut parts = s.split('$');
+        let (Some(SCHEME), Some(iterations), Some(salt), Some(digest), None) =
+            (parts.next(), parts.next(), parts.next(), parts.next(), parts.next())
+        else {
+            return Err(anyhow!("expected {}$<iterations>$<salt>$<digest>", SCHEME));
+        };
+        let iterations: u32 = iterations.parse().context("iterations is not a number")?;
+        if iterations == 0 {
+            return Err(anyhow!("iterations must be at least 1"));
+        }
+        let salt = hex::decode(salt).context("salt is not hex")?;
+        let digest = hex::decode(digest).context("digest is not hex")?;
+        if digest.len() != 32 {
+            return Err(anyhow!("digest must be 32 bytes"));
+        }
+        Ok(Self { iterations, salt, digest })
+    }
+
+    pub fn verify(&self, password: &str) -> bool {
+        constant_time_eq(&self.digest, &pbkdf2_sha256(password.as_bytes(), &self.salt, self.iterations))
+    }
+}
+
+impl std::fmt::Display for PasswordHash {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}${}${}${}", SCHEME, self.iterations, hex::encode(&self.salt), hex::encode(&self.digest))
+    }
+}
+
+/// HMAC-SHA256 keyed with `key`, as (inner, outer) hashers that have taken
+/// the padded key; clone them per message.
+fn hmac_sha256(key: &[u8]) -> (Sha256, Sha256) {
+    let mut block = [0u8; BLOCK];
+    if key.len() > BLOCK {
+        block[..32].copy_from_slice(&Sha256::digest(key));
+    } else {
+        block[..key.len()].copy_from_slice(key);
+    }
+    let mut inner = Sha256::new();
+    inner.update(block.map(|b| b ^ 0x36));
+    let mut outer = Sha256::new();
+    outer.update(block.map(|b| b ^ 0x5c));
+    (inner, outer)
+}
+
+fn hmac_finish((inner, outer): &(Sha256, Sha256), message: &[u8]) -> [u8; 32] {
+    let mut inner = inner.clone();
+    inner.update(message);
+    let mut outer = outer.clone();
+    outer.update(inner.finalize());
+    outer.finalize().into()
+}
+
+/// PBKDF2 (RFC 8018) with HMAC-SHA256, one 32-byte block.
+fn pbkdf2_sha256(password: &[u8], salt: &[u8], iterations: u32) -> [u8; 32] {
+    let keyed = hmac_sha256(password);
+    let mut u = hmac_finish(&keyed, &[salt, &1u32.to_be_bytes()].concat());
+    let mut out = u;
+    for _ in 1..iterations {
+        u = hmac_finish(&keyed, &u);
+        out.iter_mut().zip(u).for_each(|(o, b)| *o ^= b);
+    }
+    out
+}
+
+/// Named users with hashed passwords and ACLs, read from `auth.users_file`.
+pub struct UsersFileProvider {
+    users: HashMap<String, (PasswordHash, Acl)>,
+    /// Verified for unknown users; never matches
+    dummy: PasswordHash,
+}
+
+impl UsersFileProvider {
+    pub fn load(path: &Path) -> Result<Self> {
+        let text = std::fs::read_to_string(path)?;
+        Self::parse(&text).with_context(|| path.display().to_string())
+    }
+
+    /// Parse the users file format; fails on the first malformed line.
+    pub fn parse(text: &str) -> Result<Self> {
+        let mut users = HashMap::new();
+        for (idx, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let (user, hash) = line.split_once(':').ok_or_else(|| anyhow!("line {}: expected user:hash", idx + 1))?;
+            if user.is_empty() || user.contains(char::is_whitespace) {
+                return Err(anyhow!("line {}: invalid user name '{}'", idx + 1, user));
+            }
+            let mut fields = hash.split_whitespace();
+            let hash = PasswordHash::parse(fields.next().unwrap_or_default()).with_context(|| format!("line {}", idx + 1))?;
+            let acl = Acl::parse(fields).with_context(|| format!("line {}", idx + 1))?;
+            if users.insert(user.to_string(), (hash, acl)).is_some() {
+                return Err(anyhow!("line {}: duplicate user '{}'", idx + 1, user));
+            }
+        }
+        // As costly as the costliest real hash; a zero digest is never produced
+        let iterations = users.values().map(|(hash, _)| hash.iterations).max().unwrap_or(ITERATIONS);
+        let dummy = PasswordHash { iterations, salt: vec![0; 16], digest: vec![0; 32] };
+        Ok(Self { users, dummy })
+    }
+}
+
+impl AuthProvider for UsersFileProvider {
+    fn authenticate(&self, user: &str, password: &str) -> AuthResult {
+        match self.users.get(user) {
+            Some((hash, _)) if hash.verify(password) => AuthResult::Authenticated(user.to_string()),
+            Some(_) => AuthResult::Denied,
+            None => {
+                // Same work as a known user, same answer
+                self.dummy.verify(password);
+                AuthResult::Denied
+            }
+        }
+    }
+
+    fn acl(&self, user: &str) -> Acl {
+        self.users.get(user).map(|(_, acl)| acl.clone()).unwrap_or_default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+
+    #[test]
+    fn test_password_hash_roundtrip() {
+        let hash = PasswordHash::new("hunter2");
+        let parsed = PasswordHash::parse(&hash.to_string()).unwrap();
+        assert_eq!(parsed, hash);
+        assert!(parsed.verify("hunter2"));
+        assert!(!parsed.verify("hunter3"));
+        assert_ne!(PasswordHash::new("hunter2"), hash, "salts are random");
+        assert!(PasswordHash::parse("md5$00$00").is_err());
+        assert!(PasswordHash::parse("sha256$zz$00").is_err());
+        assert!(PasswordHash::parse(&format!("sha256$00${}", "00".repeat(32))).is_err(), "single-round hashes are refused");
+        assert!(PasswordHash::parse(&format!("pbkdf2-sha256$0$00${}", "00".repeat(32))).is_err());
+        assert!(hash.to_string().starts_with("pbkdf2-sha256$1000$"), "{}", hash);
+    }
+
+    #[test]
+    fn test_pbkdf2_known_answers() {
+        // The published PBKDF2-HMAC-SHA256 vectors for "password" / "salt"
+        let hex = |iterations| hex::encode(pbkdf2_sha256(b"password", b"salt", iterations));
+        assert_eq!(hex(1), "120fb6cffcf8b32c43e7225256c4f837a86548c92ccc35480805987cb70be17b");
+        assert_eq!(hex(2), "ae4d0c95af6b46d32d0adff928f06dd02a303f8ef3c251dfd6e2d85a95474c43");
+        assert_eq!(hex(4096), "c5e478d59288c841aa530db6845c4c8d962893a001ce4e11a4963873aa98134a");
+        // A key longer than the block is hashed first
+        let long = PasswordHash::parse(&PasswordHash::new(&"k".repeat(100)).to_string()).unwrap();
+        assert!(long.verify(&"k".repeat(100)));
+        assert!(!long.verify(&"k".repeat(99)));
+    }
+
+    #[test]
+    fn test_static_password_allows_spaces() {
+        let provider = StaticPasswordProvider { password: "s3cret pass".to_string() };
+        assert_eq!(authenticate(&provider, "s3cret pass"), AuthResult::Authenticated(DEFAULT_USER.to_string()));
+        assert_eq!(authenticate(&provider, "default s3cret"), AuthResult::Denied);
+    }
+
+    #[test]
+    fn test_users_file_provider() {
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        writeln!(file, "# users\nalice:{}\n\nbob:{}", PasswordHash::new("wonderland"), PasswordHash::new("builder")).unwrap();
+        let provider = UsersFileProvider::load(file.path()).unwrap();
+
+        assert_eq!(provider.authenticate("alice", "wonderland"), AuthResult::Authenticated("alice".to_string()));
+        assert_eq!(provider.authenticate("bob", "builder"), AuthResult::Authenticated("bob".to_string()));
+        assert_eq!(provider.authenticate("alice", "builder"), AuthResult::Denied);
+        assert_eq!(provider.authenticate("mallory", "wonderland"), AuthResult::Denied);
+        assert_eq!(provider.dummy.iterations, ITERATIONS, "unknown users cost a real verification");
+
+        assert_eq!(authenticate(&provider, "alice wonderland"), AuthResult::Authenticated("alice".to_string()));
+        assert_eq!(authenticate(&provider, "wonderland"), AuthResult::Denied, "no default user");
+
+        assert!(UsersFileProvider::parse("alice").is_err());
+        assert!(UsersFileProvider::parse("alice:plaintext").is_err());
+        let line = format!("a:{}", PasswordHash::new("x"));
+        let err = UsersFileProvider::parse(&format!("{}\n{}", line, line)).err().unwrap();
+        assert!(err.to_string().contains("duplicate"), "{}", err);
+    }
+
+    #[test]
+    fn test_users_file_acl_rules() {
+        let text = format!("app:{} commands=GET keys=app:*\nadmin:{}", PasswordHash::new("a"), PasswordHash::new("b"));
+        let provider = UsersFileProvider::parse(&text).unwrap();
+        assert_eq!(provider.acl("app"), Acl::parse(["commands=GET", "keys=app:*"]).unwrap());
+        assert_eq!(provider.acl("admin"), Acl::default());
+        assert!(UsersFileProvider::parse(&format!("app:{} keys", PasswordHash::new("a"))).is_err());
+    }
+}
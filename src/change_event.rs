@@ -17,8 +17,43 @@
 //! The event’s `val` carries the resulting value after the operation (for SET,
 //! INCR/DECR, APPEND/PREPEND). This choice makes idempotent application simple
 //! and makes LWW straightforward: the winner simply becomes “the value”.
+//!
+//! ## Binary-Safe Keys and Values
+//!
+//! `key` and `val` travel as raw bytes in the binary codecs: a CBOR byte
+//! string (Bincode's framing is the same length-prefixed bytes either way).
+//! Any byte sequence, NUL and invalid UTF-8 included, therefore reaches the
+//! peer exactly as sent. Older CBOR events with a text key or a value encoded
+//! as an array of numbers still decode. JSON keeps both fields as they always
+//! were, `key` a string and `val` an array of byte values, which is already
+//! exact, so no field changes meaning between versions. A decoded event is
+//! checked (`validate`) before it is applied; malformed ones are rejected.
+//!
+//! ## Schema Versions
+//!
+//! Every event carries the schema version it was written with (`v`) and the
+//! oldest version that can apply it correctly (`min_v`). Fields are only
+//! ever added, each with a default, so:
+//!
+//! | event from              | older reader                      | newer reader                |
+//! |-------------------------|-----------------------------------|-----------------------------|
+//! | additive version        | applies known fields, ignores new | fills missing with defaults |
+//! | `min_v` above the reader | rejects the event                 | applies it                  |
+//!
+//! A version whose events would be misapplied by readers that ignore its
+//! new fields raises `min_v` to itself; anti-entropy sync then carries its
+//! writes to the older nodes. Unknown fields are skipped by the CBOR and
+//! JSON decoders; Bincode has no field names and only reads its own version.
+//! Version 1 covers every field below; events from before `min_v` existed
+//! decode with `min_v = 1`.
 
+use serde::de::{self, Deserializer, SeqAccess, Visitor};
+use serde::ser::Serializer;
 use serde::{Deserialize, Serialize};
+use std::fmt;
+
+/// Schema version this node writes (`v`) and the newest whose fields it knows.
+pub const SCHEMA_VERSION: u16 = 1;
 
 /// The operation kind carried by a change event.
 ///
@@ -41,21 +76,36 @@ pub enum OpKind {
     Append,
     /// String prepend; event value contains the resulting string as bytes
     Prepend,
+    /// TTL change on an existing key (EXPIRE / PERSIST); the value is left
+    /// untouched, `ttl` carries the new TTL and `None` makes the key persistent
+    Expire,
+    /// Request for the full value of `key`: the receiver lacked the base of a
+    /// delta; `val` names the node that should republish it
+    Resend,
+    /// Confirmation that the event `ts` of node `val` was applied by `src`;
+    /// sent for events with `ack` set (`SET ... CL=quorum|all`)
+    Ack,
 }
 
 /// Canonical change-event structure used to replicate writes.
 ///
 /// - `v` (schema version): Enables evolution of the on-wire format.
+/// - `min_v`: The oldest schema version that applies the event correctly.
 /// - `op`: What kind of mutation this is (set/del/incr/decr/append/prepend).
 /// - `key`: The logical key being mutated.
 /// - `val`: The resulting value as raw bytes (UTF-8 for string values, ASCII
-///          digits for numeric results); `None` for deletions.
+///   digits for numeric results); `None` for deletions.
 /// - `ts`: A timestamp for conflict resolution. We allow Unix nanoseconds or a
-///         logical clock; the comparison is the only semantic the system needs.
+///   logical clock; the comparison is the only semantic the system needs.
 /// - `src`: The originating node identifier, used for loop prevention.
 /// - `op_id`: A 128-bit identifier (UUID v4) for idempotency/deduplication.
 /// - `prev`: Optional 32-byte Merkle root (or leaf) hash to assist anti-entropy.
 /// - `ttl`: Optional TTL-in-seconds hint (not enforced by the in-memory engine).
+/// - `group`: Set when the event is one of several writes (e.g. a SWAP) that
+///   receivers must apply together.
+/// - `delta`: Set when `val` holds only the changed bytes of a large value;
+///   see `crate::delta`.
+/// - `ack`: Set when the origin waits for receivers to confirm the event.
 #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct ChangeEvent {
     /// Schema version (allows additive, backward-compatible upgrades)
@@ -63,8 +113,10 @@ pub struct ChangeEvent {
     /// Operation kind
     pub op: OpKind,
     /// Key under mutation
+    #[serde(with = "wire_key")]
     pub key: String,
     /// Resulting value after mutation; None for deletions
+    #[serde(with = "wire_value")]
     pub val: Option<Vec<u8>>, // bytes to be agnostic to codec and content
     /// Timestamp for LWW resolution (unix_nanos or logical clock)
     pub ts: u64,
@@ -76,6 +128,42 @@ pub struct ChangeEvent {
     pub prev: Option<[u8; 32]>,
     /// Optional TTL in seconds (advisory in this prototype)
     pub ttl: Option<u64>,
+    /// Events sharing a group are held by receivers until all have arrived
+    #[serde(default)]
+    pub group: Option<EventGroup>,
+    /// `val` is a delta against the base version named here
+    #[serde(default)]
+    pub delta: Option<ValueDelta>,
+    /// Receivers reply with an `Ack` event once this event is applied
+    #[serde(default)]
+    pub ack: bool,
+    /// Oldest schema version that can apply this event (see module docs)
+    #[serde(default = "first_schema_version")]
+    pub min_v: u16,
+}
+
+fn first_schema_version() -> u16 {
+    1
+}
+
+/// Marker tying the events of one atomic multi-key write together.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct EventGroup {
+    /// Shared id (the `op_id` of the group's first event)
+    pub id: [u8; 16],
+    /// Number of events in the group
+    pub size: u16,
+}
+
+/// How to rebuild a value from the receiver's copy of an earlier version.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ValueDelta {
+    /// SHA-256 of the base value
+    pub base: [u8; 32],
+    /// Bytes kept from the start of the base
+    pub prefix: u64,
+    /// Bytes kept from the end of the base
+    pub suffix: u64,
 }
 
 impl ChangeEvent {
@@ -85,6 +173,7 @@ impl ChangeEvent {
     /// under at-least-once delivery. Timestamps should be monotonic within a
     /// node for LWW to be meaningful. Using `unix_nanos` is sufficient for a
     /// prototype; Lamport clocks would improve causality ordering across nodes.
+    #[allow(clippy::too_many_arguments)]
     pub fn new(
         v: u16,
         op: OpKind,
@@ -106,10 +195,15 @@ impl ChangeEvent {
             op_id,
             prev,
             ttl,
+            group: None,
+            delta: None,
+            ack: false,
+            min_v: first_schema_version(),
         }
     }
 
     /// Helper: Build an event whose value is a UTF-8 string.
+    #[allow(clippy::too_many_arguments)]
     pub fn with_str_value(
         v: u16,
         op: OpKind,
@@ -154,25 +248,159 @@ impl ChangeEvent {
         bincode::deserialize(bytes)
     }
 
-    /// Attempt to decode using CBOR, then Bincode, then JSON.
+    /// Attempt to decode using CBOR, then Bincode, then JSON, and `validate`
+    /// the result.
     ///
     /// This is useful for subscribers that accept multiple codecs without
     /// negotiating content-types on the transport.
     pub fn decode_any(bytes: &[u8]) -> Result<Self, String> {
-        if let Ok(e) = Self::from_cbor(bytes) {
-            return Ok(e);
+        let decoded = Self::from_cbor(bytes)
+            .ok()
+            .or_else(|| Self::from_bincode(bytes).ok())
+            .or_else(|| Self::from_json(bytes).ok())
+            .ok_or("Failed to decode ChangeEvent with CBOR, Bincode, or JSON")?;
+        decoded.validate()?;
+        Ok(decoded)
+    }
+
+    /// Reject events no well-behaved node produces or this node cannot
+    /// apply: a schema version this node is too old for, an empty key, a
+    /// write without its value, a malformed node id in `Resend`/`Ack`, or a
+    /// group or delta that cannot apply. Newer versions are accepted as long
+    /// as their `min_v` allows it.
+    pub fn validate(&self) -> Result<(), String> {
+        if self.min_v == 0 || self.min_v > self.v {
+            return Err(format!("invalid change event versions v:{} min_v:{}", self.v, self.min_v));
+        }
+        if self.min_v > SCHEMA_VERSION {
+            return Err(format!("change event needs schema version {} (this node reads up to {})", self.min_v, SCHEMA_VERSION));
+        }
+        if self.key.is_empty() {
+            return Err("change event has an empty key".into());
+        }
+        let carries_value = matches!(self.op, OpKind::Set | OpKind::Incr | OpKind::Decr | OpKind::Append | OpKind::Prepend);
+        if carries_value && self.val.is_none() {
+            return Err(format!("{:?} event for '{}' has no value", self.op, self.key));
+        }
+        if matches!(self.op, OpKind::Resend | OpKind::Ack)
+            && !self.val.as_deref().is_some_and(|node| !node.is_empty() && std::str::from_utf8(node).is_ok())
+        {
+            return Err(format!("{:?} event for '{}' does not name a node", self.op, self.key));
         }
-        if let Ok(e) = Self::from_bincode(bytes) {
-            return Ok(e);
+        if self.group.is_some_and(|g| g.size < 2) {
+            return Err(format!("event group for '{}' has fewer than two members", self.key));
         }
-        if let Ok(e) = Self::from_json(bytes) {
-            return Ok(e);
+        if self.delta.is_some() && !carries_value {
+            return Err(format!("{:?} event for '{}' cannot carry a delta", self.op, self.key));
+        }
+        Ok(())
+    }
+}
+
+/// `key` on the wire: bytes that must be UTF-8, since keys are strings in
+/// every store; a plain string in JSON.
+mod wire_key {
+    use super::*;
+
+    pub fn serialize<S: Serializer>(key: &str, serializer: S) -> Result<S::Ok, S::Error> {
+        if serializer.is_human_readable() {
+            serializer.serialize_str(key)
+        } else {
+            serializer.serialize_bytes(key.as_bytes())
+        }
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<String, D::Error> {
+        if deserializer.is_human_readable() {
+            return String::deserialize(deserializer);
+        }
+        let bytes = wire_value::deserialize_bytes(deserializer)?;
+        String::from_utf8(bytes).map_err(|_| de::Error::custom("change event key is not valid UTF-8"))
+    }
+}
+
+/// `val` on the wire: a byte string, or an array of byte values in JSON.
+mod wire_value {
+    use super::*;
+
+    pub fn serialize<S: Serializer>(val: &Option<Vec<u8>>, serializer: S) -> Result<S::Ok, S::Error> {
+        match val {
+            Some(bytes) => serializer.serialize_some(&Raw(bytes)),
+            None => serializer.serialize_none(),
+        }
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<Vec<u8>>, D::Error> {
+        Ok(Option::<Owned>::deserialize(deserializer)?.map(|b| b.0))
+    }
+
+    fn serialize_bytes<S: Serializer>(bytes: &[u8], serializer: S) -> Result<S::Ok, S::Error> {
+        if serializer.is_human_readable() {
+            serializer.collect_seq(bytes)
+        } else {
+            serializer.serialize_bytes(bytes)
+        }
+    }
+
+    pub(super) fn deserialize_bytes<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<u8>, D::Error> {
+        if deserializer.is_human_readable() {
+            deserializer.deserialize_seq(BytesVisitor)
+        } else {
+            deserializer.deserialize_byte_buf(BytesVisitor)
+        }
+    }
+
+    struct Raw<'a>(&'a [u8]);
+
+    impl Serialize for Raw<'_> {
+        fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+            serialize_bytes(self.0, serializer)
+        }
+    }
+
+    struct Owned(Vec<u8>);
+
+    impl<'de> Deserialize<'de> for Owned {
+        fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+            deserialize_bytes(deserializer).map(Owned)
+        }
+    }
+
+    /// Accepts a byte string, a text string (older CBOR keys) or a sequence
+    /// of numbers (JSON, older CBOR values).
+    struct BytesVisitor;
+
+    impl<'de> Visitor<'de> for BytesVisitor {
+        type Value = Vec<u8>;
+
+        fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            f.write_str("bytes")
+        }
+
+        fn visit_bytes<E: de::Error>(self, v: &[u8]) -> Result<Vec<u8>, E> {
+            Ok(v.to_vec())
+        }
+
+        fn visit_byte_buf<E: de::Error>(self, v: Vec<u8>) -> Result<Vec<u8>, E> {
+            Ok(v)
+        }
+
+        fn visit_str<E: de::Error>(self, v: &str) -> Result<Vec<u8>, E> {
+            Ok(v.as_bytes().to_vec())
+        }
+
+        fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Vec<u8>, A::Error> {
+            let mut out = Vec::with_capacity(seq.size_hint().unwrap_or(0));
+            while let Some(byte) = seq.next_element::<u8>()? {
+                out.push(byte);
+            }
+            Ok(out)
         }
-        Err("Failed to decode ChangeEvent with CBOR, Bincode, or JSON".into())
     }
 }
 
 /// Preferred encoding for on-wire messages.
+#[allow(dead_code)]
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum ChangeCodec {
     Json,
@@ -194,6 +422,7 @@ impl ChangeCodec {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use base64::Engine as _;
     use std::collections::{HashMap, HashSet};
 
     /// A minimal local applier used for unit tests without MQTT.
@@ -248,7 +477,7 @@ mod tests {
                 }
                 _ => {
                     if let Some(bytes) = &ev.val {
-                        let s = String::from_utf8(bytes.clone()).unwrap_or_else(|_| base64::encode(bytes));
+                        let s = String::from_utf8(bytes.clone()).unwrap_or_else(|_| base64::engine::general_purpose::STANDARD.encode(bytes));
                         self.store.insert(ev.key.clone(), s);
                     }
                 }
@@ -278,7 +507,7 @@ mod tests {
     #[test]
     fn idempotency_duplicate_event() {
         let mut applier = LocalApplier::new();
-        let mut ev = sample_event(OpKind::Set, "x", Some("1"), 10);
+        let ev = sample_event(OpKind::Set, "x", Some("1"), 10);
         // Ensure same op id for duplicate
         let op_id = ev.op_id;
         applier.apply(&ev);
@@ -296,7 +525,7 @@ mod tests {
         let mut applier = LocalApplier::new();
         // Newer event arrives first
         let ev_new = sample_event(OpKind::Set, "c", Some("new"), 200);
-        let mut ev_old = sample_event(OpKind::Set, "c", Some("old"), 100);
+        let ev_old = sample_event(OpKind::Set, "c", Some("old"), 100);
         applier.apply(&ev_new);
         applier.apply(&ev_old); // should be ignored by LWW
         assert_eq!(applier.store.get("c").cloned(), Some("new".into()));
@@ -308,6 +537,7 @@ mod tests {
         applier2.apply(&ev_new2);
         assert_eq!(applier2.store.get("d").cloned(), Some("new".into()));
     }
+    #[test]
     fn mixed_codec_interop() {
     let ev = sample_event(OpKind::Set, "k", Some("v"), 1_000);
     let j = ev.to_json().unwrap();
@@ -327,17 +557,17 @@ fn corrupted_payload_rejected() {
 #[test]
 fn non_utf8_value_safe_handling() {
     let mut applier = LocalApplier::new();
-    let mut bytes = vec![0, 159, 146, 150]; // invalid UTF-8
+    let bytes = vec![0, 159, 146, 150]; // invalid UTF-8
     let ev = ChangeEvent::new(1, OpKind::Set, "bin", Some(bytes.clone()), 5, "A", None, None);
     applier.apply(&ev);
     // LocalApplier stringify non-UTF8 via base64 fallback
     let got = applier.store.get("bin").unwrap();
-    assert_eq!(got, &base64::encode(&bytes));
+    assert_eq!(got, &base64::engine::general_purpose::STANDARD.encode(&bytes));
 }
 #[test]
 fn idempotency_burst_duplicates() {
     let mut applier = LocalApplier::new();
-    let mut ev = sample_event(OpKind::Set, "dup", Some("1"), 10);
+    let ev = sample_event(OpKind::Set, "dup", Some("1"), 10);
     let op_id = ev.op_id;
     for _ in 0..10 { applier.apply(&ev); }
     assert_eq!(applier.store.get("dup").cloned(), Some("1".into()));
@@ -358,8 +588,8 @@ fn lww_clock_skew_no_overwrite() {
 #[test]
 fn same_timestamp_tie_break_by_op_id() {
     let mut applier = LocalApplier::new();
-    let mut ev1 = sample_event(OpKind::Set, "tie", Some("A"), 500);
-    let mut ev2 = sample_event(OpKind::Set, "tie", Some("B"), 500);
+    let ev1 = sample_event(OpKind::Set, "tie", Some("A"), 500);
+    let ev2 = sample_event(OpKind::Set, "tie", Some("B"), 500);
     // Definition: choose the event with the lexicographically larger op_id
     let winner_is_ev2 = ev2.op_id > ev1.op_id;
     applier.apply(&ev1);
@@ -391,14 +621,14 @@ fn delete_lww_behavior() {
     let mut a = LocalApplier::new();
     a.apply(&sample_event(OpKind::Set, "z", Some("keep"), 10));
     a.apply(&sample_event(OpKind::Del, "z", None, 11));
-    assert!(a.store.get("z").is_none());
+    assert!(!a.store.contains_key("z"));
 }
 #[test]
 fn missing_value_for_non_del_is_ignored() {
     let mut a = LocalApplier::new();
     let ev = ChangeEvent::new(1, OpKind::Set, "m", None, 5, "node", None, None);
     a.apply(&ev);
-    assert!(a.store.get("m").is_none());
+    assert!(!a.store.contains_key("m"));
 }
 #[test]
 fn large_payload_roundtrip() {
@@ -457,4 +687,81 @@ fn ttl_is_advisory_only() {
     assert_eq!(a.store.get("ttl").cloned(), Some("x".into()));
 }
 
+#[test]
+fn binary_key_and_value_round_trip_exactly() {
+    let key = "k\0ey\u{1}\u{7f}é".to_string();
+    let val = vec![0, 0xff, 0xfe, b'a', 0, 0xc3, 0x28, 0x80];
+    let ev = ChangeEvent::new(1, OpKind::Set, key.clone(), Some(val.clone()), 7, "nodeA", None, None);
+    for codec in [ChangeCodec::Json, ChangeCodec::Cbor, ChangeCodec::Bincode] {
+        let decoded = ChangeEvent::decode_any(&codec.encode(&ev).unwrap()).unwrap();
+        assert_eq!((decoded.key.as_str(), decoded.val.as_deref()), (key.as_str(), Some(&val[..])), "{:?}", codec);
+        assert_eq!(decoded, ev);
+    }
+    // CBOR carries the raw bytes; JSON keeps the text key and the byte array it always had
+    let cbor = ev.to_cbor().unwrap();
+    assert!(cbor.windows(val.len()).any(|w| w == &val[..]));
+    let json: serde_json::Value = serde_json::from_slice(&ev.to_json().unwrap()).unwrap();
+    assert_eq!(json["val"], serde_json::json!(val));
+    assert_eq!(json["key"], key.as_str());
+    let before_binary_framing = serde_json::to_vec(&serde_json::json!({
+        "v": 1, "op": "set", "key": "k", "val": [104, 105], "ts": 1, "src": "n", "op_id": ev.op_id,
+    }))
+    .unwrap();
+    let old = ChangeEvent::from_json(&before_binary_framing).unwrap();
+    assert_eq!((old.key.as_str(), old.val.as_deref()), ("k", Some(&b"hi"[..])));
+}
+#[test]
+fn older_cbor_events_still_decode() {
+    use serde_cbor::Value;
+    let ev = sample_event(OpKind::Set, "k", Some("hi"), 9);
+    let Value::Map(mut fields) = serde_cbor::value::to_value(&ev).unwrap() else { panic!("map") };
+    // Before binary framing: text key, value as an array of numbers
+    fields.insert(Value::Text("key".into()), Value::Text("k".into()));
+    fields.insert(
+        Value::Text("val".into()),
+        Value::Array(b"hi".iter().map(|b| Value::Integer(*b as i128)).collect()),
+    );
+    let legacy = serde_cbor::to_vec(&Value::Map(fields)).unwrap();
+    assert_eq!(ChangeEvent::decode_any(&legacy).unwrap(), ev);
+}
+#[test]
+fn malformed_events_rejected() {
+    use serde_cbor::Value;
+    let set = sample_event(OpKind::Set, "k", Some("v"), 1);
+    let reject = |ev: &ChangeEvent| ChangeEvent::decode_any(&ev.to_cbor().unwrap()).unwrap_err();
+    assert!(reject(&ChangeEvent { v: 0, ..set.clone() }).contains("v:0 min_v:1"));
+    assert!(reject(&ChangeEvent { v: 3, min_v: 2, ..set.clone() }).contains("needs schema version 2"));
+    assert!(reject(&ChangeEvent { key: String::new(), ..set.clone() }).contains("empty key"));
+    assert!(reject(&ChangeEvent { val: None, ..set.clone() }).contains("no value"));
+    assert!(reject(&sample_event(OpKind::Ack, "k", None, 1)).contains("does not name a node"));
+    let lone = EventGroup { id: set.op_id, size: 1 };
+    assert!(reject(&ChangeEvent { group: Some(lone), ..set.clone() }).contains("fewer than two"));
+    assert!(ChangeEvent::decode_any(&sample_event(OpKind::Del, "k", None, 1).to_cbor().unwrap()).is_ok());
+
+    // A JSON value that is not an array of bytes, and CBOR key bytes that are not UTF-8
+    let mut json: serde_json::Value = serde_json::from_slice(&set.to_json().unwrap()).unwrap();
+    json["val"] = "dg==".into();
+    assert!(ChangeEvent::decode_any(&serde_json::to_vec(&json).unwrap()).is_err());
+    json["val"] = serde_json::json!([256]);
+    assert!(ChangeEvent::decode_any(&serde_json::to_vec(&json).unwrap()).is_err());
+    let Value::Map(mut fields) = serde_cbor::value::to_value(&set).unwrap() else { panic!("map") };
+    fields.insert(Value::Text("key".into()), Value::Bytes(vec![0xff, 0xfe]));
+    assert!(ChangeEvent::decode_any(&serde_cbor::to_vec(&Value::Map(fields)).unwrap()).is_err());
+}
+#[test]
+fn future_schema_versions_apply_their_known_fields() {
+    use serde_cbor::Value;
+    let ev = sample_event(OpKind::Set, "k", Some("v"), 9);
+    let Value::Map(mut fields) = serde_cbor::value::to_value(&ev).unwrap() else { panic!("map") };
+    fields.insert(Value::Text("v".into()), Value::Integer(SCHEMA_VERSION as i128 + 1));
+    fields.insert(Value::Text("vclock".into()), Value::Array(vec![Value::Integer(3)]));
+    let future = serde_cbor::to_vec(&Value::Map(fields.clone())).unwrap();
+    assert_eq!(ChangeEvent::decode_any(&future).unwrap(), ChangeEvent { v: SCHEMA_VERSION + 1, ..ev.clone() });
+
+    // Events from before `min_v` still decode
+    fields.remove(&Value::Text("min_v".into()));
+    fields.remove(&Value::Text("vclock".into()));
+    fields.insert(Value::Text("v".into()), Value::Integer(1));
+    assert_eq!(ChangeEvent::decode_any(&serde_cbor::to_vec(&Value::Map(fields)).unwrap()).unwrap(), ev);
+}
 }
@@ -0,0 +1,152 @@
+//! # Primary / Secondary Roles (`cluster.role`)
+//!
+//! For a leader-based topology, one node is the `primary` and takes client
+//! writes; `secondary` nodes serve reads and receive the primary's changes
+//! through replication. A client write (any command that replicates, see
+//! `EXPLAIN`) sent to a secondary is either:
+//!
+//! - **forwarded** (`cluster.forward_writes = true`): sent as-is to
+//!   `cluster.primary_addr`, and the primary's one-line reply is returned
+//! - **rejected**: `ERROR READONLY writes go to the primary <addr>`
+//!
+//! Each client connection opens its own connection to the primary on its
+//! first forwarded write and reuses it. Streamed uploads (`SET key STREAM`)
+//! are not forwarded. A primary that requires `AUTH` is sent
+//! `AUTH <cluster.primary_password>` first on each new connection (for
+//! `auth.users_file`, the setting holds `<user> <password>`); a refused
+//! `AUTH` fails the forwarded write.
+
+use anyhow::{anyhow, Context, Result};
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::net::tcp::{OwnedReadHalf, OwnedWriteHalf};
+use tokio::net::TcpStream;
+
+/// Role of this node (`cluster.role`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Role {
+    #[default]
+    Primary,
+    Secondary,
+}
+
+/// A connection to the primary, opened on first use.
+pub struct PrimaryLink {
+    addr: String,
+    timeout: Duration,
+    /// Sent as `AUTH <password>` on connect
+    password: Option<String>,
+    conn: Option<(BufReader<OwnedReadHalf>, OwnedWriteHalf)>,
+}
+
+impl PrimaryLink {
+    pub fn new(addr: &str, timeout: Duration) -> Self {
+        Self { addr: addr.to_string(), timeout, password: None, conn: None }
+    }
+
+    /// Authenticate each new connection with `AUTH <password>`.
+    pub fn with_password(mut self, password: Option<String>) -> Self {
+        self.password = password;
+        self
+    }
+
+    /// Send one request line to the primary and return its reply line
+    /// (with CRLF). The connection is dropped on any error and reopened by
+    /// the next call.
+    pub async fn forward(&mut self, request_line: &str) -> Result<String> {
+        let result = tokio::time::timeout(self.timeout, self.round_trip(request_line))
+            .await
+            .unwrap_or_else(|_| Err(anyhow!("primary {} timed out after {:?}", self.addr, self.timeout)));
+        if result.is_err() {
+            self.conn = None;
+        }
+        result
+    }
+
+    async fn round_trip(&mut self, request_line: &str) -> Result<String> {
+        if self.conn.is_none() {
+            let stream = TcpStream::connect(&self.addr).await.with_context(|| format!("connect {}", self.addr))?;
+            let (r, w) = stream.into_split();
+            self.conn = Some((BufReader::new(r), w));
+            if let Some(password) = self.password.clone() {
+                let reply = self.send(&format!("AUTH {}", password)).await?;
+                if reply != "OK\r\n" {
+                    return Err(anyhow!("primary {} refused AUTH: {}", self.addr, reply.trim_end()));
+                }
+            }
+        }
+        self.send(request_line).await
+    }
+
+    /// Write one line on the open connection and read the reply line.
+    async fn send(&mut self, request_line: &str) -> Result<String> {
+        let (reader, writer) = self.conn.as_mut().expect("connected by round_trip");
+        let line = format!("{}\r\n", request_line.trim_end_matches(['\r', '\n']));
+        writer.write_all(line.as_bytes()).await.context("write to primary")?;
+        let mut reply = String::new();
+        if reader.read_line(&mut reply).await.context("read from primary")? == 0 {
+            return Err(anyhow!("primary {} closed the connection", self.addr));
+        }
+        Ok(reply)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::net::TcpListener;
+
+    #[tokio::test]
+    async fn test_link_reconnects_after_primary_drops_it() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        tokio::spawn(async move {
+            // First connection: answer once, then hang up; second: answer once
+            for reply in ["OK\r\n", "DELETED\r\n"] {
+                let (socket, _) = listener.accept().await.unwrap();
+                let (r, mut w) = socket.into_split();
+                let mut line = String::new();
+                BufReader::new(r).read_line(&mut line).await.unwrap();
+                assert!(line.ends_with("\r\n"), "{:?}", line);
+                w.write_all(reply.as_bytes()).await.unwrap();
+            }
+        });
+
+        let mut link = PrimaryLink::new(&addr, Duration::from_secs(5));
+        assert_eq!(link.forward("SET k v").await.unwrap(), "OK\r\n");
+        assert!(link.forward("SET k v2\r\n").await.is_err(), "primary hung up");
+        assert_eq!(link.forward("DEL k\r\n").await.unwrap(), "DELETED\r\n");
+    }
+
+    #[tokio::test]
+    async fn test_link_authenticates_each_new_connection() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        tokio::spawn(async move {
+            // First connection: right password; second: refused
+            for auth_reply in ["OK\r\n", "ERROR invalid password\r\n"] {
+                let (socket, _) = listener.accept().await.unwrap();
+                let (r, mut w) = socket.into_split();
+                let mut reader = BufReader::new(r);
+                let mut line = String::new();
+                reader.read_line(&mut line).await.unwrap();
+                assert_eq!(line, "AUTH app s3cret\r\n");
+                w.write_all(auth_reply.as_bytes()).await.unwrap();
+                line.clear();
+                if reader.read_line(&mut line).await.unwrap() > 0 {
+                    assert_eq!(line, "SET k v\r\n");
+                    w.write_all(b"OK\r\n").await.unwrap();
+                }
+            }
+        });
+
+        let mut link = PrimaryLink::new(&addr, Duration::from_secs(5)).with_password(Some("app s3cret".to_string()));
+        assert_eq!(link.forward("SET k v").await.unwrap(), "OK\r\n");
+        link.conn = None;
+        let err = link.forward("SET k v").await.unwrap_err().to_string();
+        assert!(err.contains("refused AUTH: ERROR invalid password"), "{}", err);
+        assert!(link.conn.is_none(), "a refused connection is not reused");
+    }
+}
@@ -0,0 +1,72 @@
+//! # Reply Compression
+//!
+//! Optional gzip compression of large `GET` replies, for clients on
+//! high-latency or low-bandwidth links. A connection opts in with
+//! `CLIENT COMPRESS ON`; replies stay uncompressed by default.
+//!
+//! ## Wire Format
+//!
+//! Values at or above `server.compression_threshold` bytes are sent as
+//!
+//! ```text
+//! VALUE_GZIP <original_len> <base64(gzip(value))>\r\n
+//! ```
+//!
+//! Base64 keeps the reply a single text line, so line-based clients need no
+//! framing changes; they only have to recognize the `VALUE_GZIP` header.
+//! Smaller values are still sent as plain `VALUE <data>`.
+
+use anyhow::Result;
+use base64::{engine::general_purpose::STANDARD, Engine as _};
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use std::io::Write;
+
+/// Format a `GET` hit, compressing when `compress` is set and the value is
+/// at least `threshold` bytes.
+pub fn value_reply(value: &str, compress: bool, threshold: usize) -> String {
+    if compress && value.len() >= threshold {
+        match gzip_base64(value) {
+            Ok(encoded) => return format!("VALUE_GZIP {} {}\r\n", value.len(), encoded),
+            Err(e) => log::warn!("Reply compression failed, sending uncompressed: {}", e),
+        }
+    }
+    format!("VALUE {}\r\n", value)
+}
+
+/// Gzip `data` and base64-encode the result.
+pub fn gzip_base64(data: &str) -> Result<String> {
+    let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+    encoder.write_all(data.as_bytes())?;
+    Ok(STANDARD.encode(encoder.finish()?))
+}
+
+/// Reverse of `gzip_base64` (what a client does with a `VALUE_GZIP` payload).
+#[cfg(test)]
+pub fn gunzip_base64(encoded: &str) -> Result<String> {
+    use std::io::Read;
+    let compressed = STANDARD.decode(encoded)?;
+    let mut out = String::new();
+    flate2::read::GzDecoder::new(compressed.as_slice()).read_to_string(&mut out)?;
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_threshold_and_opt_in() {
+        let small = "abc";
+        let large = "abc".repeat(1000);
+        assert_eq!(value_reply(small, true, 100), "VALUE abc\r\n");
+        assert_eq!(value_reply(&large, false, 100), format!("VALUE {}\r\n", large));
+
+        let reply = value_reply(&large, true, 100);
+        let parts: Vec<&str> = reply.trim_end().split(' ').collect();
+        assert_eq!(parts[0], "VALUE_GZIP");
+        assert_eq!(parts[1], "3000");
+        assert!(reply.len() < large.len() / 10, "reply is {} bytes", reply.len());
+        assert_eq!(gunzip_base64(parts[2]).unwrap(), large);
+    }
+}
@@ -14,19 +14,127 @@
 //! storage_path = "data"
 //! sync_interval_seconds = 60
 //!
+//! [server]
+//! allowed_cidrs = ["10.0.0.0/8", "127.0.0.1"]
+//! proxy_protocol = false
+//! compression_threshold = 1024
+//! transport_compression = true   # let HELLO COMPRESS zlib compress a whole connection
+//! # password = "change-me"
+//! idle_timeout_secs = 0
+//! unauth_idle_timeout_secs = 10
+//! stream_chunk_bytes = 65536
+//! strict_crlf = false
+//! max_args = 1048576             # words after the command name; longer lines are refused unparsed
+//! listen_backlog = 1024
+//! reply_batch_max = 32            # pipelined replies per write; 1 = no coalescing
+//! test_commands = false          # VERIFY CONSISTENT, DEBUG SLEEP, for CI only
+//! command_timeout_ms = 0         # abort read-only commands after this long; 0 = off
+//! require_utf8_keys = false      # refuse writes of binary (non-UTF-8) keys with ERR_ENCODING
+//! max_stream_value_bytes = 67108864
+//! databases = 16                 # SELECT 0..databases-1
+//! # tls_cert_path = "certs/server.pem"   # serve TLS; CONFIG RELOAD TLS re-reads both
+//! # tls_key_path = "certs/server.key"
+//!
+//! [storage]
+//! hash_fn = "xxhash"
+//! lock_stripes = 0
+//! # cold_tier_path = "data/cold"
+//! hot_tier_max_bytes = 0
+//! max_ttl_seconds = 0
+//! reject_ttl_above_max = false
+//! default_ttl_seconds = 0
+//! write_reject_bytes = 0         # refuse writes above this memory use
+//! wal_format = "bincode"         # WAL records: or "msgpack"
+//! # wal_path = "data/wal.log"    # log writes and replay them on start
+//! snapshot_interval_seconds = 0  # > 0: DUMP to {storage_path}/snapshots/ this often
+//! snapshot_retain = 0            # snapshot files kept; 0 = all
+//! group_commit_window_us = 0     # > 0: DURABLE writes within this window share one fsync
+//! max_dirty_writes = 0           # > 0: fsync the WAL once more writes than this are unsynced
+//!
+//! [merkle]
+//! sync_timeout_ms = 5000
+//! sync_concurrency = 1           # anti-entropy rounds run in parallel, each with its own peer
+//! subscribe_interval_ms = 1000
+//! subscribe_buffer = 0           # > 0: root changes kept for a dropped SUBSCRIBE MERKLE to resume
+//! subscribe_resume_seconds = 30  # how long a dropped subscription waits to be resumed
+//! gc_interval_seconds = 300      # 0 = only on MERKLE GC
+//! num_buckets = 0                # 0 = one leaf per key
+//!
+//! [auth]
+//! # users_file = "users.txt"
+//!
+//! [index]
+//! value_prefix_enabled = false
+//! value_prefix_max_len = 64
+//!
+//! [hooks]
+//! # url = "http://127.0.0.1:8080/merkle-hook"
+//! include_prefixes = ["user:"]
+//! exclude_prefixes = []
+//! queue_limit = 1000
+//! max_retries = 3
+//! retry_backoff_ms = 100
+//!
+//! [cluster]
+//! role = "primary"               # or "secondary"
+//! # primary_addr = "10.0.0.1:7379"
+//! # primary_password = "change-me"   # AUTH sent to a primary that requires it
+//! forward_writes = true          # secondary: forward writes instead of rejecting them
+//! forward_timeout_ms = 5000
+//!
+//! [metrics]
+//! # dump_path = "data/metrics.prom"
+//! dump_interval_seconds = 60     # 0 = only on TASKS RUN metrics_dump
+//! dump_format = "text"           # or "json"
+//! # unix_socket_path = "/run/merklekv/metrics.sock"   # serve GET /metrics on this socket
+//!
 //! [replication]
 //! enabled = true
 //! mqtt_broker = "localhost"
 //! mqtt_port = 1883
 //! topic_prefix = "merkle_kv"
+//! topic_per_database = false     # true: database N publishes to {topic_prefix}/db{N}/events
+//! # mqtt_ca_path = "certs/broker-ca.pem"  # connect to the broker over TLS
 //! client_id = "node1"
+//! publish_lazy_expiry = false
+//! tombstone_ttl_seconds = 86400
+//! include_prefixes = []          # empty = every key replicates
+//! exclude_prefixes = ["local:"]  # node-local keys, also kept out of Merkle sync
+//! pause_queue_limit = 10000
+//! dedup_capacity = 100000
+//! causality_log_size = 10000     # applied events kept for REPLICATION CHECK CAUSALITY (0 = off)
+//! apply_queue_size = 1024        # received events waiting to be applied; the oldest are dropped beyond it
+//! delta_min_bytes = 0            # 0 = always replicate full values
+//! delta_cache_bytes = 67108864
+//! token_wait_ms = 1000
+//! peer_list = ["10.0.0.2:7379"]  # peers counted by `SET ... CL=quorum|all`
+//! ack_timeout_ms = 1000
+//! ack_all_writes = false         # acks for every write, reported by OBJECT ACKS
+//! max_lag_seconds = 0            # > 0: /ready fails while replication lags more
+//!
+//! [anti_entropy]
+//! enabled = true
+//! interval_seconds = 60
+//! peer_list = ["10.0.0.2:7379", "10.1.0.2:7379"]
+//!
+//! # Per-peer sync intervals; unlisted peers use `sync_interval_seconds`
+//! [anti_entropy.peer_intervals]
+//! "10.0.0.2:7379" = 10     # same region
+//! "10.1.0.2:7379" = 300    # remote region
 //! ```
 
-use anyhow::Result;
+use anyhow::{Context, Result};
 use config::{Config as ConfigLib, File};
 use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
 use std::path::Path;
 
+use crate::cluster::Role;
+use crate::metrics::MetricsFormat;
+use crate::net_addr;
+use crate::store::wal::WalFormat;
+use crate::store::HashFn;
+
 /// Configuration for anti-entropy synchronization.
 /// Anti-entropy helps ensure eventual consistency between nodes by periodically
 /// reconciling differences in their data sets.
@@ -36,6 +144,10 @@ pub struct AntiEntropyConfig {
     pub interval_seconds: u64,
     #[serde(default)]
     pub peer_list: Vec<String>,
+    /// Sync interval (seconds) for individual peers, keyed by their
+    /// `peer_list` entry. Peers not listed use `sync_interval_seconds`.
+    #[serde(default)]
+    pub peer_intervals: BTreeMap<String, u64>,
 }
 fn ae_is_disabled(ae: &AntiEntropyConfig) -> bool {
     !ae.enabled && ae.peer_list.is_empty()
@@ -46,10 +158,12 @@ fn ae_is_disabled(ae: &AntiEntropyConfig) -> bool {
 /// storage settings, and replication parameters.
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Config {
-    /// IP address to bind the TCP server to (e.g., "127.0.0.1" or "0.0.0.0")
+    /// IP address or hostname to bind the TCP server to (e.g., "127.0.0.1", "0.0.0.0",
+    /// "::1"). "::" binds dual-stack and also accepts IPv4 clients.
     pub host: String,
 
-    /// Port number for the TCP server to listen on (e.g., 7379)
+    /// Port number for the TCP server to listen on (e.g., 7379); 0 binds a
+    /// free port chosen by the OS (see `Server::local_addr`)
     pub port: u16,
 
     /// Path where data files should be stored (currently unused as storage is in-memory)
@@ -62,6 +176,38 @@ pub struct Config {
     /// - "sled": Persistent storage using sled embedded database
     pub engine: String,
 
+    /// TCP server options (access control, ...)
+    #[serde(default)]
+    pub server: ServerConfig,
+
+    /// Storage tuning options (key placement, ...)
+    #[serde(default)]
+    pub storage: StorageConfig,
+
+    /// Merkle tree / anti-entropy sync options
+    #[serde(default)]
+    pub merkle: MerkleConfig,
+
+    /// Client authentication backends
+    #[serde(default)]
+    pub auth: AuthConfig,
+
+    /// Secondary indexes over values
+    #[serde(default)]
+    pub index: IndexConfig,
+
+    /// HTTP webhook notified of key changes
+    #[serde(default)]
+    pub hooks: HooksConfig,
+
+    /// Primary / secondary roles
+    #[serde(default)]
+    pub cluster: ClusterConfig,
+
+    /// Metrics snapshots written to a file
+    #[serde(default)]
+    pub metrics: MetricsConfig,
+
     /// Configuration for MQTT-based replication between nodes
     pub replication: ReplicationConfig,
 
@@ -73,6 +219,514 @@ pub struct Config {
     pub anti_entropy: AntiEntropyConfig,
 }
 
+/// TCP server options.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ServerConfig {
+    /// CIDR blocks allowed to connect (e.g. "10.0.0.0/8", "::1").
+    /// Empty means every client is accepted.
+    #[serde(default)]
+    pub allowed_cidrs: Vec<String>,
+
+    /// Expect a PROXY protocol v1 header on every connection and use the
+    /// client address it carries (for the allowlist, logs and CLIENT LIST).
+    #[serde(default)]
+    pub proxy_protocol: bool,
+
+    /// Minimum value size (bytes) for a GET reply to be compressed, on
+    /// connections that opted in with `CLIENT COMPRESS ON`.
+    #[serde(default = "default_compression_threshold")]
+    pub compression_threshold: usize,
+
+    /// Accept `HELLO COMPRESS zlib`, compressing everything a connection
+    /// sends and receives afterwards. Off: `HELLO` always answers
+    /// `compression:none`.
+    #[serde(default = "default_transport_compression")]
+    pub transport_compression: bool,
+
+    /// Password clients must send with `AUTH` before any other command.
+    /// Unset (and no `auth.users_file`) means no authentication. Anti-entropy
+    /// sync does not send AUTH, so nodes used as sync peers must leave this unset.
+    #[serde(default)]
+    pub password: Option<String>,
+
+    /// Close authenticated connections idle for this many seconds (`0` = never).
+    #[serde(default)]
+    pub idle_timeout_secs: u64,
+
+    /// Close connections that have not authenticated within this many idle
+    /// seconds (`0` = never). Only applies when authentication is on; keep it
+    /// short so unauthenticated clients cannot pin connections.
+    #[serde(default = "default_unauth_idle_timeout_secs")]
+    pub unauth_idle_timeout_secs: u64,
+
+    /// Frame size for `GET key STREAM` replies, and the largest frame
+    /// accepted for `SET key STREAM <len>` uploads.
+    #[serde(default = "default_stream_chunk_bytes")]
+    pub stream_chunk_bytes: usize,
+
+    /// Largest `<len>` accepted by `SET key STREAM <len>`.
+    #[serde(default = "default_max_stream_value_bytes")]
+    pub max_stream_value_bytes: usize,
+
+    /// Number of logical databases a connection can `SELECT` (0 is the
+    /// main, replicated one); `SELECT n` for `n >= databases` is refused.
+    #[serde(default = "default_databases")]
+    pub databases: usize,
+
+    /// Reject command lines not terminated by `\r\n`. Off by default, so
+    /// `\n`-only clients work too.
+    #[serde(default)]
+    pub strict_crlf: bool,
+
+    /// Most whitespace-separated words after the command name (a value with
+    /// spaces counts each word). Longer commands are refused before any
+    /// argument is collected.
+    #[serde(default = "default_max_args")]
+    pub max_args: usize,
+
+    /// Pending-connection queue of the listening socket (1..=65535). Raise it
+    /// if reconnect bursts overflow it; the kernel caps it at `somaxconn`.
+    #[serde(default = "default_listen_backlog")]
+    pub listen_backlog: u32,
+
+    /// Most replies of pipelined commands written together in one `write`
+    /// (`1` = every reply on its own). Replies are written as soon as no
+    /// further command is waiting in the input buffer.
+    #[serde(default = "default_reply_batch_max")]
+    pub reply_batch_max: usize,
+
+    /// Serve test-only commands (`VERIFY CONSISTENT`, `DEBUG SLEEP`). Meant
+    /// for CI; leave off in production.
+    #[serde(default)]
+    pub test_commands: bool,
+
+    /// Reply `ERROR ERR_TIMEOUT` to a read-only command still running after
+    /// this many milliseconds (`0` = no limit). Writes always run to the end.
+    #[serde(default)]
+    pub command_timeout_ms: u64,
+
+    /// Refuse writes of keys that are not valid UTF-8 with `ERR_ENCODING`;
+    /// otherwise binary keys are stored and returned byte for byte.
+    #[serde(default)]
+    pub require_utf8_keys: bool,
+
+    /// PEM certificate chain served to clients; with `tls_key_path`, every
+    /// client connection is TLS. `CONFIG RELOAD TLS` re-reads both files.
+    #[serde(default)]
+    pub tls_cert_path: Option<String>,
+
+    /// PEM private key (PKCS#8, PKCS#1 or SEC1) of `tls_cert_path`.
+    #[serde(default)]
+    pub tls_key_path: Option<String>,
+}
+
+fn default_compression_threshold() -> usize {
+    1024
+}
+
+fn default_transport_compression() -> bool {
+    true
+}
+
+fn default_unauth_idle_timeout_secs() -> u64 {
+    10
+}
+
+fn default_stream_chunk_bytes() -> usize {
+    64 * 1024
+}
+
+fn default_max_stream_value_bytes() -> usize {
+    64 * 1024 * 1024
+}
+
+fn default_databases() -> usize {
+    16
+}
+
+fn default_max_args() -> usize {
+    1024 * 1024
+}
+
+fn default_listen_backlog() -> u32 {
+    1024
+}
+
+fn default_reply_batch_max() -> usize {
+    32
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        Self {
+            allowed_cidrs: Vec::new(),
+            proxy_protocol: false,
+            compression_threshold: default_compression_threshold(),
+            transport_compression: default_transport_compression(),
+            password: None,
+            idle_timeout_secs: 0,
+            unauth_idle_timeout_secs: default_unauth_idle_timeout_secs(),
+            stream_chunk_bytes: default_stream_chunk_bytes(),
+            max_stream_value_bytes: default_max_stream_value_bytes(),
+            databases: default_databases(),
+            strict_crlf: false,
+            max_args: default_max_args(),
+            listen_backlog: default_listen_backlog(),
+            reply_batch_max: default_reply_batch_max(),
+            test_commands: false,
+            command_timeout_ms: 0,
+            require_utf8_keys: false,
+            tls_cert_path: None,
+            tls_key_path: None,
+        }
+    }
+}
+
+/// Storage tuning options.
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+pub struct StorageConfig {
+    /// Hash function used for shard and Merkle bucket placement
+    /// ("xxhash", "siphash" or "fnv"). Must be identical on all nodes.
+    #[serde(default)]
+    pub hash_fn: HashFn,
+
+    /// Per-key write lock stripes for the "rwlock" engine (`0` = disabled).
+    /// Lets read-modify-write commands on different keys of the same shard
+    /// run concurrently, at the cost of a second lock per write.
+    #[serde(default)]
+    pub lock_stripes: usize,
+
+    /// Directory of the on-disk cold tier for the in-memory engines. Together
+    /// with a non-zero `hot_tier_max_bytes`, least recently used keys are
+    /// spilled there instead of growing memory. Cleared on every start.
+    #[serde(default)]
+    pub cold_tier_path: Option<String>,
+
+    /// Key + value bytes kept in memory before spilling to the cold tier (`0` = no tiering).
+    #[serde(default)]
+    pub hot_tier_max_bytes: usize,
+
+    /// Longest TTL a client may set with `SET ... EX|PX` or `EXPIRE`
+    /// (`0` = no cap). Longer TTLs are clamped, or rejected with
+    /// `reject_ttl_above_max`.
+    #[serde(default)]
+    pub max_ttl_seconds: u64,
+
+    /// Reject TTLs above `max_ttl_seconds` instead of clamping them
+    #[serde(default)]
+    pub reject_ttl_above_max: bool,
+
+    /// TTL given to keys written by a plain `SET` (`0` = they never expire)
+    #[serde(default)]
+    pub default_ttl_seconds: u64,
+
+    /// Store memory use (as reported by `MEMORY`) above which writes are
+    /// refused with `ERR_OOM` while reads and deletes still run (`0` = never).
+    /// Independent of the cold tier, which moves data rather than refusing it.
+    #[serde(default)]
+    pub write_reject_bytes: usize,
+
+    /// Record encoding of the write-ahead log ("bincode" or "msgpack"),
+    /// including the copy `PERSISTENCE OFF` rewrites it to. Backup snapshots
+    /// (`snapshot_interval_seconds`) are always DUMP text. Logs remember
+    /// their format; replaying one with another fails.
+    #[serde(default)]
+    pub wal_format: WalFormat,
+
+    /// Write-ahead log of the in-memory engines: every write is appended
+    /// here and the log is replayed on start. `PERSISTENCE OFF|ON` pauses
+    /// and resumes it at runtime. Ignored by the sled engine.
+    #[serde(default)]
+    pub wal_path: Option<String>,
+
+    /// Seconds between backup snapshots written to `{storage_path}/snapshots`
+    /// (`0` = no snapshots). `TASKS RUN snapshot` writes one at any time.
+    #[serde(default)]
+    pub snapshot_interval_seconds: u64,
+
+    /// Number of backup snapshots kept; older ones are deleted (`0` = keep all)
+    #[serde(default)]
+    pub snapshot_retain: usize,
+
+    /// Microseconds a `DURABLE` write waits for others to share its flush
+    /// (`0` = every durable write is flushed on its own)
+    #[serde(default)]
+    pub group_commit_window_us: u64,
+
+    /// Unsynced WAL writes allowed before the next write fsyncs the log,
+    /// bounding how many a crash can lose (`0` = only `SYNC` / `DURABLE` fsync)
+    #[serde(default)]
+    pub max_dirty_writes: u64,
+}
+
+impl StorageConfig {
+    /// Apply `max_ttl_seconds` to a requested TTL.
+    ///
+    /// # Returns
+    /// * `Result<u64>` - The TTL to use, in milliseconds; error if it is above
+    ///   the cap and `reject_ttl_above_max` is set
+    pub fn cap_ttl_ms(&self, ttl_ms: u64) -> Result<u64> {
+        let max_ms = self.max_ttl_seconds.saturating_mul(1000);
+        if self.max_ttl_seconds == 0 || ttl_ms <= max_ms {
+            return Ok(ttl_ms);
+        }
+        if self.reject_ttl_above_max {
+            anyhow::bail!("TTL exceeds storage.max_ttl_seconds ({})", self.max_ttl_seconds);
+        }
+        Ok(max_ms)
+    }
+}
+
+/// Merkle tree / anti-entropy sync options.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MerkleConfig {
+    /// Deadline (milliseconds) for each sync RPC to a peer (connect + request + reply).
+    /// On timeout the sync round with that peer is abandoned and retried next interval.
+    #[serde(default = "default_sync_timeout_ms")]
+    pub sync_timeout_ms: u64,
+
+    /// Anti-entropy rounds (one peer each) allowed to run at the same time;
+    /// `1` syncs the peers one after the other.
+    #[serde(default = "default_sync_concurrency")]
+    pub sync_concurrency: usize,
+
+    /// Minimum delay (milliseconds) between two root-change notifications
+    /// pushed to a `SUBSCRIBE MERKLE` connection; bursts of writes coalesce.
+    #[serde(default = "default_subscribe_interval_ms")]
+    pub subscribe_interval_ms: u64,
+
+    /// Root changes recorded for a `SUBSCRIBE MERKLE` connection that
+    /// dropped, replayed when it resumes with its id (`0` = no resuming)
+    #[serde(default)]
+    pub subscribe_buffer: usize,
+
+    /// How long (seconds) a dropped subscription keeps recording and can be resumed
+    #[serde(default = "default_subscribe_resume_seconds")]
+    pub subscribe_resume_seconds: u64,
+
+    /// How often (seconds) the `merkle_gc` task drops tree nodes left behind
+    /// by deleted keys; 0 leaves it to `MERKLE GC` / `TASKS RUN merkle_gc`.
+    #[serde(default = "default_gc_interval_seconds")]
+    pub gc_interval_seconds: u64,
+
+    /// Leaf buckets keys are hashed into (`0` = one leaf per key). Bounds the
+    /// tree depth whatever the key count, at the price of coarser diffs.
+    /// Must be identical on all nodes, like `storage.hash_fn`.
+    #[serde(default)]
+    pub num_buckets: usize,
+}
+
+fn default_gc_interval_seconds() -> u64 {
+    300
+}
+
+fn default_sync_timeout_ms() -> u64 {
+    5000
+}
+
+fn default_sync_concurrency() -> usize {
+    1
+}
+
+fn default_subscribe_interval_ms() -> u64 {
+    1000
+}
+
+fn default_subscribe_resume_seconds() -> u64 {
+    30
+}
+
+impl Default for MerkleConfig {
+    fn default() -> Self {
+        Self {
+            sync_timeout_ms: default_sync_timeout_ms(),
+            sync_concurrency: default_sync_concurrency(),
+            subscribe_interval_ms: default_subscribe_interval_ms(),
+            subscribe_buffer: 0,
+            subscribe_resume_seconds: default_subscribe_resume_seconds(),
+            gc_interval_seconds: default_gc_interval_seconds(),
+            num_buckets: 0,
+        }
+    }
+}
+
+/// Client authentication beyond the single `server.password`.
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+pub struct AuthConfig {
+    /// File of `user:pbkdf2-sha256$<iterations>$<salt>$<digest>` lines for `AUTH <user> <password>`
+    /// (see `auth`). Replaces `server.password`; read once at start.
+    #[serde(default)]
+    pub users_file: Option<String>,
+}
+
+/// Secondary indexes, maintained on every write.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct IndexConfig {
+    /// Index value prefixes so `FINDBYVALUE <prefix>` avoids a full scan.
+    /// Costs memory per key and work on every write; rebuilt on start.
+    #[serde(default)]
+    pub value_prefix_enabled: bool,
+
+    /// Bytes of each value that are indexed; longer query prefixes are
+    /// checked against the stored values
+    #[serde(default = "default_value_prefix_max_len")]
+    pub value_prefix_max_len: usize,
+}
+
+fn default_value_prefix_max_len() -> usize {
+    64
+}
+
+impl Default for IndexConfig {
+    fn default() -> Self {
+        Self {
+            value_prefix_enabled: false,
+            value_prefix_max_len: default_value_prefix_max_len(),
+        }
+    }
+}
+
+/// HTTP webhook called on key changes, for integrating external systems.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HooksConfig {
+    /// `http://host[:port]/path` to POST `{op, key, value, timestamp}` to.
+    /// Unset disables the webhook.
+    #[serde(default)]
+    pub url: Option<String>,
+
+    /// Only keys starting with one of these prefixes are sent (empty = all keys)
+    #[serde(default)]
+    pub include_prefixes: Vec<String>,
+
+    /// Keys starting with one of these prefixes are never sent; wins over include
+    #[serde(default)]
+    pub exclude_prefixes: Vec<String>,
+
+    /// Changes waiting for delivery; further changes are dropped (and logged)
+    /// while the queue is full, so a slow endpoint never blocks writes.
+    #[serde(default = "default_hook_queue_limit")]
+    pub queue_limit: usize,
+
+    /// Delivery attempts after the first failure before a change is dropped
+    #[serde(default = "default_hook_max_retries")]
+    pub max_retries: u32,
+
+    /// Delay before the first retry (milliseconds); doubles on every retry
+    #[serde(default = "default_hook_retry_backoff_ms")]
+    pub retry_backoff_ms: u64,
+}
+
+fn default_hook_queue_limit() -> usize {
+    1000
+}
+
+fn default_hook_max_retries() -> u32 {
+    3
+}
+
+fn default_hook_retry_backoff_ms() -> u64 {
+    100
+}
+
+impl Default for HooksConfig {
+    fn default() -> Self {
+        Self {
+            url: None,
+            include_prefixes: Vec::new(),
+            exclude_prefixes: Vec::new(),
+            queue_limit: default_hook_queue_limit(),
+            max_retries: default_hook_max_retries(),
+            retry_backoff_ms: default_hook_retry_backoff_ms(),
+        }
+    }
+}
+
+/// Leader-based topology: which node takes client writes.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ClusterConfig {
+    /// "primary" takes writes; a "secondary" forwards or rejects them
+    #[serde(default)]
+    pub role: Role,
+
+    /// `host:port` of the primary; required for a secondary
+    #[serde(default)]
+    pub primary_addr: Option<String>,
+
+    /// Sent as `AUTH <primary_password>` before forwarding writes, for a
+    /// primary that requires it (`<user> <password>` with `auth.users_file`)
+    #[serde(default)]
+    pub primary_password: Option<String>,
+
+    /// On a secondary, forward client writes to the primary and return its
+    /// reply instead of rejecting them with `READONLY`
+    #[serde(default = "default_forward_writes")]
+    pub forward_writes: bool,
+
+    /// Longest wait for the primary to answer a forwarded write (milliseconds)
+    #[serde(default = "default_forward_timeout_ms")]
+    pub forward_timeout_ms: u64,
+}
+
+fn default_forward_writes() -> bool {
+    true
+}
+
+fn default_forward_timeout_ms() -> u64 {
+    5000
+}
+
+impl Default for ClusterConfig {
+    fn default() -> Self {
+        Self {
+            role: Role::Primary,
+            primary_addr: None,
+            primary_password: None,
+            forward_writes: default_forward_writes(),
+            forward_timeout_ms: default_forward_timeout_ms(),
+        }
+    }
+}
+
+/// Metrics snapshot files, for deployments without a scraper, and the
+/// scrape socket.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MetricsConfig {
+    /// File the `metrics_dump` task and `METRICS DUMP` rewrite with the
+    /// current metrics (None = no snapshots; `METRICS DUMP` is refused)
+    #[serde(default)]
+    pub dump_path: Option<String>,
+
+    /// How often (seconds) the `metrics_dump` task writes `dump_path`;
+    /// 0 leaves it to `TASKS RUN metrics_dump`
+    #[serde(default = "default_dump_interval_seconds")]
+    pub dump_interval_seconds: u64,
+
+    /// Snapshot format ("text", Prometheus exposition, or "json"); also the
+    /// default of `METRICS DUMP`
+    #[serde(default)]
+    pub dump_format: MetricsFormat,
+
+    /// Unix domain socket to serve `GET /metrics` (and `/metrics.json`) on,
+    /// for a local collector; no TCP port is opened for metrics
+    #[serde(default)]
+    pub unix_socket_path: Option<String>,
+}
+
+fn default_dump_interval_seconds() -> u64 {
+    60
+}
+
+impl Default for MetricsConfig {
+    fn default() -> Self {
+        Self {
+            dump_path: None,
+            dump_interval_seconds: default_dump_interval_seconds(),
+            dump_format: MetricsFormat::default(),
+            unix_socket_path: None,
+        }
+    }
+}
+
 /// Configuration for MQTT-based replication.
 ///
 /// Replication allows multiple MerkleKV nodes to stay synchronized by publishing
@@ -92,6 +746,19 @@ pub struct ReplicationConfig {
     /// Final topics will be like "{topic_prefix}/events"
     pub topic_prefix: String,
 
+    /// Give each database its own topic, `{topic_prefix}/db{N}/events`, and
+    /// replicate the writes of every database, not just database 0. Peers
+    /// apply them to the same database. Off: one `{topic_prefix}/events` topic
+    /// carrying database 0 only. All nodes of a cluster must agree.
+    #[serde(default)]
+    pub topic_per_database: bool,
+
+    /// PEM CA certificates the broker's certificate must chain to; set, the
+    /// broker connection uses TLS. Read when a connection is opened;
+    /// `CONFIG RELOAD TLS` checks the file so a bad CA is reported early.
+    #[serde(default)]
+    pub mqtt_ca_path: Option<String>,
+
     /// Unique identifier for this node in MQTT communications
     /// Should be unique across all nodes in the cluster
     pub client_id: String,
@@ -103,9 +770,119 @@ pub struct ReplicationConfig {
     #[serde(default)]
     pub client_password: Option<String>,
 
-    /// List of peer nodes (host:port) for replication
+    /// List of peer nodes (host:port) for replication. Its length is the
+    /// number of peers `SET ... CL=all` waits for (a majority for `CL=quorum`).
     #[serde(default)]
     pub peer_list: Vec<String>,
+
+    /// Publish a DELETE event when a key expires lazily on read, so peers drop
+    /// it immediately instead of waiting for their own expiry. Off by default
+    /// because it turns reads of expired keys into replicated writes.
+    #[serde(default)]
+    pub publish_lazy_expiry: bool,
+
+    /// Grace period (seconds) a deleted key's tombstone is kept before
+    /// compaction purges it. Anti-entropy sync refuses to resurrect tombstoned
+    /// keys and shares live tombstones with peers, so this must exceed the
+    /// longest time a peer can lag behind.
+    #[serde(default = "default_tombstone_ttl_seconds")]
+    pub tombstone_ttl_seconds: u64,
+
+    /// Only keys starting with one of these prefixes replicate (empty = all keys).
+    #[serde(default)]
+    pub include_prefixes: Vec<String>,
+
+    /// Keys starting with one of these prefixes stay node-local: they are not
+    /// published, not applied from peers and not part of the Merkle tree.
+    /// Takes precedence over `include_prefixes`.
+    #[serde(default)]
+    pub exclude_prefixes: Vec<String>,
+
+    /// Maximum changes queued while replication is paused (`REPLICATION PAUSE`).
+    /// When full the oldest queued change is dropped; anti-entropy sync repairs
+    /// what peers missed.
+    #[serde(default = "default_pause_queue_limit")]
+    pub pause_queue_limit: usize,
+
+    /// Applied event ids remembered to drop redelivered events
+    /// (`REPLICATION DEDUP STATS`). When full the oldest id is forgotten.
+    #[serde(default = "default_dedup_capacity")]
+    pub dedup_capacity: usize,
+
+    /// Applied remote events kept for `REPLICATION CHECK CAUSALITY`; the
+    /// oldest is dropped when full (`0` = keep none)
+    #[serde(default = "default_causality_log_size")]
+    pub causality_log_size: usize,
+
+    /// Received events waiting for the apply loop (rounded up to a power of
+    /// two). When events arrive faster than they apply, the oldest waiting
+    /// ones are dropped and counted in `INFO` `replication_apply_dropped`;
+    /// anti-entropy sync repairs the writes they carried.
+    #[serde(default = "default_apply_queue_size")]
+    pub apply_queue_size: usize,
+
+    /// Values at least this large replicate as deltas against the previously
+    /// published value (see `delta`); 0 disables deltas.
+    #[serde(default)]
+    pub delta_min_bytes: usize,
+
+    /// Memory for the previously published values deltas are made against.
+    /// When full the oldest is dropped and its key's next write goes in full.
+    #[serde(default = "default_delta_cache_bytes")]
+    pub delta_cache_bytes: usize,
+
+    /// How long `GET key WITHTOKEN <t>` waits for the write behind `t` to
+    /// replicate before replying `NOT_CAUGHT_UP` (milliseconds).
+    #[serde(default = "default_token_wait_ms")]
+    pub token_wait_ms: u64,
+
+    /// How long `SET ... CL=quorum|all` waits for peer acks before replying
+    /// `ERR_TIMEOUT` (milliseconds). The write stays applied either way.
+    #[serde(default = "default_ack_timeout_ms")]
+    pub ack_timeout_ms: u64,
+
+    /// Publish every SET / APPEND / PREPEND with `ack` set, so `OBJECT ACKS`
+    /// reports the peers that applied it; replies do not wait for the acks.
+    /// `CL=quorum|all` writes are acked either way. Doubles event traffic.
+    #[serde(default)]
+    pub ack_all_writes: bool,
+
+    /// Replication lag (seconds) above which `/ready` on the metrics socket
+    /// answers `503`, taking the replica out of rotation (`0` = never)
+    #[serde(default)]
+    pub max_lag_seconds: u64,
+}
+
+fn default_ack_timeout_ms() -> u64 {
+    1000
+}
+
+fn default_token_wait_ms() -> u64 {
+    1000
+}
+
+fn default_pause_queue_limit() -> usize {
+    10_000
+}
+
+fn default_dedup_capacity() -> usize {
+    100_000
+}
+
+fn default_causality_log_size() -> usize {
+    10_000
+}
+
+fn default_apply_queue_size() -> usize {
+    1024
+}
+
+fn default_delta_cache_bytes() -> usize {
+    64 * 1024 * 1024
+}
+
+fn default_tombstone_ttl_seconds() -> u64 {
+    86400
 }
 
 impl Config {
@@ -126,9 +903,60 @@ impl Config {
         let settings = ConfigLib::builder().add_source(File::from(path)).build()?;
 
         let config: Config = settings.try_deserialize()?;
+        config.validate()?;
         Ok(config)
     }
+
+    /// Check addresses up front so a typo fails at startup with a clear message.
+    pub fn validate(&self) -> Result<()> {
+        net_addr::validate_host(&self.host).context("invalid `host`")?;
+        for peer in &self.anti_entropy.peer_list {
+            net_addr::split_host_port(peer).context("invalid entry in `anti_entropy.peer_list`")?;
+        }
+        for (peer, secs) in &self.anti_entropy.peer_intervals {
+            if !self.anti_entropy.peer_list.contains(peer) {
+                anyhow::bail!("`anti_entropy.peer_intervals` entry {} is not in `anti_entropy.peer_list`", peer);
+            }
+            if *secs == 0 {
+                anyhow::bail!("`anti_entropy.peer_intervals` entry {} must be at least 1 second", peer);
+            }
+        }
+        if !(1..=65535).contains(&self.server.listen_backlog) {
+            anyhow::bail!("`server.listen_backlog` must be between 1 and 65535");
+        }
+        if self.server.max_args == 0 {
+            anyhow::bail!("`server.max_args` must be at least 1");
+        }
+        if self.replication.apply_queue_size == 0 {
+            anyhow::bail!("`replication.apply_queue_size` must be at least 1");
+        }
+        if self.server.stream_chunk_bytes == 0 {
+            anyhow::bail!("`server.stream_chunk_bytes` must be at least 1");
+        }
+        if self.server.tls_cert_path.is_some() != self.server.tls_key_path.is_some() {
+            anyhow::bail!("set both `server.tls_cert_path` and `server.tls_key_path`, or neither");
+        }
+        if self.auth.users_file.is_some() && self.server.password.is_some() {
+            anyhow::bail!("set either `server.password` or `auth.users_file`, not both");
+        }
+        let storage = &self.storage;
+        if storage.max_ttl_seconds > 0 && storage.default_ttl_seconds > storage.max_ttl_seconds {
+            anyhow::bail!("`storage.default_ttl_seconds` cannot exceed `storage.max_ttl_seconds`");
+        }
+        if let Some(url) = &self.hooks.url {
+            crate::webhook::HookUrl::parse(url).context("invalid `hooks.url`")?;
+        }
+        match (&self.cluster.role, &self.cluster.primary_addr) {
+            (Role::Secondary, None) => anyhow::bail!("`cluster.primary_addr` is required when `cluster.role` is \"secondary\""),
+            (_, Some(addr)) => {
+                net_addr::split_host_port(addr).context("invalid `cluster.primary_addr`")?;
+            }
+            _ => {}
+        }
+        Ok(())
+    }
     /// Get the number of peers configured for anti-entropy synchronization.
+    #[allow(dead_code)]
     pub fn peer_list_len(&self) -> usize {
         self.anti_entropy.peer_list.len()
     }
@@ -143,26 +971,52 @@ impl Config {
     ///
     /// # Returns
     /// * `Config` - Configuration with default values
+    #[allow(dead_code)]
     pub fn default() -> Self {
         Self {
             host: "127.0.0.1".to_string(),
             port: 7379,
             storage_path: "data".to_string(),
             engine: "sled".to_string(),
+            server: ServerConfig::default(),
+            storage: StorageConfig::default(),
+            merkle: MerkleConfig::default(),
+            auth: AuthConfig::default(),
+            index: IndexConfig::default(),
+            hooks: HooksConfig::default(),
+            cluster: ClusterConfig::default(),
+            metrics: MetricsConfig::default(),
             replication: ReplicationConfig {
                 enabled: false,
                 mqtt_broker: "localhost".to_string(),
                 mqtt_port: 1883,
                 topic_prefix: "merkle_kv".to_string(),
+                topic_per_database: false,
+                mqtt_ca_path: None,
                 client_id: "node1".to_string(),
                 client_password: None,
                 peer_list: vec![], 
+                publish_lazy_expiry: false,
+                tombstone_ttl_seconds: default_tombstone_ttl_seconds(),
+                include_prefixes: vec![],
+                exclude_prefixes: vec![],
+                pause_queue_limit: default_pause_queue_limit(),
+                dedup_capacity: default_dedup_capacity(),
+                causality_log_size: default_causality_log_size(),
+                apply_queue_size: default_apply_queue_size(),
+                delta_min_bytes: 0,
+                delta_cache_bytes: default_delta_cache_bytes(),
+                token_wait_ms: default_token_wait_ms(),
+                ack_timeout_ms: default_ack_timeout_ms(),
+                ack_all_writes: false,
+                max_lag_seconds: 0,
             },
             sync_interval_seconds: 60,
             anti_entropy: AntiEntropyConfig {
                 enabled: true,          
                 interval_seconds: 60,
                 peer_list: vec![],
+                peer_intervals: BTreeMap::new(),
             },
         }
     }
@@ -217,11 +1071,358 @@ client_id = "node1"
         assert_eq!(config.port, 7379);
         assert_eq!(config.storage_path, "data");
         assert_eq!(config.sync_interval_seconds, 60);
-        assert_eq!(config.replication.enabled, true);
+        assert!(config.replication.enabled);
         assert_eq!(config.replication.mqtt_broker, "localhost");
         assert_eq!(config.replication.mqtt_port, 1883);
         assert_eq!(config.replication.topic_prefix, "merkle_kv");
         assert_eq!(config.replication.client_id, "node1");
         assert_eq!(config.replication.client_password, None);
     }
+
+    #[test]
+    fn test_config_storage_hash_fn() {
+        let mut temp_file = tempfile::Builder::new().suffix(".toml").tempfile().unwrap();
+        writeln!(
+            temp_file.as_file_mut(),
+            r#"
+host = "127.0.0.1"
+port = 7379
+storage_path = "data"
+engine = "rwlock"
+sync_interval_seconds = 60
+
+[storage]
+hash_fn = "siphash"
+
+[replication]
+enabled = false
+mqtt_broker = "localhost"
+mqtt_port = 1883
+topic_prefix = "merkle_kv"
+client_id = "node1"
+            "#
+        )
+        .unwrap();
+
+        let config = Config::load(temp_file.path()).unwrap();
+        assert_eq!(config.storage.hash_fn, HashFn::Siphash);
+
+        // The section is optional and falls back to the default hash function
+        assert_eq!(Config::default().storage.hash_fn, HashFn::Xxhash);
+    }
+
+    #[test]
+    fn test_config_server_allowed_cidrs() {
+        let mut temp_file = tempfile::Builder::new().suffix(".toml").tempfile().unwrap();
+        writeln!(
+            temp_file.as_file_mut(),
+            r#"
+host = "127.0.0.1"
+port = 7379
+storage_path = "data"
+engine = "rwlock"
+sync_interval_seconds = 60
+
+[server]
+allowed_cidrs = ["10.0.0.0/8", "::1"]
+proxy_protocol = true
+
+[replication]
+enabled = false
+mqtt_broker = "localhost"
+mqtt_port = 1883
+topic_prefix = "merkle_kv"
+client_id = "node1"
+            "#
+        )
+        .unwrap();
+
+        let config = Config::load(temp_file.path()).unwrap();
+        assert_eq!(config.server.allowed_cidrs, vec!["10.0.0.0/8", "::1"]);
+        assert!(config.server.proxy_protocol);
+
+        let defaults = Config::default();
+        assert!(defaults.server.allowed_cidrs.is_empty());
+        assert!(!defaults.server.proxy_protocol);
+    }
+
+    /// Load a config file holding the usual top-level settings, `sections`,
+    /// and `[replication]` with `replication` appended.
+    fn load_with(sections: &str, replication: &str) -> Config {
+        let mut file = tempfile::Builder::new().suffix(".toml").tempfile().unwrap();
+        writeln!(
+            file,
+            r#"
+host = "127.0.0.1"
+port = 7379
+storage_path = "data"
+engine = "rwlock"
+sync_interval_seconds = 60
+
+{}
+
+[replication]
+enabled = false
+mqtt_broker = "localhost"
+mqtt_port = 1883
+topic_prefix = "merkle_kv"
+client_id = "node1"
+{}
+"#,
+            sections, replication
+        )
+        .unwrap();
+        Config::load(file.path()).unwrap()
+    }
+
+    #[test]
+    fn test_config_storage_lock_stripes() {
+        assert_eq!(load_with("[storage]\nlock_stripes = 1024", "").storage.lock_stripes, 1024);
+        assert_eq!(Config::default().storage.lock_stripes, 0);
+    }
+
+    #[test]
+    fn test_config_storage_cold_tier() {
+        let config = load_with("[storage]\ncold_tier_path = \"cold\"\nhot_tier_max_bytes = 1048576", "");
+        assert_eq!(config.storage.cold_tier_path.as_deref(), Some("cold"));
+        assert_eq!(config.storage.hot_tier_max_bytes, 1_048_576);
+        assert_eq!(Config::default().storage.cold_tier_path, None);
+        assert_eq!(Config::default().storage.hot_tier_max_bytes, 0);
+    }
+
+    #[test]
+    fn test_config_merkle_sync_timeout() {
+        assert_eq!(load_with("[merkle]\nsync_timeout_ms = 250", "").merkle.sync_timeout_ms, 250);
+        // No [merkle] section → default sync timeout
+        assert_eq!(load_with("", "").merkle.sync_timeout_ms, 5000);
+    }
+
+    #[test]
+    fn test_config_merkle_sync_concurrency() {
+        assert_eq!(load_with("[merkle]\nsync_concurrency = 4", "").merkle.sync_concurrency, 4);
+        assert_eq!(load_with("", "").merkle.sync_concurrency, 1);
+    }
+
+    #[test]
+    fn test_config_merkle_subscribe_interval() {
+        assert_eq!(load_with("[merkle]\nsubscribe_interval_ms = 250", "").merkle.subscribe_interval_ms, 250);
+        assert_eq!(load_with("", "").merkle.subscribe_interval_ms, 1000);
+    }
+
+    #[test]
+    fn test_config_merkle_gc_interval() {
+        assert_eq!(load_with("[merkle]\ngc_interval_seconds = 60", "").merkle.gc_interval_seconds, 60);
+        assert_eq!(load_with("", "").merkle.gc_interval_seconds, 300);
+    }
+
+    #[test]
+    fn test_config_replication_tombstone_ttl() {
+        assert_eq!(load_with("", "tombstone_ttl_seconds = 3600").replication.tombstone_ttl_seconds, 3600);
+        assert_eq!(load_with("", "").replication.tombstone_ttl_seconds, 86400);
+    }
+
+    #[test]
+    fn test_config_replication_key_prefixes() {
+        let config = load_with("", "include_prefixes = [\"user:\"]\nexclude_prefixes = [\"user:tmp:\"]");
+        assert_eq!(config.replication.include_prefixes, vec!["user:"]);
+        assert_eq!(config.replication.exclude_prefixes, vec!["user:tmp:"]);
+        let defaults = load_with("", "");
+        assert!(defaults.replication.include_prefixes.is_empty());
+        assert!(defaults.replication.exclude_prefixes.is_empty());
+    }
+
+    #[test]
+    fn test_config_replication_pause_queue_limit() {
+        assert_eq!(load_with("", "pause_queue_limit = 50").replication.pause_queue_limit, 50);
+        assert_eq!(load_with("", "").replication.pause_queue_limit, 10_000);
+    }
+
+    #[test]
+    fn test_config_replication_dedup_capacity() {
+        assert_eq!(load_with("", "dedup_capacity = 500").replication.dedup_capacity, 500);
+        assert_eq!(load_with("", "").replication.dedup_capacity, 100_000);
+    }
+
+    #[test]
+    fn test_config_replication_delta_min_bytes() {
+        assert_eq!(load_with("", "delta_min_bytes = 4096").replication.delta_min_bytes, 4096);
+        assert_eq!(load_with("", "").replication.delta_min_bytes, 0);
+    }
+
+    #[test]
+    fn test_config_replication_causality_log_size() {
+        assert_eq!(load_with("", "causality_log_size = 100").replication.causality_log_size, 100);
+        assert_eq!(load_with("", "").replication.causality_log_size, 10_000);
+    }
+
+    #[test]
+    fn test_config_replication_apply_queue_size() {
+        let mut config = load_with("", "apply_queue_size = 16");
+        assert_eq!(config.replication.apply_queue_size, 16);
+        assert_eq!(load_with("", "").replication.apply_queue_size, 1024);
+        config.replication.apply_queue_size = 0;
+        assert!(config.validate().unwrap_err().to_string().contains("apply_queue_size"));
+    }
+
+    #[test]
+    fn test_config_server_compression_threshold() {
+        assert_eq!(load_with("[server]\ncompression_threshold = 4096", "").server.compression_threshold, 4096);
+        assert_eq!(Config::default().server.compression_threshold, 1024);
+    }
+
+    #[test]
+    fn test_config_server_password_and_idle_timeouts() {
+        let config = load_with("[server]\npassword = \"pw\"\nidle_timeout_secs = 300\nunauth_idle_timeout_secs = 5", "");
+        assert_eq!(config.server.password.as_deref(), Some("pw"));
+        assert_eq!(config.server.idle_timeout_secs, 300);
+        assert_eq!(config.server.unauth_idle_timeout_secs, 5);
+        let defaults = Config::default();
+        assert_eq!(defaults.server.password, None);
+        assert_eq!(defaults.server.idle_timeout_secs, 0);
+        assert_eq!(defaults.server.unauth_idle_timeout_secs, 10);
+    }
+
+    #[test]
+    fn test_config_server_stream_chunk_bytes() {
+        let mut config = load_with("[server]\nstream_chunk_bytes = 4096", "");
+        assert_eq!(config.server.stream_chunk_bytes, 4096);
+        assert_eq!(Config::default().server.stream_chunk_bytes, 65536);
+        config.server.stream_chunk_bytes = 0;
+        assert!(config.validate().unwrap_err().to_string().contains("stream_chunk_bytes"));
+    }
+
+    #[test]
+    fn test_config_server_listen_backlog() {
+        let mut config = load_with("[server]\nlisten_backlog = 128", "");
+        assert_eq!(config.server.listen_backlog, 128);
+        assert_eq!(Config::default().server.listen_backlog, 1024);
+        config.server.listen_backlog = 0;
+        assert!(config.validate().unwrap_err().to_string().contains("listen_backlog"));
+        config.server.listen_backlog = 65536;
+        assert!(config.validate().is_err());
+    }
+
+    #[test]
+    fn test_config_server_max_args() {
+        let mut config = load_with("[server]\nmax_args = 16", "");
+        assert_eq!(config.server.max_args, 16);
+        assert_eq!(Config::default().server.max_args, 1_048_576);
+        config.server.max_args = 0;
+        assert!(config.validate().unwrap_err().to_string().contains("max_args"));
+    }
+
+    #[test]
+    fn test_config_server_transport_compression() {
+        assert!(!load_with("[server]\ntransport_compression = false", "").server.transport_compression);
+        assert!(Config::default().server.transport_compression);
+    }
+
+    #[test]
+    fn test_config_validates_addresses() {
+        let mut config = Config::default();
+        config.host = "::".to_string();
+        config.anti_entropy.peer_list = vec!["[::1]:7380".to_string(), "node2:7379".to_string()];
+        assert!(config.validate().is_ok());
+
+        config.host = "fe80::zz".to_string();
+        let err = format!("{:#}", config.validate().unwrap_err());
+        assert!(err.contains("invalid `host`"), "{}", err);
+
+        config.host = "::1".to_string();
+        config.anti_entropy.peer_list = vec!["::1:7380".to_string()];
+        let err = format!("{:#}", config.validate().unwrap_err());
+        assert!(err.contains("must be bracketed"), "{}", err);
+    }
+
+    #[test]
+    fn test_peer_intervals() {
+        let mut file = tempfile::Builder::new().suffix(".toml").tempfile().unwrap();
+        writeln!(
+            file,
+            r#"
+host = "127.0.0.1"
+port = 7379
+storage_path = "data"
+engine = "rwlock"
+sync_interval_seconds = 60
+
+[replication]
+enabled = false
+mqtt_broker = "localhost"
+mqtt_port = 1883
+topic_prefix = "merkle_kv"
+client_id = "node1"
+
+[anti_entropy]
+enabled = true
+interval_seconds = 60
+peer_list = ["near:7379", "far:7379"]
+
+[anti_entropy.peer_intervals]
+"near:7379" = 5
+"#
+        )
+        .unwrap();
+        let config = Config::load(file.path()).unwrap();
+        assert_eq!(config.anti_entropy.peer_intervals.get("near:7379"), Some(&5));
+        assert_eq!(config.anti_entropy.peer_intervals.get("far:7379"), None);
+
+        let mut config = config;
+        config.anti_entropy.peer_intervals.insert("stranger:7379".to_string(), 5);
+        assert!(config.validate().unwrap_err().to_string().contains("not in `anti_entropy.peer_list`"));
+    }
+
+    #[test]
+    fn test_ttl_cap() {
+        let mut storage = StorageConfig::default();
+        assert_eq!(storage.cap_ttl_ms(u64::MAX).unwrap(), u64::MAX, "no cap by default");
+        storage.max_ttl_seconds = 60;
+        assert_eq!(storage.cap_ttl_ms(1_000).unwrap(), 1_000);
+        assert_eq!(storage.cap_ttl_ms(3_600_000).unwrap(), 60_000);
+        storage.reject_ttl_above_max = true;
+        assert!(storage.cap_ttl_ms(60_001).is_err());
+
+        let mut config = Config::default();
+        config.storage = storage;
+        config.storage.default_ttl_seconds = 120;
+        assert!(config.validate().unwrap_err().to_string().contains("default_ttl_seconds"));
+    }
+
+    #[test]
+    fn test_cluster_role() {
+        let mut file = tempfile::Builder::new().suffix(".toml").tempfile().unwrap();
+        writeln!(
+            file,
+            r#"
+host = "127.0.0.1"
+port = 7379
+storage_path = "data"
+engine = "rwlock"
+sync_interval_seconds = 60
+
+[replication]
+enabled = false
+mqtt_broker = "localhost"
+mqtt_port = 1883
+topic_prefix = "merkle_kv"
+client_id = "node2"
+
+[cluster]
+role = "secondary"
+primary_addr = "10.0.0.1:7379"
+"#
+        )
+        .unwrap();
+        let config = Config::load(file.path()).unwrap();
+        assert_eq!(config.cluster.role, Role::Secondary);
+        assert!(config.cluster.forward_writes, "forwarding is on by default");
+        assert_eq!(config.cluster.primary_password, None);
+        assert_eq!(Config::default().cluster.role, Role::Primary);
+
+        let mut config = config;
+        config.cluster.primary_addr = None;
+        assert!(config.validate().unwrap_err().to_string().contains("cluster.primary_addr"));
+        config.cluster.primary_addr = Some("no-port".to_string());
+        assert!(config.validate().is_err());
+    }
 }
@@ -0,0 +1,221 @@
+//! # Read-Your-Writes Tokens
+//!
+//! A write on node A replicates to node B asynchronously, so a client that
+//! writes to A and then reads from B may not see its own write. A consistency
+//! token closes that gap with a bounded wait:
+//!
+//! 1. With `CLIENT TOKENS ON`, every write reply is followed by
+//!    `TOKEN <node>:<ts>`. Here `ts` is the timestamp of the change event
+//!    the write published.
+//! 2. The client passes the token on a later read: `GET key WITHTOKEN <token>`.
+//! 3. The node waits up to `replication.token_wait_ms` until it has applied
+//!    every event from `<node>` up to `ts`. Then it serves the read, or replies
+//!    `NOT_CAUGHT_UP`.
+//!
+//! ## Clock
+//!
+//! Change event timestamps come from a hybrid logical clock (HLC). It follows
+//! wall-clock nanoseconds, but never goes backwards. It moves past every
+//! timestamp this node has seen from peers, so a write made after reading a
+//! replicated value always orders after it.
+//!
+//! ## Watermarks
+//!
+//! Events from one node arrive in order over MQTT. The node therefore tracks,
+//! per source node, the newest timestamp it has applied. A token is satisfied
+//! once the watermark of its node reaches the token's timestamp. Tokens issued
+//! by this node are always satisfied, because local writes apply synchronously.
+//!
+//! ## Lag
+//!
+//! When an event is applied, the wall clock minus its timestamp is how far
+//! this node trails its origin. The delay of the latest applied event is the
+//! node's replication lag (`replication.max_lag_seconds` fails `/ready` above
+//! it). It only changes as events arrive: a node that receives nothing keeps
+//! its last value.
+
+use anyhow::{anyhow, Result};
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Mutex, MutexGuard};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::sync::Notify;
+
+/// A parsed `<node>:<ts>` token.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Token {
+    pub node: String,
+    pub ts: u64,
+}
+
+impl Token {
+    pub fn parse(token: &str) -> Result<Self> {
+        let (node, ts) = token
+            .rsplit_once(':')
+            .ok_or_else(|| anyhow!("invalid consistency token '{}' (expected <node>:<ts>)", token))?;
+        let ts = ts
+            .parse::<u64>()
+            .map_err(|_| anyhow!("invalid timestamp in consistency token '{}'", token))?;
+        if node.is_empty() {
+            return Err(anyhow!("consistency token '{}' has no node id", token));
+        }
+        Ok(Self { node: node.to_string(), ts })
+    }
+}
+
+impl std::fmt::Display for Token {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}:{}", self.node, self.ts)
+    }
+}
+
+/// Hybrid logical clock plus per-source applied watermarks, shared by the
+/// replicator (which stamps and applies events) and the connections.
+pub struct ConsistencyTracker {
+    node_id: String,
+    /// Last timestamp issued or observed
+    last: Mutex<u64>,
+    /// Source node → newest applied event timestamp
+    applied: Mutex<HashMap<String, u64>>,
+    advanced: Notify,
+    /// Delay of the latest applied event (nanoseconds)
+    lag_ns: AtomicU64,
+}
+
+impl ConsistencyTracker {
+    pub fn new(node_id: impl Into<String>) -> Self {
+        Self {
+            node_id: node_id.into(),
+            last: Mutex::new(0),
+            applied: Mutex::new(HashMap::new()),
+            advanced: Notify::new(),
+            lag_ns: AtomicU64::new(0),
+        }
+    }
+
+    /// Next timestamp for a local change: wall-clock nanoseconds, strictly
+    /// greater than anything issued or observed before.
+    pub fn now(&self) -> u64 {
+        let wall = wall_ns();
+        let mut last = lock(&self.last);
+        *last = wall.max(*last + 1);
+        *last
+    }
+
+    /// Token for a local change stamped `ts`.
+    pub fn token(&self, ts: u64) -> Token {
+        Token { node: self.node_id.clone(), ts }
+    }
+
+    /// Record that an event from `source` stamped `ts` has been applied.
+    pub fn observe(&self, source: &str, ts: u64) {
+        self.lag_ns.store(wall_ns().saturating_sub(ts), Ordering::Relaxed);
+        {
+            let mut last = lock(&self.last);
+            *last = (*last).max(ts);
+        }
+        {
+            let mut applied = lock(&self.applied);
+            let watermark = applied.entry(source.to_string()).or_insert(0);
+            *watermark = (*watermark).max(ts);
+        }
+        self.advanced.notify_waiters();
+    }
+
+    /// Every source node applied here, with its newest applied timestamp.
+    pub fn sources(&self) -> Vec<(String, u64)> {
+        let mut sources: Vec<(String, u64)> = lock(&self.applied).iter().map(|(node, &ts)| (node.clone(), ts)).collect();
+        sources.sort();
+        sources
+    }
+
+    /// How far the latest applied event trailed its origin's clock.
+    pub fn lag(&self) -> Duration {
+        Duration::from_nanos(self.lag_ns.load(Ordering::Relaxed))
+    }
+
+    /// Whether every change up to `token` has been applied here.
+    pub fn caught_up(&self, token: &Token) -> bool {
+        token.node == self.node_id || lock(&self.applied).get(&token.node).is_some_and(|&ts| ts >= token.ts)
+    }
+
+    /// Wait up to `timeout` until `caught_up(token)`.
+    ///
+    /// # Returns
+    /// * `bool` - False if the deadline passed first
+    pub async fn wait_for(&self, token: &Token, timeout: Duration) -> bool {
+        let deadline = tokio::time::Instant::now() + timeout;
+        loop {
+            // Register before checking so an observe in between is not missed
+            let advanced = self.advanced.notified();
+            tokio::pin!(advanced);
+            advanced.as_mut().enable();
+            if self.caught_up(token) {
+                return true;
+            }
+            if tokio::time::timeout_at(deadline, advanced).await.is_err() {
+                return self.caught_up(token);
+            }
+        }
+    }
+}
+
+fn wall_ns() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_nanos() as u64
+}
+
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+
+    #[test]
+    fn test_clock_is_monotonic_and_passes_observed_timestamps() {
+        let clock = ConsistencyTracker::new("a");
+        let first = clock.now();
+        assert!(clock.now() > first);
+
+        // A peer far ahead pulls the local clock past its timestamp
+        clock.observe("b", first + 60_000_000_000);
+        assert!(clock.now() > first + 60_000_000_000);
+
+        let token = Token::parse("node:with:colons:42").unwrap();
+        assert_eq!(token, Token { node: "node:with:colons".to_string(), ts: 42 });
+        assert_eq!(token.to_string(), "node:with:colons:42");
+        assert!(Token::parse("42").is_err());
+        assert!(Token::parse("a:b").is_err());
+    }
+
+    #[test]
+    fn test_lag_is_the_delay_of_the_latest_applied_event() {
+        let tracker = ConsistencyTracker::new("b");
+        assert_eq!(tracker.lag(), Duration::ZERO);
+        tracker.observe("a", wall_ns() - 30_000_000_000);
+        assert!(tracker.lag() >= Duration::from_secs(30));
+        tracker.observe("a", wall_ns());
+        assert!(tracker.lag() < Duration::from_secs(1));
+        // A clock ahead of ours is no lag
+        tracker.observe("c", wall_ns() + 60_000_000_000);
+        assert_eq!(tracker.lag(), Duration::ZERO);
+    }
+
+    #[tokio::test]
+    async fn test_wait_for_returns_once_the_source_catches_up() {
+        let tracker = Arc::new(ConsistencyTracker::new("b"));
+        let token = Token { node: "a".to_string(), ts: 100 };
+        assert!(tracker.caught_up(&tracker.token(u64::MAX)), "own writes are always applied");
+        assert!(!tracker.wait_for(&token, Duration::from_millis(20)).await);
+
+        let applier = Arc::clone(&tracker);
+        tokio::spawn(async move {
+            applier.observe("a", 99);
+            tokio::time::sleep(Duration::from_millis(50)).await;
+            applier.observe("a", 100);
+        });
+        assert!(tracker.wait_for(&token, Duration::from_secs(2)).await);
+    }
+}
@@ -0,0 +1,224 @@
+//! # Logical Databases (`SELECT <index>`, `server.databases`)
+//!
+//! A connection starts in database 0, the node's main store: the one that is
+//! persisted, replicated, tracked by the Merkle tree and synced. `SELECT n`
+//! switches the connection to database `n` for its following commands.
+//!
+//! Databases 1.. are created on first `SELECT` as plain in-memory stores with
+//! TTLs and key times. They are local to the node: their writes are not
+//! logged to the WAL or seen by the webhook and Merkle sync, and replicate
+//! only with `replication.topic_per_database` (see `replication`).
+//! `server.databases` bounds the count; `SELECT n` for `n >= databases` is refused.
+//!
+//! ## Comparing Databases (`DBDIFF <db1> <db2>`)
+//!
+//! `DBDIFF` lists the keys held by only one of two databases and the keys
+//! both hold with different values:
+//!
+//! ```text
+//! DBDIFF only_db1:<n> only_db2:<m> differ:<k>
+//! ONLY <db1|db2> <key>
+//! DIFFERS <key>
+//! ```
+//!
+//! Lines follow in key order. Both databases are listed a page of
+//! `DIFF_BATCH` keys at a time (`keys_page`, resuming after the last key
+//! compared) and their values compared page by page, one lock at a time,
+//! so other clients keep running between pages. The diff is therefore not
+//! a snapshot: a key written while it runs is compared as of its page.
+
+use anyhow::{bail, Result};
+use std::collections::BTreeSet;
+use std::sync::{Arc, Mutex};
+use tokio::sync::Mutex as AsyncMutex;
+
+use crate::store::{ExpiringEngine, KVEngineStoreTrait, RwLockEngine, TimestampEngine};
+
+/// Keys whose values are read under one lock acquisition by `diff`.
+pub const DIFF_BATCH: usize = 256;
+
+/// A store shared by the connections that selected it.
+pub type Db = Arc<AsyncMutex<Box<dyn KVEngineStoreTrait + Send + Sync>>>;
+
+/// The database slots of a server, created lazily.
+pub struct Databases {
+    /// Slot `i` holds database `i` once selected; slot 0 from the start
+    slots: Mutex<Vec<Option<Db>>>,
+}
+
+impl Databases {
+    /// `main` is database 0; `count` (at least 1) is `server.databases`.
+    pub fn new(main: Db, count: usize) -> Self {
+        let mut slots = vec![None; count.max(1)];
+        slots[0] = Some(main);
+        Self { slots: Mutex::new(slots) }
+    }
+
+    /// Database `index`, creating it on first use.
+    pub fn get(&self, index: usize) -> Result<Db> {
+        let mut slots = self.slots_guard();
+        let count = slots.len();
+        let Some(slot) = slots.get_mut(index) else {
+            bail!("DB index {} is out of range (server.databases = {})", index, count);
+        };
+        if let Some(db) = slot {
+            return Ok(Arc::clone(db));
+        }
+        let engine = ExpiringEngine::new(Box::new(RwLockEngine::new("unused")?), false);
+        let db: Db = Arc::new(AsyncMutex::new(Box::new(TimestampEngine::new(Box::new(engine)))));
+        *slot = Some(Arc::clone(&db));
+        Ok(db)
+    }
+
+    fn slots_guard(&self) -> std::sync::MutexGuard<'_, Vec<Option<Db>>> {
+        self.slots.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+/// Result of `diff`, each list in key order.
+#[derive(Debug, Default, PartialEq)]
+pub struct DbDiff {
+    /// Keys only the first database holds
+    pub only_first: Vec<String>,
+    /// Keys only the second database holds
+    pub only_second: Vec<String>,
+    /// Keys both hold, with different values
+    pub differing: Vec<String>,
+}
+
+impl DbDiff {
+    /// `DBDIFF` reply, naming the databases `first` and `second`.
+    pub fn format(&self, first: usize, second: usize) -> String {
+        let mut out = format!(
+            "DBDIFF only_db{}:{} only_db{}:{} differ:{}\r\n",
+            first,
+            self.only_first.len(),
+            second,
+            self.only_second.len(),
+            self.differing.len()
+        );
+        let only = self.only_first.iter().map(|k| (k, Some(first))).chain(self.only_second.iter().map(|k| (k, Some(second))));
+        let mut lines: Vec<(&String, Option<usize>)> = only.chain(self.differing.iter().map(|k| (k, None))).collect();
+        lines.sort();
+        for (key, db) in lines {
+            match db {
+                Some(db) => out.push_str(&format!("ONLY {} {}\r\n", db, key)),
+                None => out.push_str(&format!("DIFFERS {}\r\n", key)),
+            }
+        }
+        out
+    }
+}
+
+/// Compare the keys and values of `first` and `second`.
+pub async fn diff(first: &Db, second: &Db) -> DbDiff {
+    let mut diff = DbDiff::default();
+    let mut after: Option<String> = None;
+    loop {
+        let left = first.lock().await.keys_page(after.as_deref(), DIFF_BATCH);
+        let right = second.lock().await.keys_page(after.as_deref(), DIFF_BATCH);
+        // A full page may end before keys the other one lists: compare up to
+        // the lower end, the rest comes with the next pages
+        let end = [&left, &right].into_iter().filter(|page| page.len() == DIFF_BATCH).filter_map(|page| page.last()).min().cloned();
+        let batch: BTreeSet<String> = left.into_iter().chain(right).filter(|k| end.as_ref().is_none_or(|end| k <= end)).collect();
+        let batch: Vec<String> = batch.into_iter().collect();
+        let values = |store: &dyn KVEngineStoreTrait| batch.iter().map(|k| store.get(k)).collect::<Vec<_>>();
+        let left = values(first.lock().await.as_ref());
+        let right = values(second.lock().await.as_ref());
+        for ((key, left), right) in batch.iter().zip(left).zip(right) {
+            match (left, right) {
+                (Some(_), None) => diff.only_first.push(key.clone()),
+                (None, Some(_)) => diff.only_second.push(key.clone()),
+                (Some(l), Some(r)) if l != r => diff.differing.push(key.clone()),
+                // Equal, or gone from both since the listing
+                _ => {}
+            }
+        }
+        match end {
+            Some(end) => after = Some(end),
+            None => break,
+        }
+    }
+    diff
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_databases_are_created_once_and_bounded() {
+        let main: Db = Arc::new(AsyncMutex::new(Box::new(RwLockEngine::new("unused").unwrap())));
+        let dbs = Databases::new(Arc::clone(&main), 4);
+        assert!(Arc::ptr_eq(&dbs.get(0).unwrap(), &main));
+
+        dbs.get(3).unwrap().lock().await.set("k".to_string(), "v".to_string()).unwrap();
+        assert_eq!(dbs.get(3).unwrap().lock().await.get("k"), Some("v".to_string()));
+        assert_eq!(dbs.get(1).unwrap().lock().await.get("k"), None);
+        assert_eq!(main.lock().await.get("k"), None);
+
+        let err = dbs.get(4).err().unwrap().to_string();
+        assert_eq!(err, "DB index 4 is out of range (server.databases = 4)");
+        let single = Databases::new(main, 0);
+        assert!(single.get(0).is_ok() && single.get(1).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_diff_finds_one_sided_and_differing_keys_across_batches() {
+        let main: Db = Arc::new(AsyncMutex::new(Box::new(RwLockEngine::new("unused").unwrap())));
+        let dbs = Databases::new(main, 3);
+        let (live, staging) = (dbs.get(0).unwrap(), dbs.get(2).unwrap());
+        // More shared keys than one batch, all equal but one
+        for i in 0..DIFF_BATCH + 10 {
+            let key = format!("shared:{:04}", i);
+            live.lock().await.set(key.clone(), "v".to_string()).unwrap();
+            let value = if i == DIFF_BATCH + 3 { "changed" } else { "v" };
+            staging.lock().await.set(key, value.to_string()).unwrap();
+        }
+        live.lock().await.set("old".to_string(), "1".to_string()).unwrap();
+        staging.lock().await.set("new".to_string(), "1".to_string()).unwrap();
+
+        let result = diff(&live, &staging).await;
+        let shared = format!("shared:{:04}", DIFF_BATCH + 3);
+        assert_eq!(
+            result,
+            DbDiff { only_first: vec!["old".to_string()], only_second: vec!["new".to_string()], differing: vec![shared.clone()] }
+        );
+        assert_eq!(
+            result.format(0, 2),
+            format!("DBDIFF only_db0:1 only_db2:1 differ:1\r\nONLY 2 new\r\nONLY 0 old\r\nDIFFERS {}\r\n", shared)
+        );
+        assert_eq!(diff(&staging, &staging).await, DbDiff::default());
+    }
+
+    #[tokio::test]
+    async fn test_diff_pages_interleaved_keys() {
+        let main: Db = Arc::new(AsyncMutex::new(Box::new(RwLockEngine::new("unused").unwrap())));
+        let dbs = Databases::new(main, 2);
+        let (evens, odds) = (dbs.get(0).unwrap(), dbs.get(1).unwrap());
+        // Each page of one side ends before the other's: nothing may be skipped
+        for i in 0..2 * DIFF_BATCH + 5 {
+            let db = if i % 2 == 0 { &evens } else { &odds };
+            db.lock().await.set(format!("k:{:04}", i), "v".to_string()).unwrap();
+        }
+        odds.lock().await.set("z".to_string(), "v".to_string()).unwrap();
+
+        let result = diff(&evens, &odds).await;
+        let expected = |parity: usize| (0..2 * DIFF_BATCH + 5).filter(move |i| i % 2 == parity).map(|i| format!("k:{:04}", i));
+        assert_eq!(result.only_first, expected(0).collect::<Vec<_>>());
+        assert_eq!(result.only_second, expected(1).chain(["z".to_string()]).collect::<Vec<_>>());
+        assert!(result.differing.is_empty());
+    }
+
+    #[test]
+    fn test_keys_page_resumes_after_the_last_key() {
+        let store = RwLockEngine::new("unused").unwrap();
+        for key in ["d", "a", "c", "e", "b"] {
+            store.set(key.to_string(), "v".to_string()).unwrap();
+        }
+        assert_eq!(store.keys_page(None, 2), vec!["a", "b"]);
+        assert_eq!(store.keys_page(Some("b"), 2), vec!["c", "d"]);
+        assert_eq!(store.keys_page(Some("bb"), 10), vec!["c", "d", "e"]);
+        assert!(store.keys_page(Some("e"), 2).is_empty());
+    }
+}
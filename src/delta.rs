@@ -0,0 +1,128 @@
+//! # Delta Replication for Large Values
+//!
+//! With `replication.delta_min_bytes` set, a SET/APPEND/PREPEND whose value is
+//! at least that large is published as a delta against the value this node
+//! last published for the key, instead of in full. The delta keeps the
+//! longest common prefix and suffix of the two values and carries only the
+//! bytes in between, so a small edit to a big value yields a small event:
+//!
+//! - **`ValueDelta.base`**: SHA-256 of the base value (its version)
+//! - **`ValueDelta.prefix` / `suffix`**: bytes kept from the start / end of the base
+//! - **`ChangeEvent.val`**: the replacement bytes in between
+//!
+//! A subscriber applies the delta only if its current value hashes to `base`.
+//! Otherwise it publishes a `resend` event naming the origin node, which
+//! answers with the full value. Published bases are cached per key up to
+//! `replication.delta_cache_bytes`, oldest evicted first.
+
+use sha2::{Digest, Sha256};
+use std::collections::{HashMap, VecDeque};
+
+use crate::change_event::ValueDelta;
+
+/// Version of a value: its SHA-256.
+pub fn version(value: &[u8]) -> [u8; 32] {
+    Sha256::digest(value).into()
+}
+
+/// Describe `new` relative to `base`.
+///
+/// # Returns
+/// * `(ValueDelta, Vec<u8>)` - The delta header and the replacement bytes
+pub fn diff(base: &[u8], new: &[u8]) -> (ValueDelta, Vec<u8>) {
+    let prefix = base.iter().zip(new).take_while(|(a, b)| a == b).count();
+    let max_suffix = base.len().min(new.len()) - prefix;
+    let suffix = base.iter().rev().zip(new.iter().rev()).take(max_suffix).take_while(|(a, b)| a == b).count();
+    let delta = ValueDelta { base: version(base), prefix: prefix as u64, suffix: suffix as u64 };
+    (delta, new[prefix..new.len() - suffix].to_vec())
+}
+
+/// Rebuild the new value from `base`, or None if `base` is not the version
+/// the delta was made against.
+pub fn patch(base: &[u8], delta: &ValueDelta, middle: &[u8]) -> Option<Vec<u8>> {
+    let (prefix, suffix) = (delta.prefix as usize, delta.suffix as usize);
+    if version(base) != delta.base || prefix.checked_add(suffix)? > base.len() {
+        return None;
+    }
+    let mut value = Vec::with_capacity(prefix + middle.len() + suffix);
+    value.extend_from_slice(&base[..prefix]);
+    value.extend_from_slice(middle);
+    value.extend_from_slice(&base[base.len() - suffix..]);
+    Some(value)
+}
+
+/// Last published value of each large key, bounded by total bytes.
+#[derive(Default)]
+pub struct BaseCache {
+    values: HashMap<String, Vec<u8>>,
+    order: VecDeque<String>,
+    bytes: usize,
+    capacity_bytes: usize,
+}
+
+impl BaseCache {
+    pub fn with_capacity(capacity_bytes: usize) -> Self {
+        Self { capacity_bytes, ..Self::default() }
+    }
+
+    pub fn get(&self, key: &str) -> Option<&[u8]> {
+        self.values.get(key).map(Vec::as_slice)
+    }
+
+    /// Remember `value` as the base of `key`; values over the capacity are not kept.
+    pub fn insert(&mut self, key: &str, value: Vec<u8>) {
+        self.remove(key);
+        if value.len() > self.capacity_bytes {
+            return;
+        }
+        self.bytes += value.len();
+        self.values.insert(key.to_string(), value);
+        self.order.push_back(key.to_string());
+        while self.bytes > self.capacity_bytes {
+            let Some(old) = self.order.pop_front() else { break };
+            if let Some(v) = self.values.remove(&old) {
+                self.bytes -= v.len();
+            }
+        }
+    }
+
+    pub fn remove(&mut self, key: &str) {
+        if let Some(v) = self.values.remove(key) {
+            self.bytes -= v.len();
+            self.order.retain(|k| k != key);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_diff_and_patch() {
+        let base = b"hello brave new world";
+        for new in [&b"hello bold new world"[..], b"hello", b"world", b"", b"hello brave new world!", b"xhello brave new world"] {
+            let (delta, middle) = diff(base, new);
+            assert_eq!(patch(base, &delta, &middle).as_deref(), Some(new), "{:?}", String::from_utf8_lossy(new));
+        }
+        let (delta, middle) = diff(b"aaaa", b"aaaaaa");
+        assert_eq!((delta.prefix, delta.suffix, middle.as_slice()), (4, 0, &b"aa"[..]));
+        assert_eq!(patch(b"aaab", &delta, &middle), None, "wrong base version");
+    }
+
+    #[test]
+    fn test_base_cache_is_bounded() {
+        let mut cache = BaseCache::with_capacity(10);
+        cache.insert("a", vec![0; 4]);
+        cache.insert("b", vec![0; 4]);
+        cache.insert("a", vec![1; 4]);
+        cache.insert("c", vec![0; 4]);
+        assert_eq!(cache.get("b"), None, "oldest evicted");
+        assert_eq!(cache.get("a"), Some(&[1u8; 4][..]));
+        assert_eq!(cache.bytes, 8);
+        cache.insert("big", vec![0; 11]);
+        assert_eq!(cache.get("big"), None);
+        cache.remove("a");
+        assert_eq!(cache.bytes, 4);
+    }
+}
@@ -0,0 +1,111 @@
+//! # Group Commit (`storage.group_commit_window_us`)
+//!
+//! Every `DURABLE` write needs the engine flushed to disk before its `OK`.
+//! With a window set, concurrent durable writes share one flush: the first
+//! one to arrive opens a batch, later ones join it for the next
+//! `group_commit_window_us` microseconds, then a single `sync` covers them
+//! all and every member gets its result together.
+//!
+//! A write joins a batch only after it was applied and only while that
+//! batch's flush has not started, so the flush always includes it. If the
+//! flush fails, every write of the batch replies with the error. A window of
+//! `0` flushes each durable write on its own.
+
+use anyhow::{anyhow, Result};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::watch;
+
+use crate::databases::Db;
+
+/// Outcome of a batch's flush, shared by its members.
+type FlushResult = Option<Result<(), String>>;
+
+/// Batches the flushes of durable writes to one store.
+pub struct GroupCommit {
+    store: Db,
+    window: Duration,
+    /// The batch still accepting writes, if any
+    open: Mutex<Option<watch::Receiver<FlushResult>>>,
+}
+
+impl GroupCommit {
+    pub fn new(store: Db, window: Duration) -> Self {
+        Self { store, window, open: Mutex::new(None) }
+    }
+
+    /// Flush the store with the other durable writes of the current window;
+    /// returns once a flush that started after the call has finished.
+    pub async fn sync(self: &Arc<Self>) -> Result<()> {
+        if self.window.is_zero() {
+            return self.store.lock().await.sync();
+        }
+        let mut done = {
+            let mut open = self.open_guard();
+            match open.as_ref() {
+                Some(batch) => batch.clone(),
+                None => {
+                    let (tx, rx) = watch::channel(None);
+                    *open = Some(rx.clone());
+                    // The flush runs on its own, so a member disconnecting cannot abandon the batch
+                    let this = Arc::clone(self);
+                    tokio::spawn(async move {
+                        tokio::time::sleep(this.window).await;
+                        this.open_guard().take();
+                        let result = this.store.lock().await.sync().map_err(|e| format!("{:#}", e));
+                        let _ = tx.send(Some(result));
+                    });
+                    rx
+                }
+            }
+        };
+        let result = done.wait_for(Option::is_some).await.map_err(|_| anyhow!("group commit flush was dropped"))?;
+        result.clone().expect("waited for a result").map_err(|e| anyhow!(e))
+    }
+
+    fn open_guard(&self) -> std::sync::MutexGuard<'_, Option<watch::Receiver<FlushResult>>> {
+        self.open.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::store::test_engine::TestEngine;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    fn group(window: Duration, failing: bool) -> (Arc<GroupCommit>, Arc<AtomicUsize>) {
+        let engine = if failing { TestEngine::new().failing_sync() } else { TestEngine::new() };
+        let count = engine.syncs();
+        let store: Db = Arc::new(tokio::sync::Mutex::new(Box::new(engine)));
+        (Arc::new(GroupCommit::new(store, window)), count)
+    }
+
+    #[tokio::test]
+    async fn test_concurrent_syncs_share_a_flush() {
+        let (commit, count) = group(Duration::from_millis(20), false);
+        let members: Vec<_> = (0..50)
+            .map(|_| {
+                let commit = Arc::clone(&commit);
+                tokio::spawn(async move { commit.sync().await })
+            })
+            .collect();
+        for member in members {
+            member.await.unwrap().unwrap();
+        }
+        assert_eq!(count.load(Ordering::SeqCst), 1);
+
+        // A later write opens a new batch
+        commit.sync().await.unwrap();
+        assert_eq!(count.load(Ordering::SeqCst), 2);
+
+        let (unbatched, count) = group(Duration::ZERO, false);
+        for _ in 0..3 {
+            unbatched.sync().await.unwrap();
+        }
+        assert_eq!(count.load(Ordering::SeqCst), 3);
+
+        let (failing, _) = group(Duration::from_millis(1), true);
+        assert_eq!(failing.sync().await.unwrap_err().to_string(), "disk full");
+    }
+}
@@ -0,0 +1,76 @@
+//! # Binary Keys (`server.require_utf8_keys`)
+//!
+//! Keys and values are stored as text, but a request line does not have to
+//! be valid UTF-8. Its valid runs are kept as they are, and each byte of an
+//! invalid sequence becomes the character `U+F700 + byte` (Private Use Area,
+//! so `0xFF` is `U+F7FF`). The same bytes therefore always name the same key,
+//! and replies turn those characters back into the bytes they stand for, so
+//! a binary key (or value) round-trips. `U+F780..=U+F7FF` are reserved for
+//! this: text holding them is sent back as the raw bytes.
+//!
+//! With `server.require_utf8_keys`, a write of a key that is not UTF-8 gets
+//! `ERROR ERR_ENCODING invalid key encoding` and nothing is stored.
+
+use std::borrow::Cow;
+
+/// Character standing for byte 0 (only 0x80.. ever occur).
+const ESCAPE_BASE: u32 = 0xF700;
+
+/// The character standing for `byte`.
+fn escape(byte: u8) -> char {
+    char::from_u32(ESCAPE_BASE + byte as u32).expect("Private Use Area")
+}
+
+/// The byte `c` stands for, if it is an escape.
+fn unescape(c: char) -> Option<u8> {
+    (c as u32).checked_sub(ESCAPE_BASE).filter(|b| *b >= 0x80 && *b <= 0xFF).map(|b| b as u8)
+}
+
+/// Request bytes as text, escaping the bytes that are not UTF-8.
+pub fn decode(bytes: &[u8]) -> String {
+    let mut text = String::with_capacity(bytes.len());
+    for chunk in bytes.utf8_chunks() {
+        text.push_str(chunk.valid());
+        text.extend(chunk.invalid().iter().map(|&b| escape(b)));
+    }
+    text
+}
+
+/// Whether `text` came from bytes that are not UTF-8.
+pub fn is_binary(text: &str) -> bool {
+    text.chars().any(|c| unescape(c).is_some())
+}
+
+/// Reply text as wire bytes, with escaped bytes restored.
+pub fn encode(text: &str) -> Cow<'_, [u8]> {
+    if !is_binary(text) {
+        return Cow::Borrowed(text.as_bytes());
+    }
+    let mut bytes = Vec::with_capacity(text.len());
+    for c in text.chars() {
+        match unescape(c) {
+            Some(b) => bytes.push(b),
+            None => bytes.extend_from_slice(c.encode_utf8(&mut [0; 4]).as_bytes()),
+        }
+    }
+    Cow::Owned(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_binary_bytes_round_trip() {
+        let bytes = b"SET \xff\xfe\x80 caf\xc3\xa9 \xc3\r\n";
+        let text = decode(bytes);
+        assert!(text.starts_with("SET ") && text.contains(" café "), "{:?}", text);
+        assert!(is_binary(&text));
+        assert_eq!(encode(&text).as_ref(), bytes);
+
+        // UTF-8 passes through untouched
+        assert_eq!(decode("GET clé\r\n".as_bytes()), "GET clé\r\n");
+        assert!(!is_binary("clé"));
+        assert!(matches!(encode("VALUE clé\r\n"), Cow::Borrowed(_)));
+    }
+}
@@ -0,0 +1,70 @@
+//! # Replication Key Filter
+//!
+//! Decides which keys are cluster-wide and which are node-local, from
+//! `replication.include_prefixes` / `replication.exclude_prefixes`:
+//!
+//! - an empty include list means every key is included;
+//! - exclusion wins over inclusion, so `include = ["cache:"]` with
+//!   `exclude = ["cache:tmp:"]` keeps `cache:tmp:*` local.
+//!
+//! The same filter gates publishing, applying replicated events and the
+//! Merkle tree used for anti-entropy sync. All three must agree: a key kept out
+//! of replication but left in the tree would be "repaired" by the next sync.
+//! Every node in the cluster should use the same prefixes.
+
+use crate::config::ReplicationConfig;
+
+/// Prefix filter selecting the keys that replicate.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct KeyFilter {
+    include: Vec<String>,
+    exclude: Vec<String>,
+}
+
+impl KeyFilter {
+    /// Build a filter; an empty `include` list includes every key.
+    pub fn new(include: Vec<String>, exclude: Vec<String>) -> Self {
+        Self { include, exclude }
+    }
+
+    /// Filter configured for this node's replication settings.
+    pub fn from_config(config: &ReplicationConfig) -> Self {
+        Self::new(config.include_prefixes.clone(), config.exclude_prefixes.clone())
+    }
+
+    /// Whether `key` is cluster-wide (published, applied and Merkle-tracked).
+    pub fn replicates(&self, key: &str) -> bool {
+        self.matches(key)
+    }
+
+    /// Whether `key` passes the include and exclude prefixes. Also used for
+    /// `hooks.*_prefixes`, which select webhook keys the same way.
+    pub fn matches(&self, key: &str) -> bool {
+        let included = self.include.is_empty() || self.include.iter().any(|p| key.starts_with(p.as_str()));
+        included && !self.exclude.iter().any(|p| key.starts_with(p.as_str()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn prefixes(items: &[&str]) -> Vec<String> {
+        items.iter().map(|p| p.to_string()).collect()
+    }
+
+    #[test]
+    fn test_include_and_exclude_prefixes() {
+        assert!(KeyFilter::default().replicates("anything"));
+
+        let exclude_only = KeyFilter::new(vec![], prefixes(&["local:"]));
+        assert!(exclude_only.replicates("user:1"));
+        assert!(!exclude_only.replicates("local:cache"));
+
+        let both = KeyFilter::new(prefixes(&["user:", "cache:"]), prefixes(&["cache:tmp:"]));
+        assert!(both.replicates("user:1"));
+        assert!(both.replicates("cache:page"));
+        assert!(!both.replicates("cache:tmp:x"));
+        assert!(!both.replicates("other"));
+    }
+}
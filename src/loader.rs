@@ -0,0 +1,231 @@
+//! # One-shot Loader (`--load <file>`)
+//!
+//! Bulk-loads a dataset into the configured storage engine and exits without
+//! starting the server, so CI or provisioning can pre-seed a persistent store:
+//! the `sled` engine, or any engine with `storage.wal_path`.
+//!
+//! ## File Formats
+//!
+//! A `DUMP` reply saved to a file (see `snapshot`), such as a backup
+//! snapshot: keys keep their expiries, `TOMBSTONE` records are skipped.
+//!
+//! ```text
+//! DUMP 2
+//! SET user:1 0 alice
+//! SET session:9 1767225600000 token
+//! ```
+//!
+//! Otherwise newline-delimited `key=value` pairs. The key ends at the first
+//! `=`, so values may themselves contain `=`. Blank lines and lines starting
+//! with `#` are ignored.
+//!
+//! ```text
+//! # users
+//! user:1=alice
+//! user:2=bob
+//! query=a=b&c=d
+//! ```
+//!
+//! Writes go through the normal `KVEngineStoreTrait::set` path (and the WAL,
+//! if any) and the store is synced before returning, so the data is durable
+//! when the process exits.
+
+use anyhow::{anyhow, Context, Result};
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+use std::path::Path;
+
+use crate::snapshot::{self, Record};
+use crate::store::KVEngineStoreTrait;
+
+/// Parse one line into `(key, value)`. `Ok(None)` for blank and comment lines.
+pub fn parse_line(line: &str) -> Result<Option<(String, String)>> {
+    let line = line.trim_end_matches(['\r', '\n']);
+    if line.trim().is_empty() || line.trim_start().starts_with('#') {
+        return Ok(None);
+    }
+    let (key, value) = line
+        .split_once('=')
+        .ok_or_else(|| anyhow!("expected key=value"))?;
+    if key.is_empty() {
+        return Err(anyhow!("key cannot be empty"));
+    }
+    if key.contains(char::is_whitespace) {
+        return Err(anyhow!("key cannot contain whitespace"));
+    }
+    Ok(Some((key.to_string(), value.to_string())))
+}
+
+/// Load every pair (or `DUMP` record) from `path` into `store`, then sync it.
+/// `store` must support expiries for a `DUMP` holding TTLs.
+///
+/// # Returns
+/// * `Result<usize>` - Number of keys written; fails on the first malformed line
+pub fn load_file(store: &dyn KVEngineStoreTrait, path: &Path) -> Result<usize> {
+    let file = File::open(path).with_context(|| format!("open {}", path.display()))?;
+    let mut lines = BufReader::new(file).lines();
+    let first = lines.next().transpose().with_context(|| format!("read {}", path.display()))?;
+    let loaded = match first.as_deref().and_then(|line| line.trim_end_matches('\r').strip_prefix("DUMP ")) {
+        Some(count) => {
+            let count: usize = count.parse().with_context(|| format!("{}:1: invalid count after DUMP", path.display()))?;
+            load_dump(store, path, count, lines)?
+        }
+        None => load_pairs(store, path, first.map(Ok).into_iter().chain(lines))?,
+    };
+    store.sync()?;
+    Ok(loaded)
+}
+
+fn load_pairs(store: &dyn KVEngineStoreTrait, path: &Path, lines: impl Iterator<Item = std::io::Result<String>>) -> Result<usize> {
+    let mut loaded = 0;
+    for (idx, line) in lines.enumerate() {
+        let line = line.with_context(|| format!("read {}", path.display()))?;
+        let parsed = parse_line(&line).with_context(|| format!("{}:{}", path.display(), idx + 1))?;
+        if let Some((key, value)) = parsed {
+            store.set(key, value)?;
+            loaded += 1;
+        }
+    }
+    Ok(loaded)
+}
+
+/// Load the `count` records following a `DUMP` header.
+fn load_dump(store: &dyn KVEngineStoreTrait, path: &Path, count: usize, lines: impl Iterator<Item = std::io::Result<String>>) -> Result<usize> {
+    let mut records = 0;
+    let mut loaded = 0;
+    for (idx, line) in lines.enumerate() {
+        let line = line.with_context(|| format!("read {}", path.display()))?;
+        let at = || format!("{}:{}", path.display(), idx + 2);
+        if records == count {
+            return Err(anyhow!("expected {} records after the DUMP header", count)).with_context(at);
+        }
+        records += 1;
+        if let Record::Set { key, expires_at_ms, value } = snapshot::parse_record(line.trim_end_matches('\r')).with_context(at)? {
+            store.set(key.clone(), value)?;
+            if expires_at_ms.is_some() {
+                store.set_expiry(&key, expires_at_ms).with_context(at)?;
+            }
+            loaded += 1;
+        }
+    }
+    if records < count {
+        return Err(anyhow!("{}: DUMP announces {} records, found {}", path.display(), count, records));
+    }
+    Ok(loaded)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+    use crate::server::Server;
+    use crate::store::SledEngine;
+    use std::io::Write;
+    use tokio::io::{AsyncBufReadExt, AsyncWriteExt};
+
+    #[test]
+    fn test_parse_line() {
+        assert_eq!(parse_line("a=1").unwrap(), Some(("a".to_string(), "1".to_string())));
+        assert_eq!(parse_line("q=a=b\r\n").unwrap(), Some(("q".to_string(), "a=b".to_string())));
+        assert_eq!(parse_line("empty=").unwrap(), Some(("empty".to_string(), String::new())));
+        assert_eq!(parse_line("   ").unwrap(), None);
+        assert_eq!(parse_line("# comment").unwrap(), None);
+        assert!(parse_line("novalue").is_err());
+        assert!(parse_line("=v").is_err());
+        assert!(parse_line("bad key=v").is_err());
+    }
+
+    #[test]
+    fn test_malformed_line_reports_position() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        writeln!(file, "a=1\nbroken").unwrap();
+        let store = SledEngine::new(dir.path().to_str().unwrap()).unwrap();
+        let err = load_file(&store, file.path()).unwrap_err();
+        assert!(format!("{:#}", err).contains(":2"), "{:#}", err);
+    }
+
+    #[test]
+    fn test_dump_file_is_loaded_through_the_wal_with_expiries() {
+        use crate::store::expiring::{now_ms, ExpiringEngine};
+        use crate::store::wal::WalFormat;
+        use crate::store::{RwLockEngine, WalEngine};
+
+        let dir = tempfile::tempdir().unwrap();
+        let wal_path = dir.path().join("wal.log");
+        let open = || {
+            let wal = WalEngine::open(Box::new(RwLockEngine::new("unused").unwrap()), &wal_path, WalFormat::Bincode).unwrap();
+            ExpiringEngine::new(Box::new(wal), false)
+        };
+        let far = now_ms() + 3_600_000;
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        write!(file, "DUMP 3\r\nSET user:1 0 alice smith\r\nSET session {} token\r\nTOMBSTONE gone 1\r\n", far).unwrap();
+        assert_eq!(load_file(&open(), file.path()).unwrap(), 2);
+
+        let restarted = open();
+        assert_eq!(restarted.get("user:1"), Some("alice smith".to_string()));
+        assert_eq!(restarted.expiry("user:1"), None);
+        assert_eq!(restarted.get("session"), Some("token".to_string()));
+        assert_eq!(restarted.expiry("session"), Some(far));
+        assert_eq!(restarted.len(), 2);
+
+        // A truncated or overlong DUMP is refused
+        let mut short = tempfile::NamedTempFile::new().unwrap();
+        write!(short, "DUMP 2\r\nSET a 0 1\r\n").unwrap();
+        let err = load_file(&open(), short.path()).unwrap_err();
+        assert!(format!("{:#}", err).contains("announces 2 records, found 1"), "{:#}", err);
+        let mut long = tempfile::NamedTempFile::new().unwrap();
+        write!(long, "DUMP 1\r\nSET a 0 1\r\nSET b 0 2\r\n").unwrap();
+        let err = load_file(&open(), long.path()).unwrap_err();
+        assert!(format!("{:#}", err).contains(":3"), "{:#}", err);
+    }
+
+    #[tokio::test]
+    async fn test_loaded_keys_are_served_after_restart() {
+        let data_dir = tempfile::tempdir().unwrap();
+        let storage_path = data_dir.path().join("db").to_str().unwrap().to_string();
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        writeln!(file, "# seed data\nuser:1=alice\nuser:2=bob\nquery=a=b").unwrap();
+
+        // One-shot load, then close the store like the process would on exit
+        {
+            let store = SledEngine::new(&storage_path).unwrap();
+            assert_eq!(load_file(&store, file.path()).unwrap(), 3);
+        }
+
+        // Start a server on the same storage path
+        let port = std::net::TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port();
+        let mut config = Config::default();
+        config.port = port;
+        config.storage_path = storage_path.clone();
+        // sled releases its file lock from a background thread after drop
+        let mut store = SledEngine::new(&storage_path);
+        for _ in 0..50 {
+            if store.is_ok() {
+                break;
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+            store = SledEngine::new(&storage_path);
+        }
+        tokio::spawn(Server::new(config, Box::new(store.unwrap())).run());
+
+        let mut stream = None;
+        for _ in 0..50 {
+            match tokio::net::TcpStream::connect(("127.0.0.1", port)).await {
+                Ok(s) => {
+                    stream = Some(s);
+                    break;
+                }
+                Err(_) => tokio::time::sleep(std::time::Duration::from_millis(20)).await,
+            }
+        }
+        let (r, mut w) = stream.expect("server must accept connections").into_split();
+        let mut reader = tokio::io::BufReader::new(r);
+        for (key, expected) in [("user:1", "alice"), ("user:2", "bob"), ("query", "a=b")] {
+            w.write_all(format!("GET {}\r\n", key).as_bytes()).await.unwrap();
+            let mut line = String::new();
+            reader.read_line(&mut line).await.unwrap();
+            assert_eq!(line, format!("VALUE {}\r\n", expected));
+        }
+    }
+}
@@ -0,0 +1,256 @@
+//! # Per-Module Log Levels (`LOG LEVEL <module> <level>`)
+//!
+//! The installed logger sits in front of env_logger and decides per record:
+//! a record whose target starts with an overridden module uses that module's
+//! level (the longest matching override wins), every other record uses the
+//! global level (`CONFIG SET log_level`). The global max level of the `log`
+//! crate is kept at the highest of these, so raising one module does not
+//! make the others noisier.
+//!
+//! Modules are named by their path in the crate (`replication`, `sync`,
+//! `store::sled_engine`, ...), matched as a prefix of the log target. Aliases:
+//! `storage` for `store`, `merkle` for the Merkle tree modules. The level is
+//! one of `off`, `error`, `warn`, `info`, `debug`, `trace`, or `default` to
+//! drop the override.
+//!
+//! At start the same filter is seeded from `RUST_LOG` (`parse_directives`),
+//! in env_logger's syntax: `info,merkle_kv::replication=debug,sled=warn`
+//! sets the global level to info and overrides the two targets, so one
+//! module's debug directive does not turn on debug everywhere. A bare target
+//! means `trace`; without a bare level the global level is `off` when any
+//! directive is given (as env_logger does) and `error` when `RUST_LOG` is
+//! unset. A `/regex` message filter is not supported and is ignored.
+
+use log::{LevelFilter, Log, Metadata, Record};
+use once_cell::sync::Lazy;
+use std::sync::RwLock;
+
+/// The levels used by the installed logger.
+static FILTER: Lazy<ModuleFilter> = Lazy::new(|| ModuleFilter::new(LevelFilter::Info));
+
+/// Global level plus per-module overrides.
+pub struct ModuleFilter {
+    levels: RwLock<Levels>,
+}
+
+struct Levels {
+    global: LevelFilter,
+    /// (target prefix, level)
+    overrides: Vec<(String, LevelFilter)>,
+}
+
+impl ModuleFilter {
+    pub fn new(global: LevelFilter) -> Self {
+        Self { levels: RwLock::new(Levels { global, overrides: Vec::new() }) }
+    }
+
+    pub fn global(&self) -> LevelFilter {
+        self.read().global
+    }
+
+    pub fn set_global(&self, level: LevelFilter) {
+        self.write().global = level;
+    }
+
+    /// Override the level of `module`, or drop its override with None.
+    pub fn set_module(&self, module: &str, level: Option<LevelFilter>) {
+        self.set_target(target_prefix(module), level);
+    }
+
+    /// Override the level of log targets starting with `prefix`, as given.
+    fn set_target(&self, prefix: String, level: Option<LevelFilter>) {
+        let mut levels = self.write();
+        levels.overrides.retain(|(p, _)| *p != prefix);
+        if let Some(level) = level {
+            levels.overrides.push((prefix, level));
+        }
+    }
+
+    /// Level that applies to records from `target`.
+    pub fn level_for(&self, target: &str) -> LevelFilter {
+        let levels = self.read();
+        levels
+            .overrides
+            .iter()
+            .filter(|(prefix, _)| target.starts_with(prefix.as_str()))
+            .max_by_key(|(prefix, _)| prefix.len())
+            .map_or(levels.global, |(_, level)| *level)
+    }
+
+    /// Highest level any target can log at, for `log::set_max_level`.
+    pub fn max_level(&self) -> LevelFilter {
+        let levels = self.read();
+        levels.overrides.iter().map(|(_, l)| *l).fold(levels.global, Ord::max)
+    }
+
+    fn read(&self) -> std::sync::RwLockReadGuard<'_, Levels> {
+        self.levels.read().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn write(&self) -> std::sync::RwLockWriteGuard<'_, Levels> {
+        self.levels.write().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+/// Log target prefix for a module name, resolving aliases.
+fn target_prefix(module: &str) -> String {
+    let path = match module {
+        "storage" => "store",
+        "merkle" => "store::merkle",
+        other => other,
+    };
+    format!("{}::{}", env!("CARGO_CRATE_NAME"), path)
+}
+
+/// Passes records allowed by a `ModuleFilter` on to `inner`.
+pub struct FilteredLogger<L> {
+    inner: L,
+    filter: &'static ModuleFilter,
+}
+
+impl<L: Log> Log for FilteredLogger<L> {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= self.filter.level_for(metadata.target()) && self.inner.enabled(metadata)
+    }
+
+    fn log(&self, record: &Record) {
+        if self.enabled(record.metadata()) {
+            self.inner.log(record);
+        }
+    }
+
+    fn flush(&self) {
+        self.inner.flush()
+    }
+}
+
+/// Global level and (target prefix, level) overrides of a `RUST_LOG` value.
+pub fn parse_directives(spec: &str) -> (LevelFilter, Vec<(String, LevelFilter)>) {
+    let spec = spec.split('/').next().unwrap_or_default();
+    let mut global = None;
+    let mut overrides = Vec::new();
+    let mut any = false;
+    for directive in spec.split(',').map(str::trim).filter(|d| !d.is_empty()) {
+        any = true;
+        match directive.split_once('=') {
+            Some((target, level)) => match level.trim().parse() {
+                Ok(level) => overrides.push((target.trim().to_string(), level)),
+                Err(_) => eprintln!("Ignoring RUST_LOG directive {:?}: unknown level", directive),
+            },
+            None => match directive.parse() {
+                Ok(level) => global = Some(level),
+                Err(_) => overrides.push((directive.to_string(), LevelFilter::Trace)),
+            },
+        }
+    }
+    let default = if any { LevelFilter::Off } else { LevelFilter::Error };
+    (global.unwrap_or(default), overrides)
+}
+
+/// Install `inner` behind the shared filter, starting at `global` with the
+/// target `overrides` from `parse_directives`.
+pub fn init<L: Log + 'static>(inner: L, global: LevelFilter, overrides: Vec<(String, LevelFilter)>) -> Result<(), log::SetLoggerError> {
+    FILTER.set_global(global);
+    for (target, level) in overrides {
+        FILTER.set_target(target, Some(level));
+    }
+    log::set_boxed_logger(Box::new(FilteredLogger { inner, filter: &FILTER }))?;
+    log::set_max_level(FILTER.max_level());
+    Ok(())
+}
+
+/// Current global level (`CONFIG GET log_level`).
+pub fn global_level() -> LevelFilter {
+    FILTER.global()
+}
+
+/// Change the global level (`CONFIG SET log_level`).
+pub fn set_global_level(level: LevelFilter) {
+    FILTER.set_global(level);
+    log::set_max_level(FILTER.max_level());
+}
+
+/// Change the level of one module (`LOG LEVEL`).
+pub fn set_module_level(module: &str, level: Option<LevelFilter>) {
+    FILTER.set_module(module, level);
+    log::set_max_level(FILTER.max_level());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Mutex;
+
+    /// Collects the targets of the records it is given.
+    #[derive(Default)]
+    struct Capture(Mutex<Vec<(String, log::Level)>>);
+
+    impl Log for &'static Capture {
+        fn enabled(&self, _: &Metadata) -> bool {
+            true
+        }
+        fn log(&self, record: &Record) {
+            self.0.lock().unwrap().push((record.target().to_string(), record.level()));
+        }
+        fn flush(&self) {}
+    }
+
+    #[test]
+    fn test_module_override_only_affects_that_module() {
+        let filter: &'static ModuleFilter = Box::leak(Box::new(ModuleFilter::new(LevelFilter::Info)));
+        let capture: &'static Capture = Box::leak(Box::default());
+        let logger = FilteredLogger { inner: capture, filter };
+        filter.set_module("replication", Some(LevelFilter::Debug));
+        filter.set_module("merkle", Some(LevelFilter::Error));
+        assert_eq!(filter.max_level(), LevelFilter::Debug);
+
+        let emit = |target: &str, level: log::Level| {
+            logger.log(&Record::builder().target(target).level(level).args(format_args!("x")).build());
+        };
+        emit("merkle_kv::replication", log::Level::Debug);
+        emit("merkle_kv::sync", log::Level::Debug);
+        emit("merkle_kv::sync", log::Level::Info);
+        emit("merkle_kv::store::merkle_tracked", log::Level::Warn);
+        emit("merkle_kv::store::sled_engine", log::Level::Warn);
+        let seen = capture.0.lock().unwrap().clone();
+        assert_eq!(
+            seen,
+            vec![
+                ("merkle_kv::replication".to_string(), log::Level::Debug),
+                ("merkle_kv::sync".to_string(), log::Level::Info),
+                ("merkle_kv::store::sled_engine".to_string(), log::Level::Warn),
+            ]
+        );
+
+        filter.set_module("replication", None);
+        assert_eq!(filter.level_for("merkle_kv::replication"), LevelFilter::Info);
+        filter.set_module("storage", Some(LevelFilter::Trace));
+        assert_eq!(filter.level_for("merkle_kv::store::merkle"), LevelFilter::Error, "longest prefix wins");
+        assert_eq!(filter.level_for("merkle_kv::store::sled_engine"), LevelFilter::Trace);
+    }
+
+    #[test]
+    fn test_rust_log_directives_become_overrides() {
+        let (global, overrides) = parse_directives("info, merkle_kv::replication=debug,sled=WARN,merkle_kv::sync");
+        assert_eq!(global, LevelFilter::Info);
+        assert_eq!(
+            overrides,
+            vec![
+                ("merkle_kv::replication".to_string(), LevelFilter::Debug),
+                ("sled".to_string(), LevelFilter::Warn),
+                ("merkle_kv::sync".to_string(), LevelFilter::Trace),
+            ]
+        );
+        let filter = ModuleFilter::new(global);
+        for (target, level) in overrides {
+            filter.set_target(target, Some(level));
+        }
+        assert_eq!(filter.level_for("merkle_kv::replication"), LevelFilter::Debug);
+        assert_eq!(filter.level_for("merkle_kv::server"), LevelFilter::Info, "other modules stay at the global level");
+
+        assert_eq!(parse_directives(""), (LevelFilter::Error, vec![]));
+        assert_eq!(parse_directives("merkle_kv::sync=debug").0, LevelFilter::Off);
+        assert_eq!(parse_directives("debug/some regex"), (LevelFilter::Debug, vec![]));
+        assert_eq!(parse_directives("warn,x=loud"), (LevelFilter::Warn, vec![]));
+    }
+}
@@ -27,16 +27,49 @@ use anyhow::Result;
 use std::path::PathBuf;
 
 // Core modules for the MerkleKV system
+mod acl; // Per-user command and key ACLs (users file rules)
+mod allowlist; // IP allowlist for client connections
+mod auth; // AUTH providers (static password, users file)
+mod causality; // Causal-order audit of applied replication events
+mod cluster; // Primary / secondary roles and write forwarding
+mod compression; // Optional gzip compression of large replies
 mod config; // Configuration management
+mod consistency; // Read-your-writes tokens (HLC + applied watermarks)
+mod databases; // Logical databases (SELECT, server.databases)
+mod delta; // Delta replication of large values
+mod export; // EXPORT JSON (newline-delimited JSON keyspace export)
+mod group_commit; // Shared flushes of DURABLE writes
+mod key_encoding; // Byte-for-byte binary keys and values (server.require_utf8_keys)
+mod key_filter; // Replication include/exclude key prefixes
+mod loader; // One-shot bulk loader (--load)
+mod log_filter; // Per-module log levels (LOG LEVEL)
+mod metrics; // Metrics snapshot files (METRICS DUMP)
+mod net_addr; // Host / host:port parsing and dual-stack binding
+mod peers; // CLUSTER PEERS directory of configured and discovered peers
+mod pipeline; // Reply coalescing for pipelined commands
 mod protocol; // Command parsing and protocol handling
+mod proxy_protocol; // PROXY protocol v1 header parsing
 mod replication; // MQTT-based replication (stub)
+mod runtime_config; // CONFIG GET / CONFIG SET
+mod script; // EVAL transactions of primitive ops
+mod self_test; // Startup self-test (--self-test)
 mod server; // TCP server for client connections
+mod snapshot; // DUMP snapshots and --bootstrap-from
 mod store; // Storage engine and Merkle tree
+mod streaming; // Chunked GET/SET of large values (STREAM)
+mod subscriptions; // Parked SUBSCRIBE MERKLE sessions resumed by id
 mod sync; // Anti-entropy synchronization (stub)
+mod tasks; // Background task registry (TASKS)
+mod tls; // Client and MQTT broker TLS (CONFIG RELOAD TLS)
+mod transport; // Connection-level compression negotiated by HELLO
+mod verify; // VERIFY CONSISTENT (test-only peer comparison)
+mod version; // VERSION reply: build, protocol and feature info
+mod watermark; // Write rejection above storage.write_reject_bytes
+mod webhook; // HTTP webhook for key changes (hooks.url)
 mod change_event; // Change event schema & codecs
 
 // Import storage engines
-use crate::store::{KVEngineStoreTrait, KvEngine, RwLockEngine, SledEngine};
+use crate::store::{ExpiringEngine, KVEngineStoreTrait, KvEngine, RwLockEngine, SledEngine, TieredEngine, WalEngine};
 
 /// Main entry point for the MerkleKV server.
 ///
@@ -58,16 +91,30 @@ use crate::store::{KVEngineStoreTrait, KvEngine, RwLockEngine, SledEngine};
 /// * `--config <path>` - Path to configuration file (default: config.toml)
 /// * `--engine <type>` - Storage engine type: "rwlock" or "kv" (overrides config file)
 /// * `--storage-path <path>` - Storage path (overrides config file)
+/// * `--load <file>` - Load a DUMP file or `key=value` lines into the persistent store and exit
+/// * `--hash-password` - Read a password from stdin, print its hash for an `auth.users_file` line and exit
+/// * `--bootstrap-from <host:port>` - Copy a peer's snapshot (`DUMP`) into the store before serving
+/// * `--self-test` - Check the engine, WAL and MQTT broker, print a report and exit (1 on failure)
 fn main() -> Result<()> {
     // Initialize logging - use RUST_LOG environment variable to control verbosity
     // Example: RUST_LOG=info cargo run
-    env_logger::init();
+    // env_logger accepts every level and `log_filter` is the gate, seeded
+    // with RUST_LOG's per-module directives, so `CONFIG SET log_level` and
+    // `LOG LEVEL` can change verbosity at runtime.
+    let (initial_level, overrides) = log_filter::parse_directives(&std::env::var("RUST_LOG").unwrap_or_default());
+    let logger = env_logger::Builder::from_env(env_logger::Env::new().write_style("RUST_LOG_STYLE"))
+        .filter_level(log::LevelFilter::Trace)
+        .build();
+    log_filter::init(logger, initial_level, overrides)?;
 
     // Parse command line arguments
     let args: Vec<String> = std::env::args().collect();
     let mut config_path = PathBuf::from("config.toml");
     let mut engine_type = None;
     let mut storage_path = None;
+    let mut load_path = None;
+    let mut bootstrap_peer = None;
+    let mut self_test = false;
 
     let mut i = 1;
     while i < args.len() {
@@ -99,6 +146,40 @@ fn main() -> Result<()> {
                     std::process::exit(1);
                 }
             }
+            "--hash-password" => {
+                // From stdin, not argv: arguments show up in `ps` and shell history
+                let mut password = String::new();
+                std::io::stdin().read_line(&mut password)?;
+                let password = password.trim_end_matches(['\r', '\n']);
+                if password.is_empty() {
+                    eprintln!("Error: --hash-password reads a password from stdin, got none");
+                    std::process::exit(1);
+                }
+                println!("{}", auth::PasswordHash::new(password));
+                return Ok(());
+            }
+            "--load" => {
+                if i + 1 < args.len() {
+                    load_path = Some(PathBuf::from(&args[i + 1]));
+                    i += 2;
+                } else {
+                    eprintln!("Error: --load requires a file argument");
+                    std::process::exit(1);
+                }
+            }
+            "--bootstrap-from" => {
+                if i + 1 < args.len() {
+                    bootstrap_peer = Some(args[i + 1].clone());
+                    i += 2;
+                } else {
+                    eprintln!("Error: --bootstrap-from requires a host:port argument");
+                    std::process::exit(1);
+                }
+            }
+            "--self-test" => {
+                self_test = true;
+                i += 1;
+            }
             _ => i += 1,
         }
     }
@@ -114,38 +195,96 @@ fn main() -> Result<()> {
         config.storage_path = path;
     }
 
+    // One-shot loader mode: seed the persistent store and exit without serving
+    if let Some(path) = load_path {
+        if config.engine != "sled" && config.storage.wal_path.is_none() {
+            eprintln!("Error: --load needs a persistent store: the 'sled' engine or storage.wal_path (got '{}')", config.engine);
+            std::process::exit(1);
+        }
+        // DUMP expiries go through the expiry layer, which logs them to the WAL
+        let store = ExpiringEngine::new(open_store(&config)?, false);
+        let loaded = loader::load_file(&store, &path)?;
+        println!("Loaded {} keys from {}", loaded, path.display());
+        return Ok(());
+    }
+
     // Create a multi-threaded async runtime for handling concurrent connections
     let runtime = tokio::runtime::Builder::new_multi_thread()
         .enable_all() // Enable all Tokio features (timers, I/O, etc.)
         .build()?;
 
+    // Self-test mode: check the node can run with this config, then exit
+    if self_test {
+        let report = runtime.block_on(self_test::run(&config, open_store(&config)));
+        println!("{}", report);
+        std::process::exit(report.exit_code());
+    }
+
     // Start the server in the async runtime
     runtime.block_on(async {
-        // Initialize the storage engine based on configuration
-        let store: Box<dyn KVEngineStoreTrait + Send + Sync> = match config.engine.as_str() {
-            "rwlock" => {
-                println!("Using thread-safe RwLockEngine");
-                Box::new(RwLockEngine::new(&config.storage_path)?)
-            }
-            "kv" => {
-                println!("⚠️  WARNING: Using non-thread-safe KvEngine!");
-                println!("   This engine is NOT safe for concurrent access.");
-                println!("   Only use this for single-threaded applications or testing.");
-                Box::new(KvEngine::new(&config.storage_path)?)
-            }
-            "sled" => {
-                println!("Using persistent SledEngine");
-                Box::new(SledEngine::new(&config.storage_path)?)
-            }
-            _ => {
-                eprintln!("Error: Unknown engine type '{}'", config.engine);
-                eprintln!("Available engines: rwlock, kv, sled");
-                std::process::exit(1);
-            }
-        };
+        let store = open_store(&config)?;
 
         // Create and start the TCP server
-        let server = server::Server::new(config.clone(), store);
+        let mut server = server::Server::new(config.clone(), store);
+        if let Some(peer) = bootstrap_peer {
+            server.bootstrap_from(&peer).await?;
+        }
         server.run().await
     })
 }
+
+/// Initialize the storage engine selected by `config.engine`.
+fn open_store(config: &config::Config) -> Result<Box<dyn KVEngineStoreTrait + Send + Sync>> {
+    let store: Box<dyn KVEngineStoreTrait + Send + Sync> = match config.engine.as_str() {
+        "rwlock" => {
+            let engine = RwLockEngine::with_hash_fn(&config.storage_path, config.storage.hash_fn)?
+                .with_lock_stripes(config.storage.lock_stripes);
+            println!(
+                "Using thread-safe RwLockEngine (hash_fn={}, lock_stripes={})",
+                config.storage.hash_fn,
+                engine.lock_stripes()
+            );
+            Box::new(engine)
+        }
+        "kv" => {
+            println!("⚠️  WARNING: Using non-thread-safe KvEngine!");
+            println!("   This engine is NOT safe for concurrent access.");
+            println!("   Only use this for single-threaded applications or testing.");
+            Box::new(KvEngine::new(&config.storage_path)?)
+        }
+        "sled" => {
+            println!("Using persistent SledEngine");
+            Box::new(SledEngine::new(&config.storage_path)?)
+        }
+        _ => {
+            eprintln!("Error: Unknown engine type '{}'", config.engine);
+            eprintln!("Available engines: rwlock, kv, sled");
+            std::process::exit(1);
+        }
+    };
+    let store: Box<dyn KVEngineStoreTrait + Send + Sync> =
+        match (&config.storage.cold_tier_path, config.storage.hot_tier_max_bytes) {
+            (Some(_), max_bytes) if max_bytes > 0 && config.engine == "sled" => {
+                println!("⚠️  storage.cold_tier_path ignored: the sled engine is already on disk");
+                store
+            }
+            (Some(path), max_bytes) if max_bytes > 0 => {
+                println!("Spilling to cold tier at {} above {} bytes in memory", path, max_bytes);
+                Box::new(TieredEngine::new(store, Box::new(SledEngine::new(path)?), max_bytes)?)
+            }
+            _ => store,
+        };
+    match &config.storage.wal_path {
+        Some(_) if config.engine == "sled" => {
+            println!("⚠️  storage.wal_path ignored: the sled engine is already on disk");
+            Ok(store)
+        }
+        Some(path) => {
+            println!("Logging writes to {} ({:?})", path, config.storage.wal_format);
+            let wal = WalEngine::open(store, std::path::Path::new(path), config.storage.wal_format)?
+                .with_max_dirty_writes(config.storage.max_dirty_writes);
+            Ok(Box::new(wal))
+        }
+        None => Ok(store),
+    }
+}
@@ -0,0 +1,255 @@
+//! # Metrics Snapshots (`METRICS DUMP`, `metrics.dump_path`)
+//!
+//! For deployments without a scraper, the `STATS` counters can be written to
+//! `metrics.dump_path`, on demand (`METRICS DUMP [TEXT|JSON]`) or every
+//! `metrics.dump_interval_seconds` by the `metrics_dump` task:
+//!
+//! - **`text`** (default): Prometheus text exposition, one
+//!   `merklekv_<name> <value>` sample per counter, readable by the
+//!   node_exporter textfile collector
+//! - **`json`**: one object with `timestamp_ms`, `uptime_seconds`,
+//!   `used_memory_kb` and a `counters` map
+//!
+//! The snapshot is written to a temporary file next to the path and renamed
+//! over it, so readers never see a half-written file. Clients cannot name
+//! the file: only the configured path is ever written, so a connection
+//! cannot overwrite the WAL, the config or anything else the server may write.
+//!
+//! ## Scrape Socket (`metrics.unix_socket_path`)
+//!
+//! For a collector running next to the node (e.g. a sidecar), the metrics
+//! are also served over HTTP on a Unix domain socket instead of a TCP port:
+//! `GET /metrics` answers with the text format, `GET /metrics.json` with the
+//! JSON one. Each connection gets one response and is closed. A socket file
+//! left behind by an earlier run is replaced at start.
+//!
+//! `GET /ready` is the load balancer probe: `200` normally, `503` while the
+//! replication lag (see `consistency`) is above `replication.max_lag_seconds`,
+//! so a replica that fell behind stops getting reads until it catches up.
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::Path;
+use tokio::io::{AsyncBufReadExt, AsyncRead, AsyncWrite, AsyncWriteExt, BufReader};
+
+use crate::consistency::ConsistencyTracker;
+use crate::server::ServerStats;
+use crate::store::expiring::now_ms;
+
+/// File format of a metrics snapshot (`metrics.dump_format`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum MetricsFormat {
+    #[default]
+    Text,
+    Json,
+}
+
+/// Counters that can go down; every other counter only grows.
+const GAUGES: &[&str] = &["active_connections"];
+
+/// Render the current metrics of `stats` in `format`.
+pub fn render(stats: &ServerStats, format: MetricsFormat) -> String {
+    match format {
+        MetricsFormat::Text => {
+            let mut out = String::new();
+            let mut sample = |name: &str, kind: &str, value: u64| {
+                out.push_str(&format!("# TYPE merklekv_{} {}\nmerklekv_{} {}\n", name, kind, name, value));
+            };
+            sample("uptime_seconds", "gauge", stats.uptime_seconds());
+            for (name, value) in stats.counters() {
+                sample(name, if GAUGES.contains(&name) { "gauge" } else { "counter" }, value);
+            }
+            sample("used_memory_kb", "gauge", ServerStats::used_memory_kb());
+            out
+        }
+        MetricsFormat::Json => {
+            let counters: serde_json::Map<String, serde_json::Value> =
+                stats.counters().into_iter().map(|(name, value)| (name.to_string(), value.into())).collect();
+            let snapshot = serde_json::json!({
+                "timestamp_ms": now_ms(),
+                "uptime_seconds": stats.uptime_seconds(),
+                "used_memory_kb": ServerStats::used_memory_kb(),
+                "counters": counters,
+            });
+            format!("{}\n", snapshot)
+        }
+    }
+}
+
+/// Write a snapshot of `stats` to `path`, replacing it.
+///
+/// # Returns
+/// * `Result<usize>` - Bytes written
+pub fn dump(stats: &ServerStats, path: &Path, format: MetricsFormat) -> Result<usize> {
+    let snapshot = render(stats, format);
+    let mut tmp = path.as_os_str().to_owned();
+    tmp.push(".tmp");
+    fs::write(&tmp, &snapshot).with_context(|| format!("write metrics {}", Path::new(&tmp).display()))?;
+    fs::rename(&tmp, path).with_context(|| format!("replace metrics {}", path.display()))?;
+    Ok(snapshot.len())
+}
+
+/// Inputs of the `/ready` probe.
+pub struct Readiness {
+    pub tracker: std::sync::Arc<ConsistencyTracker>,
+    /// `replication.max_lag_seconds` (`0` = lag never fails the probe)
+    pub max_lag_seconds: u64,
+}
+
+impl Readiness {
+    /// Why the node should not get traffic now, if it should not.
+    fn problem(&self) -> Option<String> {
+        let lag = self.tracker.lag();
+        (self.max_lag_seconds > 0 && lag.as_secs_f64() > self.max_lag_seconds as f64).then(|| {
+            format!("replication lag {:.1}s exceeds replication.max_lag_seconds ({})", lag.as_secs_f64(), self.max_lag_seconds)
+        })
+    }
+}
+
+/// Bind the scrape socket at `path`, replacing a stale socket file (but no
+/// other kind of file).
+#[cfg(unix)]
+pub fn bind_unix(path: &Path) -> Result<tokio::net::UnixListener> {
+    use std::os::unix::fs::FileTypeExt;
+    if let Ok(meta) = fs::symlink_metadata(path) {
+        if !meta.file_type().is_socket() {
+            anyhow::bail!("metrics socket path {} exists and is not a socket", path.display());
+        }
+        fs::remove_file(path).with_context(|| format!("remove stale metrics socket {}", path.display()))?;
+    }
+    tokio::net::UnixListener::bind(path).with_context(|| format!("bind metrics socket {}", path.display()))
+}
+
+/// Answer scrapes on `listener` until the server stops.
+#[cfg(unix)]
+pub async fn serve_unix(listener: tokio::net::UnixListener, stats: std::sync::Arc<ServerStats>, readiness: std::sync::Arc<Readiness>) {
+    loop {
+        match listener.accept().await {
+            Ok((stream, _)) => {
+                let stats = std::sync::Arc::clone(&stats);
+                let readiness = std::sync::Arc::clone(&readiness);
+                tokio::spawn(async move {
+                    if let Err(e) = respond(stream, &stats, &readiness).await {
+                        log::debug!("Metrics scrape failed: {:#}", e);
+                    }
+                });
+            }
+            Err(e) => log::warn!("Error accepting metrics connection: {}", e),
+        }
+    }
+}
+
+/// Read one HTTP request from `stream` and write the response to it.
+async fn respond<S: AsyncRead + AsyncWrite + Unpin>(stream: S, stats: &ServerStats, readiness: &Readiness) -> Result<()> {
+    let mut stream = BufReader::new(stream);
+    let mut request = String::new();
+    stream.read_line(&mut request).await?;
+    // Headers are not needed; read up to the blank line ending them
+    let mut header = String::new();
+    while stream.read_line(&mut header).await? > 2 {
+        header.clear();
+    }
+    let mut parts = request.split_whitespace();
+    let (status, content_type, body) = match (parts.next(), parts.next()) {
+        (Some("GET"), Some("/metrics")) => ("200 OK", "text/plain; version=0.0.4", render(stats, MetricsFormat::Text)),
+        (Some("GET"), Some("/metrics.json")) => ("200 OK", "application/json", render(stats, MetricsFormat::Json)),
+        (Some("GET"), Some("/ready")) => match readiness.problem() {
+            None => ("200 OK", "text/plain", "ready\n".to_string()),
+            Some(problem) => ("503 Service Unavailable", "text/plain", format!("not ready: {}\n", problem)),
+        },
+        (Some("GET"), _) => ("404 Not Found", "text/plain", "not found\n".to_string()),
+        _ => ("405 Method Not Allowed", "text/plain", "only GET is supported\n".to_string()),
+    };
+    let response = format!(
+        "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        content_type,
+        body.len(),
+        body
+    );
+    stream.write_all(response.as_bytes()).await?;
+    stream.shutdown().await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocol::Command;
+
+    #[test]
+    fn test_dump_writes_current_counters_in_each_format() {
+        let stats = ServerStats::new();
+        stats.increment_command_counter(&Command::Get { key: "k".to_string() });
+        stats.increment_command_counter(&Command::Get { key: "k".to_string() });
+        let dir = tempfile::tempdir().unwrap();
+
+        let path = dir.path().join("metrics.prom");
+        let written = dump(&stats, &path, MetricsFormat::Text).unwrap();
+        let text = fs::read_to_string(&path).unwrap();
+        assert_eq!(text.len(), written);
+        assert!(text.contains("# TYPE merklekv_get_commands counter\nmerklekv_get_commands 2\n"), "{}", text);
+        assert!(text.contains("merklekv_total_commands 2\n"), "{}", text);
+        assert!(text.contains("# TYPE merklekv_active_connections gauge\n"), "{}", text);
+
+        let path = dir.path().join("metrics.json");
+        dump(&stats, &path, MetricsFormat::Json).unwrap();
+        let json: serde_json::Value = serde_json::from_str(&fs::read_to_string(&path).unwrap()).unwrap();
+        assert_eq!(json["counters"]["get_commands"], 2);
+        assert!(json["timestamp_ms"].as_u64().unwrap() > 0);
+        assert!(!dir.path().join("metrics.json.tmp").exists());
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_metrics_are_scraped_over_the_unix_socket() {
+        use tokio::io::AsyncReadExt;
+        let stats = std::sync::Arc::new(ServerStats::new());
+        stats.increment_command_counter(&Command::Get { key: "k".to_string() });
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("metrics.sock");
+        // A socket left by an earlier run is replaced
+        drop(std::os::unix::net::UnixListener::bind(&path).unwrap());
+        let tracker = std::sync::Arc::new(ConsistencyTracker::new("replica"));
+        let readiness = std::sync::Arc::new(Readiness { tracker: std::sync::Arc::clone(&tracker), max_lag_seconds: 5 });
+        tokio::spawn(serve_unix(bind_unix(&path).unwrap(), stats, readiness));
+
+        let scrape = |request: &'static str| {
+            let path = path.clone();
+            async move {
+                let mut stream = tokio::net::UnixStream::connect(&path).await.unwrap();
+                stream.write_all(request.as_bytes()).await.unwrap();
+                let mut response = String::new();
+                stream.read_to_string(&mut response).await.unwrap();
+                response
+            }
+        };
+        let response = scrape("GET /metrics HTTP/1.1\r\nHost: localhost\r\n\r\n").await;
+        let (head, body) = response.split_once("\r\n\r\n").unwrap();
+        assert!(head.starts_with("HTTP/1.1 200 OK\r\n"), "{}", head);
+        assert!(head.contains(&format!("Content-Length: {}", body.len())), "{}", head);
+        assert!(body.contains("merklekv_get_commands 1\n"), "{}", body);
+
+        let response = scrape("GET /metrics.json HTTP/1.1\r\n\r\n").await;
+        let json: serde_json::Value = serde_json::from_str(response.split_once("\r\n\r\n").unwrap().1).unwrap();
+        assert_eq!(json["counters"]["get_commands"], 1);
+        assert!(scrape("GET / HTTP/1.1\r\n\r\n").await.starts_with("HTTP/1.1 404"));
+
+        // Readiness follows the replication lag
+        let wall_ns = || std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).unwrap().as_nanos() as u64;
+        assert!(scrape("GET /ready HTTP/1.1\r\n\r\n").await.starts_with("HTTP/1.1 200"));
+        tracker.observe("primary", wall_ns() - 30_000_000_000);
+        let response = scrape("GET /ready HTTP/1.1\r\n\r\n").await;
+        assert!(response.starts_with("HTTP/1.1 503"), "{}", response);
+        assert!(response.contains("exceeds replication.max_lag_seconds (5)"), "{}", response);
+        tracker.observe("primary", wall_ns());
+        assert!(scrape("GET /ready HTTP/1.1\r\n\r\n").await.starts_with("HTTP/1.1 200"));
+        assert!(scrape("POST /metrics HTTP/1.1\r\n\r\n").await.starts_with("HTTP/1.1 405"));
+
+        let file = dir.path().join("not-a-socket");
+        fs::write(&file, "").unwrap();
+        assert!(bind_unix(&file).is_err());
+    }
+}
@@ -0,0 +1,178 @@
+//! # Network Addresses
+//!
+//! Parsing and formatting of host / `host:port` strings so IPv4, IPv6 and
+//! hostnames are handled the same way everywhere (listen address, peer lists,
+//! SYNC targets).
+//!
+//! ## Rules
+//!
+//! - A bare host may be an IPv4 literal, an IPv6 literal with or without
+//!   brackets (`::1`, `[::1]`), or a hostname
+//! - In `host:port` form IPv6 literals must be bracketed (`[::1]:7379`);
+//!   `::1:7379` is rejected as ambiguous
+//! - Formatting always brackets IPv6 literals, so the result can be parsed
+//!   back and passed to `connect`
+//!
+//! Listening on `::` binds dual-stack (IPv6 with `IPV6_V6ONLY` off), so IPv4
+//! clients are accepted too, as IPv4-mapped addresses.
+
+use anyhow::{anyhow, Context, Result};
+use socket2::{Domain, Protocol, Socket, Type};
+use std::net::{IpAddr, SocketAddr, ToSocketAddrs};
+
+/// Strip the brackets of a bracketed IPv6 literal.
+fn unbracket(host: &str) -> &str {
+    host.strip_prefix('[')
+        .and_then(|h| h.strip_suffix(']'))
+        .unwrap_or(host)
+}
+
+/// Check that `host` is an IP literal or a syntactically valid hostname.
+pub fn validate_host(host: &str) -> Result<()> {
+    let bare = unbracket(host);
+    if bare.parse::<IpAddr>().is_ok() {
+        return Ok(());
+    }
+    if bare != host || bare.contains(':') {
+        return Err(anyhow!("invalid IPv6 address '{}'", host));
+    }
+    let valid_label = |l: &str| {
+        !l.is_empty()
+            && l.len() <= 63
+            && !l.starts_with('-')
+            && !l.ends_with('-')
+            && l.chars().all(|c| c.is_ascii_alphanumeric() || c == '-')
+    };
+    if host.is_empty() || host.len() > 253 || !host.split('.').all(valid_label) {
+        return Err(anyhow!("invalid host '{}' (expected an IP address or hostname)", host));
+    }
+    Ok(())
+}
+
+/// Split `host:port`, accepting bracketed IPv6 literals. The host is returned without brackets.
+pub fn split_host_port(addr: &str) -> Result<(String, u16)> {
+    let (host, port) = if let Some(rest) = addr.strip_prefix('[') {
+        let (host, port) = rest
+            .split_once("]:")
+            .ok_or_else(|| anyhow!("invalid address '{}', expected [ipv6]:port", addr))?;
+        (host, port)
+    } else {
+        let (host, port) = addr
+            .rsplit_once(':')
+            .ok_or_else(|| anyhow!("invalid address '{}', expected host:port", addr))?;
+        if host.contains(':') {
+            return Err(anyhow!("invalid address '{}': IPv6 literals must be bracketed, e.g. [::1]:7379", addr));
+        }
+        (host, port)
+    };
+    validate_host(host).with_context(|| format!("invalid address '{}'", addr))?;
+    let port = port
+        .parse::<u16>()
+        .map_err(|_| anyhow!("invalid port in address '{}'", addr))?;
+    Ok((host.to_string(), port))
+}
+
+/// Format `host:port`, bracketing IPv6 literals.
+pub fn join_host_port(host: &str, port: u16) -> String {
+    let bare = unbracket(host);
+    if bare.contains(':') {
+        format!("[{}]:{}", bare, port)
+    } else {
+        format!("{}:{}", bare, port)
+    }
+}
+
+/// Resolve the listen address for `host` and `port`.
+pub fn resolve_listen_addr(host: &str, port: u16) -> Result<SocketAddr> {
+    let bare = unbracket(host);
+    if let Ok(ip) = bare.parse::<IpAddr>() {
+        return Ok(SocketAddr::new(ip, port));
+    }
+    (bare, port)
+        .to_socket_addrs()
+        .with_context(|| format!("resolve listen host '{}'", host))?
+        .next()
+        .ok_or_else(|| anyhow!("listen host '{}' did not resolve to any address", host))
+}
+
+/// Bind a TCP listener with a pending-connection queue of `backlog`.
+/// IPv6 sockets are dual-stack (`IPV6_V6ONLY` off).
+pub fn bind_listener(addr: SocketAddr, backlog: u32) -> Result<tokio::net::TcpListener> {
+    let socket = Socket::new(Domain::for_address(addr), Type::STREAM, Some(Protocol::TCP))?;
+    if addr.is_ipv6() {
+        socket.set_only_v6(false)?;
+    }
+    socket.set_reuse_address(true)?;
+    socket.set_nonblocking(true)?;
+    socket.bind(&addr.into()).with_context(|| format!("bind {}", addr))?;
+    socket.listen(i32::try_from(backlog).unwrap_or(i32::MAX))?;
+    Ok(tokio::net::TcpListener::from_std(socket.into())?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_validate_host() {
+        for ok in ["127.0.0.1", "0.0.0.0", "::", "::1", "[::1]", "localhost", "node-1.example.com"] {
+            assert!(validate_host(ok).is_ok(), "{}", ok);
+        }
+        for bad in ["", ":::1", "[::1", "fe80::zz", "bad host", "-lead.example", "a..b"] {
+            assert!(validate_host(bad).is_err(), "{}", bad);
+        }
+    }
+
+    #[test]
+    fn test_split_and_join_host_port() {
+        assert_eq!(split_host_port("[::1]:7379").unwrap(), ("::1".to_string(), 7379));
+        assert_eq!(split_host_port("10.0.0.2:7380").unwrap(), ("10.0.0.2".to_string(), 7380));
+        assert_eq!(split_host_port("node2:7379").unwrap(), ("node2".to_string(), 7379));
+        assert!(split_host_port("::1:7379").is_err());
+        assert!(split_host_port("[::1]").is_err());
+        assert!(split_host_port("node2:http").is_err());
+
+        assert_eq!(join_host_port("::1", 7379), "[::1]:7379");
+        assert_eq!(join_host_port("[::1]", 7379), "[::1]:7379");
+        assert_eq!(join_host_port("127.0.0.1", 7379), "127.0.0.1:7379");
+        assert_eq!(resolve_listen_addr("[::]", 1).unwrap(), "[::]:1".parse().unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_dual_stack_listener_accepts_ipv4_and_ipv6() {
+        let listener = bind_listener(resolve_listen_addr("::", 0).unwrap(), 1024).unwrap();
+        let port = listener.local_addr().unwrap().port();
+        tokio::spawn(async move {
+            loop {
+                let _ = listener.accept().await;
+            }
+        });
+        tokio::net::TcpStream::connect(("::1", port)).await.unwrap();
+        tokio::net::TcpStream::connect(("127.0.0.1", port)).await.unwrap();
+    }
+
+    /// Read the accept queue limit the kernel applied to a listening socket
+    /// (`tcpi_sacked` of `TCP_INFO` on Linux).
+    #[cfg(target_os = "linux")]
+    fn kernel_backlog(listener: &tokio::net::TcpListener) -> u32 {
+        use std::os::fd::AsRawFd;
+        let mut info: libc::tcp_info = unsafe { std::mem::zeroed() };
+        let mut len = std::mem::size_of::<libc::tcp_info>() as libc::socklen_t;
+        let rc = unsafe {
+            libc::getsockopt(listener.as_raw_fd(), libc::IPPROTO_TCP, libc::TCP_INFO, &mut info as *mut _ as *mut libc::c_void, &mut len)
+        };
+        assert_eq!(rc, 0, "getsockopt(TCP_INFO)");
+        info.tcpi_sacked
+    }
+
+    #[tokio::test]
+    async fn test_listener_uses_configured_backlog() {
+        let listener = bind_listener(resolve_listen_addr("127.0.0.1", 0).unwrap(), 7).unwrap();
+        #[cfg(target_os = "linux")]
+        assert_eq!(kernel_backlog(&listener), 7);
+        let addr = listener.local_addr().unwrap();
+        let accepted = tokio::spawn(async move { listener.accept().await.is_ok() });
+        tokio::net::TcpStream::connect(addr).await.unwrap();
+        assert!(accepted.await.unwrap());
+    }
+}
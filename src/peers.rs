@@ -0,0 +1,176 @@
+//! # Peer Directory (`CLUSTER PEERS`)
+//!
+//! The cluster as this node sees it. Peers come from:
+//!
+//! - **configuration**: the addresses in `anti_entropy.peer_list` and
+//!   `replication.peer_list`
+//! - **discovery**: nodes whose replicated writes this node applied, known by
+//!   their `client_id` (there is no gossip; the replication stream is how a
+//!   node learns about the others), and `SYNC <host> <port>` targets that are
+//!   not configured
+//!
+//! Every sync round records its result against the peer's address, together
+//! with the `client_id` the peer reports in INFO, which ties a configured
+//! address to the node whose writes arrive over replication. Reply:
+//!
+//! ```text
+//! PEERS <n>
+//! <addr|-> <client_id|-> <configured|discovered> last_seen_ms:<ms|-> last_sync:<ok|failed|never> last_sync_ms:<ms|-> status:<status>[ error:<message>]
+//! ```
+//!
+//! `last_seen_ms` is the latest contact: a successful sync or, for a known
+//! `client_id`, the newest replicated write applied from it. `status` is
+//! `reachable` / `unreachable` after the last sync with the address,
+//! `unknown` before any, and `broker` for nodes only known from replication,
+//! which this node never contacts directly.
+
+use anyhow::Result;
+use std::sync::{Mutex, MutexGuard};
+
+use crate::config::Config;
+use crate::store::expiring::now_ms;
+
+/// Outcome of the latest sync round with a peer.
+#[derive(Debug, Clone, PartialEq)]
+struct LastSync {
+    at_ms: u64,
+    error: Option<String>,
+}
+
+/// One peer, by address, `client_id`, or both.
+#[derive(Debug, Clone, Default, PartialEq)]
+struct Peer {
+    addr: Option<String>,
+    client_id: Option<String>,
+    configured: bool,
+    last_seen_ms: Option<u64>,
+    last_sync: Option<LastSync>,
+}
+
+/// Configured peers and what sync rounds learned about them.
+pub struct PeerTable {
+    peers: Mutex<Vec<Peer>>,
+}
+
+impl PeerTable {
+    /// The peers configured in `anti_entropy.peer_list` and `replication.peer_list`.
+    pub fn from_config(config: &Config) -> Self {
+        let mut peers: Vec<Peer> = Vec::new();
+        for addr in config.anti_entropy.peer_list.iter().chain(&config.replication.peer_list) {
+            if !peers.iter().any(|p| p.addr.as_ref() == Some(addr)) {
+                peers.push(Peer { addr: Some(addr.clone()), configured: true, ..Peer::default() });
+            }
+        }
+        Self { peers: Mutex::new(peers) }
+    }
+
+    /// Remember that the node at `addr` reports `client_id`.
+    pub fn identify(&self, addr: &str, client_id: &str) {
+        self.update(addr, |peer| peer.client_id = Some(client_id.to_string()));
+    }
+
+    /// Record the result of a sync round with `addr`.
+    pub fn sync_finished(&self, addr: &str, result: &Result<()>) {
+        let at_ms = now_ms();
+        self.update(addr, |peer| {
+            peer.last_sync = Some(LastSync { at_ms, error: result.as_ref().err().map(|e| format!("{:#}", e)) });
+            if result.is_ok() {
+                peer.last_seen_ms = Some(at_ms);
+            }
+        });
+    }
+
+    /// `CLUSTER PEERS` reply. `replicated` holds every source node whose
+    /// events were applied here, with its newest event timestamp (nanoseconds).
+    pub fn format(&self, replicated: &[(String, u64)]) -> String {
+        let mut peers = self.guard().clone();
+        for (client_id, ts) in replicated {
+            let seen_ms = ts / 1_000_000;
+            match peers.iter_mut().find(|p| p.client_id.as_ref() == Some(client_id)) {
+                Some(peer) => peer.last_seen_ms = Some(peer.last_seen_ms.map_or(seen_ms, |ms| ms.max(seen_ms))),
+                None => peers.push(Peer { client_id: Some(client_id.clone()), last_seen_ms: Some(seen_ms), ..Peer::default() }),
+            }
+        }
+        let mut out = format!("PEERS {}\r\n", peers.len());
+        for peer in &peers {
+            let (last_sync, status) = match (&peer.addr, &peer.last_sync) {
+                (None, _) => ("never", "broker"),
+                (Some(_), None) => ("never", "unknown"),
+                (Some(_), Some(LastSync { error: None, .. })) => ("ok", "reachable"),
+                (Some(_), Some(LastSync { error: Some(_), .. })) => ("failed", "unreachable"),
+            };
+            out.push_str(&format!(
+                "{} {} {} last_seen_ms:{} last_sync:{} last_sync_ms:{} status:{}",
+                peer.addr.as_deref().unwrap_or("-"),
+                peer.client_id.as_deref().unwrap_or("-"),
+                if peer.configured { "configured" } else { "discovered" },
+                or_dash(peer.last_seen_ms),
+                last_sync,
+                or_dash(peer.last_sync.as_ref().map(|s| s.at_ms)),
+                status,
+            ));
+            if let Some(LastSync { error: Some(error), .. }) = &peer.last_sync {
+                out.push_str(&format!(" error:{}", error));
+            }
+            out.push_str("\r\n");
+        }
+        out
+    }
+
+    /// Apply `f` to the peer at `addr`, adding it as discovered if unknown.
+    fn update(&self, addr: &str, f: impl FnOnce(&mut Peer)) {
+        let mut peers = self.guard();
+        let index = match peers.iter().position(|p| p.addr.as_deref() == Some(addr)) {
+            Some(index) => index,
+            None => {
+                peers.push(Peer { addr: Some(addr.to_string()), ..Peer::default() });
+                peers.len() - 1
+            }
+        };
+        f(&mut peers[index]);
+    }
+
+    fn guard(&self) -> MutexGuard<'_, Vec<Peer>> {
+        self.peers.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+fn or_dash(ms: Option<u64>) -> String {
+    ms.map_or_else(|| "-".to_string(), |ms| ms.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::anyhow;
+
+    #[test]
+    fn test_configured_synced_and_replicated_peers_are_merged() {
+        let mut config = Config::default();
+        config.anti_entropy.peer_list = vec!["10.0.0.2:7379".to_string(), "10.0.0.3:7379".to_string()];
+        config.replication.peer_list = vec!["10.0.0.2:7379".to_string()];
+        let table = PeerTable::from_config(&config);
+        assert_eq!(
+            table.format(&[]),
+            "PEERS 2\r\n\
+             10.0.0.2:7379 - configured last_seen_ms:- last_sync:never last_sync_ms:- status:unknown\r\n\
+             10.0.0.3:7379 - configured last_seen_ms:- last_sync:never last_sync_ms:- status:unknown\r\n"
+        );
+
+        table.identify("10.0.0.2:7379", "node-b");
+        table.sync_finished("10.0.0.2:7379", &Ok(()));
+        table.sync_finished("10.0.0.3:7379", &Err(anyhow!("connect 10.0.0.3:7379: refused")));
+        table.sync_finished("10.0.0.9:7379", &Ok(()));
+        // node-b's writes arrive later than the sync; node-d is only known from replication
+        let later_ns = (now_ms() + 5_000) * 1_000_000;
+        let reply = table.format(&[("node-b".to_string(), later_ns), ("node-d".to_string(), 1_000_000_000)]);
+        let lines: Vec<&str> = reply.split("\r\n").collect();
+        assert_eq!(lines[0], "PEERS 4");
+        assert!(lines[1].starts_with(&format!("10.0.0.2:7379 node-b configured last_seen_ms:{} last_sync:ok last_sync_ms:", later_ns / 1_000_000)), "{}", lines[1]);
+        assert!(lines[1].ends_with("status:reachable"), "{}", lines[1]);
+        assert!(lines[2].starts_with("10.0.0.3:7379 - configured last_seen_ms:- last_sync:failed"), "{}", lines[2]);
+        assert!(lines[2].ends_with("status:unreachable error:connect 10.0.0.3:7379: refused"), "{}", lines[2]);
+        assert!(lines[3].starts_with("10.0.0.9:7379 - discovered last_seen_ms:"), "{}", lines[3]);
+        assert_eq!(lines[4], "- node-d discovered last_seen_ms:1000 last_sync:never last_sync_ms:- status:broker");
+    }
+}
@@ -0,0 +1,118 @@
+//! # Reply Coalescing (`server.reply_batch_max`)
+//!
+//! A client that pipelines commands sends several request lines before
+//! reading any reply. Writing each reply on its own costs one `write` call
+//! per command; `ReplyWriter` instead keeps replies in a buffer while more
+//! complete request lines are already waiting, and writes them together:
+//!
+//! - when the input buffer holds no further complete line (the pipeline drained)
+//! - after `server.reply_batch_max` replies, so a very long pipeline still
+//!   gets its first replies early (`1` writes every reply at once)
+//!
+//! Code that waits for the client after writing (e.g. `READY` of
+//! `SET key STREAM`) must `flush` first.
+
+use std::io;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use tokio::io::{AsyncWrite, AsyncWriteExt, BufWriter};
+
+/// Buffered writer of a connection's replies.
+pub struct ReplyWriter<W: AsyncWrite> {
+    inner: BufWriter<W>,
+    /// Replies in the buffer since the last flush
+    pending: usize,
+    max_batch: usize,
+}
+
+impl<W: AsyncWrite + Unpin> ReplyWriter<W> {
+    /// Coalesce up to `max_batch` (at least 1) replies per write to `inner`.
+    pub fn new(inner: W, max_batch: usize) -> Self {
+        Self { inner: BufWriter::new(inner), pending: 0, max_batch: max_batch.max(1) }
+    }
+
+    /// The writer the replies go to; flush first so none are still buffered.
+    pub fn get_mut(&mut self) -> &mut W {
+        self.inner.get_mut()
+    }
+
+    /// Mark the end of a reply, before reading the next request: the
+    /// buffered replies are written unless `more_input` (another complete
+    /// request is already received) and the batch is not full.
+    pub async fn end_reply(&mut self, more_input: bool) -> io::Result<()> {
+        if self.inner.buffer().is_empty() {
+            return Ok(());
+        }
+        self.pending += 1;
+        if !more_input || self.pending >= self.max_batch {
+            self.flush().await?;
+        }
+        Ok(())
+    }
+}
+
+impl<W: AsyncWrite + Unpin> AsyncWrite for ReplyWriter<W> {
+    fn poll_write(mut self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.inner).poll_write(cx, buf)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        self.pending = 0;
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_shutdown(cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Collects written bytes and counts the `write` calls reaching it.
+    #[derive(Default)]
+    struct CountingWriter {
+        data: Vec<u8>,
+        writes: usize,
+    }
+
+    impl AsyncWrite for CountingWriter {
+        fn poll_write(mut self: Pin<&mut Self>, _cx: &mut Context<'_>, buf: &[u8]) -> Poll<io::Result<usize>> {
+            self.writes += 1;
+            self.data.extend_from_slice(buf);
+            Poll::Ready(Ok(buf.len()))
+        }
+
+        fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+            Poll::Ready(Ok(()))
+        }
+
+        fn poll_shutdown(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+            Poll::Ready(Ok(()))
+        }
+    }
+
+    /// Write 100 pipelined replies, the input draining after the last one.
+    async fn pipeline(max_batch: usize) -> CountingWriter {
+        let mut writer = ReplyWriter::new(CountingWriter::default(), max_batch);
+        for i in 0..100 {
+            writer.write_all(format!("VALUE {}\r\n", i).as_bytes()).await.unwrap();
+            writer.end_reply(i < 99).await.unwrap();
+        }
+        writer.end_reply(false).await.unwrap();
+        writer.inner.into_inner()
+    }
+
+    #[tokio::test]
+    async fn test_pipelined_replies_are_coalesced_into_fewer_writes() {
+        let expected: String = (0..100).map(|i| format!("VALUE {}\r\n", i)).collect();
+        let batched = pipeline(32).await;
+        assert_eq!(String::from_utf8(batched.data).unwrap(), expected);
+        assert_eq!(batched.writes, 4, "100 replies in batches of 32");
+
+        let unbatched = pipeline(1).await;
+        assert_eq!(String::from_utf8(unbatched.data).unwrap(), expected);
+        assert_eq!(unbatched.writes, 100);
+    }
+}
@@ -49,7 +49,10 @@
 //! - Success responses: `VALUE <data>`, `OK`
 //! - Error responses: `ERROR <message>`, `NOT_FOUND`
 
-use anyhow::{anyhow, Result};
+use anyhow::Result;
+use crate::consistency::Token;
+use crate::metrics::MetricsFormat;
+use crate::script::{self, ScriptOp};
 
 /// Represents the different commands that clients can send to the server.
 ///
@@ -64,6 +67,96 @@ pub enum ReplicateAction {
     Enable,
     Disable,
     Status,
+    /// Queue local changes instead of publishing them
+    Pause,
+    /// Publish the queued changes and resume publishing
+    Resume,
+    /// Report the de-dup cache of applied event ids
+    DedupStats,
+    /// Empty the de-dup cache
+    DedupClear,
+    /// Audit the applied events for causal-order violations
+    CheckCausality,
+}
+#[derive(Debug, Clone, PartialEq)]
+pub enum MerkleAction {
+    /// Rebuild the tree from the store and compare with the live tree
+    Verify,
+    /// Replace the live tree with a fresh rebuild
+    Rebuild,
+    /// Keys and values under the node at `path` (`0` = left, `1` = right, empty = root)
+    Fetch { path: String },
+    /// Drop nodes left behind by deleted keys and report how many went
+    Gc,
+}
+/// `OBJECT` subcommand: which timestamp of a key to report.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ObjectField {
+    /// Creation time (UNIX ms); kept across overwrites
+    Created,
+    /// Last write time (UNIX ms)
+    Modified,
+    /// Last read, write or TOUCH (UNIX ms)
+    Accessed,
+    /// Seconds since the last write
+    IdleTime,
+    /// Node the last write came from and when it was applied here (UNIX ms)
+    ReplInfo,
+    /// Peers that applied the latest acked write
+    Acks,
+    /// Whether the last write is fsynced to the write-ahead log
+    Durable,
+}
+/// `SET ... CL=<level>`: how many peers must confirm a write before the reply.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum WriteConsistency {
+    /// No peer acks; replicate in the background (the default)
+    One,
+    /// A majority of the cluster, counting this node
+    Quorum,
+    /// Every peer in `replication.peer_list`
+    All,
+}
+
+impl WriteConsistency {
+    /// Peer acks needed in a cluster of this node plus `peers` others.
+    pub fn required_acks(self, peers: usize) -> usize {
+        match self {
+            WriteConsistency::One => 0,
+            WriteConsistency::Quorum => peers.div_ceil(2),
+            WriteConsistency::All => peers,
+        }
+    }
+}
+/// `EXPIRE` flag: when the new TTL may replace the current one.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ExpireCondition {
+    /// Only if the key has no TTL
+    Nx,
+    /// Only if the key already has a TTL
+    Xx,
+    /// Only if the new expiry is later than the current one (no TTL counts as never)
+    Gt,
+    /// Only if the new expiry is earlier than the current one (no TTL counts as never)
+    Lt,
+}
+
+impl ExpireCondition {
+    /// Whether a key expiring at `current` (None = never) may be given `new` (UNIX ms).
+    pub fn allows(self, current: Option<u64>, new: u64) -> bool {
+        match self {
+            ExpireCondition::Nx => current.is_none(),
+            ExpireCondition::Xx => current.is_some(),
+            ExpireCondition::Gt => current.is_some_and(|at| new > at),
+            ExpireCondition::Lt => current.is_none_or(|at| new < at),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum SubscribeChannel {
+    /// Live Merkle root changes
+    Merkle,
 }
 #[derive(Debug, Clone, PartialEq)]
 pub enum Command {
@@ -77,6 +170,40 @@ pub enum Command {
         key: String,
     },
 
+    /// GET that first waits until this node has applied the write behind `token`
+    GetWithToken {
+        key: String,
+        token: Token,
+    },
+
+    /// GET whose value is sent as `CHUNK` frames (`GET key STREAM`)
+    GetStream {
+        key: String,
+    },
+
+    /// GET that also returns the CRC32 of the stored value (`GET key WITHCRC`)
+    GetWithCrc {
+        key: String,
+    },
+
+    /// GET that also returns the remaining TTL in seconds (`GET key WITHTTL`)
+    GetWithTtl {
+        key: String,
+    },
+
+    /// Add to the number at a JSON pointer in a JSON value (`JSON.INCR key path amount`)
+    JsonIncr {
+        key: String,
+        path: String,
+        amount: serde_json::Number,
+    },
+
+    /// SET whose `len`-byte value follows as `CHUNK` frames (`SET key STREAM <len>`)
+    SetStream {
+        key: String,
+        len: usize,
+    },
+
     /// Store a key-value pair
     Set {
         /// The key to store
@@ -85,6 +212,42 @@ pub enum Command {
         value: String,
     },
 
+    /// Store a key-value pair that expires (`SET key value EX seconds|PX millis`).
+    /// Any SET whose value ends in `EX <n>` or `PX <n>` parses as this.
+    SetEx {
+        /// The key to store
+        key: String,
+        /// The value to associate with the key
+        value: String,
+        /// Time to live in milliseconds
+        ttl_ms: u64,
+    },
+
+    /// Set the TTL of an existing key without rewriting its value
+    Expire {
+        key: String,
+        seconds: u64,
+        /// NX / XX / GT / LT; None always applies
+        condition: Option<ExpireCondition>,
+    },
+
+    /// Remove the TTL of an existing key
+    Persist {
+        key: String,
+    },
+
+    /// Remaining TTL in seconds (-1 = no expiry, -2 = no such key)
+    Ttl {
+        key: String,
+    },
+
+    /// Creation / modification / access time or replication origin of a key
+    /// (`OBJECT CREATED|MODIFIED|ACCESSED|IDLETIME|REPLINFO|ACKS|DURABLE key`)
+    Object {
+        field: ObjectField,
+        key: String,
+    },
+
     /// Delete a key-value pair
     Delete {
         /// The key to delete
@@ -105,11 +268,31 @@ pub enum Command {
         /// The key to check for existence
         keys: Vec<String>,
     },
+    /// Mark keys as just used without reading them (`TOUCH key [key ...]`)
+    Touch {
+        keys: Vec<String>,
+    },
+    /// Load keys from the cold tier into memory (`WARM key [key ...]`)
+    Warm {
+        keys: Vec<String>,
+    },
     /// Scan for keys matching a prefix
     Scan {
         /// The prefix to scan for
         prefix: String,
     },
+    /// Stop the SCAN reply being streamed (only meaningful while one is)
+    Cancel,
+    /// Find keys whose values start with a prefix (needs the value index)
+    FindByValue {
+        /// The value prefix to look up
+        prefix: String,
+    },
+    /// Digest of the pairs with `start <= key <= end` (`RANGEHASH <start> <end>`)
+    RangeHash {
+        start: String,
+        end: String,
+    },
     /// Hash a key (not implemented)
     Hash {
         /// The key to hash
@@ -159,11 +342,77 @@ pub enum Command {
         pairs: Vec<(String, String)>,
     },
 
+    /// Set one or more fields of a field-map value
+    HSet {
+        /// The key holding the map
+        key: String,
+        /// The field-value pairs to store
+        pairs: Vec<(String, String)>,
+    },
+
+    /// Write the current metrics to `metrics.dump_path`
+    MetricsDump {
+        /// None = `metrics.dump_format`
+        format: Option<MetricsFormat>,
+    },
+
+    /// Switch the connection to another logical database
+    Select {
+        /// Database number, below `server.databases`
+        index: usize,
+    },
+
+    /// Keys held by only one of two databases, or with different values
+    DbDiff {
+        first: usize,
+        second: usize,
+    },
+
+    /// Exchange the values of two keys atomically; an absent key swaps as absent
+    Swap {
+        key1: String,
+        key2: String,
+    },
+
+    /// Run primitive ops as one transaction (`EVAL <op>; <op>; ...`, see `script`)
+    Eval {
+        ops: Vec<ScriptOp>,
+    },
+
+    /// Get one field of a field-map value
+    HGet {
+        /// The key holding the map
+        key: String,
+        /// The field to read
+        field: String,
+    },
+
+    /// Get every field of a field-map value
+    HGetAll {
+        /// The key holding the map
+        key: String,
+    },
+
     Sync {
         host: String,    
         port: u16,
         options: SyncOptions,
     },
+    /// Report the sync round in flight (`SYNC STATUS`)
+    SyncStatus,
+    /// Cancel the sync round in flight (`SYNC ABORT`)
+    SyncAbort,
+    /// List configured and discovered peers with their status (`CLUSTER PEERS`)
+    ClusterPeers,
+    /// Compare the replicated keys with a peer's (`VERIFY CONSISTENT <host:port>`)
+    VerifyConsistent {
+        /// Peer `host:port`
+        peer: String,
+    },
+    /// Sleep before replying, to test `server.command_timeout_ms` (`DEBUG SLEEP <ms>`)
+    DebugSleep {
+        ms: u64,
+    },
     /// Clear all keys/values in the store
     Truncate,
     
@@ -173,11 +422,26 @@ pub enum Command {
     /// Return detailed server information (version, uptime, config)
     Info,
 
+    /// Server information, counters and replication state as one JSON object
+    InfoJson,
+
     /// Return the current keystore size
     Dbsize,
 
-    /// Return server version
+    /// Return a random existing key
+    RandomKey,
+
+    /// Seconds since start and the start time (`UPTIME`)
+    Uptime,
+
+    /// Return server version, build commit, protocol version and features
     Version,
+
+    /// Handshake, offering codecs to compress the rest of the connection
+    /// (`HELLO [COMPRESS <codec> ...]`)
+    Hello {
+        codecs: Vec<String>,
+    },
     
     /// Force replication of pending changes
     Flushdb,
@@ -188,15 +452,404 @@ pub enum Command {
     /// Get memory usage    
     Memory,
 
+    /// Shrink in-memory maps to fit after large deletes
+    MemoryCompact,
+
+    /// Live data size vs on-disk size (fragmentation) of a persistent engine
+    StorageStats,
+
+    /// Rewrite the on-disk files of a persistent engine to reclaim space
+    StorageCompact,
+
+    /// Key count and memory of each shard of a sharded engine
+    ShardStats,
+
+    /// Pause or resume appending writes to the write-ahead log
+    Persistence {
+        /// False snapshots the data and stops logging; true resumes
+        enabled: bool,
+    },
+
+    /// Largest values of a bounded sample (`BIGKEYS [n]`)
+    BigKeys {
+        /// Number of keys to list (None = server default)
+        count: Option<usize>,
+    },
+
+    /// Key length / value size histograms over a bounded sample
+    MemoryHistogram {
+        /// Number of pairs to sample (None = server default)
+        samples: Option<usize>,
+    },
+
     /// List connected clients
     Clientlist,
+
+    /// Opt this connection in or out of compressed bulk replies
+    ClientCompress {
+        enabled: bool,
+    },
+
+    /// Opt this connection in or out of `TOKEN` lines after write replies
+    ClientTokens {
+        enabled: bool,
+    },
+
+    /// Merkle tree maintenance (self-check / repair)
+    Merkle {
+        action: MerkleAction,
+    },
+
+    /// Switch the connection to push mode for a channel
+    Subscribe {
+        channel: SubscribeChannel,
+        /// Subscription id and last version received (`SUBSCRIBE MERKLE <id> <version>`)
+        resume: Option<(String, u64)>,
+    },
+
+    /// Leave push mode (only meaningful while subscribed)
+    Unsubscribe,
+
+    /// List live tombstones (used by anti-entropy sync)
+    Tombstones,
+
+    /// Consistent snapshot of every key, TTL and tombstone (used by `--bootstrap-from`)
+    Dump,
+
+    /// Every key as one JSON object per line (`EXPORT JSON`)
+    ExportJson,
+
+    /// Authenticate the connection (`server.password`)
+    Auth {
+        /// Everything after AUTH: `<password>` or `<user> <password>`
+        /// (told apart by `auth::authenticate`)
+        password: String,
+    },
+
+    /// Read the effective value of a config parameter
+    ConfigGet {
+        param: String,
+    },
+
+    /// Change a runtime-mutable config parameter
+    ConfigSet {
+        param: String,
+        value: String,
+    },
+
+    /// Re-read the TLS certificate, key and MQTT CA files (`CONFIG RELOAD TLS`)
+    ConfigReloadTls,
+
+    /// Set the log level of one module; None drops the override (`LOG LEVEL <module> <level|default>`)
+    LogLevel {
+        module: String,
+        level: Option<log::LevelFilter>,
+    },
+
+    /// List background tasks and how their last run went
+    Tasks,
+
+    /// Run a background task now
+    TaskRun {
+        name: String,
+    },
+
+    /// Report what a command would do, without running it
+    Explain {
+        command: Box<Command>,
+    },
+
+    /// Run a write and flush the engine to disk before replying (`SET ... DURABLE`)
+    Durable {
+        command: Box<Command>,
+    },
+
+    /// Run a write and wait for peer acks before replying (`SET ... CL=<level>`)
+    Consistent {
+        level: WriteConsistency,
+        command: Box<Command>,
+    },
+
+    /// Run a write on this node only: no change event, no Merkle leaf (`SET ... LOCAL`)
+    Local {
+        command: Box<Command>,
+    },
+}
+
+/// What running a command would do to the store, as reported by `EXPLAIN`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Plan {
+    /// Engine operations in order, as `read:<key>`, `write:<key>`,
+    /// `delete:<key>`, `scan:<prefix>`, ... (`*` for the whole keyspace)
+    pub ops: Vec<String>,
+    /// Whether the command publishes change events to replicas
+    pub replicates: bool,
+}
+
+impl Plan {
+    fn new(ops: impl IntoIterator<Item = String>, replicates: bool) -> Self {
+        Self { ops: ops.into_iter().collect(), replicates }
+    }
+}
+
+impl Command {
+    /// The engine operations this command performs, derived from the parsed
+    /// command alone. Conditional writes (e.g. a DELETE of a missing key) are
+    /// listed as if they happen.
+    pub fn plan(&self) -> Plan {
+        let op = |kind: &str, key: &str| format!("{}:{}", kind, key);
+        match self {
+            Command::Get { key } | Command::GetWithToken { key, .. } | Command::GetStream { key } | Command::GetWithCrc { key } | Command::GetWithTtl { key } | Command::Ttl { key } | Command::Object { key, .. } | Command::HGet { key, .. } | Command::HGetAll { key } => {
+                Plan::new([op("read", key)], false)
+            }
+            Command::MultiGet { keys } | Command::Exists { keys } | Command::Touch { keys } | Command::Warm { keys } => Plan::new(keys.iter().map(|k| op("read", k)), false),
+            Command::Scan { prefix } => Plan::new([op("scan", prefix)], false),
+            Command::RangeHash { start, end } => Plan::new([op("scan", &format!("{}..{}", start, end))], false),
+            Command::FindByValue { prefix } => Plan::new([op("index", prefix)], false),
+            Command::Set { key, .. } | Command::SetStream { key, .. } => Plan::new([op("write", key)], true),
+            Command::SetEx { key, .. } => Plan::new([op("write", key), op("expire", key)], true),
+            Command::Expire { key, .. } | Command::Persist { key } => Plan::new([op("expire", key)], true),
+            Command::MultiSet { pairs } => Plan::new(pairs.iter().map(|(k, _)| op("write", k)), true),
+            Command::Eval { ops } => Plan::new(
+                ops.iter().flat_map(|o| {
+                    let write = o.writes().then(|| op("write", o.key()));
+                    std::iter::once(op("read", o.key())).chain(write)
+                }),
+                ops.iter().any(ScriptOp::writes),
+            ),
+            Command::Delete { key } => Plan::new([op("delete", key)], true),
+            Command::Increment { key, .. } | Command::Decrement { key, .. } | Command::Append { key, .. } | Command::Prepend { key, .. } | Command::HSet { key, .. }
+            | Command::JsonIncr { key, .. } => {
+                Plan::new([op("read", key), op("write", key)], true)
+            }
+            Command::Swap { key1, key2 } => {
+                Plan::new([op("read", key1), op("read", key2), op("write", key1), op("write", key2)], true)
+            }
+            // Clears are local: replicas keep their data
+            Command::Truncate | Command::Flushdb => Plan::new([op("delete", "*")], false),
+            Command::Sync { .. } => Plan::new([op("scan", "*"), op("write", "*")], false),
+            Command::Dbsize | Command::Dump | Command::ExportJson | Command::Tombstones | Command::VerifyConsistent { .. } | Command::Memory | Command::MemoryHistogram { .. } | Command::BigKeys { .. } | Command::Hash { .. } | Command::Merkle { .. } | Command::ShardStats | Command::RandomKey
+            | Command::DbDiff { .. } => {
+                Plan::new([op("scan", "*")], false)
+            }
+            Command::MemoryCompact | Command::StorageCompact => Plan::new([op("compact", "*")], false),
+            Command::Explain { command } | Command::Durable { command } | Command::Consistent { command, .. } => command.plan(),
+            Command::Local { command } => Plan { replicates: false, ..command.plan() },
+            _ => Plan::new([], false),
+        }
+    }
+
+    /// Whether `server.command_timeout_ms` may abort this command: it only
+    /// reads the store and writes nothing to the client before its reply, so
+    /// stopping it at an await point leaves neither a partial mutation nor a
+    /// partial reply. SCAN checks the timeout between reply batches instead.
+    pub fn abortable(&self) -> bool {
+        matches!(
+            self,
+            Command::Get { .. } | Command::GetWithCrc { .. } | Command::GetWithTtl { .. } | Command::Ttl { .. } | Command::HGet { .. } | Command::HGetAll { .. }
+            | Command::MultiGet { .. } | Command::Exists { .. } | Command::RangeHash { .. } | Command::FindByValue { .. } | Command::Dbsize
+            | Command::Hash { .. } | Command::Memory | Command::MemoryHistogram { .. } | Command::BigKeys { .. } | Command::VerifyConsistent { .. }
+            | Command::ExportJson | Command::DebugSleep { .. } | Command::ClusterPeers | Command::ShardStats | Command::RandomKey | Command::DbDiff { .. }
+        )
+    }
+
+    /// The command word this was parsed from, uppercase (`DEL` for
+    /// `DEL`/`DELETE`, `CLIENT` for every `CLIENT ...` subcommand).
+    pub fn name(&self) -> &'static str {
+        match self {
+            Command::Replicate { .. } => "REPLICATE",
+            Command::Get { .. } | Command::GetWithToken { .. } | Command::GetStream { .. } | Command::GetWithCrc { .. } | Command::GetWithTtl { .. } => "GET",
+            Command::Set { .. } | Command::SetEx { .. } | Command::SetStream { .. } => "SET",
+            Command::Expire { .. } => "EXPIRE",
+            Command::Persist { .. } => "PERSIST",
+            Command::Ttl { .. } => "TTL",
+            Command::Object { .. } => "OBJECT",
+            Command::Delete { .. } => "DEL",
+            Command::Ping { .. } => "PING",
+            Command::Echo { .. } => "ECHO",
+            Command::Exists { .. } => "EXISTS",
+            Command::Touch { .. } => "TOUCH",
+            Command::Warm { .. } => "WARM",
+            Command::Scan { .. } => "SCAN",
+            Command::Cancel => "CANCEL",
+            Command::FindByValue { .. } => "FINDBYVALUE",
+            Command::Hash { .. } => "HASH",
+            Command::RangeHash { .. } => "RANGEHASH",
+            Command::Increment { .. } => "INC",
+            Command::Decrement { .. } => "DEC",
+            Command::Append { .. } => "APPEND",
+            Command::Prepend { .. } => "PREPEND",
+            Command::MultiGet { .. } => "MGET",
+            Command::MultiSet { .. } => "MSET",
+            Command::HSet { .. } => "HSET",
+            Command::JsonIncr { .. } => "JSON.INCR",
+            Command::Swap { .. } => "SWAP",
+            Command::Eval { .. } => "EVAL",
+            Command::Select { .. } => "SELECT",
+            Command::DbDiff { .. } => "DBDIFF",
+            Command::MetricsDump { .. } => "METRICS",
+            Command::HGet { .. } => "HGET",
+            Command::HGetAll { .. } => "HGETALL",
+            Command::Sync { .. } | Command::SyncStatus | Command::SyncAbort => "SYNC",
+            Command::ClusterPeers => "CLUSTER",
+            Command::Truncate => "TRUNCATE",
+            Command::Stats => "STATS",
+            Command::Info | Command::InfoJson => "INFO",
+            Command::Dbsize => "DBSIZE",
+            Command::RandomKey => "RANDOMKEY",
+            Command::Version => "VERSION",
+            Command::Hello { .. } => "HELLO",
+            Command::Uptime => "UPTIME",
+            Command::Flushdb => "FLUSHDB",
+            Command::Shutdown => "SHUTDOWN",
+            Command::Memory | Command::MemoryCompact | Command::MemoryHistogram { .. } => "MEMORY",
+            Command::BigKeys { .. } => "BIGKEYS",
+            Command::StorageStats | Command::StorageCompact => "STORAGE",
+            Command::ShardStats => "SHARD",
+            Command::Persistence { .. } => "PERSISTENCE",
+            Command::Clientlist | Command::ClientCompress { .. } | Command::ClientTokens { .. } => "CLIENT",
+            Command::Merkle { .. } => "MERKLE",
+            Command::Subscribe { .. } => "SUBSCRIBE",
+            Command::Unsubscribe => "UNSUBSCRIBE",
+            Command::Tombstones => "TOMBSTONES",
+            Command::Dump => "DUMP",
+            Command::ExportJson => "EXPORT",
+            Command::VerifyConsistent { .. } => "VERIFY",
+            Command::DebugSleep { .. } => "DEBUG",
+            Command::Auth { .. } => "AUTH",
+            Command::ConfigGet { .. } | Command::ConfigSet { .. } | Command::ConfigReloadTls => "CONFIG",
+            Command::LogLevel { .. } => "LOG",
+            Command::Tasks | Command::TaskRun { .. } => "TASKS",
+            Command::Explain { .. } => "EXPLAIN",
+            Command::Durable { command } | Command::Consistent { command, .. } | Command::Local { command } => command.name(),
+        }
+    }
+}
+
+/// A command that failed to parse, pointing at where parsing stopped.
+///
+/// Tokens are the whitespace-separated words of the (trimmed) command line,
+/// counted from 1 with the command name as token 1. When something required is
+/// missing, `token` is one past the last token and `byte` the input length.
+/// Sent to clients as `ERROR ERR_PARSE <message> at token <n> (byte <b>)`.
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+#[error("ERR_PARSE {message} at token {token} (byte {byte})")]
+pub struct ParseError {
+    pub message: String,
+    pub token: usize,
+    pub byte: usize,
+}
+
+impl ParseError {
+    /// Error at the 1-based `token` of `input` (or just past the end).
+    fn at(input: &str, token: usize, message: impl Into<String>) -> Self {
+        let byte = token_offsets(input).get(token.saturating_sub(1)).copied().unwrap_or(input.len());
+        Self { message: message.into(), token, byte }
+    }
+
+    /// Error at the last token of `input`.
+    fn at_last(input: &str, message: impl Into<String>) -> Self {
+        Self::at(input, token_offsets(input).len(), message)
+    }
+
+    /// A required argument is missing after the last token.
+    fn missing(input: &str, message: impl Into<String>) -> Self {
+        Self::at(input, token_offsets(input).len() + 1, message)
+    }
+
+    /// Wrong argument count: `expected` tokens (command included) were needed.
+    fn arity(input: &str, expected: usize, message: impl Into<String>) -> Self {
+        if token_offsets(input).len() < expected {
+            Self::missing(input, message)
+        } else {
+            Self::at(input, expected + 1, message)
+        }
+    }
+
+    /// Error at the last token equal to `value`.
+    fn at_value(input: &str, value: &str, message: impl Into<String>) -> Self {
+        let offsets = token_offsets(input);
+        let token = offsets
+            .iter()
+            .rposition(|&start| input[start..].split_whitespace().next() == Some(value))
+            .map_or(offsets.len(), |idx| idx + 1);
+        Self::at(input, token, message)
+    }
+
+    /// Error at the first tab or newline in `input`.
+    fn control_char(input: &str, message: impl Into<String>) -> Self {
+        let byte = input.find(['\t', '\n']).unwrap_or(input.len());
+        let token = token_offsets(input).iter().filter(|&&start| start <= byte).count() + 1;
+        Self { message: message.into(), token, byte }
+    }
+}
+
+/// Byte offset where the 0-based `n`th token of `input` starts, scanning
+/// no further than it.
+fn nth_token_offset(input: &str, n: usize) -> Option<usize> {
+    let mut seen = 0;
+    let mut in_token = false;
+    for (idx, c) in input.char_indices() {
+        if c.is_whitespace() {
+            in_token = false;
+        } else if !in_token {
+            if seen == n {
+                return Some(idx);
+            }
+            seen += 1;
+            in_token = true;
+        }
+    }
+    None
+}
+
+/// Byte offsets where each whitespace-separated token of `input` starts.
+fn token_offsets(input: &str) -> Vec<usize> {
+    let mut offsets = Vec::new();
+    let mut in_token = false;
+    for (idx, c) in input.char_indices() {
+        if c.is_whitespace() {
+            in_token = false;
+        } else if !in_token {
+            offsets.push(idx);
+            in_token = true;
+        }
+    }
+    offsets
+}
+
+/// Alternative command names, as (alias, command). Command names are matched
+/// case-insensitively, aliases included.
+pub const COMMAND_ALIASES: &[(&str, &str)] = &[
+    ("DELETE", "DEL"),
+    ("RM", "DEL"),
+    ("INCR", "INC"),
+    ("DECR", "DEC"),
+    ("FLUSHALL", "FLUSHDB"),
+];
+
+/// Upper-case name of the command `word` stands for, resolving aliases.
+pub fn canonical_command(word: &str) -> String {
+    let upper = word.to_ascii_uppercase();
+    match COMMAND_ALIASES.iter().find(|(alias, _)| *alias == upper) {
+        Some((_, command)) => command.to_string(),
+        None => upper,
+    }
 }
 
 /// Protocol parser that converts text commands into structured Command enums.
 ///
 /// This parser is stateless and can be safely shared across threads.
-
-pub struct Protocol;
+///
+/// Lines may end in `\n` or `\r\n`; the last line before EOF may have no
+/// terminator at all. With `strict_crlf` only `\r\n` is accepted.
+///
+/// A command with more than `max_args` words after its name is refused
+/// while counting them, before any is collected.
+pub struct Protocol {
+    strict_crlf: bool,
+    max_args: usize,
+}
 
 impl Protocol {
     /// Create a new protocol parser instance.
@@ -204,7 +857,31 @@ impl Protocol {
     /// # Returns
     /// * `Protocol` - A new parser instance
     pub fn new() -> Self {
-        Self
+        Self { strict_crlf: false, max_args: usize::MAX }
+    }
+
+    /// Reject lines not terminated by `\r\n` (`server.strict_crlf`).
+    pub fn with_strict_crlf(mut self, strict: bool) -> Self {
+        self.strict_crlf = strict;
+        self
+    }
+
+    /// Refuse commands with more than `max` arguments (`server.max_args`).
+    pub fn with_max_args(mut self, max: usize) -> Self {
+        self.max_args = max;
+        self
+    }
+
+    /// Parse one line as read from a connection, terminator included.
+    ///
+    /// # Errors
+    /// As `parse`, plus a `ParseError` for a line without `\r\n` in strict mode.
+    pub fn parse_line(&self, line: &str) -> Result<Command> {
+        if self.strict_crlf && !line.ends_with("\r\n") {
+            let line = line.trim_end_matches(['\r', '\n']);
+            return Err(ParseError::at(line, token_offsets(line).len().max(1), "line must end with \\r\\n (server.strict_crlf)").into());
+        }
+        self.parse(line)
     }
 
     /// Parse a text command into a structured Command enum.
@@ -225,6 +902,8 @@ impl Protocol {
     /// - Required arguments are missing
     /// - Too many arguments are provided
     ///
+    /// Errors are `ParseError`s naming the token where parsing failed.
+    ///
     /// # Example
     /// ```rust
     /// let protocol = Protocol::new();
@@ -239,7 +918,13 @@ impl Protocol {
         
         // Check for empty input
         if input.is_empty() {
-            return Err(anyhow!("Empty command"));
+            return Err(ParseError::at(input, 1, "Empty command").into());
+        }
+        // Count before splitting: a huge argument list is refused at the
+        // first word past the limit, without collecting the rest
+        if let Some(byte) = nth_token_offset(input, self.max_args.saturating_add(1)) {
+            let message = format!("too many arguments (server.max_args is {})", self.max_args);
+            return Err(ParseError { message, token: self.max_args.saturating_add(2), byte }.into());
         }
         
         // Split command into parts - for SET we need to split into exactly 3 parts
@@ -249,29 +934,37 @@ impl Protocol {
         if first_space.is_none() {
             // Single word command - check for invalid characters in command only
             if input.contains('\t') {
-                return Err(anyhow!("Invalid character: tab character not allowed in command"));
+                return Err(ParseError::control_char(input, "Invalid character: tab character not allowed in command").into());
             }
             if input.contains('\n') {
-                return Err(anyhow!("Invalid character: newline character not allowed in command"));
+                return Err(ParseError::control_char(input, "Invalid character: newline character not allowed in command").into());
             }
             
-            match input.to_uppercase().as_str() {
-                "GET" | "SET" | "DELETE" | "DEL" | "ECHO" | "EXISTS" | "SYNC" | "REPLICATE" => {
-                    return Err(anyhow!("{} command requires arguments", input.to_uppercase()));
+            match canonical_command(input).as_str() {
+                "GET" | "SET" | "DEL" | "ECHO" | "EXISTS" | "SYNC" | "REPLICATE" | "REPLICATION" | "CLIENT" | "MERKLE" | "HSET" | "HGET" | "HGETALL" | "SUBSCRIBE" | "CONFIG" | "AUTH" | "STORAGE" | "EXPIRE" | "PERSIST" | "TTL" | "OBJECT" | "FINDBYVALUE" | "EXPLAIN" | "SWAP" | "PERSISTENCE" | "SELECT" | "METRICS" | "JSON.INCR" | "RANGEHASH" | "TOUCH" | "WARM" | "DEBUG" | "EXPORT" | "CLUSTER" | "EVAL" | "SHARD" | "DBDIFF" => {
+                    return Err(ParseError::missing(input, format!("{} command requires arguments", input.to_uppercase())).into());
                 }
                 "TRUNCATE" => return Ok(Command::Truncate),
                 "STATS" => return Ok(Command::Stats),
                 "INFO" => return Ok(Command::Info),
                 "VERSION" => return Ok(Command::Version),
+                "HELLO" => return Ok(Command::Hello { codecs: Vec::new() }),
+                "UPTIME" => return Ok(Command::Uptime),
                 "FLUSHDB" => return Ok(Command::Flushdb),
                 "MEMORY" => return Ok(Command::Memory),
+                "BIGKEYS" => return Ok(Command::BigKeys { count: None }),
                 "SCAN" => return Ok(Command::Scan { prefix: String::new() }),
                 "HASH" => return Ok(Command::Hash { pattern: None }),
-                "CLIENT" => return Ok(Command::Clientlist),
                 "PING" => return Ok(Command::Ping { message: String::new() }),
                 "SHUTDOWN" => return Ok(Command::Shutdown),
+                "UNSUBSCRIBE" => return Ok(Command::Unsubscribe),
+                "TOMBSTONES" => return Ok(Command::Tombstones),
+                "DUMP" => return Ok(Command::Dump),
                 "DBSIZE" => return Ok(Command::Dbsize),
-                _ => return Err(anyhow!("Unknown command: {}", input)),
+                "RANDOMKEY" => return Ok(Command::RandomKey),
+                "TASKS" => return Ok(Command::Tasks),
+                "CANCEL" => return Ok(Command::Cancel),
+                _ => return Err(ParseError::at(input, 1, format!("Unknown command: {}", input)).into()),
             }
         }
 
@@ -280,27 +973,45 @@ impl Protocol {
 
         // Check for invalid characters in command only
         if command.contains('\t') {
-            return Err(anyhow!("Invalid character: tab character not allowed in command"));
+            return Err(ParseError::control_char(input, "Invalid character: tab character not allowed in command").into());
         }
         if command.contains('\n') {
-            return Err(anyhow!("Invalid character: newline character not allowed in command"));
+            return Err(ParseError::control_char(input, "Invalid character: newline character not allowed in command").into());
         }
 
-        // Parse command based on the first word (case-insensitive)
-        match command.to_uppercase().as_str() {
+        // Parse command based on the first word (case-insensitive, aliases resolved)
+        match canonical_command(command).as_str() {
             "GET" => {
                 if rest.is_empty() {
-                    return Err(anyhow!("GET command requires a key"));
+                    return Err(ParseError::missing(input, "GET command requires a key").into());
+                }
+                let args: Vec<&str> = rest.split(' ').collect();
+                if let [key, option, token] = args[..] {
+                    if option.eq_ignore_ascii_case("WITHTOKEN") && !key.is_empty() {
+                        let token = Token::parse(token).map_err(|e| ParseError::at(input, 4, e.to_string()))?;
+                        return Ok(Command::GetWithToken { key: key.to_string(), token });
+                    }
+                }
+                if let [key, option] = args[..] {
+                    if option.eq_ignore_ascii_case("STREAM") && !key.is_empty() {
+                        return Ok(Command::GetStream { key: key.to_string() });
+                    }
+                    if option.eq_ignore_ascii_case("WITHCRC") && !key.is_empty() {
+                        return Ok(Command::GetWithCrc { key: key.to_string() });
+                    }
+                    if option.eq_ignore_ascii_case("WITHTTL") && !key.is_empty() {
+                        return Ok(Command::GetWithTtl { key: key.to_string() });
+                    }
                 }
                 if rest.contains(' ') {
-                    return Err(anyhow!("GET command accepts only one argument"));
+                    return Err(ParseError::at(input, 3, "GET command accepts only one argument").into());
                 }
                 // Check for invalid characters in key
                 if rest.contains('\t') {
-                    return Err(anyhow!("Invalid character: tab character not allowed in key"));
+                    return Err(ParseError::control_char(input, "Invalid character: tab character not allowed in key").into());
                 }
                 if rest.contains('\n') {
-                    return Err(anyhow!("Invalid character: newline character not allowed in key"));
+                    return Err(ParseError::control_char(input, "Invalid character: newline character not allowed in key").into());
                 }
                 Ok(Command::Get {
                     key: rest.to_string(),
@@ -309,65 +1020,189 @@ impl Protocol {
             "SET" => {
                 let second_space = rest.find(' ');
                 if second_space.is_none() {
-                    return Err(anyhow!("SET command requires a key and value"));
+                    return Err(ParseError::missing(input, "SET command requires a key and value").into());
                 }
                 let key = &rest[..second_space.unwrap()];
                 let value = &rest[second_space.unwrap() + 1..];
                 
                 if key.is_empty() {
-                    return Err(anyhow!("SET command key cannot be empty"));
+                    return Err(ParseError::at(input, 2, "SET command key cannot be empty").into());
+                }
+
+                // Trailing option words are matched in uppercase only, so values
+                // in any other case are stored whole, as before options existed.
+                // A trailing "DURABLE" flushes the engine before the reply; the
+                // rest (plain, EX/PX or STREAM) parses as usual.
+                if let Some((plain, flag)) = value.rsplit_once(' ') {
+                    if flag == "DURABLE" && !plain.is_empty() {
+                        let command = self.parse_before_option(input, flag)?;
+                        return Ok(Command::Durable { command: Box::new(command) });
+                    }
+                    // A trailing "LOCAL" keeps the write on this node
+                    if flag == "LOCAL" && !plain.is_empty() {
+                        let command = self.parse_before_option(input, flag)?;
+                        return Ok(Command::Local { command: Box::new(command) });
+                    }
+                    // Likewise a trailing "CL=<one|quorum|all>" sets the write consistency
+                    if let Some(level) = flag.strip_prefix("CL=").filter(|l| !l.is_empty() && !plain.is_empty()) {
+                        let level = match level.to_ascii_uppercase().as_str() {
+                            "ONE" => WriteConsistency::One,
+                            "QUORUM" => WriteConsistency::Quorum,
+                            "ALL" => WriteConsistency::All,
+                            _ => return Err(ParseError::at_last(input, "SET consistency level must be one, quorum or all").into()),
+                        };
+                        let command = self.parse_before_option(input, flag)?;
+                        return Ok(Command::Consistent { level, command: Box::new(command) });
+                    }
                 }
                 
                 // Check for invalid characters in key only (tabs allowed in values; newlines reserved for CRLF framing)
                 if key.contains('\t') {
-                    return Err(anyhow!("Invalid character: tab character not allowed in key"));
+                    return Err(ParseError::control_char(input, "Invalid character: tab character not allowed in key").into());
                 }
                 if key.contains('\n') {
-                    return Err(anyhow!("Invalid character: newline character not allowed in key"));
+                    return Err(ParseError::control_char(input, "Invalid character: newline character not allowed in key").into());
                 }
                 
                 // Newlines are forbidden in values due to CRLF protocol framing
                 if value.contains('\n') {
-                    return Err(anyhow!("Invalid character: newline character not allowed in value"));
+                    return Err(ParseError::control_char(input, "Invalid character: newline character not allowed in value").into());
                 }
                 
+                // "STREAM <len>" as the whole value announces a chunked upload
+                if let Some((word, len)) = value.split_once(' ') {
+                    if word == "STREAM" {
+                        if let Ok(len) = len.parse::<usize>() {
+                            return Ok(Command::SetStream { key: key.to_string(), len });
+                        }
+                    }
+                }
+
+                // Trailing "EX <seconds>" / "PX <millis>" sets a TTL. Anything
+                // else (lowercase, or a malformed amount) stays part of the value.
+                let mut tail = value.rsplitn(3, ' ');
+                if let (Some(amount), Some(unit), Some(plain)) = (tail.next(), tail.next(), tail.next()) {
+                    let scale = match unit {
+                        "EX" => Some(1000),
+                        "PX" => Some(1),
+                        _ => None,
+                    };
+                    if let (Some(scale), Ok(amount)) = (scale, amount.parse::<u64>()) {
+                        if plain.contains(' ') {
+                            return Err(ambiguous_option(input, unit));
+                        }
+                        if amount == 0 {
+                            return Err(ParseError::at_last(input, "SET expire time must be positive").into());
+                        }
+                        return Ok(Command::SetEx {
+                            key: key.to_string(),
+                            value: plain.to_string(),
+                            ttl_ms: amount.saturating_mul(scale),
+                        });
+                    }
+                }
+
                 Ok(Command::Set {
                     key: key.to_string(),
                     value: value.to_string(),
                 })
             }
-            // Support both "DEL" and "DELETE" for convenience
-            "DEL" | "DELETE" => {
+            // Also spelled DELETE and RM (see COMMAND_ALIASES)
+            "DEL" => {
                 if rest.is_empty() {
-                    return Err(anyhow!("DELETE command requires a key"));
+                    return Err(ParseError::missing(input, "DELETE command requires a key").into());
                 }
                 if rest.contains(' ') {
-                    return Err(anyhow!("DELETE command accepts only one argument"));
+                    return Err(ParseError::at(input, 3, "DELETE command accepts only one argument").into());
                 }
                 // Check for invalid characters in key
                 if rest.contains('\t') {
-                    return Err(anyhow!("Invalid character: tab character not allowed in key"));
+                    return Err(ParseError::control_char(input, "Invalid character: tab character not allowed in key").into());
                 }
                 if rest.contains('\n') {
-                    return Err(anyhow!("Invalid character: newline character not allowed in key"));
+                    return Err(ParseError::control_char(input, "Invalid character: newline character not allowed in key").into());
                 }
                 Ok(Command::Delete {
                     key: rest.to_string(),
                 })
             }
+            "EXPIRE" => {
+                let parts: Vec<&str> = rest.split_whitespace().collect();
+                let (key, seconds, flag) = match parts[..] {
+                    [key, seconds] => (key, seconds, None),
+                    [key, seconds, flag] => (key, seconds, Some(flag)),
+                    _ => return Err(ParseError::arity(input, 3, "Usage: EXPIRE key seconds [NX|XX|GT|LT]").into()),
+                };
+                let seconds = seconds
+                    .parse::<u64>()
+                    .map_err(|_| ParseError::at(input, 3, "EXPIRE seconds must be a non-negative integer"))?;
+                let condition = match flag.map(|f| f.to_ascii_uppercase()).as_deref() {
+                    None => None,
+                    Some("NX") => Some(ExpireCondition::Nx),
+                    Some("XX") => Some(ExpireCondition::Xx),
+                    Some("GT") => Some(ExpireCondition::Gt),
+                    Some("LT") => Some(ExpireCondition::Lt),
+                    Some(_) => return Err(ParseError::at(input, 4, "EXPIRE flag must be NX, XX, GT or LT").into()),
+                };
+                Ok(Command::Expire { key: key.to_string(), seconds, condition })
+            }
+            "PERSIST" | "TTL" => {
+                let name = command.to_uppercase();
+                if rest.split_whitespace().count() != 1 {
+                    return Err(ParseError::at(input, 3, format!("{} command accepts only one argument", name)).into());
+                }
+                let key = rest.trim().to_string();
+                Ok(if name == "TTL" { Command::Ttl { key } } else { Command::Persist { key } })
+            }
+            "OBJECT" => {
+                let args: Vec<&str> = rest.split_whitespace().collect();
+                let usage = "Usage: OBJECT CREATED|MODIFIED|ACCESSED|IDLETIME|REPLINFO|ACKS|DURABLE <key>";
+                let field = match args.first().map(|s| s.to_ascii_uppercase()).as_deref() {
+                    Some("CREATED") => ObjectField::Created,
+                    Some("MODIFIED") => ObjectField::Modified,
+                    Some("ACCESSED") => ObjectField::Accessed,
+                    Some("IDLETIME") => ObjectField::IdleTime,
+                    Some("REPLINFO") => ObjectField::ReplInfo,
+                    Some("ACKS") => ObjectField::Acks,
+                    Some("DURABLE") => ObjectField::Durable,
+                    _ => return Err(ParseError::at(input, 2, usage).into()),
+                };
+                match args[1..] {
+                    [key] => Ok(Command::Object { field, key: key.to_string() }),
+                    _ => Err(ParseError::arity(input, 3, usage).into()),
+                }
+            }
             "DBSIZE" => {
                 if !rest.is_empty() {
-                    return Err(anyhow!("DBSIZE command does not accept any arguments"));
+                    return Err(ParseError::at(input, 2, "DBSIZE command does not accept any arguments").into());
                 }
                 Ok(Command::Dbsize)
             }
+            "HELLO" => {
+                let args: Vec<&str> = rest.split_whitespace().collect();
+                match args.split_first() {
+                    Some((option, codecs)) if option.eq_ignore_ascii_case("COMPRESS") && !codecs.is_empty() => {
+                        Ok(Command::Hello { codecs: codecs.iter().map(|c| c.to_ascii_lowercase()).collect() })
+                    }
+                    Some((option, _)) if option.eq_ignore_ascii_case("COMPRESS") => {
+                        Err(ParseError::missing(input, "HELLO COMPRESS requires at least one codec").into())
+                    }
+                    _ => Err(ParseError::at(input, 2, "Usage: HELLO [COMPRESS <codec> ...]").into()),
+                }
+            }
+            "RANDOMKEY" => {
+                if !rest.is_empty() {
+                    return Err(ParseError::at(input, 2, "RANDOMKEY command does not accept any arguments").into());
+                }
+                Ok(Command::RandomKey)
+            }
             "PING" => {
                 // Allow optional message after PING
                 if rest.contains('\t') {
-                    return Err(anyhow!("Invalid character: tab character not allowed in message"));
+                    return Err(ParseError::control_char(input, "Invalid character: tab character not allowed in message").into());
                 }
                 if rest.contains('\n') {
-                    return Err(anyhow!("Invalid character: newline character not allowed in message"));
+                    return Err(ParseError::control_char(input, "Invalid character: newline character not allowed in message").into());
                 }
                 Ok(Command::Ping {
                     message: rest.to_string(),
@@ -376,21 +1211,35 @@ impl Protocol {
             "ECHO" => {
                 // Require a message after ECHO
                 if rest.is_empty() {
-                    return Err(anyhow!("ECHO command requires a message"));
+                    return Err(ParseError::missing(input, "ECHO command requires a message").into());
                 }
                 if rest.contains('\t') {
-                    return Err(anyhow!("Invalid character: tab character not allowed in message"));
+                    return Err(ParseError::control_char(input, "Invalid character: tab character not allowed in message").into());
                 }
                 if rest.contains('\n') {
-                    return Err(anyhow!("Invalid character: newline character not allowed in message"));
+                    return Err(ParseError::control_char(input, "Invalid character: newline character not allowed in message").into());
                 }
                 Ok(Command::Echo {
                     message: rest.to_string(),
                 })
             }
+            "TOUCH" => {
+                let keys: Vec<String> = rest.split_whitespace().map(|s| s.to_string()).collect();
+                if keys.is_empty() {
+                    return Err(ParseError::missing(input, "TOUCH command requires at least one key").into());
+                }
+                Ok(Command::Touch { keys })
+            }
+            "WARM" => {
+                let keys: Vec<String> = rest.split_whitespace().map(|s| s.to_string()).collect();
+                if keys.is_empty() {
+                    return Err(ParseError::missing(input, "WARM command requires at least one key").into());
+                }
+                Ok(Command::Warm { keys })
+            }
             "EXISTS" => {
                 if rest.is_empty() {
-                    return Err(anyhow!("EXISTS command requires at least one key"));
+                    return Err(ParseError::missing(input, "EXISTS command requires at least one key").into());
                 }
                 
                 // Extract all keys
@@ -399,16 +1248,16 @@ impl Protocol {
                     .collect();
 
                 if keys.is_empty() {
-                    return Err(anyhow!("EXISTS command requires at least one key"));
+                    return Err(ParseError::missing(input, "EXISTS command requires at least one key").into());
                 }
 
                 // Check for invalid characters in all keys
                 for key in &keys {
                     if key.contains('\t') {
-                        return Err(anyhow!("Invalid character: tab character not allowed in key"));
+                        return Err(ParseError::control_char(input, "Invalid character: tab character not allowed in key").into());
                     }
                     if key.contains('\n') {
-                        return Err(anyhow!("Invalid character: newline character not allowed in key"));
+                        return Err(ParseError::control_char(input, "Invalid character: newline character not allowed in key").into());
                     }
                 }
 
@@ -422,7 +1271,12 @@ impl Protocol {
                 //   SYNC [::1] 7878 --verify
 
                 if rest.is_empty() {
-                    return Err(anyhow!("SYNC requires arguments: <host> <port> [--full] [--verify]"));
+                    return Err(ParseError::missing(input, "SYNC requires arguments: <host> <port> [--full] [--verify]").into());
+                }
+                match rest.trim().to_ascii_uppercase().as_str() {
+                    "STATUS" => return Ok(Command::SyncStatus),
+                    "ABORT" => return Ok(Command::SyncAbort),
+                    _ => {}
                 }
 
                 // Split by ASCII whitespace
@@ -431,12 +1285,12 @@ impl Protocol {
                 // --- host ---
                 let host = it
                     .next()
-                    .ok_or_else(|| anyhow!("SYNC requires <host> as the first argument"))?
+                    .ok_or_else(|| ParseError::missing(input, "SYNC requires <host> as the first argument"))?
                     .to_string();
 
                 // Basic protocol hygiene: forbid TAB/NEWLINE inside host token
                 if host.contains('\t') || host.contains('\n') {
-                    return Err(anyhow!("Invalid character in host: tabs/newlines are not allowed"));
+                    return Err(ParseError::control_char(input, "Invalid character in host: tabs/newlines are not allowed").into());
                 }
                 // (Optional hardening: uncomment to strictly validate DNS hostname or IPv6 literal)
                 // if !(is_valid_hostname(&host) || is_ipv6_literal(&host)) {
@@ -446,16 +1300,16 @@ impl Protocol {
                 // --- port ---
                 let port_str = it
                     .next()
-                    .ok_or_else(|| anyhow!("SYNC requires <port> as the second argument"))?;
+                    .ok_or_else(|| ParseError::missing(input, "SYNC requires <port> as the second argument"))?;
                 if port_str.contains('\t') || port_str.contains('\n') {
-                    return Err(anyhow!("Invalid character in port: tabs/newlines are not allowed"));
+                    return Err(ParseError::control_char(input, "Invalid character in port: tabs/newlines are not allowed").into());
                 }
 
                 // TCP/UDP ports are 16-bit unsigned integers: 0..=65535 (IANA) 
                 // We parse to u16 to enforce the range.
                 let port: u16 = port_str
                     .parse()
-                    .map_err(|_| anyhow!("Invalid port: must be an integer in 0..=65535"))?;
+                    .map_err(|_| ParseError::at(input, 3, "Invalid port: must be an integer in 0..=65535"))?;
 
                 // --- options ---
                 // Supported flags: --full, --verify (each at most once)
@@ -464,24 +1318,24 @@ impl Protocol {
 
                 for tok in it {
                     if tok.contains('\t') || tok.contains('\n') {
-                        return Err(anyhow!("Invalid character in option: tabs/newlines are not allowed"));
+                        return Err(ParseError::control_char(input, "Invalid character in option: tabs/newlines are not allowed").into());
                     }
                     match tok {
                         "--full" => {
                             if opt_full {
-                                return Err(anyhow!("Duplicate option: --full"));
+                                return Err(ParseError::at_value(input, "--full", "Duplicate option: --full").into());
                             }
                             opt_full = true;
                         }
                         "--verify" => {
                             if opt_verify {
-                                return Err(anyhow!("Duplicate option: --verify"));
+                                return Err(ParseError::at_value(input, "--verify", "Duplicate option: --verify").into());
                             }
                             opt_verify = true;
                         }
                         _ => {
                             // Unknown flag → hard error to surface typos early
-                            return Err(anyhow!(format!("Unknown option: {}", tok)));
+                            return Err(ParseError::at_value(input, tok, format!("Unknown option: {}", tok)).into());
                         }
                     }
                 }
@@ -498,16 +1352,38 @@ impl Protocol {
                     },
                 })
             }
+            "DEBUG" => {
+                let args: Vec<&str> = rest.split_whitespace().collect();
+                match args[..] {
+                    [sub, ms] if sub.eq_ignore_ascii_case("SLEEP") => {
+                        let ms = ms.parse::<u64>().map_err(|_| ParseError::at(input, 3, "DEBUG SLEEP milliseconds must be a non-negative integer"))?;
+                        Ok(Command::DebugSleep { ms })
+                    }
+                    [sub, ..] if sub.eq_ignore_ascii_case("SLEEP") => Err(ParseError::arity(input, 3, "Usage: DEBUG SLEEP <ms>").into()),
+                    [] => Err(ParseError::missing(input, "DEBUG requires a subcommand: SLEEP <ms>").into()),
+                    [sub, ..] => Err(ParseError::at(input, 2, format!("Unknown DEBUG subcommand: {} (expected SLEEP)", sub)).into()),
+                }
+            }
+            "RANGEHASH" => {
+                let args: Vec<&str> = rest.split_whitespace().collect();
+                if args.len() != 2 {
+                    return Err(ParseError::arity(input, 3, "Usage: RANGEHASH <start_key> <end_key>").into());
+                }
+                if args[0] > args[1] {
+                    return Err(ParseError::at(input, 3, "RANGEHASH end_key must not sort before start_key").into());
+                }
+                Ok(Command::RangeHash { start: args[0].to_string(), end: args[1].to_string() })
+            }
             "HASH" => {
                 if rest.contains(' ') {
-                    return Err(anyhow!("HASH command accepts only one argument"));
+                    return Err(ParseError::at(input, 3, "HASH command accepts only one argument").into());
                 }
                 // Check for invalid characters in key
                 if rest.contains('\t') {
-                    return Err(anyhow!("Invalid character: tab character not allowed in key"));
+                    return Err(ParseError::control_char(input, "Invalid character: tab character not allowed in key").into());
                 }
                 if rest.contains('\n') {
-                    return Err(anyhow!("Invalid character: newline character not allowed in key"));
+                    return Err(ParseError::control_char(input, "Invalid character: newline character not allowed in key").into());
                 }
                 Ok(Command::Hash {
                     pattern: Some(rest.to_string()),
@@ -516,48 +1392,373 @@ impl Protocol {
             "REPLICATE" => {
                 let arg = rest.trim();
                 if arg.is_empty() {
-                    return Err(anyhow::anyhow!("REPLICATE requires one of: enable|disable|status"));
+                    return Err(ParseError::missing(input, "REPLICATE requires one of: enable|disable|status|pause|resume").into());
                 }
                 let action = match arg.to_ascii_lowercase().as_str() {
                     "enable"  => ReplicateAction::Enable,
                     "disable" => ReplicateAction::Disable,
                     "status"  => ReplicateAction::Status,
-                    _ => return Err(anyhow::anyhow!("Unknown REPLICATE action: {}", arg)),
+                    "pause"   => ReplicateAction::Pause,
+                    "resume"  => ReplicateAction::Resume,
+                    _ => return Err(ParseError::at(input, 2, format!("Unknown REPLICATE action: {}", arg)).into()),
                 };
                 Ok(Command::Replicate { action })
             }
-            "MEMORY" => {
-                if !rest.is_empty() {
-                    return Err(anyhow!("MEMORY command does not accept any arguments"));
+            // Maintenance spelling: REPLICATION PAUSE | RESUME | DEDUP STATS | DEDUP CLEAR | CHECK CAUSALITY
+            "REPLICATION" => {
+                let words: Vec<String> = rest.split_whitespace().map(str::to_ascii_uppercase).collect();
+                let action = match words.iter().map(String::as_str).collect::<Vec<_>>()[..] {
+                    ["PAUSE"] => ReplicateAction::Pause,
+                    ["RESUME"] => ReplicateAction::Resume,
+                    ["DEDUP", "STATS"] => ReplicateAction::DedupStats,
+                    ["DEDUP", "CLEAR"] => ReplicateAction::DedupClear,
+                    ["CHECK", "CAUSALITY"] => ReplicateAction::CheckCausality,
+                    _ => {
+                        return Err(ParseError::at(
+                            input,
+                            2,
+                            "Usage: REPLICATION PAUSE | REPLICATION RESUME | REPLICATION DEDUP STATS|CLEAR | REPLICATION CHECK CAUSALITY",
+                        )
+                        .into())
+                    }
+                };
+                Ok(Command::Replicate { action })
+            }
+            "HSET" => {
+                let args: Vec<&str> = rest.split_whitespace().collect();
+                if args.len() < 3 || !(args.len() - 1).is_multiple_of(2) {
+                    return Err(ParseError::missing(input, "HSET command requires a key and field-value pairs").into());
                 }
-                Ok(Command::Memory)
+                let pairs = args[1..]
+                    .chunks(2)
+                    .map(|fv| (fv[0].to_string(), fv[1].to_string()))
+                    .collect();
+                Ok(Command::HSet { key: args[0].to_string(), pairs })
             }
-            "CLIENT" => {
-                let mut it = rest.split_whitespace();
+            "JSON.INCR" => {
+                let args: Vec<&str> = rest.split_whitespace().collect();
+                if args.len() != 3 {
+                    return Err(ParseError::arity(input, 4, "Usage: JSON.INCR <key> <path> <amount>").into());
+                }
+                let amount = args[2]
+                    .parse::<serde_json::Number>()
+                    .map_err(|_| ParseError::at(input, 4, "JSON.INCR amount must be a number"))?;
+                Ok(Command::JsonIncr { key: args[0].to_string(), path: args[1].to_string(), amount })
+            }
+            "BIGKEYS" => {
+                let args: Vec<&str> = rest.split_whitespace().collect();
+                if args.len() != 1 {
+                    return Err(ParseError::arity(input, 2, "Usage: BIGKEYS [count]").into());
+                }
+                let count = args[0]
+                    .parse::<usize>()
+                    .ok()
+                    .filter(|&n| n > 0)
+                    .ok_or_else(|| ParseError::at(input, 2, "BIGKEYS count must be a positive integer"))?;
+                Ok(Command::BigKeys { count: Some(count) })
+            }
+            "SWAP" => {
+                let args: Vec<&str> = rest.split_whitespace().collect();
+                if args.len() != 2 {
+                    return Err(ParseError::arity(input, 3, "SWAP command requires exactly two keys").into());
+                }
+                Ok(Command::Swap {
+                    key1: args[0].to_string(),
+                    key2: args[1].to_string(),
+                })
+            }
+            "EVAL" => {
+                let ops = script::parse(rest).map_err(|e| ParseError::at(input, 2, e.to_string()))?;
+                Ok(Command::Eval { ops })
+            }
+            "SELECT" => {
+                let args: Vec<&str> = rest.split_whitespace().collect();
+                if args.len() != 1 {
+                    return Err(ParseError::arity(input, 2, "SELECT command requires exactly one database index").into());
+                }
+                let index = args[0]
+                    .parse::<usize>()
+                    .map_err(|_| ParseError::at(input, 2, "SELECT index must be a non-negative integer"))?;
+                Ok(Command::Select { index })
+            }
+            "DBDIFF" => {
+                let args: Vec<&str> = rest.split_whitespace().collect();
+                if args.len() != 2 {
+                    return Err(ParseError::arity(input, 3, "Usage: DBDIFF <db1> <db2>").into());
+                }
+                let index = |i: usize| {
+                    args[i].parse::<usize>().map_err(|_| ParseError::at(input, i + 2, "DBDIFF database indexes must be non-negative integers"))
+                };
+                Ok(Command::DbDiff { first: index(0)?, second: index(1)? })
+            }
+            "HGET" => {
+                let args: Vec<&str> = rest.split_whitespace().collect();
+                if args.len() != 2 {
+                    return Err(ParseError::arity(input, 3, "HGET command requires a key and a field").into());
+                }
+                Ok(Command::HGet {
+                    key: args[0].to_string(),
+                    field: args[1].to_string(),
+                })
+            }
+            "HGETALL" => {
+                let args: Vec<&str> = rest.split_whitespace().collect();
+                if args.len() != 1 {
+                    return Err(ParseError::arity(input, 2, "HGETALL command requires exactly one key").into());
+                }
+                Ok(Command::HGetAll { key: args[0].to_string() })
+            }
+            "VERIFY" => {
+                let args: Vec<&str> = rest.split_whitespace().collect();
+                match args[..] {
+                    [sub, peer] if sub.eq_ignore_ascii_case("CONSISTENT") => {
+                        crate::net_addr::split_host_port(peer).map_err(|e| ParseError::at(input, 3, e.to_string()))?;
+                        Ok(Command::VerifyConsistent { peer: peer.to_string() })
+                    }
+                    [sub, ..] if sub.eq_ignore_ascii_case("CONSISTENT") => {
+                        Err(ParseError::arity(input, 3, "VERIFY CONSISTENT requires exactly one <host:port> peer").into())
+                    }
+                    [] => Err(ParseError::missing(input, "VERIFY requires a subcommand: CONSISTENT <host:port>").into()),
+                    [sub, ..] => Err(ParseError::at(input, 2, format!("Unknown VERIFY subcommand: {} (expected CONSISTENT)", sub)).into()),
+                }
+            }
+            "MERKLE" => {
+                let args: Vec<&str> = rest.split_whitespace().collect();
+                let action = match args[..] {
+                    [sub] if sub.eq_ignore_ascii_case("VERIFY") => MerkleAction::Verify,
+                    [sub] if sub.eq_ignore_ascii_case("REBUILD") => MerkleAction::Rebuild,
+                    [sub] if sub.eq_ignore_ascii_case("GC") => MerkleAction::Gc,
+                    [sub] if sub.eq_ignore_ascii_case("FETCH") => MerkleAction::Fetch { path: String::new() },
+                    [sub, path] if sub.eq_ignore_ascii_case("FETCH") => {
+                        if !path.chars().all(|c| c == '0' || c == '1') {
+                            return Err(ParseError::at(input, 3, "MERKLE FETCH path must be made of 0 (left) and 1 (right)").into());
+                        }
+                        MerkleAction::Fetch { path: path.to_string() }
+                    }
+                    [sub, ..] if sub.eq_ignore_ascii_case("FETCH") => {
+                        return Err(ParseError::arity(input, 3, "Usage: MERKLE FETCH [path]").into());
+                    }
+                    _ => {
+                        let arg = rest.trim();
+                        return Err(ParseError::at(input, 2, format!("Unknown MERKLE subcommand: {} (expected VERIFY, REBUILD, FETCH or GC)", arg)).into());
+                    }
+                };
+                Ok(Command::Merkle { action })
+            }
+            "AUTH" => Ok(Command::Auth { password: rest.trim().to_string() }),
+            "CONFIG" => {
+                let args: Vec<&str> = rest.split_whitespace().collect();
+                match args.first().map(|s| s.to_ascii_uppercase()).as_deref() {
+                    Some("GET") if args.len() == 2 => Ok(Command::ConfigGet { param: args[1].to_string() }),
+                    Some("SET") if args.len() == 3 => Ok(Command::ConfigSet {
+                        param: args[1].to_string(),
+                        value: args[2].to_string(),
+                    }),
+                    Some("SET") => Err(ParseError::arity(input, 4, "Usage: CONFIG SET <param> <value>").into()),
+                    Some("GET") => Err(ParseError::arity(input, 3, "Usage: CONFIG GET <param>").into()),
+                    Some("RELOAD") if args.len() == 2 && args[1].eq_ignore_ascii_case("TLS") => Ok(Command::ConfigReloadTls),
+                    Some("RELOAD") => Err(ParseError::at(input, 3, "Usage: CONFIG RELOAD TLS").into()),
+                    _ => Err(ParseError::at(input, 2, "Usage: CONFIG GET <param> | CONFIG SET <param> <value> | CONFIG RELOAD TLS").into()),
+                }
+            }
+            "LOG" => {
+                let args: Vec<&str> = rest.split_whitespace().collect();
+                match args[..] {
+                    [sub, module, level] if sub.eq_ignore_ascii_case("LEVEL") => {
+                        let level = if level.eq_ignore_ascii_case("default") {
+                            None
+                        } else {
+                            Some(level.parse().map_err(|_| {
+                                ParseError::at(input, 4, format!("Invalid level '{}' (expected off, error, warn, info, debug, trace or default)", level))
+                            })?)
+                        };
+                        Ok(Command::LogLevel { module: module.to_string(), level })
+                    }
+                    [sub, ..] if sub.eq_ignore_ascii_case("LEVEL") => {
+                        Err(ParseError::arity(input, 4, "Usage: LOG LEVEL <module> <level>").into())
+                    }
+                    _ => Err(ParseError::at(input, 2, "Usage: LOG LEVEL <module> <level>").into()),
+                }
+            }
+            "SUBSCRIBE" => {
+                let args: Vec<&str> = rest.split_whitespace().collect();
+                let arg = args.first().copied().unwrap_or_default();
+                let channel = match arg.to_ascii_uppercase().as_str() {
+                    "MERKLE" => SubscribeChannel::Merkle,
+                    _ => return Err(ParseError::at(input, 2, format!("Unknown SUBSCRIBE channel: {} (expected MERKLE)", arg)).into()),
+                };
+                let resume = match args[1..] {
+                    [] => None,
+                    [id, version] => {
+                        let version = version
+                            .parse::<u64>()
+                            .map_err(|_| ParseError::at(input, 4, "SUBSCRIBE version must be a non-negative integer"))?;
+                        Some((id.to_string(), version))
+                    }
+                    _ => return Err(ParseError::at(input, 3, "Usage: SUBSCRIBE MERKLE [<id> <version>]").into()),
+                };
+                Ok(Command::Subscribe { channel, resume })
+            }
+            "MEMORY" => {
+                let mut it = rest.split_whitespace();
+                match it.next().map(|s| s.to_ascii_uppercase()).as_deref() {
+                    None => Ok(Command::Memory),
+                    Some("COMPACT") => {
+                        if it.next().is_some() {
+                            return Err(ParseError::at(input, 3, "Usage: MEMORY COMPACT").into());
+                        }
+                        Ok(Command::MemoryCompact)
+                    }
+                    Some("HISTOGRAM") => {
+                        let samples = match it.next() {
+                            Some(n) => Some(
+                                n.parse::<usize>()
+                                    .ok()
+                                    .filter(|&n| n > 0)
+                                    .ok_or_else(|| ParseError::at(input, 3, "MEMORY HISTOGRAM sample count must be a positive integer"))?,
+                            ),
+                            None => None,
+                        };
+                        if it.next().is_some() {
+                            return Err(ParseError::at(input, 4, "Usage: MEMORY HISTOGRAM [samples]").into());
+                        }
+                        Ok(Command::MemoryHistogram { samples })
+                    }
+                    Some(_) => Err(ParseError::at(input, 2, "MEMORY command does not accept any arguments").into()),
+                }
+            }
+            "STORAGE" => {
+                let mut it = rest.split_whitespace();
+                let command = match it.next().map(|s| s.to_ascii_uppercase()).as_deref() {
+                    Some("STATS") => Command::StorageStats,
+                    Some("COMPACT") => Command::StorageCompact,
+                    _ => return Err(ParseError::at(input, 2, "Usage: STORAGE STATS|COMPACT").into()),
+                };
+                if it.next().is_some() {
+                    return Err(ParseError::at(input, 3, "Usage: STORAGE STATS|COMPACT").into());
+                }
+                Ok(command)
+            }
+            "SHARD" => {
+                let mut it = rest.split_whitespace();
+                if !it.next().is_some_and(|s| s.eq_ignore_ascii_case("STATS")) {
+                    return Err(ParseError::at(input, 2, "Usage: SHARD STATS").into());
+                }
+                if it.next().is_some() {
+                    return Err(ParseError::at(input, 3, "Usage: SHARD STATS").into());
+                }
+                Ok(Command::ShardStats)
+            }
+            "PERSISTENCE" => {
+                let mut it = rest.split_whitespace();
+                let enabled = match it.next().map(|s| s.to_ascii_uppercase()).as_deref() {
+                    Some("ON") => true,
+                    Some("OFF") => false,
+                    _ => return Err(ParseError::at(input, 2, "Usage: PERSISTENCE ON|OFF").into()),
+                };
+                if it.next().is_some() {
+                    return Err(ParseError::at(input, 3, "Usage: PERSISTENCE ON|OFF").into());
+                }
+                Ok(Command::Persistence { enabled })
+            }
+            "CLIENT" => {
+                let mut it = rest.split_whitespace();
                 let sub = it.next().unwrap_or("").to_ascii_uppercase();
                 match sub.as_str() {
                     "LIST" => Ok(Command::Clientlist),
-                    _ => Err(anyhow::anyhow!("Unknown CLIENT subcommand")),
+                    "COMPRESS" => {
+                        let enabled = match it.next().map(|s| s.to_ascii_uppercase()).as_deref() {
+                            Some("ON") => true,
+                            Some("OFF") => false,
+                            _ => return Err(ParseError::at(input, 3, "Usage: CLIENT COMPRESS ON|OFF").into()),
+                        };
+                        if it.next().is_some() {
+                            return Err(ParseError::at(input, 3, "Usage: CLIENT COMPRESS ON|OFF").into());
+                        }
+                        Ok(Command::ClientCompress { enabled })
+                    }
+                    "TOKENS" => {
+                        let enabled = match it.next().map(|s| s.to_ascii_uppercase()).as_deref() {
+                            Some("ON") => true,
+                            Some("OFF") => false,
+                            _ => return Err(ParseError::at(input, 3, "Usage: CLIENT TOKENS ON|OFF").into()),
+                        };
+                        if it.next().is_some() {
+                            return Err(ParseError::at(input, 3, "Usage: CLIENT TOKENS ON|OFF").into());
+                        }
+                        Ok(Command::ClientTokens { enabled })
+                    }
+                    _ => Err(ParseError::at(input, 2, "Unknown CLIENT subcommand").into()),
                 }
             }
             "SCAN" => {
                 if rest.contains(' ') {
-                    return Err(anyhow!("SCAN command accepts only one argument"));
+                    return Err(ParseError::at(input, 3, "SCAN command accepts only one argument").into());
                 }
                 // Check for invalid characters in prefix
                 if rest.contains('\t') {
-                    return Err(anyhow!("Invalid character: tab character not allowed in prefix"));
+                    return Err(ParseError::control_char(input, "Invalid character: tab character not allowed in prefix").into());
                 }
                 if rest.contains('\n') {
-                    return Err(anyhow!("Invalid character: newline character not allowed in prefix"));
+                    return Err(ParseError::control_char(input, "Invalid character: newline character not allowed in prefix").into());
                 }
                 Ok(Command::Scan {
                     prefix: rest.to_string(),
                 })
             }
+            "TASKS" => {
+                let mut it = rest.split_whitespace();
+                match (it.next().map(|s| s.to_ascii_uppercase()).as_deref(), it.next(), it.next()) {
+                    (Some("RUN"), Some(name), None) => Ok(Command::TaskRun { name: name.to_string() }),
+                    _ => Err(ParseError::at(input, 2, "Usage: TASKS [RUN <name>]").into()),
+                }
+            }
+            "CLUSTER" => match rest.trim() {
+                sub if sub.eq_ignore_ascii_case("PEERS") => Ok(Command::ClusterPeers),
+                sub => Err(ParseError::at(input, 2, format!("Unknown CLUSTER subcommand: {} (expected PEERS)", sub)).into()),
+            },
+            "EXPORT" => match rest.trim() {
+                format if format.eq_ignore_ascii_case("JSON") => Ok(Command::ExportJson),
+                format => Err(ParseError::at(input, 2, format!("Unknown EXPORT format: {} (expected JSON)", format)).into()),
+            },
+            "METRICS" => {
+                let mut it = rest.split_whitespace();
+                let usage = "Usage: METRICS DUMP [TEXT|JSON] (writes metrics.dump_path)";
+                if !it.next().is_some_and(|sub| sub.eq_ignore_ascii_case("DUMP")) {
+                    return Err(ParseError::at(input, 2, usage).into());
+                }
+                let format = match it.next().map(|s| s.to_ascii_uppercase()).as_deref() {
+                    None => None,
+                    Some("TEXT") => Some(MetricsFormat::Text),
+                    Some("JSON") => Some(MetricsFormat::Json),
+                    Some(_) => return Err(ParseError::at(input, 3, usage).into()),
+                };
+                if it.next().is_some() {
+                    return Err(ParseError::at(input, 4, usage).into());
+                }
+                Ok(Command::MetricsDump { format })
+            }
+            "EXPLAIN" => {
+                let command = self.parse(rest)?;
+                if matches!(command, Command::Explain { .. }) {
+                    return Err(ParseError::at(input, 2, "EXPLAIN cannot explain itself").into());
+                }
+                Ok(Command::Explain { command: Box::new(command) })
+            }
+            "FINDBYVALUE" => {
+                if rest.contains(' ') {
+                    return Err(ParseError::at(input, 3, "FINDBYVALUE command accepts only one argument").into());
+                }
+                if rest.contains('\t') || rest.contains('\n') {
+                    return Err(ParseError::control_char(input, "Invalid character: control characters not allowed in prefix").into());
+                }
+                Ok(Command::FindByValue {
+                    prefix: rest.to_string(),
+                })
+            }
             "INC" => {
                 if rest.is_empty() {
-                    return Err(anyhow!("INC command requires a key"));
+                    return Err(ParseError::missing(input, "INC command requires a key").into());
                 }
                 
                 // Split the rest into key and optional amount
@@ -565,22 +1766,22 @@ impl Protocol {
                 
                 // Check if what appears to be the key is actually a number
                 if parts[0].parse::<i64>().is_ok() && parts.len() == 1 {
-                    return Err(anyhow!("INC command requires a key"));
+                    return Err(ParseError::missing(input, "INC command requires a key").into());
                 }
                 
                 // Check for invalid characters in key
                 if parts[0].contains('\t') {
-                    return Err(anyhow!("Invalid character: tab character not allowed in key"));
+                    return Err(ParseError::control_char(input, "Invalid character: tab character not allowed in key").into());
                 }
                 if parts[0].contains('\n') {
-                    return Err(anyhow!("Invalid character: newline character not allowed in key"));
+                    return Err(ParseError::control_char(input, "Invalid character: newline character not allowed in key").into());
                 }
                 
                 // Parse optional amount parameter
                 let amount = if parts.len() > 1 {
                     match parts[1].parse::<i64>() {
                         Ok(val) => Some(val),
-                        Err(_) => return Err(anyhow!("INC command amount must be a valid number")),
+                        Err(_) => return Err(ParseError::at(input, 3, "INC command amount must be a valid number").into()),
                     }
                 } else {
                     None // Default increment of 1 will be applied
@@ -593,7 +1794,7 @@ impl Protocol {
             }
             "DEC" => {
                 if rest.is_empty() {
-                    return Err(anyhow!("DEC command requires a key"));
+                    return Err(ParseError::missing(input, "DEC command requires a key").into());
                 }
                 
                 // Split the rest into key and optional amount
@@ -601,22 +1802,22 @@ impl Protocol {
                 
                 // Check if what appears to be the key is actually a number
                 if parts[0].parse::<i64>().is_ok() && parts.len() == 1 {
-                    return Err(anyhow!("DEC command requires a key"));
+                    return Err(ParseError::missing(input, "DEC command requires a key").into());
                 }
                 
                 // Check for invalid characters in key
                 if parts[0].contains('\t') {
-                    return Err(anyhow!("Invalid character: tab character not allowed in key"));
+                    return Err(ParseError::control_char(input, "Invalid character: tab character not allowed in key").into());
                 }
                 if parts[0].contains('\n') {
-                    return Err(anyhow!("Invalid character: newline character not allowed in key"));
+                    return Err(ParseError::control_char(input, "Invalid character: newline character not allowed in key").into());
                 }
                 
                 // Parse optional amount parameter
                 let amount = if parts.len() > 1 {
                     match parts[1].parse::<i64>() {
                         Ok(val) => Some(val),
-                        Err(_) => return Err(anyhow!("DEC command amount must be a valid number")),
+                        Err(_) => return Err(ParseError::at(input, 3, "DEC command amount must be a valid number").into()),
                     }
                 } else {
                     None // Default decrement of 1 will be applied
@@ -630,26 +1831,26 @@ impl Protocol {
             "APPEND" => {
                 let second_space = rest.find(' ');
                 if second_space.is_none() {
-                    return Err(anyhow!("APPEND command requires a key and value"));
+                    return Err(ParseError::missing(input, "APPEND command requires a key and value").into());
                 }
                 let key = &rest[..second_space.unwrap()];
                 let value = &rest[second_space.unwrap() + 1..];
                 
                 if key.is_empty() {
-                    return Err(anyhow!("APPEND command key cannot be empty"));
+                    return Err(ParseError::at(input, 2, "APPEND command key cannot be empty").into());
                 }
                 
                 // Check for invalid characters in key only (tabs allowed in values; newlines reserved for CRLF framing)
                 if key.contains('\t') {
-                    return Err(anyhow!("Invalid character: tab character not allowed in key"));
+                    return Err(ParseError::control_char(input, "Invalid character: tab character not allowed in key").into());
                 }
                 if key.contains('\n') {
-                    return Err(anyhow!("Invalid character: newline character not allowed in key"));
+                    return Err(ParseError::control_char(input, "Invalid character: newline character not allowed in key").into());
                 }
                 
                 // Newlines are forbidden in values due to CRLF protocol framing
                 if value.contains('\n') {
-                    return Err(anyhow!("Invalid character: newline character not allowed in value"));
+                    return Err(ParseError::control_char(input, "Invalid character: newline character not allowed in value").into());
                 }
                 
                 // Allow empty values for APPEND
@@ -662,26 +1863,26 @@ impl Protocol {
             "PREPEND" => {
                 let second_space = rest.find(' ');
                 if second_space.is_none() {
-                    return Err(anyhow!("PREPEND command requires a key and value"));
+                    return Err(ParseError::missing(input, "PREPEND command requires a key and value").into());
                 }
                 let key = &rest[..second_space.unwrap()];
                 let value = &rest[second_space.unwrap() + 1..];
                 
                 if key.is_empty() {
-                    return Err(anyhow!("PREPEND command key cannot be empty"));
+                    return Err(ParseError::at(input, 2, "PREPEND command key cannot be empty").into());
                 }
                 
                 // Check for invalid characters in key only (tabs allowed in values; newlines reserved for CRLF framing)
                 if key.contains('\t') {
-                    return Err(anyhow!("Invalid character: tab character not allowed in key"));
+                    return Err(ParseError::control_char(input, "Invalid character: tab character not allowed in key").into());
                 }
                 if key.contains('\n') {
-                    return Err(anyhow!("Invalid character: newline character not allowed in key"));
+                    return Err(ParseError::control_char(input, "Invalid character: newline character not allowed in key").into());
                 }
                 
                 // Newlines are forbidden in values due to CRLF protocol framing
                 if value.contains('\n') {
-                    return Err(anyhow!("Invalid character: newline character not allowed in value"));
+                    return Err(ParseError::control_char(input, "Invalid character: newline character not allowed in value").into());
                 }
                 
                 // Allow empty values for PREPEND
@@ -693,7 +1894,7 @@ impl Protocol {
             }
             "MGET" => {
                 if rest.is_empty() {
-                    return Err(anyhow!("MGET command requires at least one key"));
+                    return Err(ParseError::missing(input, "MGET command requires at least one key").into());
                 }
                 
                 // Extract all keys
@@ -702,16 +1903,16 @@ impl Protocol {
                     .collect();
                 
                 if keys.is_empty() {
-                    return Err(anyhow!("MGET command requires at least one key"));
+                    return Err(ParseError::missing(input, "MGET command requires at least one key").into());
                 }
                 
                 // Check for invalid characters in all keys
                 for key in &keys {
                     if key.contains('\t') {
-                        return Err(anyhow!("Invalid character: tab character not allowed in key"));
+                        return Err(ParseError::control_char(input, "Invalid character: tab character not allowed in key").into());
                     }
                     if key.contains('\n') {
-                        return Err(anyhow!("Invalid character: newline character not allowed in key"));
+                        return Err(ParseError::control_char(input, "Invalid character: newline character not allowed in key").into());
                     }
                 }
                 
@@ -719,15 +1920,15 @@ impl Protocol {
             }
             "MSET" => {
                 if rest.is_empty() {
-                    return Err(anyhow!("MSET command requires at least one key-value pair"));
+                    return Err(ParseError::missing(input, "MSET command requires at least one key-value pair").into());
                 }
                 
                 // Extract all parts
                 let args: Vec<&str> = rest.split_whitespace().collect();
                 
                 // We need an even number of parts for key-value pairs
-                if args.len() % 2 != 0 {
-                    return Err(anyhow!("MSET command requires an even number of arguments (key-value pairs)"));
+                if !args.len().is_multiple_of(2) {
+                    return Err(ParseError::missing(input, "MSET command requires an even number of arguments (key-value pairs)").into());
                 }
                 
                 let mut pairs = Vec::new();
@@ -738,10 +1939,10 @@ impl Protocol {
                     
                     // Check for invalid characters in key only (values can contain control characters)
                     if key.contains('\t') {
-                        return Err(anyhow!("Invalid character: tab character not allowed in key"));
+                        return Err(ParseError::control_char(input, "Invalid character: tab character not allowed in key").into());
                     }
                     if key.contains('\n') {
-                        return Err(anyhow!("Invalid character: newline character not allowed in key"));
+                        return Err(ParseError::control_char(input, "Invalid character: newline character not allowed in key").into());
                     }
                     
                     pairs.push((key, value));
@@ -749,7 +1950,7 @@ impl Protocol {
                 }
                 
                 if pairs.is_empty() {
-                    return Err(anyhow!("MSET command requires at least one key-value pair"));
+                    return Err(ParseError::missing(input, "MSET command requires at least one key-value pair").into());
                 }
                 
                 Ok(Command::MultiSet { pairs })
@@ -757,9 +1958,6 @@ impl Protocol {
             "FLUSHDB" => {
                 Ok(Command::Flushdb)
             }
-            "MEMORY" => {
-                Ok(Command::Memory)
-            }
             "TRUNCATE" => {
                 Ok(Command::Truncate)
             }
@@ -767,13 +1965,48 @@ impl Protocol {
                 Ok(Command::Stats)
             }
             "INFO" => {
-                Ok(Command::Info)
+                // Other sections are accepted and ignored, as before
+                if rest.trim().eq_ignore_ascii_case("JSON") {
+                    Ok(Command::InfoJson)
+                } else {
+                    Ok(Command::Info)
+                }
             }
-            _ => Err(anyhow!("Unknown command: {}", command)),
+            _ => Err(ParseError::at(input, 1, format!("Unknown command: {}", command)).into()),
+        }
+    }
+
+    /// Parse a SET line without its trailing option word `flag`, refusing a
+    /// value of several words before the options.
+    fn parse_before_option(&self, input: &str, flag: &str) -> Result<Command> {
+        let command = self.parse(&input[..input.len() - flag.len() - 1])?;
+        if set_value(&command).is_some_and(|value| value.contains(' ')) {
+            return Err(ambiguous_option(input, flag));
         }
+        Ok(command)
+    }
+}
+
+/// The value stored by a (possibly wrapped) SET.
+fn set_value(command: &Command) -> Option<&str> {
+    match command {
+        Command::Set { value, .. } | Command::SetEx { value, .. } => Some(value),
+        Command::Durable { command } | Command::Local { command } | Command::Consistent { command, .. } => set_value(command),
+        _ => None,
     }
 }
 
+/// A SET whose value of several words ends in option `word`: the last words
+/// may be text a client stored verbatim before options existed, so neither
+/// reading is safe.
+fn ambiguous_option(input: &str, word: &str) -> anyhow::Error {
+    ParseError::at_last(
+        input,
+        format!("SET option {} after a value of several words is ambiguous; send such a value with SET key STREAM <len>", word),
+    )
+    .into()
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -788,6 +2021,148 @@ mod tests {
                 key: "test_key".to_string()
             }
         );
+
+        assert_eq!(
+            protocol.parse("GET k withtoken node-a:42").unwrap(),
+            Command::GetWithToken { key: "k".to_string(), token: Token { node: "node-a".to_string(), ts: 42 } }
+        );
+        assert!(protocol.parse("GET k WITHTOKEN bogus").is_err());
+        assert!(protocol.parse("GET k OTHER node-a:42").is_err());
+    }
+
+    #[test]
+    fn test_parse_durable() {
+        let protocol = Protocol::new();
+        let durable = |command| Command::Durable { command: Box::new(command) };
+        assert_eq!(
+            protocol.parse("SET k value DURABLE").unwrap(),
+            durable(Command::Set { key: "k".to_string(), value: "value".to_string() })
+        );
+        assert_eq!(
+            protocol.parse("SET k v EX 10 DURABLE").unwrap(),
+            durable(Command::SetEx { key: "k".to_string(), value: "v".to_string(), ttl_ms: 10_000 })
+        );
+        assert_eq!(
+            protocol.parse("SET k DURABLE").unwrap(),
+            Command::Set { key: "k".to_string(), value: "DURABLE".to_string() },
+            "a lone DURABLE is the value"
+        );
+        assert_eq!(parse_error("SET k v EX 0 DURABLE").token, 5);
+    }
+
+    #[test]
+    fn test_values_ending_like_options_are_kept_whole_or_refused() {
+        let protocol = Protocol::new();
+        let set = |value: &str| Command::Set { key: "k".to_string(), value: value.to_string() };
+        // Options are uppercase: text in any other case is the value, as it always was
+        for value in ["ship it durable", "shop Local", "level cl=ONE", "call ex 5", "hello world px 250", "stream 13"] {
+            assert_eq!(protocol.parse(&format!("SET k {}", value)).unwrap(), set(value));
+        }
+        // An option after several words could be text too: refused, never cut
+        for line in ["SET k ship it DURABLE", "SET k scratch data LOCAL", "SET k a b CL=all", "SET k hello world EX 5", "SET k a b EX 5 DURABLE"] {
+            let err = parse_error(line);
+            assert!(err.message.contains("ambiguous"), "{}: {}", line, err.message);
+        }
+        // The verbatim form announces the length instead
+        assert_eq!(protocol.parse("SET k STREAM 13").unwrap(), Command::SetStream { key: "k".to_string(), len: 13 });
+    }
+
+    #[test]
+    fn test_parse_local() {
+        let protocol = Protocol::new();
+        let local = |command| Command::Local { command: Box::new(command) };
+        let set = Command::Set { key: "k".to_string(), value: "scratch".to_string() };
+        assert_eq!(protocol.parse("SET k scratch LOCAL").unwrap(), local(set.clone()));
+        assert_eq!(
+            protocol.parse("SET k v PX 500 LOCAL DURABLE").unwrap(),
+            Command::Durable { command: Box::new(local(Command::SetEx { key: "k".to_string(), value: "v".to_string(), ttl_ms: 500 })) }
+        );
+        assert_eq!(protocol.parse("SET k LOCAL").unwrap(), Command::Set { key: "k".to_string(), value: "LOCAL".to_string() });
+        let plan = protocol.parse("SET k v LOCAL").unwrap().plan();
+        assert_eq!((plan.ops, plan.replicates), (vec!["write:k".to_string()], false));
+    }
+
+    #[test]
+    fn test_parse_write_consistency() {
+        let protocol = Protocol::new();
+        let set = Command::Set { key: "k".to_string(), value: "v".to_string() };
+        assert_eq!(
+            protocol.parse("SET k v CL=quorum").unwrap(),
+            Command::Consistent { level: WriteConsistency::Quorum, command: Box::new(set.clone()) }
+        );
+        assert_eq!(
+            protocol.parse("SET k v CL=ALL DURABLE").unwrap(),
+            Command::Durable {
+                command: Box::new(Command::Consistent { level: WriteConsistency::All, command: Box::new(set.clone()) })
+            }
+        );
+        assert_eq!(protocol.parse("SET k v CL=one").unwrap().name(), "SET");
+        assert_eq!(
+            protocol.parse("SET k CL=all").unwrap(),
+            Command::Set { key: "k".to_string(), value: "CL=all".to_string() },
+            "a lone CL=... is the value"
+        );
+        assert_eq!(parse_error("SET k v CL=most").token, 4);
+
+        assert_eq!(WriteConsistency::One.required_acks(4), 0);
+        assert_eq!(WriteConsistency::Quorum.required_acks(2), 1);
+        assert_eq!(WriteConsistency::Quorum.required_acks(3), 2);
+        assert_eq!(WriteConsistency::All.required_acks(3), 3);
+    }
+
+    #[test]
+    fn test_parse_line_terminators() {
+        let lenient = Protocol::new();
+        let get = Command::Get { key: "k".to_string() };
+        for line in ["GET k\n", "GET k\r\n", "GET k"] {
+            assert_eq!(lenient.parse_line(line).unwrap(), get, "{:?}", line);
+        }
+        assert_eq!(
+            lenient.parse_line("SET k a b\n").unwrap(),
+            Command::Set { key: "k".to_string(), value: "a b".to_string() }
+        );
+
+        let strict = Protocol::new().with_strict_crlf(true);
+        assert_eq!(strict.parse_line("GET k\r\n").unwrap(), get);
+        for line in ["GET k\n", "GET k"] {
+            let err = strict.parse_line(line).unwrap_err().downcast::<ParseError>().unwrap();
+            assert_eq!(err.token, 2, "{:?}", line);
+            assert!(err.message.contains("strict_crlf"));
+        }
+    }
+
+    #[test]
+    fn test_commands_over_max_args_are_refused() {
+        let protocol = Protocol::new().with_max_args(4);
+        assert_eq!(
+            protocol.parse("MSET a 1 b 2").unwrap(),
+            Command::MultiSet { pairs: vec![("a".to_string(), "1".to_string()), ("b".to_string(), "2".to_string())] }
+        );
+        let err = protocol.parse("MSET a 1 b 2 c 3").unwrap_err().downcast::<ParseError>().unwrap();
+        assert_eq!(err.message, "too many arguments (server.max_args is 4)");
+        assert_eq!((err.token, err.byte), (6, 13));
+        // Words of a value count too
+        assert!(protocol.parse("SET k a b c").is_ok());
+        assert!(protocol.parse("SET k  a b c d").is_err());
+        assert!(Protocol::new().parse(&format!("MGET{}", " k".repeat(10_000))).is_ok());
+    }
+
+    #[test]
+    fn test_parse_stream() {
+        let protocol = Protocol::new();
+        assert_eq!(protocol.parse("GET big stream").unwrap(), Command::GetStream { key: "big".to_string() });
+        assert!(protocol.parse("GET big STREAMING").is_err());
+        assert_eq!(protocol.parse("GET k withcrc").unwrap(), Command::GetWithCrc { key: "k".to_string() });
+        assert_eq!(protocol.parse("GET k WithTtl").unwrap(), Command::GetWithTtl { key: "k".to_string() });
+        assert_eq!(
+            protocol.parse("SET big STREAM 4194304").unwrap(),
+            Command::SetStream { key: "big".to_string(), len: 4194304 }
+        );
+        // Without a length it is an ordinary value
+        assert_eq!(
+            protocol.parse("SET k STREAM of consciousness").unwrap(),
+            Command::Set { key: "k".to_string(), value: "STREAM of consciousness".to_string() }
+        );
     }
 
     #[test]
@@ -835,11 +2210,123 @@ mod tests {
                 prefix: "test_prefix".to_string()
             }
         );
-        // Test SCAN with empty prefix
-        assert!(protocol.parse("SCAN").is_err());
+        // Test SCAN with empty prefix (returns all keys; used by anti-entropy sync)
+        assert_eq!(
+            protocol.parse("SCAN").unwrap(),
+            Command::Scan {
+                prefix: String::new()
+            }
+        );
         // Test SCAN with spaces in prefix
         assert!(protocol.parse("SCAN test prefix").is_err());
     }
+
+    #[test]
+    fn test_parse_explain() {
+        let protocol = Protocol::new();
+        let explained = protocol.parse("EXPLAIN set a hello world").unwrap();
+        assert_eq!(
+            explained,
+            Command::Explain {
+                command: Box::new(Command::Set { key: "a".to_string(), value: "hello world".to_string() })
+            }
+        );
+        assert_eq!(explained.plan(), Plan { ops: vec!["write:a".to_string()], replicates: true });
+        assert_eq!(
+            protocol.parse("EXPLAIN INC n").unwrap().plan().ops,
+            vec!["read:n".to_string(), "write:n".to_string()]
+        );
+        assert!(!protocol.parse("EXPLAIN MGET a b").unwrap().plan().replicates);
+        assert!(protocol.parse("EXPLAIN PING").unwrap().plan().ops.is_empty());
+        assert!(protocol.parse("EXPLAIN").is_err());
+        assert!(protocol.parse("EXPLAIN GET").is_err(), "the explained command is validated");
+        assert!(protocol.parse("EXPLAIN EXPLAIN GET a").is_err());
+    }
+
+    #[test]
+    fn test_parse_tasks() {
+        let protocol = Protocol::new();
+        assert_eq!(protocol.parse("tasks").unwrap(), Command::Tasks);
+        assert_eq!(protocol.parse("TASKS run sweep").unwrap(), Command::TaskRun { name: "sweep".to_string() });
+        assert!(protocol.parse("TASKS RUN").is_err());
+        assert!(protocol.parse("TASKS RUN a b").is_err());
+        assert!(protocol.parse("TASKS STOP sweep").is_err());
+    }
+
+    #[test]
+    fn test_parse_swap() {
+        let protocol = Protocol::new();
+        assert_eq!(
+            protocol.parse("swap a b").unwrap(),
+            Command::Swap { key1: "a".to_string(), key2: "b".to_string() }
+        );
+        assert!(protocol.parse("SWAP").is_err());
+        assert!(protocol.parse("SWAP a").is_err());
+        assert!(protocol.parse("SWAP a b c").is_err());
+    }
+
+    #[test]
+    fn test_command_names_ignore_case_and_resolve_aliases() {
+        let protocol = Protocol::new();
+        let set = Command::Set { key: "k".to_string(), value: "v".to_string() };
+        for input in ["set k v", "SET k v", "Set k v", "sEt k v"] {
+            assert_eq!(protocol.parse(input).unwrap(), set, "{}", input);
+        }
+        let del = Command::Delete { key: "k".to_string() };
+        for input in ["DEL k", "del k", "DELETE k", "Delete k", "RM k", "rm k"] {
+            assert_eq!(protocol.parse(input).unwrap(), del, "{}", input);
+        }
+        assert_eq!(protocol.parse("incr n").unwrap(), protocol.parse("INC n").unwrap());
+        assert_eq!(protocol.parse("DECR n 2").unwrap(), protocol.parse("DEC n 2").unwrap());
+        assert_eq!(protocol.parse("FlushAll").unwrap(), Command::Flushdb);
+        assert_eq!(protocol.parse("Ping").unwrap(), Command::Ping { message: String::new() });
+        assert_eq!(parse_error("rm").message, "RM command requires arguments");
+        assert!(parse_error("REMOVE k").message.starts_with("Unknown command"), "not an alias");
+        assert_eq!(canonical_command("delete"), "DEL");
+    }
+
+    #[test]
+    fn test_parse_metrics_dump() {
+        let protocol = Protocol::new();
+        assert_eq!(protocol.parse("METRICS DUMP").unwrap(), Command::MetricsDump { format: None });
+        assert_eq!(protocol.parse("metrics dump json").unwrap(), Command::MetricsDump { format: Some(MetricsFormat::Json) });
+        assert_eq!(parse_error("METRICS SHOW x").token, 2);
+        // No client-chosen path
+        assert_eq!(parse_error("METRICS DUMP /home/merklekv/.ssh/authorized_keys").token, 3);
+        assert_eq!(parse_error("METRICS DUMP text now").token, 4);
+        assert!(protocol.parse("METRICS").is_err());
+    }
+
+    #[test]
+    fn test_parse_select() {
+        let protocol = Protocol::new();
+        assert_eq!(protocol.parse("SELECT 3").unwrap(), Command::Select { index: 3 });
+        assert_eq!(protocol.parse("dbdiff 0 2").unwrap(), Command::DbDiff { first: 0, second: 2 });
+        assert_eq!(protocol.parse("HELLO").unwrap(), Command::Hello { codecs: vec![] });
+        assert_eq!(
+            protocol.parse("hello compress LZ4 zlib").unwrap(),
+            Command::Hello { codecs: vec!["lz4".to_string(), "zlib".to_string()] }
+        );
+        assert_eq!(parse_error("HELLO COMPRESS").token, 3);
+        assert_eq!(parse_error("HELLO 3").token, 2);
+        assert_eq!(parse_error("DBDIFF 0 x").token, 3);
+        assert!(protocol.parse("DBDIFF 0").is_err());
+        assert!(protocol.parse("DBDIFF").is_err());
+        assert_eq!(parse_error("SELECT -1").token, 2);
+        assert_eq!(parse_error("SELECT 1 2").token, 3);
+        assert!(protocol.parse("SELECT").is_err());
+    }
+
+    #[test]
+    fn test_parse_findbyvalue() {
+        let protocol = Protocol::new();
+        assert_eq!(
+            protocol.parse("findbyvalue admin:").unwrap(),
+            Command::FindByValue { prefix: "admin:".to_string() }
+        );
+        assert!(protocol.parse("FINDBYVALUE").is_err());
+        assert!(protocol.parse("FINDBYVALUE a b").is_err());
+    }
     #[test]
     fn test_parse_ping() {
         let protocol = Protocol::new();
@@ -883,6 +2370,13 @@ mod tests {
         // Test DBSIZE with extra arguments (should error)
         assert!(protocol.parse("DBSIZE extra_arg").is_err());
     }
+
+    #[test]
+    fn test_parse_randomkey() {
+        let protocol = Protocol::new();
+        assert_eq!(protocol.parse("randomkey").unwrap(), Command::RandomKey);
+        assert!(protocol.parse("RANDOMKEY 5").is_err());
+    }
     #[test]
     fn test_parse_exists() {
         let protocol = Protocol::new();
@@ -922,6 +2416,18 @@ mod tests {
         let protocol = Protocol::new();
         let result = protocol.parse("CLIENT LIST").unwrap();
         assert_eq!(result, Command::Clientlist);
+
+        assert_eq!(
+            protocol.parse("client compress on").unwrap(),
+            Command::ClientCompress { enabled: true }
+        );
+        assert_eq!(
+            protocol.parse("CLIENT COMPRESS OFF").unwrap(),
+            Command::ClientCompress { enabled: false }
+        );
+        assert!(protocol.parse("CLIENT COMPRESS").is_err());
+        assert!(protocol.parse("CLIENT COMPRESS maybe").is_err());
+        assert_eq!(protocol.parse("CLIENT TOKENS ON").unwrap(), Command::ClientTokens { enabled: true });
         
         // Test CLIENT with unknown subcommand (should error)
         assert!(protocol.parse("CLIENT UNKNOWN").is_err());
@@ -1084,13 +2590,15 @@ mod tests {
         let protocol = Protocol::new();
         let result = protocol.parse("INFO").unwrap();
         assert_eq!(result, Command::Info);
+        assert_eq!(protocol.parse("INFO json").unwrap(), Command::InfoJson);
+        assert_eq!(protocol.parse("INFO server").unwrap(), Command::Info);
     }
     
     #[test]
-    fn test_parse_ping() {
+    fn test_parse_ping_without_message() {
         let protocol = Protocol::new();
         let result = protocol.parse("PING").unwrap();
-        assert_eq!(result, Command::Ping);
+        assert_eq!(result, Command::Ping { message: String::new() });
     }
     
     #[test]
@@ -1099,6 +2607,12 @@ mod tests {
         let result = protocol.parse("VERSION").unwrap();
         assert_eq!(result, Command::Version);
     }
+
+    #[test]
+    fn test_parse_uptime() {
+        let protocol = Protocol::new();
+        assert_eq!(protocol.parse("uptime").unwrap(), Command::Uptime);
+    }
     
     #[test]
     fn test_parse_flushdb() {
@@ -1152,4 +2666,363 @@ mod tests {
         assert!(protocol.parse("GET\tkey").is_err()); // Tab character
         assert!(protocol.parse("GET\nkey").is_err()); // Newline character
     }
+
+    fn parse_error(input: &str) -> ParseError {
+        Protocol::new().parse(input).unwrap_err().downcast::<ParseError>().unwrap()
+    }
+
+    #[test]
+    fn test_parse_errors_name_failing_token() {
+        let err = parse_error("SET key");
+        assert_eq!((err.token, err.byte), (3, 7));
+        assert_eq!(err.to_string(), "ERR_PARSE SET command requires a key and value at token 3 (byte 7)");
+
+        let err = parse_error("GET a b");
+        assert_eq!((err.token, err.byte), (3, 6));
+        assert!(err.to_string().contains("accepts only one argument at token 3"));
+
+        let err = parse_error("INC counter abc");
+        assert_eq!((err.token, err.byte), (3, 12));
+
+        let err = parse_error("FROB x");
+        assert_eq!((err.token, err.byte), (1, 0));
+        assert!(err.message.starts_with("Unknown command"));
+
+        let err = parse_error("SYNC host 1 --verify --fast");
+        assert_eq!(err.token, 5);
+        assert_eq!(err.message, "Unknown option: --fast");
+
+        assert_eq!(Protocol::new().parse("sync status").unwrap(), Command::SyncStatus);
+        assert_eq!(Protocol::new().parse("SYNC ABORT").unwrap(), Command::SyncAbort);
+
+        assert_eq!(
+            Protocol::new().parse("verify consistent [::1]:7380").unwrap(),
+            Command::VerifyConsistent { peer: "[::1]:7380".to_string() }
+        );
+        assert_eq!(parse_error("VERIFY CONSISTENT node2").token, 3);
+        assert_eq!(parse_error("VERIFY CONSISTENT a:1 b:2").token, 4);
+        assert_eq!(parse_error("VERIFY EVERYTHING").token, 2);
+
+        assert_eq!(Protocol::new().parse("debug sleep 250").unwrap(), Command::DebugSleep { ms: 250 });
+        assert_eq!(parse_error("DEBUG SLEEP soon").message, "DEBUG SLEEP milliseconds must be a non-negative integer");
+        assert_eq!(parse_error("DEBUG SLEEP 1 2").message, "Usage: DEBUG SLEEP <ms>");
+        assert_eq!(parse_error("DEBUG PANIC").token, 2);
+        assert!(Command::DebugSleep { ms: 1 }.abortable());
+
+        assert_eq!(Protocol::new().parse("export json").unwrap(), Command::ExportJson);
+        assert_eq!(parse_error("EXPORT CSV").message, "Unknown EXPORT format: CSV (expected JSON)");
+        assert_eq!(parse_error("EXPORT").message, "EXPORT command requires arguments");
+        assert_eq!(Protocol::new().parse("cluster peers").unwrap(), Command::ClusterPeers);
+        let eval = Protocol::new().parse("EVAL GET a; SET b 1").unwrap();
+        assert_eq!(eval.plan(), Plan { ops: vec!["read:a".to_string(), "read:b".to_string(), "write:b".to_string()], replicates: true });
+        assert!(!Protocol::new().parse("EVAL GET a").unwrap().plan().replicates);
+        assert_eq!(parse_error("EVAL").message, "EVAL command requires arguments");
+        assert_eq!(parse_error("EVAL GET a; PING").message, "EVAL op 2: unsupported op PING (expected GET, SET, DEL, INCR or CAS)");
+        assert_eq!(parse_error("CLUSTER").message, "CLUSTER command requires arguments");
+        assert!(parse_error("CLUSTER NODES").message.starts_with("Unknown CLUSTER subcommand: NODES"));
+        assert!(Protocol::new().parse("GET k").unwrap().abortable());
+        assert!(!Protocol::new().parse("SET k v").unwrap().abortable());
+        assert!(!Protocol::new().parse("SCAN k").unwrap().abortable());
+
+        let err = parse_error("SYNC host notaport");
+        assert_eq!((err.token, err.byte), (3, 10));
+
+        let err = parse_error("HGET user:1 name extra");
+        assert_eq!(err.token, 4);
+
+        let err = parse_error("GET\tkey");
+        assert_eq!((err.token, err.byte), (2, 3));
+
+        assert_eq!(parse_error("").token, 1);
+    }
+
+    #[test]
+    fn test_parse_replication_pause_resume() {
+        let protocol = Protocol::new();
+        assert_eq!(
+            protocol.parse("REPLICATION PAUSE").unwrap(),
+            Command::Replicate { action: ReplicateAction::Pause }
+        );
+        assert_eq!(
+            protocol.parse("replication resume").unwrap(),
+            Command::Replicate { action: ReplicateAction::Resume }
+        );
+        assert_eq!(
+            protocol.parse("REPLICATE pause").unwrap(),
+            Command::Replicate { action: ReplicateAction::Pause }
+        );
+        assert!(protocol.parse("REPLICATION").is_err());
+        assert!(protocol.parse("REPLICATION STOP").is_err());
+        assert_eq!(
+            protocol.parse("replication dedup stats").unwrap(),
+            Command::Replicate { action: ReplicateAction::DedupStats }
+        );
+        assert_eq!(
+            protocol.parse("REPLICATION DEDUP CLEAR").unwrap(),
+            Command::Replicate { action: ReplicateAction::DedupClear }
+        );
+        assert!(protocol.parse("REPLICATION DEDUP").is_err());
+        assert_eq!(
+            protocol.parse("REPLICATION CHECK CAUSALITY").unwrap(),
+            Command::Replicate { action: ReplicateAction::CheckCausality }
+        );
+    }
+
+    #[test]
+    fn test_parse_auth() {
+        let protocol = Protocol::new();
+        assert_eq!(
+            protocol.parse("AUTH s3cret pass").unwrap(),
+            Command::Auth { password: "s3cret pass".to_string() }
+        );
+        assert!(protocol.parse("AUTH").is_err());
+    }
+
+    #[test]
+    fn test_parse_config() {
+        let protocol = Protocol::new();
+        assert_eq!(
+            protocol.parse("CONFIG GET replication.client_id").unwrap(),
+            Command::ConfigGet { param: "replication.client_id".to_string() }
+        );
+        assert_eq!(
+            protocol.parse("config set log_level debug").unwrap(),
+            Command::ConfigSet { param: "log_level".to_string(), value: "debug".to_string() }
+        );
+        assert!(protocol.parse("CONFIG").is_err());
+        assert!(protocol.parse("CONFIG GET").is_err());
+        assert!(protocol.parse("CONFIG SET log_level").is_err());
+        assert!(protocol.parse("CONFIG RESET x").is_err());
+        assert_eq!(protocol.parse("config reload tls").unwrap(), Command::ConfigReloadTls);
+        assert_eq!(parse_error("CONFIG RELOAD CERTS").message, "Usage: CONFIG RELOAD TLS");
+
+        assert_eq!(
+            protocol.parse("log level replication DEBUG").unwrap(),
+            Command::LogLevel { module: "replication".to_string(), level: Some(log::LevelFilter::Debug) }
+        );
+        assert_eq!(
+            protocol.parse("LOG LEVEL sync default").unwrap(),
+            Command::LogLevel { module: "sync".to_string(), level: None }
+        );
+        assert_eq!(parse_error("LOG LEVEL sync loud").token, 4);
+        assert!(protocol.parse("LOG LEVEL sync").is_err());
+        assert!(protocol.parse("LOG").is_err());
+    }
+
+    #[test]
+    fn test_parse_subscribe() {
+        let protocol = Protocol::new();
+        assert_eq!(
+            protocol.parse("SUBSCRIBE merkle").unwrap(),
+            Command::Subscribe { channel: SubscribeChannel::Merkle, resume: None }
+        );
+        assert_eq!(
+            protocol.parse("SUBSCRIBE MERKLE 4f2a 17").unwrap(),
+            Command::Subscribe { channel: SubscribeChannel::Merkle, resume: Some(("4f2a".to_string(), 17)) }
+        );
+        assert!(protocol.parse("SUBSCRIBE MERKLE 4f2a").is_err());
+        assert!(protocol.parse("SUBSCRIBE MERKLE 4f2a x").is_err());
+        assert_eq!(protocol.parse("unsubscribe").unwrap(), Command::Unsubscribe);
+        assert_eq!(protocol.parse("TOMBSTONES").unwrap(), Command::Tombstones);
+        assert!(protocol.parse("SUBSCRIBE").is_err());
+        assert!(protocol.parse("SUBSCRIBE keys").is_err());
+    }
+
+    #[test]
+    fn test_parse_merkle() {
+        let protocol = Protocol::new();
+        assert_eq!(
+            protocol.parse("MERKLE VERIFY").unwrap(),
+            Command::Merkle { action: MerkleAction::Verify }
+        );
+        assert_eq!(
+            protocol.parse("merkle rebuild").unwrap(),
+            Command::Merkle { action: MerkleAction::Rebuild }
+        );
+        assert_eq!(
+            protocol.parse("MERKLE FETCH 0110").unwrap(),
+            Command::Merkle { action: MerkleAction::Fetch { path: "0110".to_string() } }
+        );
+        assert_eq!(
+            protocol.parse("merkle fetch").unwrap(),
+            Command::Merkle { action: MerkleAction::Fetch { path: String::new() } }
+        );
+        assert_eq!(protocol.parse("merkle gc").unwrap(), Command::Merkle { action: MerkleAction::Gc });
+        assert_eq!(parse_error("MERKLE FETCH 01x").token, 3);
+        assert!(protocol.parse("MERKLE FETCH 0 1").is_err());
+        assert!(protocol.parse("MERKLE").is_err());
+        assert!(protocol.parse("MERKLE FIX").is_err());
+    }
+
+    #[test]
+    fn test_parse_rangehash() {
+        let protocol = Protocol::new();
+        assert_eq!(
+            protocol.parse("rangehash user:a user:z").unwrap(),
+            Command::RangeHash { start: "user:a".to_string(), end: "user:z".to_string() }
+        );
+        assert_eq!(protocol.parse("cancel").unwrap(), Command::Cancel);
+        assert_eq!(protocol.parse("TOUCH a b").unwrap(), Command::Touch { keys: vec!["a".to_string(), "b".to_string()] });
+        assert_eq!(protocol.parse("warm a b").unwrap(), Command::Warm { keys: vec!["a".to_string(), "b".to_string()] });
+        assert!(protocol.parse("WARM").is_err());
+        assert_eq!(
+            protocol.parse("object accessed a").unwrap(),
+            Command::Object { field: ObjectField::Accessed, key: "a".to_string() }
+        );
+        assert_eq!(
+            protocol.parse("OBJECT REPLINFO a").unwrap(),
+            Command::Object { field: ObjectField::ReplInfo, key: "a".to_string() }
+        );
+        assert_eq!(
+            protocol.parse("object acks a").unwrap(),
+            Command::Object { field: ObjectField::Acks, key: "a".to_string() }
+        );
+        assert_eq!(
+            protocol.parse("OBJECT durable a").unwrap(),
+            Command::Object { field: ObjectField::Durable, key: "a".to_string() }
+        );
+        assert_eq!(parse_error("RANGEHASH a").message, "Usage: RANGEHASH <start_key> <end_key>");
+        assert_eq!(parse_error("RANGEHASH z a").message, "RANGEHASH end_key must not sort before start_key");
+        assert!(protocol.parse("RANGEHASH").is_err());
+    }
+
+    #[test]
+    fn test_parse_bigkeys() {
+        let protocol = Protocol::new();
+        assert_eq!(protocol.parse("BIGKEYS").unwrap(), Command::BigKeys { count: None });
+        assert_eq!(protocol.parse("bigkeys 5").unwrap(), Command::BigKeys { count: Some(5) });
+        assert_eq!(parse_error("BIGKEYS 0").message, "BIGKEYS count must be a positive integer");
+        assert_eq!(parse_error("BIGKEYS 5 6").message, "Usage: BIGKEYS [count]");
+    }
+
+    #[test]
+    fn test_parse_memory_histogram() {
+        let protocol = Protocol::new();
+        assert_eq!(protocol.parse("MEMORY").unwrap(), Command::Memory);
+        assert_eq!(
+            protocol.parse("MEMORY HISTOGRAM").unwrap(),
+            Command::MemoryHistogram { samples: None }
+        );
+        assert_eq!(
+            protocol.parse("memory histogram 500").unwrap(),
+            Command::MemoryHistogram { samples: Some(500) }
+        );
+        assert!(protocol.parse("MEMORY HISTOGRAM 0").is_err());
+        assert!(protocol.parse("MEMORY HISTOGRAM abc").is_err());
+        assert!(protocol.parse("MEMORY HISTOGRAM 5 6").is_err());
+
+        assert_eq!(protocol.parse("memory compact").unwrap(), Command::MemoryCompact);
+        assert_eq!(protocol.parse("STORAGE STATS").unwrap(), Command::StorageStats);
+        assert_eq!(protocol.parse("storage compact").unwrap(), Command::StorageCompact);
+        assert!(protocol.parse("STORAGE").is_err());
+        assert!(protocol.parse("STORAGE STATS now").is_err());
+        assert_eq!(protocol.parse("shard stats").unwrap(), Command::ShardStats);
+        assert!(protocol.parse("SHARD").is_err());
+        assert!(protocol.parse("SHARD KEYS").is_err());
+        assert!(protocol.parse("SHARD STATS 0").is_err());
+        assert!(protocol.parse("MEMORY COMPACT now").is_err());
+        assert!(protocol.parse("MEMORY USAGE").is_err());
+
+        assert_eq!(protocol.parse("PERSISTENCE OFF").unwrap(), Command::Persistence { enabled: false });
+        assert_eq!(protocol.parse("persistence on").unwrap(), Command::Persistence { enabled: true });
+        assert_eq!(parse_error("PERSISTENCE maybe").token, 2);
+        assert_eq!(parse_error("PERSISTENCE ON now").token, 3);
+        assert!(protocol.parse("PERSISTENCE").is_err());
+    }
+
+    #[test]
+    fn test_parse_set_with_expiry() {
+        let protocol = Protocol::new();
+        assert_eq!(
+            protocol.parse("SET session abc EX 30").unwrap(),
+            Command::SetEx { key: "session".to_string(), value: "abc".to_string(), ttl_ms: 30_000 }
+        );
+        assert_eq!(
+            protocol.parse("SET k hello PX 250").unwrap(),
+            Command::SetEx { key: "k".to_string(), value: "hello".to_string(), ttl_ms: 250 }
+        );
+        // Not a valid option → part of the value
+        assert_eq!(
+            protocol.parse("SET k value EX soon").unwrap(),
+            Command::Set { key: "k".to_string(), value: "value EX soon".to_string() }
+        );
+        assert_eq!(
+            protocol.parse("SET k EX 5").unwrap(),
+            Command::Set { key: "k".to_string(), value: "EX 5".to_string() }
+        );
+        assert!(protocol.parse("SET k v EX 0").is_err());
+    }
+
+    #[test]
+    fn test_parse_ttl_commands() {
+        let protocol = Protocol::new();
+        assert_eq!(
+            protocol.parse("EXPIRE session 30").unwrap(),
+            Command::Expire { key: "session".to_string(), seconds: 30, condition: None }
+        );
+        assert_eq!(
+            protocol.parse("EXPIRE session 30 gt").unwrap(),
+            Command::Expire { key: "session".to_string(), seconds: 30, condition: Some(ExpireCondition::Gt) }
+        );
+        assert!(protocol.parse("EXPIRE session 30 GE").is_err());
+        assert!(protocol.parse("EXPIRE session 30 NX XX").is_err());
+        assert_eq!(protocol.parse("persist session").unwrap(), Command::Persist { key: "session".to_string() });
+        assert_eq!(protocol.parse("TTL session").unwrap(), Command::Ttl { key: "session".to_string() });
+        assert!(protocol.parse("EXPIRE session").is_err());
+        assert!(protocol.parse("EXPIRE session -1").is_err());
+        assert!(protocol.parse("TTL a b").is_err());
+        assert_eq!(
+            protocol.parse("object created session").unwrap(),
+            Command::Object { field: ObjectField::Created, key: "session".to_string() }
+        );
+        assert_eq!(
+            protocol.parse("OBJECT IDLETIME session").unwrap(),
+            Command::Object { field: ObjectField::IdleTime, key: "session".to_string() }
+        );
+        assert_eq!(parse_error("OBJECT ENCODING session").token, 2);
+        assert!(protocol.parse("OBJECT MODIFIED").is_err());
+        assert!(protocol.parse("OBJECT MODIFIED a b").is_err());
+        assert!(protocol.parse("OBJECT").is_err());
+        assert!(protocol.parse("PERSIST").is_err());
+    }
+
+    #[test]
+    fn test_parse_json_incr() {
+        let protocol = Protocol::new();
+        assert_eq!(
+            protocol.parse("JSON.INCR doc /a/b -2.5").unwrap(),
+            Command::JsonIncr { key: "doc".to_string(), path: "/a/b".to_string(), amount: "-2.5".parse().unwrap() }
+        );
+        assert_eq!(parse_error("JSON.INCR doc /a ten").message, "JSON.INCR amount must be a number");
+        assert_eq!(parse_error("JSON.INCR doc /a").message, "Usage: JSON.INCR <key> <path> <amount>");
+        assert!(protocol.parse("JSON.INCR").is_err());
+    }
+
+    #[test]
+    fn test_parse_field_map_commands() {
+        let protocol = Protocol::new();
+        assert_eq!(
+            protocol.parse("HSET user:1 name ann age 31").unwrap(),
+            Command::HSet {
+                key: "user:1".to_string(),
+                pairs: vec![
+                    ("name".to_string(), "ann".to_string()),
+                    ("age".to_string(), "31".to_string())
+                ],
+            }
+        );
+        assert_eq!(
+            protocol.parse("HGET user:1 name").unwrap(),
+            Command::HGet { key: "user:1".to_string(), field: "name".to_string() }
+        );
+        assert_eq!(
+            protocol.parse("HGETALL user:1").unwrap(),
+            Command::HGetAll { key: "user:1".to_string() }
+        );
+        assert!(protocol.parse("HSET").is_err());
+        assert!(protocol.parse("HSET user:1 name").is_err());
+        assert!(protocol.parse("HSET user:1 name ann age").is_err());
+        assert!(protocol.parse("HGET user:1").is_err());
+        assert!(protocol.parse("HGETALL a b").is_err());
+    }
 }
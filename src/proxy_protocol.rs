@@ -0,0 +1,113 @@
+//! # PROXY Protocol (v1)
+//!
+//! When MerkleKV sits behind a TCP load balancer (HAProxy, AWS NLB, ...), the
+//! socket peer address is the balancer, not the client. With
+//! `server.proxy_protocol = true` every connection must start with a PROXY v1
+//! header, e.g.
+//!
+//! ```text
+//! PROXY TCP4 203.0.113.7 10.0.0.1 56324 7379\r\n
+//! ```
+//!
+//! and the source address from the header is used as the client address for
+//! the IP allowlist, logging and `CLIENT LIST`.
+//!
+//! Only the human-readable v1 format is supported. `PROXY UNKNOWN` is accepted
+//! and falls back to the socket peer address, as the spec requires.
+
+use anyhow::{anyhow, Result};
+use std::net::{IpAddr, SocketAddr};
+use tokio::io::{AsyncRead, AsyncReadExt};
+
+/// Maximum length of a v1 header including the trailing CRLF (from the spec).
+pub const MAX_V1_HEADER_LEN: usize = 107;
+
+/// Read a PROXY v1 header from the start of a stream.
+///
+/// Bytes are consumed one at a time so nothing past the header is buffered
+/// away from the command reader.
+///
+/// # Returns
+/// * `Ok(Some(addr))` - The real client address
+/// * `Ok(None)` - `PROXY UNKNOWN`; the caller should use the socket peer address
+/// * `Err` - Missing, oversized or malformed header
+pub async fn read_v1_header<R: AsyncRead + Unpin>(stream: &mut R) -> Result<Option<SocketAddr>> {
+    let mut line = Vec::with_capacity(MAX_V1_HEADER_LEN);
+    loop {
+        let byte = stream.read_u8().await?;
+        line.push(byte);
+        if byte == b'\n' {
+            break;
+        }
+        if line.len() >= MAX_V1_HEADER_LEN {
+            return Err(anyhow!("PROXY header exceeds {} bytes", MAX_V1_HEADER_LEN));
+        }
+    }
+    let line = std::str::from_utf8(&line).map_err(|_| anyhow!("PROXY header is not valid UTF-8"))?;
+    parse_v1_header(line)
+}
+
+/// Parse a single PROXY v1 header line (with or without the trailing CRLF).
+pub fn parse_v1_header(line: &str) -> Result<Option<SocketAddr>> {
+    let line = line.trim_end_matches(['\r', '\n']);
+    let parts: Vec<&str> = line.split(' ').collect();
+    if parts.first() != Some(&"PROXY") || parts.len() < 2 {
+        return Err(anyhow!("Missing PROXY protocol header"));
+    }
+    match parts[1] {
+        "UNKNOWN" => Ok(None),
+        proto @ ("TCP4" | "TCP6") => {
+            if parts.len() != 6 {
+                return Err(anyhow!("Malformed PROXY header: expected 6 fields"));
+            }
+            let src_ip: IpAddr = parts[2]
+                .parse()
+                .map_err(|_| anyhow!("Malformed PROXY header: bad source address"))?;
+            if (proto == "TCP4") != src_ip.is_ipv4() {
+                return Err(anyhow!("Malformed PROXY header: address family mismatch"));
+            }
+            let src_port: u16 = parts[4]
+                .parse()
+                .map_err(|_| anyhow!("Malformed PROXY header: bad source port"))?;
+            Ok(Some(SocketAddr::new(src_ip, src_port)))
+        }
+        other => Err(anyhow!("Unsupported PROXY protocol family '{}'", other)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_tcp4_and_tcp6() {
+        let addr = parse_v1_header("PROXY TCP4 203.0.113.7 10.0.0.1 56324 7379\r\n").unwrap();
+        assert_eq!(addr, Some("203.0.113.7:56324".parse().unwrap()));
+        let addr = parse_v1_header("PROXY TCP6 2001:db8::1 2001:db8::2 4000 7379\r\n").unwrap();
+        assert_eq!(addr, Some("[2001:db8::1]:4000".parse().unwrap()));
+    }
+
+    #[test]
+    fn test_parse_unknown_and_invalid() {
+        assert_eq!(parse_v1_header("PROXY UNKNOWN\r\n").unwrap(), None);
+        assert!(parse_v1_header("GET foo\r\n").is_err());
+        assert!(parse_v1_header("PROXY TCP4 2001:db8::1 10.0.0.1 1 2\r\n").is_err());
+        assert!(parse_v1_header("PROXY TCP4 1.2.3.4 10.0.0.1 99999 2\r\n").is_err());
+        assert!(parse_v1_header("PROXY UDP4 1.2.3.4 10.0.0.1 1 2\r\n").is_err());
+    }
+
+    #[tokio::test]
+    async fn test_read_header_leaves_commands_unread() {
+        let mut input: &[u8] = b"PROXY TCP4 198.51.100.9 10.0.0.1 1234 7379\r\nGET key\r\n";
+        let addr = read_v1_header(&mut input).await.unwrap();
+        assert_eq!(addr, Some("198.51.100.9:1234".parse().unwrap()));
+        assert_eq!(input, b"GET key\r\n");
+    }
+
+    #[tokio::test]
+    async fn test_read_header_rejects_oversized() {
+        let long = format!("PROXY {}\r\n", "x".repeat(200));
+        let mut input: &[u8] = long.as_bytes();
+        assert!(read_v1_header(&mut input).await.is_err());
+    }
+}
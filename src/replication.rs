@@ -13,6 +13,38 @@
 //! 3. **Remote Application**: Other nodes receive the message and apply the
 //!    same operation to their local storage
 //! 4. **Loop Prevention**: Nodes ignore messages from themselves
+//! 5. **Key Filtering**: Keys outside `replication.include_prefixes` or inside
+//!    `replication.exclude_prefixes` are neither published nor applied
+//! 6. **Pausing**: `REPLICATION PAUSE` queues outgoing changes (bounded by
+//!    `replication.pause_queue_limit`, oldest dropped first) while local writes
+//!    keep applying; `REPLICATION RESUME` publishes the queue in order. Disabling
+//!    replication while paused discards the queue.
+//! 7. **De-duplication**: the ids of applied events are remembered (up to
+//!    `replication.dedup_capacity`, oldest forgotten first) so redelivered
+//!    events are skipped. `REPLICATION DEDUP STATS` reports the cache and
+//!    `REPLICATION DEDUP CLEAR` empties it. The events that pass are logged
+//!    for `REPLICATION CHECK CAUSALITY` (see `causality`).
+//! 8. **Deltas**: with `replication.delta_min_bytes` set, large values are
+//!    published as deltas against the previous version; a peer missing that
+//!    version asks the origin to resend the full value (see `delta`).
+//! 9. **Acks**: `SET ... CL=quorum|all` publishes its event with `ack` set;
+//!    each receiver answers with an `Ack` event once it has applied it, and the
+//!    origin holds the client's reply until enough acks arrive (or
+//!    `replication.ack_timeout_ms` passes). With `replication.ack_all_writes`
+//!    every value write asks for acks, without waiting for them. The origin
+//!    keeps the peers that confirmed each key's latest acked write of
+//!    database 0 for `OBJECT ACKS`; a later write without acks clears them.
+//! 10. **Databases**: by default only database 0 replicates, on
+//!     `{topic_prefix}/events`. With `replication.topic_per_database`, the
+//!     writes of database N go to `{topic_prefix}/db{N}/events` and peers
+//!     apply them to their database N, so a subscriber can follow one database.
+//! 11. **Apply queue**: received events wait for the apply loop in a queue of
+//!     `replication.apply_queue_size`. It does not push back on the broker:
+//!     the MQTT connection also carries this node's own publishes, acks and
+//!     keepalives, which must not stall behind a slow apply. When the queue
+//!     overflows (a catch-up burst after a reconnect) the oldest events are
+//!     dropped and counted in `INFO` `replication_apply_dropped`; anti-entropy
+//!     sync repairs the writes they carried.
 //! 
 //! ## Message Format
 //! 
@@ -37,17 +69,177 @@
 //! - Proper error handling and retry logic
 //! - Conflict resolution for concurrent writes
 
-use anyhow::Result;
-use log::{error, warn};
-use rumqttc::{AsyncClient, Event, Incoming, MqttOptions, QoS};
-use std::collections::{HashMap, HashSet};
-use std::time::{Duration, SystemTime, UNIX_EPOCH};
-use tokio::sync::{broadcast, Mutex};
+use anyhow::{anyhow, Result};
+use log::{debug, error, warn};
+use rumqttc::{AsyncClient, Event, Incoming, MqttOptions, QoS, TlsConfiguration, Transport};
+use std::collections::{BTreeSet, HashMap, HashSet, VecDeque};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+use tokio::sync::{broadcast, Mutex, Notify};
 use std::sync::Arc;
 
+use crate::causality::{CausalityLog, Report};
 use crate::config::Config;
+use crate::consistency::ConsistencyTracker;
+use crate::databases::{Databases, Db};
+use crate::delta::{self, BaseCache};
+use crate::key_filter::KeyFilter;
+use crate::store::expiring::now_ms;
 use crate::store::KVEngineStoreTrait;
-use crate::change_event::{ChangeCodec, ChangeEvent, OpKind};
+use crate::change_event::{ChangeCodec, ChangeEvent, EventGroup, OpKind, SCHEMA_VERSION};
+
+/// Changes held back while replication is paused.
+#[derive(Default)]
+struct PauseQueue {
+    paused: bool,
+    events: VecDeque<ChangeEvent>,
+    /// Changes dropped because the queue was full
+    dropped: u64,
+}
+
+/// Snapshot of the pause state, for INFO and `REPLICATE status`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PauseStatus {
+    pub paused: bool,
+    pub queued: usize,
+    pub dropped: u64,
+}
+
+/// Received events waiting for the apply loop, for INFO.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ApplyQueueStatus {
+    pub queued: usize,
+    /// Events dropped because the queue overflowed
+    pub dropped: u64,
+}
+
+/// Ids of recently applied events, oldest evicted first.
+#[derive(Default)]
+struct DedupCache {
+    ids: HashSet<[u8; 16]>,
+    order: VecDeque<[u8; 16]>,
+    capacity: usize,
+    lookups: u64,
+    hits: u64,
+}
+
+impl DedupCache {
+    fn with_capacity(capacity: usize) -> Self {
+        Self { capacity: capacity.max(1), ..Self::default() }
+    }
+
+    /// Whether `id` was applied before; counted in the hit rate.
+    fn seen(&mut self, id: &[u8; 16]) -> bool {
+        self.lookups += 1;
+        let hit = self.ids.contains(id);
+        if hit {
+            self.hits += 1;
+        }
+        hit
+    }
+
+    fn insert(&mut self, id: [u8; 16]) {
+        if !self.ids.insert(id) {
+            return;
+        }
+        self.order.push_back(id);
+        while self.order.len() > self.capacity {
+            if let Some(old) = self.order.pop_front() {
+                self.ids.remove(&old);
+            }
+        }
+    }
+}
+
+/// Peers that confirmed each local event published with `ack` set.
+#[derive(Default)]
+struct AckTracker {
+    /// Event timestamp → nodes that applied it
+    pending: std::sync::Mutex<HashMap<u64, HashSet<String>>>,
+    arrived: Notify,
+    /// Key → timestamp of its latest acked write and the nodes that applied it
+    latest: std::sync::Mutex<HashMap<String, (u64, BTreeSet<String>)>>,
+}
+
+impl AckTracker {
+    fn pending(&self) -> std::sync::MutexGuard<'_, HashMap<u64, HashSet<String>>> {
+        self.pending.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn latest(&self) -> std::sync::MutexGuard<'_, HashMap<String, (u64, BTreeSet<String>)>> {
+        self.latest.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Make the write to `key` stamped `ts` the one `OBJECT ACKS` reports.
+    fn track(&self, key: &str, ts: u64) {
+        self.latest().insert(key.to_string(), (ts, BTreeSet::new()));
+    }
+
+    /// `key`'s latest write was published without acks.
+    fn forget(&self, key: &str) {
+        self.latest().remove(key);
+    }
+
+    /// Nodes that applied `key`'s latest acked write.
+    fn confirmed(&self, key: &str) -> Option<Vec<String>> {
+        self.latest().get(key).map(|(_, peers)| peers.iter().cloned().collect())
+    }
+
+    /// Start collecting acks for the event stamped `ts`.
+    fn expect(&self, ts: u64) {
+        self.pending().insert(ts, HashSet::new());
+    }
+
+    /// Record an ack of the write to `key` stamped `ts`; acks for events
+    /// nobody waits for (any more) only count for `OBJECT ACKS`.
+    fn record(&self, key: &str, ts: u64, peer: &str) {
+        if let Some((_, peers)) = self.latest().get_mut(key).filter(|(latest_ts, _)| *latest_ts == ts) {
+            peers.insert(peer.to_string());
+        }
+        if let Some(peers) = self.pending().get_mut(&ts) {
+            peers.insert(peer.to_string());
+            self.arrived.notify_waiters();
+        }
+    }
+
+    fn count(&self, ts: u64) -> usize {
+        self.pending().get(&ts).map_or(0, HashSet::len)
+    }
+
+    /// Wait up to `timeout` for `needed` acks of `ts`, then stop collecting.
+    ///
+    /// # Returns
+    /// * `usize` - Acks that arrived (at least `needed` unless the deadline passed)
+    async fn wait(&self, ts: u64, needed: usize, timeout: Duration) -> usize {
+        let deadline = tokio::time::Instant::now() + timeout;
+        loop {
+            // Register before checking so an ack in between is not missed
+            let arrived = self.arrived.notified();
+            tokio::pin!(arrived);
+            arrived.as_mut().enable();
+            if self.count(ts) >= needed || tokio::time::timeout_at(deadline, arrived).await.is_err() {
+                break;
+            }
+        }
+        self.pending().remove(&ts).map_or(0, |peers| peers.len())
+    }
+}
+
+/// Snapshot of the de-dup cache, for `REPLICATION DEDUP STATS`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DedupStats {
+    pub size: usize,
+    pub capacity: usize,
+    pub lookups: u64,
+    pub hits: u64,
+}
+
+impl DedupStats {
+    /// Share of lookups that found a duplicate (0 before any lookup).
+    pub fn hit_rate(&self) -> f64 {
+        if self.lookups == 0 { 0.0 } else { self.hits as f64 / self.lookups as f64 }
+    }
+}
 
 /// Handles MQTT-based replication of write operations.
 /// 
@@ -60,6 +252,15 @@ pub struct Replicator {
     
     /// Prefix for MQTT topics (e.g., "merkle_kv")
     topic_prefix: String,
+
+    /// One topic per database (`replication.topic_per_database`)
+    topic_per_database: bool,
+
+    /// Database whose writes this handle publishes (see `for_database`)
+    db: usize,
+
+    /// Databases 1.. that remote events are applied to (see `with_databases`)
+    databases: Option<Arc<Databases>>,
     
     /// Unique identifier for this node
     node_id: String,
@@ -67,8 +268,120 @@ pub struct Replicator {
     /// Preferred codec for on-wire messages
     codec: ChangeCodec,
 
-    /// Channel carrying decoded ChangeEvents from the MQTT eventloop
-    tx: broadcast::Sender<ChangeEvent>,
+    /// Channel carrying decoded ChangeEvents, with their database, from the MQTT eventloop
+    tx: broadcast::Sender<(usize, ChangeEvent)>,
+
+    /// Events the apply loop lost to an overflowing `tx`
+    apply_dropped: Arc<AtomicU64>,
+
+    /// Keys that replicate; node-local keys are dropped on publish and apply
+    filter: KeyFilter,
+
+    /// Outgoing changes queued by `pause` (shared by clones)
+    pause: Arc<std::sync::Mutex<PauseQueue>>,
+
+    /// Capacity of the pause queue
+    pause_queue_limit: usize,
+
+    /// Stamps published events and records applied ones (read-your-writes tokens)
+    clock: Arc<ConsistencyTracker>,
+
+    /// Ids of applied events (shared by clones and the apply loop)
+    dedup: Arc<std::sync::Mutex<DedupCache>>,
+
+    /// Applied events in order, for `check_causality`
+    causality: Arc<std::sync::Mutex<CausalityLog>>,
+
+    /// Values at least this large are published as deltas (0 = never)
+    delta_min_bytes: usize,
+
+    /// Last published large values, the bases deltas are made against
+    bases: Arc<std::sync::Mutex<BaseCache>>,
+
+    /// Peer acks of writes waiting on them (shared by clones and the apply loop)
+    acks: Arc<AckTracker>,
+
+    /// Ask for acks of every value write, not only `CL=quorum|all` ones
+    ack_all_writes: bool,
+}
+
+/// MQTT options of this node: broker address from the config, identity and
+/// credentials from the environment first. `client_id_suffix` gives a second
+/// connection (`--self-test`) its own session.
+fn broker_options(config: &Config, client_id_suffix: &str) -> Result<MqttOptions> {
+    // -----------------------------------------------------------------------------
+    // Design Note (Security & Operability)
+    // This block implements environment-first resolution for identity (Client ID)
+    // and secret (password). The objective is twofold: (i) enable secure injection
+    // of credentials via deployment tooling, and (ii) preserve configuration
+    // determinism when environment variables are absent. The approach deliberately
+    // avoids widening the configuration module's responsibilities.
+    // -----------------------------------------------------------------------------
+
+    // Environment-first resolution of identity and credentials
+    // Client ID: env var CLIENT_ID overrides config
+    let effective_client_id = std::env::var("CLIENT_ID")
+        .ok()
+        .filter(|s| !s.is_empty())
+        .unwrap_or_else(|| config.replication.client_id.clone());
+
+    // Password: env var CLIENT_PASSWORD overrides config.replication.client_password
+    let effective_password = std::env::var("CLIENT_PASSWORD")
+        .ok()
+        .filter(|s| !s.is_empty())
+        .or_else(|| config.replication.client_password.clone());
+
+    // Configure MQTT client options
+    let mut mqtt_options = MqttOptions::new(
+        format!("{}{}", effective_client_id, client_id_suffix),
+        &config.replication.mqtt_broker,
+        config.replication.mqtt_port,
+    );
+    mqtt_options.set_keep_alive(Duration::from_secs(30));
+
+    // -----------------------------------------------------------------------------
+    // Rationale (Compatibility)
+    // We re-use the effective Client ID as the MQTT username when a password is
+    // provided. This conservative choice avoids a schema expansion and sustains
+    // backwards compatibility. If a distinct username becomes necessary, it can be
+    // added later without perturbing the present call sites.
+    // -----------------------------------------------------------------------------
+
+    // Some brokers accept "username + password". In the absence of a dedicated
+    // username field in configuration, we conservatively re-use the Client ID
+    // as the username. This preserves the existing configuration surface.
+    // If a future username field is introduced, it can replace this parameter.
+    if let Some(pw) = effective_password {
+        mqtt_options.set_credentials(effective_client_id.clone(), pw);
+    }
+    if let Some(ca_path) = &config.replication.mqtt_ca_path {
+        mqtt_options.set_transport(tls_transport(ca_path)?);
+    }
+    Ok(mqtt_options)
+}
+
+/// TLS transport trusting the CA certificates in `ca_path`.
+fn tls_transport(ca_path: &str) -> Result<Transport> {
+    let tls = crate::tls::client_config(ca_path)?;
+    Ok(Transport::tls_with_config(TlsConfiguration::Rustls(tls)))
+}
+
+/// Connect to the broker once and wait for its CONNACK (`--self-test`).
+pub async fn probe_broker(config: &Config, timeout: Duration) -> Result<()> {
+    let (_client, mut eventloop) = AsyncClient::new(broker_options(config, "-selftest")?, 10);
+    let connack = async {
+        loop {
+            if let Event::Incoming(Incoming::ConnAck(_)) = eventloop.poll().await? {
+                return Ok::<_, rumqttc::ConnectionError>(());
+            }
+        }
+    };
+    let broker = format!("{}:{}", config.replication.mqtt_broker, config.replication.mqtt_port);
+    match tokio::time::timeout(timeout, connack).await {
+        Ok(Ok(())) => Ok(()),
+        Ok(Err(e)) => Err(anyhow!("MQTT broker {}: {}", broker, e)),
+        Err(_) => Err(anyhow!("MQTT broker {} did not answer within {:?}", broker, timeout)),
+    }
 }
 
 impl Replicator {
@@ -86,72 +399,36 @@ impl Replicator {
     /// - Starts background task to handle incoming messages
     /// 
     /// # MQTT Topics
-    /// - Publishes to: `{topic_prefix}/events`
-    /// - Subscribes to: `{topic_prefix}/events/#`
+    /// - Publishes to: `{topic_prefix}/events` (`{topic_prefix}/db{N}/events` per database)
+    /// - Subscribes to: `{topic_prefix}/events/#` (`{topic_prefix}/+/events` per database)
     pub async fn new(config: &Config) -> Result<Self> {
-        // -----------------------------------------------------------------------------
-        // Design Note (Security & Operability)
-        // This block implements environment-first resolution for identity (Client ID)
-        // and secret (password). The objective is twofold: (i) enable secure injection
-        // of credentials via deployment tooling, and (ii) preserve configuration
-        // determinism when environment variables are absent. The approach deliberately
-        // avoids widening the configuration module's responsibilities.
-        // -----------------------------------------------------------------------------
-        
-        // Environment-first resolution of identity and credentials
-        // Client ID: env var CLIENT_ID overrides config
-        let effective_client_id = std::env::var("CLIENT_ID")
-            .ok()
-            .filter(|s| !s.is_empty())
-            .unwrap_or_else(|| config.replication.client_id.clone());
-
-        // Password: env var CLIENT_PASSWORD overrides config.replication.client_password
-        let effective_password = std::env::var("CLIENT_PASSWORD")
-            .ok()
-            .filter(|s| !s.is_empty())
-            .or_else(|| config.replication.client_password.clone());
-
-        // Configure MQTT client options
-        let mut mqtt_options = MqttOptions::new(
-            &effective_client_id,
-            &config.replication.mqtt_broker,
-            config.replication.mqtt_port,
-        );
-        mqtt_options.set_keep_alive(Duration::from_secs(30));
-
-        // -----------------------------------------------------------------------------
-        // Rationale (Compatibility)
-        // We re-use the effective Client ID as the MQTT username when a password is
-        // provided. This conservative choice avoids a schema expansion and sustains
-        // backwards compatibility. If a distinct username becomes necessary, it can be
-        // added later without perturbing the present call sites.
-        // -----------------------------------------------------------------------------
-        
-        // Some brokers accept "username + password". In the absence of a dedicated
-        // username field in configuration, we conservatively re-use the Client ID
-        // as the username. This preserves the existing configuration surface.
-        // If a future username field is introduced, it can replace this parameter.
-        if let Some(pw) = effective_password {
-            mqtt_options.set_credentials(effective_client_id.clone(), pw);
-        }
+        let mqtt_options = broker_options(config, "")?;
         
     // Create MQTT client and event loop
     let (client, mut eventloop) = AsyncClient::new(mqtt_options, 10);
         
         // Subscribe to the replication topic pattern
-        let topic = format!("{}/events/#", config.replication.topic_prefix);
+        let prefix = config.replication.topic_prefix.clone();
+        let per_database = config.replication.topic_per_database;
+        let topic = if per_database { format!("{}/+/events", prefix) } else { format!("{}/events/#", prefix) };
         client.subscribe(&topic, QoS::AtLeastOnce).await?;
 
         // Create broadcast channel and spawn the MQTT poller
-        let (tx, _rx_unused) = broadcast::channel::<ChangeEvent>(1024);
+        let (tx, _rx_unused) = broadcast::channel::<(usize, ChangeEvent)>(config.replication.apply_queue_size);
         let tx_clone = tx.clone();
+        let ca_path = config.replication.mqtt_ca_path.clone();
         tokio::spawn(async move {
             loop {
                 match eventloop.poll().await {
                     Ok(Event::Incoming(Incoming::Publish(p))) => {
+                        let db = if per_database { topic_database(&prefix, &p.topic) } else { Some(0) };
+                        let Some(db) = db else {
+                            warn!("Ignoring replication message on unexpected topic {}", p.topic);
+                            continue;
+                        };
                         match ChangeEvent::decode_any(&p.payload) {
                             Ok(ev) => {
-                                let _ = tx_clone.send(ev); // ignore errors if no receivers
+                                let _ = tx_clone.send((db, ev)); // ignore errors if no receivers
                             }
                             Err(e) => warn!("Failed to decode ChangeEvent: {}", e),
                         }
@@ -159,6 +436,15 @@ impl Replicator {
                     Ok(_) => {}
                     Err(e) => {
                         error!("MQTT eventloop error: {}", e);
+                        // Reconnect with the CA file as it is now (CONFIG RELOAD TLS)
+                        if let Some(ca_path) = &ca_path {
+                            match tls_transport(ca_path) {
+                                Ok(transport) => {
+                                    eventloop.mqtt_options.set_transport(transport);
+                                }
+                                Err(e) => warn!("Keeping the previous MQTT CA: {:#}", e),
+                            }
+                        }
                         tokio::time::sleep(Duration::from_secs(3)).await;
                     }
                 }
@@ -168,11 +454,59 @@ impl Replicator {
         Ok(Self {
             client,
             topic_prefix: config.replication.topic_prefix.clone(),
+            topic_per_database: config.replication.topic_per_database,
+            db: 0,
+            databases: None,
             node_id: config.replication.client_id.clone(),
             codec: ChangeCodec::Cbor,
             tx,
+            apply_dropped: Arc::default(),
+            filter: KeyFilter::from_config(&config.replication),
+            pause: Arc::default(),
+            pause_queue_limit: config.replication.pause_queue_limit,
+            clock: Arc::new(ConsistencyTracker::new(config.replication.client_id.clone())),
+            dedup: Arc::new(std::sync::Mutex::new(DedupCache::with_capacity(config.replication.dedup_capacity))),
+            causality: Arc::new(std::sync::Mutex::new(CausalityLog::with_capacity(config.replication.causality_log_size))),
+            delta_min_bytes: config.replication.delta_min_bytes,
+            bases: Arc::new(std::sync::Mutex::new(BaseCache::with_capacity(config.replication.delta_cache_bytes))),
+            acks: Arc::default(),
+            ack_all_writes: config.replication.ack_all_writes,
         })
     }
+
+    /// Share `clock` with the server, so tokens handed to clients match the
+    /// published timestamps. Call before `start_replication_handler`.
+    pub fn with_clock(mut self, clock: Arc<ConsistencyTracker>) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    /// Apply remote writes of databases 1.. to `databases` (with
+    /// `replication.topic_per_database`). Call before `start_replication_handler`.
+    pub fn with_databases(mut self, databases: Arc<Databases>) -> Self {
+        self.databases = Some(databases);
+        self
+    }
+
+    /// The handle publishing the writes of database `db`, sharing this one's
+    /// client and queues; None if that database does not replicate.
+    pub fn for_database(&self, db: usize) -> Option<Self> {
+        if db != 0 && !self.topic_per_database {
+            return None;
+        }
+        let mut handle = self.clone();
+        handle.db = db;
+        Some(handle)
+    }
+
+    /// Topic the events of this handle's database are published on.
+    pub fn events_topic(&self) -> String {
+        if self.topic_per_database {
+            format!("{}/db{}/events", self.topic_prefix, self.db)
+        } else {
+            format!("{}/events", self.topic_prefix)
+        }
+    }
     
     /// Publish a SET operation to other nodes.
     /// 
@@ -184,7 +518,7 @@ impl Replicator {
     /// * `value` - The value that was set
     /// 
     /// # Returns
-    /// * `Result<()>` - Success if message was published, error if MQTT failed
+    /// * `Result<u64>` - Timestamp of the published event, error if MQTT failed
     /// 
     /// # Example Usage (in server.rs)
     /// ```rust
@@ -194,10 +528,32 @@ impl Replicator {
     ///     replicator.publish_set(&key, &value).await?;
     /// }
     /// ```
-    pub async fn publish_set(&self, key: &str, value: &str) -> Result<()> {
-        let ts = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_nanos() as u64;
-        let ev = ChangeEvent::with_str_value(1, OpKind::Set, key, Some(value), ts, self.node_id.clone(), None, None);
-        self.publish_event(ev).await
+    pub async fn publish_set(&self, key: &str, value: &str) -> Result<u64> {
+        self.publish_value(OpKind::Set, key, value, false).await
+    }
+
+    /// Publish a SET that receivers acknowledge (`SET ... CL=quorum|all`);
+    /// follow with `wait_for_acks` on the returned timestamp.
+    pub async fn publish_set_acked(&self, key: &str, value: &str) -> Result<u64> {
+        self.publish_value(OpKind::Set, key, value, true).await
+    }
+
+    /// Wait up to `timeout` until `needed` peers acknowledged the write
+    /// published by `publish_set_acked` at `ts`.
+    ///
+    /// # Returns
+    /// * `usize` - Number of peers that acknowledged it
+    pub async fn wait_for_acks(&self, ts: u64, needed: usize, timeout: Duration) -> usize {
+        self.acks.wait(ts, needed, timeout).await
+    }
+
+    /// Peers that applied the latest write to `key` in database 0, sorted
+    /// (`OBJECT ACKS`).
+    ///
+    /// # Returns
+    /// * `Option<Vec<String>>` - None if that write was not published with acks
+    pub fn acked_by(&self, key: &str) -> Option<Vec<String>> {
+        self.acks.confirmed(key)
     }
     
     /// Publish a DELETE operation to other nodes.
@@ -209,7 +565,7 @@ impl Replicator {
     /// * `key` - The key that was deleted
     /// 
     /// # Returns
-    /// * `Result<()>` - Success if message was published, error if MQTT failed
+    /// * `Result<u64>` - Timestamp of the published event, error if MQTT failed
     /// 
     /// # Example Usage (in server.rs)
     /// ```rust
@@ -219,43 +575,235 @@ impl Replicator {
     ///     replicator.publish_delete(&key).await?;
     /// }
     /// ```
-    pub async fn publish_delete(&self, key: &str) -> Result<()> {
-        let ts = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_nanos() as u64;
-        let ev = ChangeEvent::with_str_value(1, OpKind::Del, key, None, ts, self.node_id.clone(), None, None);
-        self.publish_event(ev).await
+    pub async fn publish_delete(&self, key: &str) -> Result<u64> {
+        lock_bases(&self.bases).remove(key);
+        let ts = self.clock.now();
+        let ev = ChangeEvent::with_str_value(SCHEMA_VERSION, OpKind::Del, key, None, ts, self.node_id.clone(), None, None);
+        self.publish_event(ev).await?;
+        Ok(ts)
     }
 
     /// Publish an INCR with resulting numeric value.
-    pub async fn publish_incr(&self, key: &str, new_value: i64) -> Result<()> {
-        let ts = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_nanos() as u64;
-        let ev = ChangeEvent::with_str_value(1, OpKind::Incr, key, Some(&new_value.to_string()), ts, self.node_id.clone(), None, None);
-        self.publish_event(ev).await
+    pub async fn publish_incr(&self, key: &str, new_value: i64) -> Result<u64> {
+        let ts = self.clock.now();
+        let ev = ChangeEvent::with_str_value(SCHEMA_VERSION, OpKind::Incr, key, Some(&new_value.to_string()), ts, self.node_id.clone(), None, None);
+        self.publish_event(ev).await?;
+        Ok(ts)
     }
 
     /// Publish a DECR with resulting numeric value.
-    pub async fn publish_decr(&self, key: &str, new_value: i64) -> Result<()> {
-        let ts = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_nanos() as u64;
-        let ev = ChangeEvent::with_str_value(1, OpKind::Decr, key, Some(&new_value.to_string()), ts, self.node_id.clone(), None, None);
-        self.publish_event(ev).await
+    pub async fn publish_decr(&self, key: &str, new_value: i64) -> Result<u64> {
+        let ts = self.clock.now();
+        let ev = ChangeEvent::with_str_value(SCHEMA_VERSION, OpKind::Decr, key, Some(&new_value.to_string()), ts, self.node_id.clone(), None, None);
+        self.publish_event(ev).await?;
+        Ok(ts)
     }
 
     /// Publish an APPEND with resulting value.
-    pub async fn publish_append(&self, key: &str, new_value: &str) -> Result<()> {
-        let ts = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_nanos() as u64;
-        let ev = ChangeEvent::with_str_value(1, OpKind::Append, key, Some(new_value), ts, self.node_id.clone(), None, None);
-        self.publish_event(ev).await
+    pub async fn publish_append(&self, key: &str, new_value: &str) -> Result<u64> {
+        self.publish_value(OpKind::Append, key, new_value, false).await
     }
 
     /// Publish a PREPEND with resulting value.
-    pub async fn publish_prepend(&self, key: &str, new_value: &str) -> Result<()> {
-        let ts = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_nanos() as u64;
-        let ev = ChangeEvent::with_str_value(1, OpKind::Prepend, key, Some(new_value), ts, self.node_id.clone(), None, None);
+    pub async fn publish_prepend(&self, key: &str, new_value: &str) -> Result<u64> {
+        self.publish_value(OpKind::Prepend, key, new_value, false).await
+    }
+
+    /// Publish a write carrying its resulting value, as a delta against the
+    /// last published version when the value is large enough. With `ack`
+    /// set, acks from receivers are collected for `wait_for_acks`.
+    async fn publish_value(&self, op: OpKind, key: &str, value: &str, ack: bool) -> Result<u64> {
+        let ts = self.clock.now();
+        let mut ev = ChangeEvent::with_str_value(SCHEMA_VERSION, op, key, Some(value), ts, self.node_id.clone(), None, None);
+        if ack || self.ack_all_writes {
+            ev.ack = true;
+            if self.db == 0 {
+                self.acks.track(key, ts);
+            }
+        }
+        if ack {
+            self.acks.expect(ts);
+        }
+        // Bases are kept for database 0 only: keys of other databases may collide
+        if self.delta_min_bytes > 0 && self.db == 0 && self.filter.replicates(key) {
+            let mut bases = lock_bases(&self.bases);
+            if value.len() >= self.delta_min_bytes {
+                if let Some(base) = bases.get(key) {
+                    let (delta, middle) = delta::diff(base, value.as_bytes());
+                    ev.val = Some(middle);
+                    ev.delta = Some(delta);
+                }
+                bases.insert(key, value.as_bytes().to_vec());
+            } else {
+                bases.remove(key);
+            }
+        }
+        self.publish_event(ev).await?;
+        Ok(ts)
+    }
+
+    /// Ask `origin` to republish `key` in full; sent when a delta's base is missing.
+    async fn request_resend(&self, key: &str, origin: &str) -> Result<()> {
+        let ev = ChangeEvent::with_str_value(SCHEMA_VERSION, OpKind::Resend, key, Some(origin), self.clock.now(), self.node_id.clone(), None, None);
+        self.publish_event(ev).await
+    }
+
+    /// Answer a resend request with the full current value (nothing if the key is gone).
+    async fn resend(&self, key: &str, value: Option<String>) -> Result<()> {
+        let Some(value) = value else {
+            return Ok(());
+        };
+        lock_bases(&self.bases).insert(key, value.as_bytes().to_vec());
+        let ev = ChangeEvent::with_str_value(SCHEMA_VERSION, OpKind::Set, key, Some(&value), self.clock.now(), self.node_id.clone(), None, None);
         self.publish_event(ev).await
     }
 
+    /// Confirm to its origin that `ev` was applied here.
+    async fn ack(&self, ev: &ChangeEvent) -> Result<()> {
+        let ack = ChangeEvent::with_str_value(SCHEMA_VERSION, OpKind::Ack, ev.key.as_str(), Some(&ev.src), ev.ts, self.node_id.clone(), None, None);
+        self.send_event(ack).await
+    }
+
+    /// Publish a TTL change (`EXPIRE` / `PERSIST`) without the value.
+    ///
+    /// # Arguments
+    /// * `ttl_secs` - Remaining time to live, or None to make the key persistent
+    pub async fn publish_expire(&self, key: &str, ttl_secs: Option<u64>) -> Result<u64> {
+        let ts = self.clock.now();
+        let ev = ChangeEvent::with_str_value(SCHEMA_VERSION, OpKind::Expire, key, None, ts, self.node_id.clone(), None, ttl_secs);
+        self.publish_event(ev).await?;
+        Ok(ts)
+    }
+
+    /// Publish several writes (e.g. the two halves of a SWAP) as one group that
+    /// peers apply together. `None` values are deletes. Node-local keys are
+    /// left out of the group.
+    pub async fn publish_group(&self, writes: &[(String, Option<String>)]) -> Result<u64> {
+        {
+            let mut bases = lock_bases(&self.bases);
+            for (key, _) in writes {
+                bases.remove(key);
+            }
+        }
+        let ts = self.clock.now();
+        let mut events: Vec<ChangeEvent> = writes
+            .iter()
+            .filter(|(key, _)| self.filter.replicates(key))
+            .map(|(key, value)| {
+                let op = if value.is_some() { OpKind::Set } else { OpKind::Del };
+                ChangeEvent::with_str_value(SCHEMA_VERSION, op, key.as_str(), value.as_deref(), ts, self.node_id.clone(), None, None)
+            })
+            .collect();
+        if events.len() > 1 {
+            let group = EventGroup { id: events[0].op_id, size: events.len() as u16 };
+            for ev in &mut events {
+                ev.group = Some(group);
+            }
+        }
+        for ev in events {
+            self.publish_event(ev).await?;
+        }
+        Ok(ts)
+    }
+
     /// Serialize and publish a change event to MQTT with QoS 1 (at-least-once).
+    /// Node-local keys (see `KeyFilter`) are silently skipped, and while paused
+    /// the event is queued instead.
     async fn publish_event(&self, ev: ChangeEvent) -> Result<()> {
-        let topic = format!("{}/events", self.topic_prefix);
+        if !self.filter.replicates(&ev.key) {
+            debug!("Not replicating node-local key {}", ev.key);
+            return Ok(());
+        }
+        // A newer write nobody acks replaces the one OBJECT ACKS reports
+        if !ev.ack && self.db == 0 && ev.op != OpKind::Resend {
+            self.acks.forget(&ev.key);
+        }
+        {
+            let mut pause = self.pause_queue();
+            if pause.paused {
+                if pause.events.len() >= self.pause_queue_limit.max(1) {
+                    pause.events.pop_front();
+                    pause.dropped += 1;
+                }
+                pause.events.push_back(ev);
+                return Ok(());
+            }
+        }
+        self.send_event(ev).await
+    }
+
+    /// Stop publishing; changes are queued until `resume`.
+    pub fn pause(&self) {
+        self.pause_queue().paused = true;
+    }
+
+    /// Publish the queued changes in order, then resume live publishing.
+    /// Changes made during the flush queue up behind it, so order is kept.
+    ///
+    /// # Returns
+    /// * `Result<usize>` - Number of changes flushed; on a publish error the
+    ///   unsent changes stay queued and replication stays paused
+    pub async fn resume(&self) -> Result<usize> {
+        let mut flushed = 0;
+        loop {
+            let batch: Vec<ChangeEvent> = {
+                let mut pause = self.pause_queue();
+                if pause.events.is_empty() {
+                    pause.paused = false;
+                    pause.dropped = 0;
+                    return Ok(flushed);
+                }
+                pause.events.drain(..).collect()
+            };
+            let mut batch = batch.into_iter();
+            while let Some(ev) = batch.next() {
+                if let Err(e) = self.send_event(ev.clone()).await {
+                    let mut pause = self.pause_queue();
+                    for (i, ev) in std::iter::once(ev).chain(batch).enumerate() {
+                        pause.events.insert(i, ev);
+                    }
+                    return Err(e);
+                }
+                flushed += 1;
+            }
+        }
+    }
+
+    /// Current pause state.
+    pub fn pause_status(&self) -> PauseStatus {
+        let pause = self.pause_queue();
+        PauseStatus { paused: pause.paused, queued: pause.events.len(), dropped: pause.dropped }
+    }
+
+    /// Current apply queue state.
+    pub fn apply_queue_status(&self) -> ApplyQueueStatus {
+        ApplyQueueStatus { queued: self.tx.len(), dropped: self.apply_dropped.load(Ordering::Relaxed) }
+    }
+
+    fn pause_queue(&self) -> std::sync::MutexGuard<'_, PauseQueue> {
+        self.pause.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Size, capacity and hit counts of the de-dup cache.
+    pub fn dedup_stats(&self) -> DedupStats {
+        let dedup = lock_dedup(&self.dedup);
+        DedupStats { size: dedup.ids.len(), capacity: dedup.capacity, lookups: dedup.lookups, hits: dedup.hits }
+    }
+
+    /// Look for events applied out of causal order (`REPLICATION CHECK CAUSALITY`).
+    pub fn check_causality(&self) -> Report {
+        lock_causality(&self.causality).check()
+    }
+
+    /// Forget every applied event id and reset the hit counts.
+    pub fn clear_dedup(&self) {
+        let mut dedup = lock_dedup(&self.dedup);
+        *dedup = DedupCache::with_capacity(dedup.capacity);
+    }
+
+    /// Encode and hand one event to the MQTT client.
+    async fn send_event(&self, ev: ChangeEvent) -> Result<()> {
+        let topic = self.events_topic();
         let payload = self.codec.encode(&ev).map_err(|e| anyhow::anyhow!(e))?;
         self.client
             .publish(&topic, QoS::AtLeastOnce, false, payload)
@@ -273,52 +821,259 @@ impl Replicator {
         // Subscribe to broadcasted events from the MQTT poller
         let mut rx = self.tx.subscribe();
         let node_id = self.node_id.clone();
+        let filter = self.filter.clone();
+        let clock = Arc::clone(&self.clock);
+        let dedup = Arc::clone(&self.dedup);
+        let replicator = self.clone();
         tokio::spawn(async move {
-            let mut seen: HashSet<[u8; 16]> = HashSet::new();
-            let mut last_ts: HashMap<String, u64> = HashMap::new();
+            // Per database: last applied timestamp of each key
+            let mut last_ts: HashMap<usize, HashMap<String, u64>> = HashMap::new();
+            // Grouped events (SWAP) waiting for the rest of their group
+            let mut pending: HashMap<[u8; 16], Vec<ChangeEvent>> = HashMap::new();
             loop {
-                let ev = match rx.recv().await {
-                    Ok(ev) => ev,
+                let (db, ev) = match rx.recv().await {
+                    Ok(message) => message,
+                    Err(broadcast::error::RecvError::Lagged(n)) => {
+                        replicator.apply_dropped.fetch_add(n, Ordering::Relaxed);
+                        warn!("Replication apply queue overflowed, dropped {} events; anti-entropy sync repairs them", n);
+                        continue;
+                    }
                     Err(e) => {
                         warn!("Replication handler receive error: {}", e);
                         continue;
                     }
                 };
                 if ev.src == node_id { continue; } // loop prevention
-                if seen.contains(&ev.op_id) { continue; } // idempotency
-                let current_ts = last_ts.get(&ev.key).cloned().unwrap_or(0);
-                if ev.ts < current_ts { continue; } // LWW
-
-                let mut guard = store.lock().await;
-                match ev.op {
-                    OpKind::Del => {
-                        guard.delete(&ev.key);
+                // The database's store, and the handle answering on its topic
+                let Some((store, replicator)) = replicator.database_target(db, &store) else {
+                    debug!("Ignoring replicated {} for database {}", ev.key, db);
+                    continue;
+                };
+                if ev.op == OpKind::Resend {
+                    if ev.val.as_deref() == Some(node_id.as_bytes()) {
+                        let value = store.lock().await.get(&ev.key);
+                        if let Err(e) = replicator.resend(&ev.key, value).await {
+                            warn!("Failed to resend {}: {}", ev.key, e);
+                        }
+                    }
+                    continue;
+                }
+                if ev.op == OpKind::Ack {
+                    if ev.val.as_deref() == Some(node_id.as_bytes()) {
+                        replicator.acks.record(&ev.key, ev.ts, &ev.src);
                     }
-                    _ => {
-                        if let Some(bytes) = ev.val.clone() {
-                            // Interpret as UTF-8 if possible, otherwise store base64 string
-                            let value = String::from_utf8(bytes.clone())
-                                .unwrap_or_else(|_| base64::encode(bytes));
-                            // We apply by writing the resulting value (idempotent)
-                            if let Err(e) = guard.set(ev.key.clone(), value) {
-                                warn!("Failed to apply event to store: {}", e);
+                    continue;
+                }
+                let batch = match ev.group {
+                    Some(group) => {
+                        let members = pending.entry(group.id).or_default();
+                        if !members.iter().any(|m| m.op_id == ev.op_id) {
+                            members.push(ev);
+                        }
+                        if members.len() < group.size as usize {
+                            if pending.len() > MAX_PENDING_GROUPS {
+                                warn!("Dropping {} incomplete replication groups", pending.len());
+                                pending.clear();
                             }
+                            continue;
                         }
+                        pending.remove(&group.id).unwrap_or_default()
+                    }
+                    None => vec![ev],
+                };
+
+                // One lock for the whole batch, so a group is applied atomically
+                let missing_base: Vec<&ChangeEvent> = {
+                    let guard = store.lock().await;
+                    let mut seen = lock_dedup(&dedup);
+                    let mut bases = lock_bases(&replicator.bases);
+                    let mut causality = lock_causality(&replicator.causality);
+                    let last_ts = last_ts.entry(db).or_default();
+                    batch
+                        .iter()
+                        .filter(|ev| !apply_event(guard.as_ref(), ev, &filter, &mut seen, &mut causality, last_ts, &mut bases))
+                        .collect()
+                };
+                // Events still waiting on a resend are not acked: they are not applied yet
+                let acked: Vec<&ChangeEvent> =
+                    batch.iter().filter(|ev| ev.ack && !missing_base.iter().any(|m| m.op_id == ev.op_id)).collect();
+                for ev in acked {
+                    if let Err(e) = replicator.ack(ev).await {
+                        warn!("Failed to ack {}: {}", ev.key, e);
+                    }
+                }
+                for ev in missing_base {
+                    if let Err(e) = replicator.request_resend(&ev.key, &ev.src).await {
+                        warn!("Failed to request resend of {}: {}", ev.key, e);
                     }
                 }
-                // Update LWW state and dedupe set
-                last_ts.insert(ev.key.clone(), ev.ts);
-                seen.insert(ev.op_id);
-
-                // TODO: Update Merkle tree – in this prototype the store engines
-                // are in-memory maps without an exposed Merkle instance. The
-                // anti-entropy module rebuilds as needed. A production design
-                // would invoke an incremental Merkle update here.
+                // Skipped events still count as applied for consistency tokens
+                for ev in &batch {
+                    clock.observe(&ev.src, ev.ts);
+                }
+
+                // The server's store is Merkle-tracked, so the writes above
+                // already updated the live tree.
             }
         });
     }
 }
 
+impl Replicator {
+    /// Store and publishing handle of database `db` for a remote event;
+    /// None if this node does not replicate that database.
+    fn database_target(&self, db: usize, main: &Db) -> Option<(Db, Replicator)> {
+        let handle = self.for_database(db)?;
+        if db == 0 {
+            return Some((Arc::clone(main), handle));
+        }
+        match self.databases.as_ref()?.get(db) {
+            Ok(store) => Some((store, handle)),
+            Err(e) => {
+                warn!("Ignoring replicated write: {}", e);
+                None
+            }
+        }
+    }
+}
+
+/// Topic's database under `{prefix}/db{N}/events`; None for other topics.
+fn topic_database(prefix: &str, topic: &str) -> Option<usize> {
+    let db = topic.strip_prefix(prefix)?.strip_prefix("/db")?.strip_suffix("/events")?;
+    db.parse().ok()
+}
+
+/// Incomplete event groups kept before they are given up on.
+const MAX_PENDING_GROUPS: usize = 1024;
+
+fn lock_dedup(dedup: &std::sync::Mutex<DedupCache>) -> std::sync::MutexGuard<'_, DedupCache> {
+    dedup.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+fn lock_causality(log: &std::sync::Mutex<CausalityLog>) -> std::sync::MutexGuard<'_, CausalityLog> {
+    log.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+fn lock_bases(bases: &std::sync::Mutex<BaseCache>) -> std::sync::MutexGuard<'_, BaseCache> {
+    bases.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+/// Apply one remote event unless it is node-local here, a duplicate, or older
+/// than the last applied write to its key (LWW). Events that are neither
+/// node-local nor duplicates go to the causality log, LWW losers included.
+///
+/// # Returns
+/// * `bool` - false if the event is a delta whose base this node lacks; it is
+///   then left unapplied (and not marked seen) for the full resend
+fn apply_event(
+    store: &(dyn KVEngineStoreTrait + Send + Sync),
+    ev: &ChangeEvent,
+    filter: &KeyFilter,
+    seen: &mut DedupCache,
+    causality: &mut CausalityLog,
+    last_ts: &mut HashMap<String, u64>,
+    bases: &mut BaseCache,
+) -> bool {
+    let skip = !filter.replicates(&ev.key) // node-local on this side
+        || seen.seen(&ev.op_id); // idempotency
+    if skip {
+        return true;
+    }
+    causality.record(ev);
+    if ev.ts < last_ts.get(&ev.key).cloned().unwrap_or(0) {
+        return true; // LWW
+    }
+    match ev.op {
+        OpKind::Del => {
+            store.delete(&ev.key);
+        }
+        OpKind::Expire => {
+            let deadline = ev.ttl.map(|secs| now_ms().saturating_add(secs.saturating_mul(1000)));
+            if let Err(e) = store.set_expiry(&ev.key, deadline) {
+                warn!("Failed to apply TTL change to store: {}", e);
+            }
+        }
+        OpKind::Resend | OpKind::Ack => {}
+        _ => {
+            if let Some(bytes) = ev.val.clone() {
+                let bytes = match &ev.delta {
+                    Some(d) => match delta::patch(store.get(&ev.key).unwrap_or_default().as_bytes(), d, &bytes) {
+                        Some(value) => value,
+                        None => return false,
+                    },
+                    None => bytes,
+                };
+                // Stores hold strings: a value that is not UTF-8 cannot be kept exactly
+                let Ok(value) = String::from_utf8(bytes) else {
+                    warn!("Rejected replicated value for {}: not valid UTF-8", ev.key);
+                    seen.insert(ev.op_id);
+                    return true;
+                };
+                // We apply by writing the resulting value (idempotent)
+                match store.set(ev.key.clone(), value) {
+                    Ok(()) => store.mark_replicated(&ev.key, &ev.src),
+                    Err(e) => warn!("Failed to apply event to store: {}", e),
+                }
+            }
+        }
+    }
+    // Update LWW state and dedupe set; our published base is stale now
+    last_ts.insert(ev.key.clone(), ev.ts);
+    seen.insert(ev.op_id);
+    bases.remove(&ev.key);
+    true
+}
+
+#[cfg(test)]
+impl Replicator {
+    /// Broker-less replicator for tests: published MQTT requests come out of
+    /// the returned receiver instead of going to a broker.
+    pub(crate) fn detached(node_id: &str) -> (Self, flume::Receiver<rumqttc::Request>) {
+        let (request_tx, request_rx) = flume::bounded(64);
+        let (tx, _rx_unused) = broadcast::channel::<(usize, ChangeEvent)>(1024);
+        let replicator = Self {
+            client: AsyncClient::from_senders(request_tx),
+            topic_prefix: "merkle_kv_test".to_string(),
+            topic_per_database: false,
+            db: 0,
+            databases: None,
+            node_id: node_id.to_string(),
+            codec: ChangeCodec::Cbor,
+            tx,
+            apply_dropped: Arc::default(),
+            filter: KeyFilter::default(),
+            pause: Arc::default(),
+            pause_queue_limit: 10_000,
+            clock: Arc::new(ConsistencyTracker::new(node_id)),
+            dedup: Arc::new(std::sync::Mutex::new(DedupCache::with_capacity(100_000))),
+            causality: Arc::new(std::sync::Mutex::new(CausalityLog::with_capacity(1024))),
+            delta_min_bytes: 0,
+            bases: Arc::new(std::sync::Mutex::new(BaseCache::with_capacity(64 * 1024 * 1024))),
+            acks: Arc::default(),
+            ack_all_writes: false,
+        };
+        (replicator, request_rx)
+    }
+
+    /// Publish each database on its own topic (`replication.topic_per_database`).
+    pub(crate) fn with_topic_per_database(mut self) -> Self {
+        self.topic_per_database = true;
+        self
+    }
+
+    /// Hand a payload to the apply loop as if it arrived from the broker.
+    pub(crate) fn deliver(&self, payload: &[u8]) {
+        self.deliver_on(&format!("{}/events", self.topic_prefix), payload);
+    }
+
+    /// Hand a payload published on `topic` to the apply loop.
+    pub(crate) fn deliver_on(&self, topic: &str, payload: &[u8]) {
+        let ev = ChangeEvent::decode_any(payload).expect("valid change event");
+        let db = if self.topic_per_database { topic_database(&self.topic_prefix, topic) } else { Some(0) };
+        let _ = self.tx.send((db.expect("database topic"), ev));
+    }
+}
+
 #[cfg(test)]
 mod tests {
     // TODO: Implement comprehensive tests for replication logic
@@ -349,4 +1104,610 @@ mod tests {
     // async fn test_mqtt_integration() {
     //     // Mock MQTT broker and test publish/subscribe flow
     // }
+
+    use super::*;
+    use crate::store::{ExpiringEngine, RwLockEngine, TimestampEngine};
+    use rumqttc::Request;
+
+    #[test]
+    fn test_topic_database_reads_the_database_index() {
+        assert_eq!(topic_database("mkv", "mkv/db0/events"), Some(0));
+        assert_eq!(topic_database("mkv", "mkv/db12/events"), Some(12));
+        assert_eq!(topic_database("mkv", "mkv/events"), None);
+        assert_eq!(topic_database("mkv", "mkv/dbx/events"), None);
+        assert_eq!(topic_database("mkv", "other/db1/events"), None);
+
+        let (replicator, _published) = Replicator::detached("n");
+        assert!(replicator.for_database(1).is_none(), "only database 0 replicates by default");
+        assert_eq!(replicator.for_database(0).unwrap().events_topic(), "merkle_kv_test/events");
+        let per_db = replicator.with_topic_per_database();
+        assert_eq!(per_db.for_database(3).unwrap().events_topic(), "merkle_kv_test/db3/events");
+        assert_eq!(per_db.events_topic(), "merkle_kv_test/db0/events");
+    }
+
+    #[tokio::test]
+    async fn test_lazy_expiry_on_one_node_deletes_key_on_peer() {
+        // Node A: the key has a TTL that already passed
+        let store_a = ExpiringEngine::new(Box::new(RwLockEngine::new("unused").unwrap()), true);
+        store_a.set("session".to_string(), "token".to_string()).unwrap();
+        store_a.set_expiry("session", Some(now_ms() - 1)).unwrap();
+
+        // Node B: still holds the key and applies replicated events
+        let store_b: Arc<Mutex<Box<dyn KVEngineStoreTrait + Send + Sync>>> =
+            Arc::new(Mutex::new(Box::new(RwLockEngine::new("unused").unwrap())));
+        store_b.lock().await.set("session".to_string(), "token".to_string()).unwrap();
+
+        let (node_a, a_published) = Replicator::detached("node-a");
+        let (node_b, _b_published) = Replicator::detached("node-b");
+        node_b.start_replication_handler(Arc::clone(&store_b)).await;
+
+        // A GET on node A expires the key and queues it for replication
+        assert_eq!(store_a.get("session"), None);
+        for key in store_a.take_expired() {
+            node_a.publish_delete(&key).await.unwrap();
+        }
+
+        // Play the broker: forward node A's publishes to node B
+        let mut forwarded = 0;
+        while let Ok(Request::Publish(p)) = a_published.try_recv() {
+            node_b.deliver(&p.payload);
+            forwarded += 1;
+        }
+        assert_eq!(forwarded, 1);
+
+        let removed = tokio::time::timeout(Duration::from_secs(2), async {
+            while store_b.lock().await.get("session").is_some() {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await;
+        assert!(removed.is_ok(), "peer must drop the lazily expired key");
+    }
+
+    #[tokio::test]
+    async fn test_excluded_prefix_is_not_published_or_applied() {
+        let (mut node_a, a_published) = Replicator::detached("node-a");
+        node_a.filter = KeyFilter::new(vec![], vec!["local:".to_string()]);
+        node_a.publish_set("local:cache", "x").await.unwrap();
+        node_a.publish_delete("local:cache").await.unwrap();
+        node_a.publish_set("user:1", "ann").await.unwrap();
+
+        let published: Vec<ChangeEvent> = std::iter::from_fn(|| match a_published.try_recv() {
+            Ok(Request::Publish(p)) => Some(ChangeEvent::decode_any(&p.payload).unwrap()),
+            _ => None,
+        })
+        .collect();
+        assert_eq!(published.len(), 1);
+        assert_eq!(published[0].key, "user:1");
+
+        // A peer that considers `local:` node-local ignores such events on apply
+        let store_b: Arc<Mutex<Box<dyn KVEngineStoreTrait + Send + Sync>>> =
+            Arc::new(Mutex::new(Box::new(RwLockEngine::new("unused").unwrap())));
+        let (mut node_b, _b_published) = Replicator::detached("node-b");
+        node_b.filter = KeyFilter::new(vec![], vec!["local:".to_string()]);
+        node_b.start_replication_handler(Arc::clone(&store_b)).await;
+        let (unfiltered, raw) = Replicator::detached("node-c");
+        unfiltered.publish_set("local:cache", "x").await.unwrap();
+        unfiltered.publish_set("user:2", "bob").await.unwrap();
+        while let Ok(Request::Publish(p)) = raw.try_recv() {
+            node_b.deliver(&p.payload);
+        }
+
+        let applied = tokio::time::timeout(Duration::from_secs(2), async {
+            while store_b.lock().await.get("user:2").is_none() {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await;
+        assert!(applied.is_ok());
+        assert_eq!(store_b.lock().await.get("local:cache"), None);
+    }
+
+    fn published_keys(published: &flume::Receiver<Request>) -> Vec<String> {
+        std::iter::from_fn(|| match published.try_recv() {
+            Ok(Request::Publish(p)) => Some(ChangeEvent::decode_any(&p.payload).unwrap().key),
+            _ => None,
+        })
+        .collect()
+    }
+
+    #[tokio::test]
+    async fn test_paused_changes_are_queued_and_flushed_on_resume() {
+        let (node, published) = Replicator::detached("node-a");
+        node.pause();
+        node.publish_set("a", "1").await.unwrap();
+        node.publish_delete("b").await.unwrap();
+        node.publish_incr("c", 3).await.unwrap();
+        assert!(published_keys(&published).is_empty(), "nothing is published while paused");
+        assert_eq!(node.pause_status(), PauseStatus { paused: true, queued: 3, dropped: 0 });
+
+        assert_eq!(node.resume().await.unwrap(), 3);
+        assert_eq!(published_keys(&published), vec!["a", "b", "c"]);
+        assert!(!node.pause_status().paused);
+
+        node.publish_set("d", "4").await.unwrap();
+        assert_eq!(published_keys(&published), vec!["d"]);
+    }
+
+    #[tokio::test]
+    async fn test_pause_queue_is_bounded() {
+        let (mut node, published) = Replicator::detached("node-a");
+        node.pause_queue_limit = 2;
+        node.pause();
+        for key in ["k1", "k2", "k3"] {
+            node.publish_set(key, "v").await.unwrap();
+        }
+        assert_eq!(node.pause_status(), PauseStatus { paused: true, queued: 2, dropped: 1 });
+        node.resume().await.unwrap();
+        assert_eq!(published_keys(&published), vec!["k2", "k3"]);
+    }
+
+    #[tokio::test]
+    async fn test_event_flood_stays_bounded_by_the_apply_queue() {
+        let store: Arc<Mutex<Box<dyn KVEngineStoreTrait + Send + Sync>>> =
+            Arc::new(Mutex::new(Box::new(RwLockEngine::new("unused").unwrap())));
+        let (mut node, _published) = Replicator::detached("node-b");
+        node.tx = broadcast::channel(8).0;
+        node.start_replication_handler(Arc::clone(&store)).await;
+
+        // The apply loop is stuck on the store lock while 500 events arrive
+        let guard = store.lock().await;
+        for i in 0..500u64 {
+            let ev = ChangeEvent::with_str_value(1, OpKind::Set, format!("k{}", i), Some("v"), i + 1, "node-a".to_string(), None, None);
+            node.deliver(&ChangeCodec::Cbor.encode(&ev).unwrap());
+            assert!(node.apply_queue_status().queued <= 8);
+        }
+        drop(guard);
+
+        let status = tokio::time::timeout(Duration::from_secs(2), async {
+            loop {
+                let status = node.apply_queue_status();
+                if status.queued == 0 && store.lock().await.len() as u64 + status.dropped == 500 {
+                    return status;
+                }
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .expect("the queue drains");
+        assert!(status.dropped >= 500 - 9, "{:?}", status);
+        // The newest events survive the overflow
+        assert_eq!(store.lock().await.get("k499"), Some("v".to_string()));
+    }
+
+    #[tokio::test]
+    async fn test_token_read_waits_for_lagging_replication() {
+        let (node_a, a_published) = Replicator::detached("node-a");
+        let tracker_b = Arc::new(ConsistencyTracker::new("node-b"));
+        let (node_b, _b_published) = Replicator::detached("node-b");
+        let node_b = node_b.with_clock(Arc::clone(&tracker_b));
+        let store_b: Arc<Mutex<Box<dyn KVEngineStoreTrait + Send + Sync>>> =
+            Arc::new(Mutex::new(Box::new(RwLockEngine::new("unused").unwrap())));
+        node_b.start_replication_handler(Arc::clone(&store_b)).await;
+
+        // The client writes on node A and keeps the token
+        let token = node_a.clock.token(node_a.publish_set("user:1", "ann").await.unwrap());
+        let Ok(Request::Publish(p)) = a_published.try_recv() else { panic!("SET must be published") };
+
+        // The broker is slow: node B only receives the event after a delay
+        assert!(!tracker_b.caught_up(&token));
+        let lag = Duration::from_millis(200);
+        let started = tokio::time::Instant::now();
+        tokio::spawn(async move {
+            tokio::time::sleep(lag).await;
+            node_b.deliver(&p.payload);
+        });
+
+        assert!(tracker_b.wait_for(&token, Duration::from_secs(5)).await);
+        assert!(started.elapsed() >= lag);
+        assert_eq!(store_b.lock().await.get("user:1"), Some("ann".to_string()));
+    }
+
+    #[tokio::test]
+    async fn test_expire_replicates_as_ttl_only() {
+        let (node_a, a_published) = Replicator::detached("node-a");
+        let store_b: Arc<Mutex<Box<dyn KVEngineStoreTrait + Send + Sync>>> =
+            Arc::new(Mutex::new(Box::new(ExpiringEngine::new(Box::new(RwLockEngine::new("unused").unwrap()), false))));
+        store_b.lock().await.set("k".to_string(), "v".to_string()).unwrap();
+        let (node_b, _b_published) = Replicator::detached("node-b");
+        node_b.start_replication_handler(Arc::clone(&store_b)).await;
+
+        node_a.publish_expire("k", Some(60)).await.unwrap();
+        let Ok(Request::Publish(p)) = a_published.try_recv() else { panic!("EXPIRE must be published") };
+        let ev = ChangeEvent::decode_any(&p.payload).unwrap();
+        assert_eq!((ev.op, ev.val.as_ref(), ev.ttl), (OpKind::Expire, None, Some(60)));
+        node_b.deliver(&p.payload);
+
+        let applied = tokio::time::timeout(Duration::from_secs(2), async {
+            while store_b.lock().await.expiry("k").is_none() {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await;
+        assert!(applied.is_ok(), "peer must apply the TTL");
+        assert_eq!(store_b.lock().await.get("k"), Some("v".to_string()));
+    }
+
+    #[tokio::test]
+    async fn test_applied_write_records_its_origin_node() {
+        let (node_a, a_published) = Replicator::detached("node-a");
+        let store_b: Arc<Mutex<Box<dyn KVEngineStoreTrait + Send + Sync>>> =
+            Arc::new(Mutex::new(Box::new(TimestampEngine::new(Box::new(RwLockEngine::new("unused").unwrap())))));
+        let (node_b, _b_published) = Replicator::detached("node-b");
+        node_b.start_replication_handler(Arc::clone(&store_b)).await;
+
+        let before = now_ms();
+        node_a.publish_set("k", "v").await.unwrap();
+        let Ok(Request::Publish(p)) = a_published.try_recv() else { panic!("SET must be published") };
+        node_b.deliver(&p.payload);
+        let times = tokio::time::timeout(Duration::from_secs(2), async {
+            loop {
+                if let Some(times) = store_b.lock().await.times("k") {
+                    return times;
+                }
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .expect("peer must apply the write");
+        assert_eq!(times.origin.as_deref(), Some("node-a"));
+        assert!(times.modified_ms >= before);
+
+        store_b.lock().await.set("k".to_string(), "local".to_string()).unwrap();
+        assert_eq!(store_b.lock().await.times("k").unwrap().origin, None);
+    }
+
+    #[tokio::test]
+    async fn test_grouped_writes_are_applied_together() {
+        let (node_a, a_published) = Replicator::detached("node-a");
+        let store_b: Arc<Mutex<Box<dyn KVEngineStoreTrait + Send + Sync>>> =
+            Arc::new(Mutex::new(Box::new(RwLockEngine::new("unused").unwrap())));
+        store_b.lock().await.set("a".to_string(), "1".to_string()).unwrap();
+        let (node_b, _b_published) = Replicator::detached("node-b");
+        node_b.start_replication_handler(Arc::clone(&store_b)).await;
+
+        // SWAP of a present key with an absent one: a SET and a DEL
+        node_a.publish_group(&[("c".to_string(), Some("1".to_string())), ("a".to_string(), None)]).await.unwrap();
+        let payloads: Vec<_> = a_published
+            .try_iter()
+            .map(|r| match r {
+                Request::Publish(p) => p.payload,
+                other => panic!("unexpected request {:?}", other),
+            })
+            .collect();
+        assert_eq!(payloads.len(), 2);
+        let events: Vec<_> = payloads.iter().map(|p| ChangeEvent::decode_any(p).unwrap()).collect();
+        assert_eq!(events[0].group, events[1].group);
+        assert_eq!(events[0].group.unwrap().size, 2);
+
+        // Half a group is held back
+        node_b.deliver(&payloads[0]);
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert_eq!(store_b.lock().await.get("c"), None);
+        assert_eq!(store_b.lock().await.get("a"), Some("1".to_string()));
+
+        node_b.deliver(&payloads[1]);
+        let applied = tokio::time::timeout(Duration::from_secs(2), async {
+            while store_b.lock().await.get("c").is_none() {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await;
+        assert!(applied.is_ok(), "complete group must be applied");
+        assert_eq!(store_b.lock().await.get("a"), None);
+    }
+
+    #[tokio::test]
+    async fn test_duplicate_events_hit_the_dedup_cache() {
+        let (node_a, a_published) = Replicator::detached("node-a");
+        let store_b: Arc<Mutex<Box<dyn KVEngineStoreTrait + Send + Sync>>> =
+            Arc::new(Mutex::new(Box::new(RwLockEngine::new("unused").unwrap())));
+        let (node_b, _b_published) = Replicator::detached("node-b");
+        node_b.start_replication_handler(Arc::clone(&store_b)).await;
+
+        node_a.publish_set("k", "v").await.unwrap();
+        let Ok(Request::Publish(p)) = a_published.try_recv() else { panic!("expected a publish") };
+        for _ in 0..3 {
+            node_b.deliver(&p.payload);
+        }
+        let stats = tokio::time::timeout(Duration::from_secs(2), async {
+            loop {
+                let stats = node_b.dedup_stats();
+                if stats.lookups == 3 {
+                    return stats;
+                }
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .expect("all deliveries must be looked up");
+        assert_eq!(stats, DedupStats { size: 1, capacity: 100_000, lookups: 3, hits: 2 });
+        assert!((stats.hit_rate() - 2.0 / 3.0).abs() < 1e-9);
+
+        node_b.clear_dedup();
+        assert_eq!(node_b.dedup_stats(), DedupStats { size: 0, capacity: 100_000, lookups: 0, hits: 0 });
+    }
+
+    #[tokio::test]
+    async fn test_events_delivered_out_of_causal_order_are_flagged() {
+        let (node_a, a_published) = Replicator::detached("node-a");
+        let store_b: Arc<Mutex<Box<dyn KVEngineStoreTrait + Send + Sync>>> =
+            Arc::new(Mutex::new(Box::new(RwLockEngine::new("unused").unwrap())));
+        let (node_b, _b_published) = Replicator::detached("node-b");
+        node_b.start_replication_handler(Arc::clone(&store_b)).await;
+
+        let mut stamps = Vec::new();
+        for key in ["first", "second", "third"] {
+            stamps.push(node_a.publish_set(key, "v").await.unwrap());
+        }
+        let payloads: Vec<_> = a_published
+            .try_iter()
+            .filter_map(|r| match r {
+                Request::Publish(p) => Some(p.payload),
+                _ => None,
+            })
+            .collect();
+        // "second" arrives after "third", and is redelivered
+        for i in [0, 2, 1, 1] {
+            node_b.deliver(&payloads[i]);
+        }
+        let report = tokio::time::timeout(Duration::from_secs(2), async {
+            loop {
+                let report = node_b.check_causality();
+                if report.events == 3 {
+                    return report;
+                }
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .expect("all events must be logged");
+        assert_eq!(report.sources, 1);
+        assert_eq!(
+            report.violations,
+            vec![crate::causality::Violation {
+                src: "node-a".to_string(),
+                ts: stamps[1],
+                key: "second".to_string(),
+                after_ts: stamps[2],
+                after_key: "third".to_string(),
+            }]
+        );
+    }
+
+    #[test]
+    fn test_dedup_cache_evicts_oldest() {
+        let mut cache = DedupCache::with_capacity(2);
+        for id in [[1; 16], [2; 16], [3; 16]] {
+            cache.insert(id);
+        }
+        assert!(!cache.seen(&[1; 16]));
+        assert!(cache.seen(&[2; 16]) && cache.seen(&[3; 16]));
+        assert_eq!(cache.ids.len(), 2);
+    }
+
+    fn next_payload(published: &flume::Receiver<Request>) -> Vec<u8> {
+        match published.try_recv() {
+            Ok(Request::Publish(p)) => p.payload.to_vec(),
+            other => panic!("expected a publish, got {:?}", other),
+        }
+    }
+
+    async fn wait_for_value(store: &Arc<Mutex<Box<dyn KVEngineStoreTrait + Send + Sync>>>, key: &str, value: &str) {
+        let applied = tokio::time::timeout(Duration::from_secs(2), async {
+            while store.lock().await.get(key).as_deref() != Some(value) {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await;
+        assert!(applied.is_ok(), "{} never reached the expected value", key);
+    }
+
+    #[tokio::test]
+    async fn test_small_edit_to_large_value_replicates_as_delta() {
+        let (mut node_a, a_published) = Replicator::detached("node-a");
+        node_a.delta_min_bytes = 1024;
+        let store_b: Arc<Mutex<Box<dyn KVEngineStoreTrait + Send + Sync>>> =
+            Arc::new(Mutex::new(Box::new(RwLockEngine::new("unused").unwrap())));
+        let (node_b, _b_published) = Replicator::detached("node-b");
+        node_b.start_replication_handler(Arc::clone(&store_b)).await;
+
+        let original = "0123456789abcdef".repeat(4096);
+        node_a.publish_set("doc", &original).await.unwrap();
+        let full = next_payload(&a_published);
+        assert!(full.len() > original.len(), "first write has no base and goes in full");
+        node_b.deliver(&full);
+        wait_for_value(&store_b, "doc", &original).await;
+
+        let edited = format!("{}EDIT{}", &original[..30_000], &original[30_004..]);
+        node_a.publish_set("doc", &edited).await.unwrap();
+        let delta = next_payload(&a_published);
+        assert!(delta.len() < 256, "delta payload is {} bytes", delta.len());
+        assert!(ChangeEvent::decode_any(&delta).unwrap().delta.is_some());
+        node_b.deliver(&delta);
+        wait_for_value(&store_b, "doc", &edited).await;
+
+        // Small values always go in full
+        node_a.publish_set("small", "v").await.unwrap();
+        assert_eq!(ChangeEvent::decode_any(&next_payload(&a_published)).unwrap().delta, None);
+    }
+
+    #[tokio::test]
+    async fn test_delta_without_base_falls_back_to_full_value() {
+        let (mut node_a, a_published) = Replicator::detached("node-a");
+        node_a.delta_min_bytes = 1024;
+        let store_a: Arc<Mutex<Box<dyn KVEngineStoreTrait + Send + Sync>>> =
+            Arc::new(Mutex::new(Box::new(RwLockEngine::new("unused").unwrap())));
+        node_a.start_replication_handler(Arc::clone(&store_a)).await;
+        let store_b: Arc<Mutex<Box<dyn KVEngineStoreTrait + Send + Sync>>> =
+            Arc::new(Mutex::new(Box::new(RwLockEngine::new("unused").unwrap())));
+        let (node_b, b_published) = Replicator::detached("node-b");
+        node_b.start_replication_handler(Arc::clone(&store_b)).await;
+
+        // Node B missed the first version, so the delta for the second has no base there
+        let v1 = "x".repeat(4096);
+        let v2 = format!("{}y", v1);
+        node_a.publish_set("doc", &v1).await.unwrap();
+        next_payload(&a_published);
+        store_a.lock().await.set("doc".to_string(), v2.clone()).unwrap();
+        node_a.publish_set("doc", &v2).await.unwrap();
+        node_b.deliver(&next_payload(&a_published));
+
+        // B asks A for the full value; A answers from its store
+        let request = tokio::time::timeout(Duration::from_secs(2), b_published.recv_async()).await.unwrap().unwrap();
+        let Request::Publish(request) = request else { panic!("expected a resend request") };
+        let ev = ChangeEvent::decode_any(&request.payload).unwrap();
+        assert_eq!((ev.op, ev.key.as_str(), ev.val.as_deref()), (OpKind::Resend, "doc", Some(&b"node-a"[..])));
+        node_a.deliver(&request.payload);
+        let resent = tokio::time::timeout(Duration::from_secs(2), a_published.recv_async()).await.unwrap().unwrap();
+        let Request::Publish(resent) = resent else { panic!("expected the full value") };
+        assert_eq!(ChangeEvent::decode_any(&resent.payload).unwrap().delta, None);
+        node_b.deliver(&resent.payload);
+        wait_for_value(&store_b, "doc", &v2).await;
+    }
+
+    /// Next publish of a node's apply loop, which runs in the background.
+    async fn recv_payload(published: &flume::Receiver<Request>) -> Vec<u8> {
+        match tokio::time::timeout(Duration::from_secs(2), published.recv_async()).await {
+            Ok(Ok(Request::Publish(p))) => p.payload.to_vec(),
+            other => panic!("expected a publish, got {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_acked_write_waits_for_every_peer() {
+        let (node_a, a_published) = Replicator::detached("node-a");
+        let store = || -> Arc<Mutex<Box<dyn KVEngineStoreTrait + Send + Sync>>> {
+            Arc::new(Mutex::new(Box::new(RwLockEngine::new("unused").unwrap())))
+        };
+        node_a.start_replication_handler(store()).await;
+        let mut peers = Vec::new();
+        for id in ["node-b", "node-c"] {
+            let (peer, published) = Replicator::detached(id);
+            peer.start_replication_handler(store()).await;
+            peers.push((peer, published));
+        }
+
+        let ts = node_a.publish_set_acked("k", "v").await.unwrap();
+        let write = next_payload(&a_published);
+        assert!(ChangeEvent::decode_any(&write).unwrap().ack);
+        let mut acks = Vec::new();
+        for (peer, published) in &peers {
+            peer.deliver(&write);
+            let ack = recv_payload(published).await;
+            let ev = ChangeEvent::decode_any(&ack).unwrap();
+            assert_eq!((ev.op, ev.ts, ev.val.as_deref()), (OpKind::Ack, ts, Some(&b"node-a"[..])));
+            acks.push(ack);
+        }
+        // An ack of another node's write does not count
+        let mut stranger = ChangeEvent::decode_any(&acks[1]).unwrap();
+        stranger.val = Some(b"node-z".to_vec());
+        node_a.deliver(&ChangeCodec::Cbor.encode(&stranger).unwrap());
+
+        let waiter = {
+            let node_a = node_a.clone();
+            tokio::spawn(async move { node_a.wait_for_acks(ts, 2, Duration::from_secs(5)).await })
+        };
+        node_a.deliver(&acks[0]);
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert!(!waiter.is_finished(), "CL=all must wait for the second peer");
+        assert_eq!(node_a.acks.count(ts), 1);
+
+        node_a.deliver(&acks[1]);
+        assert_eq!(tokio::time::timeout(Duration::from_secs(2), waiter).await.unwrap().unwrap(), 2);
+        // Nothing is left behind, and a late ack is dropped
+        node_a.deliver(&acks[1]);
+        assert_eq!(node_a.wait_for_acks(ts, 1, Duration::from_millis(50)).await, 0);
+    }
+
+    #[tokio::test]
+    async fn test_acks_of_the_latest_write_are_reported_per_key() {
+        let (mut node_a, a_published) = Replicator::detached("node-a");
+        node_a.ack_all_writes = true;
+        node_a.start_replication_handler(Arc::new(Mutex::new(Box::new(RwLockEngine::new("unused").unwrap())))).await;
+        // Stub peers: each answers a write with the Ack event a real peer sends
+        let ack_from = |peer: &str, write: &[u8]| {
+            let ev = ChangeEvent::decode_any(write).unwrap();
+            let ack = ChangeEvent::with_str_value(1, OpKind::Ack, ev.key.as_str(), Some(&ev.src), ev.ts, peer.to_string(), None, None);
+            ChangeCodec::Cbor.encode(&ack).unwrap()
+        };
+        let settled = || tokio::time::sleep(Duration::from_millis(50));
+
+        assert_eq!(node_a.acked_by("k"), None);
+        node_a.publish_set("k", "v1").await.unwrap();
+        let first = next_payload(&a_published);
+        assert!(ChangeEvent::decode_any(&first).unwrap().ack, "replication.ack_all_writes asks for acks");
+        assert_eq!(node_a.acked_by("k"), Some(vec![]));
+        node_a.deliver(&ack_from("node-c", &first));
+        node_a.deliver(&ack_from("node-b", &first));
+        settled().await;
+        assert_eq!(node_a.acked_by("k"), Some(vec!["node-b".to_string(), "node-c".to_string()]));
+
+        // Only acks of the latest write count
+        node_a.publish_append("k", "v1+").await.unwrap();
+        let second = next_payload(&a_published);
+        node_a.deliver(&ack_from("node-d", &first));
+        node_a.deliver(&ack_from("node-b", &second));
+        settled().await;
+        assert_eq!(node_a.acked_by("k"), Some(vec!["node-b".to_string()]));
+
+        // A write published without acks clears them
+        node_a.publish_delete("k").await.unwrap();
+        assert_eq!(node_a.acked_by("k"), None);
+    }
+
+    #[tokio::test]
+    async fn test_event_of_a_newer_schema_applies_its_known_fields() {
+        use serde_cbor::Value;
+        let store_b: Arc<Mutex<Box<dyn KVEngineStoreTrait + Send + Sync>>> =
+            Arc::new(Mutex::new(Box::new(RwLockEngine::new("unused").unwrap())));
+        let (node_b, _b_published) = Replicator::detached("node-b");
+        node_b.start_replication_handler(Arc::clone(&store_b)).await;
+
+        // A newer node stamps its version and adds a field this one does not know
+        let ev = ChangeEvent::with_str_value(SCHEMA_VERSION + 1, OpKind::Set, "k", Some("v"), 5, "node-a", None, None);
+        let Value::Map(mut fields) = serde_cbor::value::to_value(&ev).unwrap() else { panic!("map") };
+        fields.insert(Value::Text("vclock".into()), Value::Map([(Value::Text("node-a".into()), Value::Integer(7))].into()));
+        node_b.deliver(&serde_cbor::to_vec(&Value::Map(fields)).unwrap());
+        wait_for_value(&store_b, "k", "v").await;
+    }
+
+    #[tokio::test]
+    async fn test_binary_keys_and_values_reach_the_peer_exactly() {
+        let (mut node_a, a_published) = Replicator::detached("node-a");
+        node_a.delta_min_bytes = 1024;
+        let store_b: Arc<Mutex<Box<dyn KVEngineStoreTrait + Send + Sync>>> =
+            Arc::new(Mutex::new(Box::new(RwLockEngine::new("unused").unwrap())));
+        let (node_b, _b_published) = Replicator::detached("node-b");
+        node_b.start_replication_handler(Arc::clone(&store_b)).await;
+
+        let key = "bin\0key\u{1}\u{7f}";
+        let value = "a\0\0b\r\u{1b}[0m\u{fffd}€";
+        node_a.publish_set(key, value).await.unwrap();
+        node_b.deliver(&next_payload(&a_published));
+        wait_for_value(&store_b, key, value).await;
+
+        // Changing one 2-byte character sends a delta of a lone continuation
+        // byte: not UTF-8 on its own, rebuilt exactly on the peer
+        let original = "é".repeat(2048);
+        node_a.publish_set("doc", &original).await.unwrap();
+        node_b.deliver(&next_payload(&a_published));
+        wait_for_value(&store_b, "doc", &original).await;
+        let edited = format!("{}è{}", "é".repeat(1000), "é".repeat(1047));
+        node_a.publish_set("doc", &edited).await.unwrap();
+        let delta = next_payload(&a_published);
+        let ev = ChangeEvent::decode_any(&delta).unwrap();
+        assert!(ev.delta.is_some());
+        assert!(std::str::from_utf8(ev.val.as_deref().unwrap()).is_err(), "{:?}", ev.val);
+        node_b.deliver(&delta);
+        wait_for_value(&store_b, "doc", &edited).await;
+
+        // A full value that is not UTF-8 cannot be stored exactly: it is rejected
+        let raw = ChangeEvent::new(1, OpKind::Set, "raw", Some(vec![0xff, 0x00, 0xfe]), node_a.clock.now(), "node-a", None, None);
+        node_b.deliver(&ChangeCodec::Cbor.encode(&raw).unwrap());
+        node_a.publish_set("after", "ok").await.unwrap();
+        node_b.deliver(&next_payload(&a_published));
+        wait_for_value(&store_b, "after", "ok").await;
+        assert_eq!(store_b.lock().await.get("raw"), None);
+    }
 }
@@ -0,0 +1,250 @@
+//! # Scripts (`EVAL <op>; <op>; ...`)
+//!
+//! A fixed list of primitive operations run as one transaction, for logic
+//! that would otherwise need several round trips and race with other
+//! clients. There is no language: ops run in order, with no branching.
+//!
+//! ```text
+//! GET <key>
+//! SET <key> <value>           (the value runs to the next `;`)
+//! DEL <key>
+//! INCR <key> [amount]
+//! CAS <key> <expected> <value>
+//! ```
+//!
+//! The script holds the store lock from the first op to the last, so no
+//! other command sees it half-applied. The reply is the last op's reply. If
+//! an op fails (a non-numeric INCR, a CAS whose key does not hold
+//! `expected`, a rejected SET), the keys written so far are restored with
+//! their values and TTLs and the script replies with the error. A script
+//! has at most `MAX_OPS` ops. Its net writes are replicated as one group.
+//!
+//! A script runs on the connection's current database (`SELECT`), all of
+//! it. `SELECT` is not an op: switching part way would split the script over
+//! two stores that cannot be locked or rolled back together, so a script
+//! naming it is rejected before anything runs. Select the database first,
+//! then `EVAL`; the connection stays on it afterwards.
+//!
+//! There is no `MULTI`/`EXEC`: `EVAL` is the server's only transaction, so
+//! this is the whole of "SELECT inside a transaction". An unknown op was
+//! always refused, `SELECT` included; the rule above only names it.
+
+use anyhow::{anyhow, bail, Result};
+
+use crate::store::KVEngineStoreTrait;
+
+/// Most ops accepted in one script.
+pub const MAX_OPS: usize = 64;
+
+/// One primitive operation of a script.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ScriptOp {
+    Get { key: String },
+    Set { key: String, value: String },
+    Del { key: String },
+    Incr { key: String, amount: i64 },
+    /// Set `value` only if the key holds `expected`
+    Cas { key: String, expected: String, value: String },
+}
+
+impl ScriptOp {
+    pub fn key(&self) -> &str {
+        match self {
+            ScriptOp::Get { key } | ScriptOp::Set { key, .. } | ScriptOp::Del { key } | ScriptOp::Incr { key, .. } | ScriptOp::Cas { key, .. } => key,
+        }
+    }
+
+    /// Whether the op may change its key.
+    pub fn writes(&self) -> bool {
+        !matches!(self, ScriptOp::Get { .. })
+    }
+
+    fn name(&self) -> &'static str {
+        match self {
+            ScriptOp::Get { .. } => "GET",
+            ScriptOp::Set { .. } => "SET",
+            ScriptOp::Del { .. } => "DEL",
+            ScriptOp::Incr { .. } => "INCR",
+            ScriptOp::Cas { .. } => "CAS",
+        }
+    }
+}
+
+/// Parse the `;`-separated ops after `EVAL`.
+pub fn parse(body: &str) -> Result<Vec<ScriptOp>> {
+    let ops: Vec<&str> = body.split(';').map(str::trim).filter(|op| !op.is_empty()).collect();
+    if ops.is_empty() {
+        bail!("EVAL script has no ops");
+    }
+    if ops.len() > MAX_OPS {
+        bail!("EVAL script has {} ops, the limit is {}", ops.len(), MAX_OPS);
+    }
+    ops.iter().enumerate().map(|(i, op)| parse_op(op).map_err(|e| anyhow!("EVAL op {}: {}", i + 1, e))).collect()
+}
+
+fn parse_op(op: &str) -> Result<ScriptOp> {
+    let (name, rest) = op.split_once(char::is_whitespace).unwrap_or((op, ""));
+    let name = name.to_ascii_uppercase();
+    if name == "SELECT" {
+        bail!("SELECT cannot run inside a script; SELECT the database before EVAL");
+    }
+    if !["GET", "SET", "DEL", "INCR", "CAS"].contains(&name.as_str()) {
+        bail!("unsupported op {} (expected GET, SET, DEL, INCR or CAS)", name);
+    }
+    let rest = rest.trim_start();
+    // SET and CAS take the rest of the op as their value
+    let (key, value) = rest.split_once(char::is_whitespace).map_or((rest, ""), |(k, v)| (k, v.trim_start()));
+    if key.is_empty() {
+        bail!("{} requires a key", name);
+    }
+    let key = key.to_string();
+    let args: Vec<&str> = value.split_whitespace().collect();
+    match name.as_str() {
+        "GET" if args.is_empty() => Ok(ScriptOp::Get { key }),
+        "DEL" if args.is_empty() => Ok(ScriptOp::Del { key }),
+        "SET" if !value.is_empty() => Ok(ScriptOp::Set { key, value: value.to_string() }),
+        "INCR" if args.len() <= 1 => {
+            let amount = match args.first() {
+                Some(amount) => amount.parse().map_err(|_| anyhow!("INCR amount must be an integer: {}", amount))?,
+                None => 1,
+            };
+            Ok(ScriptOp::Incr { key, amount })
+        }
+        "CAS" if args.len() >= 2 => {
+            let (expected, value) = value.split_once(char::is_whitespace).expect("two arguments");
+            Ok(ScriptOp::Cas { key, expected: expected.to_string(), value: value.trim_start().to_string() })
+        }
+        "GET" | "DEL" => bail!("{} takes only a key", name),
+        "SET" => bail!("SET requires a value"),
+        "INCR" => bail!("Usage: INCR <key> [amount]"),
+        _ => bail!("Usage: CAS <key> <expected> <value>"),
+    }
+}
+
+/// A script that ran to the end.
+#[derive(Debug, PartialEq)]
+pub struct Outcome {
+    /// Reply of the last op
+    pub reply: String,
+    /// Final state of every written key, in first-write order (None = deleted)
+    pub writes: Vec<(String, Option<String>)>,
+}
+
+/// Run `ops` against `store`; the caller holds the store lock for the whole
+/// call. On error every write of the script has been undone.
+pub fn run(store: &dyn KVEngineStoreTrait, ops: &[ScriptOp]) -> Result<Outcome> {
+    // Key → value and expiry before the script first wrote it
+    let mut undo: Vec<(String, Option<String>, Option<u64>)> = Vec::new();
+    let mut reply = String::new();
+    for (i, op) in ops.iter().enumerate() {
+        if op.writes() && !undo.iter().any(|(key, ..)| key == op.key()) {
+            undo.push((op.key().to_string(), store.get(op.key()), store.expiry(op.key())));
+        }
+        match apply(store, op) {
+            Ok(r) => reply = r,
+            Err(e) => {
+                rollback(store, undo);
+                bail!("EVAL op {} ({} {}) failed: {:#}; script rolled back", i + 1, op.name(), op.key(), e);
+            }
+        }
+    }
+    let writes = undo.into_iter().map(|(key, ..)| {
+        let value = store.get(&key);
+        (key, value)
+    });
+    Ok(Outcome { reply, writes: writes.collect() })
+}
+
+fn apply(store: &dyn KVEngineStoreTrait, op: &ScriptOp) -> Result<String> {
+    match op {
+        ScriptOp::Get { key } => Ok(match store.get(key) {
+            Some(value) => format!("VALUE {}\r\n", value),
+            None => "NOT_FOUND\r\n".to_string(),
+        }),
+        ScriptOp::Set { key, value } => {
+            store.set(key.clone(), value.clone())?;
+            Ok("OK\r\n".to_string())
+        }
+        ScriptOp::Del { key } => Ok(if store.delete(key) { "DELETED\r\n" } else { "NOT_FOUND\r\n" }.to_string()),
+        ScriptOp::Incr { key, amount } => Ok(format!("VALUE {}\r\n", store.increment(key, Some(*amount))?)),
+        ScriptOp::Cas { key, expected, value } => {
+            if store.get(key).as_deref() != Some(expected.as_str()) {
+                bail!("value is not {:?}", expected);
+            }
+            store.set(key.clone(), value.clone())?;
+            Ok("OK\r\n".to_string())
+        }
+    }
+}
+
+/// Put each `(key, value, expiry)` back as it was, last first (None = absent).
+pub fn rollback(store: &dyn KVEngineStoreTrait, undo: Vec<(String, Option<String>, Option<u64>)>) {
+    for (key, value, expiry) in undo.into_iter().rev() {
+        match value {
+            Some(value) => {
+                let _ = store.set(key.clone(), value);
+                if expiry.is_some() {
+                    let _ = store.set_expiry(&key, expiry);
+                }
+            }
+            None => {
+                store.delete(&key);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::store::{ExpiringEngine, RwLockEngine};
+
+    fn store() -> ExpiringEngine {
+        ExpiringEngine::new(Box::new(RwLockEngine::new("unused").unwrap()), false)
+    }
+
+    #[test]
+    fn test_parse_ops_and_limits() {
+        assert_eq!(
+            parse("SET greeting hello world; incr n 5; CAS greeting hello bye now ;GET greeting").unwrap(),
+            vec![
+                ScriptOp::Set { key: "greeting".to_string(), value: "hello world".to_string() },
+                ScriptOp::Incr { key: "n".to_string(), amount: 5 },
+                ScriptOp::Cas { key: "greeting".to_string(), expected: "hello".to_string(), value: "bye now".to_string() },
+                ScriptOp::Get { key: "greeting".to_string() },
+            ]
+        );
+        assert_eq!(parse(" ; ").unwrap_err().to_string(), "EVAL script has no ops");
+        assert_eq!(parse("GET a; SET b").unwrap_err().to_string(), "EVAL op 2: SET requires a value");
+        assert_eq!(parse("GET a; INCR n x").unwrap_err().to_string(), "EVAL op 2: INCR amount must be an integer: x");
+        assert!(parse("HSET a f v").unwrap_err().to_string().contains("unsupported op HSET"));
+        assert_eq!(
+            parse("SET a 1; select 1; SET a 2").unwrap_err().to_string(),
+            "EVAL op 2: SELECT cannot run inside a script; SELECT the database before EVAL"
+        );
+        let too_many = vec!["INCR n"; MAX_OPS + 1].join(";");
+        assert_eq!(parse(&too_many).unwrap_err().to_string(), format!("EVAL script has {} ops, the limit is {}", MAX_OPS + 1, MAX_OPS));
+    }
+
+    #[test]
+    fn test_failed_op_rolls_back_the_whole_script() {
+        let store = store();
+        store.set("balance".to_string(), "10".to_string()).unwrap();
+        store.set("ttl".to_string(), "old".to_string()).unwrap();
+        let expires_at = crate::store::expiring::now_ms() + 60_000;
+        store.set_expiry("ttl", Some(expires_at)).unwrap();
+
+        let ops = parse("INCR balance -3; SET ttl new; DEL balance; SET fresh 1; CAS balance 7 0").unwrap();
+        let err = run(&store, &ops).unwrap_err().to_string();
+        assert_eq!(err, "EVAL op 5 (CAS balance) failed: value is not \"7\"; script rolled back");
+        assert_eq!(store.get("balance"), Some("10".to_string()));
+        assert_eq!(store.get("ttl"), Some("old".to_string()));
+        assert_eq!(store.expiry("ttl"), Some(expires_at));
+        assert_eq!(store.get("fresh"), None);
+
+        let ops = parse("INCR balance -3; CAS balance 7 0; SET log paid; GET balance").unwrap();
+        let outcome = run(&store, &ops).unwrap();
+        assert_eq!(outcome.reply, "VALUE 0\r\n");
+        assert_eq!(outcome.writes, vec![("balance".to_string(), Some("0".to_string())), ("log".to_string(), Some("paid".to_string()))]);
+    }
+}
@@ -0,0 +1,165 @@
+//! # Startup Self-Test (`--self-test`)
+//!
+//! Checks that a node can run with its config without serving clients, for
+//! deploy scripts and health checks of new hosts:
+//!
+//! - **engine**: the configured store (`engine`, `storage_path`, tiers, WAL) opens
+//! - **kv**: `SET` / `GET` / `DELETE` round trip on an in-memory store
+//! - **wal**: records written to a log in a temporary directory replay after a restart
+//! - **mqtt**: the broker accepts a connection, when replication is enabled
+//!
+//! The report has one `PASS` / `FAIL` / `SKIP` line per check; the process
+//! exits with 0 when nothing failed and 1 otherwise.
+
+use anyhow::{anyhow, bail, Result};
+use std::fmt;
+use std::time::Duration;
+
+use crate::config::Config;
+use crate::replication;
+use crate::store::{KVEngineStoreTrait, RwLockEngine, WalEngine};
+
+/// How long the MQTT check waits for the broker's CONNACK.
+const MQTT_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Outcome of one check: a detail line when it passed or was skipped.
+enum Outcome {
+    Pass(String),
+    Skip(String),
+    Fail(String),
+}
+
+/// Result of every check, in the order they ran.
+pub struct Report {
+    checks: Vec<(&'static str, Outcome)>,
+}
+
+impl Report {
+    fn record(&mut self, name: &'static str, result: Result<String>) {
+        let outcome = match result {
+            Ok(detail) => Outcome::Pass(detail),
+            Err(e) => Outcome::Fail(format!("{:#}", e)),
+        };
+        self.checks.push((name, outcome));
+    }
+
+    /// Whether no check failed.
+    pub fn passed(&self) -> bool {
+        !self.checks.iter().any(|(_, outcome)| matches!(outcome, Outcome::Fail(_)))
+    }
+
+    /// Process exit code: 0 when every check passed or was skipped, else 1.
+    pub fn exit_code(&self) -> i32 {
+        if self.passed() {
+            0
+        } else {
+            1
+        }
+    }
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (name, outcome) in &self.checks {
+            let (status, detail) = match outcome {
+                Outcome::Pass(d) => ("PASS", d),
+                Outcome::Skip(d) => ("SKIP", d),
+                Outcome::Fail(d) => ("FAIL", d),
+            };
+            writeln!(f, "{} {:<6} {}", status, name, detail)?;
+        }
+        write!(f, "self-test {}", if self.passed() { "passed" } else { "FAILED" })
+    }
+}
+
+/// Run every check against `config`; `store` is the configured engine as
+/// opened by the caller.
+pub async fn run(config: &Config, store: Result<Box<dyn KVEngineStoreTrait + Send + Sync>>) -> Report {
+    let mut report = Report { checks: Vec::new() };
+    report.record(
+        "engine",
+        store.and_then(|store| Ok(format!("{} engine opened with {} keys", config.engine, store.count_keys()?))),
+    );
+    report.record("kv", check_kv());
+    report.record("wal", check_wal(config));
+    if config.replication.enabled {
+        let broker = format!("{}:{}", config.replication.mqtt_broker, config.replication.mqtt_port);
+        let result = replication::probe_broker(config, MQTT_TIMEOUT).await;
+        report.record("mqtt", result.map(|()| format!("connected to {}", broker)));
+    } else {
+        report.checks.push(("mqtt", Outcome::Skip("replication disabled".to_string())));
+    }
+    report
+}
+
+fn check_kv() -> Result<String> {
+    let store = RwLockEngine::new("unused")?;
+    store.set("self-test".to_string(), "value".to_string())?;
+    if store.get("self-test").as_deref() != Some("value") {
+        bail!("GET did not return the value just SET");
+    }
+    if !store.delete("self-test") || store.get("self-test").is_some() {
+        bail!("DELETE did not remove the key");
+    }
+    Ok("SET, GET and DELETE round trip".to_string())
+}
+
+fn check_wal(config: &Config) -> Result<String> {
+    let dir = std::env::temp_dir().join(format!("merklekv-self-test-{}", uuid::Uuid::new_v4()));
+    std::fs::create_dir_all(&dir)?;
+    let result = (|| {
+        let path = dir.join("wal.log");
+        let format = config.storage.wal_format;
+        let wal = WalEngine::open(Box::new(RwLockEngine::new("unused")?), &path, format)?;
+        wal.set("kept".to_string(), "1".to_string())?;
+        wal.set("gone".to_string(), "2".to_string())?;
+        wal.delete("gone");
+        wal.sync()?;
+        drop(wal);
+
+        let replayed = WalEngine::open(Box::new(RwLockEngine::new("unused")?), &path, format)?;
+        if replayed.keys() != vec!["kept".to_string()] || replayed.get("kept").as_deref() != Some("1") {
+            return Err(anyhow!("replay gave keys {:?}, expected [\"kept\"]", replayed.keys()));
+        }
+        Ok(format!("write and replay ({:?}) in {}", format, dir.display()))
+    })();
+    let _ = std::fs::remove_dir_all(&dir);
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn in_memory_store() -> Result<Box<dyn KVEngineStoreTrait + Send + Sync>> {
+        Ok(Box::new(RwLockEngine::new("unused")?))
+    }
+
+    #[tokio::test]
+    async fn test_self_test_passes_with_a_working_config() {
+        let mut config = Config::default();
+        config.engine = "rwlock".to_string();
+        let report = run(&config, in_memory_store()).await;
+        let text = report.to_string();
+        assert_eq!(report.exit_code(), 0, "{}", text);
+        assert!(text.contains("PASS engine"), "{}", text);
+        assert!(text.contains("PASS wal"), "{}", text);
+        assert!(text.contains("SKIP mqtt   replication disabled"), "{}", text);
+        assert!(text.ends_with("self-test passed"), "{}", text);
+    }
+
+    #[tokio::test]
+    async fn test_self_test_fails_on_unreachable_broker_or_engine() {
+        let mut config = Config::default();
+        config.replication.enabled = true;
+        config.replication.mqtt_broker = "127.0.0.1".to_string();
+        config.replication.mqtt_port = std::net::TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port();
+        let report = run(&config, Err(anyhow!("storage path is locked"))).await;
+        let text = report.to_string();
+        assert_eq!(report.exit_code(), 1, "{}", text);
+        assert!(text.contains("FAIL engine storage path is locked"), "{}", text);
+        assert!(text.contains("PASS kv"), "{}", text);
+        assert!(text.contains("FAIL mqtt   MQTT broker 127.0.0.1:"), "{}", text);
+        assert!(text.ends_with("self-test FAILED"), "{}", text);
+    }
+}
@@ -15,32 +15,166 @@
 //!
 //! The server implements a Redis-like text protocol:
 //! - Basic Commands: `GET key`, `SET key value`, `DELETE key`
+//! - Expiring SET: `SET key value EX <seconds>|PX <millis>` stores the value with a TTL, replicated as a set and an
+//!   expire; `EX 0` is an error rather than part of the value
+//! - SET options are trailing words, matched in uppercase only: `EX <n>`, `PX <n>`, `DURABLE`, `LOCAL`, `CL=<level>`.
+//!   Lowercase or mixed-case words are part of the value, so `SET k ship it durable` stores `ship it durable` as
+//!   it always did. An option after a value of several words (`SET k ship it DURABLE`) is ambiguous and refused with
+//!   `ERR_PARSE`; send such values with `SET key STREAM len`, which stores the bytes verbatim
 //! - Numeric Operations: `INC key [amount]`, `DEC key [amount]`
+//! - Command names are case-insensitive; aliases: `DELETE`/`RM` = `DEL`, `INCR` = `INC`, `DECR` = `DEC`, `FLUSHALL` = `FLUSHDB`
 //! - String Operations: `APPEND key value`, `PREPEND key value`
+//! - JSON: `JSON.INCR key /json/pointer amount` → `VALUE <new number>`, updating the document under one lock and replicating it
+//! - Swap: `SWAP key1 key2` → `OK`, exchanging values (and absence) under one lock; clears both TTLs
+//! - Script: `EVAL <op>; <op>; ...` (GET/SET/DEL/INCR/CAS) → the last op's reply, or `ERROR` with every write undone;
+//!   a script runs on the connection's current database and cannot `SELECT` another
+//! - Node-local write: `SET key value [EX n] LOCAL` stores the value without a change event and keeps it out of the
+//!   Merkle tree until the key's next write of another kind
+//! - Durability: `SET key value [EX n] DURABLE` flushes the engine to disk before replying `OK`; with
+//!   `storage.group_commit_window_us`, concurrent durable writes share one flush
+//! - TTL with value: `GET key WITHTTL` → `VALUE_TTL <seconds|-1> data`, read under one lock; `NOT_FOUND` if missing
+//! - Checksums: `GET key WITHCRC` → `VALUE_CRC <crc32 hex> data`, the CRC-32 (IEEE) of the stored bytes
+//! - Streaming: `GET key STREAM` → `STREAM len\r\nCHUNK n\r\n<bytes>\r\n...END`, `SET key STREAM len` → `READY max`,
+//!   then `CHUNK` frames from the client, then the usual SET reply
+//! - Pipelining: replies of pipelined commands are written together, up to `server.reply_batch_max` per write
 //! - Bulk Operations: `MGET key1 key2 ...`, `MSET key1 value1 key2 value2 ...`, `TRUNCATE`
+//! - Scan: `SCAN prefix` → `KEYS count\r\nkey...`, written in batches of 256; `CANCEL` sent after the SCAN
+//!   stops it at the next batch with `CANCELLED <keys sent>` (`ERROR no SCAN in progress` otherwise)
+//! - Value Index: with `index.value_prefix_enabled`, `FINDBYVALUE prefix` → `KEYS count\r\nkey...`
+//! - Tasks: `TASKS` → `TASKS count\r\n<name> state:... runs:N last_run:... ...`, `TASKS RUN <name>` wakes a task now
+//! - Explain: `EXPLAIN <command>` → `PLAN <op:key>... replicate:yes|no`, validating without running it
+//! - Info: `INFO` → `INFO\r\nname:value...`, `INFO JSON` → `INFO_JSON {...}` on one line
+//! - Uptime: `UPTIME` → `UPTIME <seconds since start> <start unix time>`; a restart resets both
+//! - TTL: `EXPIRE key seconds [NX|XX|GT|LT]` / `PERSIST key` → `VALUE 1|0` (TTL changed; PERSIST on a key without one is 0), `TTL key` → `VALUE secs|-1|-2`
+//! - TTL Policy: `storage.max_ttl_seconds` clamps (or rejects) longer TTLs; `storage.default_ttl_seconds` applies to plain `SET`
+//! - Key Times: `OBJECT CREATED|MODIFIED|ACCESSED key` → `VALUE <unix ms>`, `OBJECT IDLETIME key` → `VALUE secs` since the last write; overwrites keep the creation time
+//! - Replication Origin: `OBJECT REPLINFO key` → `REPLINFO <origin node> <applied unix ms>` of the last write (this
+//!   node's `client_id` for a local write, `NOT_FOUND` for a missing key)
+//! - Write Acks: `OBJECT ACKS key` → `ACKS <n> [peer ...]`, the peers that applied the key's latest write asking for
+//!   acks (`CL=quorum|all`, or any with `replication.ack_all_writes`); `NOT_TRACKED` if that write did not
+//! - Durability: `OBJECT DURABLE key` → `DURABLE yes|no`, whether the key's last write is fsynced to the WAL
+//!   (`storage.wal_path`); writes are fsynced by `DURABLE` writes and `storage.max_dirty_writes`
+//! - Touch: `TOUCH key [key ...]` → `TOUCHED <existing>`, moving access times and LRU order (`storage.hot_tier_max_bytes`) without reading values
+//! - Warm: `WARM key [key ...]` → `WARMED <loaded>`, loading cold keys (`storage.cold_tier_path`) into memory ahead of their reads;
+//!   `INFO` `cold_tier_reads:` counts the reads that still went to disk
+//! - Random Key: `RANDOMKEY` → `VALUE <key>`, a uniformly random live key, or `NOT_FOUND` when the database is empty
+//! - Memory: `MEMORY`, `MEMORY HISTOGRAM [n]`, `MEMORY COMPACT` → `MEMORY COMPACTED before:N after:N`
+//! - Range Hash: `RANGEHASH <start> <end>` → `RANGEHASH <count> <sha256 hex>` over the pairs with start <= key <= end, in key order
+//! - Big Keys: `BIGKEYS [n]` → `BIGKEYS count sampled:N\r\n<key> <value bytes>...`, the n (default 10) largest values, largest first
+//! - Roles: a `cluster.role = "secondary"` forwards client writes to `cluster.primary_addr` and returns its reply, or refuses them with `ERROR READONLY ...`
+//! - Write Watermark: above `storage.write_reject_bytes` of memory, writes reply `ERROR ERR_OOM ...`; reads and deletes still run
+//! - Storage: `STORAGE STATS` → `STORAGE logical_bytes:N disk_bytes:N fragmentation_ratio:R`, `STORAGE COMPACT`
+//! - Shards: `SHARD STATS` → `SHARDS <n> keys:N hash_fn:<fn> skew:R\r\n<shard> keys:N memory_bytes:N\r\n...`, where
+//!   skew is the largest shard's key count over the mean (1.00 = even); `ERROR` for engines that are not sharded
+//! - Databases: `SELECT <n>` switches the connection to database n (`0..server.databases`); only database 0 is replicated,
+//!   unless `replication.topic_per_database` publishes each database on `{topic_prefix}/db{N}/events`
+//! - Database Diff: `DBDIFF <db1> <db2>` → `DBDIFF only_db<db1>:<n> only_db<db2>:<m> differ:<k>` then `ONLY <db> <key>` and
+//!   `DIFFERS <key>` lines in key order, comparing values in batches rather than under one long lock
+//! - Metrics: `METRICS DUMP [TEXT|JSON]` → `OK <n> bytes`, rewriting `metrics.dump_path` (the only file it writes), as the
+//!   `metrics_dump` task also does; with `metrics.unix_socket_path`, HTTP `GET /metrics` is served on that Unix socket, and `GET /ready`
+//!   answers `503` while the replication lag is above `replication.max_lag_seconds`
+//! - Backup Snapshots: with `storage.snapshot_interval_seconds` the `snapshot` task writes a DUMP under `{storage_path}/snapshots`, keeping `storage.snapshot_retain`
+//! - Persistence: with `storage.wal_path`, `PERSISTENCE OFF` snapshots and stops logging writes, `PERSISTENCE ON` resumes (`INFO` `wal_persistence:`)
+//! - Disk Errors: a write whose WAL append fails (full disk) is not applied and replies `ERROR ERR_DISK write failed: <cause>`;
+//!   `INFO` counts them in `wal_write_errors:` and reports `wal_status:degraded` until an append succeeds
+//! - Auth: with `server.password` or `auth.users_file` set, `AUTH <password>` / `AUTH <user> <password>`
+//!   must come first; others get `ERROR NOAUTH ...`
+//! - ACLs: users-file `commands=` / `keys=` rules limit a user; other commands get `ERROR ERR_NOPERM <reason>`
+//! - Runtime Config: `CONFIG GET <param>` → `VALUE <v>`, `CONFIG SET <param> <value>` → `OK`
+//! - TLS Rotation: `CONFIG RELOAD TLS` (or SIGHUP) → `OK`, re-reading the certificate, key and MQTT CA
+//!   for new connections; invalid files give `ERROR ...` and the old ones stay in use
+//! - Log Levels: `LOG LEVEL <module> <level|default>` → `OK`, overriding the global level for one module
+//! - Sync Control: `SYNC STATUS` → `SYNC_STATUS idle|running peer:... phase:... keys_compared:N ...`, `SYNC ABORT` → `OK`
+//! - Cluster: `CLUSTER PEERS` → `PEERS count\r\n<addr> <client_id> <configured|discovered> last_seen_ms:... status:...\r\n...`
+//! - Verify: with `server.test_commands`, `VERIFY CONSISTENT <host:port>` → `CONSISTENT keys:N root:...` or
+//!   `INCONSISTENT missing:N extra:N differing:N ...\r\nMISSING|EXTRA|DIFFERENT key\r\n...`
+//! - Key Encoding: keys and values that are not UTF-8 are stored and returned byte for byte (see
+//!   `key_encoding`); with `server.require_utf8_keys`, a write of such a key gets
+//!   `ERROR ERR_ENCODING invalid key encoding` and nothing is stored
+//! - Debug: with `server.test_commands`, `DEBUG SLEEP <ms>` → `OK` after that long
+//! - Timeouts: with `server.command_timeout_ms`, read-only commands still waiting (e.g. for the store
+//!   lock or a peer) after that long reply `ERROR ERR_TIMEOUT ...`; SCAN stops between batches with
+//!   `ERROR ERR_TIMEOUT SCAN stopped after <keys sent> keys ...`. Writes are never aborted, so no
+//!   command is left half-applied; work already under the store lock runs to completion
+//! - Snapshot: `DUMP` → `DUMP count\r\nSET key expires_at_ms value\r\nTOMBSTONE key deleted_at_ms\r\n...`
+//! - Export: `EXPORT JSON` → `EXPORT count\r\n{"key":...,"value":...,"ttl":secs|null}\r\n...`
+//! - Sync Support: `TOMBSTONES` → `TOMBSTONES count\r\nkey deleted_at_ms\r\n...`
+//! - Replication: `REPLICATE enable|disable|status`, `REPLICATION PAUSE` (queue changes) / `REPLICATION RESUME` (flush them),
+//!   `REPLICATION DEDUP STATS` → `DEDUP size:N capacity:N lookups:N hits:N hit_rate:R`, `REPLICATION DEDUP CLEAR`,
+//!   `REPLICATION CHECK CAUSALITY` → `CAUSALITY events:N sources:N violations:N` + `VIOLATION ...` lines
+//! - Consistency: `CLIENT TOKENS ON` adds `TOKEN <node>:<ts>` after write replies; `GET key WITHTOKEN <t>`
+//!   waits for that write to replicate, else replies `NOT_CAUGHT_UP`
+//! - Compression: `CLIENT COMPRESS ON` makes large GET hits reply `VALUE_GZIP <len> <base64>`
+//! - Connection Compression: `HELLO COMPRESS zlib` → `HELLO proto:1 version:... commit:... features:... compression:zlib`, after which both directions are
+//!   a sync-flushed zlib stream (`server.transport_compression`); `compression:none` keeps the connection plain
+//! - Webhook: with `hooks.url` set, matching writes are POSTed as `{op, key, value, timestamp}` JSON
+//! - Subtree Fetch: `MERKLE FETCH [path]` (`0` = left, `1` = right from the root) → `SUBTREE <hash> count\r\nkey value\r\n...` for targeted repair
+//! - Push Mode: `SUBSCRIBE MERKLE` streams `MERKLE_ROOT <root> <changes>` on root changes until `UNSUBSCRIBE`
+//! - Resume: `SUBSCRIBE MERKLE <id> <version>` replays the root changes a dropped subscription missed (`merkle.subscribe_buffer`)
 //! - Responses: `VALUE data`, `VALUES count\r\nkey1 value1\r\nkey2 value2...`, `OK`, `NOT_FOUND`, `ERROR message`
-//! - All messages are terminated with `\r\n`
+//! - All messages are terminated with `\r\n`; requests may end in `\n` alone (unless
+//!   `server.strict_crlf`), and a last request without a terminator is run before EOF closes
+//! - Line Limit: a request line over 1 MiB gets `ERROR line too long` and the connection is closed
+//!   as soon as that much has arrived, terminated or not
+//! - Argument Limit: a command with more than `server.max_args` words after its name is refused with `ERR_PARSE`
+//!   at the first word past the limit, before its arguments are collected
 //!
 //! ## Concurrency
 //!
 //! The storage engine is wrapped in `Arc<Mutex<>>` to allow safe concurrent access
 //! from multiple client connections. Each connection gets its own task but shares
 //! the same underlying storage.
-use hex; 
-use crate::sync::SyncManager;
-use crate::protocol::{SyncOptions, ReplicateAction};     // the options parsed by SYNC (full/verify)
-use crate::store::KVEngineStoreTrait;
-use anyhow::Result;
-use log::{error, info};
+//!
+//! ## Pipelining and Backpressure
+//!
+//! A connection reads, executes and answers one command at a time, so at most
+//! one parsed command per connection is ever waiting to run. Pipelined
+//! commands wait in the kernel socket buffers, not in server memory. A client
+//! that sends faster than it reads replies blocks the server's reply write,
+//! which stops reads on that socket until the client catches up.
+use crate::allowlist::IpAllowlist;
+use crate::acl::Acl;
+use crate::auth::{self, AuthProvider, AuthResult};
+use crate::cluster::{PrimaryLink, Role};
+use crate::compression;
+use crate::consistency::ConsistencyTracker;
+use crate::databases::{self, Databases};
+use crate::transport::{self, TransportReader, TransportWriter};
+use crate::export;
+use crate::key_encoding;
+use crate::group_commit::GroupCommit;
+use crate::key_filter::KeyFilter;
+use crate::metrics;
+use crate::net_addr;
+use crate::peers::PeerTable;
+use crate::subscriptions::{ParkedSubscriptions, Session};
+use crate::pipeline::ReplyWriter;
+use crate::proxy_protocol;
+use crate::runtime_config::RuntimeConfig;
+use crate::script;
+use crate::snapshot;
+use crate::streaming;
+use crate::sync::{SyncManager, SyncProgress};
+use crate::tasks::TaskRegistry;
+use crate::tls::TlsState;
+use crate::verify;
+use crate::watermark::WriteWatermark;
+use crate::webhook::Webhook;
+use crate::protocol::{MerkleAction, ObjectField, ReplicateAction, SubscribeChannel, WriteConsistency};
+use crate::store::histogram::{self, KeyspaceHistogram};
+use crate::store::{expiring, field_map, json_doc, merkle_tracked};
+use crate::store::{ExpiringEngine, KVEngineStoreTrait, MerkleTrackedEngine, SharedMerkle, StorageStats, TimestampEngine, TombstoneEngine, ValueIndexEngine};
+use anyhow::{anyhow, Context, Result};
+use log::{debug, error, info, warn};
 use std::net::SocketAddr;
 use std::sync::Arc;
 use std::sync::atomic::{AtomicU64, Ordering};
 use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
-use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
-use tokio::net::{TcpListener, TcpStream};
-use tokio::sync::Mutex;
+use tokio::io::{AsyncBufRead, AsyncBufReadExt, AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, BufReader};
+use tokio::net::TcpListener;
+use tokio::sync::{watch, Mutex};
 use std::collections::HashMap; 
-use crate::config::Config;
+use crate::config::{Config, ServerConfig};
 use crate::protocol::{Command, Protocol};
 use crate::replication::Replicator;
 
@@ -110,6 +244,9 @@ pub struct ServerStats {
     /// Server start time
     pub start_time: Instant,
 
+    /// Wall-clock start time (UNIX milliseconds), for `UPTIME` and INFO
+    pub started_unix_ms: u64,
+
     /// Number of SYNC commands processed
     pub sync_commands: AtomicU64,
 
@@ -128,6 +265,27 @@ struct ClientMeta {
 }
 type ClientTable = Arc<tokio::sync::Mutex<HashMap<u64, Arc<ClientMeta>>>>;
 
+/// How long a client may take to send its PROXY header before being dropped.
+const PROXY_HEADER_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Keys written per batch of a SCAN reply; between batches the server
+/// yields and checks whether the client sent CANCEL or went away.
+const SCAN_BATCH: usize = 256;
+
+/// Longest request line read; a longer one is refused and the connection
+/// dropped once this much arrives, without waiting for its end.
+const MAX_LINE_BYTES: usize = 1024 * 1024;
+
+/// Reply to a write refused by `server.require_utf8_keys`.
+const ENCODING_ERROR: &str = "ERROR ERR_ENCODING invalid key encoding\r\n";
+
+/// How long a client may take to finish the TLS handshake.
+const TLS_HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// A client connection: plain TCP or TLS over it.
+trait ClientStream: AsyncRead + AsyncWrite + Unpin + Send {}
+impl<S: AsyncRead + AsyncWrite + Unpin + Send> ClientStream for S {}
+
 impl Clone for ServerStats {
     fn clone(&self) -> Self {
         Self {
@@ -154,6 +312,7 @@ impl Clone for ServerStats {
             replicate_commands: AtomicU64::new(self.replicate_commands.load(Ordering::Relaxed)),
             management_commands: AtomicU64::new(self.management_commands.load(Ordering::Relaxed)),
             start_time: self.start_time,
+            started_unix_ms: self.started_unix_ms,
         }
     }
 }
@@ -190,6 +349,7 @@ impl ServerStats {
             stat_commands: AtomicU64::new(0),
             management_commands: AtomicU64::new(0),
             start_time: Instant::now(),
+            started_unix_ms: expiring::now_ms(),
             sync_commands: AtomicU64::new(0),
             replicate_commands: AtomicU64::new(0),
         }
@@ -200,6 +360,12 @@ impl ServerStats {
         self.start_time.elapsed().as_secs()
     }
     
+    /// `UPTIME` reply: seconds since start (millisecond precision) and the
+    /// UNIX time the server started.
+    pub fn uptime_reply(&self) -> String {
+        format!("UPTIME {:.3} {}\r\n", self.start_time.elapsed().as_secs_f64(), self.started_unix_ms / 1000)
+    }
+
     /// Format uptime as a human-readable string (days:hours:minutes:seconds)
     pub fn uptime_human(&self) -> String {
         let seconds = self.uptime_seconds();
@@ -216,10 +382,10 @@ impl ServerStats {
         self.total_commands.fetch_add(1, Ordering::Relaxed);
         
         match command {
-            Command::Get { .. } => {
+            Command::Get { .. } | Command::GetWithToken { .. } | Command::GetStream { .. } | Command::GetWithCrc { .. } | Command::GetWithTtl { .. } | Command::Ttl { .. } | Command::Object { .. } | Command::HGet { .. } | Command::HGetAll { .. } => {
                 self.get_commands.fetch_add(1, Ordering::Relaxed);
             }
-            Command::Scan { .. } => {
+            Command::Scan { .. } | Command::FindByValue { .. } | Command::RandomKey | Command::Cancel | Command::DbDiff { .. } => {
                 self.scan_commands.fetch_add(1, Ordering::Relaxed);
             }
             Command::Ping { .. } => {
@@ -231,10 +397,10 @@ impl ServerStats {
             Command::Dbsize => {
                 self.dbsize_commands.fetch_add(1, Ordering::Relaxed);
             }
-            Command::Exists { .. } => {
+            Command::Exists { .. } | Command::Touch { .. } | Command::Warm { .. } => {
                 self.exists_commands.fetch_add(1, Ordering::Relaxed);
             }
-            Command::Set { .. } => {
+            Command::Set { .. } | Command::SetEx { .. } | Command::SetStream { .. } | Command::Durable { .. } | Command::Consistent { .. } | Command::Local { .. } | Command::HSet { .. } | Command::JsonIncr { .. } | Command::Expire { .. } | Command::Persist { .. } => {
                 self.set_commands.fetch_add(1, Ordering::Relaxed);
             }
             Command::Delete { .. } => {
@@ -243,28 +409,31 @@ impl ServerStats {
             Command::Increment { .. } | Command::Decrement { .. } => {
                 self.numeric_commands.fetch_add(1, Ordering::Relaxed);
             }
-            Command::Append { .. } | Command::Prepend { .. } => {
+            Command::Append { .. } | Command::Prepend { .. } | Command::Swap { .. } => {
                 self.string_commands.fetch_add(1, Ordering::Relaxed);
             }
-            Command::MultiGet { .. } | Command::MultiSet { .. } | Command::Truncate => {
+            Command::MultiGet { .. } | Command::MultiSet { .. } | Command::Eval { .. } | Command::Truncate => {
                 self.bulk_commands.fetch_add(1, Ordering::Relaxed);
             }
-            Command::Stats | Command::Info => {
+            Command::Stats | Command::Info | Command::InfoJson | Command::Explain { .. } => {
                 self.stat_commands.fetch_add(1, Ordering::Relaxed);
             }
-            Command::Version | Command::Flushdb | Command::Shutdown => {
+            Command::Version | Command::Hello { .. } | Command::Uptime | Command::Flushdb | Command::Shutdown => {
                 self.management_commands.fetch_add(1, Ordering::Relaxed);
             }
-            Command::Memory => {
+            Command::Memory | Command::MemoryCompact | Command::MemoryHistogram { .. } | Command::BigKeys { .. }
+            | Command::StorageStats | Command::StorageCompact | Command::ShardStats | Command::Persistence { .. } => {
                 self.memory_commands.fetch_add(1, Ordering::Relaxed);
             }
-            Command::Clientlist => {
+            Command::Clientlist | Command::ClientCompress { .. } | Command::ClientTokens { .. } | Command::Merkle { .. } | Command::Subscribe { .. } | Command::Unsubscribe
+            | Command::ConfigGet { .. } | Command::ConfigSet { .. } | Command::ConfigReloadTls | Command::LogLevel { .. } | Command::Auth { .. }
+            | Command::Tasks | Command::TaskRun { .. } | Command::Select { .. } | Command::MetricsDump { .. } => {
                 self.management_commands.fetch_add(1, Ordering::Relaxed);
             }
-            Command::Sync {..} => {
+            Command::Sync {..} | Command::SyncStatus | Command::SyncAbort | Command::ClusterPeers | Command::Tombstones | Command::Dump | Command::ExportJson | Command::VerifyConsistent { .. } | Command::DebugSleep { .. } => {
                 self.sync_commands.fetch_add(1, Ordering::Relaxed);
             }
-            Command::Hash {..} => {
+            Command::Hash {..} | Command::RangeHash { .. } => {
                 self.hash_commands.fetch_add(1, Ordering::Relaxed);
             }
             Command::Replicate {..} => {
@@ -273,38 +442,41 @@ impl ServerStats {
         }
     }
     
-    /// Format all statistics as a multi-line string for the STATS command
-    pub fn format_stats(&self) -> String {
-        let mut result = String::new();
-        
-        result.push_str(&format!("uptime_seconds:{}\r\n", self.uptime_seconds()));
-        result.push_str(&format!("uptime:{}\r\n", self.uptime_human()));
-        result.push_str(&format!("total_connections:{}\r\n", self.total_connections.load(Ordering::Relaxed)));
-        result.push_str(&format!("active_connections:{}\r\n", self.active_connections.load(Ordering::Relaxed)));
-        result.push_str(&format!("total_commands:{}\r\n", self.total_commands.load(Ordering::Relaxed)));
-        result.push_str(&format!("get_commands:{}\r\n", self.get_commands.load(Ordering::Relaxed)));
-        result.push_str(&format!("scan_commands:{}\r\n", self.scan_commands.load(Ordering::Relaxed)));
-        result.push_str(&format!("ping_commands:{}\r\n", self.ping_commands.load(Ordering::Relaxed)));
-        result.push_str(&format!("echo_commands:{}\r\n", self.echo_commands.load(Ordering::Relaxed)));
-        result.push_str(&format!("flushdb_commands:{}\r\n", self.flushdb_commands.load(Ordering::Relaxed)));
-        result.push_str(&format!("memory_commands:{}\r\n", self.memory_commands.load(Ordering::Relaxed)));
-        result.push_str(&format!("clientlist_commands:{}\r\n", self.clientlist_commands.load(Ordering::Relaxed)));
-        result.push_str(&format!("exists_commands:{}\r\n", self.exists_commands.load(Ordering::Relaxed)));
-        result.push_str(&format!("dbsize_commands:{}\r\n", self.dbsize_commands.load(Ordering::Relaxed)));
-        result.push_str(&format!("set_commands:{}\r\n", self.set_commands.load(Ordering::Relaxed)));
-        result.push_str(&format!("delete_commands:{}\r\n", self.delete_commands.load(Ordering::Relaxed)));
-        result.push_str(&format!("numeric_commands:{}\r\n", self.numeric_commands.load(Ordering::Relaxed)));
-        result.push_str(&format!("string_commands:{}\r\n", self.string_commands.load(Ordering::Relaxed)));
-        result.push_str(&format!("bulk_commands:{}\r\n", self.bulk_commands.load(Ordering::Relaxed)));
-        result.push_str(&format!("stat_commands:{}\r\n", self.stat_commands.load(Ordering::Relaxed)));
-        result.push_str(&format!("sync_commands:{}\r\n", self.sync_commands.load(Ordering::Relaxed)));
-        result.push_str(&format!("hash_commands:{}\r\n", self.hash_commands.load(Ordering::Relaxed)));
-        result.push_str(&format!("replicate_commands:{}\r\n", self.replicate_commands.load(Ordering::Relaxed)));
-        result.push_str(&format!("management_commands:{}\r\n", self.management_commands.load(Ordering::Relaxed)));
-        
-        // Add memory usage estimate (this is a very rough estimate)
-        let estimated_memory_kb = std::process::Command::new("ps")
-            .args(&["-o", "rss=", "-p", &std::process::id().to_string()])
+    /// Connection and per-command counters, in STATS order.
+    pub fn counters(&self) -> Vec<(&'static str, u64)> {
+        [
+            ("total_connections", &self.total_connections),
+            ("active_connections", &self.active_connections),
+            ("total_commands", &self.total_commands),
+            ("get_commands", &self.get_commands),
+            ("scan_commands", &self.scan_commands),
+            ("ping_commands", &self.ping_commands),
+            ("echo_commands", &self.echo_commands),
+            ("flushdb_commands", &self.flushdb_commands),
+            ("memory_commands", &self.memory_commands),
+            ("clientlist_commands", &self.clientlist_commands),
+            ("exists_commands", &self.exists_commands),
+            ("dbsize_commands", &self.dbsize_commands),
+            ("set_commands", &self.set_commands),
+            ("delete_commands", &self.delete_commands),
+            ("numeric_commands", &self.numeric_commands),
+            ("string_commands", &self.string_commands),
+            ("bulk_commands", &self.bulk_commands),
+            ("stat_commands", &self.stat_commands),
+            ("sync_commands", &self.sync_commands),
+            ("hash_commands", &self.hash_commands),
+            ("replicate_commands", &self.replicate_commands),
+            ("management_commands", &self.management_commands),
+        ]
+        .into_iter()
+        .map(|(name, counter)| (name, counter.load(Ordering::Relaxed)))
+        .collect()
+    }
+
+    /// Resident set size of the process in KB (a very rough estimate, 0 if unknown).
+    pub fn used_memory_kb() -> u64 {
+        std::process::Command::new("ps")
+            .args(["-o", "rss=", "-p", &std::process::id().to_string()])
             .output()
             .map(|output| {
                 String::from_utf8_lossy(&output.stdout)
@@ -312,9 +484,21 @@ impl ServerStats {
                     .parse::<u64>()
                     .unwrap_or(0)
             })
-            .unwrap_or(0);
+            .unwrap_or(0)
+    }
+
+    /// Format all statistics as a multi-line string for the STATS command
+    pub fn format_stats(&self) -> String {
+        let mut result = String::new();
+        
+        result.push_str(&format!("uptime_seconds:{}\r\n", self.uptime_seconds()));
+        result.push_str(&format!("uptime:{}\r\n", self.uptime_human()));
+        for (name, value) in self.counters() {
+            result.push_str(&format!("{}:{}\r\n", name, value));
+        }
         
-        result.push_str(&format!("used_memory_kb:{}\r\n", estimated_memory_kb));
+        // Add memory usage estimate (this is a very rough estimate)
+        result.push_str(&format!("used_memory_kb:{}\r\n", Self::used_memory_kb()));
         
         result
     }
@@ -333,6 +517,21 @@ pub struct Server {
     
     /// Server statistics for monitoring and diagnostics
     stats: ServerStats,
+
+    /// Live Merkle tree maintained by the tracked store
+    merkle: SharedMerkle,
+
+    /// Change counter of the live tree (for `SUBSCRIBE MERKLE`)
+    merkle_changes: watch::Receiver<u64>,
+
+    /// Clock and applied watermarks behind read-your-writes tokens
+    consistency: Arc<ConsistencyTracker>,
+
+    /// Listening socket, once `bind` has run
+    listener: Option<TcpListener>,
+
+    /// Replicator to use instead of connecting one from the config
+    replicator: Option<Replicator>,
 }
 
 impl Server {
@@ -345,11 +544,80 @@ impl Server {
     /// # Returns
     /// * `Server` - New server instance ready to run
     pub fn new(config: Config, store: Box<dyn KVEngineStoreTrait + Send + Sync>) -> Self {
-        Self { 
-            config, 
-            store,
+        // Every write path shares this store, so wrapping it keeps the live tree current.
+        // Expiry sits outside so lazy expiry deletions also reach the tree.
+        // Tombstones are outermost: only explicit deletes (clients, replication,
+        // sync) are tombstoned, not lazy expiry. The value index sits innermost
+        // so expiry deletions reach it too. Timestamps wrap everything, seeing
+        // every write however it arrives.
+        let store: Box<dyn KVEngineStoreTrait + Send + Sync> = if config.index.value_prefix_enabled {
+            Box::new(ValueIndexEngine::new(store, config.index.value_prefix_max_len))
+        } else {
+            store
+        };
+        let tracked = MerkleTrackedEngine::new(store)
+            .with_buckets(config.merkle.num_buckets, config.storage.hash_fn)
+            .with_key_filter(KeyFilter::from_config(&config.replication));
+        let merkle = tracked.tree();
+        let merkle_changes = tracked.changes();
+        let expiring = ExpiringEngine::new(Box::new(tracked), config.replication.publish_lazy_expiry);
+        let tombstoned = TombstoneEngine::new(Box::new(expiring), config.replication.tombstone_ttl_seconds);
+        let timestamped = TimestampEngine::new(Box::new(tombstoned));
+        let consistency = Arc::new(ConsistencyTracker::new(config.replication.client_id.clone()));
+        Self {
+            config,
+            store: Box::new(timestamped),
             stats: ServerStats::new(),
+            merkle,
+            merkle_changes,
+            consistency,
+            listener: None,
+            replicator: None,
+        }
+    }
+
+    /// Replicate through `replicator` (e.g. a broker-less test one) instead
+    /// of connecting to the configured broker.
+    #[cfg(test)]
+    pub(crate) fn with_replicator(mut self, replicator: Replicator) -> Self {
+        self.replicator = Some(replicator);
+        self
+    }
+
+    /// Bind the listening socket without accepting connections yet. With
+    /// `port = 0` the OS picks a free port; `config.port` is updated to it.
+    /// Called by `run` if not called before. Needs a Tokio runtime.
+    ///
+    /// # Returns
+    /// * `Result<SocketAddr>` - The address actually bound
+    pub fn bind(&mut self) -> Result<SocketAddr> {
+        if self.listener.is_none() {
+            let addr = net_addr::resolve_listen_addr(&self.config.host, self.config.port)?;
+            let listener = net_addr::bind_listener(addr, self.config.server.listen_backlog)?;
+            self.config.port = listener.local_addr()?.port();
+            self.listener = Some(listener);
         }
+        self.local_addr().ok_or_else(|| anyhow!("listener has no local address"))
+    }
+
+    /// Populate the store from `peer`'s `DUMP` before serving (`--bootstrap-from`).
+    /// Writes go through the decorated store, so the live Merkle tree, TTLs
+    /// and tombstones start out matching the peer.
+    ///
+    /// # Returns
+    /// * `Result<usize>` - Number of keys copied
+    pub async fn bootstrap_from(&mut self, peer: &str) -> Result<usize> {
+        net_addr::split_host_port(peer).context("invalid bootstrap peer")?;
+        let timeout = Duration::from_millis(self.config.merkle.sync_timeout_ms);
+        let records = snapshot::fetch(peer, timeout).await.with_context(|| format!("bootstrap from {}", peer))?;
+        let copied = snapshot::apply(self.store.as_ref(), records, &KeyFilter::from_config(&self.config.replication))?;
+        info!("Bootstrapped {} keys from {}", copied, peer);
+        Ok(copied)
+    }
+
+    /// Address the server is listening on, or None before `bind`/`run`.
+    pub fn local_addr(&self) -> Option<SocketAddr> {
+        self.listener.as_ref().and_then(|l| l.local_addr().ok())
     }
 
     /// Start the server and begin accepting connections.
@@ -373,77 +641,320 @@ impl Server {
     /// let server = Server::new(config, store);
     /// server.run().await?; // Runs forever
     /// ```
-    pub async fn run(self) -> Result<()> {
+    pub async fn run(mut self) -> Result<()> {
+        let addr = self.bind()?;
+        let listener = self.listener.take().expect("bound above");
+        info!("Server listening on {}", addr);
+
         let cfg = Arc::new(self.config.clone());
         let clients: ClientTable = Arc::new(tokio::sync::Mutex::new(HashMap::new()));
         let client_id_gen = Arc::new(AtomicU64::new(0));
-
-        let addr = format!("{}:{}", self.config.host, self.config.port);
-        let listener = TcpListener::bind(&addr).await?;
-        info!("Server listening on {}", addr);
+        let allowlist = Arc::new(IpAllowlist::from_cidrs(&self.config.server.allowed_cidrs)?);
+        if !self.config.server.allowed_cidrs.is_empty() {
+            info!("Client access restricted to {:?}", self.config.server.allowed_cidrs);
+        }
 
         // Wrap the storage in `Arc<Mutex<>>` for safe concurrent access
         let store = Arc::new(Mutex::new(self.store));
+        let databases = Arc::new(Databases::new(Arc::clone(&store), self.config.server.databases));
+        // DURABLE writes to database 0 share flushes within storage.group_commit_window_us
+        let group_commit = Arc::new(GroupCommit::new(
+            Arc::clone(&store),
+            Duration::from_micros(self.config.storage.group_commit_window_us),
+        ));
         
         let sync_manager = Arc::new(tokio::sync::Mutex::new(
             SyncManager::new_with_shared_store(&self.config, Arc::clone(&store))
         ));
 
+        // Runtime-mutable settings (CONFIG SET) shared with the background tasks
+        let runtime_cfg = Arc::new(RuntimeConfig::new(sync_manager.lock().await.interval_handle()));
+        // Read by SYNC STATUS / SYNC ABORT while a round holds the manager
+        let sync_progress = sync_manager.lock().await.progress_handle();
+        // Read by CLUSTER PEERS, updated by every sync round
+        let peers = sync_manager.lock().await.peers_handle();
+        // Dropped SUBSCRIBE MERKLE sessions waiting to be resumed
+        let parked = Arc::new(ParkedSubscriptions::new(
+            self.config.merkle.subscribe_buffer,
+            Duration::from_secs(self.config.merkle.subscribe_resume_seconds),
+        ));
+
+        // AUTH backend: users file, static password, or none
+        let auth = auth::provider_from_config(&self.config)?;
+
+        // Client TLS, rotated by CONFIG RELOAD TLS and SIGHUP
+        let tls = Arc::new(TlsState::from_config(&self.config)?);
+        if self.config.server.tls_cert_path.is_some() {
+            info!("Client connections use TLS");
+        }
+        #[cfg(unix)]
+        {
+            let tls = Arc::clone(&tls);
+            let mut hangups = tokio::signal::unix::signal(tokio::signal::unix::SignalKind::hangup())?;
+            tokio::spawn(async move {
+                while hangups.recv().await.is_some() {
+                    match tls.reload() {
+                        Ok(files) => info!("SIGHUP: reloaded TLS files {:?}", files),
+                        Err(e) => warn!("SIGHUP: TLS reload failed, keeping the old certificates: {:#}", e),
+                    }
+                }
+            });
+        }
+
+        // Background tasks report to this registry (TASKS)
+        let tasks = Arc::new(TaskRegistry::new());
+        let watermark = Arc::new(WriteWatermark::new(self.config.storage.write_reject_bytes));
+
+        // Periodic anti-entropy with configured peers
+        let ae = &self.config.anti_entropy;
+        if ae.enabled && !ae.peer_list.is_empty() {
+            info!("Anti-entropy enabled with {} peers", ae.peer_list.len());
+            tokio::spawn(SyncManager::run_anti_entropy_loop(
+                Arc::clone(&sync_manager),
+                ae.peer_list.clone(),
+                tasks.register("anti_entropy"),
+            ));
+        }
+
+        // Tombstone compaction: purge tombstones past the grace period
+        let compaction_store = Arc::clone(&store);
+        let compaction_every = Duration::from_secs(self.config.replication.tombstone_ttl_seconds.clamp(1, 60));
+        let sweep = tasks.register("sweep");
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(compaction_every);
+            loop {
+                tokio::select! {
+                    _ = interval.tick() => {}
+                    _ = sweep.triggered() => {}
+                }
+                let store = compaction_store.lock().await;
+                let purged = sweep.run(|| store.compact_tombstones()).unwrap_or(0);
+                drop(store);
+                if purged > 0 {
+                    info!("Compaction purged {} expired tombstones", purged);
+                }
+            }
+        });
+
+        // Merkle GC: drop tree nodes left behind by deleted keys
+        let gc_tree = Arc::clone(&self.merkle);
+        let gc_every = self.config.merkle.gc_interval_seconds;
+        let merkle_gc = tasks.register("merkle_gc");
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(Duration::from_secs(gc_every.max(1)));
+            interval.tick().await; // nothing to collect at start
+            loop {
+                if gc_every == 0 {
+                    merkle_gc.triggered().await;
+                } else {
+                    tokio::select! {
+                        _ = interval.tick() => {}
+                        _ = merkle_gc.triggered() => {}
+                    }
+                }
+                let (reclaimed, nodes) = merkle_gc.run(|| merkle_tracked::gc(&gc_tree)).unwrap_or_default();
+                if reclaimed > 0 {
+                    info!("Merkle GC reclaimed {} nodes ({} left)", reclaimed, nodes);
+                }
+            }
+        });
+
         // Share server statistics across all connections
         let stats = Arc::new(self.stats.clone());
 
+        // Metrics snapshots: rewrite metrics.dump_path on an interval
+        if let Some(path) = self.config.metrics.dump_path.clone() {
+            let stats = Arc::clone(&stats);
+            let every = self.config.metrics.dump_interval_seconds;
+            let format = self.config.metrics.dump_format;
+            let metrics_dump = tasks.register("metrics_dump");
+            tokio::spawn(async move {
+                let mut interval = tokio::time::interval(Duration::from_secs(every.max(1)));
+                loop {
+                    if every == 0 {
+                        metrics_dump.triggered().await;
+                    } else {
+                        tokio::select! {
+                            _ = interval.tick() => {}
+                            _ = metrics_dump.triggered() => {}
+                        }
+                    }
+                    let run = metrics_dump.start();
+                    let result = metrics::dump(&stats, std::path::Path::new(&path), format);
+                    if let Err(e) = &result {
+                        warn!("Metrics dump to {} failed: {:#}", path, e);
+                    }
+                    metrics_dump.finish(run, result.map(|_| ()).map_err(|e| format!("{:#}", e)));
+                }
+            });
+        }
+
+        // Metrics scrape socket for a local collector
+        if let Some(path) = &self.config.metrics.unix_socket_path {
+            #[cfg(unix)]
+            {
+                let listener = metrics::bind_unix(std::path::Path::new(path))?;
+                info!("Serving metrics on unix socket {}", path);
+                let readiness = Arc::new(metrics::Readiness {
+                    tracker: Arc::clone(&self.consistency),
+                    max_lag_seconds: self.config.replication.max_lag_seconds,
+                });
+                tokio::spawn(metrics::serve_unix(listener, Arc::clone(&stats), readiness));
+            }
+            #[cfg(not(unix))]
+            warn!("metrics.unix_socket_path {} ignored: Unix sockets are not supported here", path);
+        }
+
+        // Backup snapshots: a DUMP of the store under {storage_path}/snapshots
+        if self.config.storage.snapshot_interval_seconds > 0 {
+            let store = Arc::clone(&store);
+            let every = self.config.storage.snapshot_interval_seconds;
+            let retain = self.config.storage.snapshot_retain;
+            let dir = std::path::Path::new(&self.config.storage_path).join("snapshots");
+            let backup = tasks.register("snapshot");
+            tokio::spawn(async move {
+                let mut interval = tokio::time::interval(Duration::from_secs(every));
+                interval.tick().await; // the first snapshot is one interval after start
+                loop {
+                    tokio::select! {
+                        _ = interval.tick() => {}
+                        _ = backup.triggered() => {}
+                    }
+                    let run = backup.start();
+                    let dump = snapshot::encode(store.lock().await.as_ref());
+                    let result = snapshot::write_file(&dir, &dump)
+                        .and_then(|path| Ok((path, snapshot::prune(&dir, retain)?)));
+                    match &result {
+                        Ok((path, pruned)) => info!("Wrote snapshot {} ({} old ones deleted)", path.display(), pruned),
+                        Err(e) => warn!("Snapshot to {} failed: {:#}", dir.display(), e),
+                    }
+                    backup.finish(run, result.map(|_| ()).map_err(|e| format!("{:#}", e)));
+                }
+            });
+        }
+
         let replicator: Arc<Mutex<Option<Replicator>>> = Arc::new(Mutex::new(None));
 
         // enable on start if config says so
-        if self.config.replication.enabled {
-            let r = Replicator::new(&self.config).await?;
+        let initial = match self.replicator.take() {
+            Some(r) => Some(r),
+            None if self.config.replication.enabled => Some(Replicator::new(&self.config).await?),
+            None => None,
+        };
+        if let Some(r) = initial {
+            let r = r.with_clock(Arc::clone(&self.consistency)).with_databases(Arc::clone(&databases));
             // background apply loop
             r.start_replication_handler(Arc::clone(&store)).await;
             *replicator.lock().await = Some(r);
         }
 
+        // Optional HTTP webhook for key changes (hooks.url)
+        let webhook = Webhook::spawn(&self.config.hooks)?;
+        if let Some(url) = &self.config.hooks.url {
+            info!("Key change webhook enabled: {}", url);
+        }
+
         // TODO: Add graceful shutdown handling
         // TODO: Add connection limits and rate limiting
 
         loop {
             match listener.accept().await {
-                Ok((socket, addr)) => {
-                    info!("Accepted connection from {}", addr);
-                    
+                Ok((mut socket, peer_addr)) => {
                     // Clone the Arc for this connection
                     let store_clone = Arc::clone(&store);
                     let stats_clone = Arc::clone(&stats);
-                    
-                    // Update connection statistics
-                    stats_clone.total_connections.fetch_add(1, Ordering::Relaxed);
-                    stats_clone.active_connections.fetch_add(1, Ordering::Relaxed);
-
-                    // Spawn a new task for each client connection
                     let repl_clone = Arc::clone(&replicator);
                     let sync_manager_clone = Arc::clone(&sync_manager);
-                    let id = client_id_gen.fetch_add(1, Ordering::Relaxed) + 1;
-                    let now_unix = SystemTime::now()
-                        .duration_since(UNIX_EPOCH)
-                        .unwrap_or(Duration::from_secs(0))
-                        .as_secs();
-                    let meta = Arc::new(ClientMeta {
-                        id,
-                        addr,
-                        connected_unix: now_unix,
-                        last_cmd_unix: AtomicU64::new(now_unix),
-                    });
-                    {
-                        let mut tbl = clients.lock().await;
-                        tbl.insert(id, Arc::clone(&meta));
-                    }
+                    let sync_progress = Arc::clone(&sync_progress);
+                    let peers = Arc::clone(&peers);
+                    let parked = Arc::clone(&parked);
                     let clients_clone = Arc::clone(&clients);
-                    let meta_clone = Arc::clone(&meta);
+                    let client_id_gen = Arc::clone(&client_id_gen);
+                    let allowlist = Arc::clone(&allowlist);
                     let cfg_cl = Arc::clone(&cfg);
+                    let merkle_clone = Arc::clone(&self.merkle);
+                    let merkle_changes = self.merkle_changes.clone();
+                    let runtime_cfg = Arc::clone(&runtime_cfg);
+                    let webhook = webhook.clone();
+                    let consistency = Arc::clone(&self.consistency);
+                    let tasks = Arc::clone(&tasks);
+                    let watermark = Arc::clone(&watermark);
+                    let auth = auth.clone();
+                    let databases = Arc::clone(&databases);
+                    let tls = Arc::clone(&tls);
+                    let group_commit = Arc::clone(&group_commit);
+
+                    // Spawn a new task for each client connection
                     tokio::spawn(async move {
-                        if let Err(e) = Self::handle_connection(socket, addr, store_clone, stats_clone.clone(), repl_clone, meta_clone, clients_clone.clone(), sync_manager_clone, cfg_cl).await {
+                        // Resolve the real client address before applying access control
+                        let addr = if cfg_cl.server.proxy_protocol {
+                            match tokio::time::timeout(
+                                PROXY_HEADER_TIMEOUT,
+                                proxy_protocol::read_v1_header(&mut socket),
+                            )
+                            .await
+                            {
+                                Ok(Ok(Some(client_addr))) => client_addr,
+                                Ok(Ok(None)) => peer_addr,
+                                Ok(Err(e)) => {
+                                    warn!("Rejected connection from {}: {}", peer_addr, e);
+                                    return;
+                                }
+                                Err(_) => {
+                                    warn!("Rejected connection from {}: timed out waiting for PROXY header", peer_addr);
+                                    return;
+                                }
+                            }
+                        } else {
+                            peer_addr
+                        };
+
+                        if !allowlist.is_allowed(addr.ip()) {
+                            warn!("Rejected connection from {}: address not in server.allowed_cidrs", addr);
+                            return;
+                        }
+
+                        let socket: Box<dyn ClientStream> = match tls.acceptor() {
+                            Some(acceptor) => match tokio::time::timeout(TLS_HANDSHAKE_TIMEOUT, acceptor.accept(socket)).await {
+                                Ok(Ok(stream)) => Box::new(stream),
+                                Ok(Err(e)) => {
+                                    warn!("Rejected connection from {}: TLS handshake failed: {}", addr, e);
+                                    return;
+                                }
+                                Err(_) => {
+                                    warn!("Rejected connection from {}: timed out waiting for the TLS handshake", addr);
+                                    return;
+                                }
+                            },
+                            None => Box::new(socket),
+                        };
+
+                        info!("Accepted connection from {}", addr);
+
+                        // Update connection statistics
+                        stats_clone.total_connections.fetch_add(1, Ordering::Relaxed);
+                        stats_clone.active_connections.fetch_add(1, Ordering::Relaxed);
+
+                        let id = client_id_gen.fetch_add(1, Ordering::Relaxed) + 1;
+                        let now_unix = SystemTime::now()
+                            .duration_since(UNIX_EPOCH)
+                            .unwrap_or(Duration::from_secs(0))
+                            .as_secs();
+                        let meta_clone = Arc::new(ClientMeta {
+                            id,
+                            addr,
+                            connected_unix: now_unix,
+                            last_cmd_unix: AtomicU64::new(now_unix),
+                        });
+                        {
+                            let mut tbl = clients_clone.lock().await;
+                            tbl.insert(id, Arc::clone(&meta_clone));
+                        }
+
+                        if let Err(e) = Self::handle_connection(socket, addr, store_clone, stats_clone.clone(), repl_clone, meta_clone, clients_clone.clone(), sync_manager_clone, sync_progress, peers, parked, cfg_cl, merkle_clone, merkle_changes, runtime_cfg, webhook, consistency, tasks, watermark, auth, databases, tls, group_commit).await {
                             error!("Error handling connection from {}: {}", addr, e);
                         }
-                        
+
                         // Decrement active connections when the connection ends
                         stats_clone.active_connections.fetch_sub(1, Ordering::Relaxed);
                     });
@@ -462,7 +973,7 @@ impl Server {
     /// the storage, and a response is sent back.
     /// 
     /// # Arguments
-    /// * `socket` - The TCP stream for this client connection
+    /// * `socket` - The TCP (or TLS) stream for this client connection
     /// * `addr` - Client's address (for logging)
     /// * `store` - Shared reference to the storage engine
     /// * `stats` - Shared reference to server statistics
@@ -480,8 +991,9 @@ impl Server {
     /// - Invalid commands result in ERROR responses
     /// - Network errors terminate the connection
     /// - Storage errors are converted to ERROR responses
+    #[allow(clippy::too_many_arguments)]
     async fn handle_connection(
-        socket: TcpStream,
+        socket: Box<dyn ClientStream>,
         addr: SocketAddr,
         store: Arc<Mutex<Box<dyn KVEngineStoreTrait + Send + Sync>>>,
         stats: Arc<ServerStats>,
@@ -489,11 +1001,46 @@ impl Server {
         client_meta: Arc<ClientMeta>,
         clients: ClientTable,
         sync_manager: Arc<tokio::sync::Mutex<SyncManager>>,
+        sync_progress: Arc<SyncProgress>,
+        peers: Arc<PeerTable>,
+        parked: Arc<ParkedSubscriptions>,
         cfg: Arc<crate::config::Config>,
+        merkle: SharedMerkle,
+        merkle_changes: watch::Receiver<u64>,
+        runtime_cfg: Arc<RuntimeConfig>,
+        webhook: Option<Webhook>,
+        consistency: Arc<ConsistencyTracker>,
+        tasks: Arc<TaskRegistry>,
+        watermark: Arc<WriteWatermark>,
+        auth: Option<Arc<dyn AuthProvider>>,
+        databases: Arc<Databases>,
+        tls: Arc<TlsState>,
+        group_commit: Arc<GroupCommit>,
     ) -> Result<()> {
-        let (read_half, mut write_half) = socket.into_split();
-        let mut reader = BufReader::new(read_half);
-        let protocol = Protocol::new();
+        let (read_half, write_half) = tokio::io::split(socket);
+        // Both halves stay plain unless HELLO negotiates compression
+        let mut write_half = ReplyWriter::new(TransportWriter::new(write_half), cfg.server.reply_batch_max);
+        let mut reader = BufReader::new(TransportReader::new(read_half));
+        let protocol = Protocol::new().with_strict_crlf(cfg.server.strict_crlf).with_max_args(cfg.server.max_args);
+        // Per-connection opt-in for compressed GET replies (CLIENT COMPRESS)
+        let mut compress_replies = false;
+        // Per-connection opt-in for TOKEN lines after writes (CLIENT TOKENS)
+        let mut write_tokens = false;
+        // Without an auth provider every connection starts authenticated
+        let mut authenticated = auth.is_none();
+        // Rules of the user this connection authenticated as
+        let mut acl = Acl::default();
+        // Where a secondary forwards client writes (cluster.forward_writes)
+        let secondary = cfg.cluster.role == Role::Secondary;
+        let primary_addr = cfg.cluster.primary_addr.clone().unwrap_or_default();
+        let mut primary = (secondary && cfg.cluster.forward_writes)
+            .then(|| {
+                PrimaryLink::new(&primary_addr, Duration::from_millis(cfg.cluster.forward_timeout_ms))
+                    .with_password(cfg.cluster.primary_password.clone())
+            });
+        // Database selected with SELECT; `store` is switched along with it
+        let mut store = store;
+        let mut db_index = 0;
 
         // Local helper describing what to publish after the storage write.
         enum Publish {
@@ -503,21 +1050,58 @@ impl Server {
             Decr(String, i64),
             Append(String, String),
             Prepend(String, String),
+            /// New TTL in seconds, None for PERSIST
+            Expire(String, Option<u64>),
+            /// Writes applied together (None = delete), replicated as one group
+            Group(Vec<(String, Option<String>)>),
         }
 
         loop {
+            // Write the batched replies unless another pipelined command is already here
+            let more_input = reader.buffer().contains(&b'\n');
+            if let Err(e) = write_half.end_reply(more_input).await {
+                error!("Error writing to client {}: {}", addr, e);
+                break;
+            }
+
             // Read a complete line from the client (terminated by \n)
             // Defensive upper bound to prevent OOM attacks
-            let mut request_line = String::new();
-            match reader.read_line(&mut request_line).await {
+            let mut request_bytes = Vec::new();
+            // Unauthenticated connections get the shorter idle window
+            let idle_secs = if authenticated {
+                cfg.server.idle_timeout_secs
+            } else {
+                cfg.server.unauth_idle_timeout_secs
+            };
+            // Read at most one byte past the limit, so an endless line is caught early
+            let mut limited = (&mut reader).take(MAX_LINE_BYTES as u64 + 1);
+            let read = limited.read_until(b'\n', &mut request_bytes);
+            let read = if idle_secs > 0 {
+                match tokio::time::timeout(Duration::from_secs(idle_secs), read).await {
+                    Ok(read) => read,
+                    Err(_) => {
+                        info!(
+                            "Closing {} connection {}: idle for {}s",
+                            if authenticated { "authenticated" } else { "unauthenticated" },
+                            addr,
+                            idle_secs
+                        );
+                        let _ = write_half.write_all(b"ERROR idle timeout\r\n").await;
+                        break;
+                    }
+                }
+            } else {
+                read.await
+            };
+            match read {
                 Ok(0) => {
                     // Client closed the connection
                     info!("Client {} disconnected", addr);
                     break;
                 }
                 Ok(bytes_read) => {
-                    // Check for line length abuse (1MB limit)
-                    if bytes_read > 1024 * 1024 {
+                    // Check for line length abuse
+                    if bytes_read > MAX_LINE_BYTES {
                         let error_msg = "ERROR line too long\r\n";
                         let _ = write_half.write_all(error_msg.as_bytes()).await;
                         error!("Dropping connection {}: line too long ({} bytes)", addr, bytes_read);
@@ -530,9 +1114,106 @@ impl Server {
                     break;
                 }
             };
+            // Binary keys and values are kept byte for byte (see key_encoding)
+            let request_line = String::from_utf8(request_bytes).unwrap_or_else(|e| key_encoding::decode(e.as_bytes()));
 
-            match protocol.parse(&request_line) {
+            match protocol.parse_line(&request_line) {
+                Ok(command) if !authenticated && !matches!(command, Command::Auth { .. }) => {
+                    if let Err(e) = write_half.write_all(b"ERROR NOAUTH authentication required\r\n").await {
+                        error!("Error writing to client {}: {}", addr, e);
+                        break;
+                    }
+                }
                 Ok(command) => {
+                    if let Err(reason) = acl.check(&command) {
+                        if let Err(e) = write_half.write_all(format!("ERROR ERR_NOPERM {}\r\n", reason).as_bytes()).await {
+                            error!("Error writing to client {}: {}", addr, e);
+                            break;
+                        }
+                        continue;
+                    }
+                    if cfg.server.require_utf8_keys && Self::writes_binary_key(&command) {
+                        if let Err(e) = write_half.write_all(ENCODING_ERROR.as_bytes()).await {
+                            error!("Error writing to client {}: {}", addr, e);
+                            break;
+                        }
+                        continue;
+                    }
+                    // Databases other than 0 are local, so their writes stay here
+                    let to_primary = secondary && db_index == 0 && command.plan().replicates;
+                    // SET ... DURABLE / CL=<level> / LOCAL run as the plain write, then
+                    // flush or wait for peer acks before replying (LOCAL: without publishing)
+                    let mut command = command;
+                    let mut durable = false;
+                    let mut write_cl = WriteConsistency::One;
+                    let mut local = false;
+                    let command = loop {
+                        command = match command {
+                            Command::Durable { command } => {
+                                durable = true;
+                                *command
+                            }
+                            Command::Consistent { level, command } => {
+                                write_cl = level;
+                                *command
+                            }
+                            Command::Local { command } => {
+                                local = true;
+                                *command
+                            }
+                            command => break command,
+                        };
+                    };
+                    if local && write_cl != WriteConsistency::One {
+                        if let Err(e) = write_half.write_all(b"ERROR LOCAL writes are not replicated, so CL= cannot apply\r\n").await {
+                            error!("Error writing to client {}: {}", addr, e);
+                            break;
+                        }
+                        continue;
+                    }
+                    // Chunked uploads are read in full here, then stored like a plain SET
+                    let streamed = matches!(command, Command::SetStream { .. });
+                    let command = match command {
+                        Command::SetStream { key, len } => match Self::receive_stream(&mut reader, &mut write_half, len, &cfg.server).await {
+                            Ok(Ok(value)) => Command::Set { key, value },
+                            Ok(Err(e)) => {
+                                if let Err(e) = write_half.write_all(format!("ERROR {}\r\n", e).as_bytes()).await {
+                                    error!("Error writing to client {}: {}", addr, e);
+                                    break;
+                                }
+                                continue;
+                            }
+                            Err(e) => {
+                                warn!("Closing {}: bad streamed value: {}", addr, e);
+                                let _ = write_half.write_all(format!("ERROR {}\r\n", e).as_bytes()).await;
+                                break;
+                            }
+                        },
+                        command => command,
+                    };
+                    // A secondary hands client writes to the primary, or refuses them
+                    if to_primary {
+                        let reply = match primary.as_mut() {
+                            Some(link) if !streamed => link.forward(&request_line).await.unwrap_or_else(|e| {
+                                warn!("Forwarding a write from {} failed: {:#}", addr, e);
+                                format!("ERROR primary unavailable: {}\r\n", e)
+                            }),
+                            _ => format!("ERROR READONLY writes go to the primary {}\r\n", primary_addr),
+                        };
+                        if let Err(e) = write_half.write_all(reply.as_bytes()).await {
+                            error!("Error writing to client {}: {}", addr, e);
+                            break;
+                        }
+                        continue;
+                    }
+                    // Checked after a streamed value is read so its chunks are not parsed as commands
+                    if watermark.refuses(&command, &store).await {
+                        if let Err(e) = write_half.write_all(b"ERROR ERR_OOM command not allowed when over memory limit\r\n").await {
+                            error!("Error writing to client {}: {}", addr, e);
+                            break;
+                        }
+                        continue;
+                    }
                     let now_unix = SystemTime::now()
                         .duration_since(UNIX_EPOCH)
                         .unwrap_or(Duration::from_secs(0))
@@ -544,14 +1225,59 @@ impl Server {
                     // Process the command. We avoid holding the store lock across awaits
                     // by computing an optional publish action and performing it afterward.
                     let mut publishes: Vec<Publish> = Vec::new();
-                    let response = match command.clone() {
+                    // server.command_timeout_ms aborts only `Command::abortable` ones, at an await
+                    let timeout = (cfg.server.command_timeout_ms > 0).then(|| Duration::from_millis(cfg.server.command_timeout_ms));
+                    let deadline = timeout.map(|t| Instant::now() + t);
+                    // Evaluates to None when the connection must close
+                    let execute = async { Some(match command.clone() {
                         Command::Get { key } => {
                             let store = store.lock().await;
                             match store.get(&key) {
-                                Some(value) => format!("VALUE {}\r\n", value),
+                                Some(value) => compression::value_reply(
+                                    &value,
+                                    compress_replies,
+                                    cfg.server.compression_threshold,
+                                ),
+                                None => "NOT_FOUND\r\n".to_string(),
+                            }
+                        }
+                        Command::GetStream { key } => {
+                            let value = store.lock().await.get(&key);
+                            match value {
+                                Some(value) => {
+                                    if let Err(e) = streaming::write_chunks(&mut write_half, &key_encoding::encode(&value), cfg.server.stream_chunk_bytes).await {
+                                        error!("Error writing to client {}: {}", addr, e);
+                                        return None;
+                                    }
+                                    "END\r\n".to_string()
+                                }
                                 None => "NOT_FOUND\r\n".to_string(),
                             }
                         }
+                        Command::GetWithCrc { key } => match store.lock().await.get(&key) {
+                            Some(value) => {
+                                let mut crc = flate2::Crc::new();
+                                crc.update(value.as_bytes());
+                                format!("VALUE_CRC {:08x} {}\r\n", crc.sum(), value)
+                            }
+                            None => "NOT_FOUND\r\n".to_string(),
+                        },
+                        Command::GetWithToken { key, token } => {
+                            // Bounded wait for replication; the store lock is not held meanwhile
+                            let wait = Duration::from_millis(cfg.replication.token_wait_ms);
+                            if consistency.wait_for(&token, wait).await {
+                                match store.lock().await.get(&key) {
+                                    Some(value) => compression::value_reply(
+                                        &value,
+                                        compress_replies,
+                                        cfg.server.compression_threshold,
+                                    ),
+                                    None => "NOT_FOUND\r\n".to_string(),
+                                }
+                            } else {
+                                "NOT_CAUGHT_UP\r\n".to_string()
+                            }
+                        }
                         Command::Ping { message } => {
                             let store = store.lock().await;
                             let pong_response = store.ping(&message);
@@ -567,6 +1293,31 @@ impl Server {
                             let size = store.dbsize();
                             format!("DBSIZE {}\r\n", size)
                         }
+                        Command::RandomKey => match store.lock().await.random_key() {
+                            Some(key) => format!("VALUE {}\r\n", key),
+                            None => "NOT_FOUND\r\n".to_string(),
+                        },
+                        Command::Touch { keys } => {
+                            let store = store.lock().await;
+                            let touched = keys.iter().filter(|key| store.touch(key)).count();
+                            format!("TOUCHED {}\r\n", touched)
+                        }
+                        Command::Warm { keys } => {
+                            let store = store.lock().await;
+                            let mut loaded = 0;
+                            let mut error = None;
+                            for key in &keys {
+                                match store.warm(key) {
+                                    Ok(true) => loaded += 1,
+                                    Ok(false) => {}
+                                    Err(e) => {
+                                        error = Some(format!("ERROR {} after warming {}: {}\r\n", key, loaded, e));
+                                        break;
+                                    }
+                                }
+                            }
+                            error.unwrap_or_else(|| format!("WARMED {}\r\n", loaded))
+                        }
                         Command::Exists { keys } => {
                             let store = store.lock().await;
                             let mut count = 0;
@@ -578,24 +1329,278 @@ impl Server {
                             format!("EXISTS {}\r\n", count)
                         }
                         Command::Scan { prefix } => {
+                            // The lock is held for the key listing only, not the reply
+                            let results = store.lock().await.scan(&prefix);
+                            match Self::stream_scan(&mut reader, &mut write_half, results, deadline).await {
+                                Ok(rest) => rest,
+                                Err(e) => {
+                                    error!("Error writing to client {}: {}", addr, e);
+                                    return None;
+                                }
+                            }
+                        }
+                        Command::Cancel => "ERROR no SCAN in progress\r\n".to_string(),
+                        Command::Tasks => tasks.format(),
+                        Command::MetricsDump { format } => match &cfg.metrics.dump_path {
+                            Some(path) => {
+                                let format = format.unwrap_or(cfg.metrics.dump_format);
+                                match metrics::dump(&stats, std::path::Path::new(path), format) {
+                                    Ok(bytes) => format!("OK {} bytes\r\n", bytes),
+                                    Err(e) => format!("ERROR {:#}\r\n", e),
+                                }
+                            }
+                            None => "ERROR no metrics file configured (metrics.dump_path)\r\n".to_string(),
+                        },
+                        Command::TaskRun { name } => {
+                            if tasks.trigger(&name) {
+                                "OK\r\n".to_string()
+                            } else {
+                                format!("ERROR unknown task '{}'\r\n", name)
+                            }
+                        }
+                        Command::Explain { command } => {
+                            // Parsed and validated only; nothing touches the store
+                            let plan = command.plan();
+                            let ops = if plan.ops.is_empty() { "none".to_string() } else { plan.ops.join(" ") };
+                            format!("PLAN {} replicate:{}\r\n", ops, if plan.replicates { "yes" } else { "no" })
+                        }
+                        Command::Swap { key1, key2 } => {
+                            // One lock acquisition: no other command sees a half-done swap
+                            let store = store.lock().await;
+                            let (value1, value2) = (store.get(&key1), store.get(&key2));
+                            let undo = vec![(key1.clone(), value1.clone(), store.expiry(&key1)), (key2.clone(), value2.clone(), store.expiry(&key2))];
+                            let writes = vec![(key1.clone(), value2), (key2.clone(), value1)];
+                            let mut result = Ok(());
+                            if key1 != key2 && writes.iter().any(|(_, v)| v.is_some()) {
+                                for (i, (key, value)) in writes.iter().enumerate() {
+                                    result = match value {
+                                        Some(v) => store.set(key.clone(), v.clone()),
+                                        None => {
+                                            store.delete(key);
+                                            Ok(())
+                                        }
+                                    };
+                                    if result.is_err() {
+                                        // Neither key changes: put back what was written before the failure
+                                        script::rollback(store.as_ref(), undo.into_iter().take(i).collect());
+                                        break;
+                                    }
+                                }
+                                if result.is_ok() {
+                                    publishes.push(Publish::Group(writes));
+                                }
+                            }
+                            match result {
+                                Ok(()) => "OK\r\n".to_string(),
+                                Err(e) => format!("ERROR {}\r\n", e),
+                            }
+                        }
+                        Command::Eval { ops } => {
+                            // The lock is held for the whole script (see `script`)
                             let store = store.lock().await;
-                            let results = store.scan(&prefix);
-                            let mut response = format!("KEYS {}\r\n", results.len());
-                            for k in results {
-                                response.push_str(&format!("{}\r\n", k));
+                            match script::run(store.as_ref(), &ops) {
+                                Ok(outcome) => {
+                                    if !outcome.writes.is_empty() {
+                                        publishes.push(Publish::Group(outcome.writes));
+                                    }
+                                    outcome.reply
+                                }
+                                Err(e) => format!("ERROR {}\r\n", e),
+                            }
+                        }
+                        Command::FindByValue { prefix } => {
+                            match store.lock().await.find_by_value_prefix(&prefix) {
+                                Some(results) => {
+                                    let mut response = format!("KEYS {}\r\n", results.len());
+                                    for k in results {
+                                        response.push_str(&format!("{}\r\n", k));
+                                    }
+                                    response
+                                }
+                                None => "ERROR value index is disabled (index.value_prefix_enabled)\r\n".to_string(),
                             }
-                            response
                         }
                         Command::Set { key, value } => {
                             let store = store.lock().await;
-                            match store.set(key.clone(), value.clone()) {
+                            // storage.default_ttl_seconds: a plain SET behaves like SET ... EX <default>
+                            let default_ttl = cfg.storage.default_ttl_seconds;
+                            let result = store.set(key.clone(), value.clone()).and_then(|_| {
+                                if default_ttl == 0 {
+                                    return Ok(false);
+                                }
+                                store.set_expiry(&key, Some(expiring::now_ms().saturating_add(default_ttl.saturating_mul(1000))))
+                            });
+                            match result {
+                                Ok(_) if local => {
+                                    store.mark_local(&key);
+                                    "OK\r\n".to_string()
+                                }
                                 Ok(_) => {
                                     publishes.push(Publish::Set(key.clone(), value.clone()));
+                                    if default_ttl > 0 {
+                                        publishes.push(Publish::Expire(key.clone(), Some(default_ttl)));
+                                    }
+                                    "OK\r\n".to_string()
+                                }
+                                Err(e) => format!("ERROR {}\r\n", e),
+                            }
+                        }
+                        // Received and rewritten into a SET above
+                        Command::SetStream { .. } => "ERROR streamed SET was not received\r\n".to_string(),
+                        // Unwrapped above
+                        Command::Durable { .. } => "ERROR DURABLE was not unwrapped\r\n".to_string(),
+                        Command::Consistent { .. } => "ERROR CL was not unwrapped\r\n".to_string(),
+                        Command::Local { .. } => "ERROR LOCAL was not unwrapped\r\n".to_string(),
+                        Command::SetEx { key, value, ttl_ms } => {
+                            let store = store.lock().await;
+                            let result = cfg.storage.cap_ttl_ms(ttl_ms).and_then(|ttl_ms| {
+                                store.set(key.clone(), value.clone())?;
+                                store.set_expiry(&key, Some(expiring::now_ms().saturating_add(ttl_ms)))?;
+                                Ok(ttl_ms)
+                            });
+                            match result {
+                                Ok(_) if local => {
+                                    store.mark_local(&key);
+                                    "OK\r\n".to_string()
+                                }
+                                Ok(ttl_ms) => {
+                                    publishes.push(Publish::Set(key.clone(), value.clone()));
+                                    // Whole seconds on the wire; round up so a PX TTL never arrives as 0
+                                    publishes.push(Publish::Expire(key.clone(), Some(ttl_ms.div_ceil(1000))));
                                     "OK\r\n".to_string()
                                 }
                                 Err(e) => format!("ERROR {}\r\n", e),
                             }
                         }
+                        Command::Expire { key, seconds, condition } => {
+                            let store = store.lock().await;
+                            let result = cfg.storage.cap_ttl_ms(seconds.saturating_mul(1000)).and_then(|ttl_ms| {
+                                let seconds = ttl_ms / 1000;
+                                let deadline = expiring::now_ms().saturating_add(ttl_ms);
+                                // Checked under the same lock as the update
+                                if let Some(condition) = condition {
+                                    if !store.exists(&key) || !condition.allows(store.expiry(&key), deadline) {
+                                        return Ok((false, seconds));
+                                    }
+                                }
+                                Ok((store.set_expiry(&key, Some(deadline))?, seconds))
+                            });
+                            match result {
+                                Ok((existed, seconds)) => {
+                                    if existed {
+                                        publishes.push(Publish::Expire(key.clone(), Some(seconds)));
+                                    }
+                                    format!("VALUE {}\r\n", existed as u8)
+                                }
+                                Err(e) => format!("ERROR {}\r\n", e),
+                            }
+                        }
+                        Command::Persist { key } => {
+                            let store = store.lock().await;
+                            // A key without a TTL has nothing to remove: 0, like a missing one
+                            if store.expiry(&key).is_none() {
+                                "VALUE 0\r\n".to_string()
+                            } else {
+                                match store.set_expiry(&key, None) {
+                                    Ok(existed) => {
+                                        if existed {
+                                            publishes.push(Publish::Expire(key.clone(), None));
+                                        }
+                                        format!("VALUE {}\r\n", existed as u8)
+                                    }
+                                    Err(e) => format!("ERROR {}\r\n", e),
+                                }
+                            }
+                        }
+                        Command::Ttl { key } => format!("VALUE {}\r\n", Self::remaining_ttl(store.lock().await.as_ref(), &key)),
+                        Command::GetWithTtl { key } => {
+                            // One lock for both, so the TTL belongs to the value returned
+                            let store = store.lock().await;
+                            let ttl = Self::remaining_ttl(store.as_ref(), &key);
+                            match store.get(&key) {
+                                Some(value) if ttl != -2 => format!("VALUE_TTL {} {}\r\n", ttl, value),
+                                _ => "NOT_FOUND\r\n".to_string(),
+                            }
+                        }
+                        Command::Object { field: ObjectField::Acks, key } => {
+                            let acked_by = replicator.lock().await.as_ref().filter(|_| db_index == 0).and_then(|r| r.acked_by(&key));
+                            match acked_by {
+                                _ if !store.lock().await.exists(&key) => "NOT_FOUND\r\n".to_string(),
+                                Some(peers) => format!("ACKS {}{}\r\n", peers.len(), peers.iter().map(|p| format!(" {}", p)).collect::<String>()),
+                                None => "NOT_TRACKED\r\n".to_string(),
+                            }
+                        }
+                        Command::Object { field: ObjectField::Durable, key } => {
+                            let store = store.lock().await;
+                            match store.durable(&key) {
+                                _ if !store.exists(&key) => "NOT_FOUND\r\n".to_string(),
+                                Some(durable) => format!("DURABLE {}\r\n", if durable { "yes" } else { "no" }),
+                                None => "ERROR no write-ahead log configured (storage.wal_path)\r\n".to_string(),
+                            }
+                        }
+                        Command::Object { field, key } => match store.lock().await.times(&key) {
+                            Some(times) => {
+                                let value = |ms: u64| format!("VALUE {}\r\n", ms);
+                                match field {
+                                    ObjectField::Created => value(times.created_ms),
+                                    ObjectField::Modified => value(times.modified_ms),
+                                    ObjectField::Accessed => value(times.accessed_ms),
+                                    ObjectField::IdleTime => value(expiring::now_ms().saturating_sub(times.modified_ms) / 1000),
+                                    // A local write reports this node as its origin
+                                    ObjectField::ReplInfo => format!(
+                                        "REPLINFO {} {}\r\n",
+                                        times.origin.as_deref().unwrap_or(&cfg.replication.client_id),
+                                        times.modified_ms
+                                    ),
+                                    ObjectField::Acks | ObjectField::Durable => unreachable!("OBJECT ACKS and DURABLE are answered above"),
+                                }
+                            }
+                            None => "NOT_FOUND\r\n".to_string(),
+                        },
+                        Command::JsonIncr { key, path, amount } => {
+                            // Read-modify-write under a single lock acquisition → atomic
+                            let store = store.lock().await;
+                            match json_doc::incr(store.as_ref(), &key, &path, &amount) {
+                                Ok((n, serialized)) => {
+                                    publishes.push(Publish::Set(key.clone(), serialized));
+                                    format!("VALUE {}\r\n", n)
+                                }
+                                Err(e) => format!("ERROR {}\r\n", e),
+                            }
+                        }
+                        Command::HSet { key, pairs } => {
+                            // Read-modify-write under a single lock acquisition → atomic
+                            let store = store.lock().await;
+                            match field_map::hset(store.as_ref(), &key, &pairs) {
+                                Ok((added, serialized)) => {
+                                    publishes.push(Publish::Set(key.clone(), serialized));
+                                    format!("VALUE {}\r\n", added)
+                                }
+                                Err(e) => format!("ERROR {}\r\n", e),
+                            }
+                        }
+                        Command::HGet { key, field } => {
+                            let store = store.lock().await;
+                            match field_map::hget(store.as_ref(), &key, &field) {
+                                Ok(Some(value)) => format!("VALUE {}\r\n", value),
+                                Ok(None) => "NOT_FOUND\r\n".to_string(),
+                                Err(e) => format!("ERROR {}\r\n", e),
+                            }
+                        }
+                        Command::HGetAll { key } => {
+                            let store = store.lock().await;
+                            match field_map::hgetall(store.as_ref(), &key) {
+                                Ok(Some(map)) => {
+                                    let mut out = format!("FIELDS {}\r\n", map.len());
+                                    for (field, value) in map {
+                                        out.push_str(&format!("{} {}\r\n", field, value));
+                                    }
+                                    out
+                                }
+                                Ok(None) => "NOT_FOUND\r\n".to_string(),
+                                Err(e) => format!("ERROR {}\r\n", e),
+                            }
+                        }
                         Command::Delete { key } => {
                             let deleted = {
                                 let store = store.lock().await;
@@ -613,10 +1618,124 @@ impl Server {
                             let usage = store.memory_usage();
                             format!("MEMORY {}\r\n", usage)
                         }
-                        Command::Clientlist => {
-
-                            let snapshot: Vec<Arc<ClientMeta>> = {
-                                let tbl = clients.lock().await;
+                        Command::MemoryCompact => {
+                            // One part (shard) per lock acquisition so other clients
+                            // interleave instead of waiting for the whole keyspace
+                            let before = store.lock().await.memory_usage();
+                            let mut part = 0;
+                            while store.lock().await.compact_memory(part) {
+                                part += 1;
+                                tokio::task::yield_now().await;
+                            }
+                            let after = store.lock().await.memory_usage();
+                            info!("MEMORY COMPACT: {} -> {} bytes ({} parts)", before, after, part + 1);
+                            format!("MEMORY COMPACTED before:{} after:{}\r\n", before, after)
+                        }
+                        Command::StorageStats => {
+                            match store.lock().await.storage_stats() {
+                                Some(s) => format!(
+                                    "STORAGE logical_bytes:{} disk_bytes:{} fragmentation_ratio:{:.2}\r\n",
+                                    s.logical_bytes, s.disk_bytes, s.ratio()
+                                ),
+                                None => "ERROR storage engine has no on-disk files\r\n".to_string(),
+                            }
+                        }
+                        Command::ShardStats => match store.lock().await.shard_stats() {
+                            Some(shards) => {
+                                let keys: usize = shards.iter().map(|s| s.keys).sum();
+                                let largest = shards.iter().map(|s| s.keys).max().unwrap_or(0);
+                                let skew = if keys == 0 { 0.0 } else { largest as f64 * shards.len() as f64 / keys as f64 };
+                                let mut out = format!("SHARDS {} keys:{} hash_fn:{} skew:{:.2}\r\n", shards.len(), keys, cfg.storage.hash_fn, skew);
+                                for (index, shard) in shards.iter().enumerate() {
+                                    out.push_str(&format!("{} keys:{} memory_bytes:{}\r\n", index, shard.keys, shard.memory_bytes));
+                                }
+                                out
+                            }
+                            None => "ERROR storage engine is not sharded\r\n".to_string(),
+                        },
+                        Command::StorageCompact => {
+                            // Holds the store lock throughout: the engine swaps its files
+                            let store = store.lock().await;
+                            let before = store.storage_stats();
+                            match store.compact_storage() {
+                                Ok(true) => {
+                                    let disk = |s: Option<StorageStats>| s.map_or(0, |s| s.disk_bytes);
+                                    let after = store.storage_stats();
+                                    info!("STORAGE COMPACT: {} -> {} bytes on disk", disk(before), disk(after));
+                                    format!("STORAGE COMPACTED before:{} after:{}\r\n", disk(before), disk(after))
+                                }
+                                Ok(false) => "ERROR storage engine has no on-disk files\r\n".to_string(),
+                                Err(e) => format!("ERROR {}\r\n", e),
+                            }
+                        }
+                        Command::Select { index } => match databases.get(index) {
+                            Ok(db) => {
+                                store = db;
+                                db_index = index;
+                                "OK\r\n".to_string()
+                            }
+                            Err(e) => format!("ERROR {}\r\n", e),
+                        },
+                        Command::DbDiff { first, second } => match (databases.get(first), databases.get(second)) {
+                            (Ok(a), Ok(b)) => databases::diff(&a, &b).await.format(first, second),
+                            (Err(e), _) | (_, Err(e)) => format!("ERROR {}\r\n", e),
+                        },
+                        Command::Persistence { enabled } => {
+                            // Turning off snapshots under the store lock, so no write slips past it
+                            match store.lock().await.set_persistence(enabled) {
+                                Ok(()) => {
+                                    info!("PERSISTENCE {}", if enabled { "ON" } else { "OFF" });
+                                    "OK\r\n".to_string()
+                                }
+                                Err(e) => format!("ERROR {}\r\n", e),
+                            }
+                        }
+                        Command::MemoryHistogram { samples } => {
+                            let store = store.lock().await;
+                            let hist = KeyspaceHistogram::sample(
+                                store.as_ref(),
+                                samples.unwrap_or(histogram::DEFAULT_SAMPLES),
+                            );
+                            hist.format(store.dbsize())
+                        }
+                        Command::BigKeys { count } => {
+                            let (biggest, sampled) =
+                                histogram::biggest_values(store.lock().await.as_ref(), count.unwrap_or(histogram::DEFAULT_BIG_KEYS));
+                            let mut reply = format!("BIGKEYS {} sampled:{}\r\n", biggest.len(), sampled);
+                            for (key, size) in biggest {
+                                reply.push_str(&format!("{} {}\r\n", key, size));
+                            }
+                            reply
+                        }
+                        Command::Hello { codecs } => {
+                            if reader.get_ref().compressed() || !transport::accepts(&codecs, cfg.server.transport_compression) {
+                                transport::hello_reply(&cfg, reader.get_ref().compressed())
+                            } else {
+                                // The reply goes out plain; every byte after it, each way, is compressed
+                                let reply = transport::hello_reply(&cfg, true);
+                                if let Err(e) = async { write_half.write_all(reply.as_bytes()).await?; write_half.flush().await }.await {
+                                    error!("Error writing to client {}: {}", addr, e);
+                                    return None;
+                                }
+                                let pipelined = reader.buffer().to_vec();
+                                reader.consume(pipelined.len());
+                                reader.get_mut().start_inflate(pipelined);
+                                write_half.get_mut().start_deflate();
+                                String::new()
+                            }
+                        }
+                        Command::ClientCompress { enabled } => {
+                            compress_replies = enabled;
+                            "OK\r\n".to_string()
+                        }
+                        Command::ClientTokens { enabled } => {
+                            write_tokens = enabled;
+                            "OK\r\n".to_string()
+                        }
+                        Command::Clientlist => {
+
+                            let snapshot: Vec<Arc<ClientMeta>> = {
+                                let tbl = clients.lock().await;
                                 tbl.values().cloned().collect()
                             };
                             let now = SystemTime::now()
@@ -638,12 +1757,49 @@ impl Server {
                             out
                         }
                         Command::Sync { host, port, options: _ } => {
-                            let mut mgr = sync_manager.lock().await;
+                            let mgr = sync_manager.lock().await;
                             match mgr.sync_once(&host, port).await {
                                 Ok(_)  => "OK\r\n".to_string(),
                                 Err(e) => format!("ERROR {}\r\n", e),
                             }
                         }
+                        Command::SyncStatus => sync_progress.format(),
+                        Command::ClusterPeers => peers.format(&consistency.sources()),
+                        Command::DebugSleep { ms } => {
+                            if !cfg.server.test_commands {
+                                "ERROR DEBUG is disabled (server.test_commands)\r\n".to_string()
+                            } else {
+                                tokio::time::sleep(Duration::from_millis(ms)).await;
+                                "OK\r\n".to_string()
+                            }
+                        }
+                        Command::VerifyConsistent { peer } => {
+                            if !cfg.server.test_commands {
+                                "ERROR VERIFY is disabled (server.test_commands)\r\n".to_string()
+                            } else {
+                                let timeout = Duration::from_millis(cfg.merkle.sync_timeout_ms);
+                                match snapshot::fetch(&peer, timeout).await {
+                                    Ok(records) => {
+                                        let filter = KeyFilter::from_config(&cfg.replication);
+                                        let local = verify::local_values(store.lock().await.as_ref(), &filter);
+                                        verify::compare(&local, &verify::peer_values(records, &filter)).format()
+                                    }
+                                    Err(e) => format!("ERROR verify against {} failed: {:#}\r\n", peer, e),
+                                }
+                            }
+                        }
+                        Command::SyncAbort => {
+                            if sync_progress.abort() {
+                                info!("SYNC ABORT by {}", addr);
+                                "OK\r\n".to_string()
+                            } else {
+                                "ERROR no sync in progress\r\n".to_string()
+                            }
+                        }
+                        Command::RangeHash { start, end } => {
+                            let (count, hash) = crate::store::merkle::range_hash(store.lock().await.as_ref(), &start, &end);
+                            format!("RANGEHASH {} {}\r\n", count, hex::encode(hash))
+                        }
                         Command::Hash { pattern } => {
                             // 1) Collect keys (all or prefix)
                             let (keys, pat_string) = {
@@ -658,7 +1814,8 @@ impl Server {
                             };
 
                             // 2) Build a Merkle tree over selected keys
-                            let mut tree = crate::store::merkle::MerkleTree::new();
+                            let mut tree = crate::store::merkle::MerkleTree::new()
+                                .with_buckets(cfg.merkle.num_buckets, cfg.storage.hash_fn);
                             {
                                 let store = store.lock().await;
                                 for k in keys {
@@ -683,6 +1840,127 @@ impl Server {
 
                             out
                         }
+                        Command::Merkle { action } => {
+                            let store = store.lock().await;
+                            match action {
+                                MerkleAction::Verify => {
+                                    let report = merkle_tracked::verify(store.as_ref(), &merkle, &KeyFilter::from_config(&cfg.replication));
+                                    if report.is_consistent() {
+                                        format!(
+                                            "MERKLE OK keys:{} root:{}\r\n",
+                                            report.keys,
+                                            merkle_tracked::root_hex(report.rebuilt_root.as_ref())
+                                        )
+                                    } else {
+                                        warn!("MERKLE VERIFY found {} divergent keys", report.diff_keys.len());
+                                        format!(
+                                            "MERKLE MISMATCH live:{} rebuilt:{} diff_keys:{} first:{}\r\n",
+                                            merkle_tracked::root_hex(report.live_root.as_ref()),
+                                            merkle_tracked::root_hex(report.rebuilt_root.as_ref()),
+                                            report.diff_keys.len(),
+                                            report.diff_keys.first().map(String::as_str).unwrap_or("-")
+                                        )
+                                    }
+                                }
+                                MerkleAction::Rebuild => {
+                                    let keys = merkle_tracked::rebuild(store.as_ref(), &merkle, &KeyFilter::from_config(&cfg.replication));
+                                    info!("Live Merkle tree rebuilt from store ({} keys)", keys);
+                                    "OK\r\n".to_string()
+                                }
+                                MerkleAction::Fetch { path } => match merkle_tracked::fetch_subtree(store.as_ref(), &merkle, &path) {
+                                    Some((hash, pairs)) => {
+                                        let mut response = format!("SUBTREE {} {}\r\n", hash, pairs.len());
+                                        for (k, v) in pairs {
+                                            response.push_str(&format!("{} {}\r\n", k, v));
+                                        }
+                                        response
+                                    }
+                                    None => format!("ERROR no Merkle node at path '{}'\r\n", path),
+                                },
+                                MerkleAction::Gc => {
+                                    let (reclaimed, nodes) = merkle_tracked::gc(&merkle);
+                                    format!("MERKLE GC reclaimed:{} nodes:{}\r\n", reclaimed, nodes)
+                                }
+                            }
+                        }
+                        Command::Subscribe { channel: SubscribeChannel::Merkle, resume } => {
+                            let interval = Duration::from_millis(cfg.merkle.subscribe_interval_ms);
+                            let mut session = match resume {
+                                Some(_) if !parked.enabled() => {
+                                    return Some("ERROR subscription resume is disabled (merkle.subscribe_buffer)\r\n".to_string());
+                                }
+                                Some((id, since)) => parked.resume(&id, since),
+                                None => parked.start(),
+                            };
+                            let result = Self::run_merkle_subscription(&mut reader, &mut write_half, &merkle, merkle_changes.clone(), interval, &mut session).await;
+                            match result {
+                                Ok(true) => "OK\r\n".to_string(),
+                                Ok(false) => {
+                                    info!("Client {} disconnected", addr);
+                                    parked.park(session, Arc::clone(&merkle), merkle_changes.clone(), interval);
+                                    return None;
+                                }
+                                Err(e) => {
+                                    error!("Error writing to client {}: {}", addr, e);
+                                    parked.park(session, Arc::clone(&merkle), merkle_changes.clone(), interval);
+                                    return None;
+                                }
+                            }
+                        }
+                        Command::Unsubscribe => "ERROR not subscribed\r\n".to_string(),
+                        Command::Auth { password } => match auth.clone() {
+                            None => "ERROR AUTH is not enabled on this server\r\n".to_string(),
+                            // Password hashing is slow by design: keep it off the worker threads
+                            Some(provider) => match tokio::task::spawn_blocking(move || auth::authenticate(provider.as_ref(), &password)).await {
+                                Ok(AuthResult::Authenticated(user)) => {
+                                    debug!("Client {} authenticated as {}", addr, user);
+                                    acl = auth.as_deref().map(|p| p.acl(&user)).unwrap_or_default();
+                                    authenticated = true;
+                                    "OK\r\n".to_string()
+                                }
+                                Ok(AuthResult::Denied) => {
+                                    warn!("Failed AUTH from {}", addr);
+                                    "ERROR invalid password\r\n".to_string()
+                                }
+                                Err(e) => {
+                                    error!("AUTH check for {} failed: {}", addr, e);
+                                    "ERROR invalid password\r\n".to_string()
+                                }
+                            },
+                        },
+                        Command::LogLevel { module, level } => {
+                            crate::log_filter::set_module_level(&module, level);
+                            info!("Log level of {} set to {} by {}", module, level.map_or("default".to_string(), |l| l.to_string().to_lowercase()), addr);
+                            "OK\r\n".to_string()
+                        }
+                        Command::ConfigGet { param } => match runtime_cfg.get(&cfg, &param) {
+                            Ok(value) => format!("VALUE {}\r\n", value),
+                            Err(e) => format!("ERROR {}\r\n", e),
+                        },
+                        Command::ConfigSet { param, value } => match runtime_cfg.set(&cfg, &param, &value) {
+                            Ok(()) => {
+                                info!("CONFIG SET {} = {} by {}", param, value, addr);
+                                "OK\r\n".to_string()
+                            }
+                            Err(e) => format!("ERROR {}\r\n", e),
+                        },
+                        Command::ConfigReloadTls => match tls.reload() {
+                            Ok(files) => {
+                                info!("CONFIG RELOAD TLS by {}: reloaded {:?}", addr, files);
+                                "OK\r\n".to_string()
+                            }
+                            Err(e) => format!("ERROR {:#}\r\n", e),
+                        },
+                        Command::Tombstones => {
+                            let tombstones = store.lock().await.tombstones();
+                            let mut response = format!("TOMBSTONES {}\r\n", tombstones.len());
+                            for (key, deleted_at) in tombstones {
+                                response.push_str(&format!("{} {}\r\n", key, deleted_at));
+                            }
+                            response
+                        }
+                        Command::Dump => snapshot::encode(store.lock().await.as_ref()),
+                        Command::ExportJson => export::encode(store.lock().await.as_ref()),
                         Command::Replicate { action } => {
                             match action {
                                 ReplicateAction::Enable => {
@@ -693,7 +1971,10 @@ impl Server {
                                         // Khởi động replicator mới
                                         match Replicator::new(cfg.as_ref()).await {
                                             Ok(r) => {
-                                                r.start_replication_handler(Arc::clone(&store)).await;
+                                                let r = r.with_clock(Arc::clone(&consistency)).with_databases(Arc::clone(&databases));
+                                                // Database 0, whichever one this connection selected
+                                                let main = databases.get(0).expect("database 0 always exists");
+                                                r.start_replication_handler(main).await;
                                                 *g = Some(r);
                                                 "OK\r\n".to_string()
                                             }
@@ -708,14 +1989,63 @@ impl Server {
                                 }
                                 ReplicateAction::Status => {
                                     let g = replicator.lock().await;
-                                    if g.is_some() {
+                                    if let Some(r) = g.as_ref() {
                                         // Nếu bạn có danh sách peer trong config:
                                         let n = cfg.replication.peer_list.len();
-                                        format!("REPLICATION enabled {} nodes\r\n", n)
+                                        let pause = r.pause_status();
+                                        if pause.paused {
+                                            format!("REPLICATION enabled {} nodes paused {} queued\r\n", n, pause.queued)
+                                        } else {
+                                            format!("REPLICATION enabled {} nodes\r\n", n)
+                                        }
                                     } else {
                                         "REPLICATION disabled\r\n".to_string()
                                     }
                                 }
+                                ReplicateAction::DedupStats => match replicator.lock().await.as_ref() {
+                                    Some(r) => {
+                                        let d = r.dedup_stats();
+                                        format!(
+                                            "DEDUP size:{} capacity:{} lookups:{} hits:{} hit_rate:{:.3}\r\n",
+                                            d.size, d.capacity, d.lookups, d.hits, d.hit_rate()
+                                        )
+                                    }
+                                    None => "ERROR replication is disabled\r\n".to_string(),
+                                },
+                                ReplicateAction::CheckCausality => match replicator.lock().await.as_ref() {
+                                    Some(r) => r.check_causality().format(),
+                                    None => "ERROR replication is disabled\r\n".to_string(),
+                                },
+                                ReplicateAction::DedupClear => match replicator.lock().await.as_ref() {
+                                    Some(r) => {
+                                        r.clear_dedup();
+                                        info!("Replication de-dup cache cleared by {}", addr);
+                                        "OK\r\n".to_string()
+                                    }
+                                    None => "ERROR replication is disabled\r\n".to_string(),
+                                },
+                                ReplicateAction::Pause => match replicator.lock().await.as_ref() {
+                                    Some(r) => {
+                                        r.pause();
+                                        info!("Replication paused by {}", addr);
+                                        "OK\r\n".to_string()
+                                    }
+                                    None => "ERROR replication is disabled\r\n".to_string(),
+                                },
+                                ReplicateAction::Resume => {
+                                    // Clone so the flush does not hold the replicator slot
+                                    let r = replicator.lock().await.clone();
+                                    match r {
+                                        Some(r) => match r.resume().await {
+                                            Ok(flushed) => {
+                                                info!("Replication resumed by {}, {} queued changes published", addr, flushed);
+                                                "OK\r\n".to_string()
+                                            }
+                                            Err(e) => format!("ERROR resume failed, still paused: {}\r\n", e),
+                                        },
+                                        None => "ERROR replication is disabled\r\n".to_string(),
+                                    }
+                                }
                             }
                         }
                         Command::Increment { key, amount } => {
@@ -880,6 +2210,7 @@ impl Server {
                             // Server uptime
                             info.push_str(&format!("uptime_seconds:{}\r\n", stats.uptime_seconds()));
                             info.push_str(&format!("uptime:{}\r\n", stats.uptime_human()));
+                            info.push_str(&format!("start_time_unix:{}\r\n", stats.started_unix_ms / 1000));
                             
                             // Current time
                             let now = SystemTime::now()
@@ -889,14 +2220,124 @@ impl Server {
                             info.push_str(&format!("server_time_unix:{}\r\n", now));
                             
                             // Key count
-                            let key_count = { let store = store.lock().await; store.count_keys().unwrap_or(0) };
+                            let (key_count, persistence, wal_health, tiers) = {
+                                let store = store.lock().await;
+                                (store.count_keys().unwrap_or(0), store.persistence(), store.wal_health().unwrap_or_default(), store.tier_stats())
+                            };
                             info.push_str(&format!("db_keys:{}\r\n", key_count));
+
+                            // Cold tier: demoted keys and the reads that went to disk (WARM avoids them)
+                            if let Some(tiers) = tiers {
+                                info.push_str(&format!("cold_tier_keys:{}\r\n", tiers.cold_keys));
+                                info.push_str(&format!("cold_tier_reads:{}\r\n", tiers.cold_reads));
+                            }
+
+                            // Write-ahead log state, including PERSISTENCE OFF
+                            info.push_str(&format!("wal_enabled:{}\r\n", persistence.is_some() as u8));
+                            if let Some(on) = persistence {
+                                info.push_str(&format!("wal_persistence:{}\r\n", if on { "on" } else { "off" }));
+                                info.push_str(&format!("wal_write_errors:{}\r\n", wal_health.write_errors));
+                                info.push_str(&format!("wal_fsyncs:{}\r\n", wal_health.fsyncs));
+                                info.push_str(&format!("wal_status:{}\r\n", if wal_health.degraded { "degraded" } else { "ok" }));
+                            }
+
+                            // Key placement hash; peers must agree on it for Merkle sync
+                            info.push_str(&format!("hash_fn:{}\r\n", cfg.storage.hash_fn));
+
+                            // Leaf bucket count; peers must agree on it too (0 = one leaf per key)
+                            info.push_str(&format!("merkle_buckets:{}\r\n", cfg.merkle.num_buckets));
+
+                            // Live Merkle tree size; keeps growing if deleted keys are not collected
+                            info.push_str(&format!("merkle_nodes:{}\r\n", merkle_tracked::node_count(&merkle)));
+
+                            // Replication state, including REPLICATION PAUSE
+                            let (pause, apply) = match replicator.lock().await.as_ref() {
+                                Some(r) => (Some(r.pause_status()), Some(r.apply_queue_status())),
+                                None => (None, None),
+                            };
+                            // Identity in replicated events; lets peers name this node (CLUSTER PEERS)
+                            info.push_str(&format!("client_id:{}\r\n", cfg.replication.client_id));
+                            info.push_str(&format!("replication_enabled:{}\r\n", pause.is_some() as u8));
+                            if let Some(pause) = pause {
+                                info.push_str(&format!("replication_paused:{}\r\n", pause.paused as u8));
+                                info.push_str(&format!("replication_queued:{}\r\n", pause.queued));
+                                info.push_str(&format!("replication_dropped:{}\r\n", pause.dropped));
+                            }
+                            // Received events not applied yet, and those lost to an overflowing queue
+                            if let Some(apply) = apply {
+                                info.push_str(&format!("replication_apply_queued:{}\r\n", apply.queued));
+                                info.push_str(&format!("replication_apply_dropped:{}\r\n", apply.dropped));
+                            }
                             
                             format!("INFO\r\n{}", info)
                         }
+                        Command::InfoJson => {
+                            // Same data as INFO and STATS, as one single-line object
+                            let now = SystemTime::now()
+                                .duration_since(UNIX_EPOCH)
+                                .unwrap_or(Duration::from_secs(0))
+                                .as_secs();
+                            let (key_count, memory_bytes, persistence, wal_health) = {
+                                let store = store.lock().await;
+                                (
+                                    store.count_keys().unwrap_or(0),
+                                    store.memory_usage(),
+                                    store.persistence(),
+                                    store.wal_health().unwrap_or_default(),
+                                )
+                            };
+                            let (pause, apply) = match replicator.lock().await.as_ref() {
+                                Some(r) => (Some(r.pause_status()), Some(r.apply_queue_status())),
+                                None => (None, None),
+                            };
+                            // Counted before the root read rebuilds a stale tree
+                            let merkle_nodes = merkle_tracked::node_count(&merkle);
+                            let commands: serde_json::Map<String, serde_json::Value> = stats
+                                .counters()
+                                .into_iter()
+                                .map(|(name, value)| (name.to_string(), value.into()))
+                                .collect();
+                            let info = serde_json::json!({
+                                "server": {
+                                    "version": env!("CARGO_PKG_VERSION"),
+                                    "uptime_seconds": stats.uptime_seconds(),
+                                    "start_time_unix": stats.started_unix_ms / 1000,
+                                    "server_time_unix": now,
+                                    "hash_fn": cfg.storage.hash_fn.to_string(),
+                                },
+                                "keyspace": { "db_keys": key_count },
+                                "merkle": {
+                                    "root": merkle_tracked::live_root_hex(&merkle),
+                                    "nodes": merkle_nodes,
+                                    "buckets": cfg.merkle.num_buckets,
+                                },
+                                "wal": {
+                                    "enabled": persistence.is_some(),
+                                    "persistence": persistence.unwrap_or(false),
+                                    "write_errors": wal_health.write_errors,
+                                    "fsyncs": wal_health.fsyncs,
+                                    "degraded": wal_health.degraded,
+                                },
+                                "replication": {
+                                    "enabled": pause.is_some(),
+                                    "paused": pause.as_ref().is_some_and(|p| p.paused),
+                                    "queued": pause.as_ref().map_or(0, |p| p.queued),
+                                    "dropped": pause.as_ref().map_or(0, |p| p.dropped),
+                                    "apply_queued": apply.map_or(0, |a| a.queued),
+                                    "apply_dropped": apply.map_or(0, |a| a.dropped),
+                                },
+                                "commands": commands,
+                                "memory": {
+                                    "store_bytes": memory_bytes,
+                                    "used_memory_kb": ServerStats::used_memory_kb(),
+                                },
+                            });
+                            format!("INFO_JSON {}\r\n", info)
+                        }
+                        Command::Uptime => stats.uptime_reply(),
                         Command::Version => {
-                            // Return the server version from Cargo.toml
-                            format!("VERSION {}\r\n", env!("CARGO_PKG_VERSION"))
+                            // Crate version, build commit, protocol version and features
+                            crate::version::version_reply(&cfg)
                         }
                         Command::Flushdb => {
                             // Force sync to disk if the storage engine supports it
@@ -915,30 +2356,127 @@ impl Server {
                             
                             // Log shutdown request
                             info!("Shutdown requested by client {}", addr);
+                            let _ = write_half.flush().await;
+
+                            // Flush pending writes for persistent engines before exiting
+                            if let Err(e) = store.lock().await.sync() {
+                                error!("Error syncing storage before shutdown: {}", e);
+                            }
                             
                             // Exit the process gracefully
                             // Note: In a production system, we would want to do a more graceful
                             // shutdown, such as closing all connections, flushing data to disk, etc.
                             std::process::exit(0);
                         }
+                    }) };
+                    let response = match timeout.filter(|_| command.abortable()) {
+                        Some(limit) => tokio::time::timeout(limit, execute).await.unwrap_or_else(|_| {
+                            Some(format!("ERROR ERR_TIMEOUT command exceeded server.command_timeout_ms ({} ms)\r\n", limit.as_millis()))
+                        }),
+                        None => execute.await,
+                    };
+                    let Some(response) = response else { break };
+                    // Only the command's own writes earn a token, not lazy expiry
+                    let wrote = !publishes.is_empty();
+
+                    // DURABLE: the write is on disk before the client hears OK
+                    let response = if durable && response.starts_with("OK") {
+                        // Other databases are in memory: nothing to batch
+                        let flushed = if db_index == 0 { group_commit.sync().await } else { store.lock().await.sync() };
+                        match flushed {
+                            Ok(()) => response,
+                            Err(e) => format!("ERROR write applied but not flushed: {}\r\n", e),
+                        }
+                    } else {
+                        response
                     };
+
+                    // Replicate deletions of keys that expired lazily while serving the command
+                    if cfg.replication.publish_lazy_expiry {
+                        let expired = store.lock().await.take_expired();
+                        publishes.extend(expired.into_iter().map(Publish::Delete));
+                    }
+                    // Hand changes to the webhook queue; never waits on the endpoint.
+                    // Writes to databases other than 0 are not hooked.
+                    if let Some(hook) = webhook.as_ref().filter(|_| db_index == 0) {
+                        for p in &publishes {
+                            match p {
+                                Publish::Set(k, v)      => hook.notify("set", k, Some(v)),
+                                Publish::Delete(k)       => hook.notify("delete", k, None),
+                                Publish::Incr(k, nv)     => hook.notify("incr", k, Some(&nv.to_string())),
+                                Publish::Decr(k, nv)     => hook.notify("decr", k, Some(&nv.to_string())),
+                                Publish::Append(k, nv)   => hook.notify("append", k, Some(nv)),
+                                Publish::Prepend(k, nv)  => hook.notify("prepend", k, Some(nv)),
+                                Publish::Expire(k, Some(secs)) => hook.notify("expire", k, Some(&secs.to_string())),
+                                Publish::Expire(k, None) => hook.notify("persist", k, None),
+                                Publish::Group(writes) => {
+                                    for (k, v) in writes {
+                                        match v {
+                                            Some(v) => hook.notify("set", k, Some(v)),
+                                            None => hook.notify("delete", k, None),
+                                        }
+                                    }
+                                }
+                            }
+                        }
+                    }
+
                     // Perform publishes after the store operations (lock released)
-                    let guard = replicator.lock().await;
-                    if let Some(r) = guard.as_ref() {
+                    let acks_needed = write_cl.required_acks(cfg.replication.peer_list.len());
+                    let acked = acks_needed > 0 && response.starts_with("OK");
+                    let mut token_ts = None;
+                    let mut ack_ts = None;
+                    // Databases other than 0 replicate only with replication.topic_per_database
+                    let db_replicator = replicator.lock().await.as_ref().and_then(|r| r.for_database(db_index));
+                    let ack_replicator = db_replicator.clone().filter(|_| acked);
+                    if let Some(r) = db_replicator.as_ref() {
                         for p in publishes {
-                            match p {
-                                Publish::Set(k, v)      => { let _ = r.publish_set(&k, &v).await; }
-                                Publish::Delete(k)       => { let _ = r.publish_delete(&k).await; }
-                                Publish::Incr(k, nv)     => { let _ = r.publish_incr(&k, nv).await; }
-                                Publish::Decr(k, nv)     => { let _ = r.publish_decr(&k, nv).await; }
-                                Publish::Append(k, nv)   => { let _ = r.publish_append(&k, &nv).await; }
-                                Publish::Prepend(k, nv)  => { let _ = r.publish_prepend(&k, &nv).await; }
+                            let published = match p {
+                                Publish::Set(k, v) if acked => {
+                                    let published = r.publish_set_acked(&k, &v).await;
+                                    ack_ts = published.as_ref().ok().copied();
+                                    published
+                                }
+                                Publish::Set(k, v)      => r.publish_set(&k, &v).await,
+                                Publish::Delete(k)       => r.publish_delete(&k).await,
+                                Publish::Incr(k, nv)     => r.publish_incr(&k, nv).await,
+                                Publish::Decr(k, nv)     => r.publish_decr(&k, nv).await,
+                                Publish::Append(k, nv)   => r.publish_append(&k, &nv).await,
+                                Publish::Prepend(k, nv)  => r.publish_prepend(&k, &nv).await,
+                                Publish::Expire(k, ttl)  => r.publish_expire(&k, ttl).await,
+                                Publish::Group(writes)   => r.publish_group(&writes).await,
+                            };
+                            if let Ok(ts) = published {
+                                token_ts = token_ts.max(Some(ts));
+                            }
+                        }
+                    }
+
+                    // CL=quorum|all: OK only once enough peers applied the write
+                    let mut response = response;
+                    if acked {
+                        let acks = match (&ack_replicator, ack_ts) {
+                            (Some(r), Some(ts)) => {
+                                r.wait_for_acks(ts, acks_needed, Duration::from_millis(cfg.replication.ack_timeout_ms)).await
                             }
+                            _ => 0,
+                        };
+                        if acks < acks_needed {
+                            response = format!(
+                                "ERROR ERR_TIMEOUT write applied locally but only {} of {} peer acks arrived\r\n",
+                                acks, acks_needed
+                            );
                         }
                     }
+
+                    // Read-your-writes token: the newest timestamp this command published
+                    if write_tokens && wrote {
+                        let ts = token_ts.unwrap_or_else(|| consistency.now());
+                        response.push_str(&format!("TOKEN {}\r\n", consistency.token(ts)));
+                    }
                     
                     // Send response back to client
-                    if let Err(e) = write_half.write_all(response.as_bytes()).await {
+                    if let Err(e) = write_half.write_all(&key_encoding::encode(&response)).await {
                         error!("Error writing to client {}: {}", addr, e);
                         break;
                     }
@@ -946,7 +2484,7 @@ impl Server {
                 Err(e) => {
                     // Send error response for invalid commands
                     let error_msg = format!("ERROR {}\r\n", e);
-                    if let Err(e) = write_half.write_all(error_msg.as_bytes()).await {
+                    if let Err(e) = write_half.write_all(&key_encoding::encode(&error_msg)).await {
                         error!("Error writing to client {}: {}", addr, e);
                         break;
                     }
@@ -954,6 +2492,2236 @@ impl Server {
             }
         }
 
+        let _ = write_half.flush().await;
         Ok(())
     }
+
+    /// Whether `command` writes a key that was not UTF-8 on the wire
+    /// (`server.require_utf8_keys`).
+    fn writes_binary_key(command: &Command) -> bool {
+        command.plan().ops.iter().filter_map(|op| op.strip_prefix("write:")).any(key_encoding::is_binary)
+    }
+
+    /// Remaining TTL of `key` in seconds, rounded like Redis: -1 = no expiry, -2 = no such key.
+    fn remaining_ttl(store: &(dyn KVEngineStoreTrait + Send + Sync), key: &str) -> i64 {
+        // Check the deadline first: it purges the key if already expired
+        match store.expiry(key) {
+            _ if !store.exists(key) => -2,
+            None => -1,
+            Some(at) => (at.saturating_sub(expiring::now_ms()) as i64 + 500) / 1000,
+        }
+    }
+
+    /// Handshake and read a `SET key STREAM <len>` upload.
+    ///
+    /// # Returns
+    /// * `Result<Result<String, anyhow::Error>>` - The value, or an error to
+    ///   reply with; the outer error means the framing is lost and the
+    ///   connection must close
+    async fn receive_stream<R, W>(reader: &mut R, writer: &mut W, len: usize, server: &ServerConfig) -> Result<Result<String>>
+    where
+        R: AsyncBufRead + Unpin,
+        W: AsyncWrite + Unpin,
+    {
+        if len > server.max_stream_value_bytes {
+            return Ok(Err(anyhow!(
+                "streamed value of {} bytes exceeds server.max_stream_value_bytes ({})",
+                len,
+                server.max_stream_value_bytes
+            )));
+        }
+        writer.write_all(format!("READY {}\r\n", server.stream_chunk_bytes).as_bytes()).await?;
+        writer.flush().await?;
+        let bytes = streaming::read_chunks(reader, len, server.stream_chunk_bytes).await?;
+        Ok(streaming::into_value(bytes))
+    }
+
+    /// Write the `KEYS` reply for `keys` in batches of `SCAN_BATCH`,
+    /// stopping early when the next request line is `CANCEL` or `deadline`
+    /// (`server.command_timeout_ms`) has passed; a closed connection fails
+    /// the next batch's write. Other pipelined commands wait for the reply.
+    ///
+    /// # Returns
+    /// * `Result<String>` - The end of the reply: the last batch,
+    ///   `CANCELLED <keys sent>` after CANCEL, or `ERROR ERR_TIMEOUT ...`
+    async fn stream_scan<R, W>(reader: &mut R, writer: &mut W, keys: Vec<String>, deadline: Option<Instant>) -> Result<String>
+    where
+        R: AsyncBufRead + Unpin,
+        W: AsyncWrite + Unpin,
+    {
+        let mut reply = format!("KEYS {}\r\n", keys.len());
+        for (i, batch) in keys.chunks(SCAN_BATCH).enumerate() {
+            if i > 0 {
+                writer.write_all(&key_encoding::encode(&reply)).await?;
+                reply.clear();
+                // Only bytes already received count: don't wait for the client
+                let buf = tokio::select! {
+                    biased;
+                    buf = reader.fill_buf() => Some(buf?),
+                    _ = tokio::task::yield_now() => None,
+                };
+                let line = buf.and_then(|buf| Some(&buf[..buf.iter().position(|&b| b == b'\n')?]));
+                if let Some(line) = line.filter(|l| String::from_utf8_lossy(l).trim().eq_ignore_ascii_case("CANCEL")) {
+                    let consumed = line.len() + 1;
+                    reader.consume(consumed);
+                    return Ok(format!("CANCELLED {}\r\n", i * SCAN_BATCH));
+                }
+                if deadline.is_some_and(|d| Instant::now() >= d) {
+                    return Ok(format!("ERROR ERR_TIMEOUT SCAN stopped after {} keys (server.command_timeout_ms)\r\n", i * SCAN_BATCH));
+                }
+            }
+            for key in batch {
+                reply.push_str(key);
+                reply.push_str("\r\n");
+            }
+        }
+        Ok(reply)
+    }
+
+    /// Push Merkle root changes to a subscribed connection.
+    ///
+    /// Sends `SUBSCRIBED MERKLE`, the current root, and then a new
+    /// `MERKLE_ROOT <root> <changes>` line whenever the root differs from the
+    /// last one sent, at most once per `interval`. `changes` is the live tree's
+    /// write counter, so a monitor can tell how much happened between pushes.
+    /// With a resume id (see `subscriptions`) the first line carries it and the
+    /// `session` backlog is pushed before the live changes; `session` tracks
+    /// the last root pushed, for parking it if the client goes away.
+    ///
+    /// # Returns
+    /// * `Result<bool>` - `true` after `UNSUBSCRIBE`, `false` if the client disconnected
+    async fn run_merkle_subscription<R, W>(
+        reader: &mut R,
+        writer: &mut W,
+        merkle: &SharedMerkle,
+        mut changes: watch::Receiver<u64>,
+        interval: Duration,
+        session: &mut Session,
+    ) -> Result<bool>
+    where
+        R: AsyncBufRead + Unpin,
+        W: AsyncWrite + Unpin,
+    {
+        match &session.id {
+            Some(id) => writer.write_all(format!("SUBSCRIBED MERKLE {}\r\n", id).as_bytes()).await?,
+            None => writer.write_all(b"SUBSCRIBED MERKLE\r\n").await?,
+        }
+        for (version, root) in std::mem::take(&mut session.backlog) {
+            writer.write_all(format!("MERKLE_ROOT {} {}\r\n", root, version).as_bytes()).await?;
+        }
+        let mut line = Vec::new();
+        loop {
+            let version = *changes.borrow_and_update();
+            let root = merkle_tracked::live_root_hex(merkle);
+            if session.last_root.as_ref() != Some(&root) {
+                writer
+                    .write_all(format!("MERKLE_ROOT {} {}\r\n", root, version).as_bytes())
+                    .await?;
+                session.last_root = Some(root);
+            }
+
+            writer.flush().await?;
+
+            // Debounce, then wait for the next tracked write
+            let next_change = async {
+                tokio::time::sleep(interval).await;
+                if changes.changed().await.is_err() {
+                    // The store is gone; nothing will change anymore
+                    std::future::pending::<()>().await;
+                }
+            };
+            tokio::select! {
+                _ = next_change => {}
+                // `fill_buf` is cancel safe: unread bytes stay in the reader
+                buf = reader.fill_buf() => {
+                    let buf = buf?;
+                    if buf.is_empty() {
+                        return Ok(false);
+                    }
+                    // Consume at most one line so pipelined commands after UNSUBSCRIBE survive
+                    let (chunk, complete) = match buf.iter().position(|&b| b == b'\n') {
+                        Some(pos) => (&buf[..=pos], true),
+                        None => (buf, false),
+                    };
+                    line.extend_from_slice(chunk);
+                    let consumed = chunk.len();
+                    reader.consume(consumed);
+                    if !complete {
+                        continue;
+                    }
+                    let text = String::from_utf8_lossy(&line).trim().to_string();
+                    line.clear();
+                    if text.eq_ignore_ascii_case("UNSUBSCRIBE") {
+                        return Ok(true);
+                    }
+                    if !text.is_empty() {
+                        writer.write_all(b"ERROR only UNSUBSCRIBE is allowed while subscribed\r\n").await?;
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::store::test_engine::TestEngine;
+    use crate::store::RwLockEngine;
+    use tokio::net::TcpStream;
+
+    async fn start_server(config: Config) -> TcpStream {
+        let mut server = Server::new(config, Box::new(RwLockEngine::new("unused").unwrap()));
+        let addr = server.bind().unwrap();
+        tokio::spawn(server.run());
+        TcpStream::connect(addr).await.unwrap()
+    }
+
+    fn test_config() -> Config {
+        let mut config = Config::default();
+        config.engine = "rwlock".to_string();
+        config.port = std::net::TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port();
+        config
+    }
+
+    async fn read_line<R: AsyncBufRead + Unpin>(reader: &mut R) -> String {
+        let mut line = String::new();
+        tokio::time::timeout(Duration::from_secs(5), reader.read_line(&mut line))
+            .await
+            .expect("timed out waiting for a line")
+            .unwrap();
+        line
+    }
+
+    #[tokio::test]
+    async fn test_set_pushes_merkle_root_change() {
+        let mut config = test_config();
+        config.merkle.subscribe_interval_ms = 50;
+        let port = config.port;
+        let (r, mut sub_w) = start_server(config).await.into_split();
+        let mut sub = BufReader::new(r);
+
+        sub_w.write_all(b"SUBSCRIBE MERKLE\r\n").await.unwrap();
+        assert_eq!(read_line(&mut sub).await, "SUBSCRIBED MERKLE\r\n");
+        let initial = read_line(&mut sub).await;
+        assert_eq!(initial, format!("MERKLE_ROOT {} 0\r\n", "0".repeat(64)));
+
+        // A write on another connection triggers a push with the new root
+        let (r, mut w) = TcpStream::connect(("127.0.0.1", port)).await.unwrap().into_split();
+        let mut client = BufReader::new(r);
+        w.write_all(b"SET k v\r\n").await.unwrap();
+        assert_eq!(read_line(&mut client).await, "OK\r\n");
+
+        let pushed = read_line(&mut sub).await;
+        let parts: Vec<&str> = pushed.split_whitespace().collect();
+        assert_eq!(parts[0], "MERKLE_ROOT");
+        assert_ne!(parts[1], "0".repeat(64));
+        assert_eq!(parts[2], "1");
+
+        // Commands are rejected while subscribed; UNSUBSCRIBE returns to normal mode
+        sub_w.write_all(b"GET k\r\nUNSUBSCRIBE\r\nGET k\r\n").await.unwrap();
+        assert_eq!(read_line(&mut sub).await, "ERROR only UNSUBSCRIBE is allowed while subscribed\r\n");
+        assert_eq!(read_line(&mut sub).await, "OK\r\n");
+        assert_eq!(read_line(&mut sub).await, "VALUE v\r\n");
+    }
+
+    #[tokio::test]
+    async fn test_resumed_subscription_receives_the_missed_root_changes() {
+        let mut config = test_config();
+        config.merkle.subscribe_interval_ms = 20;
+        config.merkle.subscribe_buffer = 8;
+        let port = config.port;
+        let (r, mut sub_w) = start_server(config).await.into_split();
+        let mut sub = BufReader::new(r);
+
+        sub_w.write_all(b"SUBSCRIBE MERKLE\r\n").await.unwrap();
+        let subscribed = read_line(&mut sub).await;
+        let id = subscribed.strip_prefix("SUBSCRIBED MERKLE ").expect("a resume id").trim_end().to_string();
+        assert_eq!(read_line(&mut sub).await, format!("MERKLE_ROOT {} 0\r\n", "0".repeat(64)));
+        drop((sub, sub_w));
+
+        // Writes while the subscriber is away, each long enough to be its own push
+        let (r, mut w) = TcpStream::connect(("127.0.0.1", port)).await.unwrap().into_split();
+        let mut client = BufReader::new(r);
+        for key in ["a", "b", "c"] {
+            tokio::time::sleep(Duration::from_millis(100)).await;
+            w.write_all(format!("SET {} v\r\n", key).as_bytes()).await.unwrap();
+            assert_eq!(read_line(&mut client).await, "OK\r\n");
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+
+        let (r, mut sub_w) = TcpStream::connect(("127.0.0.1", port)).await.unwrap().into_split();
+        let mut sub = BufReader::new(r);
+        sub_w.write_all(format!("SUBSCRIBE MERKLE {} 0\r\n", id).as_bytes()).await.unwrap();
+        assert_eq!(read_line(&mut sub).await, format!("SUBSCRIBED MERKLE {}\r\n", id));
+        let mut roots = Vec::new();
+        for version in 1..=3 {
+            let pushed = read_line(&mut sub).await;
+            let parts: Vec<&str> = pushed.split_whitespace().collect();
+            assert_eq!((parts[0], parts[2]), ("MERKLE_ROOT", version.to_string().as_str()), "{}", pushed);
+            roots.push(parts[1].to_string());
+        }
+        roots.dedup();
+        assert_eq!(roots.len(), 3, "each missed write changed the root");
+
+        // Live pushes continue after the replay
+        w.write_all(b"SET d v\r\n").await.unwrap();
+        assert_eq!(read_line(&mut client).await, "OK\r\n");
+        assert!(read_line(&mut sub).await.ends_with(" 4\r\n"));
+
+        // An unknown id starts over under a new one
+        let (r, mut w2) = TcpStream::connect(("127.0.0.1", port)).await.unwrap().into_split();
+        let mut other = BufReader::new(r);
+        w2.write_all(b"SUBSCRIBE MERKLE nope 3\r\n").await.unwrap();
+        let line = read_line(&mut other).await;
+        assert!(line.starts_with("SUBSCRIBED MERKLE ") && !line.contains("nope"), "{}", line);
+    }
+
+    #[tokio::test]
+    async fn test_compressed_get_round_trips() {
+        let (r, mut w) = start_server(test_config()).await.into_split();
+        let mut reader = BufReader::new(r);
+        let large = "merkle-kv".repeat(2000);
+
+        w.write_all(format!("SET big {}\r\nSET small tiny\r\n", large).as_bytes()).await.unwrap();
+        assert_eq!(read_line(&mut reader).await, "OK\r\n");
+        assert_eq!(read_line(&mut reader).await, "OK\r\n");
+
+        // Uncompressed by default
+        w.write_all(b"GET small\r\n").await.unwrap();
+        assert_eq!(read_line(&mut reader).await, "VALUE tiny\r\n");
+
+        w.write_all(b"CLIENT COMPRESS ON\r\nGET big\r\nGET small\r\n").await.unwrap();
+        assert_eq!(read_line(&mut reader).await, "OK\r\n");
+        let reply = read_line(&mut reader).await;
+        let parts: Vec<&str> = reply.trim_end().split(' ').collect();
+        assert_eq!(parts[0], "VALUE_GZIP");
+        assert_eq!(parts[1], large.len().to_string());
+        assert!(reply.len() < large.len() / 10);
+        assert_eq!(compression::gunzip_base64(parts[2]).unwrap(), large);
+        // Below the threshold replies stay plain
+        assert_eq!(read_line(&mut reader).await, "VALUE tiny\r\n");
+    }
+
+    /// Read compressed bytes from `stream` into `inflate` until `len` bytes
+    /// have been decompressed; returns them and the compressed byte count.
+    async fn read_inflated(stream: &mut TcpStream, inflate: &mut flate2::Decompress, len: usize) -> (String, usize) {
+        use tokio::io::AsyncReadExt;
+        let mut plain = Vec::with_capacity(len * 2);
+        let mut compressed = 0;
+        while plain.len() < len {
+            let mut chunk = [0u8; 4096];
+            let n = tokio::time::timeout(Duration::from_secs(5), stream.read(&mut chunk)).await.unwrap().unwrap();
+            assert!(n > 0, "connection closed after {:?}", String::from_utf8_lossy(&plain));
+            compressed += n;
+            inflate.decompress_vec(&chunk[..n], &mut plain, flate2::FlushDecompress::Sync).unwrap();
+        }
+        (String::from_utf8(plain).unwrap(), compressed)
+    }
+
+    #[tokio::test]
+    async fn test_hello_compress_round_trips_commands_over_a_compressed_wire() {
+        use std::io::Write;
+        use tokio::io::AsyncReadExt;
+        let mut stream = start_server(test_config()).await;
+        let big = "x".repeat(20_000);
+        let mut zlib = flate2::write::ZlibEncoder::new(Vec::new(), flate2::Compression::default());
+        let requests = format!("SET big {}\r\nGET big\r\nSET n 1\r\nINC n\r\nGET missing\r\n", big);
+        zlib.write_all(requests.as_bytes()).unwrap();
+        zlib.flush().unwrap();
+        let sent = zlib.get_ref().len();
+        assert!(sent < requests.len() / 10, "{} compressed bytes", sent);
+        // The first compressed requests arrive in the same packet as HELLO
+        let mut wire = b"HELLO COMPRESS lz4 zlib\r\n".to_vec();
+        wire.extend_from_slice(zlib.get_ref());
+        stream.write_all(&wire).await.unwrap();
+
+        // The plain reply line, byte by byte: the compressed stream follows it
+        let mut plain_hello = Vec::new();
+        while plain_hello.last() != Some(&b'\n') {
+            plain_hello.push(stream.read_u8().await.unwrap());
+        }
+        let hello = String::from_utf8(plain_hello).unwrap();
+        let fields: Vec<&str> = hello.trim_end().split(' ').collect();
+        let commit = crate::version::GIT_COMMIT.unwrap_or("unknown");
+        assert_eq!(fields[..3], ["HELLO", "proto:1", &format!("version:{}", env!("CARGO_PKG_VERSION"))]);
+        assert_eq!(fields[3], format!("commit:{}", commit));
+        assert_eq!(fields[4], format!("features:{}", crate::version::features(&test_config()).join(",")));
+        assert_eq!(fields[5], "compression:zlib");
+        assert_eq!(hello, transport::hello_reply(&test_config(), true));
+        let mut inflate = flate2::Decompress::new(true);
+        let expected = format!("OK\r\nVALUE {}\r\nOK\r\nVALUE 2\r\nNOT_FOUND\r\n", big);
+        let (replies, received) = read_inflated(&mut stream, &mut inflate, expected.len()).await;
+        assert_eq!(replies, expected);
+        assert!(received < expected.len() / 10, "{} compressed bytes", received);
+
+        // The streams carry on across batches; a second HELLO keeps them
+        zlib.write_all(b"GET n\r\nHELLO\r\n").unwrap();
+        zlib.flush().unwrap();
+        stream.write_all(&zlib.get_ref()[sent..]).await.unwrap();
+        let expected = format!("VALUE 2\r\n{}", transport::hello_reply(&test_config(), true));
+        assert_eq!(read_inflated(&mut stream, &mut inflate, expected.len()).await.0, expected);
+    }
+
+    #[tokio::test]
+    async fn test_hello_without_an_accepted_codec_stays_plain() {
+        let mut config = test_config();
+        config.server.transport_compression = false;
+        let (r, mut w) = start_server(config.clone()).await.into_split();
+        let mut reader = BufReader::new(r);
+        let plain = transport::hello_reply(&config, false);
+        w.write_all(b"HELLO COMPRESS zlib\r\nPING\r\n").await.unwrap();
+        assert_eq!(read_line(&mut reader).await, plain);
+        assert!(read_line(&mut reader).await.starts_with("PONG"));
+
+        let (r, mut w) = start_server(test_config()).await.into_split();
+        let mut reader = BufReader::new(r);
+        w.write_all(b"HELLO\r\nHELLO COMPRESS lz4\r\nGET k\r\n").await.unwrap();
+        assert_eq!(read_line(&mut reader).await, plain);
+        assert_eq!(read_line(&mut reader).await, plain);
+        assert_eq!(read_line(&mut reader).await, "NOT_FOUND\r\n");
+    }
+
+    #[tokio::test]
+    async fn test_config_get_and_set() {
+        let config = test_config();
+        let port = config.port;
+        let (r, mut w) = start_server(config).await.into_split();
+        let mut reader = BufReader::new(r);
+
+        w.write_all(b"CONFIG GET port\r\nCONFIG GET sync_interval_seconds\r\n").await.unwrap();
+        assert_eq!(read_line(&mut reader).await, format!("VALUE {}\r\n", port));
+        assert_eq!(read_line(&mut reader).await, "VALUE 60\r\n");
+
+        w.write_all(b"CONFIG SET sync_interval_seconds 5\r\nCONFIG GET sync_interval_seconds\r\n").await.unwrap();
+        assert_eq!(read_line(&mut reader).await, "OK\r\n");
+        assert_eq!(read_line(&mut reader).await, "VALUE 5\r\n");
+
+        w.write_all(b"CONFIG SET port 1\r\n").await.unwrap();
+        assert!(read_line(&mut reader).await.contains("restart"));
+    }
+
+    #[tokio::test]
+    async fn test_serves_over_ipv6_loopback() {
+        let mut config = test_config();
+        config.host = "::1".to_string();
+        config.port = std::net::TcpListener::bind("[::1]:0").unwrap().local_addr().unwrap().port();
+        let (r, mut w) = start_server(config).await.into_split();
+        let mut reader = BufReader::new(r);
+        w.write_all(b"SET v6 yes\r\nGET v6\r\n").await.unwrap();
+        assert_eq!(read_line(&mut reader).await, "OK\r\n");
+        assert_eq!(read_line(&mut reader).await, "VALUE yes\r\n");
+    }
+
+    async fn memory_usage<W, R>(w: &mut W, reader: &mut R) -> usize
+    where
+        W: AsyncWrite + Unpin,
+        R: AsyncBufRead + Unpin,
+    {
+        w.write_all(b"MEMORY\r\n").await.unwrap();
+        let line = read_line(reader).await;
+        line.trim_end().strip_prefix("MEMORY ").unwrap().parse().unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_memory_compact_reclaims_after_deletes() {
+        let (r, mut w) = start_server(test_config()).await.into_split();
+        let mut reader = BufReader::new(r);
+
+        let mut batch = String::new();
+        for i in 0..5000 {
+            batch.push_str(&format!("SET key:{} value\r\n", i));
+        }
+        for i in 0..4990 {
+            batch.push_str(&format!("DEL key:{}\r\n", i));
+        }
+        w.write_all(batch.as_bytes()).await.unwrap();
+        for _ in 0..(5000 + 4990) {
+            read_line(&mut reader).await;
+        }
+
+        let before = memory_usage(&mut w, &mut reader).await;
+        w.write_all(b"MEMORY COMPACT\r\n").await.unwrap();
+        let reply = read_line(&mut reader).await;
+        assert!(reply.starts_with("MEMORY COMPACTED before:"), "{}", reply);
+        let after = memory_usage(&mut w, &mut reader).await;
+        assert!(after < before / 4, "before {} after {}", before, after);
+    }
+
+    /// The `cold_tier_*` lines of INFO.
+    async fn cold_tier_info<W, R>(w: &mut W, reader: &mut R) -> String
+    where
+        W: AsyncWrite + Unpin,
+        R: AsyncBufRead + Unpin,
+    {
+        w.write_all(b"INFO\r\n").await.unwrap();
+        let mut lines = Vec::new();
+        while !lines.last().is_some_and(|l: &String| l.starts_with("replication_enabled")) {
+            lines.push(read_line(reader).await);
+        }
+        lines.into_iter().filter(|l| l.starts_with("cold_tier_")).collect()
+    }
+
+    #[tokio::test]
+    async fn test_warmed_keys_are_read_from_memory() {
+        use crate::store::{SledEngine, TieredEngine};
+        let dir = tempfile::tempdir().unwrap();
+        let cold = SledEngine::new(dir.path().to_str().unwrap()).unwrap();
+        // Room for four 10-byte entries
+        let engine = TieredEngine::new(Box::new(RwLockEngine::new("unused").unwrap()), Box::new(cold), 40).unwrap();
+        let mut server = Server::new(test_config(), Box::new(engine));
+        let addr = server.bind().unwrap();
+        tokio::spawn(server.run());
+        let (r, mut w) = TcpStream::connect(addr).await.unwrap().into_split();
+        let mut reader = BufReader::new(r);
+
+        for i in 1..=6 {
+            w.write_all(format!("SET k{} aaaaaaaa\r\n", i).as_bytes()).await.unwrap();
+            assert_eq!(read_line(&mut reader).await, "OK\r\n");
+        }
+        assert_eq!(cold_tier_info(&mut w, &mut reader).await, "cold_tier_keys:2\r\ncold_tier_reads:0\r\n");
+
+        // k1 and k2 were demoted, k6 is hot
+        w.write_all(b"WARM k1 k2 k6 missing\r\nGET k1\r\nGET k2\r\n").await.unwrap();
+        assert_eq!(read_line(&mut reader).await, "WARMED 2\r\n");
+        for _ in 0..2 {
+            assert_eq!(read_line(&mut reader).await, "VALUE aaaaaaaa\r\n");
+        }
+        assert_eq!(cold_tier_info(&mut w, &mut reader).await, "cold_tier_keys:2\r\ncold_tier_reads:0\r\n");
+
+        // A read of a key that was not warmed goes to disk
+        w.write_all(b"GET k3\r\n").await.unwrap();
+        assert_eq!(read_line(&mut reader).await, "VALUE aaaaaaaa\r\n");
+        assert_eq!(cold_tier_info(&mut w, &mut reader).await, "cold_tier_keys:2\r\ncold_tier_reads:1\r\n");
+    }
+
+    #[tokio::test]
+    async fn test_random_key_samples_the_whole_keyspace() {
+        let (r, mut w) = start_server(test_config()).await.into_split();
+        let mut reader = BufReader::new(r);
+
+        w.write_all(b"RANDOMKEY\r\nSET a 1\r\nSET b 2\r\nSET c 3\r\n").await.unwrap();
+        assert_eq!(read_line(&mut reader).await, "NOT_FOUND\r\n");
+        for _ in 0..3 {
+            assert_eq!(read_line(&mut reader).await, "OK\r\n");
+        }
+        w.write_all("RANDOMKEY\r\n".repeat(200).as_bytes()).await.unwrap();
+        let mut seen = std::collections::BTreeSet::new();
+        for _ in 0..200 {
+            seen.insert(read_line(&mut reader).await);
+        }
+        assert_eq!(seen.into_iter().collect::<Vec<_>>(), vec!["VALUE a\r\n", "VALUE b\r\n", "VALUE c\r\n"]);
+    }
+
+    #[tokio::test]
+    async fn test_shard_stats_add_up_to_the_key_count() {
+        let (r, mut w) = start_server(test_config()).await.into_split();
+        let mut reader = BufReader::new(r);
+
+        let mut batch = String::new();
+        for i in 0..500 {
+            batch.push_str(&format!("SET user:{} value\r\n", i));
+        }
+        w.write_all(batch.as_bytes()).await.unwrap();
+        for _ in 0..500 {
+            read_line(&mut reader).await;
+        }
+
+        w.write_all(b"SHARD STATS\r\n").await.unwrap();
+        let header = read_line(&mut reader).await;
+        assert!(header.starts_with("SHARDS 16 keys:500 hash_fn:xxhash skew:"), "{}", header);
+        let mut keys = 0;
+        for index in 0..16 {
+            let line = read_line(&mut reader).await;
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            assert_eq!(fields[0], index.to_string());
+            keys += fields[1].strip_prefix("keys:").unwrap().parse::<usize>().unwrap();
+            assert!(fields[2].starts_with("memory_bytes:"), "{}", line);
+        }
+        assert_eq!(keys, 500);
+
+        let dir = tempfile::tempdir().unwrap();
+        let engine = crate::store::SledEngine::new(dir.path().to_str().unwrap()).unwrap();
+        let mut server = Server::new(test_config(), Box::new(engine));
+        let addr = server.bind().unwrap();
+        tokio::spawn(server.run());
+        let (r, mut w) = TcpStream::connect(addr).await.unwrap().into_split();
+        let mut reader = BufReader::new(r);
+        w.write_all(b"SHARD STATS\r\n").await.unwrap();
+        assert_eq!(read_line(&mut reader).await, "ERROR storage engine is not sharded\r\n");
+    }
+
+    #[tokio::test]
+    async fn test_unauthenticated_connections_idle_out_sooner() {
+        let mut config = test_config();
+        config.server.password = Some("s3cret".to_string());
+        config.server.unauth_idle_timeout_secs = 1;
+        config.server.idle_timeout_secs = 3;
+        let (host, port) = (config.host.clone(), config.port);
+        let (r, mut authed_w) = start_server(config).await.into_split();
+        let mut authed = BufReader::new(r);
+
+        authed_w.write_all(b"GET k\r\nAUTH wrong\r\nAUTH s3cret\r\nGET k\r\n").await.unwrap();
+        assert_eq!(read_line(&mut authed).await, "ERROR NOAUTH authentication required\r\n");
+        assert_eq!(read_line(&mut authed).await, "ERROR invalid password\r\n");
+        assert_eq!(read_line(&mut authed).await, "OK\r\n");
+        assert_eq!(read_line(&mut authed).await, "NOT_FOUND\r\n");
+
+        let started = Instant::now();
+        let (r, _unauth_w) = TcpStream::connect((host.as_str(), port)).await.unwrap().into_split();
+        let mut unauth = BufReader::new(r);
+        assert_eq!(read_line(&mut unauth).await, "ERROR idle timeout\r\n");
+        assert_eq!(read_line(&mut unauth).await, "", "connection must be closed");
+        let closed_after = started.elapsed();
+        assert!(closed_after < Duration::from_millis(2500), "{:?}", closed_after);
+
+        // The authenticated connection, idle just as long, is still open
+        authed_w.write_all(b"PING\r\n").await.unwrap();
+        assert_eq!(read_line(&mut authed).await, "PONG \r\n");
+    }
+
+    #[tokio::test]
+    async fn test_info_json_has_all_sections() {
+        let (r, mut w) = start_server(test_config()).await.into_split();
+        let mut reader = BufReader::new(r);
+
+        w.write_all(b"SET a 1\r\nINFO JSON\r\n").await.unwrap();
+        assert_eq!(read_line(&mut reader).await, "OK\r\n");
+        let line = read_line(&mut reader).await;
+        let json = line.strip_prefix("INFO_JSON ").expect("INFO JSON reply").trim_end();
+        let info: serde_json::Value = serde_json::from_str(json).unwrap();
+        for section in ["server", "keyspace", "merkle", "replication", "commands", "memory"] {
+            assert!(info.get(section).is_some(), "missing {}: {}", section, info);
+        }
+        assert_eq!(info["keyspace"]["db_keys"], 1);
+        assert_eq!(info["commands"]["set_commands"], 1);
+        assert_eq!(info["merkle"]["root"].as_str().unwrap().len(), 64);
+        assert_eq!(info["replication"]["enabled"], false);
+    }
+
+    #[tokio::test]
+    async fn test_matching_set_is_posted_to_webhook() {
+        use tokio::io::AsyncReadExt;
+
+        let hook_listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let mut config = test_config();
+        config.hooks.url = Some(format!("http://{}/kv", hook_listener.local_addr().unwrap()));
+        config.hooks.include_prefixes = vec!["user:".to_string()];
+        let (r, mut w) = start_server(config).await.into_split();
+        let mut reader = BufReader::new(r);
+
+        w.write_all(b"SET other 1\r\nSET user:1 alice\r\n").await.unwrap();
+        assert_eq!(read_line(&mut reader).await, "OK\r\n");
+        assert_eq!(read_line(&mut reader).await, "OK\r\n");
+
+        // Stub endpoint: read one request, reply 200
+        let (stream, _) = tokio::time::timeout(Duration::from_secs(5), hook_listener.accept()).await.unwrap().unwrap();
+        let mut hook = BufReader::new(stream);
+        let request_line = read_line(&mut hook).await;
+        assert_eq!(request_line, "POST /kv HTTP/1.1\r\n");
+        let mut length = 0;
+        loop {
+            let header = read_line(&mut hook).await;
+            if header == "\r\n" {
+                break;
+            }
+            if let Some(value) = header.strip_prefix("Content-Length:") {
+                length = value.trim().parse().unwrap();
+            }
+        }
+        let mut body = vec![0; length];
+        hook.read_exact(&mut body).await.unwrap();
+        hook.get_mut().write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 0\r\n\r\n").await.unwrap();
+
+        // Only the key under the configured prefix is sent
+        let event: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(event["op"], "set");
+        assert_eq!(event["key"], "user:1");
+        assert_eq!(event["value"], "alice");
+        assert!(event["timestamp"].as_u64().unwrap() > 0);
+    }
+
+    #[tokio::test]
+    async fn test_set_with_ttl_posts_the_expiry_too() {
+        use tokio::io::AsyncReadExt;
+
+        let hook_listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let mut config = test_config();
+        config.hooks.url = Some(format!("http://{}/kv", hook_listener.local_addr().unwrap()));
+        let (r, mut w) = start_server(config).await.into_split();
+        let mut reader = BufReader::new(r);
+        w.write_all(b"SET session abc EX 60\r\nSET quick x PX 10\r\n").await.unwrap();
+        assert_eq!(read_line(&mut reader).await, "OK\r\n");
+        assert_eq!(read_line(&mut reader).await, "OK\r\n");
+
+        let mut events = Vec::new();
+        for _ in 0..4 {
+            let (stream, _) = tokio::time::timeout(Duration::from_secs(5), hook_listener.accept()).await.unwrap().unwrap();
+            let mut hook = BufReader::new(stream);
+            let mut length = 0;
+            loop {
+                let header = read_line(&mut hook).await;
+                if header == "\r\n" {
+                    break;
+                }
+                if let Some(value) = header.strip_prefix("Content-Length:") {
+                    length = value.trim().parse().unwrap();
+                }
+            }
+            let mut body = vec![0; length];
+            hook.read_exact(&mut body).await.unwrap();
+            hook.get_mut().write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 0\r\n\r\n").await.unwrap();
+            let event: serde_json::Value = serde_json::from_slice(&body).unwrap();
+            events.push(format!("{} {} {}", event["op"], event["key"], event["value"]));
+        }
+        assert_eq!(
+            events,
+            vec![
+                r#""set" "session" "abc""#,
+                r#""expire" "session" "60""#,
+                r#""set" "quick" "x""#,
+                r#""expire" "quick" "1""#,
+            ],
+            "a sub-second TTL rounds up to one second"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_write_tokens_and_token_reads() {
+        let mut config = test_config();
+        config.replication.token_wait_ms = 50;
+        let (r, mut w) = start_server(config).await.into_split();
+        let mut reader = BufReader::new(r);
+
+        // Off by default: plain replies
+        w.write_all(b"SET a 1\r\nCLIENT TOKENS ON\r\nSET a 2\r\nGET a\r\n").await.unwrap();
+        assert_eq!(read_line(&mut reader).await, "OK\r\n");
+        assert_eq!(read_line(&mut reader).await, "OK\r\n");
+        assert_eq!(read_line(&mut reader).await, "OK\r\n");
+        let token = read_line(&mut reader).await;
+        let token = token.strip_prefix("TOKEN node1:").expect("TOKEN line after a write").trim_end();
+        assert!(token.parse::<u64>().is_ok(), "{}", token);
+        assert_eq!(read_line(&mut reader).await, "VALUE 2\r\n", "reads get no token");
+
+        // Own tokens are always satisfied; another node's unseen write is not
+        w.write_all(format!("GET a WITHTOKEN node1:{}\r\nGET a WITHTOKEN node9:1\r\n", token).as_bytes()).await.unwrap();
+        assert_eq!(read_line(&mut reader).await, "VALUE 2\r\n");
+        assert_eq!(read_line(&mut reader).await, "NOT_CAUGHT_UP\r\n");
+    }
+
+    #[tokio::test]
+    async fn test_expire_persist_and_ttl() {
+        let (r, mut w) = start_server(test_config()).await.into_split();
+        let mut reader = BufReader::new(r);
+
+        w.write_all(b"TTL missing\r\nEXPIRE missing 10\r\nPERSIST missing\r\n").await.unwrap();
+        assert_eq!(read_line(&mut reader).await, "VALUE -2\r\n");
+        assert_eq!(read_line(&mut reader).await, "VALUE 0\r\n");
+        assert_eq!(read_line(&mut reader).await, "VALUE 0\r\n");
+
+        w.write_all(b"SET k v\r\nTTL k\r\nEXPIRE k 100\r\nTTL k\r\nGET k\r\n").await.unwrap();
+        assert_eq!(read_line(&mut reader).await, "OK\r\n");
+        assert_eq!(read_line(&mut reader).await, "VALUE -1\r\n");
+        assert_eq!(read_line(&mut reader).await, "VALUE 1\r\n");
+        assert_eq!(read_line(&mut reader).await, "VALUE 100\r\n");
+        assert_eq!(read_line(&mut reader).await, "VALUE v\r\n", "EXPIRE keeps the value");
+
+        w.write_all(b"PERSIST k\r\nTTL k\r\nPERSIST k\r\nEXPIRE k 0\r\nTTL k\r\nGET k\r\n").await.unwrap();
+        assert_eq!(read_line(&mut reader).await, "VALUE 1\r\n");
+        assert_eq!(read_line(&mut reader).await, "VALUE -1\r\n");
+        assert_eq!(read_line(&mut reader).await, "VALUE 0\r\n", "no TTL left to remove");
+        assert_eq!(read_line(&mut reader).await, "VALUE 1\r\n");
+        assert_eq!(read_line(&mut reader).await, "VALUE -2\r\n", "EXPIRE 0 expires the key");
+        assert_eq!(read_line(&mut reader).await, "NOT_FOUND\r\n");
+    }
+
+    #[tokio::test]
+    async fn test_pipelined_flood_is_throttled_by_unread_replies() {
+        let config = test_config();
+        let (host, port) = (config.host.clone(), config.port);
+        let (r, mut w) = start_server(config).await.into_split();
+        let mut reader = BufReader::new(r);
+        let big = "x".repeat(64 * 1024);
+        w.write_all(format!("SET big {}\r\n", big).as_bytes()).await.unwrap();
+        assert_eq!(read_line(&mut reader).await, "OK\r\n");
+
+        // ~64MB of replies: far more than the socket buffers can hold
+        const PIPELINED: u64 = 1000;
+        w.write_all("GET big\r\n".repeat(PIPELINED as usize).as_bytes()).await.unwrap();
+
+        // Without reading replies, the server stops reading commands
+        let executed = |info: &str| -> u64 {
+            let json = info.strip_prefix("INFO_JSON ").unwrap().trim_end();
+            serde_json::from_str::<serde_json::Value>(json).unwrap()["commands"]["get_commands"].as_u64().unwrap()
+        };
+        let (mr, mut mw) = TcpStream::connect((host.as_str(), port)).await.unwrap().into_split();
+        let mut monitor = BufReader::new(mr);
+        tokio::time::sleep(Duration::from_millis(300)).await;
+        mw.write_all(b"INFO JSON\r\n").await.unwrap();
+        let stalled = executed(&read_line(&mut monitor).await);
+        assert!(stalled < PIPELINED, "all {} GETs ran with no reader", stalled);
+
+        // Draining the replies lets every command run
+        let expected = format!("VALUE {}\r\n", big);
+        for _ in 0..PIPELINED {
+            assert_eq!(read_line(&mut reader).await, expected);
+        }
+        mw.write_all(b"INFO JSON\r\n").await.unwrap();
+        assert_eq!(executed(&read_line(&mut monitor).await), PIPELINED);
+    }
+
+    #[tokio::test]
+    async fn test_cluster_peers_reports_the_last_sync_with_a_configured_peer() {
+        let mut peer_config = test_config();
+        peer_config.replication.client_id = "node-b".to_string();
+        let peer = net_addr::join_host_port(&peer_config.host, peer_config.port);
+        let (host, port) = (peer_config.host.clone(), peer_config.port);
+        drop(start_server(peer_config).await);
+
+        let mut config = test_config();
+        config.anti_entropy.peer_list = vec![peer.clone()];
+        let (r, mut w) = start_server(config).await.into_split();
+        let mut reader = BufReader::new(r);
+        w.write_all(b"CLUSTER PEERS\r\n").await.unwrap();
+        assert_eq!(read_line(&mut reader).await, "PEERS 1\r\n");
+        assert_eq!(
+            read_line(&mut reader).await,
+            format!("{} - configured last_seen_ms:- last_sync:never last_sync_ms:- status:unknown\r\n", peer)
+        );
+
+        let before = crate::store::expiring::now_ms();
+        w.write_all(format!("SYNC {} {}\r\nCLUSTER PEERS\r\n", host, port).as_bytes()).await.unwrap();
+        assert_eq!(read_line(&mut reader).await, "OK\r\n");
+        assert_eq!(read_line(&mut reader).await, "PEERS 1\r\n");
+        let line = read_line(&mut reader).await;
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        assert_eq!(fields[..3], [peer.as_str(), "node-b", "configured"], "{}", line);
+        assert_eq!((fields[4], fields[6]), ("last_sync:ok", "status:reachable"), "{}", line);
+        let synced_at: u64 = fields[5].strip_prefix("last_sync_ms:").unwrap().parse().unwrap();
+        assert!(synced_at >= before, "{}", line);
+        assert_eq!(fields[3], format!("last_seen_ms:{}", synced_at));
+    }
+
+    #[tokio::test]
+    async fn test_uptime_grows_while_the_start_time_stays() {
+        let (r, mut w) = start_server(test_config()).await.into_split();
+        let mut reader = BufReader::new(r);
+        let mut uptime = async || {
+            w.write_all(b"UPTIME\r\n").await.unwrap();
+            let line = read_line(&mut reader).await;
+            let (seconds, started) = line.strip_prefix("UPTIME ").unwrap().trim_end().split_once(' ').unwrap();
+            (seconds.parse::<f64>().unwrap(), started.parse::<u64>().unwrap())
+        };
+        let (first, started) = uptime().await;
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        let (second, started_again) = uptime().await;
+        // Whole milliseconds: 0.001 + 0.05 is just above 0.051 in floating point
+        assert!(((second - first) * 1000.0).round() >= 50.0, "{} then {}", first, second);
+        assert_eq!(started, started_again);
+        let now = expiring::now_ms() / 1000;
+        assert!(started <= now && started + 10 >= now, "started {} now {}", started, now);
+    }
+
+    #[tokio::test]
+    async fn test_eval_applies_all_ops_or_none() {
+        let (r, mut w) = start_server(test_config()).await.into_split();
+        let mut reader = BufReader::new(r);
+        w.write_all(b"SET stock 3\r\nEVAL INCR stock -1; SET order:1 paid; GET stock\r\n").await.unwrap();
+        assert_eq!(read_line(&mut reader).await, "OK\r\n");
+        assert_eq!(read_line(&mut reader).await, "VALUE 2\r\n");
+
+        w.write_all(b"EVAL INCR stock -1; SET order:2 paid; CAS stock 0 9\r\nGET stock\r\nGET order:2\r\n").await.unwrap();
+        assert_eq!(
+            read_line(&mut reader).await,
+            "ERROR EVAL op 3 (CAS stock) failed: value is not \"0\"; script rolled back\r\n"
+        );
+        assert_eq!(read_line(&mut reader).await, "VALUE 2\r\n");
+        assert_eq!(read_line(&mut reader).await, "NOT_FOUND\r\n");
+    }
+
+    #[tokio::test]
+    async fn test_eval_runs_on_the_selected_database_and_cannot_switch() {
+        let mut config = test_config();
+        config.server.databases = 2;
+        let (r, mut w) = start_server(config).await.into_split();
+        let mut reader = BufReader::new(r);
+        w.write_all(b"SELECT 1\r\nEVAL SET k one; GET k\r\nEVAL SET k two; SELECT 0; SET k zero\r\nGET k\r\nSELECT 0\r\nGET k\r\n")
+            .await
+            .unwrap();
+        assert_eq!(read_line(&mut reader).await, "OK\r\n");
+        assert_eq!(read_line(&mut reader).await, "VALUE one\r\n");
+        assert_eq!(
+            read_line(&mut reader).await,
+            "ERROR ERR_PARSE EVAL op 2: SELECT cannot run inside a script; SELECT the database before EVAL at token 2 (byte 5)\r\n"
+        );
+        // Nothing of the rejected script ran, and the connection is still on database 1
+        assert_eq!(read_line(&mut reader).await, "VALUE one\r\n");
+        assert_eq!(read_line(&mut reader).await, "OK\r\n");
+        assert_eq!(read_line(&mut reader).await, "NOT_FOUND\r\n");
+    }
+
+    #[tokio::test]
+    async fn test_findbyvalue_uses_the_value_index() {
+        let (r, mut w) = start_server(test_config()).await.into_split();
+        let mut reader = BufReader::new(r);
+        w.write_all(b"FINDBYVALUE x\r\n").await.unwrap();
+        assert!(read_line(&mut reader).await.starts_with("ERROR value index is disabled"));
+
+        let mut config = test_config();
+        config.index.value_prefix_enabled = true;
+        let (r, mut w) = start_server(config).await.into_split();
+        let mut reader = BufReader::new(r);
+        w.write_all(b"SET b admin:bob\r\nSET a admin:alice\r\nSET c guest\r\nDELETE b\r\n").await.unwrap();
+        for expected in ["OK\r\n", "OK\r\n", "OK\r\n", "DELETED\r\n"] {
+            assert_eq!(read_line(&mut reader).await, expected);
+        }
+        w.write_all(b"FINDBYVALUE admin:\r\n").await.unwrap();
+        assert_eq!(read_line(&mut reader).await, "KEYS 1\r\n");
+        assert_eq!(read_line(&mut reader).await, "a\r\n");
+    }
+
+    #[tokio::test]
+    async fn test_explain_reports_the_plan_without_running_it() {
+        let (r, mut w) = start_server(test_config()).await.into_split();
+        let mut reader = BufReader::new(r);
+
+        w.write_all(b"EXPLAIN SET a 1\r\n").await.unwrap();
+        assert_eq!(read_line(&mut reader).await, "PLAN write:a replicate:yes\r\n");
+        w.write_all(b"EXPLAIN DELETE missing\r\nEXPLAIN SET\r\n").await.unwrap();
+        assert_eq!(read_line(&mut reader).await, "PLAN delete:missing replicate:yes\r\n");
+        assert!(read_line(&mut reader).await.starts_with("ERROR"));
+
+        w.write_all(b"GET a\r\nDBSIZE\r\n").await.unwrap();
+        assert_eq!(read_line(&mut reader).await, "NOT_FOUND\r\n");
+        assert_eq!(read_line(&mut reader).await, "DBSIZE 0\r\n");
+    }
+
+    #[tokio::test]
+    async fn test_port_zero_binds_an_ephemeral_port() {
+        let mut config = test_config();
+        config.port = 0;
+        let mut server = Server::new(config, Box::new(RwLockEngine::new("unused").unwrap()));
+        assert!(server.local_addr().is_none());
+        let addr = server.bind().unwrap();
+        assert_ne!(addr.port(), 0);
+        assert_eq!(server.local_addr(), Some(addr));
+        tokio::spawn(server.run());
+
+        let (r, mut w) = TcpStream::connect(addr).await.unwrap().into_split();
+        let mut reader = BufReader::new(r);
+        w.write_all(b"CONFIG GET port\r\n").await.unwrap();
+        assert_eq!(read_line(&mut reader).await, format!("VALUE {}\r\n", addr.port()));
+    }
+
+    #[tokio::test]
+    async fn test_binary_keys_are_accepted_without_require_utf8_keys() {
+        let (r, mut w) = start_server(test_config()).await.into_split();
+        let mut reader = BufReader::new(r);
+        w.write_all(b"SET \xff\xfe v\xc3\r\nGET \xff\xfe\r\nSCAN \xff\r\nDBSIZE\r\n").await.unwrap();
+        let mut replies = Vec::new();
+        for _ in 0..5 {
+            let mut line = Vec::new();
+            tokio::time::timeout(Duration::from_secs(5), reader.read_until(b'\n', &mut line)).await.unwrap().unwrap();
+            replies.push(line);
+        }
+        assert_eq!(replies[0], b"OK\r\n");
+        assert_eq!(replies[1], b"VALUE v\xc3\r\n", "returned byte for byte");
+        assert_eq!(replies[2], b"KEYS 1\r\n");
+        assert_eq!(replies[3], b"\xff\xfe\r\n");
+        assert_eq!(replies[4], b"DBSIZE 1\r\n");
+    }
+
+    #[tokio::test]
+    async fn test_require_utf8_keys_refuses_binary_keys() {
+        let mut config = test_config();
+        config.server.require_utf8_keys = true;
+        let (r, mut w) = start_server(config).await.into_split();
+        let mut reader = BufReader::new(r);
+        w.write_all(b"SET \xff\xfe v\r\nSET text\xc3\xa9 v\xff\r\nSET \x00ctl v\r\nDBSIZE\r\n").await.unwrap();
+        assert_eq!(read_line(&mut reader).await, "ERROR ERR_ENCODING invalid key encoding\r\n");
+        assert_eq!(read_line(&mut reader).await, "OK\r\n", "only the key is checked");
+        assert_eq!(read_line(&mut reader).await, "OK\r\n", "control characters are valid UTF-8");
+        assert_eq!(read_line(&mut reader).await, "DBSIZE 2\r\n");
+    }
+
+    #[tokio::test]
+    async fn test_command_timeout_aborts_a_slow_command() {
+        let mut config = test_config();
+        config.server.test_commands = true;
+        config.server.command_timeout_ms = 200;
+        let (r, mut w) = start_server(config).await.into_split();
+        let mut reader = BufReader::new(r);
+
+        let started = Instant::now();
+        w.write_all(b"DEBUG SLEEP 5000\r\n").await.unwrap();
+        assert_eq!(read_line(&mut reader).await, "ERROR ERR_TIMEOUT command exceeded server.command_timeout_ms (200 ms)\r\n");
+        let elapsed = started.elapsed();
+        assert!(elapsed >= Duration::from_millis(200) && elapsed < Duration::from_secs(2), "took {:?}", elapsed);
+
+        // The connection keeps serving; commands within the limit are unaffected
+        w.write_all(b"DEBUG SLEEP 10\r\nSET k v\r\nGET k\r\n").await.unwrap();
+        assert_eq!(read_line(&mut reader).await, "OK\r\n");
+        assert_eq!(read_line(&mut reader).await, "OK\r\n");
+        assert_eq!(read_line(&mut reader).await, "VALUE v\r\n");
+
+        let (r, mut w) = start_server(test_config()).await.into_split();
+        let mut reader = BufReader::new(r);
+        w.write_all(b"DEBUG SLEEP 10\r\n").await.unwrap();
+        assert_eq!(read_line(&mut reader).await, "ERROR DEBUG is disabled (server.test_commands)\r\n");
+    }
+
+    #[tokio::test]
+    async fn test_cancel_stops_a_large_scan_and_the_connection_keeps_serving() {
+        let engine = RwLockEngine::new("unused").unwrap();
+        for i in 0..20_000 {
+            engine.set(format!("key:{:05}", i), "v".to_string()).unwrap();
+        }
+        let mut server = Server::new(test_config(), Box::new(engine));
+        let addr = server.bind().unwrap();
+        tokio::spawn(server.run());
+        let (r, mut w) = TcpStream::connect(addr).await.unwrap().into_split();
+        let mut reader = BufReader::new(r);
+
+        let started = Instant::now();
+        w.write_all(b"SCAN key:\r\nCANCEL\r\nPING\r\n").await.unwrap();
+        assert_eq!(read_line(&mut reader).await, "KEYS 20000\r\n");
+        for _ in 0..SCAN_BATCH {
+            assert!(read_line(&mut reader).await.starts_with("key:"));
+        }
+        assert_eq!(read_line(&mut reader).await, format!("CANCELLED {}\r\n", SCAN_BATCH));
+        assert!(read_line(&mut reader).await.starts_with("PONG"));
+        assert!(started.elapsed() < Duration::from_secs(2), "took {:?}", started.elapsed());
+
+        // Without CANCEL the whole reply arrives; CANCEL on its own is an error
+        w.write_all(b"SCAN key:1\r\n").await.unwrap();
+        assert_eq!(read_line(&mut reader).await, "KEYS 10000\r\n");
+        for _ in 0..10_000 {
+            assert!(read_line(&mut reader).await.starts_with("key:1"));
+        }
+        w.write_all(b"CANCEL\r\n").await.unwrap();
+        assert_eq!(read_line(&mut reader).await, "ERROR no SCAN in progress\r\n");
+    }
+
+    #[tokio::test]
+    async fn test_pipelined_gets_get_every_reply_in_order() {
+        let mut config = test_config();
+        config.server.reply_batch_max = 16;
+        let (r, mut w) = start_server(config).await.into_split();
+        let mut reader = BufReader::new(r);
+        let mut pipeline: String = (0..50).map(|i| format!("SET k{} v{}\r\n", i, i)).collect();
+        pipeline.extend((0..500).map(|i| format!("GET k{}\r\n", i % 60)));
+        w.write_all(pipeline.as_bytes()).await.unwrap();
+        for _ in 0..50 {
+            assert_eq!(read_line(&mut reader).await, "OK\r\n");
+        }
+        for i in 0..500 {
+            let expected = if i % 60 < 50 { format!("VALUE v{}\r\n", i % 60) } else { "NOT_FOUND\r\n".to_string() };
+            assert_eq!(read_line(&mut reader).await, expected, "reply {}", i);
+        }
+
+        // A lone command after the pipeline drained is answered right away
+        w.write_all(b"GET k1\r\n").await.unwrap();
+        assert_eq!(read_line(&mut reader).await, "VALUE v1\r\n");
+    }
+
+    #[tokio::test]
+    async fn test_swap_present_and_absent_keys() {
+        let (r, mut w) = start_server(test_config()).await.into_split();
+        let mut reader = BufReader::new(r);
+        w.write_all(b"SET a 1\r\nSET b 2\r\nSWAP a b\r\nMGET a b\r\n").await.unwrap();
+        for expected in ["OK\r\n", "OK\r\n", "OK\r\n", "VALUES 2\r\n", "a 2\r\n", "b 1\r\n"] {
+            assert_eq!(read_line(&mut reader).await, expected);
+        }
+
+        // The absent side moves too: a's value goes to c and a disappears
+        w.write_all(b"SWAP a c\r\nGET a\r\nGET c\r\nSWAP x y\r\nDBSIZE\r\n").await.unwrap();
+        for expected in ["OK\r\n", "NOT_FOUND\r\n", "VALUE 2\r\n", "OK\r\n", "DBSIZE 2\r\n"] {
+            assert_eq!(read_line(&mut reader).await, expected);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_failed_swap_leaves_both_keys_unchanged() {
+        let mut server = Server::new(test_config(), Box::new(TestEngine::new().read_only("locked")));
+        let addr = server.bind().unwrap();
+        tokio::spawn(server.run());
+        let (r, mut w) = TcpStream::connect(addr).await.unwrap().into_split();
+        let mut reader = BufReader::new(r);
+
+        // a is deleted first, then writing its value to locked fails: a comes back with its TTL
+        w.write_all(b"SET a 1 EX 100\r\nSWAP a locked\r\nGET a\r\nTTL a\r\nEXISTS locked\r\n").await.unwrap();
+        assert_eq!(read_line(&mut reader).await, "OK\r\n");
+        assert_eq!(read_line(&mut reader).await, "ERROR locked is read-only\r\n");
+        assert_eq!(read_line(&mut reader).await, "VALUE 1\r\n");
+        let ttl: u64 = read_line(&mut reader).await.trim_end().strip_prefix("VALUE ").unwrap().parse().unwrap();
+        assert!((90..=100).contains(&ttl), "{}", ttl);
+        assert_eq!(read_line(&mut reader).await, "EXISTS 0\r\n");
+    }
+
+    #[tokio::test]
+    async fn test_concurrent_swaps_never_lose_a_value() {
+        let mut server = Server::new(test_config(), Box::new(RwLockEngine::new("unused").unwrap()));
+        let addr = server.bind().unwrap();
+        tokio::spawn(server.run());
+
+        let (r, mut w) = TcpStream::connect(addr).await.unwrap().into_split();
+        let mut reader = BufReader::new(r);
+        w.write_all(b"SET a 1\r\nSET b 2\r\n").await.unwrap();
+        read_line(&mut reader).await;
+        read_line(&mut reader).await;
+
+        // 8 clients x 25 swaps: an even total, so a and b end where they started
+        let mut swappers = Vec::new();
+        for _ in 0..8 {
+            swappers.push(tokio::spawn(async move {
+                let (r, mut w) = TcpStream::connect(addr).await.unwrap().into_split();
+                let mut reader = BufReader::new(r);
+                for _ in 0..25 {
+                    w.write_all(b"SWAP a b\r\n").await.unwrap();
+                    assert_eq!(read_line(&mut reader).await, "OK\r\n");
+                }
+            }));
+        }
+        for swapper in swappers {
+            swapper.await.unwrap();
+        }
+        w.write_all(b"GET a\r\nGET b\r\n").await.unwrap();
+        assert_eq!(read_line(&mut reader).await, "VALUE 1\r\n");
+        assert_eq!(read_line(&mut reader).await, "VALUE 2\r\n");
+    }
+
+    #[tokio::test]
+    async fn test_ttl_cap_and_default_ttl() {
+        let mut config = test_config();
+        config.storage.max_ttl_seconds = 100;
+        config.storage.default_ttl_seconds = 30;
+        let (r, mut w) = start_server(config).await.into_split();
+        let mut reader = BufReader::new(r);
+
+        w.write_all(b"SET plain v\r\nTTL plain\r\n").await.unwrap();
+        assert_eq!(read_line(&mut reader).await, "OK\r\n");
+        assert_eq!(read_line(&mut reader).await, "VALUE 30\r\n");
+
+        w.write_all(b"SET long v EX 5000\r\nTTL long\r\nEXPIRE plain 99999\r\nTTL plain\r\n").await.unwrap();
+        for expected in ["OK\r\n", "VALUE 100\r\n", "VALUE 1\r\n", "VALUE 100\r\n"] {
+            assert_eq!(read_line(&mut reader).await, expected);
+        }
+
+        let mut config = test_config();
+        config.storage.max_ttl_seconds = 100;
+        config.storage.reject_ttl_above_max = true;
+        let (r, mut w) = start_server(config).await.into_split();
+        let mut reader = BufReader::new(r);
+        w.write_all(b"SET long v EX 5000\r\nEXISTS long\r\nSET plain v\r\nTTL plain\r\n").await.unwrap();
+        assert!(read_line(&mut reader).await.contains("max_ttl_seconds"));
+        for expected in ["EXISTS 0\r\n", "OK\r\n", "VALUE -1\r\n"] {
+            assert_eq!(read_line(&mut reader).await, expected);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_tasks_lists_the_sweeper_after_it_runs() {
+        let (r, mut w) = start_server(test_config()).await.into_split();
+        let mut reader = BufReader::new(r);
+
+        /// `(runs, last_run)` of the sweep task
+        async fn sweep_status<R: AsyncBufRead + Unpin, W: AsyncWrite + Unpin>(reader: &mut R, w: &mut W) -> (u64, u64) {
+            w.write_all(b"TASKS\r\n").await.unwrap();
+            let count: usize = read_line(reader).await.trim_end().strip_prefix("TASKS ").unwrap().parse().unwrap();
+            let mut status = None;
+            for _ in 0..count {
+                let line = read_line(reader).await;
+                if let Some(fields) = line.strip_prefix("sweep ") {
+                    let field = |name: &str| {
+                        fields.split_whitespace().find_map(|f| f.strip_prefix(name)).unwrap().to_string()
+                    };
+                    status = Some((field("runs:").parse().unwrap(), field("last_run:").parse().unwrap_or(0)));
+                }
+            }
+            status.expect("sweep task listed")
+        }
+
+        // The sweeper runs once right at start
+        let mut first = sweep_status(&mut reader, &mut w).await;
+        for _ in 0..50 {
+            if first.0 > 0 {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(20)).await;
+            first = sweep_status(&mut reader, &mut w).await;
+        }
+        assert!(first.0 >= 1 && first.1 > 0, "sweep never ran: {:?}", first);
+
+        tokio::time::sleep(Duration::from_millis(5)).await;
+        w.write_all(b"TASKS RUN sweep\r\nTASKS RUN nope\r\n").await.unwrap();
+        assert_eq!(read_line(&mut reader).await, "OK\r\n");
+        assert!(read_line(&mut reader).await.starts_with("ERROR unknown task"));
+        let mut again = sweep_status(&mut reader, &mut w).await;
+        for _ in 0..50 {
+            if again.0 > first.0 {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(20)).await;
+            again = sweep_status(&mut reader, &mut w).await;
+        }
+        assert!(again.0 > first.0 && again.1 > first.1, "{:?} then {:?}", first, again);
+    }
+
+    #[tokio::test]
+    async fn test_conditional_expire_flags() {
+        let (r, mut w) = start_server(test_config()).await.into_split();
+        let mut reader = BufReader::new(r);
+        w.write_all(b"SET k v\r\n").await.unwrap();
+        assert_eq!(read_line(&mut reader).await, "OK\r\n");
+
+        // (command, reply, TTL afterwards)
+        let steps = [
+            ("EXPIRE k 100 XX", "0", "-1"), // no TTL yet
+            ("EXPIRE k 100 GT", "0", "-1"), // no TTL counts as never, nothing is later
+            ("EXPIRE k 100 LT", "1", "100"),
+            ("EXPIRE k 50 NX", "0", "100"),
+            ("EXPIRE k 200 LT", "0", "100"),
+            ("EXPIRE k 50 GT", "0", "100"),
+            ("EXPIRE k 200 GT", "1", "200"),
+            ("EXPIRE k 150 XX", "1", "150"),
+            ("PERSIST k", "1", "-1"),
+            ("EXPIRE k 10 NX", "1", "10"),
+            ("EXPIRE missing 10 LT", "0", "-2"),
+        ];
+        for (command, reply, ttl) in steps {
+            let key = command.split_whitespace().nth(1).unwrap();
+            w.write_all(format!("{}\r\nTTL {}\r\n", command, key).as_bytes()).await.unwrap();
+            assert_eq!(read_line(&mut reader).await, format!("VALUE {}\r\n", reply), "{}", command);
+            assert_eq!(read_line(&mut reader).await, format!("VALUE {}\r\n", ttl), "TTL after {}", command);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_auth_with_users_file() {
+        use std::io::Write;
+
+        let mut users = tempfile::NamedTempFile::new().unwrap();
+        writeln!(users, "alice:{}", auth::PasswordHash::new("wonderland")).unwrap();
+        let mut config = test_config();
+        config.auth.users_file = Some(users.path().to_str().unwrap().to_string());
+        let (r, mut w) = start_server(config).await.into_split();
+        let mut reader = BufReader::new(r);
+
+        w.write_all(b"GET k\r\nAUTH alice builder\r\nAUTH wonderland\r\nAUTH alice wonderland\r\nGET k\r\n").await.unwrap();
+        assert_eq!(read_line(&mut reader).await, "ERROR NOAUTH authentication required\r\n");
+        assert_eq!(read_line(&mut reader).await, "ERROR invalid password\r\n");
+        assert_eq!(read_line(&mut reader).await, "ERROR invalid password\r\n");
+        assert_eq!(read_line(&mut reader).await, "OK\r\n");
+        assert_eq!(read_line(&mut reader).await, "NOT_FOUND\r\n");
+    }
+
+    #[tokio::test]
+    async fn test_slow_auth_does_not_stall_other_connections() {
+        use std::io::Write;
+
+        // A hash far costlier than the test default, checked on every AUTH
+        let mut users = tempfile::NamedTempFile::new().unwrap();
+        writeln!(users, "alice:pbkdf2-sha256$50000$00${}", "00".repeat(32)).unwrap();
+        writeln!(users, "bob:{}", auth::PasswordHash::new("builder")).unwrap();
+        let mut config = test_config();
+        config.auth.users_file = Some(users.path().to_str().unwrap().to_string());
+        config.server.unauth_idle_timeout_secs = 0;
+        let server = start_server(config).await;
+        let port = server.peer_addr().unwrap().port();
+        let (r, mut other) = server.into_split();
+        let mut reader = BufReader::new(r);
+        other.write_all(b"AUTH bob builder\r\n").await.unwrap();
+        assert_eq!(read_line(&mut reader).await, "OK\r\n");
+
+        let (r, mut w) = TcpStream::connect(("127.0.0.1", port)).await.unwrap().into_split();
+        let mut slow = BufReader::new(r);
+        w.write_all(b"AUTH alice guess\r\n").await.unwrap();
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        other.write_all(b"PING\r\n").await.unwrap();
+        assert_eq!(read_line(&mut reader).await, "PONG \r\n");
+        // The PING was answered while the AUTH is still hashing
+        let mut line = String::new();
+        assert!(tokio::time::timeout(Duration::from_millis(10), slow.read_line(&mut line)).await.is_err(), "{:?}", line);
+        assert_eq!(read_line(&mut slow).await, "ERROR invalid password\r\n");
+    }
+
+    #[tokio::test]
+    async fn test_values_that_end_like_options_still_round_trip() {
+        let (r, mut w) = start_server(test_config()).await.into_split();
+        let mut reader = BufReader::new(r);
+        // Stored whole before SET had options, and still
+        w.write_all(b"SET a ship it durable\r\nSET b keep it local\r\nSET c wait cl=one\r\nSET d back in ex 5\r\nGET a\r\nGET b\r\nGET c\r\nGET d\r\nTTL d\r\n")
+            .await
+            .unwrap();
+        for _ in 0..4 {
+            assert_eq!(read_line(&mut reader).await, "OK\r\n");
+        }
+        for value in ["ship it durable", "keep it local", "wait cl=one", "back in ex 5"] {
+            assert_eq!(read_line(&mut reader).await, format!("VALUE {}\r\n", value));
+        }
+        assert_eq!(read_line(&mut reader).await, "VALUE -1\r\n");
+        // Uppercase after several words is refused, and nothing is stored
+        w.write_all(b"SET e ship it DURABLE\r\nEXISTS e\r\n").await.unwrap();
+        assert!(read_line(&mut reader).await.contains("ambiguous"));
+        assert_eq!(read_line(&mut reader).await, "EXISTS 0\r\n");
+    }
+
+    #[tokio::test]
+    async fn test_acl_restricts_commands_and_keys() {
+        use std::io::Write;
+
+        let mut users = tempfile::NamedTempFile::new().unwrap();
+        writeln!(users, "admin:{}", auth::PasswordHash::new("root")).unwrap();
+        writeln!(users, "reader:{} commands=GET,SCAN keys=app:*", auth::PasswordHash::new("ro")).unwrap();
+        let mut config = test_config();
+        config.auth.users_file = Some(users.path().to_str().unwrap().to_string());
+        let server = start_server(config).await;
+        let port = server.peer_addr().unwrap().port();
+        let (r, mut w) = server.into_split();
+        let mut reader = BufReader::new(r);
+        w.write_all(b"AUTH admin root\r\nSET app:1 v\r\nSET other v\r\n").await.unwrap();
+        for _ in 0..3 {
+            assert_eq!(read_line(&mut reader).await, "OK\r\n");
+        }
+
+        let (r, mut w) = TcpStream::connect(("127.0.0.1", port)).await.unwrap().into_split();
+        let mut reader = BufReader::new(r);
+        w.write_all(b"AUTH reader ro\r\nGET app:1\r\nDEL app:1\r\nGET other\r\nSCAN app:\r\n").await.unwrap();
+        assert_eq!(read_line(&mut reader).await, "OK\r\n");
+        assert_eq!(read_line(&mut reader).await, "VALUE v\r\n");
+        assert_eq!(read_line(&mut reader).await, "ERROR ERR_NOPERM command DEL is not allowed\r\n");
+        assert_eq!(read_line(&mut reader).await, "ERROR ERR_NOPERM key 'other' is not allowed\r\n");
+        assert_eq!(read_line(&mut reader).await, "KEYS 1\r\n");
+        assert_eq!(read_line(&mut reader).await, "app:1\r\n");
+    }
+
+    #[tokio::test]
+    async fn test_stream_large_value_both_ways() {
+        let mut config = test_config();
+        config.server.stream_chunk_bytes = 64 * 1024;
+        config.server.max_stream_value_bytes = 8 * 1024 * 1024;
+        let (r, mut w) = start_server(config).await.into_split();
+        let mut reader = BufReader::new(r);
+
+        // 3 MiB of varied bytes, more than a protocol line may carry
+        let value: String = (0..3 * 1024 * 1024).map(|i| (b'a' + (i % 26) as u8) as char).collect();
+        w.write_all(format!("SET big STREAM {}\r\n", value.len()).as_bytes()).await.unwrap();
+        assert_eq!(read_line(&mut reader).await, "READY 65536\r\n");
+        for chunk in value.as_bytes().chunks(50_000) {
+            w.write_all(format!("CHUNK {}\r\n", chunk.len()).as_bytes()).await.unwrap();
+            w.write_all(chunk).await.unwrap();
+            w.write_all(b"\r\n").await.unwrap();
+        }
+        assert_eq!(read_line(&mut reader).await, "OK\r\n");
+
+        w.write_all(b"GET big STREAM\r\n").await.unwrap();
+        assert_eq!(read_line(&mut reader).await, format!("STREAM {}\r\n", value.len()));
+        let received = streaming::read_chunks(&mut reader, value.len(), 64 * 1024).await.unwrap();
+        assert_eq!(read_line(&mut reader).await, "END\r\n");
+        assert!(received == value.as_bytes(), "streamed value differs");
+
+        w.write_all(b"GET missing STREAM\r\nSET big STREAM 9000000\r\n").await.unwrap();
+        assert_eq!(read_line(&mut reader).await, "NOT_FOUND\r\n");
+        assert!(read_line(&mut reader).await.contains("max_stream_value_bytes"));
+    }
+
+    #[tokio::test]
+    async fn test_durable_set_syncs_before_reply() {
+        let engine = TestEngine::new();
+        let syncs = engine.syncs();
+        let mut server = Server::new(test_config(), Box::new(engine));
+        let addr = server.bind().unwrap();
+        tokio::spawn(server.run());
+        let (r, mut w) = TcpStream::connect(addr).await.unwrap().into_split();
+        let mut reader = BufReader::new(r);
+
+        w.write_all(b"SET bulk v\r\n").await.unwrap();
+        assert_eq!(read_line(&mut reader).await, "OK\r\n");
+        assert_eq!(syncs.load(Ordering::SeqCst), 0, "plain SET does not flush");
+
+        w.write_all(b"SET critical v DURABLE\r\nSET ttl v EX 60 DURABLE\r\nGET critical\r\n").await.unwrap();
+        assert_eq!(read_line(&mut reader).await, "OK\r\n");
+        assert_eq!(read_line(&mut reader).await, "OK\r\n");
+        assert_eq!(read_line(&mut reader).await, "VALUE v\r\n");
+        assert_eq!(syncs.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn test_group_commit_shares_flushes_between_concurrent_durable_writes() {
+        let engine = TestEngine::new();
+        let syncs = engine.syncs();
+        let mut config = test_config();
+        config.storage.group_commit_window_us = 20_000;
+        let mut server = Server::new(config, Box::new(engine));
+        let addr = server.bind().unwrap();
+        tokio::spawn(server.run());
+
+        let mut clients = Vec::new();
+        for _ in 0..40 {
+            let (r, w) = TcpStream::connect(addr).await.unwrap().into_split();
+            clients.push((BufReader::new(r), w));
+        }
+        for (i, (_, w)) in clients.iter_mut().enumerate() {
+            w.write_all(format!("SET key:{} v DURABLE\r\n", i).as_bytes()).await.unwrap();
+        }
+        for (reader, _) in clients.iter_mut() {
+            assert_eq!(read_line(reader).await, "OK\r\n");
+            assert!(syncs.load(Ordering::SeqCst) >= 1, "OK only after a flush");
+        }
+        let flushes = syncs.load(Ordering::SeqCst);
+        assert!(flushes <= 4, "40 durable writes took {} flushes", flushes);
+    }
+
+    #[tokio::test]
+    async fn test_bootstrap_from_populated_peer() {
+        let peer = start_server(test_config()).await;
+        let peer_addr = peer.peer_addr().unwrap().to_string();
+        let (r, mut w) = peer.into_split();
+        let mut reader = BufReader::new(r);
+        w.write_all(b"SET a 1\r\nSET b two words\r\nEXPIRE b 600\r\nSET gone x\r\nDEL gone\r\n").await.unwrap();
+        for expected in ["OK\r\n", "OK\r\n", "VALUE 1\r\n", "OK\r\n", "DELETED\r\n"] {
+            assert_eq!(read_line(&mut reader).await, expected);
+        }
+
+        let mut node = Server::new(test_config(), Box::new(RwLockEngine::new("unused").unwrap()));
+        assert_eq!(node.bootstrap_from(&peer_addr).await.unwrap(), 2);
+        let addr = node.bind().unwrap();
+        tokio::spawn(node.run());
+        let (r, mut w) = TcpStream::connect(addr).await.unwrap().into_split();
+        let mut reader = BufReader::new(r);
+        w.write_all(b"DBSIZE\r\nGET a\r\nGET b\r\nTTL b\r\nTOMBSTONES\r\n").await.unwrap();
+        assert_eq!(read_line(&mut reader).await, "DBSIZE 2\r\n");
+        assert_eq!(read_line(&mut reader).await, "VALUE 1\r\n");
+        assert_eq!(read_line(&mut reader).await, "VALUE two words\r\n");
+        let ttl: u64 = read_line(&mut reader).await.trim_end().strip_prefix("VALUE ").unwrap().parse().unwrap();
+        assert!((590..=600).contains(&ttl), "TTL carried over: {}", ttl);
+        assert_eq!(read_line(&mut reader).await, "TOMBSTONES 1\r\n");
+        assert!(read_line(&mut reader).await.starts_with("gone "));
+
+        let mut empty = Server::new(test_config(), Box::new(RwLockEngine::new("unused").unwrap()));
+        assert!(empty.bootstrap_from("127.0.0.1:1").await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_line_terminators() {
+        let (r, mut w) = start_server(test_config()).await.into_split();
+        let mut reader = BufReader::new(r);
+        w.write_all(b"SET a 1\nGET a\r\nGET a").await.unwrap();
+        w.shutdown().await.unwrap();
+        for expected in ["OK\r\n", "VALUE 1\r\n", "VALUE 1\r\n"] {
+            assert_eq!(read_line(&mut reader).await, expected);
+        }
+        assert_eq!(read_line(&mut reader).await, "", "connection closed after EOF");
+
+        let mut config = test_config();
+        config.server.strict_crlf = true;
+        let (r, mut w) = start_server(config).await.into_split();
+        let mut reader = BufReader::new(r);
+        w.write_all(b"SET a 1\nGET a\r\n").await.unwrap();
+        assert!(read_line(&mut reader).await.contains("strict_crlf"));
+        assert_eq!(read_line(&mut reader).await, "NOT_FOUND\r\n");
+    }
+
+    #[tokio::test]
+    async fn test_commands_over_max_args_are_refused_and_not_applied() {
+        let mut config = test_config();
+        config.server.max_args = 4;
+        let (r, mut w) = start_server(config).await.into_split();
+        let mut reader = BufReader::new(r);
+        let flood = format!("MSET{}\r\n", (0..50_000).map(|i| format!(" k{} v", i)).collect::<String>());
+        w.write_all(flood.as_bytes()).await.unwrap();
+        w.write_all(b"GET k0\r\nMSET a 1 b 2\r\nDBSIZE\r\n").await.unwrap();
+        assert_eq!(
+            read_line(&mut reader).await,
+            "ERROR ERR_PARSE too many arguments (server.max_args is 4) at token 6 (byte 15)\r\n"
+        );
+        assert_eq!(read_line(&mut reader).await, "NOT_FOUND\r\n");
+        assert_eq!(read_line(&mut reader).await, "OK\r\n");
+        assert_eq!(read_line(&mut reader).await, "DBSIZE 2\r\n");
+    }
+
+    #[tokio::test]
+    async fn test_an_endless_line_is_refused_without_waiting_for_its_end() {
+        let (r, mut w) = start_server(test_config()).await.into_split();
+        let mut reader = BufReader::new(r);
+        // No newline ever comes: one byte past the limit must be enough
+        w.write_all(b"SET k ").await.unwrap();
+        w.write_all(&vec![b'a'; MAX_LINE_BYTES - 5]).await.unwrap();
+        assert_eq!(read_line(&mut reader).await, "ERROR line too long\r\n");
+        assert_eq!(read_line(&mut reader).await, "");
+    }
+
+    /// Start a server that replicates through a broker-less replicator; the
+    /// returned receiver yields its published MQTT requests.
+    async fn start_replicated(config: Config, node_id: &str) -> (SocketAddr, Replicator, flume::Receiver<rumqttc::Request>) {
+        let (replicator, published) = Replicator::detached(node_id);
+        let mut server = Server::new(config, Box::new(RwLockEngine::new("unused").unwrap())).with_replicator(replicator.clone());
+        let addr = server.bind().unwrap();
+        tokio::spawn(server.run());
+        (addr, replicator, published)
+    }
+
+    /// Play the broker: deliver everything `from` publishes to `to`.
+    fn forward(from: flume::Receiver<rumqttc::Request>, to: Replicator) {
+        tokio::spawn(async move {
+            while let Ok(request) = from.recv_async().await {
+                if let rumqttc::Request::Publish(p) = request {
+                    to.deliver(&p.payload);
+                }
+            }
+        });
+    }
+
+    #[tokio::test]
+    async fn test_local_set_neither_publishes_nor_moves_the_merkle_root() {
+        let (addr, _replicator, published) = start_replicated(test_config(), "node-a").await;
+        let (r, mut w) = TcpStream::connect(addr).await.unwrap().into_split();
+        let mut reader = BufReader::new(r);
+        let publishes = || published.try_iter().filter(|r| matches!(r, rumqttc::Request::Publish(_))).count();
+
+        w.write_all(b"SET shared 1\r\nMERKLE VERIFY\r\n").await.unwrap();
+        assert_eq!(read_line(&mut reader).await, "OK\r\n");
+        let before = read_line(&mut reader).await;
+        assert!(before.starts_with("MERKLE OK keys:1 "), "{}", before);
+        assert_eq!(publishes(), 1);
+
+        w.write_all(b"SET scratch data LOCAL\r\nSET timed x EX 60 LOCAL\r\nGET scratch\r\nMERKLE VERIFY\r\n").await.unwrap();
+        assert_eq!(read_line(&mut reader).await, "OK\r\n");
+        assert_eq!(read_line(&mut reader).await, "OK\r\n");
+        assert_eq!(read_line(&mut reader).await, "VALUE data\r\n");
+        assert_eq!(read_line(&mut reader).await, before, "LOCAL writes leave the synced root alone");
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert_eq!(publishes(), 0, "LOCAL writes publish nothing");
+
+        w.write_all(b"SET k v CL=all LOCAL\r\n").await.unwrap();
+        assert!(read_line(&mut reader).await.starts_with("ERROR LOCAL writes are not replicated"));
+
+        // A plain write replicates the key again
+        w.write_all(b"SET scratch tmp\r\nMERKLE VERIFY\r\n").await.unwrap();
+        assert_eq!(read_line(&mut reader).await, "OK\r\n");
+        assert!(read_line(&mut reader).await.starts_with("MERKLE OK keys:2 "));
+        assert_eq!(publishes(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_verify_consistent_after_replicated_workload() {
+        let mut config = test_config();
+        config.server.test_commands = true;
+        let (addr_a, repl_a, published_a) = start_replicated(config, "node-a").await;
+        let mut config_b = test_config();
+        config_b.server.test_commands = true;
+        let (addr_b, repl_b, published_b) = start_replicated(config_b, "node-b").await;
+        forward(published_a, repl_b);
+        forward(published_b, repl_a);
+
+        let (r, mut w) = TcpStream::connect(addr_a).await.unwrap().into_split();
+        let mut reader = BufReader::new(r);
+        let workload: &[(&str, &str)] = &[
+            ("SET user:1 ann", "OK"),
+            ("SET user:2 bob", "OK"),
+            ("APPEND user:1 -smith", "VALUE ann-smith"),
+            ("INC counter 5", "VALUE 5"),
+            ("SET gone x", "OK"),
+            ("DEL gone", "DELETED"),
+            ("MSET a 1 b 2", "OK"),
+            ("SWAP a c", "OK"),
+        ];
+        for (command, reply) in workload {
+            w.write_all(format!("{}\r\n", command).as_bytes()).await.unwrap();
+            assert_eq!(read_line(&mut reader).await, format!("{}\r\n", reply), "{}", command);
+        }
+
+        // Replication is asynchronous: poll until B has caught up
+        let verify = format!("VERIFY CONSISTENT {}\r\n", addr_b);
+        let mut last = String::new();
+        for _ in 0..100 {
+            w.write_all(verify.as_bytes()).await.unwrap();
+            last = read_line(&mut reader).await;
+            if last.starts_with("CONSISTENT ") {
+                break;
+            }
+            let differences: usize = last.split_whitespace().skip(1).take(3).map(|f| f.split(':').nth(1).unwrap().parse::<usize>().unwrap()).sum();
+            for _ in 0..differences {
+                read_line(&mut reader).await;
+            }
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+        assert!(last.starts_with("CONSISTENT keys:5 root:"), "{}", last);
+
+        // A node that saw none of it reports every key as missing
+        let mut config_c = test_config();
+        config_c.server.test_commands = true;
+        let (r, mut w) = start_server(config_c).await.into_split();
+        let mut reader = BufReader::new(r);
+        w.write_all(b"SET c stale\r\nSET only-here 1\r\n").await.unwrap();
+        read_line(&mut reader).await;
+        read_line(&mut reader).await;
+        w.write_all(verify.as_bytes()).await.unwrap();
+        let header = read_line(&mut reader).await;
+        assert!(header.starts_with("INCONSISTENT missing:4 extra:1 differing:1 "), "{}", header);
+        let mut lines = Vec::new();
+        for _ in 0..6 {
+            lines.push(read_line(&mut reader).await.trim_end().to_string());
+        }
+        assert_eq!(lines, ["MISSING b", "MISSING counter", "MISSING user:1", "MISSING user:2", "EXTRA only-here", "DIFFERENT c"]);
+    }
+
+    #[tokio::test]
+    async fn test_verify_requires_test_commands() {
+        let (r, mut w) = start_server(test_config()).await.into_split();
+        let mut reader = BufReader::new(r);
+        w.write_all(b"VERIFY CONSISTENT 127.0.0.1:1\r\n").await.unwrap();
+        assert_eq!(read_line(&mut reader).await, "ERROR VERIFY is disabled (server.test_commands)\r\n");
+    }
+
+    #[tokio::test]
+    async fn test_get_withcrc_returns_crc32_of_value() {
+        let (r, mut w) = start_server(test_config()).await.into_split();
+        let mut reader = BufReader::new(r);
+        w.write_all(b"SET k 123456789\r\nGET k WITHCRC\r\nSET k 123456780\r\nGET k WITHCRC\r\nGET nope WITHCRC\r\n").await.unwrap();
+        assert_eq!(read_line(&mut reader).await, "OK\r\n");
+        // The standard CRC-32 check value
+        assert_eq!(read_line(&mut reader).await, "VALUE_CRC cbf43926 123456789\r\n");
+        assert_eq!(read_line(&mut reader).await, "OK\r\n");
+        let changed = read_line(&mut reader).await;
+        let (crc, value) = changed.trim_end().strip_prefix("VALUE_CRC ").unwrap().split_once(' ').unwrap();
+        assert_eq!(value, "123456780");
+        let mut expected = flate2::Crc::new();
+        expected.update(value.as_bytes());
+        assert_eq!(u32::from_str_radix(crc, 16).unwrap(), expected.sum());
+        assert_ne!(crc, "cbf43926");
+        assert_eq!(read_line(&mut reader).await, "NOT_FOUND\r\n");
+    }
+
+    #[tokio::test]
+    async fn test_json_incr_updates_a_nested_number() {
+        let (r, mut w) = start_server(test_config()).await.into_split();
+        let mut reader = BufReader::new(r);
+        w.write_all(b"SET doc {\"hits\":{\"today\":9},\"name\":\"x\"}\r\njson.incr doc /hits/today 3\r\nGET doc\r\n")
+            .await
+            .unwrap();
+        assert_eq!(read_line(&mut reader).await, "OK\r\n");
+        assert_eq!(read_line(&mut reader).await, "VALUE 12\r\n");
+        assert_eq!(read_line(&mut reader).await, "VALUE {\"hits\":{\"today\":12},\"name\":\"x\"}\r\n");
+        w.write_all(b"JSON.INCR doc /name 1\r\nJSON.INCR doc /hits/total 1\r\n").await.unwrap();
+        assert_eq!(read_line(&mut reader).await, "ERROR value at /name is not a number\r\n");
+        assert_eq!(read_line(&mut reader).await, "ERROR path /hits/total not found\r\n");
+    }
+
+    #[tokio::test]
+    async fn test_bigkeys_lists_the_largest_values_first() {
+        let (r, mut w) = start_server(test_config()).await.into_split();
+        let mut reader = BufReader::new(r);
+        let mut setup = String::new();
+        for (i, size) in [3, 300, 30, 3000, 1, 30000].iter().enumerate() {
+            setup.push_str(&format!("SET k{} {}\r\n", i, "x".repeat(*size)));
+        }
+        w.write_all(setup.as_bytes()).await.unwrap();
+        for _ in 0..6 {
+            assert_eq!(read_line(&mut reader).await, "OK\r\n");
+        }
+        w.write_all(b"BIGKEYS 3\r\n").await.unwrap();
+        assert_eq!(read_line(&mut reader).await, "BIGKEYS 3 sampled:6\r\n");
+        assert_eq!(read_line(&mut reader).await, "k5 30000\r\n");
+        assert_eq!(read_line(&mut reader).await, "k3 3000\r\n");
+        assert_eq!(read_line(&mut reader).await, "k1 300\r\n");
+        w.write_all(b"BIGKEYS\r\n").await.unwrap();
+        assert_eq!(read_line(&mut reader).await, "BIGKEYS 6 sampled:6\r\n");
+    }
+
+    #[tokio::test]
+    async fn test_rangehash_matches_an_engine_with_the_same_range() {
+        let (r, mut w) = start_server(test_config()).await.into_split();
+        let mut reader = BufReader::new(r);
+        w.write_all(b"SET user:1 ann\r\nSET user:2 bob\r\nSET other x\r\nRANGEHASH user:0 user:9\r\n").await.unwrap();
+        for _ in 0..3 {
+            assert_eq!(read_line(&mut reader).await, "OK\r\n");
+        }
+        let local = RwLockEngine::new("unused").unwrap();
+        local.set("user:1".to_string(), "ann".to_string()).unwrap();
+        local.set("user:2".to_string(), "bob".to_string()).unwrap();
+        let (_, hash) = crate::store::merkle::range_hash(&local, "user:0", "user:9");
+        assert_eq!(read_line(&mut reader).await, format!("RANGEHASH 2 {}\r\n", hex::encode(&hash)));
+
+        w.write_all(b"SET user:2 bert\r\nRANGEHASH user:0 user:9\r\n").await.unwrap();
+        assert_eq!(read_line(&mut reader).await, "OK\r\n");
+        let changed = read_line(&mut reader).await;
+        assert!(changed.starts_with("RANGEHASH 2 ") && !changed.contains(&hex::encode(&hash)), "{}", changed);
+    }
+
+    #[tokio::test]
+    async fn test_get_withttl_returns_value_and_remaining_ttl() {
+        let (r, mut w) = start_server(test_config()).await.into_split();
+        let mut reader = BufReader::new(r);
+        w.write_all(b"SET session abc EX 100\r\nSET plain xyz\r\nGET session WITHTTL\r\nGET plain withttl\r\nGET nope WITHTTL\r\n")
+            .await
+            .unwrap();
+        assert_eq!(read_line(&mut reader).await, "OK\r\n");
+        assert_eq!(read_line(&mut reader).await, "OK\r\n");
+        assert_eq!(read_line(&mut reader).await, "VALUE_TTL 100 abc\r\n");
+        assert_eq!(read_line(&mut reader).await, "VALUE_TTL -1 xyz\r\n");
+        assert_eq!(read_line(&mut reader).await, "NOT_FOUND\r\n");
+    }
+
+    #[tokio::test]
+    async fn test_log_level_overrides_one_module() {
+        let (r, mut w) = start_server(test_config()).await.into_split();
+        let mut reader = BufReader::new(r);
+        w.write_all(b"LOG LEVEL tasks trace\r\nLOG LEVEL tasks chatty\r\nLOG LEVEL tasks default\r\n").await.unwrap();
+        assert_eq!(read_line(&mut reader).await, "OK\r\n");
+        assert!(read_line(&mut reader).await.contains("Invalid level 'chatty'"));
+        assert_eq!(read_line(&mut reader).await, "OK\r\n");
+    }
+
+    #[tokio::test]
+    async fn test_object_times_survive_overwrite_and_reset_on_recreate() {
+        let (r, mut w) = start_server(test_config()).await.into_split();
+        let mut reader = BufReader::new(r);
+        async fn stamp(w: &mut tokio::net::tcp::OwnedWriteHalf, reader: &mut BufReader<tokio::net::tcp::OwnedReadHalf>, cmd: &str) -> u64 {
+            w.write_all(format!("{}\r\n", cmd).as_bytes()).await.unwrap();
+            let line = read_line(reader).await;
+            line.trim_end().strip_prefix("VALUE ").unwrap_or_else(|| panic!("{}: {}", cmd, line)).parse().unwrap()
+        }
+
+        w.write_all(b"OBJECT CREATED k\r\nSET k v1\r\n").await.unwrap();
+        assert_eq!(read_line(&mut reader).await, "NOT_FOUND\r\n");
+        assert_eq!(read_line(&mut reader).await, "OK\r\n");
+        let created = stamp(&mut w, &mut reader, "OBJECT CREATED k").await;
+        assert_eq!(stamp(&mut w, &mut reader, "OBJECT MODIFIED k").await, created);
+
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        w.write_all(b"SET k v2\r\n").await.unwrap();
+        assert_eq!(read_line(&mut reader).await, "OK\r\n");
+        assert_eq!(stamp(&mut w, &mut reader, "OBJECT CREATED k").await, created, "overwrite keeps created-at");
+        assert!(stamp(&mut w, &mut reader, "OBJECT MODIFIED k").await > created);
+        assert_eq!(stamp(&mut w, &mut reader, "OBJECT IDLETIME k").await, 0);
+
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        w.write_all(b"DEL k\r\nOBJECT MODIFIED k\r\nSET k v3\r\n").await.unwrap();
+        assert_eq!(read_line(&mut reader).await, "DELETED\r\n");
+        assert_eq!(read_line(&mut reader).await, "NOT_FOUND\r\n");
+        assert_eq!(read_line(&mut reader).await, "OK\r\n");
+        assert!(stamp(&mut w, &mut reader, "OBJECT CREATED k").await > created, "delete + recreate resets created-at");
+    }
+
+    #[tokio::test]
+    async fn test_touch_moves_the_access_time_only() {
+        let (r, mut w) = start_server(test_config()).await.into_split();
+        let mut reader = BufReader::new(r);
+        w.write_all(b"SET a 1\r\nSET b 2\r\nOBJECT ACCESSED a\r\n").await.unwrap();
+        assert_eq!(read_line(&mut reader).await, "OK\r\n");
+        assert_eq!(read_line(&mut reader).await, "OK\r\n");
+        let written = read_line(&mut reader).await;
+
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        w.write_all(b"TOUCH a b missing\r\nOBJECT ACCESSED a\r\nOBJECT MODIFIED a\r\n").await.unwrap();
+        assert_eq!(read_line(&mut reader).await, "TOUCHED 2\r\n");
+        let touched = read_line(&mut reader).await;
+        assert_eq!(read_line(&mut reader).await, written, "TOUCH is not a write");
+        let ms = |line: &str| line.trim_end().strip_prefix("VALUE ").unwrap().parse::<u64>().unwrap();
+        assert!(ms(&touched) >= ms(&written) + 20, "{} -> {}", written, touched);
+    }
+
+    #[tokio::test]
+    async fn test_writes_rejected_above_memory_watermark() {
+        let mut config = test_config();
+        config.storage.write_reject_bytes = 16 * 1024;
+        let (r, mut w) = start_server(config).await.into_split();
+        let mut reader = BufReader::new(r);
+        let big = "x".repeat(32 * 1024);
+
+        // Below the watermark: accepted (and it pushes usage over)
+        w.write_all(format!("SET big {}\r\n", big).as_bytes()).await.unwrap();
+        assert_eq!(read_line(&mut reader).await, "OK\r\n");
+        tokio::time::sleep(crate::watermark::REFRESH).await;
+
+        let oom = "ERROR ERR_OOM command not allowed when over memory limit\r\n";
+        w.write_all(b"SET k v\r\nAPPEND big y\r\nINC n\r\nGET k\r\n").await.unwrap();
+        for _ in 0..3 {
+            assert_eq!(read_line(&mut reader).await, oom);
+        }
+        assert_eq!(read_line(&mut reader).await, "NOT_FOUND\r\n", "rejected SET wrote nothing");
+        w.write_all(b"GET big\r\nDEL big\r\n").await.unwrap();
+        assert_eq!(read_line(&mut reader).await, format!("VALUE {}\r\n", big));
+        assert_eq!(read_line(&mut reader).await, "DELETED\r\n");
+
+        // Deleting brings usage back under: writes resume after a refresh
+        tokio::time::sleep(crate::watermark::REFRESH).await;
+        w.write_all(b"SET k v\r\n").await.unwrap();
+        assert_eq!(read_line(&mut reader).await, "OK\r\n");
+    }
+
+    #[tokio::test]
+    async fn test_secondary_forwards_writes_to_primary() {
+        // Stub primary: records each request line and answers OK
+        let primary = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let primary_addr = primary.local_addr().unwrap().to_string();
+        let (seen_tx, mut seen_rx) = tokio::sync::mpsc::unbounded_channel();
+        tokio::spawn(async move {
+            let (socket, _) = primary.accept().await.unwrap();
+            let (r, mut w) = socket.into_split();
+            let mut r = BufReader::new(r);
+            loop {
+                let mut line = String::new();
+                if r.read_line(&mut line).await.unwrap() == 0 {
+                    break;
+                }
+                seen_tx.send(line).unwrap();
+                w.write_all(b"OK\r\n").await.unwrap();
+            }
+        });
+
+        let mut config = test_config();
+        config.cluster.role = crate::cluster::Role::Secondary;
+        config.cluster.primary_addr = Some(primary_addr.clone());
+        let (r, mut w) = start_server(config).await.into_split();
+        let mut reader = BufReader::new(r);
+        w.write_all(b"SET k v\r\n").await.unwrap();
+        assert_eq!(read_line(&mut reader).await, "OK\r\n", "the primary's reply");
+        assert_eq!(seen_rx.recv().await.unwrap(), "SET k v\r\n");
+        // Reads stay local: the secondary only gets the key by replication
+        w.write_all(b"GET k\r\n").await.unwrap();
+        assert_eq!(read_line(&mut reader).await, "NOT_FOUND\r\n");
+
+        let mut config = test_config();
+        config.cluster.role = crate::cluster::Role::Secondary;
+        config.cluster.primary_addr = Some(primary_addr.clone());
+        config.cluster.forward_writes = false;
+        let (r, mut w) = start_server(config).await.into_split();
+        let mut reader = BufReader::new(r);
+        w.write_all(b"DEL k\r\n").await.unwrap();
+        assert_eq!(read_line(&mut reader).await, format!("ERROR READONLY writes go to the primary {}\r\n", primary_addr));
+    }
+
+    #[tokio::test]
+    async fn test_merkle_fetch_returns_exactly_the_subtree_keys() {
+        let mut config = test_config();
+        config.replication.exclude_prefixes = vec!["local:".to_string()];
+        let (r, mut w) = start_server(config).await.into_split();
+        let mut reader = BufReader::new(r);
+        let pairs: Vec<(String, String)> = (0..11).map(|i| (format!("k{:02}", i), format!("v{}", i))).collect();
+        for (k, v) in &pairs {
+            w.write_all(format!("SET {} {}\r\n", k, v).as_bytes()).await.unwrap();
+            assert_eq!(read_line(&mut reader).await, "OK\r\n");
+        }
+        w.write_all(b"SET local:x 1\r\n").await.unwrap();
+        assert_eq!(read_line(&mut reader).await, "OK\r\n");
+
+        // The same tree built locally says which leaves sit under path 10
+        let tree = crate::store::merkle::MerkleTree::from_pairs(pairs.iter().map(|(k, v)| (k.as_str(), v.as_str())));
+        let node = tree.subtree("10").unwrap();
+        let expected = node.leaf_keys();
+        assert!(!expected.is_empty() && expected.len() < pairs.len());
+
+        w.write_all(b"MERKLE FETCH 10\r\n").await.unwrap();
+        assert_eq!(read_line(&mut reader).await, format!("SUBTREE {} {}\r\n", hex::encode(&node.hash), expected.len()));
+        for key in &expected {
+            let value = &pairs.iter().find(|(k, _)| k == key).unwrap().1;
+            assert_eq!(read_line(&mut reader).await, format!("{} {}\r\n", key, value));
+        }
+
+        // The root covers every replicated key; node-local keys are not in the tree
+        w.write_all(b"MERKLE FETCH\r\n").await.unwrap();
+        let header = read_line(&mut reader).await;
+        assert!(header.ends_with(&format!(" {}\r\n", pairs.len())), "{}", header);
+        for _ in 0..pairs.len() {
+            assert!(!read_line(&mut reader).await.starts_with("local:"));
+        }
+        w.write_all(b"MERKLE FETCH 1111111\r\n").await.unwrap();
+        assert_eq!(read_line(&mut reader).await, "ERROR no Merkle node at path '1111111'\r\n");
+    }
+
+    #[tokio::test]
+    async fn test_consistent_set_reports_missing_peer_acks() {
+        let mut config = test_config();
+        config.replication.peer_list = vec!["10.0.0.2:7379".to_string(), "10.0.0.3:7379".to_string()];
+        config.replication.ack_timeout_ms = 50;
+        let (r, mut w) = start_server(config).await.into_split();
+        let mut reader = BufReader::new(r);
+
+        // No peer can ack here: CL=one replies at once, quorum and all time out
+        w.write_all(b"SET a v CL=one\r\nSET b v CL=quorum\r\nSET c v CL=all DURABLE\r\nGET c\r\n").await.unwrap();
+        assert_eq!(read_line(&mut reader).await, "OK\r\n");
+        assert_eq!(read_line(&mut reader).await, "ERROR ERR_TIMEOUT write applied locally but only 0 of 1 peer acks arrived\r\n");
+        assert_eq!(read_line(&mut reader).await, "ERROR ERR_TIMEOUT write applied locally but only 0 of 2 peer acks arrived\r\n");
+        assert_eq!(read_line(&mut reader).await, "VALUE v\r\n", "the write stays applied");
+    }
+
+    #[tokio::test]
+    async fn test_merkle_gc_reclaims_nodes_of_deleted_keys() {
+        let (r, mut w) = start_server(test_config()).await.into_split();
+        let mut reader = BufReader::new(r);
+        for i in 0..20 {
+            w.write_all(format!("SET k{:02} v\r\n", i).as_bytes()).await.unwrap();
+            assert_eq!(read_line(&mut reader).await, "OK\r\n");
+        }
+        // Reading the root materializes the tree: 20 leaves + 19 internal nodes
+        w.write_all(b"MERKLE FETCH\r\n").await.unwrap();
+        assert!(read_line(&mut reader).await.ends_with(" 20\r\n"));
+        for _ in 0..20 {
+            read_line(&mut reader).await;
+        }
+        for i in 5..20 {
+            w.write_all(format!("DEL k{:02}\r\n", i).as_bytes()).await.unwrap();
+            assert_eq!(read_line(&mut reader).await, "DELETED\r\n");
+        }
+
+        w.write_all(b"MERKLE GC\r\nMERKLE GC\r\nINFO JSON\r\n").await.unwrap();
+        assert_eq!(read_line(&mut reader).await, "MERKLE GC reclaimed:30 nodes:9\r\n");
+        assert_eq!(read_line(&mut reader).await, "MERKLE GC reclaimed:0 nodes:9\r\n");
+        let info = read_line(&mut reader).await;
+        let info: serde_json::Value = serde_json::from_str(info.strip_prefix("INFO_JSON ").unwrap()).unwrap();
+        assert_eq!(info["merkle"]["nodes"], 9);
+    }
+
+    #[tokio::test]
+    async fn test_persistence_off_is_reported_and_needs_a_wal() {
+        let (r, mut w) = start_server(test_config()).await.into_split();
+        let mut reader = BufReader::new(r);
+        w.write_all(b"PERSISTENCE OFF\r\n").await.unwrap();
+        assert!(read_line(&mut reader).await.starts_with("ERROR no write-ahead log"));
+
+        let dir = tempfile::tempdir().unwrap();
+        let wal = crate::store::WalEngine::open(
+            Box::new(RwLockEngine::new("unused").unwrap()),
+            &dir.path().join("wal.log"),
+            crate::store::wal::WalFormat::Bincode,
+        )
+        .unwrap();
+        let mut server = Server::new(test_config(), Box::new(wal));
+        let addr = server.bind().unwrap();
+        tokio::spawn(server.run());
+        let (r, mut w) = TcpStream::connect(addr).await.unwrap().into_split();
+        let mut reader = BufReader::new(r);
+        w.write_all(b"PERSISTENCE OFF\r\nSET k v\r\nGET k\r\nINFO JSON\r\n").await.unwrap();
+        assert_eq!(read_line(&mut reader).await, "OK\r\n");
+        assert_eq!(read_line(&mut reader).await, "OK\r\n");
+        assert_eq!(read_line(&mut reader).await, "VALUE v\r\n");
+        let info = read_line(&mut reader).await;
+        let info: serde_json::Value = serde_json::from_str(info.strip_prefix("INFO_JSON ").unwrap()).unwrap();
+        assert_eq!(info["wal"], serde_json::json!({ "enabled": true, "persistence": false, "write_errors": 0, "degraded": false, "fsyncs": 0 }));
+
+        w.write_all(b"PERSISTENCE ON\r\nINFO\r\n").await.unwrap();
+        assert_eq!(read_line(&mut reader).await, "OK\r\n");
+        assert_eq!(read_line(&mut reader).await, "INFO\r\n");
+        let mut lines = Vec::new();
+        while !lines.last().is_some_and(|l: &String| l.starts_with("replication_enabled")) {
+            lines.push(read_line(&mut reader).await);
+        }
+        assert!(lines.contains(&"wal_enabled:1\r\n".to_string()), "{:?}", lines);
+        assert!(lines.contains(&"wal_persistence:on\r\n".to_string()), "{:?}", lines);
+        assert!(lines.contains(&"wal_status:ok\r\n".to_string()), "{:?}", lines);
+    }
+
+    #[tokio::test]
+    async fn test_object_durable_reports_writes_not_yet_fsynced() {
+        let (r, mut w) = start_server(test_config()).await.into_split();
+        let mut reader = BufReader::new(r);
+        w.write_all(b"SET k v\r\nOBJECT DURABLE k\r\n").await.unwrap();
+        assert_eq!(read_line(&mut reader).await, "OK\r\n");
+        assert_eq!(read_line(&mut reader).await, "ERROR no write-ahead log configured (storage.wal_path)\r\n");
+
+        let dir = tempfile::tempdir().unwrap();
+        let wal = crate::store::WalEngine::open(
+            Box::new(RwLockEngine::new("unused").unwrap()),
+            &dir.path().join("wal.log"),
+            crate::store::wal::WalFormat::Bincode,
+        )
+        .unwrap();
+        let mut server = Server::new(test_config(), Box::new(wal));
+        let addr = server.bind().unwrap();
+        tokio::spawn(server.run());
+        let (r, mut w) = TcpStream::connect(addr).await.unwrap().into_split();
+        let mut reader = BufReader::new(r);
+        // Without max_dirty_writes, writes are only in the OS cache until an fsync
+        w.write_all(b"SET k v\r\nOBJECT DURABLE k\r\nOBJECT DURABLE missing\r\n").await.unwrap();
+        assert_eq!(read_line(&mut reader).await, "OK\r\n");
+        assert_eq!(read_line(&mut reader).await, "DURABLE no\r\n");
+        assert_eq!(read_line(&mut reader).await, "NOT_FOUND\r\n");
+
+        // A DURABLE write fsyncs the log, and with it every earlier write
+        w.write_all(b"SET other v DURABLE\r\nOBJECT DURABLE k\r\nOBJECT DURABLE other\r\n").await.unwrap();
+        assert_eq!(read_line(&mut reader).await, "OK\r\n");
+        assert_eq!(read_line(&mut reader).await, "DURABLE yes\r\n");
+        assert_eq!(read_line(&mut reader).await, "DURABLE yes\r\n");
+    }
+
+    #[tokio::test]
+    async fn test_dbdiff_reports_keys_missing_or_changed_between_databases() {
+        let mut config = test_config();
+        config.server.databases = 3;
+        let (r, mut w) = start_server(config).await.into_split();
+        let mut reader = BufReader::new(r);
+        w.write_all(b"MSET same 1 changed live gone x\r\nSELECT 2\r\nMSET same 1 changed staging added y\r\n").await.unwrap();
+        for _ in 0..3 {
+            assert_eq!(read_line(&mut reader).await, "OK\r\n");
+        }
+        w.write_all(b"DBDIFF 0 2\r\n").await.unwrap();
+        assert_eq!(read_line(&mut reader).await, "DBDIFF only_db0:1 only_db2:1 differ:1\r\n");
+        assert_eq!(read_line(&mut reader).await, "ONLY 2 added\r\n");
+        assert_eq!(read_line(&mut reader).await, "DIFFERS changed\r\n");
+        assert_eq!(read_line(&mut reader).await, "ONLY 0 gone\r\n");
+
+        // Comparing a database with itself or an empty one
+        w.write_all(b"DBDIFF 2 2\r\nDBDIFF 1 0\r\nDBDIFF 0 3\r\n").await.unwrap();
+        assert_eq!(read_line(&mut reader).await, "DBDIFF only_db2:0 only_db2:0 differ:0\r\n");
+        assert_eq!(read_line(&mut reader).await, "DBDIFF only_db1:0 only_db0:3 differ:0\r\n");
+        for key in ["changed", "gone", "same"] {
+            assert_eq!(read_line(&mut reader).await, format!("ONLY 0 {}\r\n", key));
+        }
+        assert_eq!(read_line(&mut reader).await, "ERROR DB index 3 is out of range (server.databases = 3)\r\n");
+    }
+
+    #[tokio::test]
+    async fn test_select_switches_database_within_the_configured_count() {
+        let mut config = test_config();
+        config.server.databases = 2;
+        let (r, mut w) = start_server(config).await.into_split();
+        let mut reader = BufReader::new(r);
+        w.write_all(b"SET k main\r\nSELECT 1\r\nGET k\r\nSET k other\r\nSELECT 2\r\nGET k\r\nSELECT 0\r\nGET k\r\n")
+            .await
+            .unwrap();
+        assert_eq!(read_line(&mut reader).await, "OK\r\n");
+        assert_eq!(read_line(&mut reader).await, "OK\r\n");
+        assert_eq!(read_line(&mut reader).await, "NOT_FOUND\r\n");
+        assert_eq!(read_line(&mut reader).await, "OK\r\n");
+        assert_eq!(
+            read_line(&mut reader).await,
+            "ERROR DB index 2 is out of range (server.databases = 2)\r\n"
+        );
+        assert_eq!(read_line(&mut reader).await, "VALUE other\r\n", "a refused SELECT keeps the database");
+        assert_eq!(read_line(&mut reader).await, "OK\r\n");
+        assert_eq!(read_line(&mut reader).await, "VALUE main\r\n");
+    }
+
+    #[tokio::test]
+    async fn test_topic_per_database_publishes_each_database_on_its_own_topic() {
+        let start = |node_id: &'static str| async move {
+            let (replicator, published) = Replicator::detached(node_id);
+            let replicator = replicator.with_topic_per_database();
+            let mut server = Server::new(test_config(), Box::new(RwLockEngine::new("unused").unwrap()))
+                .with_replicator(replicator.clone());
+            let addr = server.bind().unwrap();
+            tokio::spawn(server.run());
+            (addr, replicator, published)
+        };
+        let (addr_a, _repl_a, published_a) = start("node-a").await;
+        let (addr_b, repl_b, _published_b) = start("node-b").await;
+
+        let (r, mut w) = TcpStream::connect(addr_a).await.unwrap().into_split();
+        let mut reader = BufReader::new(r);
+        w.write_all(b"SELECT 3\r\nSET k in-db3\r\nSELECT 0\r\nSET k in-db0\r\n").await.unwrap();
+        for _ in 0..4 {
+            assert_eq!(read_line(&mut reader).await, "OK\r\n");
+        }
+        let mut topics = Vec::new();
+        for _ in 0..2 {
+            let request = tokio::time::timeout(Duration::from_secs(5), published_a.recv_async()).await.unwrap().unwrap();
+            let rumqttc::Request::Publish(p) = request else { panic!("expected a publish, got {:?}", request) };
+            topics.push(p.topic.clone());
+            repl_b.deliver_on(&p.topic, &p.payload);
+        }
+        assert_eq!(topics, vec!["merkle_kv_test/db3/events", "merkle_kv_test/db0/events"]);
+        // A subscriber to one database's topic sees only that database's writes
+        assert!(rumqttc::matches(&topics[0], "merkle_kv_test/db3/events"));
+        assert!(!rumqttc::matches(&topics[0], "merkle_kv_test/db0/events"));
+        assert!(rumqttc::matches(&topics[0], "merkle_kv_test/+/events"));
+
+        // The peer applies each write to the same database
+        let (r, mut w) = TcpStream::connect(addr_b).await.unwrap().into_split();
+        let mut reader = BufReader::new(r);
+        let mut replies = Vec::new();
+        for _ in 0..100 {
+            w.write_all(b"GET k\r\nSELECT 3\r\nGET k\r\nSELECT 0\r\n").await.unwrap();
+            replies = vec![read_line(&mut reader).await, read_line(&mut reader).await, read_line(&mut reader).await];
+            read_line(&mut reader).await;
+            if replies[0] == "VALUE in-db0\r\n" && replies[2] == "VALUE in-db3\r\n" {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+        assert_eq!(replies, vec!["VALUE in-db0\r\n", "OK\r\n", "VALUE in-db3\r\n"]);
+    }
+
+    #[tokio::test]
+    async fn test_nodes_with_same_bucket_count_converge_after_sync() {
+        let start = |keys: Vec<(String, String)>| async move {
+            let mut config = test_config();
+            config.merkle.num_buckets = 16;
+            let store = RwLockEngine::new("unused").unwrap();
+            for (k, v) in keys {
+                store.set(k, v).unwrap();
+            }
+            let mut server = Server::new(config, Box::new(store));
+            let addr = server.bind().unwrap();
+            tokio::spawn(server.run());
+            addr
+        };
+        let pairs = |range: std::ops::Range<usize>, value: &str| range.map(|i| (format!("k{}", i), value.to_string())).collect::<Vec<_>>();
+        let peer = start([pairs(0..200, "v"), pairs(200..210, "new")].concat()).await;
+        let local = start([pairs(0..195, "v"), pairs(195..200, "old")].concat()).await;
+
+        let merkle_info = |addr: SocketAddr| async move {
+            let (r, mut w) = TcpStream::connect(addr).await.unwrap().into_split();
+            w.write_all(b"INFO JSON\r\n").await.unwrap();
+            let info = read_line(&mut BufReader::new(r)).await;
+            let info: serde_json::Value = serde_json::from_str(info.strip_prefix("INFO_JSON ").unwrap()).unwrap();
+            info["merkle"].clone()
+        };
+        let before = merkle_info(local).await;
+        assert_eq!(before["buckets"], 16);
+        assert_eq!(before["nodes"], 31, "16 buckets bound the tree to 31 nodes for 200 keys");
+        assert_ne!(before["root"], merkle_info(peer).await["root"]);
+
+        let (r, mut w) = TcpStream::connect(local).await.unwrap().into_split();
+        let mut reader = BufReader::new(r);
+        w.write_all(format!("SYNC {} {}\r\n", peer.ip(), peer.port()).as_bytes()).await.unwrap();
+        assert_eq!(read_line(&mut reader).await, "OK\r\n");
+        let after = merkle_info(local).await;
+        assert_eq!(after["root"], merkle_info(peer).await["root"], "same bucket count: converged");
+        assert_eq!(after["nodes"], 31);
+    }
+
+    #[tokio::test]
+    async fn test_snapshot_task_writes_dumps_and_prunes_old_ones() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut config = test_config();
+        config.storage_path = dir.path().display().to_string();
+        config.storage.snapshot_interval_seconds = 3600;
+        config.storage.snapshot_retain = 2;
+        let (r, mut w) = start_server(config).await.into_split();
+        let mut reader = BufReader::new(r);
+        let snapshots = dir.path().join("snapshots");
+        let files = || -> Vec<std::path::PathBuf> {
+            let mut files: Vec<_> = std::fs::read_dir(&snapshots).map_or(Vec::new(), |d| d.map(|e| e.unwrap().path()).collect());
+            files.sort();
+            files
+        };
+
+        for round in 1..=3 {
+            w.write_all(format!("SET round {}\r\nTASKS RUN snapshot\r\n", round).as_bytes()).await.unwrap();
+            assert_eq!(read_line(&mut reader).await, "OK\r\n");
+            assert_eq!(read_line(&mut reader).await, "OK\r\n");
+            let expected = format!("DUMP 1\r\nSET round 0 {}\r\n", round);
+            for _ in 0..200 {
+                if files().last().and_then(|f| std::fs::read_to_string(f).ok()).as_deref() == Some(expected.as_str()) {
+                    break;
+                }
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+            assert_eq!(std::fs::read_to_string(files().last().unwrap()).unwrap(), expected);
+        }
+        for _ in 0..200 {
+            if files().len() == 2 {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        let kept = files();
+        assert_eq!(kept.len(), 2, "{:?}", kept);
+        assert_eq!(std::fs::read_to_string(&kept[0]).unwrap(), "DUMP 1\r\nSET round 0 2\r\n");
+    }
+
+    #[tokio::test]
+    async fn test_metrics_dump_writes_current_counters() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut config = test_config();
+        config.metrics.dump_path = Some(dir.path().join("periodic.json").to_string_lossy().into_owned());
+        config.metrics.dump_interval_seconds = 0;
+        config.metrics.dump_format = crate::metrics::MetricsFormat::Json;
+        let (r, mut w) = start_server(config).await.into_split();
+        let mut reader = BufReader::new(r);
+        w.write_all(b"SET a 1\r\nGET a\r\nGET b\r\n").await.unwrap();
+        for _ in 0..3 {
+            read_line(&mut reader).await;
+        }
+
+        // The configured file, in the format asked for
+        let path = dir.path().join("periodic.json");
+        w.write_all(b"METRICS DUMP TEXT\r\n").await.unwrap();
+        let reply = read_line(&mut reader).await;
+        let text = std::fs::read_to_string(&path).unwrap();
+        assert_eq!(reply, format!("OK {} bytes\r\n", text.len()));
+        assert!(text.contains("merklekv_get_commands 2\n"), "{}", text);
+        assert!(text.contains("merklekv_set_commands 1\n"), "{}", text);
+        assert!(text.contains("merklekv_total_commands 4\n"), "the dump counts itself: {}", text);
+
+        // With metrics.dump_path, TASKS RUN writes the configured file in its format
+        w.write_all(b"TASKS RUN metrics_dump\r\n").await.unwrap();
+        assert_eq!(read_line(&mut reader).await, "OK\r\n");
+        let periodic = dir.path().join("periodic.json");
+        for _ in 0..100 {
+            if periodic.exists() {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        let json: serde_json::Value = serde_json::from_str(&std::fs::read_to_string(&periodic).unwrap()).unwrap();
+        assert_eq!(json["counters"]["get_commands"], 2);
+
+        // A client cannot pick the file
+        w.write_all(b"METRICS DUMP /tmp/elsewhere.prom\r\n").await.unwrap();
+        assert!(read_line(&mut reader).await.starts_with("ERROR ERR_PARSE"));
+        assert_eq!(std::fs::read_dir(dir.path()).unwrap().count(), 1);
+
+        let (r, mut w) = start_server(test_config()).await.into_split();
+        let mut reader = BufReader::new(r);
+        w.write_all(b"METRICS DUMP\r\n").await.unwrap();
+        assert_eq!(read_line(&mut reader).await, "ERROR no metrics file configured (metrics.dump_path)\r\n");
+    }
+
+    #[tokio::test]
+    async fn test_config_reload_tls_serves_the_new_certificate_to_new_connections() {
+        use tokio_rustls::rustls::pki_types::ServerName;
+        use tokio_rustls::TlsConnector;
+
+        let fixture = |name: &str| format!("{}/tests/fixtures/tls/{}", env!("CARGO_MANIFEST_DIR"), name);
+        let cert_der = |name: &str| {
+            let pem = std::fs::read(fixture(name)).unwrap();
+            let der = rustls_pemfile::certs(&mut pem.as_slice()).next().unwrap().unwrap().to_vec();
+            der
+        };
+        let dir = tempfile::tempdir().unwrap();
+        let (cert, key) = (dir.path().join("server.pem"), dir.path().join("server.key"));
+        std::fs::copy(fixture("server-a.pem"), &cert).unwrap();
+        std::fs::copy(fixture("server-a.key"), &key).unwrap();
+        let mut config = test_config();
+        config.server.tls_cert_path = Some(cert.display().to_string());
+        config.server.tls_key_path = Some(key.display().to_string());
+        let plain = start_server(config).await;
+        let addr = plain.peer_addr().unwrap();
+
+        let connector = TlsConnector::from(crate::tls::client_config(&fixture("ca.pem")).unwrap());
+        let connect = || async {
+            let tcp = TcpStream::connect(addr).await.unwrap();
+            let stream = connector.connect(ServerName::try_from("localhost").unwrap(), tcp).await.unwrap();
+            let served = stream.get_ref().1.peer_certificates().unwrap()[0].to_vec();
+            let (r, w) = tokio::io::split(stream);
+            (BufReader::new(r), w, served)
+        };
+        let (mut old_r, mut old_w, served) = connect().await;
+        assert_eq!(served, cert_der("server-a.pem"));
+        old_w.write_all(b"SET k v\r\n").await.unwrap();
+        assert_eq!(read_line(&mut old_r).await, "OK\r\n");
+
+        std::fs::copy(fixture("server-b.pem"), &cert).unwrap();
+        std::fs::copy(fixture("server-b.key"), &key).unwrap();
+        old_w.write_all(b"CONFIG RELOAD TLS\r\n").await.unwrap();
+        assert_eq!(read_line(&mut old_r).await, "OK\r\n");
+        let (mut new_r, mut new_w, served) = connect().await;
+        assert_eq!(served, cert_der("server-b.pem"));
+        new_w.write_all(b"GET k\r\n").await.unwrap();
+        assert_eq!(read_line(&mut new_r).await, "VALUE v\r\n");
+
+        // The connection made before the reload is still served
+        old_w.write_all(b"GET k\r\n").await.unwrap();
+        assert_eq!(read_line(&mut old_r).await, "VALUE v\r\n");
+
+        // Invalid files fail the reload and keep the current certificate
+        std::fs::write(&key, "not a key").unwrap();
+        new_w.write_all(b"CONFIG RELOAD TLS\r\n").await.unwrap();
+        assert_eq!(read_line(&mut new_r).await, format!("ERROR no private key in {}\r\n", key.display()));
+        assert_eq!(connect().await.2, cert_der("server-b.pem"));
+        drop(plain);
+    }
 }
@@ -0,0 +1,225 @@
+//! # Snapshots (`DUMP`, `--bootstrap-from`)
+//!
+//! `DUMP` returns every key, with its TTL, and every live tombstone, read
+//! under one store lock so the snapshot is consistent:
+//!
+//! ```text
+//! DUMP <n>\r\n
+//! SET <key> <expires_at_ms|0> <value>\r\n
+//! TOMBSTONE <key> <deleted_at_ms>\r\n
+//! ```
+//!
+//! `merkle_kv --bootstrap-from <host:port>` pulls a peer's DUMP at start and
+//! writes it to the local store before serving, so a new replica is populated
+//! in one round trip instead of by anti-entropy key by key. Keys the local
+//! replication prefix filter leaves out are skipped, as in sync. As with
+//! sync, the peer must not require `AUTH`.
+//!
+//! ## Backup Snapshots (`storage.snapshot_interval_seconds`)
+//!
+//! The `snapshot` task writes the DUMP of the store to
+//! `{storage_path}/snapshots/snapshot-<unix_ms>.dump` every interval (or on
+//! `TASKS RUN snapshot`), keeping the newest `storage.snapshot_retain` files.
+//! Files are written to a temporary name, fsynced, renamed, and the directory
+//! fsynced, so a crash never leaves a partial snapshot behind nor loses a
+//! finished one. They hold the DUMP text above whatever
+//! `storage.wal_format` is, which only applies to the write-ahead log.
+
+use anyhow::{anyhow, Context, Result};
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::net::tcp::OwnedReadHalf;
+use tokio::net::TcpStream;
+
+use crate::key_filter::KeyFilter;
+use crate::store::expiring::now_ms;
+use crate::store::KVEngineStoreTrait;
+
+/// One `DUMP` record.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Record {
+    Set { key: String, expires_at_ms: Option<u64>, value: String },
+    Tombstone { key: String, deleted_at_ms: u64 },
+}
+
+/// Build the `DUMP` reply for `store`; the caller holds the store lock.
+pub fn encode(store: &dyn KVEngineStoreTrait) -> String {
+    let keys = store.keys_sorted();
+    let tombstones = store.tombstones();
+    let mut lines = Vec::with_capacity(keys.len() + tombstones.len());
+    for key in keys {
+        if let Some(value) = store.get(&key) {
+            lines.push(format!("SET {} {} {}\r\n", key, store.expiry(&key).unwrap_or(0), value));
+        }
+    }
+    for (key, deleted_at_ms) in tombstones {
+        lines.push(format!("TOMBSTONE {} {}\r\n", key, deleted_at_ms));
+    }
+    format!("DUMP {}\r\n{}", lines.len(), lines.concat())
+}
+
+/// Parse one record line (without its CRLF).
+pub fn parse_record(line: &str) -> Result<Record> {
+    let mut parts = line.splitn(4, ' ');
+    match (parts.next(), parts.next(), parts.next(), parts.next()) {
+        (Some("SET"), Some(key), Some(expires), Some(value)) if !key.is_empty() => {
+            let expires: u64 = expires.parse().context("invalid expiry")?;
+            Ok(Record::Set { key: key.to_string(), expires_at_ms: (expires > 0).then_some(expires), value: value.to_string() })
+        }
+        (Some("TOMBSTONE"), Some(key), Some(at), None) if !key.is_empty() => {
+            Ok(Record::Tombstone { key: key.to_string(), deleted_at_ms: at.parse().context("invalid deletion time")? })
+        }
+        _ => Err(anyhow!("malformed DUMP record '{}'", line)),
+    }
+}
+
+/// Send `DUMP` to `addr` and read the whole snapshot; each read is bounded by `timeout`.
+pub async fn fetch(addr: &str, timeout: Duration) -> Result<Vec<Record>> {
+    let stream = tokio::time::timeout(timeout, TcpStream::connect(addr))
+        .await
+        .map_err(|_| anyhow!("connect {} timed out", addr))?
+        .with_context(|| format!("connect {}", addr))?;
+    let (r, mut w) = stream.into_split();
+    w.write_all(b"DUMP\r\n").await.context("write DUMP")?;
+    let mut reader = BufReader::new(r);
+    let header = read_line(&mut reader, addr, timeout).await?;
+    let count: usize = header
+        .strip_prefix("DUMP ")
+        .ok_or_else(|| anyhow!("unexpected DUMP response: {}", header))?
+        .parse()
+        .context("invalid count after DUMP")?;
+    let mut records = Vec::with_capacity(count);
+    for _ in 0..count {
+        records.push(parse_record(&read_line(&mut reader, addr, timeout).await?)?);
+    }
+    Ok(records)
+}
+
+async fn read_line(reader: &mut BufReader<OwnedReadHalf>, addr: &str, timeout: Duration) -> Result<String> {
+    let mut line = String::new();
+    let n = tokio::time::timeout(timeout, reader.read_line(&mut line))
+        .await
+        .map_err(|_| anyhow!("DUMP from {} timed out after {:?}", addr, timeout))??;
+    if n == 0 {
+        return Err(anyhow!("peer {} closed during DUMP", addr));
+    }
+    Ok(line.trim_end_matches(['\r', '\n']).to_string())
+}
+
+/// Write `records` into `store` (skipping keys `filter` keeps node-local) and sync it.
+///
+/// # Returns
+/// * `Result<usize>` - Number of keys written
+pub fn apply(store: &dyn KVEngineStoreTrait, records: Vec<Record>, filter: &KeyFilter) -> Result<usize> {
+    let mut written = 0;
+    for record in records {
+        match record {
+            Record::Set { key, expires_at_ms, value } if filter.replicates(&key) => {
+                store.set(key.clone(), value)?;
+                if expires_at_ms.is_some() {
+                    store.set_expiry(&key, expires_at_ms)?;
+                }
+                written += 1;
+            }
+            Record::Tombstone { key, deleted_at_ms } if filter.replicates(&key) => {
+                store.add_tombstone(&key, deleted_at_ms);
+            }
+            _ => {}
+        }
+    }
+    store.sync()?;
+    Ok(written)
+}
+
+/// Write the DUMP `dump` to a new timestamped file in `dir`, creating it.
+///
+/// # Returns
+/// * `Result<PathBuf>` - The snapshot file
+pub fn write_file(dir: &Path, dump: &str) -> Result<PathBuf> {
+    fs::create_dir_all(dir).with_context(|| format!("create snapshot directory {}", dir.display()))?;
+    let mut at = now_ms();
+    let path = loop {
+        // Zero-padded so file names sort by time
+        let path = dir.join(format!("snapshot-{:020}.dump", at));
+        if !path.exists() {
+            break path;
+        }
+        at += 1;
+    };
+    let tmp = path.with_extension("dump.tmp");
+    let mut file = fs::File::create(&tmp).with_context(|| format!("create snapshot {}", tmp.display()))?;
+    file.write_all(dump.as_bytes())
+        .and_then(|()| file.sync_all())
+        .with_context(|| format!("write snapshot {}", tmp.display()))?;
+    drop(file);
+    fs::rename(&tmp, &path).with_context(|| format!("rename snapshot {}", path.display()))?;
+    // The rename is durable only once the directory entry is
+    fs::File::open(dir)
+        .and_then(|d| d.sync_all())
+        .with_context(|| format!("fsync snapshot directory {}", dir.display()))?;
+    Ok(path)
+}
+
+/// Delete all but the newest `retain` snapshot files of `dir` (`0` keeps all).
+///
+/// # Returns
+/// * `Result<usize>` - Number of files deleted
+pub fn prune(dir: &Path, retain: usize) -> Result<usize> {
+    if retain == 0 {
+        return Ok(0);
+    }
+    let mut files: Vec<PathBuf> = fs::read_dir(dir)
+        .with_context(|| format!("list snapshots in {}", dir.display()))?
+        .filter_map(|entry| entry.ok().map(|e| e.path()))
+        .filter(|path| {
+            let name = path.file_name().and_then(|n| n.to_str()).unwrap_or("");
+            name.starts_with("snapshot-") && name.ends_with(".dump")
+        })
+        .collect();
+    files.sort();
+    let stale = files.len().saturating_sub(retain);
+    for path in &files[..stale] {
+        fs::remove_file(path).with_context(|| format!("delete snapshot {}", path.display()))?;
+    }
+    Ok(stale)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_write_file_and_prune_keep_the_newest_snapshots() {
+        let dir = tempfile::tempdir().unwrap();
+        let snapshots = dir.path().join("snapshots");
+        let paths: Vec<PathBuf> = (0..4).map(|i| write_file(&snapshots, &format!("DUMP {}\r\n", i)).unwrap()).collect();
+        assert!(paths.windows(2).all(|w| w[0] < w[1]), "names sort by time: {:?}", paths);
+        fs::write(snapshots.join("notes.txt"), "kept").unwrap();
+
+        assert_eq!(prune(&snapshots, 0).unwrap(), 0);
+        assert_eq!(prune(&snapshots, 2).unwrap(), 2);
+        assert!(!paths[0].exists() && !paths[1].exists());
+        assert_eq!(fs::read_to_string(&paths[3]).unwrap(), "DUMP 3\r\n");
+        assert!(snapshots.join("notes.txt").exists());
+        assert_eq!(fs::read_dir(&snapshots).unwrap().count(), 3);
+    }
+
+    #[test]
+    fn test_parse_record() {
+        assert_eq!(
+            parse_record("SET k 0 a value").unwrap(),
+            Record::Set { key: "k".to_string(), expires_at_ms: None, value: "a value".to_string() }
+        );
+        assert_eq!(
+            parse_record("SET k 1700000000000 ").unwrap(),
+            Record::Set { key: "k".to_string(), expires_at_ms: Some(1_700_000_000_000), value: String::new() }
+        );
+        assert_eq!(parse_record("TOMBSTONE gone 42").unwrap(), Record::Tombstone { key: "gone".to_string(), deleted_at_ms: 42 });
+        assert!(parse_record("SET k soon v").is_err());
+        assert!(parse_record("SET k 0").is_err());
+        assert!(parse_record("DEL k").is_err());
+    }
+}
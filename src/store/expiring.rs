@@ -0,0 +1,370 @@
+//! # Key Expiry (TTL)
+//!
+//! A decorator that adds per-key expiry to any `KVEngineStoreTrait`
+//! implementation. Deadlines are kept in memory next to the wrapped engine as
+//! absolute UNIX milliseconds.
+//!
+//! ## Lazy Expiry
+//!
+//! Keys are expired lazily: a read (`get`, `exists`) or read-modify-write
+//! (`increment`, `append`, ...) that finds a key past its deadline deletes it
+//! from the inner engine and behaves as if the key were absent. Listing
+//! operations (`keys`, `scan`, `sample`) simply hide expired keys.
+//!
+//! When `track_lazy_expiry` is enabled, every lazily expired key is queued so
+//! the server can replicate the deletion (`replication.publish_lazy_expiry`);
+//! drain the queue with `take_expired`.
+//!
+//! ## Semantics
+//!
+//! - `set` clears any existing expiry (plain SET makes a key persistent)
+//! - `increment`/`decrement`/`append`/`prepend` keep the existing expiry
+//! - `delete`/`truncate` drop expiries together with the data
+//!
+//! Every change of deadline is first passed to the wrapped engine's
+//! `log_expiry`, and deadlines the engine replayed (`logged_expiries`) are
+//! loaded on start, so with a write-ahead log keys keep their TTL across a
+//! restart. Other persistent engines (sled, the cold tier) do not keep them:
+//! their keys come back without a TTL.
+
+use anyhow::Result;
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use super::kv_trait::{random_index, KVEngineStoreTrait, ShardStats, StorageStats, TierStats, WalHealth};
+
+/// Draws `random_key` makes from the inner engine before listing the live keys.
+const RANDOM_KEY_DRAWS: usize = 8;
+
+/// Current UNIX time in milliseconds.
+pub fn now_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as u64
+}
+
+/// Storage engine wrapper that enforces key expiry.
+pub struct ExpiringEngine {
+    inner: Box<dyn KVEngineStoreTrait + Send + Sync>,
+    /// Absolute deadlines (UNIX ms) of keys that have a TTL
+    deadlines: Mutex<HashMap<String, u64>>,
+    /// Whether lazily expired keys are queued for `take_expired`
+    track_lazy_expiry: bool,
+    /// Keys removed by lazy expiry, waiting to be drained
+    expired: Mutex<Vec<String>>,
+}
+
+impl ExpiringEngine {
+    /// Wrap an engine. With `track_lazy_expiry`, lazily expired keys are
+    /// queued for `take_expired`.
+    pub fn new(inner: Box<dyn KVEngineStoreTrait + Send + Sync>, track_lazy_expiry: bool) -> Self {
+        let deadlines = inner.logged_expiries().into_iter().collect();
+        Self {
+            inner,
+            deadlines: Mutex::new(deadlines),
+            track_lazy_expiry,
+            expired: Mutex::new(Vec::new()),
+        }
+    }
+
+    fn deadlines(&self) -> std::sync::MutexGuard<'_, HashMap<String, u64>> {
+        self.deadlines.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Delete `key` if its deadline has passed. Returns true if it was expired.
+    fn purge_if_expired(&self, key: &str) -> bool {
+        let expired = {
+            let mut deadlines = self.deadlines();
+            match deadlines.get(key) {
+                Some(&at) if at <= now_ms() => {
+                    deadlines.remove(key);
+                    true
+                }
+                _ => false,
+            }
+        };
+        if expired && self.inner.delete(key) && self.track_lazy_expiry {
+            self.expired
+                .lock()
+                .unwrap_or_else(|e| e.into_inner())
+                .push(key.to_string());
+        }
+        expired
+    }
+
+    /// Whether `key` is past its deadline (without removing it).
+    fn is_expired(deadlines: &HashMap<String, u64>, key: &str, now: u64) -> bool {
+        deadlines.get(key).is_some_and(|&at| at <= now)
+    }
+
+    fn visible(&self, keys: Vec<String>) -> Vec<String> {
+        let deadlines = self.deadlines();
+        if deadlines.is_empty() {
+            return keys;
+        }
+        let now = now_ms();
+        keys.into_iter()
+            .filter(|k| !Self::is_expired(&deadlines, k, now))
+            .collect()
+    }
+}
+
+impl KVEngineStoreTrait for ExpiringEngine {
+    fn get(&self, key: &str) -> Option<String> {
+        if self.purge_if_expired(key) {
+            return None;
+        }
+        self.inner.get(key)
+    }
+
+    fn set(&self, key: String, value: String) -> Result<()> {
+        self.inner.set(key.clone(), value)?;
+        self.deadlines().remove(&key);
+        Ok(())
+    }
+
+    fn delete(&self, key: &str) -> bool {
+        let deadline = self.deadlines().remove(key);
+        let deleted = self.inner.delete(key);
+        // Deleting a key that had already expired reports it as absent
+        deleted && deadline.is_none_or(|at| at > now_ms())
+    }
+
+    fn keys(&self) -> Vec<String> {
+        self.visible(self.inner.keys())
+    }
+
+    fn scan(&self, prefix: &str) -> Vec<String> {
+        self.visible(self.inner.scan(prefix))
+    }
+
+    fn ping(&self, message: &str) -> String {
+        self.inner.ping(message)
+    }
+
+    fn echo(&self, message: &str) -> String {
+        self.inner.echo(message)
+    }
+
+    fn exists(&self, key: &str) -> bool {
+        !self.purge_if_expired(key) && self.inner.exists(key)
+    }
+
+    fn memory_usage(&self) -> usize {
+        self.inner.memory_usage()
+    }
+
+    fn len(&self) -> usize {
+        self.inner.len()
+    }
+
+    fn dbsize(&self) -> usize {
+        self.inner.dbsize()
+    }
+
+    fn is_empty(&self) -> bool {
+        self.inner.is_empty()
+    }
+
+    fn increment(&self, key: &str, amount: Option<i64>) -> Result<i64> {
+        self.purge_if_expired(key);
+        self.inner.increment(key, amount)
+    }
+
+    fn decrement(&self, key: &str, amount: Option<i64>) -> Result<i64> {
+        self.purge_if_expired(key);
+        self.inner.decrement(key, amount)
+    }
+
+    fn append(&self, key: &str, value: &str) -> Result<String> {
+        self.purge_if_expired(key);
+        self.inner.append(key, value)
+    }
+
+    fn prepend(&self, key: &str, value: &str) -> Result<String> {
+        self.purge_if_expired(key);
+        self.inner.prepend(key, value)
+    }
+
+    fn truncate(&self) -> Result<()> {
+        self.inner.truncate()?;
+        self.deadlines().clear();
+        Ok(())
+    }
+
+    fn count_keys(&self) -> Result<u64> {
+        self.inner.count_keys()
+    }
+
+    fn sync(&self) -> Result<()> {
+        self.inner.sync()
+    }
+
+    fn sample(&self, limit: usize) -> Vec<(String, String)> {
+        let pairs = self.inner.sample(limit);
+        let deadlines = self.deadlines();
+        let now = now_ms();
+        pairs
+            .into_iter()
+            .filter(|(k, _)| !Self::is_expired(&deadlines, k, now))
+            .collect()
+    }
+
+    /// Redraw past expired keys, which keeps the pick uniform over the live
+    /// ones; a keyspace that is mostly expired falls back to listing them.
+    fn random_key(&self) -> Option<String> {
+        for _ in 0..RANDOM_KEY_DRAWS {
+            let key = self.inner.random_key()?;
+            if !Self::is_expired(&self.deadlines(), &key, now_ms()) {
+                return Some(key);
+            }
+        }
+        let keys = self.keys();
+        (!keys.is_empty()).then(|| keys[random_index(keys.len())].clone())
+    }
+
+    fn set_expiry(&self, key: &str, expires_at_ms: Option<u64>) -> Result<bool> {
+        if self.purge_if_expired(key) || !self.inner.exists(key) {
+            return Ok(false);
+        }
+        self.inner.log_expiry(key, expires_at_ms)?;
+        let mut deadlines = self.deadlines();
+        match expires_at_ms {
+            Some(at) => deadlines.insert(key.to_string(), at),
+            None => deadlines.remove(key),
+        };
+        Ok(true)
+    }
+
+    fn expiry(&self, key: &str) -> Option<u64> {
+        if self.purge_if_expired(key) {
+            return None;
+        }
+        self.deadlines().get(key).copied()
+    }
+
+    fn take_expired(&self) -> Vec<String> {
+        std::mem::take(&mut *self.expired.lock().unwrap_or_else(|e| e.into_inner()))
+    }
+
+    fn touch(&self, key: &str) -> bool {
+        !self.purge_if_expired(key) && self.inner.touch(key)
+    }
+
+    fn mark_local(&self, key: &str) {
+        self.inner.mark_local(key)
+    }
+
+    fn is_local(&self, key: &str) -> bool {
+        self.inner.is_local(key)
+    }
+
+    fn compact_memory(&self, part: usize) -> bool {
+        if part == 0 {
+            self.deadlines().shrink_to_fit();
+        }
+        self.inner.compact_memory(part)
+    }
+
+    fn storage_stats(&self) -> Option<StorageStats> {
+        self.inner.storage_stats()
+    }
+
+    fn shard_stats(&self) -> Option<Vec<ShardStats>> {
+        self.inner.shard_stats()
+    }
+
+    fn warm(&self, key: &str) -> Result<bool> {
+        if self.purge_if_expired(key) {
+            return Ok(false);
+        }
+        self.inner.warm(key)
+    }
+
+    fn tier_stats(&self) -> Option<TierStats> {
+        self.inner.tier_stats()
+    }
+
+    fn compact_storage(&self) -> Result<bool> {
+        self.inner.compact_storage()
+    }
+
+    fn find_by_value_prefix(&self, prefix: &str) -> Option<Vec<String>> {
+        let mut keys = self.inner.find_by_value_prefix(prefix)?;
+        keys.retain(|key| !self.purge_if_expired(key));
+        Some(keys)
+    }
+
+    fn persistence(&self) -> Option<bool> {
+        self.inner.persistence()
+    }
+
+    fn wal_health(&self) -> Option<WalHealth> {
+        self.inner.wal_health()
+    }
+
+    fn durable(&self, key: &str) -> Option<bool> {
+        self.inner.durable(key)
+    }
+
+    fn set_persistence(&self, enabled: bool) -> Result<()> {
+        self.inner.set_persistence(enabled)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::store::RwLockEngine;
+
+    fn engine(track: bool) -> ExpiringEngine {
+        ExpiringEngine::new(Box::new(RwLockEngine::new("unused").unwrap()), track)
+    }
+
+    #[test]
+    fn test_expired_key_is_removed_on_read() {
+        let e = engine(true);
+        e.set("k".to_string(), "v".to_string()).unwrap();
+        e.set("live".to_string(), "v".to_string()).unwrap();
+        assert!(e.set_expiry("k", Some(now_ms() - 1)).unwrap());
+        assert!(e.set_expiry("live", Some(now_ms() + 60_000)).unwrap());
+
+        // Listing hides the key without removing it
+        assert_eq!(e.keys(), vec!["live".to_string()]);
+        assert!(e.take_expired().is_empty());
+
+        assert_eq!(e.get("k"), None);
+        assert!(!e.inner.exists("k"));
+        assert_eq!(e.take_expired(), vec!["k".to_string()]);
+        assert!(e.take_expired().is_empty());
+        assert_eq!(e.get("live"), Some("v".to_string()));
+    }
+
+    #[test]
+    fn test_tracking_disabled_queues_nothing() {
+        let e = engine(false);
+        e.set("k".to_string(), "v".to_string()).unwrap();
+        e.set_expiry("k", Some(0)).unwrap();
+        assert!(!e.exists("k"));
+        assert!(e.take_expired().is_empty());
+    }
+
+    #[test]
+    fn test_set_clears_and_rmw_keeps_expiry() {
+        let e = engine(false);
+        e.set("n".to_string(), "1".to_string()).unwrap();
+        let at = now_ms() + 60_000;
+        e.set_expiry("n", Some(at)).unwrap();
+        e.increment("n", None).unwrap();
+        assert_eq!(e.expiry("n"), Some(at));
+        e.set("n".to_string(), "5".to_string()).unwrap();
+        assert_eq!(e.expiry("n"), None);
+
+        // Expired keys restart from scratch on increment
+        e.set_expiry("n", Some(0)).unwrap();
+        assert_eq!(e.increment("n", Some(2)).unwrap(), 2);
+        assert!(!e.set_expiry("missing", Some(at)).unwrap());
+    }
+}
@@ -0,0 +1,144 @@
+//! # Field Maps (HSET / HGET / HGETALL)
+//!
+//! Hash-like values stored under a single key. The map is serialized as a
+//! compact JSON object with fields in sorted order, so the same logical map
+//! always produces the same bytes: replicated values and Merkle leaf hashes
+//! stay identical across nodes.
+//!
+//! ## Atomicity
+//!
+//! `hset` is a read-modify-write over the engine. Callers must run it while
+//! holding the shared store lock (as the server does for every command), which
+//! makes multi-field updates atomic with respect to all other commands. Field
+//! updates keep the key's TTL.
+
+use anyhow::{anyhow, Result};
+use std::collections::BTreeMap;
+
+use super::kv_trait::KVEngineStoreTrait;
+
+/// Field name → value.
+pub type FieldMap = BTreeMap<String, String>;
+
+/// Decode a stored value as a field map.
+pub fn decode(value: &str) -> Result<FieldMap> {
+    serde_json::from_str(value).map_err(|_| anyhow!("Value is not a field map"))
+}
+
+/// Encode a field map in its canonical (sorted, compact) form.
+pub fn encode(map: &FieldMap) -> String {
+    serde_json::to_string(map).expect("string map always serializes")
+}
+
+/// Set one or more fields, creating the map if the key is absent.
+///
+/// # Returns
+/// * `Result<(usize, String)>` - Number of newly added fields and the new serialized value
+pub fn hset(store: &dyn KVEngineStoreTrait, key: &str, pairs: &[(String, String)]) -> Result<(usize, String)> {
+    let mut map = match store.get(key) {
+        Some(value) => decode(&value)?,
+        None => FieldMap::new(),
+    };
+    let mut added = 0;
+    for (field, value) in pairs {
+        if map.insert(field.clone(), value.clone()).is_none() {
+            added += 1;
+        }
+    }
+    // Updating fields must not make an expiring map persistent
+    let expires_at = store.expiry(key);
+    let serialized = encode(&map);
+    store.set(key.to_string(), serialized.clone())?;
+    if expires_at.is_some() {
+        store.set_expiry(key, expires_at)?;
+    }
+    Ok((added, serialized))
+}
+
+/// Get a single field. `Ok(None)` if the key or the field is absent.
+pub fn hget(store: &dyn KVEngineStoreTrait, key: &str, field: &str) -> Result<Option<String>> {
+    match store.get(key) {
+        Some(value) => Ok(decode(&value)?.remove(field)),
+        None => Ok(None),
+    }
+}
+
+/// Get every field. `Ok(None)` if the key is absent.
+pub fn hgetall(store: &dyn KVEngineStoreTrait, key: &str) -> Result<Option<FieldMap>> {
+    store.get(key).map(|value| decode(&value)).transpose()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::store::RwLockEngine;
+    use std::sync::Arc;
+    use tokio::sync::Mutex;
+
+    fn pairs(items: &[(&str, &str)]) -> Vec<(String, String)> {
+        items.iter().map(|(f, v)| (f.to_string(), v.to_string())).collect()
+    }
+
+    #[test]
+    fn test_multi_field_set_and_get() {
+        let store = RwLockEngine::new("unused").unwrap();
+        let (added, value) = hset(&store, "user:1", &pairs(&[("name", "ann"), ("age", "31")])).unwrap();
+        assert_eq!(added, 2);
+        // Canonical form: sorted, compact
+        assert_eq!(value, r#"{"age":"31","name":"ann"}"#);
+
+        let (added, _) = hset(&store, "user:1", &pairs(&[("age", "32"), ("city", "hue")])).unwrap();
+        assert_eq!(added, 1);
+
+        assert_eq!(hget(&store, "user:1", "age").unwrap(), Some("32".to_string()));
+        assert_eq!(hget(&store, "user:1", "zip").unwrap(), None);
+        assert_eq!(hget(&store, "nobody", "age").unwrap(), None);
+
+        let all = hgetall(&store, "user:1").unwrap().unwrap();
+        assert_eq!(all.len(), 3);
+        assert_eq!(all["name"], "ann");
+        assert!(hgetall(&store, "nobody").unwrap().is_none());
+    }
+
+    #[test]
+    fn test_non_map_value_is_rejected() {
+        let store = RwLockEngine::new("unused").unwrap();
+        store.set("plain".to_string(), "hello".to_string()).unwrap();
+        assert!(hset(&store, "plain", &pairs(&[("f", "v")])).is_err());
+        assert!(hget(&store, "plain", "f").is_err());
+        // The original value is untouched
+        assert_eq!(store.get("plain"), Some("hello".to_string()));
+    }
+
+    #[test]
+    fn test_field_update_keeps_ttl() {
+        let store = crate::store::ExpiringEngine::new(Box::new(RwLockEngine::new("unused").unwrap()), false);
+        hset(&store, "m", &pairs(&[("a", "1")])).unwrap();
+        let at = crate::store::expiring::now_ms() + 60_000;
+        store.set_expiry("m", Some(at)).unwrap();
+        hset(&store, "m", &pairs(&[("b", "2")])).unwrap();
+        assert_eq!(store.expiry("m"), Some(at));
+    }
+
+    #[tokio::test]
+    async fn test_concurrent_field_updates_do_not_clobber() {
+        let store: Arc<Mutex<Box<dyn KVEngineStoreTrait + Send + Sync>>> =
+            Arc::new(Mutex::new(Box::new(RwLockEngine::new("unused").unwrap())));
+
+        let mut tasks = Vec::new();
+        for i in 0..50 {
+            let store = Arc::clone(&store);
+            tasks.push(tokio::spawn(async move {
+                let guard = store.lock().await;
+                hset(guard.as_ref(), "shared", &[(format!("f{}", i), i.to_string())]).unwrap();
+            }));
+        }
+        for t in tasks {
+            t.await.unwrap();
+        }
+
+        let all = hgetall(store.lock().await.as_ref(), "shared").unwrap().unwrap();
+        assert_eq!(all.len(), 50);
+        assert_eq!(all["f7"], "7");
+    }
+}
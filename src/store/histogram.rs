@@ -0,0 +1,210 @@
+//! # Keyspace Histograms
+//!
+//! Distribution statistics for capacity planning (`MEMORY HISTOGRAM`): how long
+//! keys are and how large values are, bucketed by size.
+//!
+//! The histogram is computed from a bounded sample (`KVEngineStoreTrait::sample`)
+//! rather than a full scan, so it stays cheap on large stores. Counts describe
+//! the sample; multiply by `total / sampled` for a rough keyspace estimate.
+//!
+//! ## Buckets
+//!
+//! Buckets grow by 4x, from `<=16` bytes up to `<=1MiB`, plus an overflow
+//! bucket for anything larger.
+//!
+//! ## Largest Keys (`BIGKEYS [n]`)
+//!
+//! `biggest_values` keeps the `n` largest values of a sample of up to
+//! `MAX_SAMPLES` pairs in a bounded min-heap, so finding memory hogs costs
+//! one pass and `O(n)` memory instead of sorting the keyspace. Stores with
+//! more pairs than that are only sampled, and the result may miss some.
+
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+
+use super::kv_trait::KVEngineStoreTrait;
+
+/// Samples used when the client does not ask for a specific count.
+pub const DEFAULT_SAMPLES: usize = 1000;
+
+/// Upper bound on samples per request, to keep the command cheap.
+pub const MAX_SAMPLES: usize = 100_000;
+
+/// Keys `BIGKEYS` lists when the client does not ask for a count.
+pub const DEFAULT_BIG_KEYS: usize = 10;
+
+/// Inclusive upper bounds (bytes) of every bucket except the overflow one.
+pub const BUCKET_BOUNDS: [usize; 9] = [16, 64, 256, 1024, 4096, 16384, 65536, 262144, 1048576];
+
+/// Counts of sizes per bucket.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SizeHistogram {
+    counts: [u64; BUCKET_BOUNDS.len() + 1],
+}
+
+impl SizeHistogram {
+    /// Record one size (in bytes).
+    pub fn record(&mut self, size: usize) {
+        let bucket = BUCKET_BOUNDS
+            .iter()
+            .position(|&bound| size <= bound)
+            .unwrap_or(BUCKET_BOUNDS.len());
+        self.counts[bucket] += 1;
+    }
+
+    /// Per-bucket counts, in bucket order (overflow last).
+    pub fn counts(&self) -> &[u64] {
+        &self.counts
+    }
+
+    /// Label for bucket `i`: `le_<bound>` or `gt_<largest bound>` for the overflow.
+    pub fn bucket_label(i: usize) -> String {
+        match BUCKET_BOUNDS.get(i) {
+            Some(bound) => format!("le_{}", bound),
+            None => format!("gt_{}", BUCKET_BOUNDS[BUCKET_BOUNDS.len() - 1]),
+        }
+    }
+}
+
+/// Key length and value size histograms over a sample of the keyspace.
+#[derive(Debug, Clone, Default)]
+pub struct KeyspaceHistogram {
+    /// Number of pairs actually sampled
+    pub sampled: usize,
+    /// Key lengths in bytes
+    pub key_len: SizeHistogram,
+    /// Value sizes in bytes
+    pub value_size: SizeHistogram,
+}
+
+impl KeyspaceHistogram {
+    /// Sample up to `limit` pairs (capped at `MAX_SAMPLES`) and bucket them.
+    pub fn sample(store: &dyn KVEngineStoreTrait, limit: usize) -> Self {
+        let mut hist = Self::default();
+        for (key, value) in store.sample(limit.min(MAX_SAMPLES)) {
+            hist.sampled += 1;
+            hist.key_len.record(key.len());
+            hist.value_size.record(value.len());
+        }
+        hist
+    }
+
+    /// Render as `name:value` lines (same layout as INFO / STATS).
+    pub fn format(&self, total_keys: usize) -> String {
+        let mut out = format!("sampled_keys:{}\r\ntotal_keys:{}\r\n", self.sampled, total_keys);
+        for (name, hist) in [("key_len", &self.key_len), ("value_size", &self.value_size)] {
+            for (i, count) in hist.counts().iter().enumerate() {
+                out.push_str(&format!("{}_{}:{}\r\n", name, SizeHistogram::bucket_label(i), count));
+            }
+        }
+        out
+    }
+}
+
+/// The `n` largest values of a sample of the keyspace.
+///
+/// # Returns
+/// * `(Vec<(String, usize)>, usize)` - Keys with their value sizes in bytes,
+///   largest first (ties by key), and the number of pairs sampled
+pub fn biggest_values(store: &dyn KVEngineStoreTrait, n: usize) -> (Vec<(String, usize)>, usize) {
+    let mut heap: BinaryHeap<Reverse<(usize, String)>> = BinaryHeap::with_capacity(n + 1);
+    let mut sampled = 0;
+    for (key, value) in store.sample(MAX_SAMPLES) {
+        sampled += 1;
+        heap.push(Reverse((value.len(), key)));
+        if heap.len() > n {
+            heap.pop();
+        }
+    }
+    let mut biggest: Vec<(String, usize)> = heap.into_iter().map(|Reverse((size, key))| (key, size)).collect();
+    biggest.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+    (biggest, sampled)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::store::RwLockEngine;
+
+    fn populated() -> RwLockEngine {
+        let engine = RwLockEngine::new("unused").unwrap();
+        // 600 small values, 300 medium, 100 large; keys are short except one group
+        for i in 0..600 {
+            engine.set(format!("s:{}", i), "x".repeat(10)).unwrap();
+        }
+        for i in 0..300 {
+            engine.set(format!("m:{}", i), "x".repeat(500)).unwrap();
+        }
+        for i in 0..100 {
+            engine.set(format!("long-key-{}-{}", i, "k".repeat(80)), "x".repeat(5000)).unwrap();
+        }
+        engine
+    }
+
+    #[test]
+    fn test_bucket_boundaries() {
+        let mut h = SizeHistogram::default();
+        for size in [0, 16, 17, 1048576, 1048577] {
+            h.record(size);
+        }
+        assert_eq!(h.counts()[0], 2);
+        assert_eq!(h.counts()[1], 1);
+        assert_eq!(h.counts()[8], 1);
+        assert_eq!(h.counts()[9], 1);
+        assert_eq!(SizeHistogram::bucket_label(0), "le_16");
+        assert_eq!(SizeHistogram::bucket_label(9), "gt_1048576");
+    }
+
+    #[test]
+    fn test_full_sample_matches_distribution() {
+        let engine = populated();
+        let hist = KeyspaceHistogram::sample(&engine, 10_000);
+        assert_eq!(hist.sampled, 1000);
+        // value sizes: 10 → le_16, 500 → le_1024, 5000 → le_16384
+        assert_eq!(hist.value_size.counts()[0], 600);
+        assert_eq!(hist.value_size.counts()[3], 300);
+        assert_eq!(hist.value_size.counts()[5], 100);
+        // key lengths: short keys → le_16, long keys → le_256
+        assert_eq!(hist.key_len.counts()[0], 900);
+        assert_eq!(hist.key_len.counts()[2], 100);
+    }
+
+    #[test]
+    fn test_bounded_sample_roughly_reflects_distribution() {
+        let engine = populated();
+        let hist = KeyspaceHistogram::sample(&engine, 200);
+        assert!(hist.sampled <= 200 && hist.sampled >= 150, "sampled {}", hist.sampled);
+
+        let share = |n: u64| n as f64 / hist.sampled as f64;
+        let small = share(hist.value_size.counts()[0]);
+        let medium = share(hist.value_size.counts()[3]);
+        let large = share(hist.value_size.counts()[5]);
+        assert!((0.4..0.8).contains(&small), "small share {}", small);
+        assert!((0.15..0.45).contains(&medium), "medium share {}", medium);
+        assert!((0.02..0.2).contains(&large), "large share {}", large);
+    }
+
+    #[test]
+    fn test_biggest_values_keeps_the_largest() {
+        let engine = populated();
+        engine.set("huge".to_string(), "x".repeat(9000)).unwrap();
+        engine.set("bigger".to_string(), "x".repeat(7000)).unwrap();
+        let (biggest, sampled) = biggest_values(&engine, 3);
+        assert_eq!(sampled, 1002);
+        assert_eq!(biggest.len(), 3);
+        assert_eq!(biggest[0], ("huge".to_string(), 9000));
+        assert_eq!(biggest[1], ("bigger".to_string(), 7000));
+        assert_eq!(biggest[2].1, 5000);
+        assert_eq!(biggest_values(&RwLockEngine::new("unused").unwrap(), 3), (Vec::new(), 0));
+    }
+
+    #[test]
+    fn test_format_lines() {
+        let engine = populated();
+        let out = KeyspaceHistogram::sample(&engine, 10_000).format(1000);
+        assert!(out.starts_with("sampled_keys:1000\r\ntotal_keys:1000\r\n"));
+        assert!(out.contains("value_size_le_16:600\r\n"));
+        assert!(out.contains("key_len_gt_1048576:0\r\n"));
+        assert_eq!(out.lines().count(), 2 + 2 * (BUCKET_BOUNDS.len() + 1));
+    }
+}
@@ -0,0 +1,80 @@
+//! # JSON Documents (`JSON.INCR`)
+//!
+//! Updates of a number inside a value holding a JSON document, addressed by
+//! a JSON pointer (RFC 6901, e.g. `/stats/views`; `` "" `` is the whole value).
+//!
+//! ## Atomicity
+//!
+//! Like `field_map::hset`, `incr` is a read-modify-write over the engine and
+//! callers must hold the shared store lock. The document is written back in
+//! compact form (object keys sorted) and keeps the key's TTL.
+
+use anyhow::{anyhow, Result};
+use serde_json::{Number, Value};
+
+use super::kv_trait::KVEngineStoreTrait;
+
+/// Add `amount` to the number at `pointer` in the document under `key`.
+/// Integers stay integers; a float on either side gives a float.
+///
+/// # Returns
+/// * `Result<(Number, String)>` - The new number and the new serialized document
+pub fn incr(store: &dyn KVEngineStoreTrait, key: &str, pointer: &str, amount: &Number) -> Result<(Number, String)> {
+    let value = store.get(key).ok_or_else(|| anyhow!("no such key"))?;
+    let mut doc: Value = serde_json::from_str(&value).map_err(|_| anyhow!("value is not JSON"))?;
+    let target = doc.pointer_mut(pointer).ok_or_else(|| anyhow!("path {} not found", pointer))?;
+    let Value::Number(current) = target else {
+        return Err(anyhow!("value at {} is not a number", pointer));
+    };
+    let sum = add(current, amount)?;
+    *target = Value::Number(sum.clone());
+
+    let expires_at = store.expiry(key);
+    let serialized = doc.to_string();
+    store.set(key.to_string(), serialized.clone())?;
+    if expires_at.is_some() {
+        store.set_expiry(key, expires_at)?;
+    }
+    Ok((sum, serialized))
+}
+
+fn add(current: &Number, amount: &Number) -> Result<Number> {
+    if let (Some(a), Some(b)) = (current.as_i64(), amount.as_i64()) {
+        return a.checked_add(b).map(Number::from).ok_or_else(|| anyhow!("increment would overflow"));
+    }
+    let sum = current.as_f64().unwrap_or(f64::NAN) + amount.as_f64().unwrap_or(f64::NAN);
+    Number::from_f64(sum).ok_or_else(|| anyhow!("increment result is not a finite number"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::store::RwLockEngine;
+
+    fn number(s: &str) -> Number {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn test_incr_nested_number_and_errors() {
+        let store = RwLockEngine::new("unused").unwrap();
+        store.set("doc".to_string(), r#"{"name":"a","stats":{"views":41,"ratio":0.5},"tags":[1,2]}"#.to_string()).unwrap();
+
+        let (n, doc) = incr(&store, "doc", "/stats/views", &number("1")).unwrap();
+        assert_eq!(n, number("42"));
+        assert_eq!(doc, r#"{"name":"a","stats":{"ratio":0.5,"views":42},"tags":[1,2]}"#);
+        assert_eq!(store.get("doc"), Some(doc));
+        assert_eq!(incr(&store, "doc", "/stats/views", &number("-50")).unwrap().0, number("-8"));
+        assert_eq!(incr(&store, "doc", "/stats/ratio", &number("0.25")).unwrap().0, number("0.75"));
+        assert_eq!(incr(&store, "doc", "/tags/1", &number("1.5")).unwrap().0, number("3.5"));
+
+        let err = |key: &str, path: &str| incr(&store, key, path, &number("1")).unwrap_err().to_string();
+        assert_eq!(err("doc", "/name"), "value at /name is not a number");
+        assert_eq!(err("doc", "/stats/clicks"), "path /stats/clicks not found");
+        assert_eq!(err("doc", "stats"), "path stats not found");
+        assert_eq!(err("missing", "/a"), "no such key");
+        store.set("text".to_string(), "plain".to_string()).unwrap();
+        assert_eq!(err("text", ""), "value is not JSON");
+        assert_eq!(store.get("text"), Some("plain".to_string()));
+    }
+}
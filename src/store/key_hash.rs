@@ -0,0 +1,142 @@
+//! # Key Hashing
+//!
+//! This module provides the hash function used to place keys, both when
+//! distributing them across engine shards and when assigning them to Merkle
+//! buckets. Every component that needs "which slot does this key belong to"
+//! goes through `HashFn` so the placement stays consistent inside a node.
+//!
+//! ## Supported Functions
+//!
+//! - `xxhash`: xxHash64 (default) - very fast, good distribution
+//! - `siphash`: SipHash-1-3 with fixed zero keys - slower, robust to adversarial keys
+//! - `fnv`: FNV-1a 64-bit - tiny and fast for short keys
+//!
+//! ## Cluster Consistency
+//!
+//! All implementations are seeded with fixed constants so the same key always
+//! hashes to the same value on every node and across restarts. Nodes that take
+//! part in anti-entropy sync must be configured with the same function, otherwise
+//! their bucket layouts differ and tree comparison becomes meaningless.
+
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::hash::Hasher;
+use std::str::FromStr;
+
+/// Hash function used for key placement (`storage.hash_fn`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum HashFn {
+    /// xxHash64 with seed 0
+    #[default]
+    Xxhash,
+    /// SipHash-1-3 with zero keys
+    Siphash,
+    /// FNV-1a 64-bit
+    Fnv,
+}
+
+impl HashFn {
+    /// Hash a key to a 64-bit value.
+    pub fn hash(self, key: &str) -> u64 {
+        match self {
+            HashFn::Xxhash => xxhash_rust::xxh64::xxh64(key.as_bytes(), 0),
+            HashFn::Siphash => {
+                let mut hasher = siphasher::sip::SipHasher13::new_with_keys(0, 0);
+                hasher.write(key.as_bytes());
+                hasher.finish()
+            }
+            HashFn::Fnv => {
+                let mut hasher = fnv::FnvHasher::default();
+                hasher.write(key.as_bytes());
+                hasher.finish()
+            }
+        }
+    }
+
+    /// Map a key to one of `slots` slots (shards, buckets, ...).
+    ///
+    /// # Panics
+    /// Panics if `slots` is zero.
+    pub fn slot(self, key: &str, slots: usize) -> usize {
+        (self.hash(key) % slots as u64) as usize
+    }
+
+    /// The configuration name of this hash function.
+    pub fn as_str(self) -> &'static str {
+        match self {
+            HashFn::Xxhash => "xxhash",
+            HashFn::Siphash => "siphash",
+            HashFn::Fnv => "fnv",
+        }
+    }
+}
+
+impl fmt::Display for HashFn {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for HashFn {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        match s.to_ascii_lowercase().as_str() {
+            "xxhash" => Ok(HashFn::Xxhash),
+            "siphash" => Ok(HashFn::Siphash),
+            "fnv" => Ok(HashFn::Fnv),
+            _ => Err(anyhow::anyhow!(
+                "Unknown hash function '{}' (expected xxhash, siphash or fnv)",
+                s
+            )),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ALL: [HashFn; 3] = [HashFn::Xxhash, HashFn::Siphash, HashFn::Fnv];
+
+    #[test]
+    fn test_hash_is_deterministic() {
+        for hash_fn in ALL {
+            assert_eq!(hash_fn.hash("user:123"), hash_fn.hash("user:123"));
+            assert_ne!(hash_fn.hash("user:123"), hash_fn.hash("user:124"));
+        }
+    }
+
+    #[test]
+    fn test_functions_differ() {
+        let key = "some-key";
+        assert_ne!(HashFn::Xxhash.hash(key), HashFn::Siphash.hash(key));
+        assert_ne!(HashFn::Xxhash.hash(key), HashFn::Fnv.hash(key));
+        assert_ne!(HashFn::Siphash.hash(key), HashFn::Fnv.hash(key));
+    }
+
+    #[test]
+    fn test_slot_in_range_and_spread() {
+        for hash_fn in ALL {
+            let mut used = [0usize; 8];
+            for i in 0..1000 {
+                let slot = hash_fn.slot(&format!("key:{}", i), 8);
+                assert!(slot < 8);
+                used[slot] += 1;
+            }
+            // Every slot receives a reasonable share of sequential keys
+            assert!(used.iter().all(|&n| n > 50), "{} skewed: {:?}", hash_fn, used);
+        }
+    }
+
+    #[test]
+    fn test_parse_and_display() {
+        for hash_fn in ALL {
+            assert_eq!(hash_fn.as_str().parse::<HashFn>().unwrap(), hash_fn);
+        }
+        assert_eq!("XXHASH".parse::<HashFn>().unwrap(), HashFn::Xxhash);
+        assert!("md5".parse::<HashFn>().is_err());
+        assert_eq!(HashFn::default(), HashFn::Xxhash);
+    }
+}
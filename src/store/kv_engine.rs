@@ -48,6 +48,9 @@ pub struct KvEngine {
     // sled_db: sled::Db,
 }
 
+// Inherent API kept for direct (non-trait) callers; the server goes through
+// `KVEngineStoreTrait`, so these are unused in the binary itself.
+#[allow(dead_code)]
 impl KvEngine {
     /// Create a new storage engine instance.
     ///
@@ -175,9 +178,10 @@ impl KvEngine {
         self.data.read().unwrap().contains_key(key)
     }
     pub fn memory_usage(&self) -> usize {
-        // Rough estimate: size of HashMap + sizes of keys and values
+        // Rough estimate: size of HashMap and its allocated slots + sizes of keys and values
         let map = self.data.read().unwrap();
         let mut size = std::mem::size_of_val(&*map);
+        size += map.capacity() * std::mem::size_of::<(String, String)>();
         for (k, v) in map.iter() {
             size += std::mem::size_of_val(k) + k.len();
             size += std::mem::size_of_val(v) + v.len();
@@ -436,9 +440,10 @@ impl KVEngineStoreTrait for KvEngine {
     }
 
     fn memory_usage(&self) -> usize {
-        // Rough estimate: size of HashMap + sizes of keys and values
+        // Rough estimate: size of HashMap and its allocated slots + sizes of keys and values
         let map = self.data.read().unwrap();
         let mut size = std::mem::size_of_val(&*map);
+        size += map.capacity() * std::mem::size_of::<(String, String)>();
         for (k, v) in map.iter() {
             size += std::mem::size_of_val(k) + k.len();
             size += std::mem::size_of_val(v) + v.len();
@@ -575,6 +580,19 @@ impl KVEngineStoreTrait for KvEngine {
         // In a persistent storage engine, this would flush data to disk
         Ok(())
     }
+
+    fn sample(&self, limit: usize) -> Vec<(String, String)> {
+        let data = self.data.read().unwrap();
+        data.iter()
+            .take(limit)
+            .map(|(k, v)| (k.clone(), v.clone()))
+            .collect()
+    }
+
+    fn compact_memory(&self, _part: usize) -> bool {
+        self.data.write().unwrap().shrink_to_fit();
+        false
+    }
 }
 
 #[cfg(test)]
@@ -588,23 +606,23 @@ mod tests {
         let temp_dir = tempdir().unwrap();
         let storage_path = temp_dir.path().to_str().unwrap();
 
-        let mut engine = KvEngine::new(storage_path).unwrap();
+        let engine = KvEngine::new(storage_path).unwrap();
 
         // Test basic set and get operations
-        engine.set("key1".to_string(), "value1".to_string());
+        engine.set("key1".to_string(), "value1".to_string()).unwrap();
         assert_eq!(engine.get("key1"), Some("value1".to_string()));
 
         // Test overwriting an existing key
-        engine.set("key1".to_string(), "new_value".to_string());
+        engine.set("key1".to_string(), "new_value".to_string()).unwrap();
         assert_eq!(engine.get("key1"), Some("new_value".to_string()));
 
         // Test delete operation
-        engine.delete("key1");
+        assert!(engine.delete("key1"));
         assert_eq!(engine.get("key1"), None);
 
         // Test keys() method with multiple entries
-        engine.set("key2".to_string(), "value2".to_string());
-        engine.set("key3".to_string(), "value3".to_string());
+        engine.set("key2".to_string(), "value2".to_string()).unwrap();
+        engine.set("key3".to_string(), "value3".to_string()).unwrap();
 
         let keys = engine.keys();
         assert_eq!(keys.len(), 2);
@@ -616,7 +634,7 @@ mod tests {
     fn test_increment_operations() {
         let temp_dir = tempdir().unwrap();
         let storage_path = temp_dir.path().to_str().unwrap();
-        let mut engine = KvEngine::new(storage_path).unwrap();
+        let engine = KvEngine::new(storage_path).unwrap();
         
         // Test incrementing a non-existent key (should create with value 1)
         let result = engine.increment("counter1", None).unwrap();
@@ -634,7 +652,7 @@ mod tests {
         assert_eq!(engine.get("counter1"), Some("4".to_string()));
         
         // Test incrementing a key with non-numeric value
-        engine.set("text".to_string(), "hello".to_string());
+        engine.set("text".to_string(), "hello".to_string()).unwrap();
         let result = engine.increment("text", None);
         assert!(result.is_err());
     }
@@ -643,7 +661,7 @@ mod tests {
     fn test_decrement_operations() {
         let temp_dir = tempdir().unwrap();
         let storage_path = temp_dir.path().to_str().unwrap();
-        let mut engine = KvEngine::new(storage_path).unwrap();
+        let engine = KvEngine::new(storage_path).unwrap();
         
         // Test decrementing a non-existent key (should create with value -1)
         let result = engine.decrement("counter1", None).unwrap();
@@ -656,7 +674,7 @@ mod tests {
         assert_eq!(engine.get("counter1"), Some("-4".to_string()));
         
         // Test decrementing a key with non-numeric value
-        engine.set("text".to_string(), "hello".to_string());
+        engine.set("text".to_string(), "hello".to_string()).unwrap();
         let result = engine.decrement("text", None);
         assert!(result.is_err());
     }
@@ -665,10 +683,10 @@ mod tests {
     fn test_string_operations() {
         let temp_dir = tempdir().unwrap();
         let storage_path = temp_dir.path().to_str().unwrap();
-        let mut engine = KvEngine::new(storage_path).unwrap();
+        let engine = KvEngine::new(storage_path).unwrap();
         
         // Set up a key for testing
-        engine.set("greeting".to_string(), "World!".to_string());
+        engine.set("greeting".to_string(), "World!".to_string()).unwrap();
         
         // Test append to existing key
         let result = engine.append("greeting", " Hello!").unwrap();
@@ -689,7 +707,7 @@ mod tests {
         assert!(result.is_err());
         
         // Set up a new key for testing
-        engine.set("new_key".to_string(), "Start: ".to_string());
+        engine.set("new_key".to_string(), "Start: ".to_string()).unwrap();
         assert_eq!(engine.get("new_key"), Some("Start: ".to_string()));
     }
     
@@ -697,12 +715,12 @@ mod tests {
     fn test_truncate_operation() {
         let temp_dir = tempdir().unwrap();
         let storage_path = temp_dir.path().to_str().unwrap();
-        let mut engine = KvEngine::new(storage_path).unwrap();
+        let engine = KvEngine::new(storage_path).unwrap();
         
         // Add some data
-        engine.set("key1".to_string(), "value1".to_string());
-        engine.set("key2".to_string(), "value2".to_string());
-        engine.set("key3".to_string(), "value3".to_string());
+        engine.set("key1".to_string(), "value1".to_string()).unwrap();
+        engine.set("key2".to_string(), "value2".to_string()).unwrap();
+        engine.set("key3".to_string(), "value3".to_string()).unwrap();
         
         // Verify data exists
         assert_eq!(engine.keys().len(), 3);
@@ -711,7 +729,7 @@ mod tests {
         assert_eq!(engine.get("key3"), Some("value3".to_string()));
         
         // Truncate the store
-        engine.truncate();
+        engine.truncate().unwrap();
         
         // Verify all data is gone
         assert_eq!(engine.keys().len(), 0);
@@ -720,7 +738,7 @@ mod tests {
         assert_eq!(engine.get("key3"), None);
         
         // Verify we can add new data after truncate
-        engine.set("new_key".to_string(), "new_value".to_string());
+        engine.set("new_key".to_string(), "new_value".to_string()).unwrap();
         assert_eq!(engine.keys().len(), 1);
         assert_eq!(engine.get("new_key"), Some("new_value".to_string()));
     }
@@ -12,6 +12,71 @@
 
 use anyhow::Result;
 
+/// When a key was created, last written and last used (UNIX milliseconds).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct KeyTimes {
+    pub created_ms: u64,
+    pub modified_ms: u64,
+    /// Last read, write or `TOUCH`
+    pub accessed_ms: u64,
+    /// Node whose replicated write set the current value; None if it was
+    /// written on this node
+    pub origin: Option<String>,
+}
+
+/// On-disk footprint of a persistent engine compared to its live data.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StorageStats {
+    /// Key + value bytes of the live pairs
+    pub logical_bytes: u64,
+    /// Bytes of the engine's files on disk
+    pub disk_bytes: u64,
+}
+
+/// Load of one shard of a sharded engine (`SHARD STATS`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ShardStats {
+    pub keys: usize,
+    /// Estimated as in `memory_usage`
+    pub memory_bytes: usize,
+}
+
+/// Cold tier occupancy and traffic (`INFO` `cold_tier_*`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TierStats {
+    /// Keys currently demoted to the cold tier
+    pub cold_keys: usize,
+    /// Reads served from the cold tier (each promoted the key)
+    pub cold_reads: u64,
+}
+
+/// Write failures of a write-ahead log (`INFO` `wal_write_errors`, `wal_status`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct WalHealth {
+    /// Appends refused since start (disk full, I/O error)
+    pub write_errors: u64,
+    /// Whether the latest append failed; cleared by the next one that succeeds
+    pub degraded: bool,
+    /// fsyncs of the log since start (`SYNC`, `DURABLE`, `storage.max_dirty_writes`)
+    pub fsyncs: u64,
+}
+
+impl StorageStats {
+    /// Fragmentation ratio (`disk / logical`); 0 when there is no live data.
+    pub fn ratio(&self) -> f64 {
+        if self.logical_bytes == 0 {
+            0.0
+        } else {
+            self.disk_bytes as f64 / self.logical_bytes as f64
+        }
+    }
+}
+
+/// A uniformly random index below `len` (> 0), from a v4 UUID's random bits.
+pub(crate) fn random_index(len: usize) -> usize {
+    (uuid::Uuid::new_v4().as_u128() % len as u128) as usize
+}
+
 /// Common interface for all key-value storage engines.
 ///
 /// This trait defines the core operations that any storage engine must implement.
@@ -55,6 +120,38 @@ pub trait KVEngineStoreTrait: Send + Sync {
     /// * `Vec<String>` - Vector of all keys in the store
     fn keys(&self) -> Vec<String>;
 
+    /// Get all keys in byte order.
+    ///
+    /// `keys()` follows the engine's internal layout (hash order for the
+    /// in-memory engines), which differs between runs and nodes; exports,
+    /// snapshots and Merkle builds use this order instead so they are
+    /// reproducible.
+    ///
+    /// # Returns
+    /// * `Vec<String>` - Vector of all keys, sorted
+    fn keys_sorted(&self) -> Vec<String> {
+        let mut keys = self.keys();
+        keys.sort_unstable();
+        keys
+    }
+
+    /// Get up to `limit` keys after `after` (all keys for `None`), in byte order.
+    ///
+    /// Listings page through the store with this, passing the last key of
+    /// one page to get the next, so the store can be released between pages.
+    ///
+    /// # Returns
+    /// * `Vec<String>` - Vector of at most `limit` keys, sorted
+    fn keys_page(&self, after: Option<&str>, limit: usize) -> Vec<String> {
+        let mut keys: Vec<String> = self.keys().into_iter().filter(|k| after.is_none_or(|a| k.as_str() > a)).collect();
+        if keys.len() > limit {
+            keys.select_nth_unstable(limit);
+            keys.truncate(limit);
+        }
+        keys.sort_unstable();
+        keys
+    }
+
     /// Scan for keys matching a prefix.
     ///
     /// # Returns
@@ -88,6 +185,7 @@ pub trait KVEngineStoreTrait: Send + Sync {
     ///
     /// # Returns
     /// * `usize` - Number of key-value pairs
+    #[allow(dead_code)]
     fn len(&self) -> usize;
 
     /// Get the size of the database (number of key-value pairs).
@@ -99,6 +197,7 @@ pub trait KVEngineStoreTrait: Send + Sync {
     ///
     /// # Returns
     /// * `bool` - True if the store is empty, false otherwise
+    #[allow(dead_code)]
     fn is_empty(&self) -> bool;
     
     /// Increment a numeric value.
@@ -159,4 +258,223 @@ pub trait KVEngineStoreTrait: Send + Sync {
     /// # Returns
     /// * `Result<()>` - Success or error
     fn sync(&self) -> Result<()>;
+
+    /// Return up to `limit` (key, value) pairs for statistics sampling.
+    ///
+    /// Engines should override this to stop early instead of listing every key;
+    /// the default implementation is only bounded in what it returns.
+    ///
+    /// # Arguments
+    /// * `limit` - Maximum number of pairs to return
+    fn sample(&self, limit: usize) -> Vec<(String, String)> {
+        self.keys()
+            .into_iter()
+            .take(limit)
+            .filter_map(|k| self.get(&k).map(|v| (k, v)))
+            .collect()
+    }
+
+    /// Return a uniformly random existing key, for `RANDOMKEY`.
+    ///
+    /// Engines should override this to pick without listing every key; the
+    /// default implementation lists them all.
+    fn random_key(&self) -> Option<String> {
+        let keys = self.keys();
+        (!keys.is_empty()).then(|| keys[random_index(keys.len())].clone())
+    }
+
+    /// Set or clear the expiry of an existing key.
+    ///
+    /// # Arguments
+    /// * `key` - The key to update
+    /// * `expires_at_ms` - Absolute UNIX time in milliseconds, or None to persist the key
+    ///
+    /// # Returns
+    /// * `Result<bool>` - True if the key exists, error if the engine has no TTL support
+    fn set_expiry(&self, _key: &str, _expires_at_ms: Option<u64>) -> Result<bool> {
+        Err(anyhow::anyhow!("Key expiry is not supported by this engine"))
+    }
+
+    /// Get the absolute expiry (UNIX milliseconds) of a key.
+    ///
+    /// # Returns
+    /// * `Option<u64>` - None if the key has no expiry, is absent, or TTLs are unsupported
+    fn expiry(&self, _key: &str) -> Option<u64> {
+        None
+    }
+
+    /// Drain the keys removed by lazy expiry (on read) since the last call.
+    ///
+    /// # Returns
+    /// * `Vec<String>` - Expired keys, empty if tracking is disabled or unsupported
+    fn take_expired(&self) -> Vec<String> {
+        Vec::new()
+    }
+
+    /// Creation and last modification time of a key.
+    ///
+    /// # Returns
+    /// * `Option<KeyTimes>` - None if the key is absent or timestamps are not tracked
+    fn times(&self, _key: &str) -> Option<KeyTimes> {
+        None
+    }
+
+    /// Record that the value just written to `key` came from node `origin`
+    /// through replication (`OBJECT REPLINFO`); the next local write clears it.
+    fn mark_replicated(&self, _key: &str, _origin: &str) {}
+
+    /// Keep the value just written to `key` out of the Merkle tree
+    /// (`SET ... LOCAL`); the next write of any other kind brings it back.
+    fn mark_local(&self, _key: &str) {}
+
+    /// Whether `key` holds a `SET ... LOCAL` value.
+    fn is_local(&self, _key: &str) -> bool {
+        false
+    }
+
+    /// Mark `key` as just used without reading its value (`TOUCH`): moves
+    /// its access time and its place in LRU order.
+    ///
+    /// # Returns
+    /// * `bool` - True if the key exists
+    fn touch(&self, key: &str) -> bool {
+        self.exists(key)
+    }
+
+    /// Deleted keys still inside the tombstone grace period, with their
+    /// deletion time (UNIX milliseconds), sorted by key.
+    ///
+    /// # Returns
+    /// * `Vec<(String, u64)>` - Live tombstones, empty if tombstones are unsupported
+    fn tombstones(&self) -> Vec<(String, u64)> {
+        Vec::new()
+    }
+
+    /// Record a delete learned from a peer, keeping its original deletion time.
+    ///
+    /// # Returns
+    /// * `bool` - True if recorded (false if the key exists, the tombstone is
+    ///   already past the grace period, or tombstones are unsupported)
+    fn add_tombstone(&self, _key: &str, _deleted_at_ms: u64) -> bool {
+        false
+    }
+
+    /// Purge tombstones older than the grace period.
+    ///
+    /// # Returns
+    /// * `usize` - Number of tombstones purged
+    fn compact_tombstones(&self) -> usize {
+        0
+    }
+
+    /// Shrink the in-memory structures of one part (e.g. a shard) to fit their
+    /// contents, releasing capacity left behind by deletes.
+    ///
+    /// Callers compact part `0`, `1`, ... until this returns false, and may
+    /// release any outer lock between parts so readers are never blocked for
+    /// the whole keyspace. Engines without reclaimable memory keep the default.
+    ///
+    /// # Returns
+    /// * `bool` - True if more parts remain after `part`
+    fn compact_memory(&self, _part: usize) -> bool {
+        false
+    }
+
+    /// Compare the on-disk size with the live data size.
+    ///
+    /// # Returns
+    /// * `Option<StorageStats>` - None for engines without on-disk files
+    fn storage_stats(&self) -> Option<StorageStats> {
+        None
+    }
+
+    /// Key count and memory of each shard, in shard order.
+    ///
+    /// # Returns
+    /// * `Option<Vec<ShardStats>>` - None for engines that are not sharded
+    fn shard_stats(&self) -> Option<Vec<ShardStats>> {
+        None
+    }
+
+    /// Load `key` from a slower tier into memory ahead of its use (`WARM`).
+    ///
+    /// # Returns
+    /// * `Result<bool>` - True if the key was loaded; false if it was already
+    ///   in memory, is missing, or the engine has a single tier
+    fn warm(&self, _key: &str) -> Result<bool> {
+        Ok(false)
+    }
+
+    /// Occupancy and reads of the cold tier.
+    ///
+    /// # Returns
+    /// * `Option<TierStats>` - None for engines without a cold tier
+    fn tier_stats(&self) -> Option<TierStats> {
+        None
+    }
+
+    /// Keys whose values start with `prefix`, sorted, from a value index.
+    ///
+    /// # Returns
+    /// * `Option<Vec<String>>` - None if no value index is maintained
+    fn find_by_value_prefix(&self, _prefix: &str) -> Option<Vec<String>> {
+        None
+    }
+
+    /// Rewrite the on-disk files to release space held by overwritten and
+    /// deleted data. The caller must keep other operations out meanwhile.
+    ///
+    /// # Returns
+    /// * `Result<bool>` - True if compacted, false for engines without on-disk files
+    fn compact_storage(&self) -> Result<bool> {
+        Ok(false)
+    }
+
+    /// Whether writes are appended to a write-ahead log.
+    ///
+    /// # Returns
+    /// * `Option<bool>` - None for engines without a log
+    fn persistence(&self) -> Option<bool> {
+        None
+    }
+
+    /// Write failures of the write-ahead log.
+    ///
+    /// # Returns
+    /// * `Option<WalHealth>` - None for engines without a log
+    fn wal_health(&self) -> Option<WalHealth> {
+        None
+    }
+
+    /// Whether the last write to `key` is fsynced to the log.
+    ///
+    /// # Returns
+    /// * `Option<bool>` - None for engines without a log
+    fn durable(&self, _key: &str) -> Option<bool> {
+        None
+    }
+
+    /// Record a key's new expiry in the log, so it survives a restart.
+    /// Called by the expiry layer before it applies the change.
+    ///
+    /// # Returns
+    /// * `Result<()>` - Ok for engines without a log, error if appending failed
+    fn log_expiry(&self, _key: &str, _expires_at_ms: Option<u64>) -> Result<()> {
+        Ok(())
+    }
+
+    /// Expiries (key, UNIX ms) replayed from the log, for the expiry layer to
+    /// pick up on start.
+    ///
+    /// # Returns
+    /// * `Vec<(String, u64)>` - Empty for engines without a log
+    fn logged_expiries(&self) -> Vec<(String, u64)> {
+        Vec::new()
+    }
+
+    /// Pause (`false`) or resume (`true`) appending writes to the log.
+    /// Pausing first writes a snapshot of the current data.
+    fn set_persistence(&self, _enabled: bool) -> Result<()> {
+        Err(anyhow::anyhow!("no write-ahead log configured (storage.wal_path)"))
+    }
 }
@@ -1,6 +1,31 @@
+//! # Merkle Tree
+//!
+//! Binary hash tree over the (key, value) pairs of the store, used to compare
+//! two nodes cheaply. By default every key is a leaf, so the tree gets one
+//! level deeper each time the key count doubles.
+//!
+//! ## Bucketing (`merkle.num_buckets`)
+//!
+//! With `with_buckets`, keys are instead hashed (by `storage.hash_fn`) into a
+//! fixed number of leaf buckets, each hashing the leaves of the keys it holds.
+//! The tree then always has `num_buckets` leaves, bounding its depth and the
+//! cost of comparing roots and subtrees whatever the key count, at the price
+//! of coarser diffs: a differing bucket's keys are all compared. Trees are
+//! only comparable when both sides use the same bucket count and hash.
+//!
+//! ## Range Hashes (`RANGEHASH`)
+//!
+//! `range_hash` digests the pairs whose keys fall in a lexical range without
+//! building a tree: SHA-256 over the leaf encodings in key order. It depends
+//! only on the data, not on bucketing or the hash function, so two nodes can
+//! compare a range and skip it during sync when the digests match.
+
 use sha2::{Digest, Sha256};
 use std::collections::HashMap;
 
+use super::key_hash::HashFn;
+use super::kv_trait::KVEngineStoreTrait;
+
 // === Safe leaf encoding: length-prefix (u32 big-endian) ===
 // Why? Concatenating "key:value" is ambiguous (e.g., "a::b").
 // Length-prefixing eliminates ambiguity and is robust to any bytes (including NUL).
@@ -22,6 +47,23 @@ pub struct MerkleNode {
     pub right: Option<Box<MerkleNode>>,
     /// Present only for leaf nodes (None for internal nodes)
     pub key: Option<String>,
+    /// Keys held by a bucket leaf (bucketed trees only)
+    pub bucket_keys: Vec<String>,
+}
+
+impl MerkleNode {
+    /// Keys of the leaves under this node, in key order.
+    pub fn leaf_keys(&self) -> Vec<String> {
+        fn go(n: &MerkleNode, acc: &mut Vec<String>) {
+            if let Some(k) = &n.key { acc.push(k.clone()); }
+            acc.extend(n.bucket_keys.iter().cloned());
+            if let Some(l) = &n.left { go(l, acc); }
+            if let Some(r) = &n.right { go(r, acc); }
+        }
+        let mut out = Vec::new();
+        go(self, &mut out);
+        out
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -29,17 +71,53 @@ pub struct MerkleTree {
     pub root: Option<MerkleNode>,
     // Stores leaf hashes keyed by user-provided key (we don't store raw values here).
     leaf_map: HashMap<String, Vec<u8>>,
+    // Set by the `stage_*` methods: leaves changed but `root` was not rebuilt yet.
+    dirty: bool,
+    // Leaf bucket count and placement hash; None keeps one leaf per key.
+    buckets: Option<(usize, HashFn)>,
 }
 
+#[allow(dead_code)]
 impl MerkleTree {
     /// Create an empty Merkle tree.
     pub fn new() -> Self {
         Self {
             root: None,
             leaf_map: HashMap::new(),
+            dirty: false,
+            buckets: None,
         }
     }
 
+    /// Hash keys into `num_buckets` leaf buckets placed by `hash_fn`
+    /// (0 keeps one leaf per key). The root is stale until `refresh`.
+    pub fn with_buckets(mut self, num_buckets: usize, hash_fn: HashFn) -> Self {
+        self.buckets = (num_buckets > 0).then_some((num_buckets, hash_fn));
+        self.dirty = !self.leaf_map.is_empty();
+        self
+    }
+
+    /// Leaf bucket count and placement hash, None for one leaf per key.
+    pub fn buckets(&self) -> Option<(usize, HashFn)> {
+        self.buckets
+    }
+
+    /// Build a tree from (key, value) pairs with a single rebuild at the end.
+    /// Much cheaper than calling `insert` per pair on large data sets.
+    pub fn from_pairs<I, K, V>(pairs: I) -> Self
+    where
+        I: IntoIterator<Item = (K, V)>,
+        K: AsRef<str>,
+        V: AsRef<str>,
+    {
+        let mut tree = Self::new();
+        for (k, v) in pairs {
+            tree.stage_insert(k.as_ref(), v.as_ref());
+        }
+        tree.refresh();
+        tree
+    }
+
     /// Shared helper: compute a leaf hash from (key, value).
     /// Using a shared function guarantees tests and implementation stay in sync.
     fn compute_leaf_hash(key: &str, value: &str) -> Vec<u8> {
@@ -61,6 +139,53 @@ impl MerkleTree {
         self.rebuild();
     }
 
+    /// Insert or update a leaf without rebuilding the tree.
+    /// The root is stale until `refresh` is called.
+    pub fn stage_insert(&mut self, key: &str, value: &str) {
+        let hash = Self::compute_leaf_hash(key, value);
+        self.leaf_map.insert(key.to_string(), hash);
+        self.dirty = true;
+    }
+
+    /// Remove a leaf without rebuilding the tree.
+    /// The root is stale until `refresh` is called.
+    pub fn stage_remove(&mut self, key: &str) {
+        if self.leaf_map.remove(key).is_some() {
+            self.dirty = true;
+        }
+    }
+
+    /// Drop every leaf.
+    pub fn clear(&mut self) {
+        self.leaf_map.clear();
+        self.root = None;
+        self.dirty = false;
+    }
+
+    /// Rebuild the tree if leaves were staged since the last rebuild.
+    pub fn refresh(&mut self) {
+        if self.dirty {
+            self.rebuild();
+        }
+    }
+
+    /// Number of leaves (keys) in the tree.
+    pub fn len(&self) -> usize {
+        self.leaf_map.len()
+    }
+
+    /// Whether the tree has no leaves.
+    pub fn is_empty(&self) -> bool {
+        self.leaf_map.is_empty()
+    }
+
+    /// Test hook: overwrite a leaf hash with garbage to simulate a maintenance bug.
+    #[cfg(test)]
+    pub fn corrupt_leaf(&mut self, key: &str) {
+        self.leaf_map.insert(key.to_string(), vec![0xAB; 32]);
+        self.rebuild();
+    }
+
     /// Get a reference to the current root hash (if the tree is non-empty).
     pub fn get_root_hash(&self) -> Option<&Vec<u8>> {
         self.root.as_ref().map(|node| &node.hash)
@@ -71,24 +196,13 @@ impl MerkleTree {
     /// - Sort leaves by key (lexicographical) for deterministic root.
     /// - Pair nodes left-to-right; if odd, "promote" the last node.
     fn rebuild(&mut self) {
+        self.dirty = false;
         if self.leaf_map.is_empty() {
             self.root = None;
             return;
         }
 
-        // ✅ Determinism: sort leaves by key so the same set yields the same root.
-        let mut leaves: Vec<_> = self.leaf_map.iter().collect();
-        leaves.sort_by(|a, b| a.0.cmp(b.0));
-
-        let mut nodes: Vec<MerkleNode> = leaves
-            .into_iter()
-            .map(|(k, h)| MerkleNode {
-                hash: h.clone(),
-                left: None,
-                right: None,
-                key: Some(k.clone()), // store key at leaves
-            })
-            .collect();
+        let mut nodes = self.leaf_nodes();
 
         // Bottom-up reduction: combine pairs into parents until a single root remains.
         while nodes.len() > 1 {
@@ -107,6 +221,7 @@ impl MerkleTree {
                         left: Some(Box::new(chunk[0].clone())),
                         right: Some(Box::new(chunk[1].clone())),
                         key: None, // internal node
+                        bucket_keys: Vec::new(),
                     });
                 } else {
                     // Convention used here: with an odd count, promote the last node.
@@ -120,6 +235,45 @@ impl MerkleTree {
         self.root = nodes.into_iter().next();
     }
 
+    /// Bottom level of the tree: one node per key, or one per bucket.
+    fn leaf_nodes(&self) -> Vec<MerkleNode> {
+        // ✅ Determinism: sort leaves by key so the same set yields the same root.
+        let mut leaves: Vec<_> = self.leaf_map.iter().collect();
+        leaves.sort_by(|a, b| a.0.cmp(b.0));
+
+        let Some((num_buckets, hash_fn)) = self.buckets else {
+            return leaves
+                .into_iter()
+                .map(|(k, h)| MerkleNode {
+                    hash: h.clone(),
+                    left: None,
+                    right: None,
+                    key: Some(k.clone()), // store key at leaves
+                    bucket_keys: Vec::new(),
+                })
+                .collect();
+        };
+        // Every bucket is a leaf, empty or not, so the shape is fixed
+        let mut hashers = vec![Sha256::new(); num_buckets];
+        let mut bucket_keys = vec![Vec::new(); num_buckets];
+        for (k, h) in leaves {
+            let b = hash_fn.slot(k, num_buckets);
+            hashers[b].update(h);
+            bucket_keys[b].push(k.clone());
+        }
+        hashers
+            .into_iter()
+            .zip(bucket_keys)
+            .map(|(hasher, keys)| MerkleNode {
+                hash: hasher.finalize().to_vec(),
+                left: None,
+                right: None,
+                key: None,
+                bucket_keys: keys,
+            })
+            .collect()
+    }
+
     // ===================== Traversal & Views =====================
 
     /// Return the sorted keys (lexicographic) currently present in the tree.
@@ -152,6 +306,28 @@ impl MerkleTree {
         out
     }
 
+    /// Node reached from the root by `path`: `0` steps left, `1` right; the
+    /// empty path is the root. None if the path leaves the tree or the tree
+    /// is stale (see `refresh`).
+    pub fn subtree(&self, path: &str) -> Option<&MerkleNode> {
+        if self.dirty {
+            return None;
+        }
+        path.chars().try_fold(self.root.as_ref()?, |node, step| match step {
+            '0' => node.left.as_deref(),
+            '1' => node.right.as_deref(),
+            _ => None,
+        })
+    }
+
+    /// Levels of the current tree (1 for a single leaf, 0 when empty).
+    pub fn depth(&self) -> usize {
+        fn go(n: &MerkleNode) -> usize {
+            1 + n.left.as_deref().map(go).unwrap_or(0).max(n.right.as_deref().map(go).unwrap_or(0))
+        }
+        self.root.as_ref().map(go).unwrap_or(0)
+    }
+
     /// Count nodes (internal + leaves) in the current tree.
     pub fn node_count(&self) -> usize {
         fn cnt(n: &MerkleNode) -> usize {
@@ -162,6 +338,19 @@ impl MerkleTree {
         self.root.as_ref().map(cnt).unwrap_or(0)
     }
 
+    /// Drop what deleted keys left behind: the materialized nodes of a stale
+    /// tree (staged removals keep the old nodes until the next rebuild) and
+    /// leaf-map capacity freed by removals.
+    ///
+    /// # Returns
+    /// * `usize` - Number of tree nodes reclaimed
+    pub fn gc(&mut self) -> usize {
+        let before = self.node_count();
+        self.refresh();
+        self.leaf_map.shrink_to_fit();
+        before.saturating_sub(self.node_count())
+    }
+
     // ===================== DIFF SUPPORT (find the “wrong” keys) =====================
 
     /// Return the exact set of differing keys between `self` and `other`.
@@ -176,6 +365,16 @@ impl MerkleTree {
         for k in self.leaf_map.keys() { all_keys.insert(k); }
         for k in other.leaf_map.keys() { all_keys.insert(k); }
 
+        // Same bucket layout on both sides: only keys of differing buckets can differ
+        if let (Some((n, hash_fn)), true) = (self.buckets, self.buckets == other.buckets) {
+            let mine = self.leaf_nodes();
+            let theirs = other.leaf_nodes();
+            all_keys.retain(|k| {
+                let b = hash_fn.slot(k, n);
+                mine[b].hash != theirs[b].hash
+            });
+        }
+
         let mut diffs: Vec<String> = Vec::new();
 
         for k in all_keys {
@@ -204,6 +403,23 @@ impl MerkleTree {
     }
 }
 
+/// Digest of the pairs of `store` with `start <= key <= end` (byte order).
+///
+/// # Returns
+/// * `(usize, Vec<u8>)` - Number of pairs in the range and their SHA-256
+pub fn range_hash(store: &dyn KVEngineStoreTrait, start: &str, end: &str) -> (usize, Vec<u8>) {
+    let keys: Vec<String> = store.keys_sorted().into_iter().filter(|k| k.as_str() >= start && k.as_str() <= end).collect();
+    let mut hasher = Sha256::new();
+    let mut count = 0;
+    for key in keys {
+        if let Some(value) = store.get(&key) {
+            hasher.update(encode_leaf(&key, &value));
+            count += 1;
+        }
+    }
+    (count, hasher.finalize().to_vec())
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -212,7 +428,6 @@ mod tests {
     use std::collections::HashSet;
     use rand::rngs::StdRng;
     use rand::{SeedableRng, Rng};
-    use rand::seq::SliceRandom;
 
     fn set<T: Eq + std::hash::Hash + Clone>(xs: &[T]) -> HashSet<T> {
         xs.iter().cloned().collect()
@@ -388,7 +603,7 @@ mod tests {
     /// 🧭 Determinism (even count): same set, different insertion orders → same root.
     #[test]
     fn hard_determinism_even_count_different_insert_orders() {
-        let pairs = vec![("k1","v1"), ("k2","v2"), ("k3","v3"), ("k4","v4")];
+        let pairs = [("k1","v1"), ("k2","v2"), ("k3","v3"), ("k4","v4")];
 
         // Order A
         let mut t1 = MerkleTree::new();
@@ -406,7 +621,7 @@ mod tests {
     /// 🧭 Determinism (odd count): same set, different insertion orders → same root.
     #[test]
     fn hard_determinism_odd_count_different_insert_orders() {
-        let pairs = vec![("a","1"), ("b","2"), ("c","3")];
+        let pairs = [("a","1"), ("b","2"), ("c","3")];
 
         let mut t1 = MerkleTree::new();
         for (k,v) in pairs.iter() { t1.insert(k, v); }
@@ -446,7 +661,7 @@ mod tests {
     /// 🧪 Two independent trees over the same dataset (different orders) → same root.
     #[test]
     fn hard_two_independent_trees_same_set_same_root() {
-        let set1 = vec![("u","1"), ("v","2"), ("w","3"), ("z","4"), ("q","5")];
+        let set1 = [("u","1"), ("v","2"), ("w","3"), ("z","4"), ("q","5")];
 
         // Tree 1: order 1
         let mut t1 = MerkleTree::new();
@@ -647,7 +862,7 @@ mod tests {
 
         // Sort by key and compute manually.
         let mut sorted = items.clone();
-        sorted.sort_by(|a,b| a.0.cmp(&b.0));
+        sorted.sort_by(|a,b| a.0.cmp(b.0));
 
         let h: Vec<Vec<u8>> = sorted.iter().map(|(k,v)| leaf_hash(k,v)).collect();
 
@@ -742,7 +957,7 @@ mod tests {
     #[test]
     fn diff_empty_vs_nonempty_returns_all_keys() {
         let mut a = MerkleTree::new();
-        let mut b = MerkleTree::new();
+        let b = MerkleTree::new();
 
         for (k,v) in &[("x","1"), ("y","2"), ("z","3")] { a.insert(k,v); }
         // b stays empty
@@ -1012,7 +1227,7 @@ mod tests {
         let got = t.get_root_hash().unwrap().clone();
 
         let mut sorted = items.clone();
-        sorted.sort_by(|a,b| a.0.cmp(&b.0));
+        sorted.sort_by(|a,b| a.0.cmp(b.0));
         let hs: Vec<Vec<u8>> = sorted.iter().map(|(k,v)| leaf_hash(k,v)).collect();
 
         let mut h12 = Sha256::new(); h12.update(&hs[0]); h12.update(&hs[1]); let h12 = h12.finalize();
@@ -1180,5 +1395,84 @@ mod tests {
         let pre = t.preorder_hashes();
         assert_eq!(pre.len(), t.node_count());
     }
-    
+
+    // 23) Subtree by path: exactly the leaves under the node, in order
+    #[test]
+    fn t23_subtree_by_path() {
+        // 6 leaves: [ab, cd, ef] → [abcd, ef] → root
+        let t = MerkleTree::from_pairs(["a", "b", "c", "d", "e", "f"].map(|k| (k, "v")));
+        assert_eq!(t.subtree("").unwrap().leaf_keys(), ["a", "b", "c", "d", "e", "f"]);
+        assert_eq!(t.subtree("0").unwrap().leaf_keys(), ["a", "b", "c", "d"]);
+        assert_eq!(t.subtree("1").unwrap().leaf_keys(), ["e", "f"]);
+        assert_eq!(t.subtree("011").unwrap().leaf_keys(), ["d"]);
+        assert_eq!(t.subtree("011").unwrap().hash, leaf_hash("d", "v"));
+        // The left half is a full subtree: rebuilding it alone gives the same hash
+        let left = MerkleTree::from_pairs(["a", "b", "c", "d"].map(|k| (k, "v")));
+        assert_eq!(&t.subtree("0").unwrap().hash, left.get_root_hash().unwrap());
+        assert!(t.subtree("0110").is_none(), "below a leaf");
+        assert!(t.subtree("2").is_none());
+        assert!(MerkleTree::new().subtree("").is_none());
+    }
+
+    // 24) Bucketing: depth is fixed by the bucket count, diffs still exact
+    #[test]
+    fn t24_buckets_bound_depth_and_diff_converges() {
+        let bucketed = |n: usize| {
+            let mut t = MerkleTree::new().with_buckets(64, HashFn::Xxhash);
+            for i in 0..n { t.stage_insert(&format!("k{i}"), "v"); }
+            t.refresh();
+            t
+        };
+        for n in [1, 10, 10_000] {
+            let t = bucketed(n);
+            assert_eq!(t.depth(), 7, "64 leaf buckets = 6 internal levels, for {} keys", n);
+            assert_eq!(t.node_count(), 127);
+            assert_eq!(t.inorder_keys().len(), n);
+        }
+        assert_eq!(MerkleTree::from_pairs((0..10_000).map(|i| (format!("k{i}"), "v"))).depth(), 15);
+
+        // Two nodes, the second with a changed, a missing and an extra key
+        let mut a = bucketed(1000);
+        let mut remote: HashMap<String, String> = (0..1000).map(|i| (format!("k{i}"), "v".to_string())).collect();
+        remote.insert("k1".to_string(), "changed".to_string());
+        remote.remove("k2");
+        remote.insert("extra".to_string(), "v".to_string());
+        let mut b = MerkleTree::new().with_buckets(64, HashFn::Xxhash);
+        for (k, v) in &remote { b.stage_insert(k, v); }
+        b.refresh();
+        let mut diffs = a.diff_keys(&b);
+        diffs.sort();
+        assert_eq!(diffs, ["extra", "k1", "k2"]);
+        for k in diffs {
+            match remote.get(&k) {
+                Some(v) => a.insert(&k, v),
+                None => a.remove(&k),
+            }
+        }
+        assert_eq!(a.get_root_hash(), b.get_root_hash(), "same bucket count: converged");
+        assert!(a.subtree("").unwrap().leaf_keys().contains(&"extra".to_string()));
+    }
+
+    // 25) Range hash: same range data → same digest, whatever the insertion order
+    #[test]
+    fn t25_range_hash_matches_for_identical_ranges() {
+        use crate::store::RwLockEngine;
+        let a = RwLockEngine::new("unused").unwrap();
+        let b = RwLockEngine::new("unused").unwrap();
+        for k in ["user:1", "user:2", "user:3", "zeta"] {
+            a.set(k.to_string(), format!("v-{}", k)).unwrap();
+        }
+        for k in ["user:3", "user:1", "user:2", "alpha"] {
+            b.set(k.to_string(), format!("v-{}", k)).unwrap();
+        }
+        let (count, hash) = range_hash(&a, "user:", "user:~");
+        assert_eq!(count, 3);
+        assert_eq!(range_hash(&b, "user:", "user:~"), (3, hash.clone()), "keys outside the range are ignored");
+        assert_eq!(range_hash(&a, "user:1", "user:3").1, hash, "both ends are inclusive");
+
+        b.set("user:2".to_string(), "changed".to_string()).unwrap();
+        assert_ne!(range_hash(&b, "user:", "user:~").1, hash);
+        assert_eq!(range_hash(&b, "user:3", "user:3"), range_hash(&a, "user:3", "user:3"));
+        assert_eq!(range_hash(&a, "m", "n"), (0, Sha256::digest(b"").to_vec()));
+    }
 }
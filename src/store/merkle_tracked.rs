@@ -0,0 +1,561 @@
+//! # Merkle-Tracked Engine
+//!
+//! A decorator around any `KVEngineStoreTrait` implementation that keeps a live
+//! Merkle tree in step with every write. Leaves are updated incrementally and
+//! the tree is only rebuilt when someone actually reads the root, so writes stay
+//! cheap on large data sets.
+//!
+//! Because every mutation path (client commands, replication apply loop,
+//! anti-entropy sync) goes through the shared store, wrapping the engine once at
+//! server start is enough to cover them all.
+//!
+//! ## Safety Valve
+//!
+//! If a bug ever lets the live tree drift from the store, `verify` rebuilds a
+//! tree from scratch and reports the differences, and `rebuild` replaces the
+//! live tree with the fresh one (`MERKLE VERIFY` / `MERKLE REBUILD`).
+//!
+//! ## Garbage Collection
+//!
+//! Deletes only stage leaf removals, so until the next root read the live
+//! tree still holds the nodes of deleted keys. `gc` (`MERKLE GC`, and the
+//! `merkle_gc` background task) drops them; `node_count` (`merkle_nodes` in
+//! `INFO`) makes growth observable.
+//!
+//! ## Node-Local Keys
+//!
+//! Keys rejected by the replication `KeyFilter` are never added to the tree,
+//! so anti-entropy sync does not try to copy them between nodes. A key
+//! written with `SET ... LOCAL` (`mark_local`) is left out the same way,
+//! until its next write of any other kind.
+//!
+//! ## Change Feed
+//!
+//! Every tracked write bumps a change counter published on a `watch` channel
+//! (`changes`), so root-change subscribers (`SUBSCRIBE MERKLE`) can sleep until
+//! something happened instead of polling the tree.
+
+use anyhow::Result;
+use std::collections::HashSet;
+use std::sync::{Arc, Mutex};
+use tokio::sync::watch;
+
+use super::key_hash::HashFn;
+use super::kv_trait::{KVEngineStoreTrait, ShardStats, StorageStats, TierStats, WalHealth};
+use super::merkle::MerkleTree;
+use crate::key_filter::KeyFilter;
+
+/// Handle to the live Merkle tree shared between the engine and the server.
+pub type SharedMerkle = Arc<Mutex<MerkleTree>>;
+
+/// Storage engine wrapper that maintains a live Merkle tree.
+pub struct MerkleTrackedEngine {
+    inner: Box<dyn KVEngineStoreTrait + Send + Sync>,
+    tree: SharedMerkle,
+    /// Number of tracked writes so far
+    changes: watch::Sender<u64>,
+    /// Keys outside the filter are node-local and left out of the tree
+    filter: KeyFilter,
+    /// Keys holding a `SET ... LOCAL` value, also left out
+    local: Mutex<HashSet<String>>,
+}
+
+impl MerkleTrackedEngine {
+    /// Wrap an engine, seeding the live tree from its current contents.
+    pub fn new(inner: Box<dyn KVEngineStoreTrait + Send + Sync>) -> Self {
+        let filter = KeyFilter::default();
+        let tree = Arc::new(Mutex::new(build_tree(inner.as_ref(), &filter, None)));
+        let (changes, _) = watch::channel(0);
+        Self { inner, tree, changes, filter, local: Mutex::new(HashSet::new()) }
+    }
+
+    /// Track only keys accepted by `filter`, reseeding the tree accordingly.
+    pub fn with_key_filter(mut self, filter: KeyFilter) -> Self {
+        let fresh = build_tree(self.inner.as_ref(), &filter, buckets(&self.tree));
+        *self.tree.lock().unwrap_or_else(|e| e.into_inner()) = fresh;
+        self.filter = filter;
+        self
+    }
+
+    /// Hash keys into `num_buckets` leaf buckets (see `MerkleTree::with_buckets`).
+    pub fn with_buckets(self, num_buckets: usize, hash_fn: HashFn) -> Self {
+        {
+            let mut tree = self.tree.lock().unwrap_or_else(|e| e.into_inner());
+            *tree = std::mem::replace(&mut *tree, MerkleTree::new()).with_buckets(num_buckets, hash_fn);
+            tree.refresh();
+        }
+        self
+    }
+
+    /// Handle to the live tree.
+    pub fn tree(&self) -> SharedMerkle {
+        Arc::clone(&self.tree)
+    }
+
+    /// Subscribe to the change counter, bumped after every tracked write.
+    pub fn changes(&self) -> watch::Receiver<u64> {
+        self.changes.subscribe()
+    }
+
+    fn with_tree<F: FnOnce(&mut MerkleTree)>(&self, f: F) {
+        {
+            let mut tree = self.tree.lock().unwrap_or_else(|e| e.into_inner());
+            f(&mut tree);
+        }
+        self.changes.send_modify(|n| *n += 1);
+    }
+
+    /// Apply a single-key tree update, unless the key is node-local. Any
+    /// such write ends a `SET ... LOCAL`.
+    fn with_key_tree<F: FnOnce(&mut MerkleTree)>(&self, key: &str, f: F) {
+        self.local_guard().remove(key);
+        if self.filter.replicates(key) {
+            self.with_tree(f);
+        }
+    }
+
+    fn local_guard(&self) -> std::sync::MutexGuard<'_, HashSet<String>> {
+        self.local.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn track_current(&self, key: &str) {
+        match self.inner.get(key) {
+            Some(value) => self.with_key_tree(key, |t| t.stage_insert(key, &value)),
+            None => self.with_key_tree(key, |t| t.stage_remove(key)),
+        }
+    }
+}
+
+impl KVEngineStoreTrait for MerkleTrackedEngine {
+    fn get(&self, key: &str) -> Option<String> {
+        self.inner.get(key)
+    }
+
+    fn set(&self, key: String, value: String) -> Result<()> {
+        self.inner.set(key.clone(), value.clone())?;
+        self.with_key_tree(&key, |t| t.stage_insert(&key, &value));
+        Ok(())
+    }
+
+    fn delete(&self, key: &str) -> bool {
+        let deleted = self.inner.delete(key);
+        if deleted {
+            self.with_key_tree(key, |t| t.stage_remove(key));
+        }
+        deleted
+    }
+
+    fn keys(&self) -> Vec<String> {
+        self.inner.keys()
+    }
+
+    fn scan(&self, prefix: &str) -> Vec<String> {
+        self.inner.scan(prefix)
+    }
+
+    fn ping(&self, message: &str) -> String {
+        self.inner.ping(message)
+    }
+
+    fn echo(&self, message: &str) -> String {
+        self.inner.echo(message)
+    }
+
+    fn exists(&self, key: &str) -> bool {
+        self.inner.exists(key)
+    }
+
+    fn memory_usage(&self) -> usize {
+        self.inner.memory_usage()
+    }
+
+    fn len(&self) -> usize {
+        self.inner.len()
+    }
+
+    fn dbsize(&self) -> usize {
+        self.inner.dbsize()
+    }
+
+    fn is_empty(&self) -> bool {
+        self.inner.is_empty()
+    }
+
+    fn increment(&self, key: &str, amount: Option<i64>) -> Result<i64> {
+        let value = self.inner.increment(key, amount)?;
+        self.track_current(key);
+        Ok(value)
+    }
+
+    fn decrement(&self, key: &str, amount: Option<i64>) -> Result<i64> {
+        let value = self.inner.decrement(key, amount)?;
+        self.track_current(key);
+        Ok(value)
+    }
+
+    fn append(&self, key: &str, value: &str) -> Result<String> {
+        let new_value = self.inner.append(key, value)?;
+        self.with_key_tree(key, |t| t.stage_insert(key, &new_value));
+        Ok(new_value)
+    }
+
+    fn prepend(&self, key: &str, value: &str) -> Result<String> {
+        let new_value = self.inner.prepend(key, value)?;
+        self.with_key_tree(key, |t| t.stage_insert(key, &new_value));
+        Ok(new_value)
+    }
+
+    fn truncate(&self) -> Result<()> {
+        self.inner.truncate()?;
+        self.local_guard().clear();
+        self.with_tree(|t| t.clear());
+        Ok(())
+    }
+
+    fn count_keys(&self) -> Result<u64> {
+        self.inner.count_keys()
+    }
+
+    fn sync(&self) -> Result<()> {
+        self.inner.sync()
+    }
+
+    fn sample(&self, limit: usize) -> Vec<(String, String)> {
+        self.inner.sample(limit)
+    }
+
+    fn random_key(&self) -> Option<String> {
+        self.inner.random_key()
+    }
+
+    fn touch(&self, key: &str) -> bool {
+        self.inner.touch(key)
+    }
+
+    fn compact_memory(&self, part: usize) -> bool {
+        self.inner.compact_memory(part)
+    }
+
+    fn storage_stats(&self) -> Option<StorageStats> {
+        self.inner.storage_stats()
+    }
+
+    fn shard_stats(&self) -> Option<Vec<ShardStats>> {
+        self.inner.shard_stats()
+    }
+
+    fn warm(&self, key: &str) -> Result<bool> {
+        self.inner.warm(key)
+    }
+
+    fn tier_stats(&self) -> Option<TierStats> {
+        self.inner.tier_stats()
+    }
+
+    fn compact_storage(&self) -> Result<bool> {
+        self.inner.compact_storage()
+    }
+
+    fn find_by_value_prefix(&self, prefix: &str) -> Option<Vec<String>> {
+        self.inner.find_by_value_prefix(prefix)
+    }
+
+    fn persistence(&self) -> Option<bool> {
+        self.inner.persistence()
+    }
+
+    fn wal_health(&self) -> Option<WalHealth> {
+        self.inner.wal_health()
+    }
+
+    fn durable(&self, key: &str) -> Option<bool> {
+        self.inner.durable(key)
+    }
+
+    fn log_expiry(&self, key: &str, expires_at_ms: Option<u64>) -> Result<()> {
+        self.inner.log_expiry(key, expires_at_ms)
+    }
+
+    fn logged_expiries(&self) -> Vec<(String, u64)> {
+        self.inner.logged_expiries()
+    }
+
+    fn mark_local(&self, key: &str) {
+        if self.inner.exists(key) {
+            self.with_key_tree(key, |t| t.stage_remove(key));
+            self.local_guard().insert(key.to_string());
+        }
+    }
+
+    fn is_local(&self, key: &str) -> bool {
+        self.local_guard().contains(key)
+    }
+
+    fn set_persistence(&self, enabled: bool) -> Result<()> {
+        self.inner.set_persistence(enabled)
+    }
+}
+
+/// Build a Merkle tree from scratch over every key in `store` accepted by
+/// `filter` and not `is_local`, with the given bucket layout (see `MerkleTree::buckets`).
+pub fn build_tree(store: &dyn KVEngineStoreTrait, filter: &KeyFilter, buckets: Option<(usize, HashFn)>) -> MerkleTree {
+    let mut tree = MerkleTree::new();
+    if let Some((num_buckets, hash_fn)) = buckets {
+        tree = tree.with_buckets(num_buckets, hash_fn);
+    }
+    for k in store.keys_sorted().into_iter().filter(|k| filter.replicates(k) && !store.is_local(k)) {
+        if let Some(v) = store.get(&k) {
+            tree.stage_insert(&k, &v);
+        }
+    }
+    tree.refresh();
+    tree
+}
+
+/// Outcome of comparing the live tree against a fresh rebuild.
+#[derive(Debug, Clone)]
+pub struct VerifyReport {
+    /// Number of keys in the freshly rebuilt tree
+    pub keys: usize,
+    /// Root of the live (incrementally maintained) tree
+    pub live_root: Option<Vec<u8>>,
+    /// Root of the tree rebuilt from the store
+    pub rebuilt_root: Option<Vec<u8>>,
+    /// Keys whose leaves differ between the two trees
+    pub diff_keys: Vec<String>,
+}
+
+impl VerifyReport {
+    /// Whether the live tree matches the store.
+    pub fn is_consistent(&self) -> bool {
+        self.live_root == self.rebuilt_root && self.diff_keys.is_empty()
+    }
+}
+
+/// Rebuild a tree from `store` and compare it with the live tree.
+///
+/// `filter` must be the one the live tree was built with.
+///
+/// The caller must hold the store lock so no writes race with the comparison.
+pub fn verify(store: &dyn KVEngineStoreTrait, live: &SharedMerkle, filter: &KeyFilter) -> VerifyReport {
+    let fresh = build_tree(store, filter, buckets(live));
+    let mut live = live.lock().unwrap_or_else(|e| e.into_inner());
+    live.refresh();
+    VerifyReport {
+        keys: fresh.len(),
+        live_root: live.get_root_hash().cloned(),
+        rebuilt_root: fresh.get_root_hash().cloned(),
+        diff_keys: live.diff_keys(&fresh),
+    }
+}
+
+/// Replace the live tree with one rebuilt from `store`. Returns the new key count.
+///
+/// The caller must hold the store lock so no writes race with the rebuild.
+pub fn rebuild(store: &dyn KVEngineStoreTrait, live: &SharedMerkle, filter: &KeyFilter) -> usize {
+    // Reading the store may purge expired keys, which takes the tree lock
+    let fresh = build_tree(store, filter, buckets(live));
+    let keys = fresh.len();
+    *live.lock().unwrap_or_else(|e| e.into_inner()) = fresh;
+    keys
+}
+
+/// Keys and values under the live tree node at `path` (see `MerkleTree::subtree`),
+/// with the node's hash hex-encoded. None if there is no node at `path`.
+///
+/// The caller must hold the store lock so the values match the tree.
+pub fn fetch_subtree(store: &dyn KVEngineStoreTrait, live: &SharedMerkle, path: &str) -> Option<(String, Vec<(String, String)>)> {
+    let (hash, keys) = {
+        let mut tree = live.lock().unwrap_or_else(|e| e.into_inner());
+        tree.refresh();
+        let node = tree.subtree(path)?;
+        (hex::encode(&node.hash), node.leaf_keys())
+    };
+    let pairs = keys.into_iter().filter_map(|k| store.get(&k).map(|v| (k, v))).collect();
+    Some((hash, pairs))
+}
+
+/// Reclaim the live tree's orphaned nodes (see `MerkleTree::gc`).
+///
+/// # Returns
+/// * `(usize, usize)` - Nodes reclaimed and nodes left
+pub fn gc(live: &SharedMerkle) -> (usize, usize) {
+    let mut tree = live.lock().unwrap_or_else(|e| e.into_inner());
+    let reclaimed = tree.gc();
+    (reclaimed, tree.node_count())
+}
+
+/// Bucket layout of the live tree (see `MerkleTree::buckets`).
+pub fn buckets(live: &SharedMerkle) -> Option<(usize, HashFn)> {
+    live.lock().unwrap_or_else(|e| e.into_inner()).buckets()
+}
+
+/// Nodes (internal and leaves) currently held by the live tree.
+pub fn node_count(live: &SharedMerkle) -> usize {
+    live.lock().unwrap_or_else(|e| e.into_inner()).node_count()
+}
+
+/// Current root of the live tree, hex-encoded (see `root_hex`).
+pub fn live_root_hex(live: &SharedMerkle) -> String {
+    let mut tree = live.lock().unwrap_or_else(|e| e.into_inner());
+    tree.refresh();
+    root_hex(tree.get_root_hash())
+}
+
+/// Hex-encode a root hash; the empty tree is 64 zeros (same sentinel as `HASH`).
+pub fn root_hex(root: Option<&Vec<u8>>) -> String {
+    match root {
+        Some(h) => hex::encode(h),
+        None => "0".repeat(64),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::store::RwLockEngine;
+
+    fn tracked() -> MerkleTrackedEngine {
+        MerkleTrackedEngine::new(Box::new(RwLockEngine::new("unused").unwrap()))
+    }
+
+    #[test]
+    fn test_live_tree_follows_all_writes() {
+        let engine = tracked();
+        engine.set("a".to_string(), "1".to_string()).unwrap();
+        engine.set("b".to_string(), "x".to_string()).unwrap();
+        engine.set("c".to_string(), "gone".to_string()).unwrap();
+        engine.increment("a", Some(4)).unwrap();
+        engine.decrement("n", None).unwrap();
+        engine.append("b", "y").unwrap();
+        engine.prepend("b", "w").unwrap();
+        engine.delete("c");
+
+        let tree = engine.tree();
+        let report = verify(&engine, &tree, &KeyFilter::default());
+        assert!(report.is_consistent(), "{:?}", report);
+        assert_eq!(report.keys, 3);
+
+        engine.truncate().unwrap();
+        assert!(verify(&engine, &tree, &KeyFilter::default()).is_consistent());
+        assert!(tree.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_seeds_from_existing_contents() {
+        let inner = RwLockEngine::new("unused").unwrap();
+        inner.set("k1".to_string(), "v1".to_string()).unwrap();
+        inner.set("k2".to_string(), "v2".to_string()).unwrap();
+        let engine = MerkleTrackedEngine::new(Box::new(inner));
+        let tree = engine.tree();
+        assert_eq!(tree.lock().unwrap().len(), 2);
+        assert!(verify(&engine, &tree, &KeyFilter::default()).is_consistent());
+    }
+
+    #[test]
+    fn test_local_value_stays_out_of_the_tree_until_its_next_write() {
+        let engine = tracked();
+        let tree = engine.tree();
+        engine.set("shared".to_string(), "1".to_string()).unwrap();
+        let root = live_root_hex(&tree);
+
+        engine.set("scratch".to_string(), "7".to_string()).unwrap();
+        engine.mark_local("scratch");
+        engine.mark_local("missing");
+        assert!(engine.is_local("scratch") && !engine.is_local("missing"));
+        assert_eq!(live_root_hex(&tree), root);
+        assert!(verify(&engine, &tree, &KeyFilter::default()).is_consistent());
+
+        // A write of another kind makes the key replicated again
+        engine.increment("scratch", None).unwrap();
+        assert!(!engine.is_local("scratch"));
+        assert_ne!(live_root_hex(&tree), root);
+        let report = verify(&engine, &tree, &KeyFilter::default());
+        assert!(report.is_consistent() && report.keys == 2, "{:?}", report);
+    }
+
+    #[test]
+    fn test_verify_detects_corruption_and_rebuild_repairs() {
+        let engine = tracked();
+        for i in 0..10 {
+            engine.set(format!("key{}", i), format!("val{}", i)).unwrap();
+        }
+        let tree = engine.tree();
+        tree.lock().unwrap().corrupt_leaf("key3");
+
+        let report = verify(&engine, &tree, &KeyFilter::default());
+        assert!(!report.is_consistent());
+        assert_ne!(report.live_root, report.rebuilt_root);
+        assert_eq!(report.diff_keys, vec!["key3".to_string()]);
+
+        assert_eq!(rebuild(&engine, &tree, &KeyFilter::default()), 10);
+        assert!(verify(&engine, &tree, &KeyFilter::default()).is_consistent());
+    }
+
+    #[test]
+    fn test_change_feed_counts_writes() {
+        let engine = tracked();
+        let mut changes = engine.changes();
+        let empty_root = live_root_hex(&engine.tree());
+        engine.set("a".to_string(), "1".to_string()).unwrap();
+        engine.append("a", "2").unwrap();
+        // Failed or no-op writes are not reported
+        assert!(!engine.delete("missing"));
+        assert!(changes.has_changed().unwrap());
+        assert_eq!(*changes.borrow_and_update(), 2);
+        assert_ne!(live_root_hex(&engine.tree()), empty_root);
+    }
+
+    #[test]
+    fn test_node_local_keys_stay_out_of_tree() {
+        let inner = RwLockEngine::new("unused").unwrap();
+        inner.set("local:seed".to_string(), "v".to_string()).unwrap();
+        let filter = KeyFilter::new(vec![], vec!["local:".to_string()]);
+        let engine = MerkleTrackedEngine::new(Box::new(inner)).with_key_filter(filter.clone());
+        let tree = engine.tree();
+        let empty_root = live_root_hex(&tree);
+
+        engine.set("local:cache".to_string(), "1".to_string()).unwrap();
+        engine.increment("local:hits", None).unwrap();
+        engine.delete("local:seed");
+        assert_eq!(live_root_hex(&tree), empty_root);
+
+        engine.set("user:1".to_string(), "ann".to_string()).unwrap();
+        let report = verify(&engine, &tree, &filter);
+        assert!(report.is_consistent(), "{:?}", report);
+        assert_eq!(report.keys, 1);
+    }
+
+    #[test]
+    fn test_root_hex_empty_sentinel() {
+        assert_eq!(root_hex(None), "0".repeat(64));
+        assert_eq!(root_hex(Some(&vec![0xab, 0x01])), "ab01");
+    }
+
+    #[test]
+    fn test_gc_returns_node_count_to_baseline_after_deletes() {
+        let engine = tracked();
+        for i in 0..10 {
+            engine.set(format!("keep{}", i), "v".to_string()).unwrap();
+        }
+        let tree = engine.tree();
+        live_root_hex(&tree);
+        let baseline = node_count(&tree);
+        assert_eq!(baseline, 19);
+
+        for i in 0..1000 {
+            engine.set(format!("tmp{}", i), "v".to_string()).unwrap();
+        }
+        live_root_hex(&tree);
+        assert_eq!(node_count(&tree), 2 * 1010 - 1);
+        for i in 0..1000 {
+            assert!(engine.delete(&format!("tmp{}", i)));
+        }
+        // The deleted keys' nodes stay until something rebuilds the tree
+        assert_eq!(node_count(&tree), 2 * 1010 - 1);
+
+        assert_eq!(gc(&tree), (2 * 1010 - 1 - baseline, baseline));
+        assert_eq!(gc(&tree), (0, baseline), "nothing left to reclaim");
+        assert!(verify(&engine, &tree, &KeyFilter::default()).is_consistent());
+    }
+}
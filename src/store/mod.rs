@@ -3,9 +3,22 @@
 //! This module contains the storage components for MerkleKV:
 //!
 //! - **`kv_trait`**: Common interface for all storage engines
-//! - **`rwlock_engine`**: Thread-safe in-memory storage using RwLock<HashMap>
+//! - **`key_hash`**: Configurable key hash used for shard and bucket placement
+//! - **`rwlock_engine`**: Thread-safe in-memory storage using sharded RwLock<HashMap>
 //! - **`kv_engine`**: Non-thread-safe in-memory storage using Arc<HashMap>
-//! - **`merkle`**: Merkle tree implementation for efficient synchronization
+//! - **`merkle`**: Merkle tree implementation for efficient synchronization, optionally bucketed
+//! - **`expiring`**: Engine wrapper adding per-key TTLs with lazy expiry
+//! - **`field_map`**: Hash-like values for HSET / HGET / HGETALL
+//! - **`json_doc`**: Numbers inside JSON values (`JSON.INCR`)
+//! - **`histogram`**: Sampled key length / value size histograms
+//! - **`merkle_tracked`**: Engine wrapper that keeps a live Merkle tree up to date
+//! - **`timestamps`**: Engine wrapper that records key creation and modification times
+//! - **`tombstones`**: Engine wrapper that keeps deleted keys for a GC grace period
+//! - **`wal`**: Write-ahead log record encoding (bincode or MessagePack) and file framing
+//! - **`wal_engine`**: Engine wrapper that logs writes to a WAL and replays it on start
+//! - **`value_index`**: Engine wrapper that indexes value prefixes (`FINDBYVALUE`)
+//! - **`tiered`**: Engine wrapper that spills least recently used keys to a cold on-disk tier
+//! - **`test_engine`**: In-memory engine for tests that counts and fails syncs (tests only)
 //!
 //! ## Design Philosophy
 //!
@@ -21,14 +34,36 @@
 //! - Add support for range queries and iteration
 //! - Optimize Merkle tree for incremental updates
 
+pub mod expiring;
+pub mod field_map;
+pub mod histogram;
+pub mod json_doc;
+pub mod key_hash;
 pub mod kv_engine;
 pub mod kv_trait;
 pub mod merkle;
+pub mod merkle_tracked;
 pub mod rwlock_engine;
 pub mod sled_engine;
+#[cfg(test)]
+pub mod test_engine;
+pub mod tiered;
+pub mod timestamps;
+pub mod tombstones;
+pub mod value_index;
+pub mod wal;
+pub mod wal_engine;
 
 // Re-export the trait and engines for convenience
+pub use expiring::ExpiringEngine;
+pub use key_hash::HashFn;
 pub use kv_engine::KvEngine;
-pub use kv_trait::KVEngineStoreTrait;
+pub use kv_trait::{KVEngineStoreTrait, StorageStats};
+pub use merkle_tracked::{MerkleTrackedEngine, SharedMerkle};
 pub use rwlock_engine::RwLockEngine;
 pub use sled_engine::SledEngine;
+pub use tiered::TieredEngine;
+pub use timestamps::TimestampEngine;
+pub use tombstones::TombstoneEngine;
+pub use value_index::ValueIndexEngine;
+pub use wal_engine::WalEngine;
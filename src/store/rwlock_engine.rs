@@ -1,16 +1,39 @@
 //! # Thread-Safe Key-Value Storage Engine
 //!
-//! This module provides a thread-safe in-memory storage engine using RwLock<HashMap>.
+//! This module provides a thread-safe in-memory storage engine using sharded RwLock<HashMap>.
 //! Implements the `KVEngineStoreTrait` interface for consistent API across all engines.
 //!
 //! ## Thread Safety Implementation
 //!
-//! The current implementation uses `RwLock<HashMap<String, String>>` for thread-safe access:
+//! The keyspace is split across a fixed number of `RwLock<HashMap<String, String>>` shards.
+//! A key's shard is chosen by the configured `HashFn` (`storage.hash_fn`):
 //! - **Multiple concurrent readers**: Multiple threads can read simultaneously
-//! - **Single writer**: Only one thread can write at a time
+//! - **Single writer per shard**: Writes to different shards proceed in parallel
 //! - **No race conditions**: All operations are properly synchronized
 //! - **Efficient**: Readers don't block each other, only writers block
 //!
+//! ## Per-Key Lock Striping (optional)
+//!
+//! With `storage.lock_stripes > 0` every write first takes one lock from a
+//! fixed pool of stripe mutexes chosen by key hash. The stripe serializes writes
+//! to the same key; the shard lock is then only taken for the map access itself
+//! (a read lock to fetch the current value, a write lock to store the new one).
+//! Read-modify-write commands (`INCR`, `APPEND`, ...) therefore parse and build
+//! their new values concurrently for different keys of the same shard, instead
+//! of doing all of that work under the shard's exclusive lock.
+//!
+//! Tradeoffs:
+//! - Each write takes two locks instead of one; plain `SET`/`DELETE` get slightly
+//!   slower when there is no contention
+//! - The map insert itself is still exclusive per shard, so the gain grows with
+//!   the cost of computing the new value (large appends), not with plain SETs
+//! - Keys that share a stripe serialize with each other (more stripes, fewer
+//!   false conflicts; each stripe is a single `Mutex<()>`)
+//! - `TRUNCATE` does not take stripes: a write racing with it may land just
+//!   after the clear, as if it had been issued after the TRUNCATE
+//! - Merkle tree updates are unaffected: `MerkleTrackedEngine` guards its tree
+//!   with its own lock
+//!
 //! ## Future Implementation Plans
 //!
 //! This is a production-ready in-memory implementation. Future versions could:
@@ -22,24 +45,36 @@
 
 use anyhow::Result;
 use std::collections::HashMap;
-use std::sync::{Arc, RwLock};
+use std::sync::{Arc, Mutex, MutexGuard, RwLock};
+
+use super::key_hash::HashFn;
+use super::kv_trait::{random_index, KVEngineStoreTrait, ShardStats};
 
-use super::kv_trait::KVEngineStoreTrait;
+/// Number of shards used by the engine.
+pub const DEFAULT_SHARD_COUNT: usize = 16;
+
+type Shard = RwLock<HashMap<String, String>>;
 
 /// Thread-safe in-memory key-value storage engine.
 ///
-/// This implementation uses `RwLock<HashMap>` to provide thread-safe access:
+/// This implementation uses sharded `RwLock<HashMap>` to provide thread-safe access:
 /// - Multiple threads can read simultaneously (shared read lock)
-/// - Only one thread can write at a time (exclusive write lock)
+/// - Only one thread can write to a shard at a time (exclusive write lock)
 /// - All operations are atomic and race-condition free
 ///
 /// **Note**: This implementation is not persistent! All data is lost when
 /// the process terminates.
 #[derive(Clone)]
 pub struct RwLockEngine {
-    /// Thread-safe shared reference to the key-value data
-    /// Using RwLock allows multiple readers or a single writer
-    data: Arc<RwLock<HashMap<String, String>>>,
+    /// Thread-safe shared reference to the key-value shards
+    /// Using RwLock allows multiple readers or a single writer per shard
+    shards: Arc<Vec<Shard>>,
+
+    /// Hash function that places keys into shards
+    hash_fn: HashFn,
+
+    /// Per-key write lock stripes; empty when striping is disabled
+    stripes: Arc<Vec<Mutex<()>>>,
     // TODO: Add persistent storage implementation
     // In a real implementation, this would use a persistent storage engine like Sled:
     // storage_path: PathBuf,
@@ -47,7 +82,7 @@ pub struct RwLockEngine {
 }
 
 impl RwLockEngine {
-    /// Create a new storage engine instance.
+    /// Create a new storage engine instance using the default hash function.
     ///
     /// # Arguments
     /// * `_storage_path` - Path where data should be stored (currently unused)
@@ -57,16 +92,85 @@ impl RwLockEngine {
     ///
     /// # Thread Safety
     /// The returned engine is safe to share across multiple threads.
+    #[allow(dead_code)]
     pub fn new(_storage_path: &str) -> Result<Self> {
+        Self::with_hash_fn(_storage_path, HashFn::default())
+    }
+
+    /// Create a new storage engine instance that shards keys with `hash_fn`.
+    pub fn with_hash_fn(_storage_path: &str, hash_fn: HashFn) -> Result<Self> {
         // TODO: Initialize persistent storage engine here
         // For example, with Sled:
         // let db = sled::open(storage_path)?;
         // Ok(Self { storage_path: storage_path.into(), sled_db: db })
 
+        let shards = (0..DEFAULT_SHARD_COUNT)
+            .map(|_| RwLock::new(HashMap::new()))
+            .collect();
         Ok(Self {
-            data: Arc::new(RwLock::new(HashMap::new())),
+            shards: Arc::new(shards),
+            hash_fn,
+            stripes: Arc::new(Vec::new()),
         })
     }
+
+    /// Enable per-key lock striping with `stripes` locks (`0` disables it).
+    pub fn with_lock_stripes(mut self, stripes: usize) -> Self {
+        self.stripes = Arc::new((0..stripes).map(|_| Mutex::new(())).collect());
+        self
+    }
+
+    /// Number of lock stripes (`0` when striping is disabled).
+    pub fn lock_stripes(&self) -> usize {
+        self.stripes.len()
+    }
+
+    /// Index of the shard holding `key`.
+    pub fn shard_index(&self, key: &str) -> usize {
+        self.hash_fn.slot(key, self.shards.len())
+    }
+
+    /// The shard holding `key`.
+    fn shard(&self, key: &str) -> &Shard {
+        &self.shards[self.shard_index(key)]
+    }
+
+    /// Lock the stripe of `key`, or `None` when striping is disabled.
+    fn stripe(&self, key: &str) -> Option<MutexGuard<'_, ()>> {
+        if self.stripes.is_empty() {
+            return None;
+        }
+        let index = self.hash_fn.slot(key, self.stripes.len());
+        Some(self.stripes[index].lock().unwrap())
+    }
+
+    /// Read-modify-write `key`: `update` maps the current value to the new
+    /// value plus the result returned to the caller.
+    ///
+    /// Without striping this runs entirely under the shard's write lock. With
+    /// striping the key's stripe is held throughout, and `update` runs without
+    /// any shard lock.
+    fn update<T>(
+        &self,
+        key: &str,
+        update: impl FnOnce(Option<&String>) -> Result<(String, T)>,
+    ) -> Result<T> {
+        let shard = self.shard(key);
+        match self.stripe(key) {
+            None => {
+                let mut data = shard.write().unwrap();
+                let (new_value, out) = update(data.get(key))?;
+                data.insert(key.to_string(), new_value);
+                Ok(out)
+            }
+            Some(_stripe) => {
+                let current = shard.read().unwrap().get(key).cloned();
+                let (new_value, out) = update(current.as_ref())?;
+                shard.write().unwrap().insert(key.to_string(), new_value);
+                Ok(out)
+            }
+        }
+    }
 }
 
 impl KVEngineStoreTrait for RwLockEngine {
@@ -93,7 +197,7 @@ impl KVEngineStoreTrait for RwLockEngine {
     /// ```
     fn get(&self, key: &str) -> Option<String> {
         // Acquire shared read lock - multiple readers can proceed simultaneously
-        let data = self.data.read().unwrap();
+        let data = self.shard(key).read().unwrap();
         data.get(key).cloned()
     }
 
@@ -116,8 +220,9 @@ impl KVEngineStoreTrait for RwLockEngine {
     /// engine.set("user:123".to_string(), "john_doe".to_string());
     /// ```
     fn set(&self, key: String, value: String) -> Result<()> {
-        // Acquire exclusive write lock - only one writer at a time
-        let mut data = self.data.write().unwrap();
+        let _stripe = self.stripe(&key);
+        // Acquire exclusive write lock - only one writer per shard at a time
+        let mut data = self.shard(&key).write().unwrap();
         data.insert(key, value);
         Ok(())
     }
@@ -145,8 +250,9 @@ impl KVEngineStoreTrait for RwLockEngine {
     /// }
     /// ```
     fn delete(&self, key: &str) -> bool {
-        // Acquire exclusive write lock - only one writer at a time
-        let mut data = self.data.write().unwrap();
+        let _stripe = self.stripe(key);
+        // Acquire exclusive write lock - only one writer per shard at a time
+        let mut data = self.shard(key).write().unwrap();
         data.remove(key).is_some()
     }
 
@@ -164,9 +270,12 @@ impl KVEngineStoreTrait for RwLockEngine {
     /// This operation creates a new vector. In a production system, consider
     /// using an iterator-based approach for better memory efficiency.
     fn keys(&self) -> Vec<String> {
-        // Acquire shared read lock - multiple readers can proceed simultaneously
-        let data = self.data.read().unwrap();
-        data.keys().cloned().collect()
+        // Acquire shared read locks shard by shard
+        let mut keys = Vec::new();
+        for shard in self.shards.iter() {
+            keys.extend(shard.read().unwrap().keys().cloned());
+        }
+        keys
     }
 
     /// Get the number of key-value pairs in the store.
@@ -177,21 +286,19 @@ impl KVEngineStoreTrait for RwLockEngine {
     /// # Thread Safety
     /// Multiple threads can call this method concurrently without issues.
     fn len(&self) -> usize {
-        let data = self.data.read().unwrap();
-        data.len()
+        self.shards.iter().map(|shard| shard.read().unwrap().len()).sum()
     }
     fn scan(&self, prefix: &str) -> Vec<String> {
-
-        let data = self.data.read().unwrap();
-
         if prefix.is_empty() {
-            return data.keys().cloned().collect();
+            return self.keys();
         }
 
-        data.keys()
-            .filter(|k| k.starts_with(prefix))
-            .cloned()
-            .collect()
+        let mut keys = Vec::new();
+        for shard in self.shards.iter() {
+            let data = shard.read().unwrap();
+            keys.extend(data.keys().filter(|k| k.starts_with(prefix)).cloned());
+        }
+        keys
     }
     fn ping(&self, message: &str) -> String {
         format!("PONG {}", message)
@@ -202,24 +309,24 @@ impl KVEngineStoreTrait for RwLockEngine {
     }
 
     fn dbsize(&self) -> usize {
-        let data = self.data.read().unwrap();
-        data.len()
+        self.len()
     }
     
     fn exists(&self, key: &str) -> bool {
-        let data = self.data.read().unwrap();
+        let data = self.shard(key).read().unwrap();
         data.contains_key(key)
     }
 
     fn memory_usage(&self) -> usize {
-        // Rough estimate: size of HashMap + sizes of keys and values
-        let map = self.data.read().unwrap();
-        let mut size = std::mem::size_of_val(&*map);
-        for (k, v) in map.iter() {
-            size += std::mem::size_of_val(k) + k.len();
-            size += std::mem::size_of_val(v) + v.len();
-        }
-        size
+        self.shards.iter().map(|shard| shard_memory(&shard.read().unwrap())).sum()
+    }
+
+    fn shard_stats(&self) -> Option<Vec<ShardStats>> {
+        let stats = self.shards.iter().map(|shard| {
+            let map = shard.read().unwrap();
+            ShardStats { keys: map.len(), memory_bytes: shard_memory(&map) }
+        });
+        Some(stats.collect())
     }
     /// Check if the store is empty.
     ///
@@ -229,8 +336,7 @@ impl KVEngineStoreTrait for RwLockEngine {
     /// # Thread Safety
     /// Multiple threads can call this method concurrently without issues.
     fn is_empty(&self) -> bool {
-        let data = self.data.read().unwrap();
-        data.is_empty()
+        self.shards.iter().all(|shard| shard.read().unwrap().is_empty())
     }
     
     /// Increment a numeric value.
@@ -250,30 +356,25 @@ impl KVEngineStoreTrait for RwLockEngine {
     /// Only one thread can increment at a time. Other threads will wait for the
     /// write lock to be released.
     fn increment(&self, key: &str, amount: Option<i64>) -> Result<i64> {
-        // Acquire exclusive write lock
-        let mut data = self.data.write().unwrap();
-        
         // Default increment amount is 1
         let increment_by = amount.unwrap_or(1);
-        
-        // Get the current value or initialize to 0
-        let current_value = match data.get(key) {
-            Some(value) => {
-                // Try to parse the current value as a number
-                value.parse::<i64>().map_err(|_| {
-                    anyhow::anyhow!("Value for key '{}' is not a valid number", key)
-                })?
-            }
-            None => 0, // Key doesn't exist, start from 0
-        };
-        
-        // Calculate the new value
-        let new_value = current_value + increment_by;
-        
-        // Store the new value
-        data.insert(key.to_string(), new_value.to_string());
-        
-        Ok(new_value)
+
+        self.update(key, |current| {
+            // Get the current value or initialize to 0
+            let current_value = match current {
+                Some(value) => {
+                    // Try to parse the current value as a number
+                    value.parse::<i64>().map_err(|_| {
+                        anyhow::anyhow!("Value for key '{}' is not a valid number", key)
+                    })?
+                }
+                None => 0, // Key doesn't exist, start from 0
+            };
+
+            // Calculate and store the new value
+            let new_value = current_value + increment_by;
+            Ok((new_value.to_string(), new_value))
+        })
     }
     
     /// Decrement a numeric value.
@@ -293,30 +394,25 @@ impl KVEngineStoreTrait for RwLockEngine {
     /// Only one thread can decrement at a time. Other threads will wait for the
     /// write lock to be released.
     fn decrement(&self, key: &str, amount: Option<i64>) -> Result<i64> {
-        // Acquire exclusive write lock
-        let mut data = self.data.write().unwrap();
-        
         // Default decrement amount is 1
         let decrement_by = amount.unwrap_or(1);
-        
-        // Get the current value or initialize to 0
-        let current_value = match data.get(key) {
-            Some(value) => {
-                // Try to parse the current value as a number
-                value.parse::<i64>().map_err(|_| {
-                    anyhow::anyhow!("Value for key '{}' is not a valid number", key)
-                })?
-            }
-            None => 0, // Key doesn't exist, start from 0
-        };
-        
-        // Calculate the new value
-        let new_value = current_value - decrement_by;
-        
-        // Store the new value
-        data.insert(key.to_string(), new_value.to_string());
-        
-        Ok(new_value)
+
+        self.update(key, |current| {
+            // Get the current value or initialize to 0
+            let current_value = match current {
+                Some(value) => {
+                    // Try to parse the current value as a number
+                    value.parse::<i64>().map_err(|_| {
+                        anyhow::anyhow!("Value for key '{}' is not a valid number", key)
+                    })?
+                }
+                None => 0, // Key doesn't exist, start from 0
+            };
+
+            // Calculate and store the new value
+            let new_value = current_value - decrement_by;
+            Ok((new_value.to_string(), new_value))
+        })
     }
     
     /// Append a value to an existing string.
@@ -335,23 +431,14 @@ impl KVEngineStoreTrait for RwLockEngine {
     /// Only one thread can append at a time. Other threads will wait for the
     /// write lock to be released.
     fn append(&self, key: &str, value: &str) -> Result<String> {
-        // Acquire exclusive write lock
-        let mut data = self.data.write().unwrap();
-        
-        // Check if the key exists
-        if let Some(current_value) = data.get(key) {
-            // Append the new value
-            let new_value = format!("{}{}", current_value, value);
-            
-            // Store the new value
-            data.insert(key.to_string(), new_value.clone());
-            
-            Ok(new_value)
-        } else {
-            // Key doesn't exist, create it with the value
-            data.insert(key.to_string(), value.to_string());
-            Ok(value.to_string())
-        }
+        self.update(key, |current| {
+            // Append to the existing value, or create the key with the value
+            let new_value = match current {
+                Some(current_value) => format!("{}{}", current_value, value),
+                None => value.to_string(),
+            };
+            Ok((new_value.clone(), new_value))
+        })
     }
     
     /// Prepend a value to an existing string.
@@ -370,23 +457,14 @@ impl KVEngineStoreTrait for RwLockEngine {
     /// Only one thread can prepend at a time. Other threads will wait for the
     /// write lock to be released.
     fn prepend(&self, key: &str, value: &str) -> Result<String> {
-        // Acquire exclusive write lock
-        let mut data = self.data.write().unwrap();
-        
-        // Check if the key exists
-        if let Some(current_value) = data.get(key) {
-            // Prepend the new value
-            let new_value = format!("{}{}", value, current_value);
-            
-            // Store the new value
-            data.insert(key.to_string(), new_value.clone());
-            
-            Ok(new_value)
-        } else {
-            // Key doesn't exist, create it with the value
-            data.insert(key.to_string(), value.to_string());
-            Ok(value.to_string())
-        }
+        self.update(key, |current| {
+            // Prepend to the existing value, or create the key with the value
+            let new_value = match current {
+                Some(current_value) => format!("{}{}", value, current_value),
+                None => value.to_string(),
+            };
+            Ok((new_value.clone(), new_value))
+        })
     }
     
     /// Clear all keys/values in the store.
@@ -400,11 +478,10 @@ impl KVEngineStoreTrait for RwLockEngine {
     /// Only one thread can truncate at a time. Other threads will wait for the
     /// write lock to be released.
     fn truncate(&self) -> Result<()> {
-        // Acquire exclusive write lock
-        let mut data = self.data.write().unwrap();
-        
-        // Clear all entries
-        data.clear();
+        // Acquire exclusive write locks and clear all entries shard by shard
+        for shard in self.shards.iter() {
+            shard.write().unwrap().clear();
+        }
         
         Ok(())
     }
@@ -417,8 +494,7 @@ impl KVEngineStoreTrait for RwLockEngine {
     /// # Thread Safety
     /// Multiple threads can call this method concurrently without issues.
     fn count_keys(&self) -> Result<u64> {
-        let data = self.data.read().unwrap();
-        Ok(data.len() as u64)
+        Ok(self.len() as u64)
     }
     
     /// Force synchronization of pending changes to persistent storage.
@@ -434,6 +510,66 @@ impl KVEngineStoreTrait for RwLockEngine {
         // In a persistent storage engine, this would flush data to disk
         Ok(())
     }
+
+    /// Compact one shard at a time; only that shard is write-locked.
+    fn compact_memory(&self, part: usize) -> bool {
+        if let Some(shard) = self.shards.get(part) {
+            shard.write().unwrap().shrink_to_fit();
+        }
+        part + 1 < self.shards.len()
+    }
+
+    /// Sample evenly across shards so the result is not biased to one shard.
+    fn sample(&self, limit: usize) -> Vec<(String, String)> {
+        let per_shard = limit.div_ceil(self.shards.len());
+        let mut out = Vec::with_capacity(limit);
+        for shard in self.shards.iter() {
+            let remaining = limit - out.len();
+            if remaining == 0 {
+                break;
+            }
+            let data = shard.read().unwrap();
+            out.extend(
+                data.iter()
+                    .take(per_shard.min(remaining))
+                    .map(|(k, v)| (k.clone(), v.clone())),
+            );
+        }
+        out
+    }
+
+    /// Pick a position among all keys by shard sizes, then walk that shard only.
+    fn random_key(&self) -> Option<String> {
+        loop {
+            let lens: Vec<usize> = self.shards.iter().map(|shard| shard.read().unwrap().len()).collect();
+            let total: usize = lens.iter().sum();
+            if total == 0 {
+                return None;
+            }
+            let mut index = random_index(total);
+            let shard = lens.iter().position(|&len| {
+                let here = index < len;
+                if !here {
+                    index -= len;
+                }
+                here
+            })?;
+            if let Some(key) = self.shards[shard].read().unwrap().keys().nth(index) {
+                return Some(key.clone());
+            }
+            // The shard shrank since it was counted; draw again
+        }
+    }
+}
+
+/// Rough estimate: size of the HashMap and its allocated slots + sizes of keys and values
+fn shard_memory(map: &HashMap<String, String>) -> usize {
+    let mut size = std::mem::size_of_val(map) + map.capacity() * std::mem::size_of::<(String, String)>();
+    for (k, v) in map.iter() {
+        size += std::mem::size_of_val(k) + k.len();
+        size += std::mem::size_of_val(v) + v.len();
+    }
+    size
 }
 
 #[cfg(test)]
@@ -498,7 +634,7 @@ mod tests {
 
         // Spawn multiple reader threads
         let mut handles = vec![];
-        for i in 0..10 {
+        for _ in 0..10 {
             let engine_clone = engine.clone();
             let handle = thread::spawn(move || {
                 for _ in 0..100 {
@@ -591,4 +727,132 @@ mod tests {
         // Final verification
         assert_eq!(engine.len(), 100);
     }
+
+    #[test]
+    fn test_hash_fns_round_trip_all_keys() {
+        for hash_fn in [HashFn::Xxhash, HashFn::Siphash, HashFn::Fnv] {
+            let engine = RwLockEngine::with_hash_fn("./test_data", hash_fn).unwrap();
+            for i in 0..500 {
+                engine.set(format!("key:{}", i), format!("value:{}", i)).unwrap();
+            }
+
+            assert_eq!(engine.len(), 500);
+            for i in 0..500 {
+                assert_eq!(engine.get(&format!("key:{}", i)), Some(format!("value:{}", i)));
+            }
+            let mut keys = engine.keys();
+            keys.sort();
+            let mut expected: Vec<String> = (0..500).map(|i| format!("key:{}", i)).collect();
+            expected.sort();
+            assert_eq!(keys, expected);
+        }
+    }
+
+    #[test]
+    fn test_shard_placement_is_consistent() {
+        let engine = RwLockEngine::with_hash_fn("./test_data", HashFn::Fnv).unwrap();
+        assert_eq!(engine.hash_fn, HashFn::Fnv);
+
+        for i in 0..200 {
+            let key = format!("user:{}", i);
+            let shard = engine.shard_index(&key);
+            assert_eq!(shard, HashFn::Fnv.slot(&key, DEFAULT_SHARD_COUNT));
+
+            // Writes, updates and reads for a key always land in the same shard
+            engine.set(key.clone(), "a".to_string()).unwrap();
+            engine.append(&key, "b").unwrap();
+            assert_eq!(engine.shard_index(&key), shard);
+            assert!(engine.shards[shard].read().unwrap().contains_key(&key));
+            assert_eq!(engine.get(&key), Some("ab".to_string()));
+        }
+
+        assert!(engine.delete("user:0"));
+        assert!(!engine.exists("user:0"));
+        assert_eq!(engine.len(), 199);
+    }
+
+    #[test]
+    fn test_lock_striping_concurrent_writers() {
+        let engine = Arc::new(RwLockEngine::new("./test_data").unwrap().with_lock_stripes(256));
+        assert_eq!(engine.lock_stripes(), 256);
+
+        // Many threads: distinct keys (most sharing shards) plus one hot counter
+        let threads = 8;
+        let per_thread = 500;
+        let start = std::time::Instant::now();
+        let handles: Vec<_> = (0..threads)
+            .map(|t| {
+                let engine = engine.clone();
+                thread::spawn(move || {
+                    for i in 0..per_thread {
+                        let key = format!("key_{}_{}", t, i);
+                        engine.set(key.clone(), "a".to_string()).unwrap();
+                        engine.append(&key, "b").unwrap();
+                        engine.prepend(&key, "z").unwrap();
+                        engine.increment("hot", None).unwrap();
+                    }
+                })
+            })
+            .collect();
+        for handle in handles {
+            handle.join().unwrap();
+        }
+        let elapsed = start.elapsed();
+
+        // Every key written exactly once, every RMW applied, no lost increments
+        assert_eq!(engine.len(), threads * per_thread + 1);
+        for t in 0..threads {
+            for i in 0..per_thread {
+                assert_eq!(engine.get(&format!("key_{}_{}", t, i)), Some("zab".to_string()));
+            }
+        }
+        assert_eq!(engine.get("hot"), Some((threads * per_thread).to_string()));
+        // Generous bound: catches lock convoys, not a benchmark
+        assert!(elapsed < std::time::Duration::from_secs(10), "took {:?}", elapsed);
+    }
+
+    #[test]
+    fn test_lock_striping_keeps_semantics() {
+        let engine = RwLockEngine::new("./test_data").unwrap().with_lock_stripes(4);
+        assert_eq!(engine.decrement("n", Some(3)).unwrap(), -3);
+        engine.set("s".to_string(), "x".to_string()).unwrap();
+        assert!(engine.increment("s", None).is_err());
+        // A failed update leaves the value untouched
+        assert_eq!(engine.get("s"), Some("x".to_string()));
+        assert!(engine.delete("s"));
+        assert_eq!(RwLockEngine::new("./test_data").unwrap().lock_stripes(), 0);
+    }
+
+    #[test]
+    fn test_shard_stats_cover_every_key() {
+        let engine = RwLockEngine::with_hash_fn("./test_data", HashFn::Xxhash).unwrap();
+        for i in 0..1000 {
+            engine.set(format!("user:{}", i), "x".repeat(i % 50)).unwrap();
+        }
+        let stats = engine.shard_stats().unwrap();
+        assert_eq!(stats.len(), DEFAULT_SHARD_COUNT);
+        assert_eq!(stats.iter().map(|s| s.keys).sum::<usize>(), engine.len());
+        assert_eq!(stats.iter().map(|s| s.memory_bytes).sum::<usize>(), engine.memory_usage());
+        for (index, shard) in stats.iter().enumerate() {
+            let keys = (0..1000).filter(|i| engine.shard_index(&format!("user:{}", i)) == index).count();
+            assert_eq!(shard.keys, keys, "shard {}", index);
+        }
+    }
+
+    #[test]
+    fn test_random_key_returns_every_key() {
+        let engine = RwLockEngine::new("./test_data").unwrap();
+        assert_eq!(engine.random_key(), None);
+        let keys = ["a", "b", "c", "d", "e"];
+        for key in keys {
+            engine.set(key.to_string(), "v".to_string()).unwrap();
+        }
+        let mut seen = std::collections::HashSet::new();
+        for _ in 0..500 {
+            let key = engine.random_key().unwrap();
+            assert!(keys.contains(&key.as_str()), "{}", key);
+            seen.insert(key);
+        }
+        assert_eq!(seen.len(), keys.len());
+    }
 }
@@ -1,47 +1,145 @@
 // src/store/sled_engine.rs
 use anyhow::{Result, anyhow};
 use sled::{Db, Tree, IVec};
-use super::kv_trait::KVEngineStoreTrait;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{RwLock, RwLockReadGuard};
+use std::time::Duration;
+use super::kv_trait::{random_index, KVEngineStoreTrait, StorageStats};
 
-pub struct SledEngine {
+const TREE_NAME: &[u8] = b"merkle_kv";
+
+struct SledState {
     db: Db,
     tree: Tree,
 }
 
+impl SledState {
+    fn open(path: &Path) -> Result<Self> {
+        let db = sled::open(path)?;
+        let tree = db.open_tree(TREE_NAME)?;
+        Ok(Self { db, tree })
+    }
+
+    /// Open `path`, waiting for a just-dropped handle to release its file lock
+    /// (sled releases it from a background thread).
+    fn open_with_retry(path: &Path) -> Result<Self> {
+        let mut attempt = 0;
+        loop {
+            match Self::open(path) {
+                Ok(state) => return Ok(state),
+                Err(_) if attempt < 50 => {
+                    attempt += 1;
+                    std::thread::sleep(Duration::from_millis(20));
+                }
+                Err(e) => return Err(e),
+            }
+        }
+    }
+}
+
+pub struct SledEngine {
+    path: PathBuf,
+    /// Swapped as a whole by `compact_storage`
+    state: RwLock<SledState>,
+}
+
 impl SledEngine {
     pub fn new(storage_path: &str) -> Result<Self> {
-        let db = sled::open(storage_path)?;
-        let tree = db.open_tree(b"merkle_kv")?;
-        Ok(Self { db, tree })
+        let path = PathBuf::from(storage_path);
+        recover_compaction(&path)?;
+        let state = SledState::open(&path)?;
+        Ok(Self { path, state: RwLock::new(state) })
     }
 
     fn to_string_opt(v: Option<IVec>) -> Option<String> {
         v.map(|ivec| String::from_utf8_lossy(&ivec).to_string())
     }
+
+    fn state(&self) -> RwLockReadGuard<'_, SledState> {
+        self.state.read().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn tree(&self) -> Tree {
+        self.state().tree.clone()
+    }
+
+    fn sibling(&self, suffix: &str) -> PathBuf {
+        sibling(&self.path, suffix)
+    }
+}
+
+/// `<path>.<suffix>`, next to the database directory.
+fn sibling(path: &Path, suffix: &str) -> PathBuf {
+    let name = path.file_name().map(|n| n.to_string_lossy().to_string()).unwrap_or_default();
+    path.with_file_name(format!("{}.{}", name, suffix))
+}
+
+/// Finish or undo a `compact_storage` cut short by a crash. The staging copy
+/// is only renamed into place once it has been verified, so:
+/// - no database but a `.old` one: the crash fell between the two renames,
+///   put the original back
+/// - both: the swap completed, drop the original
+/// - a `.compact` left over was never swapped in, drop it
+fn recover_compaction(path: &Path) -> Result<()> {
+    let retired = sibling(path, "old");
+    if retired.exists() {
+        if path.exists() {
+            log::warn!("Removing {} left by an interrupted compaction", retired.display());
+            remove_dir_if_exists(&retired)?;
+        } else {
+            log::warn!("Restoring {} from {} after an interrupted compaction", path.display(), retired.display());
+            fs::rename(&retired, path)?;
+        }
+    }
+    let staging = sibling(path, "compact");
+    if staging.exists() {
+        log::warn!("Removing {} left by an interrupted compaction", staging.display());
+        remove_dir_if_exists(&staging)?;
+    }
+    Ok(())
+}
+
+/// Total size of the files under `path`.
+fn dir_size(path: &Path) -> Result<u64> {
+    let mut total = 0;
+    for entry in fs::read_dir(path)? {
+        let entry = entry?;
+        let meta = entry.metadata()?;
+        total += if meta.is_dir() { dir_size(&entry.path())? } else { meta.len() };
+    }
+    Ok(total)
+}
+
+fn remove_dir_if_exists(path: &Path) -> Result<()> {
+    match fs::remove_dir_all(path) {
+        Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e.into()),
+        _ => Ok(()),
+    }
 }
 
 impl KVEngineStoreTrait for SledEngine {
     fn get(&self, key: &str) -> Option<String> {
-        match self.tree.get(key) {
+        match self.tree().get(key) {
             Ok(opt) => Self::to_string_opt(opt),
             Err(_) => None,
         }
     }
 
     fn set(&self, key: String, value: String) -> Result<()> {
-        self.tree.insert(key.as_bytes(), value.as_bytes())?;
+        self.tree().insert(key.as_bytes(), value.as_bytes())?;
         Ok(())
     }
 
     fn delete(&self, key: &str) -> bool {
-        match self.tree.remove(key) {
+        match self.tree().remove(key) {
             Ok(opt) => opt.is_some(),
             Err(_) => false,
         }
     }
 
     fn keys(&self) -> Vec<String> {
-        let iter = self.tree.iter();
+        let iter = self.tree().iter();
         iter.keys()
             .filter_map(|r| r.ok())
             .filter_map(|k| String::from_utf8(k.to_vec()).ok())
@@ -49,7 +147,7 @@ impl KVEngineStoreTrait for SledEngine {
     }
 
     fn len(&self) -> usize {
-        self.tree.len()
+        self.tree().len()
     }
 
     fn is_empty(&self) -> bool {
@@ -61,7 +159,7 @@ impl KVEngineStoreTrait for SledEngine {
             return self.keys();
         }
 
-        self.tree
+        self.tree()
             .scan_prefix(prefix.as_bytes())
             .filter_map(|res| res.ok())               
             .filter_map(|(k, _v)|                       
@@ -76,10 +174,10 @@ impl KVEngineStoreTrait for SledEngine {
         format!("ECHO {}", message)
     }
     fn dbsize(&self) -> usize {
-        self.tree.len()
+        self.tree().len()
     }
     fn exists(&self, key: &str) -> bool {
-        match self.tree.get(key) {
+        match self.tree().get(key) {
             Ok(opt) => opt.is_some(),
             Err(_) => false,
         }
@@ -87,12 +185,12 @@ impl KVEngineStoreTrait for SledEngine {
     fn memory_usage(&self) -> usize {
         // Sled does not provide a direct way to get memory usage.
         // This is a rough estimate based on the number of entries.
-        self.tree.len() * 100 // Assume average 100 bytes per entry
+        self.tree().len() * 100 // Assume average 100 bytes per entry
     }
     fn increment(&self, key: &str, amount: Option<i64>) -> Result<i64> {
         let amt = amount.unwrap_or(1);
         // get current
-        let current = match self.tree.get(key) {
+        let current = match self.tree().get(key) {
             Ok(Some(v)) => {
                 let s = String::from_utf8_lossy(&v).to_string();
                 s.parse::<i64>().map_err(|e| anyhow!("parse int error: {}", e))?
@@ -101,7 +199,7 @@ impl KVEngineStoreTrait for SledEngine {
             Err(e) => return Err(anyhow!(e)),
         };
         let new = current + amt;
-        self.tree.insert(key.as_bytes(), new.to_string().as_bytes())?;
+        self.tree().insert(key.as_bytes(), new.to_string().as_bytes())?;
         Ok(new)
     }
 
@@ -111,38 +209,211 @@ impl KVEngineStoreTrait for SledEngine {
     }
 
     fn append(&self, key: &str, value: &str) -> Result<String> {
-        let current = match self.tree.get(key) {
+        let current = match self.tree().get(key) {
             Ok(Some(v)) => String::from_utf8_lossy(&v).to_string(),
             Ok(None) => String::new(),
             Err(e) => return Err(anyhow!(e)),
         };
         let new = format!("{}{}", current, value);
-        self.tree.insert(key.as_bytes(), new.as_bytes())?;
+        self.tree().insert(key.as_bytes(), new.as_bytes())?;
         Ok(new)
     }
 
     fn prepend(&self, key: &str, value: &str) -> Result<String> {
-        let current = match self.tree.get(key) {
+        let current = match self.tree().get(key) {
             Ok(Some(v)) => String::from_utf8_lossy(&v).to_string(),
             Ok(None) => String::new(),
             Err(e) => return Err(anyhow!(e)),
         };
         let new = format!("{}{}", value, current);
-        self.tree.insert(key.as_bytes(), new.as_bytes())?;
+        self.tree().insert(key.as_bytes(), new.as_bytes())?;
         Ok(new)
     }
 
     fn truncate(&self) -> Result<()> {
-        self.tree.clear()?;
+        self.tree().clear()?;
         Ok(())
     }
 
     fn count_keys(&self) -> Result<u64> {
-        Ok(self.tree.len() as u64)
+        Ok(self.tree().len() as u64)
     }
 
     fn sync(&self) -> Result<()> {
-        self.db.flush()?;
+        self.state().db.flush()?;
         Ok(())
     }
+
+    /// sled iterates in key order, so the first `limit` pairs would only show
+    /// one end of the keyspace: stride across all of it from a random offset.
+    fn sample(&self, limit: usize) -> Vec<(String, String)> {
+        let tree = self.tree();
+        let len = tree.len();
+        if len == 0 || limit == 0 {
+            return Vec::new();
+        }
+        let stride = (len / limit).max(1);
+        tree.iter()
+            .skip(random_index(stride))
+            .step_by(stride)
+            .filter_map(|r| r.ok())
+            .take(limit)
+            .map(|(k, v)| {
+                (
+                    String::from_utf8_lossy(&k).to_string(),
+                    String::from_utf8_lossy(&v).to_string(),
+                )
+            })
+            .collect()
+    }
+
+    fn storage_stats(&self) -> Option<StorageStats> {
+        let logical_bytes = self
+            .tree()
+            .iter()
+            .filter_map(|r| r.ok())
+            .map(|(k, v)| (k.len() + v.len()) as u64)
+            .sum();
+        let disk_bytes = dir_size(&self.path).ok()?;
+        Some(StorageStats { logical_bytes, disk_bytes })
+    }
+
+    /// sled never shrinks its files in place, so rewrite the live pairs into a
+    /// fresh database next to this one and swap the directories. The copy is
+    /// reopened and counted before the swap; if any step of the swap fails the
+    /// original directory is put back and stays in use. A crash mid-swap is
+    /// repaired on the next start (see `recover_compaction`).
+    fn compact_storage(&self) -> Result<bool> {
+        let mut state = self.state.write().unwrap_or_else(|e| e.into_inner());
+        let staging = self.sibling("compact");
+        let retired = self.sibling("old");
+        remove_dir_if_exists(&staging)?;
+        remove_dir_if_exists(&retired)?;
+        let copied = (|| -> Result<()> {
+            {
+                let fresh = SledState::open(&staging)?;
+                for pair in state.tree.iter() {
+                    let (key, value) = pair?;
+                    fresh.tree.insert(key, value)?;
+                }
+                fresh.db.flush()?;
+            }
+            let check = SledState::open_with_retry(&staging)?;
+            if check.tree.len() != state.tree.len() {
+                return Err(anyhow!("compacted copy has {} keys, expected {}", check.tree.len(), state.tree.len()));
+            }
+            Ok(())
+        })();
+        if let Err(e) = copied.and_then(|()| Ok(state.db.flush()?)) {
+            let _ = remove_dir_if_exists(&staging);
+            return Err(e);
+        }
+
+        // The open handle follows its files through the renames, so on
+        // failure the original only needs its name back
+        fs::rename(&self.path, &retired)?;
+        if let Err(e) = fs::rename(&staging, &self.path) {
+            fs::rename(&retired, &self.path)?;
+            let _ = remove_dir_if_exists(&staging);
+            return Err(e.into());
+        }
+        match SledState::open_with_retry(&self.path) {
+            Ok(fresh) => *state = fresh,
+            Err(e) => {
+                fs::rename(&self.path, &staging)?;
+                fs::rename(&retired, &self.path)?;
+                let _ = remove_dir_if_exists(&staging);
+                return Err(e);
+            }
+        }
+        if let Err(e) = remove_dir_if_exists(&retired) {
+            log::warn!("Compacted {}, but removing {} failed: {}", self.path.display(), retired.display(), e);
+        }
+        Ok(true)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ratio(engine: &SledEngine) -> f64 {
+        engine.sync().unwrap();
+        engine.storage_stats().unwrap().ratio()
+    }
+
+    #[test]
+    fn test_sample_spans_the_whole_keyspace() {
+        let dir = tempfile::tempdir().unwrap();
+        let engine = SledEngine::new(dir.path().join("db").to_str().unwrap()).unwrap();
+        assert!(engine.sample(10).is_empty());
+        for i in 0..100 {
+            engine.set(format!("k{:03}", i), "v".to_string()).unwrap();
+        }
+
+        let sample = engine.sample(10);
+        assert_eq!(sample.len(), 10);
+        // Every tenth key from an offset below ten, so the last is past k090
+        assert!(sample.last().unwrap().0.as_str() >= "k090", "{:?}", sample);
+        assert_eq!(engine.sample(1000).len(), 100);
+    }
+
+    #[test]
+    fn test_fragmentation_rises_with_overwrites_and_falls_after_compaction() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("db");
+        let engine = SledEngine::new(path.to_str().unwrap()).unwrap();
+        let big = "v".repeat(1000);
+        for i in 0..2000 {
+            engine.set(format!("k{}", i), big.clone()).unwrap();
+        }
+        let filled = ratio(&engine);
+
+        // Shrinking every value leaves the old bytes on disk
+        for i in 0..2000 {
+            engine.set(format!("k{}", i), "v".to_string()).unwrap();
+        }
+        let fragmented = ratio(&engine);
+        assert!(fragmented > filled * 10.0, "{} -> {}", filled, fragmented);
+
+        assert!(engine.compact_storage().unwrap());
+        let compacted = ratio(&engine);
+        assert!(compacted < fragmented, "{} -> {}", fragmented, compacted);
+        assert!(!engine.sibling("old").exists());
+
+        // Data survives the swap, and the engine keeps working on the new files
+        assert_eq!(engine.dbsize(), 2000);
+        assert_eq!(engine.get("k7"), Some("v".to_string()));
+        engine.set("after".to_string(), "compact".to_string()).unwrap();
+        drop(engine);
+        let reopened = SledState::open_with_retry(&path).unwrap();
+        assert_eq!(reopened.tree.get("after").unwrap().as_deref(), Some(&b"compact"[..]));
+    }
+
+    #[test]
+    fn test_interrupted_compaction_is_repaired_on_start() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("db");
+        let open = || SledEngine::new(path.to_str().unwrap()).unwrap();
+        let engine = open();
+        engine.set("k".to_string(), "v".to_string()).unwrap();
+        engine.sync().unwrap();
+        drop(engine);
+
+        // Crash between the renames: only the original, as .old
+        std::thread::sleep(Duration::from_millis(100));
+        fs::rename(&path, sibling(&path, "old")).unwrap();
+        fs::create_dir(sibling(&path, "compact")).unwrap();
+        let engine = open();
+        assert_eq!(engine.get("k"), Some("v".to_string()));
+        assert!(!sibling(&path, "old").exists());
+        assert!(!sibling(&path, "compact").exists());
+        drop(engine);
+
+        // Crash after the swap: the original is dropped
+        fs::create_dir(sibling(&path, "old")).unwrap();
+        std::thread::sleep(Duration::from_millis(100));
+        assert_eq!(open().get("k"), Some("v".to_string()));
+        assert!(!sibling(&path, "old").exists());
+    }
 }
@@ -0,0 +1,80 @@
+//! # Test Engine
+//!
+//! In-memory engine for tests that need to watch or break the storage under
+//! a server or a wrapper: it counts `sync` calls, can fail them, and can
+//! refuse writes to one key.
+
+use anyhow::{anyhow, Result};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+use super::kv_trait::KVEngineStoreTrait;
+use super::rwlock_engine::RwLockEngine;
+
+pub struct TestEngine {
+    inner: RwLockEngine,
+    syncs: Arc<AtomicUsize>,
+    failing_sync: bool,
+    read_only_key: Option<String>,
+}
+
+impl TestEngine {
+    pub fn new() -> Self {
+        Self {
+            inner: RwLockEngine::new("unused").unwrap(),
+            syncs: Arc::new(AtomicUsize::new(0)),
+            failing_sync: false,
+            read_only_key: None,
+        }
+    }
+
+    /// Make every `sync` fail with `disk full` (it is still counted).
+    pub fn failing_sync(mut self) -> Self {
+        self.failing_sync = true;
+        self
+    }
+
+    /// Make `set` of `key` fail with `<key> is read-only`.
+    pub fn read_only(mut self, key: &str) -> Self {
+        self.read_only_key = Some(key.to_string());
+        self
+    }
+
+    /// Counter of `sync` calls, readable after the engine is boxed away.
+    pub fn syncs(&self) -> Arc<AtomicUsize> {
+        Arc::clone(&self.syncs)
+    }
+}
+
+impl KVEngineStoreTrait for TestEngine {
+    fn get(&self, key: &str) -> Option<String> { self.inner.get(key) }
+    fn set(&self, key: String, value: String) -> Result<()> {
+        if self.read_only_key.as_deref() == Some(key.as_str()) {
+            return Err(anyhow!("{} is read-only", key));
+        }
+        self.inner.set(key, value)
+    }
+    fn delete(&self, key: &str) -> bool { self.inner.delete(key) }
+    fn keys(&self) -> Vec<String> { self.inner.keys() }
+    fn scan(&self, prefix: &str) -> Vec<String> { self.inner.scan(prefix) }
+    fn ping(&self, message: &str) -> String { self.inner.ping(message) }
+    fn echo(&self, message: &str) -> String { self.inner.echo(message) }
+    fn exists(&self, key: &str) -> bool { self.inner.exists(key) }
+    fn memory_usage(&self) -> usize { self.inner.memory_usage() }
+    fn len(&self) -> usize { self.inner.len() }
+    fn dbsize(&self) -> usize { self.inner.dbsize() }
+    fn is_empty(&self) -> bool { self.inner.is_empty() }
+    fn increment(&self, key: &str, amount: Option<i64>) -> Result<i64> { self.inner.increment(key, amount) }
+    fn decrement(&self, key: &str, amount: Option<i64>) -> Result<i64> { self.inner.decrement(key, amount) }
+    fn append(&self, key: &str, value: &str) -> Result<String> { self.inner.append(key, value) }
+    fn prepend(&self, key: &str, value: &str) -> Result<String> { self.inner.prepend(key, value) }
+    fn truncate(&self) -> Result<()> { self.inner.truncate() }
+    fn count_keys(&self) -> Result<u64> { self.inner.count_keys() }
+    fn sync(&self) -> Result<()> {
+        self.syncs.fetch_add(1, Ordering::SeqCst);
+        if self.failing_sync {
+            return Err(anyhow!("disk full"));
+        }
+        self.inner.sync()
+    }
+}
@@ -0,0 +1,416 @@
+//! # Cold Storage Tier
+//!
+//! A decorator that caps how much data an in-memory engine holds. The engine
+//! itself is the *hot* tier; once the hot keys and values exceed
+//! `max_hot_bytes`, the least recently used keys are demoted to a file-backed
+//! *cold* tier (a `SledEngine`) instead of being dropped. A read or write of a
+//! cold key transparently promotes it back to the hot tier; `WARM` promotes
+//! keys ahead of their first read. `INFO` reports `cold_tier_keys` and
+//! `cold_tier_reads`, the reads that had to go to disk.
+//!
+//! Every key lives in exactly one tier, so listing and counting operations
+//! combine both and the rest of the server sees one logical keyspace.
+//!
+//! ## Caveats
+//!
+//! - Only key and value bytes count towards the cap, not map overhead.
+//! - The most recently used key always stays hot, even if it alone exceeds the cap.
+//! - Full walks (`MERKLE VERIFY`, anti-entropy snapshots) read every key and so
+//!   cycle cold keys through the hot tier.
+//! - The cold tier is spill space, not persistence: it is cleared on start,
+//!   because the in-memory hot tier it complements starts empty.
+
+use anyhow::Result;
+use log::warn;
+use std::collections::{BTreeMap, HashMap};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Mutex, MutexGuard};
+
+use super::kv_trait::{KVEngineStoreTrait, StorageStats, TierStats};
+
+/// Recency and size bookkeeping for the hot tier.
+#[derive(Default)]
+struct HotSet {
+    tick: u64,
+    /// key → (last access tick, key + value bytes)
+    entries: HashMap<String, (u64, usize)>,
+    /// last access tick → key, oldest first
+    order: BTreeMap<u64, String>,
+    bytes: usize,
+}
+
+impl HotSet {
+    /// Mark `key` as just used, holding `value_len` bytes of value.
+    fn touch(&mut self, key: &str, value_len: usize) {
+        self.remove(key);
+        self.tick += 1;
+        let size = key.len() + value_len;
+        self.entries.insert(key.to_string(), (self.tick, size));
+        self.order.insert(self.tick, key.to_string());
+        self.bytes += size;
+    }
+
+    /// Mark the tracked `key` as just used, keeping its size.
+    ///
+    /// # Returns
+    /// * `bool` - False if `key` is not in the hot tier
+    fn bump(&mut self, key: &str) -> bool {
+        let Some(&(_, size)) = self.entries.get(key) else {
+            return false;
+        };
+        self.touch(key, size - key.len());
+        true
+    }
+
+    fn remove(&mut self, key: &str) {
+        if let Some((tick, size)) = self.entries.remove(key) {
+            self.order.remove(&tick);
+            self.bytes -= size;
+        }
+    }
+
+    /// Least recently used key, never the only remaining one.
+    fn pop_oldest(&mut self) -> Option<String> {
+        if self.entries.len() < 2 {
+            return None;
+        }
+        let (_, key) = self.order.pop_first()?;
+        if let Some((_, size)) = self.entries.remove(&key) {
+            self.bytes -= size;
+        }
+        Some(key)
+    }
+
+    fn clear(&mut self) {
+        *self = Self::default();
+    }
+}
+
+/// Storage engine wrapper that spills least recently used keys to disk.
+pub struct TieredEngine {
+    hot: Box<dyn KVEngineStoreTrait + Send + Sync>,
+    cold: Box<dyn KVEngineStoreTrait + Send + Sync>,
+    /// Key + value bytes the hot tier may hold before demoting
+    max_hot_bytes: usize,
+    lru: Mutex<HotSet>,
+    /// `get`s served from the cold tier
+    cold_reads: AtomicU64,
+}
+
+impl TieredEngine {
+    /// Put `cold` behind `hot`, keeping at most `max_hot_bytes` of keys and
+    /// values in `hot`. The cold tier is cleared first.
+    pub fn new(
+        hot: Box<dyn KVEngineStoreTrait + Send + Sync>,
+        cold: Box<dyn KVEngineStoreTrait + Send + Sync>,
+        max_hot_bytes: usize,
+    ) -> Result<Self> {
+        cold.truncate()?;
+        let mut lru = HotSet::default();
+        for key in hot.keys() {
+            if let Some(value) = hot.get(&key) {
+                lru.touch(&key, value.len());
+            }
+        }
+        let engine = Self { hot, cold, max_hot_bytes, lru: Mutex::new(lru), cold_reads: AtomicU64::new(0) };
+        engine.enforce_cap();
+        Ok(engine)
+    }
+
+    /// Whether `key` currently lives in the cold tier.
+    #[cfg(test)]
+    pub fn is_cold(&self, key: &str) -> bool {
+        self.cold.exists(key)
+    }
+
+    fn lru(&self) -> MutexGuard<'_, HotSet> {
+        self.lru.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Move `key` back to the hot tier if it is cold.
+    fn promote(&self, key: &str) -> Result<()> {
+        if let Some(value) = self.cold.get(key) {
+            self.hot.set(key.to_string(), value.clone())?;
+            self.cold.delete(key);
+            self.lru().touch(key, value.len());
+            self.enforce_cap();
+        }
+        Ok(())
+    }
+
+    /// Record a hot write of `key`, then demote until under the cap.
+    fn track(&self, key: &str, value_len: usize) {
+        self.lru().touch(key, value_len);
+        self.enforce_cap();
+    }
+
+    fn enforce_cap(&self) {
+        let mut lru = self.lru();
+        while lru.bytes > self.max_hot_bytes {
+            let Some(key) = lru.pop_oldest() else { break };
+            let Some(value) = self.hot.get(&key) else { continue };
+            if let Err(e) = self.cold.set(key.clone(), value.clone()) {
+                warn!("Cold tier write failed, keeping {} in memory: {}", key, e);
+                lru.touch(&key, value.len());
+                break;
+            }
+            self.hot.delete(&key);
+        }
+    }
+}
+
+impl KVEngineStoreTrait for TieredEngine {
+    fn get(&self, key: &str) -> Option<String> {
+        if let Some(value) = self.hot.get(key) {
+            self.lru().touch(key, value.len());
+            return Some(value);
+        }
+        let value = self.cold.get(key)?;
+        self.cold_reads.fetch_add(1, Ordering::Relaxed);
+        if let Err(e) = self.promote(key) {
+            warn!("Failed to promote {} from the cold tier: {}", key, e);
+        }
+        Some(value)
+    }
+
+    fn set(&self, key: String, value: String) -> Result<()> {
+        let len = value.len();
+        self.hot.set(key.clone(), value)?;
+        self.cold.delete(&key);
+        self.track(&key, len);
+        Ok(())
+    }
+
+    fn delete(&self, key: &str) -> bool {
+        self.lru().remove(key);
+        let hot = self.hot.delete(key);
+        let cold = self.cold.delete(key);
+        hot || cold
+    }
+
+    fn keys(&self) -> Vec<String> {
+        let mut keys = self.hot.keys();
+        keys.extend(self.cold.keys());
+        keys
+    }
+
+    fn scan(&self, prefix: &str) -> Vec<String> {
+        let mut keys = self.hot.scan(prefix);
+        keys.extend(self.cold.scan(prefix));
+        keys
+    }
+
+    fn ping(&self, message: &str) -> String {
+        self.hot.ping(message)
+    }
+
+    fn echo(&self, message: &str) -> String {
+        self.hot.echo(message)
+    }
+
+    fn exists(&self, key: &str) -> bool {
+        self.hot.exists(key) || self.cold.exists(key)
+    }
+
+    fn memory_usage(&self) -> usize {
+        self.hot.memory_usage()
+    }
+
+    fn len(&self) -> usize {
+        self.hot.len() + self.cold.len()
+    }
+
+    fn dbsize(&self) -> usize {
+        self.hot.dbsize() + self.cold.dbsize()
+    }
+
+    fn is_empty(&self) -> bool {
+        self.hot.is_empty() && self.cold.is_empty()
+    }
+
+    fn increment(&self, key: &str, amount: Option<i64>) -> Result<i64> {
+        self.promote(key)?;
+        let value = self.hot.increment(key, amount)?;
+        self.track(key, value.to_string().len());
+        Ok(value)
+    }
+
+    fn decrement(&self, key: &str, amount: Option<i64>) -> Result<i64> {
+        self.promote(key)?;
+        let value = self.hot.decrement(key, amount)?;
+        self.track(key, value.to_string().len());
+        Ok(value)
+    }
+
+    fn append(&self, key: &str, value: &str) -> Result<String> {
+        self.promote(key)?;
+        let new_value = self.hot.append(key, value)?;
+        self.track(key, new_value.len());
+        Ok(new_value)
+    }
+
+    fn prepend(&self, key: &str, value: &str) -> Result<String> {
+        self.promote(key)?;
+        let new_value = self.hot.prepend(key, value)?;
+        self.track(key, new_value.len());
+        Ok(new_value)
+    }
+
+    fn truncate(&self) -> Result<()> {
+        self.hot.truncate()?;
+        self.cold.truncate()?;
+        self.lru().clear();
+        Ok(())
+    }
+
+    fn count_keys(&self) -> Result<u64> {
+        Ok(self.hot.count_keys()? + self.cold.count_keys()?)
+    }
+
+    fn sync(&self) -> Result<()> {
+        self.hot.sync()?;
+        self.cold.sync()
+    }
+
+    fn sample(&self, limit: usize) -> Vec<(String, String)> {
+        // Sampling must not promote cold keys
+        self.hot.sample(limit)
+    }
+
+    fn compact_memory(&self, part: usize) -> bool {
+        self.hot.compact_memory(part)
+    }
+
+    fn touch(&self, key: &str) -> bool {
+        if self.lru().bump(key) {
+            return true;
+        }
+        if !self.cold.exists(key) {
+            return self.hot.exists(key);
+        }
+        // A touched key is hot: bring it back before colder ones
+        if let Err(e) = self.promote(key) {
+            warn!("Failed to promote {} from the cold tier: {}", key, e);
+        }
+        true
+    }
+
+    fn storage_stats(&self) -> Option<StorageStats> {
+        self.cold.storage_stats()
+    }
+
+    /// Promote a cold key; warming more than fits demotes the least recently
+    /// used keys, which may include ones warmed just before.
+    fn warm(&self, key: &str) -> Result<bool> {
+        if !self.cold.exists(key) {
+            return Ok(false);
+        }
+        self.promote(key)?;
+        Ok(true)
+    }
+
+    fn tier_stats(&self) -> Option<TierStats> {
+        Some(TierStats { cold_keys: self.cold.len(), cold_reads: self.cold_reads.load(Ordering::Relaxed) })
+    }
+
+    fn compact_storage(&self) -> Result<bool> {
+        self.cold.compact_storage()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::store::{RwLockEngine, SledEngine};
+
+    fn tiered(dir: &tempfile::TempDir, max_hot_bytes: usize) -> TieredEngine {
+        let cold = SledEngine::new(dir.path().to_str().unwrap()).unwrap();
+        TieredEngine::new(Box::new(RwLockEngine::new("unused").unwrap()), Box::new(cold), max_hot_bytes).unwrap()
+    }
+
+    #[test]
+    fn test_cold_key_is_demoted_and_read_back() {
+        let dir = tempfile::tempdir().unwrap();
+        // Room for roughly two 10-byte entries
+        let engine = tiered(&dir, 25);
+        engine.set("k1".to_string(), "aaaaaaaa".to_string()).unwrap();
+        engine.set("k2".to_string(), "bbbbbbbb".to_string()).unwrap();
+        engine.set("k3".to_string(), "cccccccc".to_string()).unwrap();
+
+        // k1 is least recently used and spilled to disk, but still visible
+        assert!(engine.is_cold("k1"));
+        assert!(!engine.is_cold("k3"));
+        assert_eq!(engine.dbsize(), 3);
+        assert!(engine.exists("k1"));
+        let mut keys = engine.keys();
+        keys.sort();
+        assert_eq!(keys, vec!["k1", "k2", "k3"]);
+
+        // GET loads it back and demotes the now-coldest key instead
+        assert_eq!(engine.get("k1"), Some("aaaaaaaa".to_string()));
+        assert!(!engine.is_cold("k1"));
+        assert!(engine.is_cold("k2"));
+        assert_eq!(engine.get("k2"), Some("bbbbbbbb".to_string()));
+    }
+
+    #[test]
+    fn test_writes_to_cold_keys_promote_them() {
+        let dir = tempfile::tempdir().unwrap();
+        let engine = tiered(&dir, 10);
+        engine.set("n".to_string(), "41".to_string()).unwrap();
+        engine.set("s".to_string(), "mid".to_string()).unwrap();
+        engine.set("big".to_string(), "xxxxxx".to_string()).unwrap();
+        assert!(engine.is_cold("n"));
+
+        assert_eq!(engine.increment("n", None).unwrap(), 42);
+        assert!(!engine.is_cold("n"));
+        assert_eq!(engine.append("s", "dle").unwrap(), "middle");
+
+        assert!(engine.delete("big"));
+        assert!(!engine.exists("big"));
+        engine.truncate().unwrap();
+        assert_eq!(engine.dbsize(), 0);
+    }
+
+    #[test]
+    fn test_touch_keeps_a_key_hot_and_promotes_cold_ones() {
+        let dir = tempfile::tempdir().unwrap();
+        let engine = tiered(&dir, 25);
+        engine.set("k1".to_string(), "aaaaaaaa".to_string()).unwrap();
+        engine.set("k2".to_string(), "bbbbbbbb".to_string()).unwrap();
+        // Without the touch k1 would be the one demoted next
+        assert!(engine.touch("k1"));
+        engine.set("k3".to_string(), "cccccccc".to_string()).unwrap();
+        assert!(!engine.is_cold("k1"));
+        assert!(engine.is_cold("k2"));
+
+        assert!(engine.touch("k2"));
+        assert!(!engine.is_cold("k2"));
+        assert!(engine.is_cold("k1"), "the least recently used key makes room");
+        assert!(!engine.touch("missing"));
+    }
+
+    #[test]
+    fn test_warm_loads_cold_keys_so_reads_stay_in_memory() {
+        let dir = tempfile::tempdir().unwrap();
+        let engine = tiered(&dir, 40);
+        // Room for four 10-byte entries
+        for key in ["k1", "k2", "k3", "k4", "k5", "k6"] {
+            engine.set(key.to_string(), "aaaaaaaa".to_string()).unwrap();
+        }
+        assert!(engine.is_cold("k1") && engine.is_cold("k2"));
+        assert_eq!(engine.tier_stats(), Some(TierStats { cold_keys: 2, cold_reads: 0 }));
+
+        assert!(engine.warm("k1").unwrap());
+        assert!(engine.warm("k2").unwrap());
+        assert!(!engine.warm("k2").unwrap(), "already hot");
+        assert!(!engine.warm("missing").unwrap());
+        assert!(engine.is_cold("k3") && engine.is_cold("k4"), "warming makes room like a read");
+        assert_eq!(engine.get("k1"), Some("aaaaaaaa".to_string()));
+        assert_eq!(engine.get("k2"), Some("aaaaaaaa".to_string()));
+        assert_eq!(engine.tier_stats(), Some(TierStats { cold_keys: 2, cold_reads: 0 }));
+
+        assert_eq!(engine.get("k3"), Some("aaaaaaaa".to_string()));
+        assert_eq!(engine.tier_stats().unwrap().cold_reads, 1);
+    }
+}
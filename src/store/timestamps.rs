@@ -0,0 +1,356 @@
+//! # Key Timestamps (`OBJECT CREATED|MODIFIED|ACCESSED|IDLETIME|REPLINFO`)
+//!
+//! A decorator that records when each key was created, last written and
+//! last used:
+//!
+//! - A write to an absent key (including one that expired) stamps all times
+//! - A write to an existing key (`set`, `increment`, `append`, ...) moves
+//!   the modification and access times, so an overwrite keeps the creation time
+//! - A read that finds the key, or `TOUCH`, only moves the access time
+//! - `delete` forgets them, so a later write creates the key afresh
+//!
+//! Times are UNIX milliseconds of this node's clock; they are kept in memory,
+//! not persisted, and not replicated (a replica stamps keys when it applies them).
+//! With each write the engine also keeps the node it came from: replication
+//! calls `mark_replicated` after applying a peer's write, and any other write
+//! resets the origin to this node.
+
+use anyhow::Result;
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+use super::expiring::now_ms;
+use super::kv_trait::{KVEngineStoreTrait, KeyTimes, ShardStats, StorageStats, TierStats, WalHealth};
+
+/// Storage engine wrapper that tracks creation and modification times.
+pub struct TimestampEngine {
+    inner: Box<dyn KVEngineStoreTrait + Send + Sync>,
+    times: Mutex<HashMap<String, KeyTimes>>,
+}
+
+impl TimestampEngine {
+    pub fn new(inner: Box<dyn KVEngineStoreTrait + Send + Sync>) -> Self {
+        Self { inner, times: Mutex::new(HashMap::new()) }
+    }
+
+    fn times_guard(&self) -> std::sync::MutexGuard<'_, HashMap<String, KeyTimes>> {
+        self.times.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Stamp a write to `key`; `existed` is whether it was live before the write.
+    fn stamp(&self, key: &str, existed: bool) {
+        let now = now_ms();
+        let mut times = self.times_guard();
+        match times.get_mut(key) {
+            Some(t) if existed => {
+                t.modified_ms = now;
+                t.accessed_ms = now;
+                t.origin = None;
+            }
+            _ => {
+                times.insert(key.to_string(), KeyTimes { created_ms: now, modified_ms: now, accessed_ms: now, origin: None });
+            }
+        }
+    }
+
+    /// Stamp a use of the existing `key`.
+    fn accessed(&self, key: &str) {
+        if let Some(t) = self.times_guard().get_mut(key) {
+            t.accessed_ms = now_ms();
+        }
+    }
+
+    /// Run a write on the inner engine and stamp it if it succeeds.
+    fn write<T>(&self, key: &str, op: impl FnOnce(&dyn KVEngineStoreTrait) -> Result<T>) -> Result<T> {
+        let existed = self.inner.exists(key);
+        let result = op(self.inner.as_ref())?;
+        self.stamp(key, existed);
+        Ok(result)
+    }
+}
+
+impl KVEngineStoreTrait for TimestampEngine {
+    fn get(&self, key: &str) -> Option<String> {
+        let value = self.inner.get(key)?;
+        self.accessed(key);
+        Some(value)
+    }
+
+    fn set(&self, key: String, value: String) -> Result<()> {
+        let name = key.clone();
+        self.write(&name, |inner| inner.set(key, value))
+    }
+
+    fn delete(&self, key: &str) -> bool {
+        self.times_guard().remove(key);
+        self.inner.delete(key)
+    }
+
+    fn keys(&self) -> Vec<String> {
+        self.inner.keys()
+    }
+
+    fn scan(&self, prefix: &str) -> Vec<String> {
+        self.inner.scan(prefix)
+    }
+
+    fn ping(&self, message: &str) -> String {
+        self.inner.ping(message)
+    }
+
+    fn echo(&self, message: &str) -> String {
+        self.inner.echo(message)
+    }
+
+    fn exists(&self, key: &str) -> bool {
+        self.inner.exists(key)
+    }
+
+    fn memory_usage(&self) -> usize {
+        self.inner.memory_usage()
+    }
+
+    fn len(&self) -> usize {
+        self.inner.len()
+    }
+
+    fn dbsize(&self) -> usize {
+        self.inner.dbsize()
+    }
+
+    fn is_empty(&self) -> bool {
+        self.inner.is_empty()
+    }
+
+    fn increment(&self, key: &str, amount: Option<i64>) -> Result<i64> {
+        self.write(key, |inner| inner.increment(key, amount))
+    }
+
+    fn decrement(&self, key: &str, amount: Option<i64>) -> Result<i64> {
+        self.write(key, |inner| inner.decrement(key, amount))
+    }
+
+    fn append(&self, key: &str, value: &str) -> Result<String> {
+        self.write(key, |inner| inner.append(key, value))
+    }
+
+    fn prepend(&self, key: &str, value: &str) -> Result<String> {
+        self.write(key, |inner| inner.prepend(key, value))
+    }
+
+    fn truncate(&self) -> Result<()> {
+        self.inner.truncate()?;
+        self.times_guard().clear();
+        Ok(())
+    }
+
+    fn count_keys(&self) -> Result<u64> {
+        self.inner.count_keys()
+    }
+
+    fn sync(&self) -> Result<()> {
+        self.inner.sync()
+    }
+
+    fn sample(&self, limit: usize) -> Vec<(String, String)> {
+        self.inner.sample(limit)
+    }
+
+    fn random_key(&self) -> Option<String> {
+        self.inner.random_key()
+    }
+
+    fn set_expiry(&self, key: &str, expires_at_ms: Option<u64>) -> Result<bool> {
+        self.inner.set_expiry(key, expires_at_ms)
+    }
+
+    fn expiry(&self, key: &str) -> Option<u64> {
+        self.inner.expiry(key)
+    }
+
+    fn take_expired(&self) -> Vec<String> {
+        let expired = self.inner.take_expired();
+        let mut times = self.times_guard();
+        for key in &expired {
+            times.remove(key);
+        }
+        expired
+    }
+
+    fn times(&self, key: &str) -> Option<KeyTimes> {
+        if !self.inner.exists(key) {
+            // Expired or written below this layer: nothing to report
+            self.times_guard().remove(key);
+            return None;
+        }
+        self.times_guard().get(key).cloned()
+    }
+
+    fn mark_replicated(&self, key: &str, origin: &str) {
+        if let Some(t) = self.times_guard().get_mut(key) {
+            t.origin = Some(origin.to_string());
+        }
+    }
+
+    fn touch(&self, key: &str) -> bool {
+        let exists = self.inner.touch(key);
+        if exists {
+            self.accessed(key);
+        }
+        exists
+    }
+
+    fn mark_local(&self, key: &str) {
+        self.inner.mark_local(key)
+    }
+
+    fn is_local(&self, key: &str) -> bool {
+        self.inner.is_local(key)
+    }
+
+    fn tombstones(&self) -> Vec<(String, u64)> {
+        self.inner.tombstones()
+    }
+
+    fn add_tombstone(&self, key: &str, deleted_at_ms: u64) -> bool {
+        self.inner.add_tombstone(key, deleted_at_ms)
+    }
+
+    fn compact_tombstones(&self) -> usize {
+        self.inner.compact_tombstones()
+    }
+
+    fn compact_memory(&self, part: usize) -> bool {
+        if part == 0 {
+            // Drop the times of keys that expired without being read
+            let mut times = self.times_guard();
+            times.retain(|key, _| self.inner.exists(key));
+            times.shrink_to_fit();
+        }
+        self.inner.compact_memory(part)
+    }
+
+    fn storage_stats(&self) -> Option<StorageStats> {
+        self.inner.storage_stats()
+    }
+
+    fn shard_stats(&self) -> Option<Vec<ShardStats>> {
+        self.inner.shard_stats()
+    }
+
+    fn warm(&self, key: &str) -> Result<bool> {
+        self.inner.warm(key)
+    }
+
+    fn tier_stats(&self) -> Option<TierStats> {
+        self.inner.tier_stats()
+    }
+
+    fn compact_storage(&self) -> Result<bool> {
+        self.inner.compact_storage()
+    }
+
+    fn find_by_value_prefix(&self, prefix: &str) -> Option<Vec<String>> {
+        self.inner.find_by_value_prefix(prefix)
+    }
+
+    fn persistence(&self) -> Option<bool> {
+        self.inner.persistence()
+    }
+
+    fn wal_health(&self) -> Option<WalHealth> {
+        self.inner.wal_health()
+    }
+
+    fn durable(&self, key: &str) -> Option<bool> {
+        self.inner.durable(key)
+    }
+
+    fn log_expiry(&self, key: &str, expires_at_ms: Option<u64>) -> Result<()> {
+        self.inner.log_expiry(key, expires_at_ms)
+    }
+
+    fn logged_expiries(&self) -> Vec<(String, u64)> {
+        self.inner.logged_expiries()
+    }
+
+    fn set_persistence(&self, enabled: bool) -> Result<()> {
+        self.inner.set_persistence(enabled)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::store::RwLockEngine;
+
+    fn engine() -> TimestampEngine {
+        TimestampEngine::new(Box::new(RwLockEngine::new("unused").unwrap()))
+    }
+
+    #[test]
+    fn test_overwrite_keeps_created_and_recreate_resets_it() {
+        let e = engine();
+        assert_eq!(e.times("k"), None);
+        e.set("k".to_string(), "v1".to_string()).unwrap();
+        let first = e.times("k").unwrap();
+        assert_eq!(first.created_ms, first.modified_ms);
+
+        std::thread::sleep(std::time::Duration::from_millis(5));
+        e.set("k".to_string(), "v2".to_string()).unwrap();
+        e.append("k", "!").unwrap();
+        let overwritten = e.times("k").unwrap();
+        assert_eq!(overwritten.created_ms, first.created_ms, "overwrite keeps created-at");
+        assert!(overwritten.modified_ms > first.modified_ms);
+
+        std::thread::sleep(std::time::Duration::from_millis(5));
+        assert!(e.delete("k"));
+        assert_eq!(e.times("k"), None);
+        e.increment("k", None).unwrap();
+        let recreated = e.times("k").unwrap();
+        assert!(recreated.created_ms > first.created_ms, "delete + recreate resets created-at");
+        assert_eq!(recreated.created_ms, recreated.modified_ms);
+    }
+
+    #[test]
+    fn test_failed_write_does_not_stamp() {
+        let e = engine();
+        e.set("n".to_string(), "text".to_string()).unwrap();
+        let before = e.times("n").unwrap();
+        assert!(e.increment("n", None).is_err());
+        assert_eq!(e.times("n"), Some(before));
+    }
+
+    #[test]
+    fn test_reads_and_touch_move_only_the_access_time() {
+        let e = engine();
+        e.set("k".to_string(), "v".to_string()).unwrap();
+        let written = e.times("k").unwrap();
+        assert_eq!(written.accessed_ms, written.modified_ms);
+
+        std::thread::sleep(std::time::Duration::from_millis(5));
+        assert!(e.touch("k"));
+        let touched = e.times("k").unwrap();
+        assert!(touched.accessed_ms > written.accessed_ms);
+        assert_eq!(touched.modified_ms, written.modified_ms);
+
+        std::thread::sleep(std::time::Duration::from_millis(5));
+        assert_eq!(e.get("k"), Some("v".to_string()));
+        assert!(e.times("k").unwrap().accessed_ms > touched.accessed_ms);
+        assert!(!e.touch("missing"));
+        assert_eq!(e.times("missing"), None);
+    }
+
+    #[test]
+    fn test_replicated_origin_is_cleared_by_a_local_write() {
+        let e = engine();
+        e.set("k".to_string(), "remote".to_string()).unwrap();
+        e.mark_replicated("k", "node-a");
+        assert_eq!(e.times("k").unwrap().origin.as_deref(), Some("node-a"));
+
+        e.append("k", "+local").unwrap();
+        assert_eq!(e.times("k").unwrap().origin, None);
+        e.mark_replicated("missing", "node-a");
+        assert_eq!(e.times("missing"), None);
+    }
+}
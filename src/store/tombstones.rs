@@ -0,0 +1,323 @@
+//! # Tombstones
+//!
+//! A decorator that remembers deleted keys for a grace period
+//! (`replication.tombstone_ttl_seconds`) so anti-entropy sync cannot bring them
+//! back from a peer that has not seen the delete yet.
+//!
+//! ## Lifecycle
+//!
+//! - `delete` of an existing key records a tombstone stamped with the current time
+//! - Any later write to the key (`set`, `increment`, `append`, ...) clears it
+//! - Sync merges the peer's live tombstones (`add_tombstone`), keeping the
+//!   original deletion time, so deletes propagate through nodes that never had the key
+//! - `compact_tombstones` purges tombstones older than the grace period; until then
+//!   they are retained and returned by `tombstones`
+//!
+//! The grace period must exceed the longest time a peer can lag behind (one
+//! anti-entropy interval plus any outage), otherwise a lagging peer resurrects
+//! the key once the tombstone is gone. Tombstones are kept in memory and are
+//! not persisted; `truncate` clears them together with the data.
+
+use anyhow::Result;
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+use super::expiring::now_ms;
+use super::kv_trait::{KVEngineStoreTrait, ShardStats, StorageStats, TierStats, WalHealth};
+
+/// Storage engine wrapper that records tombstones for deleted keys.
+pub struct TombstoneEngine {
+    inner: Box<dyn KVEngineStoreTrait + Send + Sync>,
+    /// Grace period in milliseconds
+    ttl_ms: u64,
+    /// Deleted key → deletion time (UNIX ms)
+    tombstones: Mutex<HashMap<String, u64>>,
+}
+
+impl TombstoneEngine {
+    /// Wrap an engine, keeping tombstones for `ttl_seconds`.
+    pub fn new(inner: Box<dyn KVEngineStoreTrait + Send + Sync>, ttl_seconds: u64) -> Self {
+        Self {
+            inner,
+            ttl_ms: ttl_seconds.saturating_mul(1000),
+            tombstones: Mutex::new(HashMap::new()),
+        }
+    }
+
+    fn tombstones_guard(&self) -> std::sync::MutexGuard<'_, HashMap<String, u64>> {
+        self.tombstones.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn is_live(&self, deleted_at: u64, now: u64) -> bool {
+        deleted_at.saturating_add(self.ttl_ms) > now
+    }
+
+    fn clear_tombstone(&self, key: &str) {
+        self.tombstones_guard().remove(key);
+    }
+
+    /// Purge tombstones that are past the grace period at `now` (UNIX ms).
+    pub fn compact_at(&self, now: u64) -> usize {
+        let mut tombstones = self.tombstones_guard();
+        let before = tombstones.len();
+        tombstones.retain(|_, &mut at| at.saturating_add(self.ttl_ms) > now);
+        before - tombstones.len()
+    }
+}
+
+impl KVEngineStoreTrait for TombstoneEngine {
+    fn get(&self, key: &str) -> Option<String> {
+        self.inner.get(key)
+    }
+
+    fn set(&self, key: String, value: String) -> Result<()> {
+        self.inner.set(key.clone(), value)?;
+        self.clear_tombstone(&key);
+        Ok(())
+    }
+
+    fn delete(&self, key: &str) -> bool {
+        let deleted = self.inner.delete(key);
+        if deleted {
+            self.tombstones_guard().insert(key.to_string(), now_ms());
+        }
+        deleted
+    }
+
+    fn keys(&self) -> Vec<String> {
+        self.inner.keys()
+    }
+
+    fn scan(&self, prefix: &str) -> Vec<String> {
+        self.inner.scan(prefix)
+    }
+
+    fn ping(&self, message: &str) -> String {
+        self.inner.ping(message)
+    }
+
+    fn echo(&self, message: &str) -> String {
+        self.inner.echo(message)
+    }
+
+    fn exists(&self, key: &str) -> bool {
+        self.inner.exists(key)
+    }
+
+    fn memory_usage(&self) -> usize {
+        self.inner.memory_usage()
+    }
+
+    fn len(&self) -> usize {
+        self.inner.len()
+    }
+
+    fn dbsize(&self) -> usize {
+        self.inner.dbsize()
+    }
+
+    fn is_empty(&self) -> bool {
+        self.inner.is_empty()
+    }
+
+    fn increment(&self, key: &str, amount: Option<i64>) -> Result<i64> {
+        let value = self.inner.increment(key, amount)?;
+        self.clear_tombstone(key);
+        Ok(value)
+    }
+
+    fn decrement(&self, key: &str, amount: Option<i64>) -> Result<i64> {
+        let value = self.inner.decrement(key, amount)?;
+        self.clear_tombstone(key);
+        Ok(value)
+    }
+
+    fn append(&self, key: &str, value: &str) -> Result<String> {
+        let new_value = self.inner.append(key, value)?;
+        self.clear_tombstone(key);
+        Ok(new_value)
+    }
+
+    fn prepend(&self, key: &str, value: &str) -> Result<String> {
+        let new_value = self.inner.prepend(key, value)?;
+        self.clear_tombstone(key);
+        Ok(new_value)
+    }
+
+    fn truncate(&self) -> Result<()> {
+        self.inner.truncate()?;
+        self.tombstones_guard().clear();
+        Ok(())
+    }
+
+    fn count_keys(&self) -> Result<u64> {
+        self.inner.count_keys()
+    }
+
+    fn sync(&self) -> Result<()> {
+        self.inner.sync()
+    }
+
+    fn sample(&self, limit: usize) -> Vec<(String, String)> {
+        self.inner.sample(limit)
+    }
+
+    fn random_key(&self) -> Option<String> {
+        self.inner.random_key()
+    }
+
+    fn set_expiry(&self, key: &str, expires_at_ms: Option<u64>) -> Result<bool> {
+        self.inner.set_expiry(key, expires_at_ms)
+    }
+
+    fn expiry(&self, key: &str) -> Option<u64> {
+        self.inner.expiry(key)
+    }
+
+    fn take_expired(&self) -> Vec<String> {
+        self.inner.take_expired()
+    }
+
+    fn tombstones(&self) -> Vec<(String, u64)> {
+        let now = now_ms();
+        let mut live: Vec<(String, u64)> = self
+            .tombstones_guard()
+            .iter()
+            .filter(|(_, &at)| self.is_live(at, now))
+            .map(|(k, &at)| (k.clone(), at))
+            .collect();
+        live.sort();
+        live
+    }
+
+    fn add_tombstone(&self, key: &str, deleted_at_ms: u64) -> bool {
+        if !self.is_live(deleted_at_ms, now_ms()) || self.inner.exists(key) {
+            return false;
+        }
+        let mut tombstones = self.tombstones_guard();
+        let at = tombstones.entry(key.to_string()).or_insert(deleted_at_ms);
+        *at = (*at).max(deleted_at_ms);
+        true
+    }
+
+    fn compact_tombstones(&self) -> usize {
+        self.compact_at(now_ms())
+    }
+
+    fn touch(&self, key: &str) -> bool {
+        self.inner.touch(key)
+    }
+
+    fn mark_local(&self, key: &str) {
+        self.inner.mark_local(key)
+    }
+
+    fn is_local(&self, key: &str) -> bool {
+        self.inner.is_local(key)
+    }
+
+    fn compact_memory(&self, part: usize) -> bool {
+        if part == 0 {
+            self.tombstones_guard().shrink_to_fit();
+        }
+        self.inner.compact_memory(part)
+    }
+
+    fn storage_stats(&self) -> Option<StorageStats> {
+        self.inner.storage_stats()
+    }
+
+    fn shard_stats(&self) -> Option<Vec<ShardStats>> {
+        self.inner.shard_stats()
+    }
+
+    fn warm(&self, key: &str) -> Result<bool> {
+        self.inner.warm(key)
+    }
+
+    fn tier_stats(&self) -> Option<TierStats> {
+        self.inner.tier_stats()
+    }
+
+    fn compact_storage(&self) -> Result<bool> {
+        self.inner.compact_storage()
+    }
+
+    fn find_by_value_prefix(&self, prefix: &str) -> Option<Vec<String>> {
+        self.inner.find_by_value_prefix(prefix)
+    }
+
+    fn persistence(&self) -> Option<bool> {
+        self.inner.persistence()
+    }
+
+    fn wal_health(&self) -> Option<WalHealth> {
+        self.inner.wal_health()
+    }
+
+    fn durable(&self, key: &str) -> Option<bool> {
+        self.inner.durable(key)
+    }
+
+    fn log_expiry(&self, key: &str, expires_at_ms: Option<u64>) -> Result<()> {
+        self.inner.log_expiry(key, expires_at_ms)
+    }
+
+    fn logged_expiries(&self) -> Vec<(String, u64)> {
+        self.inner.logged_expiries()
+    }
+
+    fn set_persistence(&self, enabled: bool) -> Result<()> {
+        self.inner.set_persistence(enabled)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::store::RwLockEngine;
+
+    fn engine(ttl_seconds: u64) -> TombstoneEngine {
+        TombstoneEngine::new(Box::new(RwLockEngine::new("unused").unwrap()), ttl_seconds)
+    }
+
+    #[test]
+    fn test_tombstone_survives_compaction_within_grace_period() {
+        let e = engine(60);
+        e.set("k".to_string(), "v".to_string()).unwrap();
+        assert!(e.delete("k"));
+        assert!(!e.delete("k"), "deleting an absent key must not restamp");
+        let (key, deleted_at) = e.tombstones().pop().unwrap();
+        assert_eq!(key, "k");
+
+        // Inside the grace window compaction keeps it
+        assert_eq!(e.compact_at(deleted_at + 59_999), 0);
+        assert_eq!(e.tombstones().len(), 1);
+
+        // Past the window it is purged
+        assert_eq!(e.compact_at(deleted_at + 60_000), 1);
+        assert!(e.tombstones().is_empty());
+    }
+
+    #[test]
+    fn test_write_clears_tombstone() {
+        let e = engine(60);
+        e.set("k".to_string(), "v".to_string()).unwrap();
+        e.delete("k");
+        e.append("k", "again").unwrap();
+        assert!(e.tombstones().is_empty());
+    }
+
+    #[test]
+    fn test_add_tombstone_from_peer() {
+        let e = engine(60);
+        let now = now_ms();
+        assert!(e.add_tombstone("gone", now - 1_000));
+        assert_eq!(e.tombstones(), vec![("gone".to_string(), now - 1_000)]);
+        // Expired peer tombstones and live local keys are ignored
+        assert!(!e.add_tombstone("old", now - 61_000));
+        e.set("live".to_string(), "v".to_string()).unwrap();
+        assert!(!e.add_tombstone("live", now));
+        assert_eq!(e.tombstones().len(), 1);
+    }
+}
@@ -0,0 +1,317 @@
+//! # Value-Prefix Index
+//!
+//! A decorator that keeps a secondary index from value prefixes to keys, so
+//! `FINDBYVALUE <prefix>` finds the keys whose values start with a prefix
+//! without scanning the whole keyspace. Enabled with `index.value_prefix_enabled`.
+//!
+//! Like the Merkle tracker, it sits on the shared store, so every write path
+//! (client commands, replication, anti-entropy sync) keeps it current. The
+//! index lives in memory and is rebuilt from the wrapped engine's contents on
+//! start.
+//!
+//! ## Trade-offs
+//!
+//! - Every write also updates the index, and each key costs one more copy of
+//!   its key plus up to `index.value_prefix_max_len` bytes of its value.
+//! - Only the first `value_prefix_max_len` bytes of each value are indexed.
+//!   Longer query prefixes are answered from the index and then checked against
+//!   the stored values.
+
+use anyhow::Result;
+use std::collections::{BTreeSet, HashMap};
+use std::sync::{Mutex, MutexGuard};
+
+use super::kv_trait::{KVEngineStoreTrait, ShardStats, StorageStats, TierStats, WalHealth};
+
+/// The longest prefix of `value` that is at most `max_len` bytes and ends on a char boundary.
+fn truncate(value: &str, max_len: usize) -> &str {
+    let mut end = value.len().min(max_len);
+    while !value.is_char_boundary(end) {
+        end -= 1;
+    }
+    &value[..end]
+}
+
+#[derive(Default)]
+struct ValueIndex {
+    /// key → indexed value prefix
+    by_key: HashMap<String, String>,
+    /// (indexed value prefix, key), ordered for prefix range scans
+    by_value: BTreeSet<(String, String)>,
+}
+
+impl ValueIndex {
+    fn insert(&mut self, key: &str, value_prefix: &str) {
+        self.remove(key);
+        self.by_key.insert(key.to_string(), value_prefix.to_string());
+        self.by_value.insert((value_prefix.to_string(), key.to_string()));
+    }
+
+    fn remove(&mut self, key: &str) {
+        if let Some(prefix) = self.by_key.remove(key) {
+            self.by_value.remove(&(prefix, key.to_string()));
+        }
+    }
+
+    /// Keys whose indexed prefix starts with `prefix`.
+    fn lookup(&self, prefix: &str) -> Vec<String> {
+        self.by_value
+            .range((prefix.to_string(), String::new())..)
+            .take_while(|(value, _)| value.starts_with(prefix))
+            .map(|(_, key)| key.clone())
+            .collect()
+    }
+}
+
+/// Storage engine wrapper that maintains a value-prefix index.
+pub struct ValueIndexEngine {
+    inner: Box<dyn KVEngineStoreTrait + Send + Sync>,
+    /// Bytes of each value that are indexed
+    max_len: usize,
+    index: Mutex<ValueIndex>,
+}
+
+impl ValueIndexEngine {
+    /// Wrap an engine, indexing the first `max_len` bytes of every value
+    /// already stored in it.
+    pub fn new(inner: Box<dyn KVEngineStoreTrait + Send + Sync>, max_len: usize) -> Self {
+        let engine = Self { inner, max_len, index: Mutex::default() };
+        {
+            let mut index = engine.index();
+            for key in engine.inner.keys() {
+                if let Some(value) = engine.inner.get(&key) {
+                    index.insert(&key, truncate(&value, max_len));
+                }
+            }
+        }
+        engine
+    }
+
+    fn index(&self) -> MutexGuard<'_, ValueIndex> {
+        self.index.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn track(&self, key: &str, value: &str) {
+        self.index().insert(key, truncate(value, self.max_len));
+    }
+}
+
+impl KVEngineStoreTrait for ValueIndexEngine {
+    fn get(&self, key: &str) -> Option<String> {
+        self.inner.get(key)
+    }
+
+    fn set(&self, key: String, value: String) -> Result<()> {
+        self.inner.set(key.clone(), value.clone())?;
+        self.track(&key, &value);
+        Ok(())
+    }
+
+    fn delete(&self, key: &str) -> bool {
+        let deleted = self.inner.delete(key);
+        self.index().remove(key);
+        deleted
+    }
+
+    fn keys(&self) -> Vec<String> {
+        self.inner.keys()
+    }
+
+    fn scan(&self, prefix: &str) -> Vec<String> {
+        self.inner.scan(prefix)
+    }
+
+    fn ping(&self, message: &str) -> String {
+        self.inner.ping(message)
+    }
+
+    fn echo(&self, message: &str) -> String {
+        self.inner.echo(message)
+    }
+
+    fn exists(&self, key: &str) -> bool {
+        self.inner.exists(key)
+    }
+
+    fn memory_usage(&self) -> usize {
+        self.inner.memory_usage()
+    }
+
+    fn len(&self) -> usize {
+        self.inner.len()
+    }
+
+    fn dbsize(&self) -> usize {
+        self.inner.dbsize()
+    }
+
+    fn is_empty(&self) -> bool {
+        self.inner.is_empty()
+    }
+
+    fn increment(&self, key: &str, amount: Option<i64>) -> Result<i64> {
+        let value = self.inner.increment(key, amount)?;
+        self.track(key, &value.to_string());
+        Ok(value)
+    }
+
+    fn decrement(&self, key: &str, amount: Option<i64>) -> Result<i64> {
+        let value = self.inner.decrement(key, amount)?;
+        self.track(key, &value.to_string());
+        Ok(value)
+    }
+
+    fn append(&self, key: &str, value: &str) -> Result<String> {
+        let new_value = self.inner.append(key, value)?;
+        self.track(key, &new_value);
+        Ok(new_value)
+    }
+
+    fn prepend(&self, key: &str, value: &str) -> Result<String> {
+        let new_value = self.inner.prepend(key, value)?;
+        self.track(key, &new_value);
+        Ok(new_value)
+    }
+
+    fn truncate(&self) -> Result<()> {
+        self.inner.truncate()?;
+        *self.index() = ValueIndex::default();
+        Ok(())
+    }
+
+    fn count_keys(&self) -> Result<u64> {
+        self.inner.count_keys()
+    }
+
+    fn sync(&self) -> Result<()> {
+        self.inner.sync()
+    }
+
+    fn sample(&self, limit: usize) -> Vec<(String, String)> {
+        self.inner.sample(limit)
+    }
+
+    fn random_key(&self) -> Option<String> {
+        self.inner.random_key()
+    }
+
+    fn touch(&self, key: &str) -> bool {
+        self.inner.touch(key)
+    }
+
+    fn compact_memory(&self, part: usize) -> bool {
+        if part == 0 {
+            self.index().by_key.shrink_to_fit();
+        }
+        self.inner.compact_memory(part)
+    }
+
+    fn storage_stats(&self) -> Option<StorageStats> {
+        self.inner.storage_stats()
+    }
+
+    fn shard_stats(&self) -> Option<Vec<ShardStats>> {
+        self.inner.shard_stats()
+    }
+
+    fn warm(&self, key: &str) -> Result<bool> {
+        self.inner.warm(key)
+    }
+
+    fn tier_stats(&self) -> Option<TierStats> {
+        self.inner.tier_stats()
+    }
+
+    fn compact_storage(&self) -> Result<bool> {
+        self.inner.compact_storage()
+    }
+
+    fn find_by_value_prefix(&self, prefix: &str) -> Option<Vec<String>> {
+        let mut keys = self.index().lookup(truncate(prefix, self.max_len));
+        if prefix.len() > self.max_len {
+            keys.retain(|key| self.inner.get(key).is_some_and(|v| v.starts_with(prefix)));
+        }
+        keys.sort();
+        Some(keys)
+    }
+
+    fn persistence(&self) -> Option<bool> {
+        self.inner.persistence()
+    }
+
+    fn wal_health(&self) -> Option<WalHealth> {
+        self.inner.wal_health()
+    }
+
+    fn durable(&self, key: &str) -> Option<bool> {
+        self.inner.durable(key)
+    }
+
+    fn log_expiry(&self, key: &str, expires_at_ms: Option<u64>) -> Result<()> {
+        self.inner.log_expiry(key, expires_at_ms)
+    }
+
+    fn logged_expiries(&self) -> Vec<(String, u64)> {
+        self.inner.logged_expiries()
+    }
+
+    fn set_persistence(&self, enabled: bool) -> Result<()> {
+        self.inner.set_persistence(enabled)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::store::RwLockEngine;
+
+    fn indexed(max_len: usize) -> ValueIndexEngine {
+        ValueIndexEngine::new(Box::new(RwLockEngine::new("unused").unwrap()), max_len)
+    }
+
+    fn find(engine: &ValueIndexEngine, prefix: &str) -> Vec<String> {
+        engine.find_by_value_prefix(prefix).unwrap()
+    }
+
+    #[test]
+    fn test_index_follows_updates_and_deletes() {
+        let engine = indexed(64);
+        engine.set("u1".to_string(), "admin:alice".to_string()).unwrap();
+        engine.set("u2".to_string(), "admin:bob".to_string()).unwrap();
+        engine.set("u3".to_string(), "guest:carol".to_string()).unwrap();
+        assert_eq!(find(&engine, "admin:"), vec!["u1", "u2"]);
+        assert_eq!(find(&engine, "admin:b"), vec!["u2"]);
+        assert_eq!(find(&engine, ""), vec!["u1", "u2", "u3"]);
+        assert!(find(&engine, "root").is_empty());
+
+        // Overwrites, string ops and counters move keys between prefixes
+        engine.set("u1".to_string(), "guest:alice".to_string()).unwrap();
+        engine.prepend("u3", "x").unwrap();
+        engine.set("n".to_string(), "9".to_string()).unwrap();
+        engine.increment("n", None).unwrap();
+        assert_eq!(find(&engine, "admin:"), vec!["u2"]);
+        assert_eq!(find(&engine, "guest:"), vec!["u1"]);
+        assert_eq!(find(&engine, "xguest:"), vec!["u3"]);
+        assert_eq!(find(&engine, "10"), vec!["n"]);
+        assert!(find(&engine, "9").is_empty());
+
+        assert!(engine.delete("u2"));
+        assert!(find(&engine, "admin:").is_empty());
+        engine.truncate().unwrap();
+        assert!(find(&engine, "").is_empty());
+    }
+
+    #[test]
+    fn test_long_prefixes_and_rebuild() {
+        let inner = RwLockEngine::new("unused").unwrap();
+        inner.set("a".to_string(), "abcdef".to_string()).unwrap();
+        inner.set("b".to_string(), "abcxyz".to_string()).unwrap();
+        inner.set("c".to_string(), "é-accent".to_string()).unwrap();
+
+        // Existing contents are indexed on start; only 3 bytes per value
+        let engine = ValueIndexEngine::new(Box::new(inner), 3);
+        assert_eq!(find(&engine, "abc"), vec!["a", "b"]);
+        assert_eq!(find(&engine, "abcd"), vec!["a"], "longer prefixes are checked against the values");
+        assert_eq!(find(&engine, "é-"), vec!["c"]);
+    }
+}
@@ -0,0 +1,474 @@
+//! # Write-Ahead Log Records (`storage.wal_format`)
+//!
+//! The record format and file framing of the write-ahead log, including the
+//! compacted copy `PERSISTENCE OFF` writes over it. Each record carries the
+//! value, its write time and the key's absolute expiry (`ttl_ms`); the
+//! `clock` field is reserved for a vector clock and is always written empty.
+//! Records are encoded with the configured format:
+//!
+//! - **`bincode`** (default): compact, not self-describing
+//! - **`msgpack`**: MessagePack, compact and self-describing, readable by
+//!   other tools; the record is an array `[op, key, value, ts, ttl_ms, clock]`
+//!
+//! ## File Layout
+//!
+//! ```text
+//! "MKVWAL" <version: u8> <format: u8>      header, format 1 = bincode, 2 = msgpack
+//! <len: u32 big-endian> <record bytes>     repeated
+//! ```
+//!
+//! The header records the format the file was written in, so replaying it
+//! with a different `storage.wal_format` fails with a clear error instead of
+//! misreading records. A torn final record (a crash mid-append) ends replay.
+//!
+//! `wal_engine` writes and replays these logs for `storage.wal_path`.
+
+use anyhow::{anyhow, bail, Context, Result};
+use serde::{Deserialize, Serialize};
+use std::fs::{File, OpenOptions};
+use std::io::{BufWriter, Read, Write};
+use std::path::Path;
+
+const MAGIC: &[u8; 6] = b"MKVWAL";
+const VERSION: u8 = 1;
+
+/// Encoding of WAL and snapshot records (`storage.wal_format`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum WalFormat {
+    #[default]
+    Bincode,
+    Msgpack,
+}
+
+impl WalFormat {
+    fn tag(self) -> u8 {
+        match self {
+            WalFormat::Bincode => 1,
+            WalFormat::Msgpack => 2,
+        }
+    }
+
+    fn from_tag(tag: u8) -> Option<Self> {
+        match tag {
+            1 => Some(WalFormat::Bincode),
+            2 => Some(WalFormat::Msgpack),
+            _ => None,
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            WalFormat::Bincode => "bincode",
+            WalFormat::Msgpack => "msgpack",
+        }
+    }
+
+    pub fn encode(self, record: &WalRecord) -> Result<Vec<u8>> {
+        match self {
+            WalFormat::Bincode => bincode::serialize(record).context("bincode encode"),
+            WalFormat::Msgpack => Ok(msgpack::encode(record)),
+        }
+    }
+
+    pub fn decode(self, bytes: &[u8]) -> Result<WalRecord> {
+        match self {
+            WalFormat::Bincode => bincode::deserialize(bytes).context("bincode decode"),
+            WalFormat::Msgpack => msgpack::decode(bytes),
+        }
+    }
+}
+
+/// One logged write.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct WalRecord {
+    /// `set`, `del`, ...
+    pub op: String,
+    pub key: String,
+    /// None for deletes
+    pub value: Option<Vec<u8>>,
+    /// Write time (UNIX ms)
+    pub ts: u64,
+    /// Absolute expiry (UNIX ms), None without TTL
+    pub ttl_ms: Option<u64>,
+    /// Vector clock: node id → counter, sorted by node id
+    pub clock: Vec<(String, u64)>,
+}
+
+/// Appends framed records to a log file.
+pub struct WalWriter {
+    file: BufWriter<File>,
+    format: WalFormat,
+    /// File length up to the end of the last flushed record
+    flushed_len: u64,
+    /// File length once buffered records are written
+    written_len: u64,
+}
+
+impl WalWriter {
+    /// Open `path` for appending, writing the header if the file is new.
+    /// An existing file must have been written in `format`.
+    pub fn open(path: &Path, format: WalFormat) -> Result<Self> {
+        let mut file = OpenOptions::new()
+            .read(true)
+            .append(true)
+            .create(true)
+            .open(path)
+            .with_context(|| format!("open WAL {}", path.display()))?;
+        if file.metadata()?.len() == 0 {
+            file.write_all(MAGIC)?;
+            file.write_all(&[VERSION, format.tag()])?;
+        } else {
+            check_header(&mut file, path, format)?;
+        }
+        let len = file.metadata()?.len();
+        Ok(Self { file: BufWriter::new(file), format, flushed_len: len, written_len: len })
+    }
+
+    /// A writer appending to an already open `file` (a failing one, in tests).
+    #[cfg(test)]
+    pub fn from_file(file: File, format: WalFormat) -> Self {
+        Self { file: BufWriter::new(file), format, flushed_len: 0, written_len: 0 }
+    }
+
+    pub fn append(&mut self, record: &WalRecord) -> Result<()> {
+        let bytes = self.format.encode(record)?;
+        let len = u32::try_from(bytes.len()).map_err(|_| anyhow!("WAL record over 4 GiB"))?;
+        self.file.write_all(&len.to_be_bytes())?;
+        self.file.write_all(&bytes)?;
+        self.written_len += 4 + bytes.len() as u64;
+        Ok(())
+    }
+
+    /// Hand buffered records to the OS, without waiting for the disk.
+    pub fn flush(&mut self) -> Result<()> {
+        self.file.flush()?;
+        self.flushed_len = self.written_len;
+        Ok(())
+    }
+
+    /// Drop the records appended since the last successful flush, from the
+    /// buffer and from the file, after a failed append or flush: a record
+    /// that was refused must not reach the log later or leave a torn tail.
+    pub fn discard_unflushed(&mut self) -> Result<()> {
+        let file = self.file.get_ref().try_clone()?;
+        // into_parts hands back the buffer instead of flushing it on drop
+        let _ = std::mem::replace(&mut self.file, BufWriter::new(file)).into_parts();
+        self.written_len = self.flushed_len;
+        self.file.get_ref().set_len(self.flushed_len)?;
+        Ok(())
+    }
+
+    /// Flush buffered records and fsync the file.
+    pub fn sync(&mut self) -> Result<()> {
+        self.file.flush()?;
+        self.file.get_ref().sync_data()?;
+        Ok(())
+    }
+}
+
+/// Read every complete record of the log at `path`, which must have been
+/// written in `format`.
+pub fn replay(path: &Path, format: WalFormat) -> Result<Vec<WalRecord>> {
+    let mut file = File::open(path).with_context(|| format!("open WAL {}", path.display()))?;
+    check_header(&mut file, path, format)?;
+    let mut rest = Vec::new();
+    file.read_to_end(&mut rest)?;
+    let mut records = Vec::new();
+    let mut at = 0;
+    while let Some(len) = rest.get(at..at + 4) {
+        let len = u32::from_be_bytes(len.try_into().expect("4 bytes")) as usize;
+        let Some(bytes) = rest.get(at + 4..at + 4 + len) else { break };
+        records.push(format.decode(bytes).with_context(|| format!("WAL {} record {}", path.display(), records.len()))?);
+        at += 4 + len;
+    }
+    Ok(records)
+}
+
+fn check_header(file: &mut File, path: &Path, format: WalFormat) -> Result<()> {
+    let mut header = [0u8; 8];
+    file.read_exact(&mut header).with_context(|| format!("WAL {} has no header", path.display()))?;
+    if &header[..6] != MAGIC {
+        bail!("{} is not a MerkleKV WAL", path.display());
+    }
+    if header[6] != VERSION {
+        bail!("WAL {} has unsupported version {}", path.display(), header[6]);
+    }
+    match WalFormat::from_tag(header[7]) {
+        Some(found) if found == format => Ok(()),
+        Some(found) => bail!(
+            "WAL {} was written as {} but storage.wal_format is {}",
+            path.display(),
+            found.name(),
+            format.name()
+        ),
+        None => bail!("WAL {} has unknown format tag {}", path.display(), header[7]),
+    }
+}
+
+/// The MessagePack subset used by `WalRecord`: nil, unsigned ints, str, bin,
+/// arrays and maps.
+mod msgpack {
+    use super::WalRecord;
+    use anyhow::{anyhow, bail, Result};
+
+    pub fn encode(record: &WalRecord) -> Vec<u8> {
+        let mut out = vec![0x96]; // fixarray of 6
+        str(&mut out, &record.op);
+        str(&mut out, &record.key);
+        match &record.value {
+            Some(v) => bin(&mut out, v),
+            None => out.push(0xc0),
+        }
+        uint(&mut out, record.ts);
+        match record.ttl_ms {
+            Some(t) => uint(&mut out, t),
+            None => out.push(0xc0),
+        }
+        len_header(&mut out, record.clock.len(), 0x80, 0xde);
+        for (node, counter) in &record.clock {
+            str(&mut out, node);
+            uint(&mut out, *counter);
+        }
+        out
+    }
+
+    fn uint(out: &mut Vec<u8>, n: u64) {
+        match n {
+            0..=0x7f => out.push(n as u8),
+            0x80..=0xff => out.extend_from_slice(&[0xcc, n as u8]),
+            0x100..=0xffff => {
+                out.push(0xcd);
+                out.extend_from_slice(&(n as u16).to_be_bytes());
+            }
+            0x1_0000..=0xffff_ffff => {
+                out.push(0xce);
+                out.extend_from_slice(&(n as u32).to_be_bytes());
+            }
+            _ => {
+                out.push(0xcf);
+                out.extend_from_slice(&n.to_be_bytes());
+            }
+        }
+    }
+
+    fn str(out: &mut Vec<u8>, s: &str) {
+        let len = s.len();
+        if len < 32 {
+            out.push(0xa0 | len as u8);
+        } else if len <= 0xff {
+            out.extend_from_slice(&[0xd9, len as u8]);
+        } else if len <= 0xffff {
+            out.push(0xda);
+            out.extend_from_slice(&(len as u16).to_be_bytes());
+        } else {
+            out.push(0xdb);
+            out.extend_from_slice(&(len as u32).to_be_bytes());
+        }
+        out.extend_from_slice(s.as_bytes());
+    }
+
+    fn bin(out: &mut Vec<u8>, b: &[u8]) {
+        let len = b.len();
+        if len <= 0xff {
+            out.extend_from_slice(&[0xc4, len as u8]);
+        } else if len <= 0xffff {
+            out.push(0xc5);
+            out.extend_from_slice(&(len as u16).to_be_bytes());
+        } else {
+            out.push(0xc6);
+            out.extend_from_slice(&(len as u32).to_be_bytes());
+        }
+        out.extend_from_slice(b);
+    }
+
+    /// Array / map header: fix form below 16 entries, else the 16-bit form
+    /// (`long`) or its 32-bit successor.
+    fn len_header(out: &mut Vec<u8>, len: usize, fix: u8, long: u8) {
+        if len < 16 {
+            out.push(fix | len as u8);
+        } else if len <= 0xffff {
+            out.push(long);
+            out.extend_from_slice(&(len as u16).to_be_bytes());
+        } else {
+            out.push(long + 1);
+            out.extend_from_slice(&(len as u32).to_be_bytes());
+        }
+    }
+
+    pub fn decode(bytes: &[u8]) -> Result<WalRecord> {
+        let mut r = Reader { bytes, at: 0 };
+        if r.array_len()? != 6 {
+            bail!("msgpack WAL record must be an array of 6");
+        }
+        let op = r.str()?;
+        let key = r.str()?;
+        let value = if r.nil()? { None } else { Some(r.bin()?) };
+        let ts = r.uint()?;
+        let ttl_ms = if r.nil()? { None } else { Some(r.uint()?) };
+        let clock = (0..r.map_len()?).map(|_| Ok((r.str()?, r.uint()?))).collect::<Result<_>>()?;
+        if r.at != bytes.len() {
+            bail!("trailing bytes after msgpack WAL record");
+        }
+        Ok(WalRecord { op, key, value, ts, ttl_ms, clock })
+    }
+
+    struct Reader<'a> {
+        bytes: &'a [u8],
+        at: usize,
+    }
+
+    impl Reader<'_> {
+        fn take(&mut self, n: usize) -> Result<&[u8]> {
+            let bytes = self.bytes.get(self.at..self.at + n).ok_or_else(|| anyhow!("truncated msgpack record"))?;
+            self.at += n;
+            Ok(bytes)
+        }
+
+        fn byte(&mut self) -> Result<u8> {
+            Ok(self.take(1)?[0])
+        }
+
+        fn be(&mut self, n: usize) -> Result<u64> {
+            Ok(self.take(n)?.iter().fold(0, |acc, &b| acc << 8 | b as u64))
+        }
+
+        /// Consume a nil if one is next.
+        fn nil(&mut self) -> Result<bool> {
+            let is_nil = *self.bytes.get(self.at).ok_or_else(|| anyhow!("truncated msgpack record"))? == 0xc0;
+            if is_nil {
+                self.at += 1;
+            }
+            Ok(is_nil)
+        }
+
+        fn uint(&mut self) -> Result<u64> {
+            match self.byte()? {
+                b @ 0x00..=0x7f => Ok(b as u64),
+                0xcc => self.be(1),
+                0xcd => self.be(2),
+                0xce => self.be(4),
+                0xcf => self.be(8),
+                b => bail!("expected msgpack uint, found 0x{:02x}", b),
+            }
+        }
+
+        fn str(&mut self) -> Result<String> {
+            let len = match self.byte()? {
+                b @ 0xa0..=0xbf => (b & 0x1f) as usize,
+                0xd9 => self.be(1)? as usize,
+                0xda => self.be(2)? as usize,
+                0xdb => self.be(4)? as usize,
+                b => bail!("expected msgpack str, found 0x{:02x}", b),
+            };
+            Ok(String::from_utf8(self.take(len)?.to_vec())?)
+        }
+
+        fn bin(&mut self) -> Result<Vec<u8>> {
+            let len = match self.byte()? {
+                0xc4 => self.be(1)?,
+                0xc5 => self.be(2)?,
+                0xc6 => self.be(4)?,
+                b => bail!("expected msgpack bin, found 0x{:02x}", b),
+            };
+            Ok(self.take(len as usize)?.to_vec())
+        }
+
+        fn array_len(&mut self) -> Result<usize> {
+            match self.byte()? {
+                b @ 0x90..=0x9f => Ok((b & 0x0f) as usize),
+                0xdc => Ok(self.be(2)? as usize),
+                0xdd => Ok(self.be(4)? as usize),
+                b => bail!("expected msgpack array, found 0x{:02x}", b),
+            }
+        }
+
+        fn map_len(&mut self) -> Result<usize> {
+            match self.byte()? {
+                b @ 0x80..=0x8f => Ok((b & 0x0f) as usize),
+                0xde => Ok(self.be(2)? as usize),
+                0xdf => Ok(self.be(4)? as usize),
+                b => bail!("expected msgpack map, found 0x{:02x}", b),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn records() -> Vec<WalRecord> {
+        vec![
+            WalRecord {
+                op: "set".to_string(),
+                key: "user:1".to_string(),
+                value: Some(vec![0, 159, 146, 150, 255]),
+                ts: 1_700_000_000_123,
+                ttl_ms: Some(1_700_000_060_000),
+                clock: vec![("node1".to_string(), 7), ("node2".to_string(), 300)],
+            },
+            WalRecord {
+                op: "set".to_string(),
+                key: "k".repeat(40),
+                value: Some(vec![b'x'; 70_000]),
+                ts: 200,
+                ttl_ms: None,
+                clock: (0..20).map(|i| (format!("n{:02}", i), i)).collect(),
+            },
+            WalRecord { op: "del".to_string(), key: "gone".to_string(), value: None, ts: 5, ttl_ms: None, clock: vec![] },
+        ]
+    }
+
+    #[test]
+    fn test_round_trip_each_format() {
+        for format in [WalFormat::Bincode, WalFormat::Msgpack] {
+            let dir = tempfile::tempdir().unwrap();
+            let path = dir.path().join("wal.log");
+            let mut wal = WalWriter::open(&path, format).unwrap();
+            for record in &records()[..2] {
+                wal.append(record).unwrap();
+            }
+            wal.sync().unwrap();
+            drop(wal);
+            // Reopening appends after the existing records
+            let mut wal = WalWriter::open(&path, format).unwrap();
+            wal.append(&records()[2]).unwrap();
+            wal.sync().unwrap();
+            assert_eq!(replay(&path, format).unwrap(), records(), "{:?}", format);
+        }
+        // MessagePack output is standard: fixarray(6), fixstr "set", ...
+        let bytes = WalFormat::Msgpack.encode(&records()[0]).unwrap();
+        assert_eq!(&bytes[..5], &[0x96, 0xa3, b's', b'e', b't']);
+    }
+
+    #[test]
+    fn test_wrong_format_is_rejected_on_reopen() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("wal.log");
+        let mut wal = WalWriter::open(&path, WalFormat::Msgpack).unwrap();
+        wal.append(&records()[0]).unwrap();
+        wal.sync().unwrap();
+        drop(wal);
+
+        let err = replay(&path, WalFormat::Bincode).unwrap_err().to_string();
+        assert!(err.contains("was written as msgpack but storage.wal_format is bincode"), "{}", err);
+        assert!(WalWriter::open(&path, WalFormat::Bincode).is_err());
+
+        std::fs::write(&path, b"not a wal").unwrap();
+        assert!(replay(&path, WalFormat::Msgpack).unwrap_err().to_string().contains("not a MerkleKV WAL"));
+    }
+
+    #[test]
+    fn test_torn_final_record_ends_replay() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("wal.log");
+        let mut wal = WalWriter::open(&path, WalFormat::Msgpack).unwrap();
+        wal.append(&records()[2]).unwrap();
+        wal.append(&records()[0]).unwrap();
+        wal.sync().unwrap();
+        let len = std::fs::metadata(&path).unwrap().len();
+        File::options().write(true).open(&path).unwrap().set_len(len - 3).unwrap();
+        assert_eq!(replay(&path, WalFormat::Msgpack).unwrap(), records()[2..]);
+    }
+}
@@ -0,0 +1,679 @@
+//! # Write-Ahead Log Engine (`storage.wal_path`, `PERSISTENCE ON|OFF`)
+//!
+//! A wrapper that appends every successful write to a log file (see `wal`
+//! for the format) and replays that log into the wrapped engine on start:
+//!
+//! - `set`, `increment`, `append`, ... log a `set` of the resulting value,
+//!   with the key's expiry in `ttl_ms` (`set` clears it, the others keep it)
+//! - `EXPIRE`, `PERSIST`, ... log an `expire` carrying the new expiry
+//!   (`log_expiry`, called by `expiring`)
+//! - `delete` logs a `del`, only when a key was removed
+//! - `truncate` logs a `truncate`
+//!
+//! Records are flushed to the OS after each write; `SYNC` fsyncs the log.
+//! With `storage.max_dirty_writes` set, a write that takes the number of
+//! records appended since the last fsync above it also fsyncs, so a crash
+//! loses at most that many writes even under a burst with no `SYNC`. A
+//! failed fsync is logged and retried by the next write.
+//!
+//! `OBJECT DURABLE key` tells whether the key's last write would survive a
+//! crash: the engine remembers the keys written since the last fsync, and
+//! those written while persistence is off (see below) until they are
+//! logged again.
+//!
+//! ## Write Failures (full disk)
+//!
+//! A write is applied in memory only once its record is in the log. When
+//! appending fails (`ENOSPC`, an I/O error), the partial record is cut off,
+//! the in-memory state is left as it was and the command fails with
+//! `ERR_DISK write failed: <cause>`, so memory and disk never disagree.
+//! `DEL` has no error reply: a delete that cannot be logged keeps the key
+//! and reports it as not deleted. Failures are counted (`INFO`
+//! `wal_write_errors`) and `wal_status` is `degraded` until an append succeeds.
+//!
+//! ## Pausing (`PERSISTENCE OFF`)
+//!
+//! Turning persistence off writes a snapshot of the current data (one `set`
+//! record per key) to a temporary file and renames it over the log, then
+//! stops appending; if the snapshot cannot be written, persistence stays
+//! on. Writes made while off live only in memory and are lost on a restart
+//! before `PERSISTENCE ON`, which resumes appending to the snapshot and first
+//! logs the current state (`set` or `del`) of every key written meanwhile.
+//!
+//! Replay rebuilds the expiries next to the data and hands them to the
+//! `expiring` layer (`logged_expiries`), so a key keeps its deadline across a
+//! restart; one that passed while the server was down expires on first
+//! access. Key times are kept by the layers above and are not logged.
+
+use anyhow::{anyhow, Context, Result};
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Mutex, MutexGuard};
+
+use super::expiring::now_ms;
+use super::kv_trait::{KVEngineStoreTrait, ShardStats, StorageStats, TierStats, WalHealth};
+use super::wal::{self, WalFormat, WalRecord, WalWriter};
+
+/// Storage engine wrapper that logs writes to a WAL file.
+pub struct WalEngine {
+    inner: Box<dyn KVEngineStoreTrait + Send + Sync>,
+    path: PathBuf,
+    format: WalFormat,
+    /// None while persistence is off
+    writer: Mutex<Option<WalWriter>>,
+    write_errors: AtomicU64,
+    /// Whether the latest append failed
+    degraded: AtomicBool,
+    /// `storage.max_dirty_writes` (0 = no bound)
+    max_dirty_writes: u64,
+    /// Records appended since the last fsync
+    dirty: AtomicU64,
+    fsyncs: AtomicU64,
+    /// Keys whose last record is not fsynced yet
+    unsynced: Mutex<HashSet<String>>,
+    /// Keys last written while persistence was off (all the keys a
+    /// truncate removed, too)
+    unlogged: Mutex<HashSet<String>>,
+    /// Absolute expiries (UNIX ms) as logged, for `set` records and snapshots
+    expiries: Mutex<HashMap<String, u64>>,
+}
+
+impl WalEngine {
+    /// Replay the log at `path` (if any) into `inner` and open it for appending.
+    pub fn open(inner: Box<dyn KVEngineStoreTrait + Send + Sync>, path: &Path, format: WalFormat) -> Result<Self> {
+        let mut expiries = HashMap::new();
+        if path.exists() {
+            for record in wal::replay(path, format)? {
+                match (record.op.as_str(), record.value) {
+                    ("set", Some(value)) => {
+                        let value = String::from_utf8(value)
+                            .with_context(|| format!("WAL {} value of {:?} is not UTF-8", path.display(), record.key))?;
+                        set_or_clear(&mut expiries, &record.key, record.ttl_ms);
+                        inner.set(record.key, value)?;
+                    }
+                    ("expire", _) => set_or_clear(&mut expiries, &record.key, record.ttl_ms),
+                    ("del", _) => {
+                        expiries.remove(&record.key);
+                        inner.delete(&record.key);
+                    }
+                    ("truncate", _) => {
+                        expiries.clear();
+                        inner.truncate()?;
+                    }
+                    (op, _) => return Err(anyhow!("WAL {} has unknown record op {:?}", path.display(), op)),
+                }
+            }
+        }
+        let writer = WalWriter::open(path, format)?;
+        Ok(Self {
+            inner,
+            path: path.to_path_buf(),
+            format,
+            writer: Mutex::new(Some(writer)),
+            write_errors: AtomicU64::new(0),
+            degraded: AtomicBool::new(false),
+            max_dirty_writes: 0,
+            dirty: AtomicU64::new(0),
+            fsyncs: AtomicU64::new(0),
+            unsynced: Mutex::new(HashSet::new()),
+            unlogged: Mutex::new(HashSet::new()),
+            expiries: Mutex::new(expiries),
+        })
+    }
+
+    /// fsync the log whenever more than `max` writes are unsynced (`0` = never).
+    pub fn with_max_dirty_writes(mut self, max: u64) -> Self {
+        self.max_dirty_writes = max;
+        self
+    }
+
+    /// fsync `writer`, resetting the unsynced count.
+    fn fsync(&self, writer: &mut WalWriter) -> Result<()> {
+        writer.sync()?;
+        lock(&self.unsynced).clear();
+        self.dirty.store(0, Ordering::Relaxed);
+        self.fsyncs.fetch_add(1, Ordering::Relaxed);
+        Ok(())
+    }
+
+    fn writer_guard(&self) -> MutexGuard<'_, Option<WalWriter>> {
+        self.writer.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Append one record, if persistence is on. On failure nothing of the
+    /// record stays in the log and the error is an `ERR_DISK` one.
+    fn log(&self, op: &str, key: &str, value: Option<&str>, ttl_ms: Option<u64>) -> Result<()> {
+        let mut writer = self.writer_guard();
+        let Some(writer) = writer.as_mut() else {
+            let mut unlogged = lock(&self.unlogged);
+            match op {
+                // Called before the truncate: these are the keys it removes
+                "truncate" => unlogged.extend(self.inner.keys()),
+                _ => {
+                    unlogged.insert(key.to_string());
+                }
+            }
+            return Ok(());
+        };
+        if let Err(e) = writer.append(&record(op, key, value, ttl_ms)).and_then(|()| writer.flush()) {
+            if let Err(cut) = writer.discard_unflushed() {
+                log::error!("Cutting the failed record off WAL {} failed: {:#}", self.path.display(), cut);
+            }
+            self.write_errors.fetch_add(1, Ordering::Relaxed);
+            self.degraded.store(true, Ordering::Relaxed);
+            log::error!("WAL {} append failed: {:#}", self.path.display(), e);
+            return Err(anyhow!("ERR_DISK write failed: {:#}", e));
+        }
+        self.degraded.store(false, Ordering::Relaxed);
+        lock(&self.unlogged).remove(key);
+        lock(&self.unsynced).insert(key.to_string());
+        let dirty = self.dirty.fetch_add(1, Ordering::Relaxed) + 1;
+        if self.max_dirty_writes > 0 && dirty > self.max_dirty_writes {
+            if let Err(e) = self.fsync(writer) {
+                log::error!("WAL {} fsync after {} unsynced writes failed: {:#}", self.path.display(), dirty, e);
+            }
+        }
+        Ok(())
+    }
+
+    /// Apply `op` to `key` in memory, then log the key's new value with its
+    /// expiry unchanged; if that fails, put the old value back.
+    fn logged_update<T>(&self, key: &str, op: impl FnOnce() -> Result<T>) -> Result<T> {
+        let before = self.inner.get(key);
+        let result = op()?;
+        let ttl_ms = lock(&self.expiries).get(key).copied();
+        if let Err(e) = self.log("set", key, self.inner.get(key).as_deref(), ttl_ms) {
+            match before {
+                Some(value) => self.inner.set(key.to_string(), value)?,
+                None => {
+                    self.inner.delete(key);
+                }
+            }
+            return Err(e);
+        }
+        Ok(result)
+    }
+
+    /// Write every key to a fresh file and rename it over the log.
+    fn snapshot(&self) -> Result<()> {
+        let tmp = self.path.with_extension("snapshot.tmp");
+        let _ = fs::remove_file(&tmp);
+        let mut snapshot = WalWriter::open(&tmp, self.format)?;
+        let expiries = lock(&self.expiries).clone();
+        for key in self.inner.keys() {
+            if let Some(value) = self.inner.get(&key) {
+                snapshot.append(&record("set", &key, Some(&value), expiries.get(&key).copied()))?;
+            }
+        }
+        snapshot.sync()?;
+        drop(snapshot);
+        fs::rename(&tmp, &self.path).with_context(|| format!("replace WAL {}", self.path.display()))
+    }
+
+    /// Append the current state of every key written while persistence was
+    /// off: a `set` with its expiry, or a `del` if it is gone.
+    fn log_unlogged(&self, writer: &mut WalWriter) -> Result<()> {
+        let mut unlogged = lock(&self.unlogged);
+        let expiries = lock(&self.expiries).clone();
+        for key in unlogged.iter() {
+            let record = match self.inner.get(key) {
+                Some(value) => record("set", key, Some(&value), expiries.get(key).copied()),
+                None => record("del", key, None, None),
+            };
+            writer.append(&record)?;
+        }
+        writer.flush()?;
+        lock(&self.unsynced).extend(unlogged.drain());
+        Ok(())
+    }
+}
+
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+fn set_or_clear(expiries: &mut HashMap<String, u64>, key: &str, ttl_ms: Option<u64>) {
+    match ttl_ms {
+        Some(at) => expiries.insert(key.to_string(), at),
+        None => expiries.remove(key),
+    };
+}
+
+fn record(op: &str, key: &str, value: Option<&str>, ttl_ms: Option<u64>) -> WalRecord {
+    WalRecord {
+        op: op.to_string(),
+        key: key.to_string(),
+        value: value.map(|v| v.as_bytes().to_vec()),
+        ts: now_ms(),
+        ttl_ms,
+        clock: Vec::new(),
+    }
+}
+
+impl KVEngineStoreTrait for WalEngine {
+    fn get(&self, key: &str) -> Option<String> {
+        self.inner.get(key)
+    }
+
+    fn set(&self, key: String, value: String) -> Result<()> {
+        self.log("set", &key, Some(&value), None)?;
+        lock(&self.expiries).remove(&key);
+        self.inner.set(key, value)
+    }
+
+    fn delete(&self, key: &str) -> bool {
+        if !self.inner.exists(key) {
+            return false;
+        }
+        if let Err(e) = self.log("del", key, None, None) {
+            log::error!("Delete of {:?} not applied: {}", key, e);
+            return false;
+        }
+        lock(&self.expiries).remove(key);
+        self.inner.delete(key)
+    }
+
+    fn keys(&self) -> Vec<String> {
+        self.inner.keys()
+    }
+
+    fn scan(&self, prefix: &str) -> Vec<String> {
+        self.inner.scan(prefix)
+    }
+
+    fn ping(&self, message: &str) -> String {
+        self.inner.ping(message)
+    }
+
+    fn echo(&self, message: &str) -> String {
+        self.inner.echo(message)
+    }
+
+    fn exists(&self, key: &str) -> bool {
+        self.inner.exists(key)
+    }
+
+    fn memory_usage(&self) -> usize {
+        self.inner.memory_usage()
+    }
+
+    fn len(&self) -> usize {
+        self.inner.len()
+    }
+
+    fn dbsize(&self) -> usize {
+        self.inner.dbsize()
+    }
+
+    fn is_empty(&self) -> bool {
+        self.inner.is_empty()
+    }
+
+    fn increment(&self, key: &str, amount: Option<i64>) -> Result<i64> {
+        self.logged_update(key, || self.inner.increment(key, amount))
+    }
+
+    fn decrement(&self, key: &str, amount: Option<i64>) -> Result<i64> {
+        self.logged_update(key, || self.inner.decrement(key, amount))
+    }
+
+    fn append(&self, key: &str, value: &str) -> Result<String> {
+        self.logged_update(key, || self.inner.append(key, value))
+    }
+
+    fn prepend(&self, key: &str, value: &str) -> Result<String> {
+        self.logged_update(key, || self.inner.prepend(key, value))
+    }
+
+    fn truncate(&self) -> Result<()> {
+        self.log("truncate", "", None, None)?;
+        lock(&self.expiries).clear();
+        self.inner.truncate()
+    }
+
+    fn count_keys(&self) -> Result<u64> {
+        self.inner.count_keys()
+    }
+
+    fn sync(&self) -> Result<()> {
+        if let Some(writer) = self.writer_guard().as_mut() {
+            self.fsync(writer)?;
+        }
+        self.inner.sync()
+    }
+
+    fn sample(&self, limit: usize) -> Vec<(String, String)> {
+        self.inner.sample(limit)
+    }
+
+    fn random_key(&self) -> Option<String> {
+        self.inner.random_key()
+    }
+
+    fn touch(&self, key: &str) -> bool {
+        self.inner.touch(key)
+    }
+
+    fn compact_memory(&self, part: usize) -> bool {
+        self.inner.compact_memory(part)
+    }
+
+    fn storage_stats(&self) -> Option<StorageStats> {
+        self.inner.storage_stats()
+    }
+
+    fn shard_stats(&self) -> Option<Vec<ShardStats>> {
+        self.inner.shard_stats()
+    }
+
+    fn warm(&self, key: &str) -> Result<bool> {
+        self.inner.warm(key)
+    }
+
+    fn tier_stats(&self) -> Option<TierStats> {
+        self.inner.tier_stats()
+    }
+
+    fn compact_storage(&self) -> Result<bool> {
+        self.inner.compact_storage()
+    }
+
+    fn persistence(&self) -> Option<bool> {
+        Some(self.writer_guard().is_some())
+    }
+
+    fn wal_health(&self) -> Option<WalHealth> {
+        Some(WalHealth {
+            write_errors: self.write_errors.load(Ordering::Relaxed),
+            degraded: self.degraded.load(Ordering::Relaxed),
+            fsyncs: self.fsyncs.load(Ordering::Relaxed),
+        })
+    }
+
+    fn durable(&self, key: &str) -> Option<bool> {
+        Some(!lock(&self.unsynced).contains(key) && !lock(&self.unlogged).contains(key))
+    }
+
+    fn log_expiry(&self, key: &str, expires_at_ms: Option<u64>) -> Result<()> {
+        self.log("expire", key, None, expires_at_ms)?;
+        set_or_clear(&mut lock(&self.expiries), key, expires_at_ms);
+        Ok(())
+    }
+
+    fn logged_expiries(&self) -> Vec<(String, u64)> {
+        lock(&self.expiries).iter().map(|(key, &at)| (key.clone(), at)).collect()
+    }
+
+    fn set_persistence(&self, enabled: bool) -> Result<()> {
+        let mut writer = self.writer_guard();
+        match (enabled, writer.is_some()) {
+            (true, false) => {
+                let mut reopened = WalWriter::open(&self.path, self.format)?;
+                if let Err(e) = self.log_unlogged(&mut reopened) {
+                    // Stay off: the keys are still unlogged and the next ON retries
+                    if let Err(cut) = reopened.discard_unflushed() {
+                        log::error!("Cutting the failed records off WAL {} failed: {:#}", self.path.display(), cut);
+                    }
+                    return Err(anyhow!("ERR_DISK write failed: {:#}", e));
+                }
+                *writer = Some(reopened);
+            }
+            (false, true) => {
+                // Take the writer first so nothing appends to the replaced file
+                let mut paused = writer.take().expect("checked above");
+                if let Err(e) = paused.sync().and_then(|()| self.snapshot()) {
+                    // The log was not replaced: keep appending to it
+                    *writer = Some(paused);
+                    return Err(e);
+                }
+                lock(&self.unsynced).clear();
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::store::RwLockEngine;
+
+    fn logged(path: &Path) -> Vec<(String, String, Option<String>)> {
+        wal::replay(path, WalFormat::Bincode)
+            .unwrap()
+            .into_iter()
+            .map(|r| (r.op, r.key, r.value.map(|v| String::from_utf8(v).unwrap())))
+            .collect()
+    }
+
+    fn entry(op: &str, key: &str, value: Option<&str>) -> (String, String, Option<String>) {
+        (op.to_string(), key.to_string(), value.map(str::to_string))
+    }
+
+    fn open(path: &Path) -> WalEngine {
+        WalEngine::open(Box::new(RwLockEngine::new("unused").unwrap()), path, WalFormat::Bincode).unwrap()
+    }
+
+    #[test]
+    fn test_writes_while_off_stay_in_memory_only_until_resumed() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("wal.log");
+        let e = open(&path);
+        assert_eq!(e.persistence(), Some(true));
+        e.set("a".to_string(), "1".to_string()).unwrap();
+        e.increment("n", Some(5)).unwrap();
+        e.delete("missing");
+
+        e.set_persistence(false).unwrap();
+        assert_eq!(e.persistence(), Some(false));
+        e.set("off".to_string(), "x".to_string()).unwrap();
+        assert!(e.delete("a"));
+        assert_eq!(e.get("off"), Some("x".to_string()));
+        assert_eq!(e.get("a"), None);
+        // The snapshot holds the data as of OFF, nothing written since
+        let mut snapshot = logged(&path);
+        snapshot.sort();
+        assert_eq!(snapshot, vec![entry("set", "a", Some("1")), entry("set", "n", Some("5"))]);
+
+        // ON logs what changed meanwhile before anything else
+        e.set_persistence(true).unwrap();
+        let mut caught_up = logged(&path)[2..].to_vec();
+        caught_up.sort();
+        assert_eq!(caught_up, vec![entry("del", "a", None), entry("set", "off", Some("x"))]);
+        e.append("n", "0").unwrap();
+        assert_eq!(logged(&path).last(), Some(&entry("set", "n", Some("50"))));
+        drop(e);
+
+        let restarted = open(&path);
+        assert_eq!(restarted.get("a"), None, "the delete made while off is logged by ON");
+        assert_eq!(restarted.get("off"), Some("x".to_string()));
+        assert_eq!(restarted.get("n"), Some("50".to_string()));
+    }
+
+    #[test]
+    fn test_truncate_while_off_is_logged_by_persistence_on() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("wal.log");
+        let e = open(&path);
+        e.set("a".to_string(), "1".to_string()).unwrap();
+        e.set("b".to_string(), "2".to_string()).unwrap();
+        e.set_persistence(false).unwrap();
+        e.truncate().unwrap();
+        e.set("b".to_string(), "3".to_string()).unwrap();
+        e.set_persistence(true).unwrap();
+        drop(e);
+
+        let restarted = open(&path);
+        assert_eq!(restarted.keys(), vec!["b".to_string()]);
+        assert_eq!(restarted.get("b"), Some("3".to_string()));
+    }
+
+    #[test]
+    fn test_failed_snapshot_keeps_persistence_on() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("wal.log");
+        let e = open(&path);
+        e.set("a".to_string(), "1".to_string()).unwrap();
+        // A directory where the snapshot file goes makes writing it fail
+        fs::create_dir(path.with_extension("snapshot.tmp")).unwrap();
+        assert!(e.set_persistence(false).is_err());
+        assert_eq!(e.persistence(), Some(true));
+        e.set("b".to_string(), "2".to_string()).unwrap();
+        assert_eq!(e.durable("b"), Some(false), "logged, not yet fsynced");
+        drop(e);
+
+        let mut restarted = open(&path).keys();
+        restarted.sort();
+        assert_eq!(restarted, vec!["a".to_string(), "b".to_string()]);
+    }
+
+    #[test]
+    fn test_replay_applies_deletes_and_truncate() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("wal.log");
+        let e = open(&path);
+        e.set("gone".to_string(), "1".to_string()).unwrap();
+        e.truncate().unwrap();
+        e.set("k".to_string(), "v".to_string()).unwrap();
+        e.set("d".to_string(), "v".to_string()).unwrap();
+        assert!(e.delete("d"));
+        e.sync().unwrap();
+        drop(e);
+
+        let restarted = open(&path);
+        assert_eq!(restarted.keys(), vec!["k".to_string()]);
+    }
+
+    #[test]
+    fn test_failed_append_rejects_the_write_and_leaves_memory_unchanged() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("wal.log");
+        let e = open(&path);
+        e.set("n".to_string(), "1".to_string()).unwrap();
+        assert_eq!(e.wal_health(), Some(WalHealth { write_errors: 0, degraded: false, fsyncs: 0 }));
+
+        // Every write to /dev/full fails with ENOSPC, like a full disk
+        let full = fs::OpenOptions::new().write(true).open("/dev/full").unwrap();
+        let working = e.writer_guard().replace(WalWriter::from_file(full, WalFormat::Bincode));
+        let err = e.set("k".to_string(), "v".to_string()).unwrap_err().to_string();
+        assert!(err.starts_with("ERR_DISK write failed: "), "{}", err);
+        assert_eq!(e.get("k"), None);
+        assert!(e.increment("n", Some(1)).is_err());
+        assert!(e.append("new", "x").is_err());
+        assert!(!e.delete("n"));
+        assert!(e.truncate().is_err());
+        assert_eq!(e.keys(), vec!["n".to_string()]);
+        assert_eq!(e.get("n"), Some("1".to_string()));
+        assert_eq!(e.wal_health(), Some(WalHealth { write_errors: 5, degraded: true, fsyncs: 0 }));
+
+        *e.writer_guard() = working;
+        e.set("k".to_string(), "v".to_string()).unwrap();
+        assert_eq!(e.wal_health(), Some(WalHealth { write_errors: 5, degraded: false, fsyncs: 0 }));
+        drop(e);
+        let mut restarted = open(&path).keys();
+        restarted.sort();
+        assert_eq!(restarted, vec!["k".to_string(), "n".to_string()]);
+    }
+
+    #[test]
+    fn test_exceeding_max_dirty_writes_fsyncs_immediately() {
+        let dir = tempfile::tempdir().unwrap();
+        let e = open(&dir.path().join("wal.log")).with_max_dirty_writes(3);
+        let fsyncs = |e: &WalEngine| e.wal_health().unwrap().fsyncs;
+        for i in 0..3 {
+            e.set(format!("k{}", i), "v".to_string()).unwrap();
+        }
+        assert_eq!(fsyncs(&e), 0, "three unsynced writes are within the bound");
+        e.increment("n", None).unwrap();
+        assert_eq!(fsyncs(&e), 1, "the fourth write fsyncs");
+        for i in 0..7 {
+            e.set(format!("burst{}", i), "v".to_string()).unwrap();
+        }
+        assert_eq!(fsyncs(&e), 2, "the burst fsyncs at its fourth write");
+
+        // SYNC restarts the count
+        e.sync().unwrap();
+        assert_eq!(fsyncs(&e), 3);
+        for i in 0..3 {
+            e.set(format!("k{}", i), "w".to_string()).unwrap();
+        }
+        assert_eq!(fsyncs(&e), 3);
+        e.set("after".to_string(), "v".to_string()).unwrap();
+        assert_eq!(fsyncs(&e), 4);
+
+        let unbounded = open(&dir.path().join("other.log"));
+        for i in 0..100 {
+            unbounded.set(format!("k{}", i), "v".to_string()).unwrap();
+        }
+        assert_eq!(fsyncs(&unbounded), 0);
+    }
+
+    #[test]
+    fn test_keys_are_durable_once_their_last_write_is_fsynced() {
+        let dir = tempfile::tempdir().unwrap();
+        let e = open(&dir.path().join("wal.log")).with_max_dirty_writes(2);
+        e.set("a".to_string(), "1".to_string()).unwrap();
+        e.increment("n", None).unwrap();
+        assert_eq!(e.durable("a"), Some(false));
+        assert_eq!(e.durable("n"), Some(false));
+        // The third write exceeds the bound and fsyncs all three
+        e.set("b".to_string(), "2".to_string()).unwrap();
+        assert_eq!(e.durable("a"), Some(true));
+        e.set("a".to_string(), "3".to_string()).unwrap();
+        assert_eq!(e.durable("a"), Some(false), "a new write is pending again");
+        assert_eq!(e.durable("b"), Some(true));
+        e.sync().unwrap();
+        assert_eq!(e.durable("a"), Some(true));
+
+        // A write while off is not durable until ON logs it and it is fsynced
+        e.set_persistence(false).unwrap();
+        e.set("off".to_string(), "x".to_string()).unwrap();
+        assert_eq!(e.durable("off"), Some(false));
+        assert_eq!(e.durable("a"), Some(true), "the snapshot is fsynced");
+        e.set_persistence(true).unwrap();
+        assert_eq!(e.durable("off"), Some(false));
+        e.sync().unwrap();
+        assert_eq!(e.durable("off"), Some(true));
+    }
+
+    #[test]
+    fn test_expiries_survive_a_restart() {
+        use crate::store::expiring::ExpiringEngine;
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("wal.log");
+        let far = now_ms() + 3_600_000;
+        let e = ExpiringEngine::new(Box::new(open(&path)), false);
+        for key in ["ttl", "counter", "reset", "persisted", "lapsed"] {
+            e.set(key.to_string(), "1".to_string()).unwrap();
+        }
+        assert!(e.set_expiry("ttl", Some(far)).unwrap());
+        assert!(e.set_expiry("counter", Some(far)).unwrap());
+        e.increment("counter", Some(1)).unwrap();
+        assert!(e.set_expiry("reset", Some(far)).unwrap());
+        e.set("reset".to_string(), "2".to_string()).unwrap();
+        assert!(e.set_expiry("persisted", Some(far)).unwrap());
+        assert!(e.set_expiry("persisted", None).unwrap());
+        assert!(e.set_expiry("lapsed", Some(now_ms() + 20)).unwrap());
+        drop(e);
+        std::thread::sleep(std::time::Duration::from_millis(40));
+
+        let check = |e: &ExpiringEngine| {
+            assert_eq!(e.expiry("ttl"), Some(far));
+            assert_eq!(e.expiry("counter"), Some(far), "INCR keeps the TTL");
+            assert_eq!(e.get("counter"), Some("2".to_string()));
+            assert_eq!(e.expiry("reset"), None, "SET clears it");
+            assert_eq!(e.expiry("persisted"), None);
+            assert_eq!(e.get("lapsed"), None, "expired while down");
+        };
+        let restarted = ExpiringEngine::new(Box::new(open(&path)), false);
+        check(&restarted);
+
+        // A PERSISTENCE OFF snapshot keeps them too
+        restarted.set_persistence(false).unwrap();
+        drop(restarted);
+        check(&ExpiringEngine::new(Box::new(open(&path)), false));
+    }
+}
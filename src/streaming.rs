@@ -0,0 +1,125 @@
+//! # Chunked Value Transfer
+//!
+//! Large values can be moved in frames instead of on one protocol line, so no
+//! single read or write has to buffer the whole line and the connection task
+//! yields to others between frames.
+//!
+//! ## Download
+//!
+//! `GET key STREAM` replies `STREAM <len>\r\n`, then the value as frames of
+//! at most `server.stream_chunk_bytes`, then `END\r\n` (or `NOT_FOUND\r\n`):
+//!
+//! ```text
+//! CHUNK <n>\r\n<n bytes>\r\n
+//! ```
+//!
+//! ## Upload
+//!
+//! `SET key STREAM <len>` replies `READY <max frame bytes>\r\n` (or an `ERROR`
+//! if `<len>` exceeds `server.max_stream_value_bytes`). The client then sends
+//! `CHUNK` frames totalling exactly `<len>` bytes, and the server stores the
+//! value as a plain `SET` would and replies the same way. Values must be UTF-8
+//! without newlines, like any other value. A malformed frame closes the
+//! connection, since the framing can no longer be trusted.
+
+use anyhow::{anyhow, Result};
+use tokio::io::{AsyncBufRead, AsyncBufReadExt, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+/// Longest `CHUNK <n>` header line accepted, CRLF included.
+const MAX_HEADER_BYTES: u64 = 32;
+
+/// Send `value` as `STREAM <len>` followed by `CHUNK` frames of at most
+/// `chunk_bytes`. The closing `END` is left to the caller.
+pub async fn write_chunks<W: AsyncWrite + Unpin>(writer: &mut W, value: &[u8], chunk_bytes: usize) -> std::io::Result<()> {
+    writer.write_all(format!("STREAM {}\r\n", value.len()).as_bytes()).await?;
+    for chunk in value.chunks(chunk_bytes.max(1)) {
+        writer.write_all(format!("CHUNK {}\r\n", chunk.len()).as_bytes()).await?;
+        writer.write_all(chunk).await?;
+        writer.write_all(b"\r\n").await?;
+        tokio::task::yield_now().await;
+    }
+    Ok(())
+}
+
+/// Read `CHUNK` frames until exactly `len` bytes have arrived.
+///
+/// # Errors
+/// Fails on EOF, a malformed header or terminator, an empty frame, or a frame
+/// larger than `max_chunk` or past `len`.
+pub async fn read_chunks<R: AsyncBufRead + Unpin>(reader: &mut R, len: usize, max_chunk: usize) -> Result<Vec<u8>> {
+    let mut value = Vec::with_capacity(len);
+    while value.len() < len {
+        let mut header = String::new();
+        (&mut *reader).take(MAX_HEADER_BYTES).read_line(&mut header).await?;
+        if !header.ends_with('\n') {
+            return Err(anyhow!("expected CHUNK <n> frame header"));
+        }
+        let n: usize = match header.trim_end().split_once(' ') {
+            Some(("CHUNK", n)) => n.parse().map_err(|_| anyhow!("invalid chunk length '{}'", n))?,
+            _ => return Err(anyhow!("expected CHUNK <n>, got '{}'", header.trim_end())),
+        };
+        if n == 0 || n > max_chunk {
+            return Err(anyhow!("chunk length must be 1..={}, got {}", max_chunk, n));
+        }
+        if value.len() + n > len {
+            return Err(anyhow!("chunks exceed the announced {} bytes", len));
+        }
+        let start = value.len();
+        value.resize(start + n, 0);
+        reader.read_exact(&mut value[start..]).await?;
+        let mut crlf = [0u8; 2];
+        reader.read_exact(&mut crlf).await?;
+        if &crlf != b"\r\n" {
+            return Err(anyhow!("chunk not terminated by CRLF"));
+        }
+        tokio::task::yield_now().await;
+    }
+    Ok(value)
+}
+
+/// Check an uploaded value against the rules for line-based values.
+pub fn into_value(bytes: Vec<u8>) -> Result<String> {
+    let value = String::from_utf8(bytes).map_err(|_| anyhow!("streamed value is not valid UTF-8"))?;
+    if value.contains('\n') {
+        return Err(anyhow!("newline character not allowed in value"));
+    }
+    Ok(value)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::BufReader;
+
+    #[tokio::test]
+    async fn test_chunks_roundtrip() {
+        let value = "0123456789".repeat(10);
+        let mut wire = Vec::new();
+        write_chunks(&mut wire, value.as_bytes(), 32).await.unwrap();
+        let header = b"STREAM 100\r\n";
+        assert!(wire.starts_with(header));
+        assert!(wire[header.len()..].starts_with(b"CHUNK 32\r\n"));
+
+        let mut reader = BufReader::new(&wire[header.len()..]);
+        assert_eq!(read_chunks(&mut reader, 100, 32).await.unwrap(), value.as_bytes());
+        assert_eq!(read_chunks(&mut BufReader::new(&b""[..]), 0, 32).await.unwrap(), b"");
+    }
+
+    #[tokio::test]
+    async fn test_bad_frames_are_rejected() {
+        for (wire, max_chunk) in [
+            (&b"CHUNK 4\r\nabcd\r\n"[..], 2),
+            (b"CHUNK 8\r\nabcdefgh\r\n", 8),
+            (b"CHUNK 4\r\nabcdXX", 8),
+            (b"CHUNK 0\r\n", 8),
+            (b"PUT 4\r\nabcd\r\n", 8),
+            (b"CHUNK 2\r\nab\r\n", 8),
+        ] {
+            let result = read_chunks(&mut BufReader::new(wire), 4, max_chunk).await;
+            assert!(result.is_err(), "{:?}", String::from_utf8_lossy(wire));
+        }
+        assert!(into_value(vec![0xff]).is_err());
+        assert!(into_value(b"a\nb".to_vec()).is_err());
+        assert_eq!(into_value(b"a b".to_vec()).unwrap(), "a b");
+    }
+}
@@ -0,0 +1,171 @@
+//! # Resumable Subscriptions (`merkle.subscribe_buffer`)
+//!
+//! With `merkle.subscribe_buffer` set, `SUBSCRIBE MERKLE` answers
+//! `SUBSCRIBED MERKLE <id>`. When the connection drops, the subscription is
+//! parked: for `merkle.subscribe_resume_seconds` it keeps recording the root
+//! changes it would have pushed, the newest `subscribe_buffer` of them. A
+//! client that reconnects sends `SUBSCRIBE MERKLE <id> <version>`, with the
+//! version of the last `MERKLE_ROOT` it received, and first gets the recorded
+//! changes after that version, then live ones as before.
+//!
+//! This is best-effort: changes beyond the buffer are lost (the replay starts
+//! at the oldest one kept), and an unknown or expired id starts a new
+//! subscription under a new id, which tells the client it missed changes.
+
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::Duration;
+use tokio::sync::watch;
+use tokio::task::JoinHandle;
+use tokio::time::Instant;
+
+use crate::store::merkle_tracked::{self, SharedMerkle};
+
+/// A pushed root change: the tree's write counter and the root.
+pub type RootChange = (u64, String);
+
+/// State a subscription carries across a reconnect.
+#[derive(Debug, Default)]
+pub struct Session {
+    /// Resume id; None when resuming is disabled
+    pub id: Option<String>,
+    /// Last root pushed or recorded
+    pub last_root: Option<String>,
+    /// Recorded changes to push before the live ones
+    pub backlog: Vec<RootChange>,
+}
+
+/// What a parked subscription recorded so far.
+#[derive(Default)]
+struct Recorded {
+    changes: VecDeque<RootChange>,
+    last_root: Option<String>,
+}
+
+struct Parked {
+    recorded: Arc<Mutex<Recorded>>,
+    recorder: JoinHandle<()>,
+}
+
+/// Subscriptions whose client went away, waiting to be resumed.
+pub struct ParkedSubscriptions {
+    /// `merkle.subscribe_buffer` (0 = resuming disabled)
+    capacity: usize,
+    /// `merkle.subscribe_resume_seconds`
+    linger: Duration,
+    parked: Mutex<HashMap<String, Parked>>,
+}
+
+impl ParkedSubscriptions {
+    pub fn new(capacity: usize, linger: Duration) -> Self {
+        Self { capacity, linger, parked: Mutex::new(HashMap::new()) }
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.capacity > 0
+    }
+
+    /// A new session: with a fresh id when resuming is enabled.
+    pub fn start(&self) -> Session {
+        Session { id: self.enabled().then(|| uuid::Uuid::new_v4().simple().to_string()), ..Session::default() }
+    }
+
+    /// Take back the subscription `id`, with the changes recorded after
+    /// `since`; a new session if `id` is unknown or expired.
+    pub fn resume(&self, id: &str, since: u64) -> Session {
+        let Some(parked) = self.guard().remove(id) else { return self.start() };
+        parked.recorder.abort();
+        let recorded = lock(&parked.recorded);
+        Session {
+            id: Some(id.to_string()),
+            last_root: recorded.last_root.clone(),
+            backlog: recorded.changes.iter().filter(|(version, _)| *version > since).cloned().collect(),
+        }
+    }
+
+    /// Keep recording the root changes of a disconnected `session` (see
+    /// `Server::run_merkle_subscription` for the debounce) until it is
+    /// resumed or `linger` passes.
+    pub fn park(self: &Arc<Self>, session: Session, merkle: SharedMerkle, mut changes: watch::Receiver<u64>, interval: Duration) {
+        let Some(id) = session.id else { return };
+        let recorded = Arc::new(Mutex::new(Recorded { changes: session.backlog.into(), last_root: session.last_root }));
+        let this = Arc::clone(self);
+        let state = Arc::clone(&recorded);
+        let key = id.clone();
+        let recorder = tokio::spawn(async move {
+            let deadline = Instant::now() + this.linger;
+            loop {
+                let version = *changes.borrow_and_update();
+                let root = merkle_tracked::live_root_hex(&merkle);
+                {
+                    let mut state = lock(&state);
+                    if state.last_root.as_ref() != Some(&root) {
+                        state.changes.push_back((version, root.clone()));
+                        while state.changes.len() > this.capacity {
+                            state.changes.pop_front();
+                        }
+                        state.last_root = Some(root);
+                    }
+                }
+                let next_change = async {
+                    tokio::time::sleep(interval).await;
+                    if changes.changed().await.is_err() {
+                        std::future::pending::<()>().await;
+                    }
+                };
+                tokio::select! {
+                    _ = tokio::time::sleep_until(deadline) => break,
+                    _ = next_change => {}
+                }
+            }
+            let mut parked = this.guard();
+            if parked.get(&key).is_some_and(|p| Arc::ptr_eq(&p.recorded, &state)) {
+                parked.remove(&key);
+            }
+        });
+        self.guard().insert(id, Parked { recorded, recorder });
+    }
+
+    fn guard(&self) -> MutexGuard<'_, HashMap<String, Parked>> {
+        lock(&self.parked)
+    }
+}
+
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::store::merkle::MerkleTree;
+
+    #[tokio::test]
+    async fn test_parked_subscription_keeps_the_newest_changes_until_it_expires() {
+        let merkle: SharedMerkle = Arc::new(Mutex::new(MerkleTree::new()));
+        let (tx, rx) = watch::channel(0u64);
+        let subs = Arc::new(ParkedSubscriptions::new(2, Duration::from_millis(300)));
+        let session = subs.start();
+        let id = session.id.clone().unwrap();
+        subs.park(Session { last_root: Some(merkle_tracked::live_root_hex(&merkle)), ..session }, Arc::clone(&merkle), rx, Duration::from_millis(5));
+
+        for (version, key) in [(1, "a"), (2, "b"), (3, "c")] {
+            merkle.lock().unwrap().insert(key, "v");
+            tx.send(version).unwrap();
+            tokio::time::sleep(Duration::from_millis(30)).await;
+        }
+        let resumed = subs.resume(&id, 1);
+        assert_eq!(resumed.id.as_deref(), Some(id.as_str()));
+        let versions: Vec<u64> = resumed.backlog.iter().map(|(v, _)| *v).collect();
+        assert_eq!(versions, vec![2, 3], "the buffer keeps the newest two");
+        assert_eq!(resumed.last_root, Some(merkle_tracked::live_root_hex(&merkle)));
+
+        // Resuming twice, or after the linger, starts over
+        assert_ne!(subs.resume(&id, 0).id.as_deref(), Some(id.as_str()));
+        let (_tx, rx) = watch::channel(0u64);
+        subs.park(resumed, merkle, rx, Duration::from_millis(5));
+        tokio::time::sleep(Duration::from_millis(400)).await;
+        assert_ne!(subs.resume(&id, 0).id.as_deref(), Some(id.as_str()));
+        assert!(ParkedSubscriptions::new(0, Duration::ZERO).start().id.is_none());
+    }
+}
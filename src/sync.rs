@@ -9,35 +9,186 @@
 //! Notes:
 //! - This is intentionally simple (no prefix fanout).
 //! - Wire format matches your server.rs today:
-//!     SCAN <prefix>   →  "KEYS <n>\r\n<k1>\r\n...<kn>\r\n"
-//!     GET <key>       →  "VALUE <plain>\r\n"   or "NOT_FOUND\r\n"
+//!   SCAN <prefix>   →  "KEYS <n>\r\n<k1>\r\n...<kn>\r\n"
+//!   GET <key>       →  "VALUE <plain>\r\n"   or "NOT_FOUND\r\n"
 //! - MerkleTree expects &str values, so we assume UTF-8 strings.
+//! - Every peer RPC is bounded by `merkle.sync_timeout_ms`. A hung peer aborts
+//!   the round *before* anything is applied (the remote snapshot is complete
+//!   or the round fails), so local data is never left half-synced.
+//! - Tombstones: the peer's live tombstones are fetched with the snapshot
+//!   (`TOMBSTONES` → `"TOMBSTONES <n>\r\n<key> <deleted_at_ms>\r\n..."`) and
+//!   merged locally. A key this node holds a live tombstone for is re-created
+//!   from the peer only if the peer wrote it after the delete (its
+//!   `OBJECT MODIFIED` time is later than `deleted_at`); the delete wins ties
+//!   and peers that cannot say. This stops a lagging peer from resurrecting a
+//!   delete within `replication.tombstone_ttl_seconds` without losing a newer
+//!   write. Peers without the command are treated as having no tombstones.
+//! - Keys rejected by the replication prefix filter (`KeyFilter`) are
+//!   node-local: they are left out of both snapshots, so sync neither copies
+//!   the peer's local keys nor deletes ours. Keys holding a `SET ... LOCAL`
+//!   value are left out of the local snapshot and never overwritten here.
+//! - With `anti_entropy.enabled`, `run_anti_entropy_loop` syncs with every peer
+//!   in `anti_entropy.peer_list`, each on its own schedule: the peer's entry in
+//!   `anti_entropy.peer_intervals`, else the global `sync_interval_seconds`.
+//!   A failing peer is logged and retried at its next slot. Up to
+//!   `merkle.sync_concurrency` peers are synced at the same time; rounds
+//!   starting together diff against one local snapshot instead of each
+//!   scanning the store, and apply one after the other under the store lock.
+//! - `SYNC STATUS` reports the oldest round in flight (peer, phase, keys
+//!   compared against the peer's SCAN count, keys transferred, and how many
+//!   rounds run concurrently) from `SyncProgress`, which is shared outside the
+//!   manager's lock. `SYNC ABORT` cancels every round still fetching the
+//!   remote snapshot; the apply step runs under the store lock and is never
+//!   interrupted, so an aborted round applies nothing.
+//! - Every round's result, and the `client_id` the peer reports in INFO, is
+//!   recorded in the `PeerTable` shown by `CLUSTER PEERS`.
 //!
 //! How the SYNC command handler should call this:
 //!     let mut mgr = sync_manager.lock().await;
 //!     match mgr.sync_once(&host, port).await { Ok(_) => "OK", Err(e) => ... };
 
 use anyhow::{anyhow, Context, Result};
-use log::{debug, info};
+use log::{debug, info, warn};
 use std::collections::HashMap;
-use std::sync::Arc;
+use std::future::Future;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, MutexGuard};
 use std::time::Duration;
 use tokio::{
-    io::{AsyncBufReadExt, AsyncWriteExt, BufReader},
+    io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader},
     net::TcpStream,
     sync::Mutex,
+    task::JoinSet,
     time,
 };
 
 use crate::config::Config;
+use crate::key_filter::KeyFilter;
+use crate::net_addr;
+use crate::peers::PeerTable;
 use crate::store::merkle::MerkleTree;
-use crate::store::KVEngineStoreTrait;
+use crate::store::{HashFn, KVEngineStoreTrait};
+use crate::tasks::TaskHandle;
 
+/// A sync round in flight, as reported by `SYNC STATUS`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RoundStatus {
+    /// Peer `host:port`
+    pub peer: String,
+    /// `snapshot` (fetching the peer's data) or `apply`
+    pub phase: &'static str,
+    /// Keys in the peer's SCAN reply
+    pub keys_total: usize,
+    /// Remote keys fetched and compared so far
+    pub keys_compared: usize,
+    /// Local keys written or deleted to match the peer
+    pub keys_transferred: usize,
+}
+
+/// Progress of the sync rounds in flight, readable while they hold the manager.
+#[derive(Default)]
+pub struct SyncProgress {
+    /// Oldest first
+    rounds: std::sync::Mutex<Vec<RoundStatus>>,
+    abort: AtomicBool,
+}
+
+impl SyncProgress {
+    /// The oldest round in flight, if any.
+    #[cfg(test)]
+    pub fn status(&self) -> Option<RoundStatus> {
+        self.rounds().first().cloned()
+    }
+
+    /// Ask the rounds in flight to stop.
+    ///
+    /// # Returns
+    /// * `bool` - False if no round is running
+    pub fn abort(&self) -> bool {
+        let rounds = self.rounds();
+        if !rounds.is_empty() {
+            self.abort.store(true, Ordering::Relaxed);
+        }
+        !rounds.is_empty()
+    }
+
+    /// `SYNC STATUS` reply; `concurrent:<n>` is added while several rounds run.
+    pub fn format(&self) -> String {
+        let rounds = self.rounds();
+        match rounds.first() {
+            None => "SYNC_STATUS idle\r\n".to_string(),
+            Some(r) => format!(
+                "SYNC_STATUS running peer:{} phase:{} keys_compared:{} keys_total:{} keys_transferred:{} progress:{}%{}\r\n",
+                r.peer,
+                r.phase,
+                r.keys_compared,
+                r.keys_total,
+                r.keys_transferred,
+                (r.keys_compared * 100).checked_div(r.keys_total).unwrap_or(0),
+                if rounds.len() > 1 { format!(" concurrent:{}", rounds.len()) } else { String::new() },
+            ),
+        }
+    }
+
+    fn rounds(&self) -> MutexGuard<'_, Vec<RoundStatus>> {
+        self.rounds.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn begin(&self, peer: &str) {
+        let mut rounds = self.rounds();
+        if rounds.is_empty() {
+            self.abort.store(false, Ordering::Relaxed);
+        }
+        rounds.push(RoundStatus { peer: peer.to_string(), phase: "snapshot", keys_total: 0, keys_compared: 0, keys_transferred: 0 });
+    }
+
+    fn end(&self, peer: &str) {
+        let mut rounds = self.rounds();
+        if let Some(i) = rounds.iter().position(|r| r.peer == peer) {
+            rounds.remove(i);
+        }
+        if rounds.is_empty() {
+            self.abort.store(false, Ordering::Relaxed);
+        }
+    }
+
+    fn update(&self, peer: &str, f: impl FnOnce(&mut RoundStatus)) {
+        if let Some(round) = self.rounds().iter_mut().find(|r| r.peer == peer) {
+            f(round);
+        }
+    }
+
+    /// Fail if `SYNC ABORT` was sent for this round.
+    fn check_abort(&self) -> Result<()> {
+        if self.abort.load(Ordering::Relaxed) {
+            return Err(anyhow!("sync aborted by SYNC ABORT"));
+        }
+        Ok(())
+    }
+}
+
+#[derive(Clone)]
 pub struct SyncManager {
     /// Shared storage used by all connections and the sync process
     store: Arc<Mutex<Box<dyn KVEngineStoreTrait + Send + Sync>>>,
-    /// Optional background interval
-    sync_interval: Duration,
+    /// Background anti-entropy interval in seconds (changeable via `CONFIG SET`)
+    sync_interval_seconds: Arc<AtomicU64>,
+    /// Deadline for each peer RPC (`merkle.sync_timeout_ms`)
+    rpc_timeout: Duration,
+    /// Local key placement hash; peers are expected to use the same one
+    hash_fn: HashFn,
+    /// Merkle leaf buckets (`merkle.num_buckets`); peers are expected to agree
+    num_buckets: usize,
+    /// Node-local keys (replication prefix filter) are left out of both snapshots
+    key_filter: KeyFilter,
+    /// Per-peer overrides of the anti-entropy interval (`anti_entropy.peer_intervals`)
+    peer_intervals: HashMap<String, Duration>,
+    /// Anti-entropy rounds run at the same time (`merkle.sync_concurrency`)
+    sync_concurrency: usize,
+    /// Round in flight, for `SYNC STATUS` / `SYNC ABORT`
+    progress: Arc<SyncProgress>,
+    /// Per-peer sync results, for `CLUSTER PEERS`
+    peers: Arc<PeerTable>,
 }
 
 impl SyncManager {
@@ -48,66 +199,237 @@ impl SyncManager {
     ) -> Self {
         Self {
             store,
-            sync_interval: Duration::from_secs(cfg.sync_interval_seconds),
+            sync_interval_seconds: Arc::new(AtomicU64::new(cfg.sync_interval_seconds)),
+            rpc_timeout: Duration::from_millis(cfg.merkle.sync_timeout_ms),
+            hash_fn: cfg.storage.hash_fn,
+            num_buckets: cfg.merkle.num_buckets,
+            key_filter: KeyFilter::from_config(&cfg.replication),
+            peer_intervals: cfg
+                .anti_entropy
+                .peer_intervals
+                .iter()
+                .map(|(peer, secs)| (peer.clone(), Duration::from_secs((*secs).max(1))))
+                .collect(),
+            sync_concurrency: cfg.merkle.sync_concurrency.max(1),
+            progress: Arc::new(SyncProgress::default()),
+            peers: Arc::new(PeerTable::from_config(cfg)),
         }
     }
 
+    /// Shared handle to the progress of the round in flight.
+    pub fn progress_handle(&self) -> Arc<SyncProgress> {
+        Arc::clone(&self.progress)
+    }
+
+    /// Shared handle to the configured and synced peers.
+    pub fn peers_handle(&self) -> Arc<PeerTable> {
+        Arc::clone(&self.peers)
+    }
+
+    /// Shared handle to the anti-entropy interval (seconds); changes apply from the next round.
+    pub fn interval_handle(&self) -> Arc<AtomicU64> {
+        Arc::clone(&self.sync_interval_seconds)
+    }
+
     /// One-shot sync: make local data equal to remote data.
-    pub async fn sync_once(&mut self, host: &str, port: u16) -> Result<()> {
-        let addr = format!("{host}:{port}");
+    pub async fn sync_once(&self, host: &str, port: u16) -> Result<()> {
+        self.sync_addr(&net_addr::join_host_port(host, port), None).await
+    }
+
+    /// One round with `addr`, diffing against `local` when given (a snapshot
+    /// shared by rounds started together) instead of a fresh one.
+    async fn sync_addr(&self, addr: &str, local: Option<Arc<MerkleTree>>) -> Result<()> {
+        self.progress.begin(addr);
+        let result = self.sync_round(addr, local).await;
+        self.progress.end(addr);
+        self.peers.sync_finished(addr, &result);
+        result
+    }
+
+    async fn sync_round(&self, addr: &str, local: Option<Arc<MerkleTree>>) -> Result<()> {
         info!("SYNC (Merkle diff) → {}", addr);
 
+        // 0) Placement sanity check: bucket layouts only line up if both nodes
+        //    hash keys the same way into as many buckets. A mismatch is
+        //    reported but not fatal.
+        self.check_remote_placement(addr, "hash_fn", self.hash_fn.as_str(), "storage.hash_fn").await;
+        self.check_remote_placement(addr, "merkle_buckets", &self.num_buckets.to_string(), "merkle.num_buckets").await;
+        if let Ok(Some(client_id)) = self.with_deadline(addr, "INFO", self.read_remote_info_field(addr, "client_id")).await {
+            self.peers.identify(addr, &client_id);
+        }
+
         // 1) Local snapshot
-        let (local_tree, _local_map) = self.build_local_merkle_snapshot().await;
+        let local_tree = match local {
+            Some(tree) => tree,
+            None => Arc::new(self.build_local_merkle_snapshot().await.0),
+        };
 
-        // 2) Remote snapshot
-        let (remote_tree, remote_map) = self.build_remote_merkle_snapshot(&addr).await?;
+        // 2) Remote snapshot (data + live tombstones)
+        let (remote_tree, remote_map) = self.build_remote_merkle_snapshot(addr).await?;
+        let remote_tombstones = self
+            .with_deadline(addr, "TOMBSTONES", self.read_remote_tombstones(addr))
+            .await?;
 
         // 3) Diff
         let diffs = local_tree.diff_keys(&remote_tree);
-        if diffs.is_empty() {
-            info!("SYNC: already identical (no diff)");
-            return Ok(());
+
+        // 3b) The peer's write times of keys it holds and we deleted
+        let deleted: HashMap<String, u64> = self.store.lock().await.tombstones().into_iter().collect();
+        let mut remote_written = HashMap::new();
+        for k in diffs.iter().filter(|k| remote_map.contains_key(*k) && deleted.contains_key(*k)) {
+            if let Some(at) = self.with_deadline(addr, "OBJECT MODIFIED", self.read_remote_modified(addr, k)).await? {
+                remote_written.insert(k.clone(), at);
+            }
         }
 
-        // 4) Apply changes: local := remote
-        let mut guard = self.store.lock().await;
-        for k in diffs {
-            if let Some(rv) = remote_map.get(&k) {
+        // 4) Apply changes: local := remote, except for keys deleted after the
+        //    peer's write. Last chance to abort: nothing has been applied yet.
+        self.progress.check_abort()?;
+        self.progress.update(addr, |r| r.phase = "apply");
+        let guard = self.store.lock().await;
+        let local_tombstones: HashMap<String, u64> = guard.tombstones().into_iter().collect();
+        let mut kept_deleted = 0;
+        for k in &diffs {
+            if guard.is_local(k) {
+                continue;
+            }
+            if let Some(rv) = remote_map.get(k) {
+                if let Some(&deleted_at) = local_tombstones.get(k) {
+                    if remote_written.get(k).is_none_or(|&written| written <= deleted_at) {
+                        // peer has not seen the delete yet → keep it deleted
+                        kept_deleted += 1;
+                        continue;
+                    }
+                }
                 // set / overwrite
                 let _ = guard.set(k.clone(), rv.clone());
             } else {
                 // missing remotely → delete local
-                let _ = guard.delete(&k);
+                let _ = guard.delete(k);
             }
+            self.progress.update(addr, |r| r.keys_transferred += 1);
+        }
+        for (k, deleted_at) in remote_tombstones {
+            if !self.key_filter.replicates(&k) {
+                continue;
+            }
+            guard.add_tombstone(&k, deleted_at);
         }
 
-        info!("SYNC: done (local now matches remote)");
+        if diffs.is_empty() {
+            info!("SYNC: already identical (no diff)");
+        } else if kept_deleted > 0 {
+            info!("SYNC: done ({} tombstoned keys not resurrected)", kept_deleted);
+        } else {
+            info!("SYNC: done (local now matches remote)");
+        }
         Ok(())
     }
 
-    /// Optional background loop (best-effort).
-    pub async fn start_sync_loop(&mut self, host: String, port: u16) {
-        let mut interval = time::interval(self.sync_interval);
-        let addr = format!("{host}:{port}");
+    /// Interval between two syncs with `peer`: its `anti_entropy.peer_intervals`
+    /// entry, else the current global interval.
+    pub fn peer_interval(&self, peer: &str) -> Duration {
+        self.peer_intervals
+            .get(peer)
+            .copied()
+            .unwrap_or_else(|| Duration::from_secs(self.sync_interval_seconds.load(Ordering::Relaxed).max(1)))
+    }
+
+    /// Background anti-entropy loop. Every peer (`host:port`) is synced once at
+    /// start, then again `peer_interval` after its previous sync finished, so
+    /// nearby peers can be synced often and distant ones rarely. Up to
+    /// `sync_concurrency` peers are synced at once, on copies of the manager
+    /// taken when their rounds start. Errors and timeouts are logged; the
+    /// failed peer is retried at its next slot. Every peer sync is reported to
+    /// `task`; triggering it syncs all idle peers now.
+    pub async fn run_anti_entropy_loop(manager: Arc<Mutex<SyncManager>>, peers: Vec<String>, task: TaskHandle) {
+        let concurrency = manager.lock().await.sync_concurrency;
+        let start = time::Instant::now();
+        // Next sync of each peer; None while its round runs
+        let mut schedule: Vec<(Option<time::Instant>, String)> = peers.into_iter().map(|peer| (Some(start), peer)).collect();
+        let mut running = JoinSet::new();
         loop {
-            interval.tick().await;
-            if let Err(e) = self.sync_once(host.as_str(), port).await {
-                log::warn!("background sync with {} failed: {}", addr, e);
+            let now = time::Instant::now();
+            let mut due: Vec<usize> = (0..schedule.len()).filter(|&i| schedule[i].0.is_some_and(|at| at <= now)).collect();
+            due.sort_by_key(|&i| schedule[i].0);
+            due.truncate(concurrency.saturating_sub(running.len()));
+            if !due.is_empty() {
+                let worker = Arc::new(manager.lock().await.clone());
+                let local = Arc::new(worker.build_local_merkle_snapshot().await.0);
+                for i in due {
+                    schedule[i].0 = None;
+                    let (worker, local, peer) = (Arc::clone(&worker), Arc::clone(&local), schedule[i].1.clone());
+                    let run = task.start();
+                    running.spawn(async move {
+                        let result = worker.sync_peer(&peer, Some(local)).await;
+                        (i, run, result)
+                    });
+                }
+                continue;
+            }
+            let next = schedule.iter().filter_map(|(at, _)| *at).min();
+            if next.is_none() && running.is_empty() {
+                return;
+            }
+            tokio::select! {
+                _ = time::sleep_until(next.unwrap_or(now)), if next.is_some() => {}
+                Some(done) = running.join_next() => match done {
+                    Ok((i, run, result)) => {
+                        let peer = &schedule[i].1;
+                        task.finish(run, result.map_err(|e| format!("{}: {}", peer, e)));
+                        let period = manager.lock().await.peer_interval(peer);
+                        schedule[i].0 = Some(time::Instant::now() + period);
+                    }
+                    Err(e) => warn!("anti-entropy: sync round failed: {}", e),
+                },
+                _ = task.triggered() => {
+                    let now = time::Instant::now();
+                    for slot in schedule.iter_mut().filter(|slot| slot.0.is_some()) {
+                        slot.0 = Some(now);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Run one anti-entropy round over `peers`, returning how many succeeded.
+    #[cfg(test)]
+    pub async fn sync_peers_once(manager: &Arc<Mutex<SyncManager>>, peers: &[String]) -> usize {
+        let mut ok = 0;
+        for peer in peers {
+            if manager.lock().await.sync_peer(peer, None).await.is_ok() {
+                ok += 1;
+            }
+        }
+        ok
+    }
+
+    /// Sync with one `host:port` peer, logging failures.
+    async fn sync_peer(&self, peer: &str, local: Option<Arc<MerkleTree>>) -> Result<()> {
+        let (host, port) = match net_addr::split_host_port(peer) {
+            Ok(hp) => hp,
+            Err(e) => {
+                warn!("anti-entropy: skipping peer: {}", e);
+                return Err(e);
             }
+        };
+        let result = self.sync_addr(&net_addr::join_host_port(&host, port), local).await;
+        if let Err(e) = &result {
+            warn!("anti-entropy: sync with {} abandoned: {}", peer, e);
         }
+        result
     }
 
     // ───────────── Snapshots ─────────────
 
     /// Build local Merkle snapshot from scan("") + get().
     async fn build_local_merkle_snapshot(&self) -> (MerkleTree, HashMap<String, String>) {
-        let mut t = MerkleTree::new();
+        let mut t = MerkleTree::new().with_buckets(self.num_buckets, self.hash_fn);
         let mut map = HashMap::new();
 
-        let mut guard = self.store.lock().await;
+        let guard = self.store.lock().await;
         let keys = guard.scan(""); // empty prefix → all keys
-        for k in keys {
+        for k in keys.into_iter().filter(|k| self.key_filter.replicates(k) && !guard.is_local(k)) {
             if let Some(v) = guard.get(&k) {
                 t.insert(&k, &v);
                 map.insert(k, v);
@@ -123,12 +445,18 @@ impl SyncManager {
         &self,
         addr: &str,
     ) -> Result<(MerkleTree, HashMap<String, String>)> {
-        let keys = self.read_remote_keys_via_scan(addr).await?;
-        let mut t = MerkleTree::new();
+        let keys = self.with_deadline(addr, "SCAN", self.read_remote_keys_via_scan(addr)).await?;
+        self.progress.update(addr, |r| r.keys_total = keys.len());
+        let mut t = MerkleTree::new().with_buckets(self.num_buckets, self.hash_fn);
         let mut map = HashMap::new();
 
         for k in keys {
-            match self.read_remote_value_plain(addr, &k).await? {
+            self.progress.check_abort()?;
+            self.progress.update(addr, |r| r.keys_compared += 1);
+            if !self.key_filter.replicates(&k) {
+                continue;
+            }
+            match self.with_deadline(addr, "GET", self.read_remote_value_plain(addr, &k)).await? {
                 Some(v) => {
                     t.insert(&k, &v);
                     map.insert(k, v);
@@ -142,8 +470,52 @@ impl SyncManager {
         Ok((t, map))
     }
 
+    /// Compare a placement setting the peer advertises in INFO (`field`) with ours and warn on mismatch.
+    async fn check_remote_placement(&self, addr: &str, field: &str, local: &str, setting: &str) {
+        match self.with_deadline(addr, "INFO", self.read_remote_info_field(addr, field)).await {
+            Ok(Some(remote)) if remote != local => {
+                warn!(
+                    "SYNC: peer {} uses {}={} but this node uses {}={}; \
+                     {} must be identical across the cluster",
+                    addr, field, remote, field, local, setting
+                );
+            }
+            Ok(Some(_)) => {}
+            Ok(None) => debug!("SYNC: peer {} does not advertise {}", addr, field),
+            Err(e) => debug!("SYNC: could not read INFO from {}: {}", addr, e),
+        }
+    }
+
     // ───────────── Wire I/O ─────────────
 
+    /// Bound a peer RPC by `rpc_timeout`.
+    async fn with_deadline<T>(&self, addr: &str, what: &str, rpc: impl Future<Output = Result<T>>) -> Result<T> {
+        match time::timeout(self.rpc_timeout, rpc).await {
+            Ok(result) => result,
+            Err(_) => Err(anyhow!("{} to {} timed out after {:?}", what, addr, self.rpc_timeout)),
+        }
+    }
+
+    /// INFO: send "INFO\r\n" and return the value of `field` from the
+    /// "name:value" lines. The write half is closed so the peer ends the
+    /// connection after replying, letting us read the whole response.
+    async fn read_remote_info_field(&self, addr: &str, field: &str) -> Result<Option<String>> {
+        debug!("→ {} : INFO", addr);
+        let mut stream = TcpStream::connect(addr)
+            .await
+            .with_context(|| format!("connect {}", addr))?;
+        stream.write_all(b"INFO\r\n").await.context("write INFO")?;
+        stream.shutdown().await.context("shutdown INFO writer")?;
+
+        let mut response = String::new();
+        BufReader::new(stream).read_to_string(&mut response).await?;
+        let prefix = format!("{}:", field);
+        Ok(response
+            .lines()
+            .find_map(|line| line.strip_prefix(prefix.as_str()))
+            .map(|v| v.trim().to_string()))
+    }
+
     /// SCAN (all keys): send "SCAN \r\n" → expect:
     ///  "KEYS <n>\r\n"
     ///   then n lines, each is one key.
@@ -188,6 +560,63 @@ impl SyncManager {
         Ok(keys)
     }
 
+    /// TOMBSTONES: "TOMBSTONES\r\n" → "TOMBSTONES <n>\r\n" then n "<key> <deleted_at_ms>" lines.
+    /// An ERROR reply (peer without tombstone support) yields an empty list.
+    async fn read_remote_tombstones(&self, addr: &str) -> Result<Vec<(String, u64)>> {
+        debug!("→ {} : TOMBSTONES", addr);
+        let mut stream = TcpStream::connect(addr)
+            .await
+            .with_context(|| format!("connect {}", addr))?;
+        stream.write_all(b"TOMBSTONES\r\n").await.context("write TOMBSTONES")?;
+
+        let mut reader = BufReader::new(stream);
+        let mut header = String::new();
+        if reader.read_line(&mut header).await? == 0 {
+            return Err(anyhow!("peer closed while reading TOMBSTONES header"));
+        }
+        let header = header.trim_end();
+        if header.starts_with("ERROR") {
+            debug!("SYNC: peer {} does not support TOMBSTONES", addr);
+            return Ok(Vec::new());
+        }
+        let count: usize = header
+            .strip_prefix("TOMBSTONES ")
+            .ok_or_else(|| anyhow!("unexpected TOMBSTONES response: {}", header))?
+            .parse()
+            .context("invalid count after TOMBSTONES")?;
+
+        let mut tombstones = Vec::with_capacity(count);
+        for _ in 0..count {
+            let mut line = String::new();
+            if reader.read_line(&mut line).await? == 0 {
+                return Err(anyhow!("peer closed while reading tombstones"));
+            }
+            let (key, at) = line
+                .trim_end()
+                .rsplit_once(' ')
+                .ok_or_else(|| anyhow!("malformed tombstone line: {}", line.trim_end()))?;
+            tombstones.push((key.to_string(), at.parse().context("invalid tombstone timestamp")?));
+        }
+        Ok(tombstones)
+    }
+
+    /// Last write time of one key: "OBJECT MODIFIED <key>\r\n" → "VALUE <unix ms>\r\n".
+    /// None when the peer does not know it (absent key, no key times, older peer).
+    async fn read_remote_modified(&self, addr: &str, key: &str) -> Result<Option<u64>> {
+        let cmd = format!("OBJECT MODIFIED {key}\r\n");
+        debug!("→ {} : {}", addr, cmd.trim_end());
+        let mut stream = TcpStream::connect(addr)
+            .await
+            .with_context(|| format!("connect {}", addr))?;
+        stream.write_all(cmd.as_bytes()).await.context("write OBJECT MODIFIED")?;
+
+        let mut line = String::new();
+        if BufReader::new(stream).read_line(&mut line).await? == 0 {
+            return Err(anyhow!("peer closed on OBJECT MODIFIED {}", key));
+        }
+        Ok(line.trim_end().strip_prefix("VALUE ").and_then(|at| at.parse().ok()))
+    }
+
     /// GET one key: "GET <key>\r\n" → "VALUE <plain>\r\n" or "NOT_FOUND\r\n"
     async fn read_remote_value_plain(&self, addr: &str, key: &str) -> Result<Option<String>> {
         let cmd = format!("GET {key}\r\n");
@@ -213,3 +642,394 @@ impl SyncManager {
         Err(anyhow!("unexpected GET response for {key}: {}", line))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::store::{RwLockEngine, TombstoneEngine};
+    use crate::tasks::TaskRegistry;
+    use std::time::Instant;
+    use tokio::net::TcpListener;
+
+    type SharedStore = Arc<Mutex<Box<dyn KVEngineStoreTrait + Send + Sync>>>;
+
+    fn manager(timeout_ms: u64) -> (SyncManager, SharedStore) {
+        let mut cfg = Config::default();
+        cfg.merkle.sync_timeout_ms = timeout_ms;
+        let store: SharedStore = Arc::new(Mutex::new(Box::new(RwLockEngine::new("unused").unwrap())));
+        (SyncManager::new_with_shared_store(&cfg, Arc::clone(&store)), store)
+    }
+
+    /// A peer that accepts connections but never answers.
+    async fn silent_peer() -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        tokio::spawn(async move {
+            let mut held = Vec::new();
+            while let Ok((socket, _)) = listener.accept().await {
+                held.push(socket);
+            }
+        });
+        addr
+    }
+
+    /// A peer holding `foo=bar` that has deleted `gone` (tombstoned at `deleted_at`).
+    async fn tombstone_peer(deleted_at: u64) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        tokio::spawn(async move {
+            while let Ok((socket, _)) = listener.accept().await {
+                tokio::spawn(async move {
+                    let (r, mut w) = socket.into_split();
+                    let mut line = String::new();
+                    BufReader::new(r).read_line(&mut line).await.unwrap();
+                    let reply = match line.trim_end() {
+                        "SCAN" => "KEYS 1\r\nfoo\r\n".to_string(),
+                        "GET foo" => "VALUE bar\r\n".to_string(),
+                        "TOMBSTONES" => format!("TOMBSTONES 1\r\ngone {}\r\n", deleted_at),
+                        _ => "ERROR unsupported\r\n".to_string(),
+                    };
+                    w.write_all(reply.as_bytes()).await.unwrap();
+                });
+            }
+        });
+        addr
+    }
+
+    /// A peer holding `foo=bar`, written at `modified_ms`.
+    async fn rewritten_peer(modified_ms: u64) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        tokio::spawn(async move {
+            while let Ok((socket, _)) = listener.accept().await {
+                tokio::spawn(async move {
+                    let (r, mut w) = socket.into_split();
+                    let mut line = String::new();
+                    BufReader::new(r).read_line(&mut line).await.unwrap();
+                    let reply = match line.trim_end() {
+                        "SCAN" => "KEYS 1\r\nfoo\r\n".to_string(),
+                        "GET foo" => "VALUE bar\r\n".to_string(),
+                        "OBJECT MODIFIED foo" => format!("VALUE {}\r\n", modified_ms),
+                        "TOMBSTONES" => "TOMBSTONES 0\r\n".to_string(),
+                        _ => "ERROR unsupported\r\n".to_string(),
+                    };
+                    w.write_all(reply.as_bytes()).await.unwrap();
+                });
+            }
+        });
+        addr
+    }
+
+    /// A peer holding a single key `foo=bar`.
+    async fn one_key_peer() -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        tokio::spawn(async move {
+            while let Ok((socket, _)) = listener.accept().await {
+                tokio::spawn(async move {
+                    let (r, mut w) = socket.into_split();
+                    let mut line = String::new();
+                    BufReader::new(r).read_line(&mut line).await.unwrap();
+                    let reply = match line.trim_end() {
+                        "SCAN" => "KEYS 1\r\nfoo\r\n",
+                        "GET foo" => "VALUE bar\r\n",
+                        _ => "ERROR unsupported\r\n",
+                    };
+                    w.write_all(reply.as_bytes()).await.unwrap();
+                });
+            }
+        });
+        addr
+    }
+
+    /// A peer with `keys` keys that answers each GET after `delay`.
+    async fn slow_peer(keys: usize, delay: Duration) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        tokio::spawn(async move {
+            while let Ok((socket, _)) = listener.accept().await {
+                tokio::spawn(async move {
+                    let (r, mut w) = socket.into_split();
+                    let mut line = String::new();
+                    BufReader::new(r).read_line(&mut line).await.unwrap();
+                    let reply = match line.trim_end() {
+                        "SCAN" => format!("KEYS {}\r\n{}", keys, (0..keys).map(|i| format!("k{}\r\n", i)).collect::<String>()),
+                        get if get.starts_with("GET ") => {
+                            tokio::time::sleep(delay).await;
+                            "VALUE v\r\n".to_string()
+                        }
+                        _ => "ERROR unsupported\r\n".to_string(),
+                    };
+                    let _ = w.write_all(reply.as_bytes()).await;
+                });
+            }
+        });
+        addr
+    }
+
+    #[tokio::test]
+    async fn test_status_and_abort_of_slow_sync() {
+        let (mgr, store) = manager(1_000);
+        let progress = mgr.progress_handle();
+        assert_eq!(progress.format(), "SYNC_STATUS idle\r\n");
+        assert!(!progress.abort(), "nothing to abort");
+
+        let peer = slow_peer(100, Duration::from_millis(20)).await;
+        let (host, port) = peer.rsplit_once(':').unwrap();
+        let (host, port) = (host.to_string(), port.parse::<u16>().unwrap());
+        let sync = tokio::spawn(async move { mgr.sync_once(&host, port).await });
+
+        // Wait until some keys have been compared
+        let status = tokio::time::timeout(Duration::from_secs(5), async {
+            loop {
+                match progress.status() {
+                    Some(status) if status.keys_compared >= 3 => return status,
+                    _ => tokio::time::sleep(Duration::from_millis(10)).await,
+                }
+            }
+        })
+        .await
+        .expect("sync must report progress");
+        assert_eq!((status.peer.as_str(), status.phase, status.keys_total, status.keys_transferred), (peer.as_str(), "snapshot", 100, 0));
+        assert!(progress.format().starts_with(&format!("SYNC_STATUS running peer:{} phase:snapshot", peer)));
+
+        assert!(progress.abort());
+        let result = tokio::time::timeout(Duration::from_secs(5), sync).await.unwrap().unwrap();
+        assert!(result.unwrap_err().to_string().contains("aborted"));
+        assert_eq!(progress.format(), "SYNC_STATUS idle\r\n");
+        assert!(store.lock().await.keys().is_empty(), "an aborted round applies nothing");
+    }
+
+    #[tokio::test]
+    async fn test_silent_peer_times_out_without_blocking_store() {
+        let (mgr, store) = manager(100);
+        let peer = silent_peer().await;
+        let (host, port) = peer.rsplit_once(':').unwrap();
+        let (host, port) = (host.to_string(), port.parse::<u16>().unwrap());
+
+        let started = Instant::now();
+        let sync = tokio::spawn(async move { mgr.sync_once(&host, port).await });
+
+        // Other work on the shared store proceeds while the sync is stuck on the peer
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        tokio::time::timeout(Duration::from_millis(50), async {
+            store.lock().await.set("local".to_string(), "1".to_string()).unwrap();
+        })
+        .await
+        .expect("store must not be held while waiting on the peer");
+
+        let result = tokio::time::timeout(Duration::from_secs(5), sync)
+            .await
+            .expect("sync must give up on a silent peer")
+            .unwrap();
+        assert!(result.unwrap_err().to_string().contains("timed out"));
+        assert!(started.elapsed() < Duration::from_secs(2));
+
+        // Nothing was applied by the abandoned round
+        let guard = store.lock().await;
+        assert_eq!(guard.keys(), vec!["local".to_string()]);
+    }
+
+    #[tokio::test]
+    async fn test_anti_entropy_round_moves_on_after_timeout() {
+        let (mgr, store) = manager(100);
+        let mgr = Arc::new(Mutex::new(mgr));
+        let peers = vec![silent_peer().await, "not-an-address".to_string(), one_key_peer().await];
+
+        let ok = tokio::time::timeout(Duration::from_secs(5), SyncManager::sync_peers_once(&mgr, &peers))
+            .await
+            .expect("round must finish despite the silent peer");
+        assert_eq!(ok, 1);
+        assert_eq!(store.lock().await.get("foo"), Some("bar".to_string()));
+    }
+
+    #[tokio::test]
+    async fn test_local_values_are_neither_overwritten_nor_deleted() {
+        let cfg = Config::default();
+        let tracked = crate::store::MerkleTrackedEngine::new(Box::new(RwLockEngine::new("unused").unwrap()));
+        let store: SharedStore = Arc::new(Mutex::new(Box::new(tracked)));
+        for key in ["foo", "scratch"] {
+            let guard = store.lock().await;
+            guard.set(key.to_string(), "mine".to_string()).unwrap();
+            guard.mark_local(key);
+        }
+        let mgr = SyncManager::new_with_shared_store(&cfg, Arc::clone(&store));
+        let peer = one_key_peer().await;
+        let (host, port) = peer.rsplit_once(':').unwrap();
+        mgr.sync_once(host, port.parse().unwrap()).await.unwrap();
+        let guard = store.lock().await;
+        assert_eq!(guard.get("foo"), Some("mine".to_string()));
+        assert_eq!(guard.get("scratch"), Some("mine".to_string()));
+    }
+
+    #[tokio::test]
+    async fn test_tombstones_block_resurrection_and_propagate() {
+        let cfg = Config::default();
+        let store: SharedStore = Arc::new(Mutex::new(Box::new(TombstoneEngine::new(
+            Box::new(RwLockEngine::new("unused").unwrap()),
+            60,
+        ))));
+        let mgr = SyncManager::new_with_shared_store(&cfg, Arc::clone(&store));
+
+        // We deleted `foo`; the lagging peer still has it
+        {
+            let guard = store.lock().await;
+            guard.set("foo".to_string(), "old".to_string()).unwrap();
+            assert!(guard.delete("foo"));
+        }
+        let deleted_at = crate::store::expiring::now_ms() - 1_000;
+        let peer = tombstone_peer(deleted_at).await;
+        let (host, port) = peer.rsplit_once(':').unwrap();
+        mgr.sync_once(host, port.parse().unwrap()).await.unwrap();
+
+        let guard = store.lock().await;
+        assert_eq!(guard.get("foo"), None, "tombstoned key must not be resurrected");
+        // The peer's tombstone for a key we never had is merged with its original time
+        let tombstones = guard.tombstones();
+        assert_eq!(tombstones.len(), 2);
+        assert!(tombstones.contains(&("gone".to_string(), deleted_at)));
+    }
+
+    #[tokio::test]
+    async fn test_write_newer_than_the_tombstone_wins() {
+        let cfg = Config::default();
+        let store: SharedStore = Arc::new(Mutex::new(Box::new(TombstoneEngine::new(
+            Box::new(RwLockEngine::new("unused").unwrap()),
+            60,
+        ))));
+        let mgr = SyncManager::new_with_shared_store(&cfg, Arc::clone(&store));
+        let deleted_at = {
+            let guard = store.lock().await;
+            guard.set("foo".to_string(), "old".to_string()).unwrap();
+            assert!(guard.delete("foo"));
+            guard.tombstones()[0].1
+        };
+
+        // Written before the delete: stays deleted, and so does a tie
+        for modified in [deleted_at - 1_000, deleted_at] {
+            let peer = rewritten_peer(modified).await;
+            let (host, port) = peer.rsplit_once(':').unwrap();
+            mgr.sync_once(host, port.parse().unwrap()).await.unwrap();
+            assert_eq!(store.lock().await.get("foo"), None);
+        }
+
+        // Written after: the peer's value is taken and the tombstone cleared
+        let peer = rewritten_peer(deleted_at + 1_000).await;
+        let (host, port) = peer.rsplit_once(':').unwrap();
+        mgr.sync_once(host, port.parse().unwrap()).await.unwrap();
+        let guard = store.lock().await;
+        assert_eq!(guard.get("foo"), Some("bar".to_string()));
+        assert!(guard.tombstones().is_empty());
+    }
+
+    /// An empty peer that counts the sync rounds (SCAN requests) it serves.
+    async fn counting_peer() -> (String, Arc<std::sync::atomic::AtomicUsize>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        let rounds = Arc::new(std::sync::atomic::AtomicUsize::new(0));
+        let counter = Arc::clone(&rounds);
+        tokio::spawn(async move {
+            while let Ok((socket, _)) = listener.accept().await {
+                let counter = Arc::clone(&counter);
+                tokio::spawn(async move {
+                    let (r, mut w) = socket.into_split();
+                    let mut line = String::new();
+                    BufReader::new(r).read_line(&mut line).await.unwrap();
+                    let reply = match line.trim_end() {
+                        "SCAN" => {
+                            counter.fetch_add(1, Ordering::SeqCst);
+                            "KEYS 0\r\n"
+                        }
+                        "TOMBSTONES" => "TOMBSTONES 0\r\n",
+                        _ => "ERROR unsupported\r\n",
+                    };
+                    let _ = w.write_all(reply.as_bytes()).await;
+                });
+            }
+        });
+        (addr, rounds)
+    }
+
+    #[tokio::test]
+    async fn test_peers_are_scheduled_on_their_own_intervals() {
+        let (near, near_rounds) = counting_peer().await;
+        let (far, far_rounds) = counting_peer().await;
+        let (mut mgr, _store) = manager(1000);
+        mgr.peer_intervals.insert(near.clone(), Duration::from_millis(50));
+        mgr.peer_intervals.insert(far.clone(), Duration::from_millis(600));
+        assert_eq!(mgr.peer_interval("unlisted:7379"), Duration::from_secs(60));
+
+        let tasks = Arc::new(TaskRegistry::new());
+        let task = tokio::spawn(SyncManager::run_anti_entropy_loop(
+            Arc::new(Mutex::new(mgr)),
+            vec![near, far],
+            tasks.register("anti_entropy"),
+        ));
+        tokio::time::sleep(Duration::from_millis(1000)).await;
+        task.abort();
+
+        let (near, far) = (near_rounds.load(Ordering::SeqCst), far_rounds.load(Ordering::SeqCst));
+        assert!((1..=2).contains(&far), "far peer synced {} times", far);
+        assert!(near >= 5 * far, "near peer synced {} times, far {}", near, far);
+        assert!(tasks.list()[0].1.runs > 0, "peer syncs are reported to the task");
+    }
+
+    /// An empty peer whose SCAN takes `delay`; `in_flight` counts the SCANs
+    /// being served across all such peers and `peak` the most at once.
+    async fn busy_peer(delay: Duration, in_flight: Arc<std::sync::atomic::AtomicUsize>, peak: Arc<std::sync::atomic::AtomicUsize>) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        tokio::spawn(async move {
+            while let Ok((socket, _)) = listener.accept().await {
+                let (in_flight, peak) = (Arc::clone(&in_flight), Arc::clone(&peak));
+                tokio::spawn(async move {
+                    let (r, mut w) = socket.into_split();
+                    let mut line = String::new();
+                    BufReader::new(r).read_line(&mut line).await.unwrap();
+                    let reply = match line.trim_end() {
+                        "SCAN" => {
+                            peak.fetch_max(in_flight.fetch_add(1, Ordering::SeqCst) + 1, Ordering::SeqCst);
+                            tokio::time::sleep(delay).await;
+                            in_flight.fetch_sub(1, Ordering::SeqCst);
+                            "KEYS 0\r\n"
+                        }
+                        "TOMBSTONES" => "TOMBSTONES 0\r\n",
+                        _ => "ERROR unsupported\r\n",
+                    };
+                    let _ = w.write_all(reply.as_bytes()).await;
+                });
+            }
+        });
+        addr
+    }
+
+    #[tokio::test]
+    async fn test_peers_are_synced_concurrently_up_to_the_bound() {
+        let in_flight = Arc::new(std::sync::atomic::AtomicUsize::new(0));
+        let peak = Arc::new(std::sync::atomic::AtomicUsize::new(0));
+        let mut peers = Vec::new();
+        for _ in 0..4 {
+            peers.push(busy_peer(Duration::from_millis(200), Arc::clone(&in_flight), Arc::clone(&peak)).await);
+        }
+        let mut cfg = Config::default();
+        cfg.merkle.sync_concurrency = 2;
+        let store: SharedStore = Arc::new(Mutex::new(Box::new(RwLockEngine::new("unused").unwrap())));
+        let mgr = SyncManager::new_with_shared_store(&cfg, store);
+        let progress = mgr.progress_handle();
+
+        let tasks = Arc::new(TaskRegistry::new());
+        let task = tokio::spawn(SyncManager::run_anti_entropy_loop(
+            Arc::new(Mutex::new(mgr)),
+            peers,
+            tasks.register("anti_entropy"),
+        ));
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert!(progress.format().ends_with(" concurrent:2\r\n"), "{}", progress.format());
+        // Two batches of two: all four peers are done well before 4 × 200 ms
+        tokio::time::sleep(Duration::from_millis(500)).await;
+        task.abort();
+
+        assert_eq!(peak.load(Ordering::SeqCst), 2, "never more than merkle.sync_concurrency rounds at once");
+        assert_eq!(tasks.list()[0].1.runs, 4, "every peer was synced once");
+    }
+}
@@ -0,0 +1,209 @@
+//! # Background Tasks (`TASKS`)
+//!
+//! Registry of the server's periodic background work, so operators can see
+//! what runs and how it went:
+//!
+//! - **`anti_entropy`**: Merkle sync with the configured peers (only when
+//!   anti-entropy is enabled); one run per peer sync
+//! - **`sweep`**: purge of tombstones past their grace period
+//! - **`merkle_gc`**: drop of Merkle tree nodes left behind by deleted keys
+//!   (every `merkle.gc_interval_seconds`, or only when triggered if 0)
+//! - **`metrics_dump`**: rewrite of `metrics.dump_path` with the current
+//!   metrics (only when set; every `metrics.dump_interval_seconds`)
+//!
+//! `TASKS` lists every task as
+//! `<name> state:idle|running runs:N last_run:<unix ms>|never last_duration_ms:N last_error:"<msg>"|none`.
+//! The error is quoted, with `"`, `\` and control characters escaped, so it
+//! stays one field however it reads. A run that panics is recorded as failed
+//! with the panic message, and the task keeps its schedule.
+//! `TASKS RUN <name>` wakes a task now instead of at its next slot; every task
+//! is safe to run at any time.
+
+use std::collections::BTreeMap;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::Instant;
+use tokio::sync::Notify;
+
+use crate::store::expiring::now_ms;
+
+/// Outcome of a task's runs so far.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct TaskStatus {
+    pub running: bool,
+    pub runs: u64,
+    /// Start of the last finished run (UNIX milliseconds)
+    pub last_run_ms: Option<u64>,
+    pub last_duration_ms: u64,
+    /// Error of the last finished run, if it failed
+    pub last_error: Option<String>,
+}
+
+struct TaskEntry {
+    status: TaskStatus,
+    trigger: Arc<Notify>,
+}
+
+/// Shared table of background tasks.
+#[derive(Default)]
+pub struct TaskRegistry {
+    tasks: Mutex<BTreeMap<&'static str, TaskEntry>>,
+}
+
+impl TaskRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add a task and return the handle its loop reports through.
+    pub fn register(self: &Arc<Self>, name: &'static str) -> TaskHandle {
+        let trigger = Arc::new(Notify::new());
+        self.tasks()
+            .insert(name, TaskEntry { status: TaskStatus::default(), trigger: Arc::clone(&trigger) });
+        TaskHandle { registry: Arc::clone(self), name, trigger }
+    }
+
+    /// Wake `name` now.
+    ///
+    /// # Returns
+    /// * `bool` - False if no such task is registered
+    pub fn trigger(&self, name: &str) -> bool {
+        match self.tasks().get(name) {
+            Some(entry) => {
+                entry.trigger.notify_one();
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Every task with its status, by name.
+    pub fn list(&self) -> Vec<(&'static str, TaskStatus)> {
+        self.tasks().iter().map(|(name, entry)| (*name, entry.status.clone())).collect()
+    }
+
+    /// `TASKS` reply: `TASKS <count>` then one line per task.
+    pub fn format(&self) -> String {
+        let tasks = self.list();
+        let mut out = format!("TASKS {}\r\n", tasks.len());
+        for (name, status) in tasks {
+            out.push_str(&format!(
+                "{} state:{} runs:{} last_run:{} last_duration_ms:{} last_error:{}\r\n",
+                name,
+                if status.running { "running" } else { "idle" },
+                status.runs,
+                status.last_run_ms.map_or("never".to_string(), |ms| ms.to_string()),
+                status.last_duration_ms,
+                status.last_error.as_ref().map_or("none".to_string(), |e| format!("{:?}", e)),
+            ));
+        }
+        out
+    }
+
+    fn tasks(&self) -> MutexGuard<'_, BTreeMap<&'static str, TaskEntry>> {
+        self.tasks.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn update(&self, name: &str, f: impl FnOnce(&mut TaskStatus)) {
+        if let Some(entry) = self.tasks().get_mut(name) {
+            f(&mut entry.status);
+        }
+    }
+}
+
+/// A task loop's link to the registry.
+pub struct TaskHandle {
+    registry: Arc<TaskRegistry>,
+    name: &'static str,
+    trigger: Arc<Notify>,
+}
+
+impl TaskHandle {
+    /// Resolves on `TASKS RUN <name>`; a trigger while the task is busy is kept
+    /// for the next wait.
+    pub async fn triggered(&self) {
+        self.trigger.notified().await
+    }
+
+    /// Mark a run as started.
+    pub fn start(&self) -> (Instant, u64) {
+        self.registry.update(self.name, |s| s.running = true);
+        (Instant::now(), now_ms())
+    }
+
+    /// Record the outcome of the run begun by `start`.
+    pub fn finish(&self, (started, started_ms): (Instant, u64), result: Result<(), String>) {
+        self.registry.update(self.name, |s| {
+            s.running = false;
+            s.runs += 1;
+            s.last_run_ms = Some(started_ms);
+            s.last_duration_ms = started.elapsed().as_millis() as u64;
+            s.last_error = result.err();
+        });
+    }
+
+    /// Run `work` as one run of the task, recording a panic as its error.
+    ///
+    /// # Returns
+    /// * `Option<T>` - What `work` returned, or None if it panicked
+    pub fn run<T>(&self, work: impl FnOnce() -> T) -> Option<T> {
+        let run = self.start();
+        match panic::catch_unwind(AssertUnwindSafe(work)) {
+            Ok(value) => {
+                self.finish(run, Ok(()));
+                Some(value)
+            }
+            Err(payload) => {
+                let message = payload
+                    .downcast_ref::<&str>()
+                    .map(|s| s.to_string())
+                    .or_else(|| payload.downcast_ref::<String>().cloned())
+                    .unwrap_or_else(|| "panicked".to_string());
+                self.finish(run, Err(format!("panicked: {}", message)));
+                None
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn test_runs_and_triggers_are_recorded() {
+        let registry = Arc::new(TaskRegistry::new());
+        let task = registry.register("sweep");
+        assert_eq!(registry.list(), vec![("sweep", TaskStatus::default())]);
+        assert!(registry.format().contains("sweep state:idle runs:0 last_run:never"));
+
+        let run = task.start();
+        assert!(registry.list()[0].1.running);
+        task.finish(run, Err("disk full".to_string()));
+        let status = &registry.list()[0].1;
+        assert_eq!((status.running, status.runs), (false, 1));
+        assert!(status.last_run_ms.is_some());
+        assert!(registry.format().ends_with("last_error:\"disk full\"\r\n"));
+
+        // A trigger sent before anyone waits is not lost
+        assert!(registry.trigger("sweep"));
+        assert!(!registry.trigger("nope"));
+        tokio::time::timeout(Duration::from_secs(1), task.triggered()).await.unwrap();
+    }
+
+    #[test]
+    fn test_a_panicking_run_is_recorded_as_failed() {
+        let registry = Arc::new(TaskRegistry::new());
+        let task = registry.register("merkle_gc");
+        assert_eq!(task.run(|| 7), Some(7));
+        assert_eq!(registry.list()[0].1.last_error, None);
+
+        assert_eq!(task.run(|| -> u32 { panic!("tree \"poisoned\"\nbadly") }), None);
+        let status = &registry.list()[0].1;
+        assert_eq!((status.running, status.runs), (false, 2));
+        assert_eq!(status.last_error.as_deref(), Some("panicked: tree \"poisoned\"\nbadly"));
+        // Quoted and escaped, the error stays the last field of one line
+        assert!(registry.format().ends_with("last_error:\"panicked: tree \\\"poisoned\\\"\\nbadly\"\r\n"));
+    }
+}
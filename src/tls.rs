@@ -0,0 +1,177 @@
+//! # TLS (`server.tls_cert_path`, `replication.mqtt_ca_path`, `CONFIG RELOAD TLS`)
+//!
+//! - **Clients**: with `server.tls_cert_path` and `server.tls_key_path` set,
+//!   every client connection starts with a TLS handshake (after the PROXY
+//!   header, when `server.proxy_protocol` is on)
+//! - **MQTT broker**: with `replication.mqtt_ca_path` set, the broker
+//!   connection uses TLS and the broker's certificate must chain to that CA
+//!
+//! ## Rotation
+//!
+//! `CONFIG RELOAD TLS` (or SIGHUP) re-reads the files from the same paths.
+//! Every file is parsed before anything is swapped, so a bad certificate or
+//! key fails the reload and the previous ones stay in use. New client
+//! connections get the new certificate; open connections keep the session
+//! they negotiated. The MQTT CA is read again whenever the broker connection
+//! is reopened, so the reload only checks it.
+
+use anyhow::{anyhow, bail, Context, Result};
+use std::fs::File;
+use std::io::BufReader;
+use std::sync::{Arc, RwLock};
+use tokio_rustls::rustls::pki_types::{CertificateDer, PrivateKeyDer};
+use tokio_rustls::rustls::{ClientConfig, RootCertStore, ServerConfig};
+use tokio_rustls::TlsAcceptor;
+
+use crate::config::Config;
+
+/// Server TLS settings for the certificate chain in `cert_path` and the
+/// private key in `key_path`.
+pub fn server_config(cert_path: &str, key_path: &str) -> Result<Arc<ServerConfig>> {
+    let certs = read_certs(cert_path)?;
+    let mut reader = BufReader::new(File::open(key_path).with_context(|| format!("open TLS key {}", key_path))?);
+    let key: PrivateKeyDer<'static> = rustls_pemfile::private_key(&mut reader)
+        .with_context(|| format!("read TLS key {}", key_path))?
+        .ok_or_else(|| anyhow!("no private key in {}", key_path))?;
+    let config = ServerConfig::builder()
+        .with_no_client_auth()
+        .with_single_cert(certs, key)
+        .with_context(|| format!("TLS certificate {} with key {}", cert_path, key_path))?;
+    Ok(Arc::new(config))
+}
+
+/// Client TLS settings trusting the CA certificates in `ca_path`.
+pub fn client_config(ca_path: &str) -> Result<Arc<ClientConfig>> {
+    let mut roots = RootCertStore::empty();
+    for cert in read_certs(ca_path)? {
+        roots.add(cert).with_context(|| format!("CA certificate in {}", ca_path))?;
+    }
+    Ok(Arc::new(ClientConfig::builder().with_root_certificates(roots).with_no_client_auth()))
+}
+
+fn read_certs(path: &str) -> Result<Vec<CertificateDer<'static>>> {
+    let mut reader = BufReader::new(File::open(path).with_context(|| format!("open TLS certificate {}", path))?);
+    let certs = rustls_pemfile::certs(&mut reader)
+        .collect::<std::io::Result<Vec<_>>>()
+        .with_context(|| format!("read TLS certificate {}", path))?;
+    if certs.is_empty() {
+        bail!("no certificate in {}", path);
+    }
+    Ok(certs)
+}
+
+/// The certificate served to clients, swapped by `TlsState::reload`.
+struct ServerCert {
+    cert_path: String,
+    key_path: String,
+    acceptor: RwLock<TlsAcceptor>,
+}
+
+/// The TLS files of a node and the acceptor built from them.
+pub struct TlsState {
+    server: Option<ServerCert>,
+    mqtt_ca_path: Option<String>,
+}
+
+impl TlsState {
+    /// Load the configured files; fails on the first invalid one.
+    pub fn from_config(config: &Config) -> Result<Self> {
+        let server = match (&config.server.tls_cert_path, &config.server.tls_key_path) {
+            (Some(cert_path), Some(key_path)) => Some(ServerCert {
+                acceptor: RwLock::new(TlsAcceptor::from(server_config(cert_path, key_path)?)),
+                cert_path: cert_path.clone(),
+                key_path: key_path.clone(),
+            }),
+            _ => None,
+        };
+        let mqtt_ca_path = config.replication.mqtt_ca_path.clone();
+        if let Some(ca_path) = &mqtt_ca_path {
+            client_config(ca_path)?;
+        }
+        Ok(Self { server, mqtt_ca_path })
+    }
+
+    /// Acceptor for a new client connection; None when clients use plain TCP.
+    pub fn acceptor(&self) -> Option<TlsAcceptor> {
+        let server = self.server.as_ref()?;
+        Some(server.acceptor.read().unwrap_or_else(|e| e.into_inner()).clone())
+    }
+
+    /// Re-read every configured file, swapping the client certificate only
+    /// when all of them are valid.
+    ///
+    /// # Returns
+    /// * `Result<Vec<String>>` - The files that were reloaded
+    pub fn reload(&self) -> Result<Vec<String>> {
+        if self.server.is_none() && self.mqtt_ca_path.is_none() {
+            bail!("TLS is not configured");
+        }
+        let mut reloaded = Vec::new();
+        if let Some(ca_path) = &self.mqtt_ca_path {
+            client_config(ca_path)?;
+            reloaded.push(ca_path.clone());
+        }
+        if let Some(server) = &self.server {
+            let acceptor = TlsAcceptor::from(server_config(&server.cert_path, &server.key_path)?);
+            *server.acceptor.write().unwrap_or_else(|e| e.into_inner()) = acceptor;
+            reloaded.extend([server.cert_path.clone(), server.key_path.clone()]);
+        }
+        Ok(reloaded)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio_rustls::rustls::pki_types::ServerName;
+    use tokio_rustls::TlsConnector;
+
+    /// Path of a file in `tests/fixtures/tls`.
+    fn fixture(name: &str) -> String {
+        format!("{}/tests/fixtures/tls/{}", env!("CARGO_MANIFEST_DIR"), name)
+    }
+
+    /// The certificate `acceptor` presents to a client trusting the test CA.
+    async fn served_cert(acceptor: TlsAcceptor) -> CertificateDer<'static> {
+        let (client, server) = tokio::io::duplex(16 * 1024);
+        let connector = TlsConnector::from(client_config(&fixture("ca.pem")).unwrap());
+        let name = ServerName::try_from("localhost").unwrap();
+        let (client, _server) = tokio::join!(connector.connect(name, client), acceptor.accept(server));
+        let client = client.unwrap();
+        client.get_ref().1.peer_certificates().unwrap()[0].clone().into_owned()
+    }
+
+    #[tokio::test]
+    async fn test_reload_keeps_the_old_certificate_when_the_new_files_are_invalid() {
+        let dir = tempfile::tempdir().unwrap();
+        let cert = dir.path().join("server.pem");
+        let key = dir.path().join("server.key");
+        std::fs::copy(fixture("server-a.pem"), &cert).unwrap();
+        std::fs::copy(fixture("server-a.key"), &key).unwrap();
+        let mut config = Config::default();
+        config.server.tls_cert_path = Some(cert.display().to_string());
+        config.server.tls_key_path = Some(key.display().to_string());
+        let tls = TlsState::from_config(&config).unwrap();
+        let cert_a = read_certs(&fixture("server-a.pem")).unwrap().remove(0);
+        assert_eq!(served_cert(tls.acceptor().unwrap()).await, cert_a);
+
+        std::fs::write(&cert, "not a certificate").unwrap();
+        let err = tls.reload().unwrap_err().to_string();
+        assert_eq!(err, format!("no certificate in {}", cert.display()));
+        assert_eq!(served_cert(tls.acceptor().unwrap()).await, cert_a);
+
+        std::fs::copy(fixture("server-b.pem"), &cert).unwrap();
+        std::fs::copy(fixture("server-b.key"), &key).unwrap();
+        assert_eq!(tls.reload().unwrap().len(), 2);
+        let cert_b = read_certs(&fixture("server-b.pem")).unwrap().remove(0);
+        assert_eq!(served_cert(tls.acceptor().unwrap()).await, cert_b);
+
+        let plain = TlsState::from_config(&Config::default()).unwrap();
+        assert!(plain.acceptor().is_none());
+        assert_eq!(plain.reload().unwrap_err().to_string(), "TLS is not configured");
+        config.replication.mqtt_ca_path = Some(fixture("missing.pem"));
+        assert!(TlsState::from_config(&config).is_err());
+        config.replication.mqtt_ca_path = Some(fixture("ca.pem"));
+        assert_eq!(TlsState::from_config(&config).unwrap().reload().unwrap().len(), 3);
+    }
+}
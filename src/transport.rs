@@ -0,0 +1,282 @@
+//! # Connection Compression (`HELLO COMPRESS <codec> ...`)
+//!
+//! `CLIENT COMPRESS` shrinks large `GET` replies only. A client on a slow
+//! link can instead compress the whole connection, both ways, by offering
+//! codecs in `HELLO`:
+//!
+//! ```text
+//! HELLO COMPRESS zlib
+//! HELLO proto:1 version:0.1.0 commit:3f2a9c1d0e4b features:compression,subscribe,tombstones compression:zlib
+//! ```
+//!
+//! `version`, `commit` and `features` are those of `VERSION` (see `version`),
+//! so a client learns what the server supports in the same round trip.
+//!
+//! The reply is the last plain line in either direction: every byte the
+//! client sends after its `HELLO` line, and every byte the server sends
+//! after the reply, is one zlib stream per direction. Each side sync-flushes
+//! its stream whenever it waits for the other (the server at the end of each
+//! reply batch, see `pipeline`), so a request or reply never sits in the
+//! compressor. Framing inside the streams is unchanged: text lines.
+//!
+//! `zlib` is the only codec. A `HELLO` without `COMPRESS`, offering no known
+//! codec, or sent with `server.transport_compression` off gets
+//! `compression:none` and the connection stays plain. Once compressed it
+//! stays so; a later `HELLO` reports `zlib` again.
+
+use flate2::{Compress, Compression, Decompress, FlushCompress, FlushDecompress, Status};
+use std::io;
+use std::pin::Pin;
+use std::task::{ready, Context, Poll};
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+
+use crate::config::Config;
+use crate::version;
+
+/// Codec name in `HELLO COMPRESS` and its reply.
+pub const ZLIB: &str = "zlib";
+
+/// Compressed bytes read from the socket at a time.
+const READ_CHUNK: usize = 8 * 1024;
+
+/// `HELLO` reply, `compressed` telling if the connection uses `zlib`.
+pub fn hello_reply(config: &Config, compressed: bool) -> String {
+    format!(
+        "HELLO proto:{} version:{} commit:{} features:{} compression:{}\r\n",
+        version::PROTOCOL_VERSION,
+        env!("CARGO_PKG_VERSION"),
+        version::GIT_COMMIT.unwrap_or("unknown"),
+        version::features(config).join(","),
+        if compressed { ZLIB } else { "none" }
+    )
+}
+
+/// Whether the server takes up one of the `offered` codecs.
+pub fn accepts(offered: &[String], enabled: bool) -> bool {
+    enabled && offered.iter().any(|codec| codec.eq_ignore_ascii_case(ZLIB))
+}
+
+struct Inflate {
+    zlib: Decompress,
+    /// Compressed bytes not decompressed yet
+    input: Vec<u8>,
+    /// Decompressed bytes not read yet, from `pos`
+    output: Vec<u8>,
+    pos: usize,
+    /// The last call filled `output`, so zlib may hold more
+    full: bool,
+    /// The client finished its stream
+    ended: bool,
+}
+
+/// Read half of a connection, inflating once `start_inflate` is called.
+pub struct TransportReader<R> {
+    inner: R,
+    inflate: Option<Inflate>,
+}
+
+impl<R: AsyncRead + Unpin> TransportReader<R> {
+    pub fn new(inner: R) -> Self {
+        Self { inner, inflate: None }
+    }
+
+    pub fn compressed(&self) -> bool {
+        self.inflate.is_some()
+    }
+
+    /// Treat everything from here on as a zlib stream, starting with
+    /// `buffered`: bytes already read past the `HELLO` line.
+    pub fn start_inflate(&mut self, buffered: Vec<u8>) {
+        self.inflate = Some(Inflate { zlib: Decompress::new(true), input: buffered, output: Vec::new(), pos: 0, full: false, ended: false });
+    }
+}
+
+impl<R: AsyncRead + Unpin> AsyncRead for TransportReader<R> {
+    fn poll_read(mut self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut ReadBuf<'_>) -> Poll<io::Result<()>> {
+        let this = &mut *self;
+        let Some(inflate) = this.inflate.as_mut() else { return Pin::new(&mut this.inner).poll_read(cx, buf) };
+        loop {
+            if inflate.pos < inflate.output.len() {
+                let n = buf.remaining().min(inflate.output.len() - inflate.pos);
+                buf.put_slice(&inflate.output[inflate.pos..inflate.pos + n]);
+                inflate.pos += n;
+                return Poll::Ready(Ok(()));
+            }
+            if inflate.ended {
+                return Poll::Ready(Ok(()));
+            }
+            if !inflate.input.is_empty() || inflate.full {
+                inflate.output.clear();
+                inflate.output.reserve(READ_CHUNK);
+                inflate.pos = 0;
+                let before = inflate.zlib.total_in();
+                let status = inflate
+                    .zlib
+                    .decompress_vec(&inflate.input, &mut inflate.output, FlushDecompress::None)
+                    .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+                let consumed = (inflate.zlib.total_in() - before) as usize;
+                inflate.input.drain(..consumed);
+                inflate.full = inflate.output.len() == inflate.output.capacity();
+                inflate.ended = status == Status::StreamEnd;
+                if !inflate.output.is_empty() || consumed > 0 || inflate.ended {
+                    continue;
+                }
+            }
+            // Everything received is decompressed: wait for more
+            let mut chunk = [0u8; READ_CHUNK];
+            let mut read = ReadBuf::new(&mut chunk);
+            ready!(Pin::new(&mut this.inner).poll_read(cx, &mut read))?;
+            if read.filled().is_empty() {
+                return Poll::Ready(Ok(()));
+            }
+            inflate.input.extend_from_slice(read.filled());
+        }
+    }
+}
+
+struct Deflate {
+    zlib: Compress,
+    /// Compressed bytes not written to the socket yet
+    output: Vec<u8>,
+    /// Input went in since the last sync flush
+    unflushed: bool,
+}
+
+impl Deflate {
+    /// Compress all of `input` into `output`, with `flush` at the end.
+    fn compress(&mut self, mut input: &[u8], flush: FlushCompress) -> io::Result<()> {
+        loop {
+            self.output.reserve(input.len() / 2 + 64);
+            let before = self.zlib.total_in();
+            self.zlib.compress_vec(input, &mut self.output, flush).map_err(io::Error::other)?;
+            input = &input[(self.zlib.total_in() - before) as usize..];
+            // Spare room left over means zlib had nothing more to produce
+            if input.is_empty() && self.output.len() < self.output.capacity() {
+                return Ok(());
+            }
+        }
+    }
+}
+
+/// Write half of a connection, deflating once `start_deflate` is called.
+pub struct TransportWriter<W> {
+    inner: W,
+    deflate: Option<Deflate>,
+}
+
+impl<W: AsyncWrite + Unpin> TransportWriter<W> {
+    pub fn new(inner: W) -> Self {
+        Self { inner, deflate: None }
+    }
+
+    /// Compress everything written from here on; the caller has flushed.
+    pub fn start_deflate(&mut self) {
+        self.deflate = Some(Deflate { zlib: Compress::new(Compression::default(), true), output: Vec::new(), unflushed: false });
+    }
+
+    /// Write out the compressed bytes pending.
+    fn poll_drain(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let Some(deflate) = self.deflate.as_mut() else { return Poll::Ready(Ok(())) };
+        while !deflate.output.is_empty() {
+            let n = ready!(Pin::new(&mut self.inner).poll_write(cx, &deflate.output))?;
+            if n == 0 {
+                return Poll::Ready(Err(io::ErrorKind::WriteZero.into()));
+            }
+            deflate.output.drain(..n);
+        }
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl<W: AsyncWrite + Unpin> AsyncWrite for TransportWriter<W> {
+    fn poll_write(mut self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<io::Result<usize>> {
+        let this = &mut *self;
+        if this.deflate.is_none() {
+            return Pin::new(&mut this.inner).poll_write(cx, buf);
+        }
+        // Take new input only once the previous output is out
+        ready!(this.poll_drain(cx))?;
+        let deflate = this.deflate.as_mut().expect("checked above");
+        deflate.compress(buf, FlushCompress::None)?;
+        deflate.unflushed = true;
+        Poll::Ready(Ok(buf.len()))
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let this = &mut *self;
+        if let Some(deflate) = this.deflate.as_mut().filter(|d| d.unflushed) {
+            deflate.compress(&[], FlushCompress::Sync)?;
+            deflate.unflushed = false;
+        }
+        ready!(this.poll_drain(cx))?;
+        Pin::new(&mut this.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        ready!(self.as_mut().poll_flush(cx))?;
+        Pin::new(&mut self.inner).poll_shutdown(cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+    use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+
+    #[test]
+    fn test_negotiation() {
+        assert!(accepts(&["lz4".to_string(), "ZLIB".to_string()], true));
+        assert!(!accepts(&["zlib".to_string()], false));
+        assert!(!accepts(&["lz4".to_string()], true));
+        let mut config = Config::default();
+        config.replication.enabled = false;
+        config.anti_entropy.enabled = false;
+        let commit = version::GIT_COMMIT.unwrap_or("unknown");
+        let fields = format!("version:{} commit:{} features:compression,subscribe,tombstones", env!("CARGO_PKG_VERSION"), commit);
+        assert_eq!(hello_reply(&config, true), format!("HELLO proto:1 {} compression:zlib\r\n", fields));
+        assert_eq!(hello_reply(&config, false), format!("HELLO proto:1 {} compression:none\r\n", fields));
+    }
+
+    #[tokio::test]
+    async fn test_lines_survive_the_switch_in_both_directions() {
+        // Plain bytes, then a zlib stream written in two sync-flushed parts
+        let mut wire = b"HELLO COMPRESS zlib\r\n".to_vec();
+        let mut zlib = flate2::write::ZlibEncoder::new(Vec::new(), Compression::default());
+        zlib.write_all(b"SET k v\r\nGET ").unwrap();
+        zlib.flush().unwrap();
+        zlib.write_all(b"k\r\n").unwrap();
+        wire.extend(zlib.finish().unwrap());
+
+        let mut reader = BufReader::new(TransportReader::new(wire.as_slice()));
+        let mut line = String::new();
+        reader.read_line(&mut line).await.unwrap();
+        assert_eq!(line, "HELLO COMPRESS zlib\r\n");
+        let buffered = reader.buffer().to_vec();
+        reader.consume(buffered.len());
+        reader.get_mut().start_inflate(buffered);
+        let mut lines = Vec::new();
+        line.clear();
+        while reader.read_line(&mut line).await.unwrap() > 0 {
+            lines.push(std::mem::take(&mut line));
+        }
+        assert_eq!(lines, vec!["SET k v\r\n", "GET k\r\n"]);
+
+        let mut writer = TransportWriter::new(Vec::new());
+        writer.write_all(b"HELLO proto:1 compression:zlib\r\n").await.unwrap();
+        writer.start_deflate();
+        let reply = "VALUE v\r\n".repeat(100);
+        writer.write_all(reply.as_bytes()).await.unwrap();
+        writer.flush().await.unwrap();
+        let wire = writer.inner;
+        let (plain, compressed) = wire.split_at(b"HELLO proto:1 compression:zlib\r\n".len());
+        assert_eq!(plain, b"HELLO proto:1 compression:zlib\r\n");
+        assert!(compressed.len() < reply.len() / 10, "{} bytes", compressed.len());
+        // A sync flush makes everything so far decodable without the stream end
+        let mut inflated = Vec::new();
+        let mut decoder = Decompress::new(true);
+        inflated.reserve(reply.len() * 2);
+        decoder.decompress_vec(compressed, &mut inflated, FlushDecompress::Sync).unwrap();
+        assert_eq!(inflated, reply.as_bytes());
+    }
+}
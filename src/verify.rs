@@ -0,0 +1,143 @@
+//! # End-to-End Consistency Check (`VERIFY CONSISTENT <peer>`)
+//!
+//! A convenience for replication tests: fetch the peer's `DUMP`, build a
+//! Merkle tree of each side's replicated keys and report every key that
+//! differs. Node-local keys (see `KeyFilter`), TTLs and tombstones are not
+//! compared. Only served with `server.test_commands` on. Replies:
+//!
+//! ```text
+//! CONSISTENT keys:<n> root:<hex>\r\n
+//! INCONSISTENT missing:<n> extra:<n> differing:<n> local_root:<hex> peer_root:<hex>\r\n
+//! MISSING <key>\r\n      (only on the peer)
+//! EXTRA <key>\r\n        (only here)
+//! DIFFERENT <key>\r\n    (values differ)
+//! ```
+//!
+//! Listed keys are sorted within each group. As with sync, the peer must not
+//! require `AUTH`.
+
+use std::collections::HashMap;
+
+use crate::key_filter::KeyFilter;
+use crate::snapshot::Record;
+use crate::store::merkle::MerkleTree;
+use crate::store::merkle_tracked;
+use crate::store::KVEngineStoreTrait;
+
+/// Outcome of comparing this node's keys with a peer's.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Report {
+    pub keys: usize,
+    pub local_root: String,
+    pub peer_root: String,
+    /// Keys only the peer holds
+    pub missing: Vec<String>,
+    /// Keys only this node holds
+    pub extra: Vec<String>,
+    /// Keys both hold with different values
+    pub differing: Vec<String>,
+}
+
+impl Report {
+    pub fn is_consistent(&self) -> bool {
+        self.missing.is_empty() && self.extra.is_empty() && self.differing.is_empty()
+    }
+
+    /// The `VERIFY CONSISTENT` reply.
+    pub fn format(&self) -> String {
+        if self.is_consistent() {
+            return format!("CONSISTENT keys:{} root:{}\r\n", self.keys, self.local_root);
+        }
+        let mut out = format!(
+            "INCONSISTENT missing:{} extra:{} differing:{} local_root:{} peer_root:{}\r\n",
+            self.missing.len(),
+            self.extra.len(),
+            self.differing.len(),
+            self.local_root,
+            self.peer_root
+        );
+        for (tag, keys) in [("MISSING", &self.missing), ("EXTRA", &self.extra), ("DIFFERENT", &self.differing)] {
+            for key in keys {
+                out.push_str(&format!("{} {}\r\n", tag, key));
+            }
+        }
+        out
+    }
+}
+
+/// Replicated keys and values of `store`; the caller holds the store lock.
+pub fn local_values(store: &dyn KVEngineStoreTrait, filter: &KeyFilter) -> HashMap<String, String> {
+    store
+        .keys()
+        .into_iter()
+        .filter(|key| filter.replicates(key))
+        .filter_map(|key| store.get(&key).map(|value| (key, value)))
+        .collect()
+}
+
+/// Replicated keys and values of a peer's `DUMP`.
+pub fn peer_values(records: Vec<Record>, filter: &KeyFilter) -> HashMap<String, String> {
+    records
+        .into_iter()
+        .filter_map(|record| match record {
+            Record::Set { key, value, .. } if filter.replicates(&key) => Some((key, value)),
+            _ => None,
+        })
+        .collect()
+}
+
+/// Compare this node's values with the peer's.
+pub fn compare(local: &HashMap<String, String>, peer: &HashMap<String, String>) -> Report {
+    let local_tree = MerkleTree::from_pairs(local);
+    let peer_tree = MerkleTree::from_pairs(peer);
+    let mut report = Report {
+        keys: local.len(),
+        local_root: merkle_tracked::root_hex(local_tree.get_root_hash()),
+        peer_root: merkle_tracked::root_hex(peer_tree.get_root_hash()),
+        missing: Vec::new(),
+        extra: Vec::new(),
+        differing: Vec::new(),
+    };
+    // diff_keys returns keys in sorted order
+    for key in local_tree.diff_keys(&peer_tree) {
+        match (local.contains_key(&key), peer.contains_key(&key)) {
+            (false, _) => report.missing.push(key),
+            (true, false) => report.extra.push(key),
+            (true, true) => report.differing.push(key),
+        }
+    }
+    report
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn values(pairs: &[(&str, &str)]) -> HashMap<String, String> {
+        pairs.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect()
+    }
+
+    #[test]
+    fn test_compare_reports_each_kind_of_difference() {
+        let local = values(&[("a", "1"), ("b", "2"), ("c", "3")]);
+        let same = compare(&local, &local.clone());
+        assert!(same.is_consistent());
+        assert_eq!(same.local_root, same.peer_root);
+        assert_eq!(same.format(), format!("CONSISTENT keys:3 root:{}\r\n", same.local_root));
+
+        let peer = values(&[("a", "1"), ("c", "changed"), ("d", "4")]);
+        let report = compare(&local, &peer);
+        assert_eq!((report.missing.clone(), report.extra.clone(), report.differing.clone()), (vec!["d".to_string()], vec!["b".to_string()], vec!["c".to_string()]));
+        let reply = report.format();
+        assert!(reply.starts_with("INCONSISTENT missing:1 extra:1 differing:1 local_root:"), "{}", reply);
+        assert!(reply.ends_with("\r\nMISSING d\r\nEXTRA b\r\nDIFFERENT c\r\n"), "{}", reply);
+
+        let filter = KeyFilter::new(vec![], vec!["local:".to_string()]);
+        let records = vec![
+            Record::Set { key: "a".to_string(), expires_at_ms: Some(1), value: "1".to_string() },
+            Record::Set { key: "local:x".to_string(), expires_at_ms: None, value: "x".to_string() },
+            Record::Tombstone { key: "gone".to_string(), deleted_at_ms: 1 },
+        ];
+        assert_eq!(peer_values(records, &filter), values(&[("a", "1")]));
+    }
+}
@@ -0,0 +1,86 @@
+//! # Build and Capability Information (`VERSION`)
+//!
+//! Lets clients and ops tools discover what a server supports before relying
+//! on it. The reply is a single line so older clients that only read the
+//! version token keep working:
+//!
+//! ```text
+//! VERSION 0.1.0 commit:3f2a9c1d0e4b protocol:1 features:compression,subscribe,tombstones,auth
+//! ```
+//!
+//! `commit` is `unknown` when the binary was not built from a git checkout
+//! (set `MERKLEKV_GIT_COMMIT` to override). `features` lists compiled-in
+//! capabilities followed by the optional ones enabled in this node's config.
+
+use crate::config::Config;
+
+/// Text protocol version; bump on incompatible wire changes.
+pub const PROTOCOL_VERSION: u32 = 1;
+
+/// Git commit the binary was built from, if known at build time.
+pub const GIT_COMMIT: Option<&str> = option_env!("MERKLEKV_GIT_COMMIT");
+
+/// Capabilities available in every build.
+const COMPILED_FEATURES: &[&str] = &["compression", "subscribe", "tombstones"];
+
+/// Compiled-in features plus those enabled by `config`.
+pub fn features(config: &Config) -> Vec<&'static str> {
+    let mut features = COMPILED_FEATURES.to_vec();
+    if config.server.password.is_some() || config.auth.users_file.is_some() {
+        features.push("auth");
+    }
+    if config.replication.enabled {
+        features.push("replication");
+    }
+    if config.anti_entropy.enabled {
+        features.push("anti_entropy");
+    }
+    if config.server.tls_cert_path.is_some() {
+        features.push("tls");
+    }
+    features
+}
+
+/// Reply for the `VERSION` command.
+pub fn version_reply(config: &Config) -> String {
+    format!(
+        "VERSION {} commit:{} protocol:{} features:{}\r\n",
+        env!("CARGO_PKG_VERSION"),
+        GIT_COMMIT.unwrap_or("unknown"),
+        PROTOCOL_VERSION,
+        features(config).join(",")
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_version_reply_reports_crate_version_and_features() {
+        let mut config = Config::default();
+        config.replication.enabled = false;
+        config.anti_entropy.enabled = false;
+        let reply = version_reply(&config);
+        let tokens: Vec<&str> = reply.trim_end().split(' ').collect();
+        assert_eq!(tokens[0], "VERSION");
+        assert_eq!(tokens[1], env!("CARGO_PKG_VERSION"));
+        assert!(tokens[2].starts_with("commit:"));
+        assert_eq!(tokens[3], format!("protocol:{}", PROTOCOL_VERSION));
+        assert_eq!(tokens[4], "features:compression,subscribe,tombstones");
+
+        config.server.password = Some("pw".to_string());
+        config.replication.enabled = true;
+        assert!(version_reply(&config).ends_with("features:compression,subscribe,tombstones,auth,replication\r\n"));
+
+        // Per-user auth is auth too
+        let mut users = Config::default();
+        users.replication.enabled = false;
+        users.anti_entropy.enabled = false;
+        users.auth.users_file = Some("users.txt".to_string());
+        assert!(version_reply(&users).ends_with("features:compression,subscribe,tombstones,auth\r\n"));
+
+        config.server.tls_cert_path = Some("certs/server.pem".to_string());
+        assert!(version_reply(&config).ends_with(",replication,tls\r\n"));
+    }
+}
@@ -0,0 +1,87 @@
+//! # Write Rejection Above a Memory Watermark (`storage.write_reject_bytes`)
+//!
+//! With the watermark set, a client command that writes a key (any `write:`
+//! op in its `EXPLAIN` plan: SET, APPEND, INC, HSET, ...) is refused while
+//! the store uses more memory than the watermark:
+//!
+//! ```text
+//! ERROR ERR_OOM command not allowed when over memory limit\r\n
+//! ```
+//!
+//! Reads, deletes, EXPIRE and admin commands still run, so operators can free
+//! memory and recover. Changes applied from replicas and sync are not refused.
+//!
+//! Measuring memory walks the whole store, so the measurement is reused for
+//! `REFRESH`: usage may overshoot the watermark by the writes accepted within
+//! one refresh, and writes resume at most one refresh after memory is freed.
+
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use crate::protocol::Command;
+use crate::store::KVEngineStoreTrait;
+
+/// How long one memory measurement is trusted.
+pub const REFRESH: Duration = Duration::from_millis(100);
+
+/// Shared watermark state of all connections.
+pub struct WriteWatermark {
+    limit_bytes: usize,
+    /// Last measurement and when it was taken
+    last: Mutex<Option<(Instant, usize)>>,
+}
+
+impl WriteWatermark {
+    /// `limit_bytes` of 0 never refuses.
+    pub fn new(limit_bytes: usize) -> Self {
+        Self { limit_bytes, last: Mutex::new(None) }
+    }
+
+    /// Whether `command` must be refused, measuring `store` if the last
+    /// measurement is older than `REFRESH`.
+    pub async fn refuses(&self, command: &Command, store: &tokio::sync::Mutex<Box<dyn KVEngineStoreTrait + Send + Sync>>) -> bool {
+        if self.limit_bytes == 0 || !command.plan().ops.iter().any(|op| op.starts_with("write:")) {
+            return false;
+        }
+        let fresh = (*self.last_guard()).filter(|(at, _)| at.elapsed() < REFRESH);
+        let usage = match fresh {
+            Some((_, usage)) => usage,
+            None => {
+                let usage = store.lock().await.memory_usage();
+                *self.last_guard() = Some((Instant::now(), usage));
+                usage
+            }
+        };
+        usage > self.limit_bytes
+    }
+
+    fn last_guard(&self) -> std::sync::MutexGuard<'_, Option<(Instant, usize)>> {
+        self.last.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocol::Protocol;
+    use crate::store::RwLockEngine;
+
+    #[tokio::test]
+    async fn test_only_writes_are_refused_and_measurements_are_reused() {
+        let parse = |line: &str| Protocol::new().parse(line).unwrap();
+        let store: tokio::sync::Mutex<Box<dyn KVEngineStoreTrait + Send + Sync>> =
+            tokio::sync::Mutex::new(Box::new(RwLockEngine::new("unused").unwrap()));
+        let empty = store.lock().await.memory_usage();
+        let watermark = WriteWatermark::new(empty + 100);
+        assert!(!watermark.refuses(&parse("SET k v"), &store).await);
+
+        store.lock().await.set("k".to_string(), "v".repeat(1000)).unwrap();
+        assert!(!watermark.refuses(&parse("SET k v"), &store).await, "measurement reused within REFRESH");
+        tokio::time::sleep(REFRESH).await;
+        assert!(watermark.refuses(&parse("INC n"), &store).await);
+        assert!(watermark.refuses(&parse("APPEND k v"), &store).await);
+        assert!(!watermark.refuses(&parse("GET k"), &store).await);
+        assert!(!watermark.refuses(&parse("DEL k"), &store).await);
+        assert!(!WriteWatermark::new(0).refuses(&parse("SET k v"), &store).await);
+    }
+}
@@ -0,0 +1,239 @@
+//! # Key Change Webhook
+//!
+//! POSTs every matching write to an external HTTP endpoint (`hooks.url`), so
+//! other systems can react to key changes. This is for integration, not
+//! cluster consistency: MQTT replication and anti-entropy are unaffected, and
+//! delivery is best effort.
+//!
+//! ## Payload
+//!
+//! One request per change, with a JSON body:
+//!
+//! ```json
+//! {"op":"set","key":"user:1","value":"alice","timestamp":1700000000000}
+//! ```
+//!
+//! `op` is `set`, `delete`, `incr`, `decr`, `append`, `prepend`, `expire` or
+//! `persist`; `value` is the new value (the TTL in seconds for `expire`,
+//! `null` for deletes and `persist`) and `timestamp` is UNIX milliseconds.
+//!
+//! ## Delivery
+//!
+//! Changes go through a bounded queue drained by one background task, so a
+//! slow or unreachable endpoint never blocks writes. A change is retried with
+//! exponential backoff on connection errors and non-2xx replies, then dropped.
+//! Changes arriving while the queue is full are dropped. Both are logged.
+//! Only plain `http://` URLs are supported.
+
+use anyhow::{anyhow, Context, Result};
+use log::warn;
+use serde::Serialize;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::net::TcpStream;
+use tokio::sync::mpsc;
+
+use crate::config::HooksConfig;
+use crate::key_filter::KeyFilter;
+use crate::net_addr;
+
+/// Deadline for one delivery attempt (connect + request + status line).
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// One key change, as sent to the endpoint.
+#[derive(Debug, Clone, Serialize)]
+pub struct HookEvent {
+    pub op: &'static str,
+    pub key: String,
+    pub value: Option<String>,
+    pub timestamp: u64,
+}
+
+/// Parsed `http://host[:port]/path` endpoint.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HookUrl {
+    pub host: String,
+    pub port: u16,
+    pub path: String,
+}
+
+impl HookUrl {
+    /// Parse a plain HTTP URL; the port defaults to 80 and the path to `/`.
+    pub fn parse(url: &str) -> Result<Self> {
+        let rest = url
+            .strip_prefix("http://")
+            .ok_or_else(|| anyhow!("webhook url '{}' must start with http://", url))?;
+        let (authority, path) = match rest.find('/') {
+            Some(i) => (&rest[..i], rest[i..].to_string()),
+            None => (rest, "/".to_string()),
+        };
+        let has_port = match authority.strip_prefix('[') {
+            Some(bracketed) => !bracketed.ends_with(']'),
+            None => authority.contains(':'),
+        };
+        let (host, port) = if has_port {
+            net_addr::split_host_port(authority)?
+        } else {
+            let host = authority.trim_start_matches('[').trim_end_matches(']');
+            (host.to_string(), 80)
+        };
+        net_addr::validate_host(&host).with_context(|| format!("invalid webhook url '{}'", url))?;
+        Ok(Self { host, port, path })
+    }
+}
+
+/// Handle used by connections to queue changes for the webhook.
+#[derive(Clone)]
+pub struct Webhook {
+    filter: KeyFilter,
+    queue: mpsc::Sender<HookEvent>,
+}
+
+impl Webhook {
+    /// Start the delivery task, or return `None` if `hooks.url` is unset.
+    pub fn spawn(config: &HooksConfig) -> Result<Option<Self>> {
+        let Some(url) = config.url.as_deref() else {
+            return Ok(None);
+        };
+        let url = HookUrl::parse(url)?;
+        let (queue, mut events) = mpsc::channel::<HookEvent>(config.queue_limit.max(1));
+        let max_retries = config.max_retries;
+        let backoff = Duration::from_millis(config.retry_backoff_ms);
+        tokio::spawn(async move {
+            while let Some(event) = events.recv().await {
+                deliver(&url, &event, max_retries, backoff).await;
+            }
+        });
+        Ok(Some(Self {
+            filter: KeyFilter::new(config.include_prefixes.clone(), config.exclude_prefixes.clone()),
+            queue,
+        }))
+    }
+
+    /// Queue a change to `key` if it matches the prefix filters. Never waits.
+    pub fn notify(&self, op: &'static str, key: &str, value: Option<&str>) {
+        if !self.filter.matches(key) {
+            return;
+        }
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or(Duration::from_secs(0))
+            .as_millis() as u64;
+        let event = HookEvent { op, key: key.to_string(), value: value.map(str::to_string), timestamp };
+        if let Err(mpsc::error::TrySendError::Full(event)) = self.queue.try_send(event) {
+            warn!("Webhook queue full, dropping {} of {}", event.op, event.key);
+        }
+    }
+}
+
+/// POST `event`, retrying with doubling backoff up to `max_retries` times.
+async fn deliver(url: &HookUrl, event: &HookEvent, max_retries: u32, backoff: Duration) {
+    let body = match serde_json::to_vec(event) {
+        Ok(body) => body,
+        Err(e) => {
+            warn!("Failed to encode webhook event for {}: {}", event.key, e);
+            return;
+        }
+    };
+    let mut delay = backoff;
+    for attempt in 0..=max_retries {
+        match tokio::time::timeout(REQUEST_TIMEOUT, post(url, &body)).await {
+            Ok(Ok(())) => return,
+            Ok(Err(e)) if attempt == max_retries => {
+                warn!("Webhook delivery of {} failed, dropping it: {}", event.key, e)
+            }
+            Err(_) if attempt == max_retries => {
+                warn!("Webhook delivery of {} timed out, dropping it", event.key)
+            }
+            _ => {
+                tokio::time::sleep(delay).await;
+                delay *= 2;
+            }
+        }
+    }
+}
+
+/// Send one HTTP/1.1 POST and check for a 2xx status.
+async fn post(url: &HookUrl, body: &[u8]) -> Result<()> {
+    let mut stream = TcpStream::connect((url.host.as_str(), url.port)).await?;
+    let head = format!(
+        "POST {} HTTP/1.1\r\nHost: {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+        url.path,
+        net_addr::join_host_port(&url.host, url.port),
+        body.len()
+    );
+    stream.write_all(head.as_bytes()).await?;
+    stream.write_all(body).await?;
+
+    let mut status_line = String::new();
+    BufReader::new(stream).read_line(&mut status_line).await?;
+    let status = status_line
+        .split_whitespace()
+        .nth(1)
+        .and_then(|code| code.parse::<u16>().ok())
+        .ok_or_else(|| anyhow!("malformed HTTP status line '{}'", status_line.trim_end()))?;
+    if !(200..300).contains(&status) {
+        return Err(anyhow!("endpoint replied {}", status));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::AsyncReadExt;
+    use tokio::net::TcpListener;
+
+    #[test]
+    fn test_parse_url() {
+        let url = HookUrl::parse("http://127.0.0.1:8080/hooks/kv").unwrap();
+        assert_eq!(url, HookUrl { host: "127.0.0.1".to_string(), port: 8080, path: "/hooks/kv".to_string() });
+        let url = HookUrl::parse("http://example.com").unwrap();
+        assert_eq!((url.host.as_str(), url.port, url.path.as_str()), ("example.com", 80, "/"));
+        assert_eq!(HookUrl::parse("http://[::1]:9000/x").unwrap().host, "::1");
+        assert_eq!(HookUrl::parse("http://[::1]/x").unwrap().port, 80);
+        assert!(HookUrl::parse("https://example.com").is_err());
+        assert!(HookUrl::parse("http://bad host/").is_err());
+    }
+
+    /// Accept one request, reply `status`, and return its body.
+    async fn serve_once(listener: &TcpListener, status: &str) -> String {
+        let (stream, _) = listener.accept().await.unwrap();
+        let mut reader = BufReader::new(stream);
+        let mut length = 0;
+        loop {
+            let mut line = String::new();
+            reader.read_line(&mut line).await.unwrap();
+            if line == "\r\n" {
+                break;
+            }
+            if let Some(value) = line.to_ascii_lowercase().strip_prefix("content-length:") {
+                length = value.trim().parse().unwrap();
+            }
+        }
+        let mut body = vec![0; length];
+        reader.read_exact(&mut body).await.unwrap();
+        let reply = format!("HTTP/1.1 {}\r\nContent-Length: 0\r\n\r\n", status);
+        reader.get_mut().write_all(reply.as_bytes()).await.unwrap();
+        String::from_utf8(body).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_failed_delivery_is_retried() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let config = HooksConfig {
+            url: Some(format!("http://{}/hook", listener.local_addr().unwrap())),
+            retry_backoff_ms: 10,
+            ..HooksConfig::default()
+        };
+        let hook = Webhook::spawn(&config).unwrap().unwrap();
+        hook.notify("delete", "k", None);
+
+        let first = serve_once(&listener, "503 Service Unavailable").await;
+        let second = serve_once(&listener, "200 OK").await;
+        assert_eq!(first, second);
+        let event: serde_json::Value = serde_json::from_str(&second).unwrap();
+        assert_eq!(event["op"], "delete");
+        assert!(event["value"].is_null());
+    }
+}